@@ -2,7 +2,7 @@ use anyhow::Result;
 use libp2p::PeerId;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use umbra_crypto::ChatCrypto;
-use umbra_net::P2PNode;
+use umbra_net::{NetError, P2PNode};
 use umbra_identity::{Identity, Prover, Storage};
 use std::collections::HashMap;
 
@@ -17,6 +17,7 @@ pub struct ChatSession {
     prover: Option<Prover>,
     #[allow(dead_code)]
     peer_identities: HashMap<PeerId, [u8; 32]>,
+    #[allow(dead_code)]
     data_dir: String,
 }
 
@@ -61,6 +62,10 @@ impl ChatSession {
             .node
             .take_message_receiver()
             .ok_or_else(|| anyhow::anyhow!("Failed to get message receiver"))?;
+        let mut group_rx = self
+            .node
+            .take_group_receiver()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get group receiver"))?;
 
         // Async stdin reader
         let stdin = tokio::io::stdin();
@@ -83,6 +88,12 @@ impl ChatSession {
                     self.handle_incoming_message(peer_id, data);
                 }
 
+                // Invites and membership changes
+                Some(update) = group_rx.recv() => {
+                    UI::print_group_update(&update);
+                    UI::print_prompt(&self.username);
+                }
+
                 // Handle user input
                 Ok(Some(line)) = reader.next_line() => {
                     if !self.handle_user_input(&line).await? {
@@ -96,16 +107,24 @@ impl ChatSession {
     }
 
     fn handle_incoming_message(&mut self, peer_id: PeerId, data: Vec<u8>) {
-        // Try to decrypt with new message exchange protocol
-        match self.node.decrypt_message(peer_id, &data) {
-            Ok((username, content, verified_identity)) => {
-                if let Some(id) = verified_identity {
+        // Group messages (MLS), then pairwise, then the legacy topic key
+        let decrypted = match self.node.decrypt_group_message(&data) {
+            Err(NetError::CoverTraffic) => Err(NetError::CoverTraffic),
+            result => result.or_else(|_| self.node.decrypt_message(peer_id, &data)),
+        };
+        match decrypted {
+            // A peer's cover traffic: nothing to show
+            Err(NetError::CoverTraffic) => {}
+            Ok(msg) => {
+                if !msg.verification.is_verified() {
+                    UI::print_unverified_message(&msg.username, &msg.content);
+                } else if let Some(id) = msg.identity {
                     // Store verified identity
                     self.peer_identities.insert(peer_id, id);
                     let id_hex = hex::encode(&id[..8]);
-                    UI::print_verified_message(&username, &content, &id_hex);
+                    UI::print_verified_message(&msg.username, &msg.content, &id_hex);
                 } else {
-                    UI::print_incoming_message(&username, &content);
+                    UI::print_incoming_message(&msg.username, &msg.content);
                 }
             }
             Err(_) => {
@@ -180,10 +199,46 @@ impl ChatSession {
             return Ok(true);
         }
 
+        if let Some(peer) = message.strip_prefix("/invite ") {
+            match peer.trim().parse::<PeerId>() {
+                Ok(peer) => match self.node.invite_to_group(&self.topic, peer) {
+                    Ok(()) => UI::print_success(&format!("Invited {} to {}", peer, self.topic)),
+                    Err(e) => UI::print_error(&format!("Invite failed: {}", e)),
+                },
+                Err(_) => UI::print_error("Usage: /invite <peer-id>"),
+            }
+            UI::print_prompt(&self.username);
+            return Ok(true);
+        }
+
+        if message == "/accept" {
+            // The room we opened on startup is ours alone; give it up for the one we're invited to
+            let alone = self.node.group(&self.topic).is_some_and(|group| group.member_count() < 2);
+            if alone {
+                let _ = self.node.leave_group(&self.topic);
+            }
+            match self.node.accept_group_invite(&self.topic) {
+                Ok(()) => UI::print_success("Invite accepted, waiting to be added..."),
+                Err(e) => {
+                    if alone {
+                        let _ = self.node.join_group(&self.topic);
+                    }
+                    UI::print_error(&format!("No invite to accept: {}", e));
+                }
+            }
+            UI::print_prompt(&self.username);
+            return Ok(true);
+        }
+
         // Send encrypted message
-        // For group chat, we use topic-based encryption (shared key)
-        // For 1-1 chat, we'd use peer-specific encryption with send_encrypted_message
+        // Group chat: encrypted once under the room's MLS epoch, readable by every member
         
+        if self.node.group(&self.topic).map_or(true, |group| group.member_count() < 2) {
+            UI::print_error("Nobody else is in this room yet: /invite a peer or /accept an invite.");
+            UI::print_prompt(&self.username);
+            return Ok(true);
+        }
+
         // Check if we have any peers
         let peers = self.node.connected_peers();
         if peers.is_empty() {
@@ -192,24 +247,7 @@ impl ChatSession {
             return Ok(true);
         }
 
-        // Try new encrypted message format first
-        // For group chat, we send to all peers with the first peer's session
-        // (This is temporary - proper group keys come in v0.5)
-        let send_result = if let Some(&first_peer) = peers.first() {
-            self.node.send_encrypted_message(
-                &self.topic,
-                first_peer,
-                &self.username,
-                message,
-            )
-        } else {
-            // Fallback to legacy topic-based encryption
-            let formatted_msg = format!("{}: {}", self.username, message);
-            let topic_key = Self::derive_topic_key(&self.topic);
-            let crypto = ChatCrypto::from_key(&topic_key);
-            let encrypted = crypto.encrypt(formatted_msg.as_bytes());
-            self.node.publish(&self.topic, encrypted)
-        };
+        let send_result = self.node.send_group_message(&self.topic, &self.username, message);
 
         match send_result {
             Ok(_) => {
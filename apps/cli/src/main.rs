@@ -4,8 +4,8 @@ mod ui;
 use anyhow::Result;
 use chat::ChatSession;
 use clap::{Parser, Subcommand};
-use tracing::info;
-use umbra_net::P2PNode;
+use tracing::{info, warn};
+use umbra_net::{keystore, IdentitySource, NodeConfig, P2PNode};
 use umbra_identity::{Identity, Prover, Storage};
 use ui::UI;
 
@@ -19,6 +19,10 @@ struct Cli {
     /// Data directory for identity and keys (default: ~/.umbra)
     #[arg(long, global = true)]
     data_dir: Option<String>,
+    
+    /// Node config file (TOML): listen addresses, bootstrap peers, tuning
+    #[arg(long, global = true)]
+    config: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -87,7 +91,11 @@ async fn main() -> Result<()> {
 
     match cli.command {
         Commands::Start { port, connect, topic, username } => {
-            start_chat(port, connect, topic, username, &data_dir).await?;
+            let config = match &cli.config {
+                Some(path) => NodeConfig::load(std::path::Path::new(path))?,
+                None => NodeConfig::default(),
+            };
+            start_chat(config, port, connect, topic, username, &data_dir).await?;
         }
         Commands::Identity { command } => {
             handle_identity_command(command, &data_dir).await?;
@@ -100,19 +108,31 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn start_chat(port: Option<u16>, connect: Option<String>, topic: String, username: String, data_dir: &str) -> Result<()> {
+async fn start_chat(config: NodeConfig, port: Option<u16>, connect: Option<String>, topic: String, username: String, data_dir: &str) -> Result<()> {
     UI::print_banner();
     
-    // Create node with specific port if provided
-    let mut node = if let Some(p) = port {
+    // A port given on the command line overrides the config's listen addresses
+    let config = if let Some(p) = port {
         info!("Starting node on port {}...", p);
-        UI::print_spinner("Initializing P2P node...");
-        P2PNode::new_with_port(p).await?
+        config.with_port(p)
     } else {
-        info!("Starting node on random port...");
-        UI::print_spinner("Initializing P2P node...");
-        P2PNode::new().await?
+        info!("Starting node on {:?}...", config.listen_addrs);
+        config
+    };
+    // Same peer across restarts: keys live in the data dir, encrypted under UMBRA_PASSPHRASE
+    let config = match (&config.identity, std::env::var_os(keystore::PASSPHRASE_ENV)) {
+        (IdentitySource::Ephemeral, Some(_)) => config.with_identity(IdentitySource::KeyFile {
+            path: std::path::Path::new(data_dir).join(keystore::KEY_FILE),
+            passphrase: None,
+        }),
+        (IdentitySource::Ephemeral, None) => {
+            warn!("{} not set: this session's identity won't be kept", keystore::PASSPHRASE_ENV);
+            config
+        }
+        _ => config,
     };
+    UI::print_spinner("Initializing P2P node...");
+    let mut node = P2PNode::from_config(config).await?;
     
     let peer_id = node.local_peer_id();
     let addrs = node.listening_addresses();
@@ -120,8 +140,8 @@ async fn start_chat(port: Option<u16>, connect: Option<String>, topic: String, u
     UI::print_success("Node started successfully!");
     UI::print_node_info(peer_id, &addrs);
     
-    // Subscribe to topic
-    node.subscribe(&topic)?;
+    // Subscribe to topic as an encrypted group
+    node.join_group(&topic)?;
     UI::print_success(&format!("Subscribed to topic: {}", topic));
     
     // Connect to peer if specified
@@ -1,6 +1,8 @@
 use colored::*;
 use libp2p::{Multiaddr, PeerId};
 use std::io::{self, Write};
+use umbra_net::GroupUpdate;
+use umbra_wire::group::group_change;
 
 pub struct UI;
 
@@ -66,6 +68,8 @@ impl UI {
         println!("    {} - Show help", "/help".bright_magenta());
         println!("    {} - Show connected peers", "/peers".bright_magenta());
         println!("    {} - Show your identity", "/whoami".bright_magenta());
+        println!("    {} - Invite a peer into this room", "/invite <peer-id>".bright_magenta());
+        println!("    {} - Accept an invite", "/accept".bright_magenta());
         println!("    {} - Exit chat", "/quit".bright_magenta());
         println!();
         println!("{}", "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━".bright_blue());
@@ -105,6 +109,35 @@ impl UI {
         );
     }
 
+    pub fn print_unverified_message(sender_username: &str, msg: &str) {
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        println!(
+            "\n{} {} {} {} {}",
+            sender_username.bright_magenta().bold(),
+            "?".yellow().bold(),
+            format!("[{}:unverified]", timestamp).dimmed(),
+            ">".dimmed(),
+            msg.bright_white()
+        );
+    }
+
+    pub fn print_group_update(update: &GroupUpdate) {
+        let text = match update {
+            GroupUpdate::Invited { topic, inviter, .. } => {
+                format!("{} invited you to {} (type /accept to join)", inviter, topic)
+            }
+            GroupUpdate::Joined { topic } => format!("Joined {}", topic),
+            GroupUpdate::Changed { by, change, .. } => match change {
+                group_change::Change::Add(_) => format!("{} added a member", by),
+                group_change::Change::Remove(_) => format!("{} removed a member", by),
+                group_change::Change::SetRole(_) => format!("{} changed a member's role", by),
+                group_change::Change::Metadata(metadata) => format!("{} renamed the room to {}", by, metadata.name),
+            },
+            GroupUpdate::Removed { topic } => format!("You were removed from {}", topic),
+        };
+        println!("\n{} {}", "[GROUP]".bright_cyan().bold(), text.bright_white());
+    }
+
     pub fn print_decryption_error() {
         println!("{} {}", "[WARN]".yellow().bold(), "Received encrypted message (decryption failed)".yellow());
     }
@@ -123,6 +156,8 @@ impl UI {
         println!("  {} - Show this help message", "/help".bright_magenta().bold());
         println!("  {} - Show connected peers and node information", "/peers".bright_magenta().bold());
         println!("  {} - Show your identity ID", "/whoami".bright_magenta().bold());
+        println!("  {} - Invite a peer into this room (admins only)", "/invite <peer-id>".bright_magenta().bold());
+        println!("  {} - Accept an invite to this room", "/accept".bright_magenta().bold());
         println!("  {} - Clear the screen", "/clear".bright_magenta().bold());
         println!("  {} - Exit the chat (or use /exit)", "/quit".bright_magenta().bold());
         println!();
@@ -1,16 +1,54 @@
 use anyhow::Result;
-use tracing::info;
+use std::path::PathBuf;
+use tracing::{info, warn};
 
 /// Headless UMBRA node for relays/gateways
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     
+    // Listen addresses, bootstrap peers and tuning from UMBRA_CONFIG (TOML), if set; with --mix,
+    // also delay and forward Sphinx mix packets. Either way the node relays onion circuits for
+    // other peers (announced in the DHT) and serves a mailbox.
+    let mut config = match std::env::var_os("UMBRA_CONFIG") {
+        Some(path) => umbra_sdk::NodeConfig::load(&PathBuf::from(path))?,
+        None => umbra_sdk::NodeConfig::default(),
+    };
+    let mix = config.behaviours.mix || std::env::args().any(|arg| arg == "--mix");
+    config.behaviours.mix = mix;
+    config.behaviours.relay = true;
+    config.behaviours.mailbox = true;
+    
+    // Keys and mailbox survive restarts in UMBRA_DATA_DIR (UMBRA_MAILBOX_DIR still works)
+    let data_dir = std::env::var_os("UMBRA_DATA_DIR")
+        .or_else(|| std::env::var_os("UMBRA_MAILBOX_DIR"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("umbra-data"));
+    std::fs::create_dir_all(&data_dir)?;
+    
+    // Unless the config names its own key file, keep our keys in the data dir when
+    // UMBRA_PASSPHRASE is set to encrypt them with
+    if matches!(config.identity, umbra_sdk::IdentitySource::Ephemeral) {
+        if std::env::var_os(umbra_sdk::PASSPHRASE_ENV).is_some() {
+            config.identity = umbra_sdk::IdentitySource::KeyFile {
+                path: data_dir.join(umbra_sdk::KEY_FILE),
+                passphrase: None,
+            };
+        } else {
+            warn!("{} not set: using a fresh identity that won't survive a restart", umbra_sdk::PASSPHRASE_ENV);
+        }
+    }
+    
     info!("Starting UMBRA headless node...");
-    let mut node = umbra_sdk::Node::spawn().await?;
+    let mut node = umbra_sdk::Node::spawn_with_config(config).await?;
     
     info!("Node ID: {}", node.peer_id());
-    info!("Running as relay/gateway...");
+    
+    // Hold messages for offline peers
+    node.enable_mailbox(Some(data_dir.join("mailbox.bin")))?;
+    
+    let role = if mix { "mix" } else { "relay/gateway" };
+    info!("Running as {} with data in {}...", role, data_dir.display());
     
     node.run().await?;
     
@@ -7,7 +7,7 @@ use zeroize::Zeroizing;
 
 const NONCE_SIZE: usize = 12;
 
-/// AEAD envelope for encrypting message payloads
+/// AEAD envelope for encrypting message payloads (held whole in memory; large payloads go through `stream`)
 pub struct Envelope {
     cipher: ChaCha20Poly1305,
 }
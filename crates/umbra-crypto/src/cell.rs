@@ -0,0 +1,316 @@
+// Fixed-size relay cells
+// Every relay cell is CELL_SIZE bytes on every link, whatever it carries and however many hops
+// it has left. A cell is a header of MAX_CELL_HOPS slots followed by the payload. Adding a layer
+// puts a slot at the front of the header: the layer's counter and a MAC over the counter and the
+// payload. Peeling a layer takes that slot off, shifts the rest of the header forward, refills
+// the end with keystream and decrypts header and payload under a keystream for that counter, so
+// a cell shares no bytes with itself on the next link and a relay can't tell how far it has
+// left to go. Each direction of each hop keeps its own counter, and counters already seen are
+// rejected, so a replayed cell dies at the first hop it comes back to.
+
+use crate::error::{CryptoError, Result};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Size of every relay cell on the wire
+pub const CELL_SIZE: usize = 16 * 1024;
+/// Most layers a cell's header has room for
+pub const MAX_CELL_HOPS: usize = 5;
+/// Largest command a cell can carry
+pub const MAX_CELL_COMMAND: usize = PAYLOAD_SIZE - 4;
+
+const MAC_SIZE: usize = 16;
+const SLOT_SIZE: usize = 8 + MAC_SIZE;
+const HEADER_SIZE: usize = MAX_CELL_HOPS * SLOT_SIZE;
+const PAYLOAD_SIZE: usize = CELL_SIZE - HEADER_SIZE;
+// Counters behind the newest one that are still accepted, for cells that overtook each other
+const WINDOW: u64 = 64;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Keys for one direction of one hop
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct DirectionKeys {
+    header: [u8; 32],
+    payload: [u8; 32],
+    mac: [u8; 32],
+}
+
+impl DirectionKeys {
+    fn derive(key: &[u8; 32]) -> Self {
+        let hk = Hkdf::<Sha256>::new(None, key);
+        let expand = |info: &[u8]| {
+            let mut okm = [0u8; 32];
+            hk.expand(info, &mut okm).expect("32 bytes is a valid HKDF output length");
+            okm
+        };
+        Self {
+            header: expand(b"umbra cell header"),
+            payload: expand(b"umbra cell payload"),
+            mac: expand(b"umbra cell mac"),
+        }
+    }
+
+    fn hmac(&self, counter: u64, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.mac).expect("HMAC accepts any key length");
+        mac.update(&counter.to_be_bytes());
+        mac.update(payload);
+        mac
+    }
+
+    fn mac(&self, counter: u64, payload: &[u8]) -> [u8; MAC_SIZE] {
+        self.hmac(counter, payload).finalize().into_bytes()[..MAC_SIZE].try_into().unwrap()
+    }
+
+    fn verify(&self, counter: u64, payload: &[u8], mac: &[u8]) -> bool {
+        self.hmac(counter, payload).verify_truncated_left(mac).is_ok()
+    }
+
+    /// XOR the keystreams for `counter` over header and payload
+    fn apply(&self, counter: u64, header: &mut [u8], payload: &mut [u8]) {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        ChaCha20::new(&self.header.into(), &nonce.into()).apply_keystream(header);
+        ChaCha20::new(&self.payload.into(), &nonce.into()).apply_keystream(payload);
+    }
+}
+
+/// Counters accepted so far in one direction
+#[derive(Default)]
+struct ReplayWindow {
+    /// One past the newest counter accepted (0 before the first)
+    top: u64,
+    /// Bit i set: counter `top - 1 - i` was accepted
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Record `counter`; false if it was seen before or is too old to tell
+    fn accept(&mut self, counter: u64) -> bool {
+        if counter >= self.top {
+            let shift = counter - self.top + 1;
+            self.seen = if shift >= WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.top = counter + 1;
+            return true;
+        }
+        let age = self.top - 1 - counter;
+        if age >= WINDOW || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+/// One hop's cell keys from one side: we add layers with `outward` and peel the other side's
+/// with `inward`
+pub struct CellLayer {
+    outward: DirectionKeys,
+    inward: DirectionKeys,
+    next_counter: u64,
+    window: ReplayWindow,
+}
+
+impl CellLayer {
+    /// From the hop's session keys (our send key, our receive key)
+    pub fn new(send_key: &[u8; 32], recv_key: &[u8; 32]) -> Self {
+        Self {
+            outward: DirectionKeys::derive(send_key),
+            inward: DirectionKeys::derive(recv_key),
+            next_counter: 0,
+            window: ReplayWindow::default(),
+        }
+    }
+
+    /// Add our layer to `cell`
+    pub fn wrap(&mut self, cell: &[u8]) -> Result<Vec<u8>> {
+        check_size(cell)?;
+        let counter = self.next_counter;
+        self.next_counter += 1;
+
+        let mut header = cell[..HEADER_SIZE].to_vec();
+        let mut payload = cell[HEADER_SIZE..].to_vec();
+        self.outward.apply(counter, &mut header, &mut payload);
+
+        let mut wrapped = Vec::with_capacity(CELL_SIZE);
+        wrapped.extend_from_slice(&counter.to_be_bytes());
+        wrapped.extend_from_slice(&self.outward.mac(counter, &payload));
+        wrapped.extend_from_slice(&header[..HEADER_SIZE - SLOT_SIZE]);
+        wrapped.extend_from_slice(&payload);
+        Ok(wrapped)
+    }
+
+    /// Check and take off the other side's layer, rejecting counters we've seen before
+    pub fn peel(&mut self, cell: &[u8]) -> Result<Vec<u8>> {
+        check_size(cell)?;
+        let counter = u64::from_be_bytes(cell[..8].try_into().unwrap());
+        let payload = &cell[HEADER_SIZE..];
+        if !self.inward.verify(counter, payload, &cell[8..SLOT_SIZE]) {
+            return Err(CryptoError::Decryption("Bad cell MAC".to_string()));
+        }
+        if !self.window.accept(counter) {
+            return Err(CryptoError::Decryption(format!("Replayed cell {}", counter)));
+        }
+
+        let mut header = cell[SLOT_SIZE..HEADER_SIZE].to_vec();
+        header.extend_from_slice(&[0u8; SLOT_SIZE]);
+        let mut payload = payload.to_vec();
+        self.inward.apply(counter, &mut header, &mut payload);
+
+        header.extend_from_slice(&payload);
+        Ok(header)
+    }
+}
+
+/// A cell carrying `command` in the clear, ready for its first layer
+pub fn new_cell(command: &[u8]) -> Result<Vec<u8>> {
+    if command.len() > MAX_CELL_COMMAND {
+        return Err(CryptoError::Encryption(format!(
+            "Cell command is {} bytes ({} fit)",
+            command.len(),
+            MAX_CELL_COMMAND
+        )));
+    }
+    let mut cell = vec![0u8; CELL_SIZE];
+    rand::thread_rng().fill_bytes(&mut cell[..HEADER_SIZE]);
+    cell[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&(command.len() as u32).to_be_bytes());
+    cell[HEADER_SIZE + 4..HEADER_SIZE + 4 + command.len()].copy_from_slice(command);
+    Ok(cell)
+}
+
+/// The command in a cell whose layers are all off
+pub fn cell_command(cell: &[u8]) -> Result<&[u8]> {
+    check_size(cell)?;
+    let len = u32::from_be_bytes(cell[HEADER_SIZE..HEADER_SIZE + 4].try_into().unwrap()) as usize;
+    if len > MAX_CELL_COMMAND {
+        return Err(CryptoError::Decryption("Invalid cell command length".to_string()));
+    }
+    Ok(&cell[HEADER_SIZE + 4..HEADER_SIZE + 4 + len])
+}
+
+fn check_size(cell: &[u8]) -> Result<()> {
+    if cell.len() != CELL_SIZE {
+        return Err(CryptoError::Decryption(format!("Cell is {} bytes, not {}", cell.len(), CELL_SIZE)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (client side, relay side) of `n` hops
+    fn hops(n: usize) -> (Vec<CellLayer>, Vec<CellLayer>) {
+        (0..n)
+            .map(|_| {
+                let (a, b): ([u8; 32], [u8; 32]) = (rand::random(), rand::random());
+                (CellLayer::new(&a, &b), CellLayer::new(&b, &a))
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn test_forward_and_back() {
+        let (mut client, mut relays) = hops(3);
+
+        let mut cell = new_cell(b"extend").unwrap();
+        for hop in client.iter_mut().rev() {
+            cell = hop.wrap(&cell).unwrap();
+        }
+        for relay in relays.iter_mut() {
+            assert_eq!(cell.len(), CELL_SIZE);
+            cell = relay.peel(&cell).unwrap();
+        }
+        assert_eq!(cell_command(&cell).unwrap(), b"extend");
+
+        let mut cell = new_cell(b"extended").unwrap();
+        for relay in relays.iter_mut().rev() {
+            cell = relay.wrap(&cell).unwrap();
+            assert_eq!(cell.len(), CELL_SIZE);
+        }
+        for hop in client.iter_mut() {
+            cell = hop.peel(&cell).unwrap();
+        }
+        assert_eq!(cell_command(&cell).unwrap(), b"extended");
+    }
+
+    #[test]
+    fn test_shorter_circuit_looks_the_same() {
+        // A cell for the first hop of a longer circuit and one that goes all the way are the
+        // same size, and neither carries its command in the clear
+        let (mut client, mut relays) = hops(1);
+        let cell = client[0].wrap(&new_cell(b"deliver").unwrap()).unwrap();
+        assert_eq!(cell.len(), CELL_SIZE);
+        assert!(!cell.windows(7).any(|w| w == b"deliver"));
+        assert_eq!(cell_command(&relays[0].peel(&cell).unwrap()).unwrap(), b"deliver");
+    }
+
+    #[test]
+    fn test_cell_changes_at_every_hop() {
+        let (mut client, mut relays) = hops(3);
+        let mut cell = new_cell(b"m").unwrap();
+        for hop in client.iter_mut().rev() {
+            cell = hop.wrap(&cell).unwrap();
+        }
+        for relay in relays.iter_mut().take(2) {
+            let next = relay.peel(&cell).unwrap();
+            let same = cell.iter().zip(&next).filter(|(a, b)| a == b).count();
+            assert!(same < CELL_SIZE / 64, "{} bytes unchanged", same);
+            cell = next;
+        }
+    }
+
+    #[test]
+    fn test_replay_rejected() {
+        let (mut client, mut relays) = hops(1);
+        let first = client[0].wrap(&new_cell(b"one").unwrap()).unwrap();
+        let second = client[0].wrap(&new_cell(b"two").unwrap()).unwrap();
+
+        // Out of order is fine, twice is not
+        relays[0].peel(&second).unwrap();
+        relays[0].peel(&first).unwrap();
+        assert!(relays[0].peel(&first).is_err());
+        assert!(relays[0].peel(&second).is_err());
+
+        // Nor is anything that fell out of the window
+        let old = client[0].wrap(&new_cell(b"old").unwrap()).unwrap();
+        for _ in 0..WINDOW {
+            let cell = client[0].wrap(&new_cell(b"m").unwrap()).unwrap();
+            relays[0].peel(&cell).unwrap();
+        }
+        assert!(relays[0].peel(&old).is_err());
+    }
+
+    #[test]
+    fn test_tampered_cell_rejected() {
+        let (mut client, mut relays) = hops(2);
+        let mut cell = new_cell(b"m").unwrap();
+        for hop in client.iter_mut().rev() {
+            cell = hop.wrap(&cell).unwrap();
+        }
+
+        let mut tampered = cell.clone();
+        tampered[CELL_SIZE - 1] ^= 1;
+        assert!(relays[0].peel(&tampered).is_err());
+        // The hop after checks its own MAC too
+        let mut peeled = relays[0].peel(&cell).unwrap();
+        peeled[CELL_SIZE - 1] ^= 1;
+        assert!(relays[1].peel(&peeled).is_err());
+
+        // Wrong hop, wrong size
+        assert!(relays[1].peel(&cell).is_err());
+        assert!(relays[0].peel(&cell[1..]).is_err());
+    }
+
+    #[test]
+    fn test_command_size_limit() {
+        assert!(new_cell(&vec![0; MAX_CELL_COMMAND]).is_ok());
+        assert!(new_cell(&vec![0; MAX_CELL_COMMAND + 1]).is_err());
+    }
+}
@@ -25,6 +25,12 @@ pub enum CryptoError {
     
     #[error("HPKE operation failed: {0}")]
     Hpke(String),
+    
+    #[error("Handshake peer mismatch: {0}")]
+    PeerMismatch(String),
+    
+    #[error("No session with peer: {0}")]
+    NoSession(String),
 }
 
 pub type Result<T> = std::result::Result<T, CryptoError>;
@@ -1,35 +1,56 @@
 // Hybrid handshake: X25519 + ML-KEM-768 + Ed25519 + Dilithium3
 // Always-on (Option C): No feature gates, full quantum resistance
+// Signatures and keys cover the whole transcript (see key_schedule)
 
-use crate::error::Result;
+use crate::error::{CryptoError, Result};
 use crate::kem::HybridKem;
-use crate::identity::IdentityKey;
-use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use crate::identity::{HybridSignature, HybridVerifyingKey, IdentityKey, PqPolicy};
+use crate::key_schedule::{HandshakeRole, SessionKeys, Transcript};
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use x25519_dalek::PublicKey;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HandshakeInit {
-    pub peer_id: Vec<u8>,
+    pub peer_id: Vec<u8>, // Sender's (initiator's) own PeerId
     pub x25519_pk: [u8; 32],
     pub pq_pk: Vec<u8>,
     #[serde(with = "serde_arrays")]
     pub signature: [u8; 64], // Ed25519 signature
     pub pq_signature: Vec<u8>, // Dilithium3 signature
     pub verify_key: [u8; 32], // Ed25519 public key
+    pub pq_verify_key: Vec<u8>, // Dilithium3 public key
+    pub key_confirmation: bool, // Initiator will send a HandshakeFinish
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HandshakeResp {
-    pub peer_id: Vec<u8>,
+    pub peer_id: Vec<u8>, // Sender's (responder's) own PeerId
     pub x25519_pk: [u8; 32],
     pub pq_ct: Vec<u8>,
     #[serde(with = "serde_arrays")]
     pub signature: [u8; 64], // Ed25519 signature
     pub pq_signature: Vec<u8>, // Dilithium3 signature
     pub verify_key: [u8; 32], // Ed25519 public key
+    pub pq_verify_key: Vec<u8>, // Dilithium3 public key
+    pub confirm_mac: [u8; 32], // Responder key-confirmation MAC
+}
+
+/// Optional third message: initiator proves it derived the same keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeFinish {
+    pub confirm_mac: [u8; 32], // Initiator key-confirmation MAC
+}
+
+impl HandshakeFinish {
+    pub fn new(keys: &SessionKeys) -> Self {
+        Self { confirm_mac: keys.confirmation_mac() }
+    }
+
+    /// Responder side: check the initiator's MAC
+    pub fn verify(&self, keys: &SessionKeys) -> Result<()> {
+        keys.verify_peer_confirmation(&self.confirm_mac)
+    }
 }
 
 mod serde_arrays {
@@ -55,124 +76,171 @@ mod serde_arrays {
 pub struct Handshake {
     identity: IdentityKey,
     kem: HybridKem,
+    pq_policy: PqPolicy,
+    key_confirmation: bool,
 }
 
 impl Handshake {
     pub fn new(identity: IdentityKey) -> Result<Self> {
         let kem = HybridKem::generate()?;
-        Ok(Self { identity, kem })
+        Ok(Self { identity, kem, pq_policy: PqPolicy::default(), key_confirmation: false })
     }
 
-    pub fn initiate(&self, peer_id: PeerId) -> Result<HandshakeInit> {
-        let peer_id_bytes = peer_id.to_bytes();
-        let x25519_pk = *self.kem.classical_public_key().as_bytes();
-        let verify_key = self.identity.verifying_key().to_bytes();
-        
-        let mut msg = Vec::new();
-        msg.extend_from_slice(&peer_id_bytes);
-        msg.extend_from_slice(&x25519_pk);
-        
-        let pq_pk = self.kem.pq_public_key()?;
-        msg.extend_from_slice(&pq_pk);
+    /// Set whether classical-only peers are accepted
+    pub fn with_pq_policy(mut self, policy: PqPolicy) -> Self {
+        self.pq_policy = policy;
+        self
+    }
+
+    /// Announce (in the signed init) that we'll send a `HandshakeFinish`
+    pub fn with_key_confirmation(mut self, enabled: bool) -> Self {
+        self.key_confirmation = enabled;
+        self
+    }
+
+    /// Start a handshake; `local_peer_id` is our own PeerId, which the responder checks
+    /// against the peer it's actually connected to
+    pub fn initiate(&self, local_peer_id: PeerId) -> Result<HandshakeInit> {
+        let mut init = HandshakeInit {
+            peer_id: local_peer_id.to_bytes(),
+            x25519_pk: *self.kem.classical_public_key().as_bytes(),
+            pq_pk: self.kem.pq_public_key()?,
+            signature: [0u8; 64],
+            pq_signature: Vec::new(),
+            verify_key: self.identity.verifying_key().to_bytes(),
+            pq_verify_key: self.identity.pq_verifying_key(),
+            key_confirmation: self.key_confirmation,
+        };
         
-        let hybrid_sig = self.identity.sign(&msg)?;
+        // Sign the transcript so far (protocol label + every init field)
+        let hybrid_sig = self.identity.sign(&Self::init_transcript(&init).hash())?;
+        init.signature = hybrid_sig.classical.try_into()
+            .map_err(|_| CryptoError::InvalidSignature("Invalid signature length".into()))?;
+        init.pq_signature = hybrid_sig.pq.unwrap_or_default();
         
-        Ok(HandshakeInit {
-            peer_id: peer_id_bytes,
-            x25519_pk,
-            pq_pk,
-            signature: hybrid_sig.classical.try_into()
-                .map_err(|_| crate::error::CryptoError::InvalidSignature("Invalid signature length".into()))?,
-            pq_signature: hybrid_sig.pq.unwrap_or_default(),
-            verify_key,
-        })
+        Ok(init)
     }
 
+    /// Answer an init received from `remote_peer_id` (as authenticated by the transport)
     pub fn respond(
         self,
-        peer_id: PeerId,
+        local_peer_id: PeerId,
+        remote_peer_id: PeerId,
         init: &HandshakeInit,
-        peer_verify_key: &VerifyingKey,
-    ) -> Result<(HandshakeResp, [u8; 32])> {
-        // Verify Ed25519 signature
-        let mut msg = Vec::new();
-        msg.extend_from_slice(&init.peer_id);
-        msg.extend_from_slice(&init.x25519_pk);
-        msg.extend_from_slice(&init.pq_pk);
-        
-        let sig = Signature::from_bytes(&init.signature);
-        peer_verify_key.verify(&msg, &sig)
-            .map_err(|e| crate::error::CryptoError::InvalidSignature(e.to_string()))?;
-        
-        // TODO: Verify Dilithium3 signature when pq feature is enabled
-        // For now, Ed25519 verification is sufficient
+        peer_verify_key: &HybridVerifyingKey,
+    ) -> Result<(HandshakeResp, SessionKeys)> {
+        // A relayed init names someone other than our peer; a reflected one names us
+        Self::check_sender(&init.peer_id, remote_peer_id, local_peer_id)?;
+
+        // Verify Ed25519 + Dilithium3 signatures over the init transcript
+        let mut transcript = Self::init_transcript(init);
+        let sig = Self::hybrid_signature(&init.signature, &init.pq_signature);
+        peer_verify_key.verify(&transcript.hash(), &sig, self.pq_policy)?;
         
         // Hybrid KEM encapsulation
         let peer_x25519_pk = PublicKey::from(init.x25519_pk);
-        
-        let (pq_ct, shared_secret) = {
-            let (ct, secret) = self.kem.encapsulate(&peer_x25519_pk, &init.pq_pk)?;
-            (ct, secret.as_bytes().to_vec())
-        };
-        
-        let session_key = Self::derive_key(&shared_secret);
+        let (pq_ct, shared_secret) = self.kem.encapsulate(&peer_x25519_pk, &init.pq_pk)?;
         
         // Create response
-        let peer_id_bytes = peer_id.to_bytes();
-        let x25519_pk = *self.kem.classical_public_key().as_bytes();
-        
-        let mut resp_msg = Vec::new();
-        resp_msg.extend_from_slice(&peer_id_bytes);
-        resp_msg.extend_from_slice(&x25519_pk);
-        resp_msg.extend_from_slice(&pq_ct);
-        
-        let hybrid_sig = self.identity.sign(&resp_msg)?;
-        let verify_key = self.identity.verifying_key().to_bytes();
-        
-        let resp = HandshakeResp {
-            peer_id: peer_id_bytes,
-            x25519_pk,
+        let mut resp = HandshakeResp {
+            peer_id: local_peer_id.to_bytes(),
+            x25519_pk: *self.kem.classical_public_key().as_bytes(),
             pq_ct,
-            signature: hybrid_sig.classical.try_into()
-                .map_err(|_| crate::error::CryptoError::InvalidSignature("Invalid signature length".into()))?,
-            pq_signature: hybrid_sig.pq.unwrap_or_default(),
-            verify_key,
+            signature: [0u8; 64],
+            pq_signature: Vec::new(),
+            verify_key: self.identity.verifying_key().to_bytes(),
+            pq_verify_key: self.identity.pq_verifying_key(),
+            confirm_mac: [0u8; 32],
         };
+        Self::append_resp(&mut transcript, &resp);
+        let transcript_hash = transcript.hash();
+        
+        let hybrid_sig = self.identity.sign(&transcript_hash)?;
+        resp.signature = hybrid_sig.classical.try_into()
+            .map_err(|_| CryptoError::InvalidSignature("Invalid signature length".into()))?;
+        resp.pq_signature = hybrid_sig.pq.unwrap_or_default();
         
-        Ok((resp, session_key))
+        let keys = SessionKeys::derive(shared_secret.as_bytes(), transcript_hash, HandshakeRole::Responder)?;
+        resp.confirm_mac = keys.confirmation_mac();
+        
+        Ok((resp, keys))
     }
 
+    /// Finish as initiator with a resp from `remote_peer_id`; `init` must be the message
+    /// this handshake sent
     pub fn complete(
         self,
+        remote_peer_id: PeerId,
+        init: &HandshakeInit,
         resp: &HandshakeResp,
-        peer_verify_key: &VerifyingKey,
-    ) -> Result<[u8; 32]> {
-        // Verify signature
-        let mut msg = Vec::new();
-        msg.extend_from_slice(&resp.peer_id);
-        msg.extend_from_slice(&resp.x25519_pk);
-        msg.extend_from_slice(&resp.pq_ct);
-        
-        let sig = Signature::from_bytes(&resp.signature);
-        peer_verify_key.verify(&msg, &sig)
-            .map_err(|e| crate::error::CryptoError::InvalidSignature(e.to_string()))?;
+        peer_verify_key: &HybridVerifyingKey,
+    ) -> Result<SessionKeys> {
+        if init.x25519_pk != *self.kem.classical_public_key().as_bytes() {
+            return Err(CryptoError::KeyDerivation(
+                "Init message was not created by this handshake".to_string(),
+            ));
+        }
+        let local_peer_id = PeerId::from_bytes(&init.peer_id)
+            .map_err(|_| CryptoError::PeerMismatch("Init carries no valid PeerId".to_string()))?;
+        Self::check_sender(&resp.peer_id, remote_peer_id, local_peer_id)?;
+        
+        // Verify Ed25519 + Dilithium3 signatures over the full transcript
+        let mut transcript = Self::init_transcript(init);
+        Self::append_resp(&mut transcript, resp);
+        let transcript_hash = transcript.hash();
+        
+        let sig = Self::hybrid_signature(&resp.signature, &resp.pq_signature);
+        peer_verify_key.verify(&transcript_hash, &sig, self.pq_policy)?;
         
         // Hybrid KEM decapsulation
         let peer_x25519_pk = PublicKey::from(resp.x25519_pk);
+        let shared_secret = self.kem.decapsulate(&peer_x25519_pk, &resp.pq_ct)?;
         
-        let shared_secret = {
-            let secret = self.kem.decapsulate(&peer_x25519_pk, &resp.pq_ct)?;
-            secret.as_bytes().to_vec()
-        };
+        // Responder must prove it derived the same keys
+        let keys = SessionKeys::derive(shared_secret.as_bytes(), transcript_hash, HandshakeRole::Initiator)?;
+        keys.verify_peer_confirmation(&resp.confirm_mac)?;
         
-        Ok(Self::derive_key(&shared_secret))
+        Ok(keys)
+    }
+
+    /// The PeerId a message claims must be the connected peer's, and never our own
+    fn check_sender(claimed: &[u8], remote_peer_id: PeerId, local_peer_id: PeerId) -> Result<()> {
+        if remote_peer_id == local_peer_id {
+            return Err(CryptoError::PeerMismatch("Handshake with ourselves".to_string()));
+        }
+        if claimed != remote_peer_id.to_bytes().as_slice() {
+            return Err(CryptoError::PeerMismatch(format!(
+                "Message names a different sender than {}",
+                remote_peer_id
+            )));
+        }
+        Ok(())
+    }
+
+    fn init_transcript(init: &HandshakeInit) -> Transcript {
+        let mut transcript = Transcript::new();
+        transcript.append(b"init peer_id", &init.peer_id);
+        transcript.append(b"init x25519_pk", &init.x25519_pk);
+        transcript.append(b"init pq_pk", &init.pq_pk);
+        transcript.append(b"init verify_key", &init.verify_key);
+        transcript.append(b"init pq_verify_key", &init.pq_verify_key);
+        transcript.append(b"init key_confirmation", &[init.key_confirmation as u8]);
+        transcript
+    }
+
+    fn append_resp(transcript: &mut Transcript, resp: &HandshakeResp) {
+        transcript.append(b"resp peer_id", &resp.peer_id);
+        transcript.append(b"resp x25519_pk", &resp.x25519_pk);
+        transcript.append(b"resp pq_ct", &resp.pq_ct);
+        transcript.append(b"resp verify_key", &resp.verify_key);
+        transcript.append(b"resp pq_verify_key", &resp.pq_verify_key);
     }
 
-    fn derive_key(shared: &[u8]) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(b"umbra-quantum-shield-v0.3");
-        hasher.update(shared);
-        hasher.finalize().into()
+    fn hybrid_signature(classical: &[u8; 64], pq: &[u8]) -> HybridSignature {
+        HybridSignature {
+            classical: classical.to_vec(),
+            pq: (!pq.is_empty()).then(|| pq.to_vec()),
+        }
     }
 }
 
@@ -184,43 +252,56 @@ mod tests {
         IdentityKey::generate().unwrap()
     }
 
+    fn peer(seed: u8) -> PeerId {
+        libp2p::identity::Keypair::ed25519_from_bytes([seed; 32]).unwrap().public().to_peer_id()
+    }
+
+    fn alice() -> PeerId {
+        peer(1)
+    }
+
+    fn bob() -> PeerId {
+        peer(2)
+    }
+
     #[test]
     fn test_handshake_flow() {
         let alice_id = gen_identity();
-        let alice_pk = *alice_id.verifying_key();
+        let alice_pk = alice_id.hybrid_verifying_key();
         let bob_id = gen_identity();
-        let bob_pk = *bob_id.verifying_key();
+        let bob_pk = bob_id.hybrid_verifying_key();
         
-        let alice_peer = PeerId::random();
-        let bob_peer = PeerId::random();
+        let alice_peer = alice();
+        let bob_peer = bob();
         
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let init = alice_hs.initiate(alice_peer).unwrap();
         
         let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-        let (resp, bob_key) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+        let (resp, bob_keys) = bob_hs.respond(bob_peer, alice_peer, &init, &alice_pk).unwrap();
         
         // FIX: Reuse the same alice_hs instance to preserve KEM keys
-        let alice_key = alice_hs.complete(&resp, &bob_pk).unwrap();
+        let alice_keys = alice_hs.complete(bob(), &init, &resp, &bob_pk).unwrap();
         
-        assert_eq!(alice_key.len(), 32);
-        assert_eq!(bob_key.len(), 32);
         // CRITICAL: Verify both sides derive the SAME session key
-        assert_eq!(alice_key, bob_key, "Alice and Bob must derive matching session keys!");
+        assert_eq!(alice_keys.session_key(), bob_keys.session_key(), "Alice and Bob must derive matching session keys!");
+        assert_eq!(alice_keys.send_key(), bob_keys.recv_key());
+        assert_eq!(alice_keys.recv_key(), bob_keys.send_key());
+        assert_eq!(alice_keys.transcript_hash(), bob_keys.transcript_hash());
     }
 
     #[test]
     fn test_invalid_signature() {
         let alice_id = gen_identity();
         let wrong_id = gen_identity();
-        let wrong_pk = *wrong_id.verifying_key();
+        let wrong_pk = wrong_id.hybrid_verifying_key();
         
-        let alice_peer = PeerId::random();
+        let alice_peer = alice();
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let init = alice_hs.initiate(alice_peer).unwrap();
         
         let bob_hs = Handshake::new(gen_identity()).unwrap();
-        let result = bob_hs.respond(PeerId::random(), &init, &wrong_pk);
+        let result = bob_hs.respond(bob(), alice(), &init, &wrong_pk);
         
         assert!(result.is_err());
     }
@@ -228,11 +309,11 @@ mod tests {
     #[test]
     fn test_tampered_public_key() {
         let alice_id = gen_identity();
-        let alice_pk = alice_id.verifying_key();
+        let alice_pk = &alice_id.hybrid_verifying_key();
         let bob_id = gen_identity();
         
-        let alice_peer = PeerId::random();
-        let bob_peer = PeerId::random();
+        let alice_peer = alice();
+        let bob_peer = bob();
         
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let mut init = alice_hs.initiate(alice_peer).unwrap();
@@ -241,7 +322,7 @@ mod tests {
         init.x25519_pk[0] ^= 0xFF;
         
         let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-        let result = bob_hs.respond(bob_peer, &init, &alice_pk);
+        let result = bob_hs.respond(bob_peer, alice_peer, &init, alice_pk);
         
         // Should fail signature verification
         assert!(result.is_err());
@@ -250,52 +331,79 @@ mod tests {
     #[test]
     fn test_signature_verification_in_response() {
         let alice_id = gen_identity();
-        let alice_pk = alice_id.verifying_key();
+        let alice_pk = &alice_id.hybrid_verifying_key();
         let bob_id = gen_identity();
         let wrong_id = gen_identity();
-        let wrong_pk = *wrong_id.verifying_key();
+        let wrong_pk = wrong_id.hybrid_verifying_key();
         
-        let alice_peer = PeerId::random();
-        let bob_peer = PeerId::random();
+        let alice_peer = alice();
+        let bob_peer = bob();
         
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let init = alice_hs.initiate(alice_peer).unwrap();
         
         let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-        let (resp, _) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+        let (resp, _) = bob_hs.respond(bob_peer, alice_peer, &init, alice_pk).unwrap();
         
         // Try to complete with wrong verification key
-        let alice_hs2 = Handshake::new(gen_identity()).unwrap();
-        let result = alice_hs2.complete(&resp, &wrong_pk);
+        let result = alice_hs.complete(bob(), &init, &resp, &wrong_pk);
         
         assert!(result.is_err());
     }
 
     #[test]
-    fn test_key_derivation_deterministic() {
-        let shared_secret = b"test_shared_secret_32_bytes_long";
-        let key1 = Handshake::derive_key(shared_secret);
-        let key2 = Handshake::derive_key(shared_secret);
+    fn test_tampered_confirmation_mac_rejected() {
+        let alice_id = gen_identity();
+        let bob_id = gen_identity();
+        
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+        
+        let bob_hs = Handshake::new(bob_id.clone()).unwrap();
+        let (mut resp, _) = bob_hs.respond(bob(), alice(), &init, &alice_id.hybrid_verifying_key()).unwrap();
+        resp.confirm_mac[0] ^= 0xFF;
         
-        assert_eq!(key1, key2);
-        assert_eq!(key1.len(), 32);
+        assert!(alice_hs.complete(bob(), &init, &resp, &bob_id.hybrid_verifying_key()).is_err());
     }
 
     #[test]
-    fn test_key_derivation_unique() {
-        let secret1 = b"secret1_________________________";
-        let secret2 = b"secret2_________________________";
+    fn test_response_bound_to_init() {
+        let alice_id = gen_identity();
+        let bob_id = gen_identity();
+        let alice_pk = alice_id.hybrid_verifying_key();
         
-        let key1 = Handshake::derive_key(secret1);
-        let key2 = Handshake::derive_key(secret2);
+        // Alice runs two handshakes; Bob answers the first one
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init1 = alice_hs.initiate(alice()).unwrap();
+        let init2 = alice_hs.initiate(peer(3)).unwrap();
         
-        assert_ne!(key1, key2);
+        let bob_hs = Handshake::new(bob_id.clone()).unwrap();
+        let (resp, _) = bob_hs.respond(bob(), alice(), &init1, &alice_pk).unwrap();
+        
+        // Responder's signature covers init1's peer_id, so it can't be spliced onto init2
+        assert!(alice_hs.complete(bob(), &init2, &resp, &bob_id.hybrid_verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_complete_rejects_foreign_init() {
+        let alice_id = gen_identity();
+        let bob_id = gen_identity();
+        
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+        
+        let bob_hs = Handshake::new(bob_id.clone()).unwrap();
+        let (resp, _) = bob_hs.respond(bob(), alice(), &init, &alice_id.hybrid_verifying_key()).unwrap();
+        
+        // A different handshake instance doesn't own init's ephemeral keys
+        let other_hs = Handshake::new(alice_id).unwrap();
+        assert!(other_hs.complete(bob(), &init, &resp, &bob_id.hybrid_verifying_key()).is_err());
     }
 
     #[test]
     fn test_serialization_roundtrip_init() {
         let alice_id = gen_identity();
-        let alice_peer = PeerId::random();
+        let alice_peer = alice();
         
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let init = alice_hs.initiate(alice_peer).unwrap();
@@ -312,17 +420,17 @@ mod tests {
     #[test]
     fn test_serialization_roundtrip_resp() {
         let alice_id = gen_identity();
-        let alice_pk = alice_id.verifying_key();
+        let alice_pk = &alice_id.hybrid_verifying_key();
         let bob_id = gen_identity();
         
-        let alice_peer = PeerId::random();
-        let bob_peer = PeerId::random();
+        let alice_peer = alice();
+        let bob_peer = bob();
         
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let init = alice_hs.initiate(alice_peer).unwrap();
         
         let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-        let (resp, _) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+        let (resp, _) = bob_hs.respond(bob_peer, alice_peer, &init, alice_pk).unwrap();
         
         // Serialize and deserialize
         let serialized = bincode::serialize(&resp).unwrap();
@@ -352,20 +460,158 @@ mod tests {
     #[test]
     fn test_handshake_key_is_32_bytes() {
         let alice_id = gen_identity();
-        let alice_pk = alice_id.verifying_key();
+        let alice_pk = &alice_id.hybrid_verifying_key();
         let bob_id = gen_identity();
         
-        let alice_peer = PeerId::random();
-        let bob_peer = PeerId::random();
+        let alice_peer = alice();
+        let bob_peer = bob();
         
         let alice_hs = Handshake::new(alice_id.clone()).unwrap();
         let init = alice_hs.initiate(alice_peer).unwrap();
         
         let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-        let (_, bob_key) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+        let (_, bob_keys) = bob_hs.respond(bob_peer, alice_peer, &init, alice_pk).unwrap();
+        let bob_key = bob_keys.session_key();
         
         assert_eq!(bob_key.len(), 32);
         // Ensure key is not all zeros
         assert!(bob_key.iter().any(|&b| b != 0));
     }
+
+    #[test]
+    fn test_handshake_carries_pq_verify_key() {
+        let alice_id = gen_identity();
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+
+        assert_eq!(init.pq_verify_key, alice_id.pq_verifying_key());
+        let parsed = HybridVerifyingKey::from_bytes(&init.verify_key, &init.pq_verify_key).unwrap();
+        assert_eq!(parsed, alice_id.hybrid_verifying_key());
+    }
+
+    #[test]
+    fn test_tampered_pq_signature_rejected() {
+        let alice_id = gen_identity();
+        let alice_pk = alice_id.hybrid_verifying_key();
+
+        let alice_hs = Handshake::new(alice_id).unwrap();
+        let mut init = alice_hs.initiate(alice()).unwrap();
+        init.pq_signature[10] ^= 0xFF;
+
+        // Ed25519 part is still valid, Dilithium3 part isn't
+        let bob_hs = Handshake::new(gen_identity()).unwrap();
+        assert!(bob_hs.respond(bob(), alice(), &init, &alice_pk).is_err());
+    }
+
+    #[test]
+    fn test_stripped_pq_signature_rejected() {
+        let alice_id = gen_identity();
+        let alice_pk = alice_id.hybrid_verifying_key();
+
+        let alice_hs = Handshake::new(alice_id).unwrap();
+        let mut init = alice_hs.initiate(alice()).unwrap();
+        init.pq_signature.clear();
+
+        let bob_hs = Handshake::new(gen_identity()).unwrap();
+        assert!(bob_hs.respond(bob(), alice(), &init, &alice_pk).is_err());
+    }
+
+    #[test]
+    fn test_pq_policy_rejects_classical_only_peer() {
+        let alice_id = gen_identity();
+        let classical_pk = HybridVerifyingKey::classical_only(*alice_id.verifying_key());
+
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+
+        // Lenient default accepts a peer we only know by Ed25519 key
+        let bob_hs = Handshake::new(gen_identity()).unwrap();
+        assert!(bob_hs.respond(bob(), alice(), &init, &classical_pk).is_ok());
+
+        let strict_hs = Handshake::new(gen_identity()).unwrap()
+            .with_pq_policy(PqPolicy::RequirePq);
+        assert!(strict_hs.respond(bob(), alice(), &init, &classical_pk).is_err());
+    }
+
+    #[test]
+    fn test_finish_confirms_initiator() {
+        let alice_id = gen_identity();
+        let bob_id = gen_identity();
+        
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap().with_key_confirmation(true);
+        let init = alice_hs.initiate(alice()).unwrap();
+        assert!(init.key_confirmation);
+        
+        let bob_hs = Handshake::new(bob_id.clone()).unwrap();
+        let (resp, bob_keys) = bob_hs.respond(bob(), alice(), &init, &alice_id.hybrid_verifying_key()).unwrap();
+        let alice_keys = alice_hs.complete(bob(), &init, &resp, &bob_id.hybrid_verifying_key()).unwrap();
+        
+        let finish = HandshakeFinish::new(&alice_keys);
+        assert!(finish.verify(&bob_keys).is_ok());
+        
+        let mut forged = finish.clone();
+        forged.confirm_mac[0] ^= 0xFF;
+        assert!(forged.verify(&bob_keys).is_err());
+        
+        // The responder's own MAC can't be reflected back as a finish
+        let reflected = HandshakeFinish { confirm_mac: resp.confirm_mac };
+        assert!(reflected.verify(&bob_keys).is_err());
+    }
+
+    #[test]
+    fn test_key_confirmation_flag_is_signed() {
+        let alice_id = gen_identity();
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap().with_key_confirmation(true);
+        let mut init = alice_hs.initiate(alice()).unwrap();
+        
+        // Stripping the flag would let a MITM skip the finish; the signature forbids it
+        init.key_confirmation = false;
+        let bob_hs = Handshake::new(gen_identity()).unwrap();
+        assert!(bob_hs.respond(bob(), alice(), &init, &alice_id.hybrid_verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_relayed_init_rejected() {
+        let alice_id = gen_identity();
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+
+        // Eve forwards Alice's signed init to Bob over her own connection
+        let eve = peer(3);
+        let bob_hs = Handshake::new(gen_identity()).unwrap();
+        let result = bob_hs.respond(bob(), eve, &init, &alice_id.hybrid_verifying_key());
+        assert!(matches!(result, Err(CryptoError::PeerMismatch(_))));
+    }
+
+    #[test]
+    fn test_reflected_init_rejected() {
+        let alice_id = gen_identity();
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+
+        // Bob bounces Alice's init straight back to her
+        let reflect_hs = Handshake::new(alice_id.clone()).unwrap();
+        let result = reflect_hs.respond(alice(), bob(), &init, &alice_id.hybrid_verifying_key());
+        assert!(matches!(result, Err(CryptoError::PeerMismatch(_))));
+
+        // ...or arrives at her on a connection to herself
+        let self_hs = Handshake::new(alice_id.clone()).unwrap();
+        let result = self_hs.respond(alice(), alice(), &init, &alice_id.hybrid_verifying_key());
+        assert!(matches!(result, Err(CryptoError::PeerMismatch(_))));
+    }
+
+    #[test]
+    fn test_relayed_resp_rejected() {
+        let alice_id = gen_identity();
+        let bob_id = gen_identity();
+        let alice_hs = Handshake::new(alice_id.clone()).unwrap();
+        let init = alice_hs.initiate(alice()).unwrap();
+
+        let bob_hs = Handshake::new(bob_id.clone()).unwrap();
+        let (resp, _) = bob_hs.respond(bob(), alice(), &init, &alice_id.hybrid_verifying_key()).unwrap();
+
+        // Alice talked to Eve, but the resp is Bob's
+        let result = alice_hs.complete(peer(3), &init, &resp, &bob_id.hybrid_verifying_key());
+        assert!(matches!(result, Err(CryptoError::PeerMismatch(_))));
+    }
 }
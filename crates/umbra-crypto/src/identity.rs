@@ -2,6 +2,7 @@ use crate::error::{CryptoError, Result};
 use ed25519_dalek::{Signer, Verifier, Signature, SigningKey, VerifyingKey};
 use pqcrypto_dilithium::dilithium3;
 use pqcrypto_traits::sign::{PublicKey as PqPublicKey, SecretKey as PqSecretKey, DetachedSignature as PqSignature};
+use zeroize::Zeroizing;
 
 /// Identity keypair with hybrid signatures (always-on) - Pure Rust!
 #[derive(Clone)]
@@ -28,6 +29,43 @@ impl IdentityKey {
         })
     }
     
+    /// Secret key material (Ed25519 seed || Dilithium3 secret || Dilithium3 public), for storing at rest
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(Self::encoded_len()));
+        bytes.extend_from_slice(self.classical_signing.as_bytes());
+        bytes.extend_from_slice(&self.pq_secret);
+        bytes.extend_from_slice(&self.pq_public);
+        bytes
+    }
+
+    /// Inverse of `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::encoded_len() {
+            return Err(CryptoError::InvalidKeyLength {
+                expected: Self::encoded_len(),
+                got: bytes.len(),
+            });
+        }
+        let (seed, pq) = bytes.split_at(32);
+        let (pq_secret, pq_public) = pq.split_at(dilithium3::secret_key_bytes());
+        dilithium3::SecretKey::from_bytes(pq_secret)
+            .map_err(|_| CryptoError::PostQuantum("Invalid secret key".to_string()))?;
+        dilithium3::PublicKey::from_bytes(pq_public)
+            .map_err(|_| CryptoError::PostQuantum("Invalid public key".to_string()))?;
+
+        let classical_signing = SigningKey::from_bytes(seed.try_into().expect("32-byte seed"));
+        Ok(Self {
+            classical_verifying: classical_signing.verifying_key(),
+            classical_signing,
+            pq_secret: pq_secret.to_vec(),
+            pq_public: pq_public.to_vec(),
+        })
+    }
+
+    fn encoded_len() -> usize {
+        32 + dilithium3::secret_key_bytes() + dilithium3::public_key_bytes()
+    }
+
     pub fn verifying_key(&self) -> &VerifyingKey {
         &self.classical_verifying
     }
@@ -35,6 +73,14 @@ impl IdentityKey {
     pub fn pq_verifying_key(&self) -> Vec<u8> {
         self.pq_public.clone()
     }
+
+    /// Public half of both keys, for sharing with peers
+    pub fn hybrid_verifying_key(&self) -> HybridVerifyingKey {
+        HybridVerifyingKey {
+            classical: self.classical_verifying,
+            pq: Some(self.pq_public.clone()),
+        }
+    }
     
     /// Sign a message with hybrid signature
     pub fn sign(&self, message: &[u8]) -> Result<HybridSignature> {
@@ -53,29 +99,10 @@ impl IdentityKey {
         })
     }
     
-    /// Verify a hybrid signature
+    /// Verify a hybrid signature (both parts must be present and valid)
     pub fn verify(&self, message: &[u8], signature: &HybridSignature) -> Result<()> {
-        // Verify classical signature
-        let sig = Signature::from_slice(&signature.classical)
-            .map_err(|_| CryptoError::SignatureVerification)?;
-        self.classical_verifying.verify(message, &sig)
-            .map_err(|_| CryptoError::SignatureVerification)?;
-        
-        // Verify PQ signature
-        if let Some(pq_sig_bytes) = &signature.pq {
-            // Reconstruct public key and signature from bytes
-            let pk = dilithium3::PublicKey::from_bytes(&self.pq_public)
-                .map_err(|_| CryptoError::PostQuantum("Invalid public key".to_string()))?;
-            
-            let sig = dilithium3::DetachedSignature::from_bytes(pq_sig_bytes)
-                .map_err(|_| CryptoError::PostQuantum("Invalid signature".to_string()))?;
-            
-            // Verify with Dilithium3 (pure Rust!)
-            dilithium3::verify_detached_signature(&sig, message, &pk)
-                .map_err(|_| CryptoError::SignatureVerification)?;
-        }
-        
-        Ok(())
+        self.hybrid_verifying_key()
+            .verify(message, signature, PqPolicy::RequirePq)
     }
 }
 
@@ -85,6 +112,94 @@ pub struct HybridSignature {
     pub pq: Option<Vec<u8>>,
 }
 
+/// Whether peers without a Dilithium3 key are accepted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PqPolicy {
+    /// Accept classical-only peers (Ed25519 is still verified)
+    #[default]
+    AllowClassical,
+    /// Reject peers that can't produce a valid Dilithium3 signature
+    RequirePq,
+}
+
+/// Peer's public identity: Ed25519 key plus optional Dilithium3 key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HybridVerifyingKey {
+    classical: VerifyingKey,
+    pq: Option<Vec<u8>>,
+}
+
+impl HybridVerifyingKey {
+    /// Build from wire bytes (empty `pq` means a classical-only peer)
+    pub fn from_bytes(classical: &[u8; 32], pq: &[u8]) -> Result<Self> {
+        let classical = VerifyingKey::from_bytes(classical)
+            .map_err(|e| CryptoError::InvalidSignature(format!("Invalid verify key: {}", e)))?;
+
+        if pq.is_empty() {
+            return Ok(Self::classical_only(classical));
+        }
+
+        dilithium3::PublicKey::from_bytes(pq)
+            .map_err(|_| CryptoError::PostQuantum("Invalid Dilithium3 public key".to_string()))?;
+
+        Ok(Self {
+            classical,
+            pq: Some(pq.to_vec()),
+        })
+    }
+
+    pub fn classical_only(classical: VerifyingKey) -> Self {
+        Self { classical, pq: None }
+    }
+
+    pub fn classical(&self) -> &VerifyingKey {
+        &self.classical
+    }
+
+    pub fn pq(&self) -> Option<&[u8]> {
+        self.pq.as_deref()
+    }
+
+    pub fn is_hybrid(&self) -> bool {
+        self.pq.is_some()
+    }
+
+    /// Verify a hybrid signature: Ed25519 always, Dilithium3 whenever we hold
+    /// a PQ key for the peer. A stripped PQ signature is never accepted.
+    pub fn verify(&self, message: &[u8], signature: &HybridSignature, policy: PqPolicy) -> Result<()> {
+        let sig = Signature::from_slice(&signature.classical)
+            .map_err(|_| CryptoError::SignatureVerification)?;
+        self.classical.verify(message, &sig)
+            .map_err(|_| CryptoError::SignatureVerification)?;
+
+        match (&self.pq, &signature.pq) {
+            (Some(pq_key), Some(pq_sig_bytes)) => {
+                let pk = dilithium3::PublicKey::from_bytes(pq_key)
+                    .map_err(|_| CryptoError::PostQuantum("Invalid public key".to_string()))?;
+
+                let sig = dilithium3::DetachedSignature::from_bytes(pq_sig_bytes)
+                    .map_err(|_| CryptoError::PostQuantum("Invalid signature".to_string()))?;
+
+                dilithium3::verify_detached_signature(&sig, message, &pk)
+                    .map_err(|_| CryptoError::PostQuantum("Dilithium3 signature verification failed".to_string()))
+            }
+            (Some(_), None) => Err(CryptoError::PostQuantum(
+                "Missing Dilithium3 signature".to_string(),
+            )),
+            (None, _) if policy == PqPolicy::RequirePq => Err(CryptoError::PostQuantum(
+                "Peer has no Dilithium3 key (classical-only peers rejected)".to_string(),
+            )),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+impl From<VerifyingKey> for HybridVerifyingKey {
+    fn from(classical: VerifyingKey) -> Self {
+        Self::classical_only(classical)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,4 +225,64 @@ mod tests {
         let signature = key.sign(message).unwrap();
         assert!(key.verify(b"wrong message", &signature).is_err());
     }
+
+    #[test]
+    fn test_verify_rejects_stripped_pq_signature() {
+        let key = IdentityKey::generate().unwrap();
+        let mut signature = key.sign(b"msg").unwrap();
+        signature.pq = None;
+
+        let public = key.hybrid_verifying_key();
+        assert!(public.verify(b"msg", &signature, PqPolicy::AllowClassical).is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_wrong_pq_key() {
+        let key = IdentityKey::generate().unwrap();
+        let other = IdentityKey::generate().unwrap();
+        let signature = key.sign(b"msg").unwrap();
+
+        // Correct Ed25519 key, someone else's Dilithium3 key
+        let public = HybridVerifyingKey::from_bytes(
+            key.verifying_key().as_bytes(),
+            &other.pq_verifying_key(),
+        ).unwrap();
+        assert!(public.verify(b"msg", &signature, PqPolicy::AllowClassical).is_err());
+    }
+
+    #[test]
+    fn test_classical_only_policy() {
+        let key = IdentityKey::generate().unwrap();
+        let signature = key.sign(b"msg").unwrap();
+        let public = HybridVerifyingKey::classical_only(*key.verifying_key());
+
+        assert!(public.verify(b"msg", &signature, PqPolicy::AllowClassical).is_ok());
+        assert!(public.verify(b"msg", &signature, PqPolicy::RequirePq).is_err());
+    }
+
+    #[test]
+    fn test_hybrid_key_from_bytes() {
+        let key = IdentityKey::generate().unwrap();
+        let public = HybridVerifyingKey::from_bytes(
+            key.verifying_key().as_bytes(),
+            &key.pq_verifying_key(),
+        ).unwrap();
+        assert_eq!(public, key.hybrid_verifying_key());
+
+        assert!(HybridVerifyingKey::from_bytes(key.verifying_key().as_bytes(), &[1, 2, 3]).is_err());
+        assert!(!HybridVerifyingKey::from_bytes(key.verifying_key().as_bytes(), &[]).unwrap().is_hybrid());
+    }
+
+    #[test]
+    fn test_identity_key_roundtrip() {
+        let key = IdentityKey::generate().unwrap();
+        let restored = IdentityKey::from_bytes(&key.to_bytes()).unwrap();
+        assert_eq!(restored.hybrid_verifying_key(), key.hybrid_verifying_key());
+
+        // The restored key signs for the original public key
+        let signature = restored.sign(b"msg").unwrap();
+        assert!(key.verify(b"msg", &signature).is_ok());
+
+        assert!(IdentityKey::from_bytes(&key.to_bytes()[1..]).is_err());
+    }
 }
@@ -1,9 +1,15 @@
 use crate::error::{CryptoError, Result};
 use x25519_dalek::{PublicKey, StaticSecret};
-use sha2::{Sha256, Digest};
+use hkdf::Hkdf;
+use sha2::Sha256;
 use pqcrypto_kyber::kyber768;
 use pqcrypto_traits::kem::{PublicKey as PqPublicKey, SecretKey as PqSecretKey, Ciphertext as PqCiphertext, SharedSecret as PqSharedSecret};
 
+extern "C" {
+    // Seeded key generation (coins = d || z), built into pqcrypto-kyber but not bound by it
+    fn PQCLEAN_KYBER768_CLEAN_crypto_kem_keypair_derand(pk: *mut u8, sk: *mut u8, coins: *const u8) -> i32;
+}
+
 /// Hybrid shared secret combining X25519 and ML-KEM outputs
 pub struct HybridSharedSecret {
     data: Vec<u8>,
@@ -32,6 +38,20 @@ pub struct HybridKem {
 }
 
 impl HybridKem {
+    /// X25519 key length
+    pub const CLASSICAL_KEY_LEN: usize = 32;
+    /// Kyber768 public key length
+    pub const PQ_PUBLIC_KEY_LEN: usize = 1184;
+    /// Kyber768 secret key length
+    pub const PQ_SECRET_KEY_LEN: usize = 2400;
+    /// Kyber768 ciphertext length
+    pub const PQ_CIPHERTEXT_LEN: usize = 1088;
+    /// Kyber768 key generation seed length (d || z)
+    pub const PQ_SEED_LEN: usize = 64;
+
+    /// Offset of the public key inside a Kyber768 secret key (after the IND-CPA secret)
+    const PQ_PUBLIC_KEY_OFFSET: usize = 1152;
+
     pub fn generate() -> Result<Self> {
         let classical_secret = StaticSecret::random_from_rng(rand::thread_rng());
         let classical_public = PublicKey::from(&classical_secret);
@@ -47,6 +67,54 @@ impl HybridKem {
         })
     }
     
+    /// Key pair fully determined by the X25519 secret and a Kyber768 key generation seed
+    pub fn from_seed(classical_secret: [u8; 32], pq_seed: &[u8; Self::PQ_SEED_LEN]) -> Result<Self> {
+        let mut pq_public = vec![0u8; Self::PQ_PUBLIC_KEY_LEN];
+        let mut pq_secret = vec![0u8; Self::PQ_SECRET_KEY_LEN];
+        // SAFETY: the buffers are the sizes Kyber768 writes and the seed the size it reads
+        let status = unsafe {
+            PQCLEAN_KYBER768_CLEAN_crypto_kem_keypair_derand(pq_public.as_mut_ptr(), pq_secret.as_mut_ptr(), pq_seed.as_ptr())
+        };
+        if status != 0 {
+            return Err(CryptoError::PostQuantum("Seeded key generation failed".to_string()));
+        }
+
+        let classical_secret = StaticSecret::from(classical_secret);
+        let classical_public = PublicKey::from(&classical_secret);
+
+        Ok(Self {
+            classical_secret,
+            classical_public,
+            pq_secret,
+            pq_public,
+        })
+    }
+
+    /// Rebuild from stored secret halves; the Kyber768 secret key embeds its public key
+    pub fn from_secret_bytes(classical_secret: [u8; 32], pq_secret: &[u8]) -> Result<Self> {
+        let sk = kyber768::SecretKey::from_bytes(pq_secret)
+            .map_err(|_| CryptoError::PostQuantum("Invalid secret key".to_string()))?;
+        let pq_public = sk.as_bytes()[Self::PQ_PUBLIC_KEY_OFFSET..Self::PQ_PUBLIC_KEY_OFFSET + Self::PQ_PUBLIC_KEY_LEN].to_vec();
+
+        let classical_secret = StaticSecret::from(classical_secret);
+        let classical_public = PublicKey::from(&classical_secret);
+
+        Ok(Self {
+            classical_secret,
+            classical_public,
+            pq_secret: pq_secret.to_vec(),
+            pq_public,
+        })
+    }
+
+    pub fn classical_secret_bytes(&self) -> zeroize::Zeroizing<[u8; 32]> {
+        zeroize::Zeroizing::new(self.classical_secret.to_bytes())
+    }
+
+    pub fn pq_secret_key(&self) -> &[u8] {
+        &self.pq_secret
+    }
+
     pub fn classical_public_key(&self) -> &PublicKey {
         &self.classical_public
     }
@@ -65,11 +133,13 @@ impl HybridKem {
         // Encapsulate (pure Rust!)
         let (pq_shared, ciphertext) = kyber768::encapsulate(&pq_pk);
         
-        let mut hasher = Sha256::new();
-        hasher.update(b"UMBRA-HYBRID-KEM");
-        hasher.update(classical_shared.as_bytes());
-        hasher.update(pq_shared.as_bytes());
-        let combined = hasher.finalize().to_vec();
+        let combined = Self::combine(
+            classical_shared.as_bytes(),
+            pq_shared.as_bytes(),
+            self.classical_public.as_bytes(),
+            peer_classical_pk.as_bytes(),
+            ciphertext.as_bytes(),
+        )?;
         
         Ok((ciphertext.as_bytes().to_vec(), HybridSharedSecret { data: combined }))
     }
@@ -87,14 +157,42 @@ impl HybridKem {
         // Decapsulate (pure Rust!)
         let pq_shared = kyber768::decapsulate(&ct, &pq_secret);
         
-        let mut hasher = Sha256::new();
-        hasher.update(b"UMBRA-HYBRID-KEM");
-        hasher.update(classical_shared.as_bytes());
-        hasher.update(pq_shared.as_bytes());
-        let combined = hasher.finalize().to_vec();
+        let combined = Self::combine(
+            classical_shared.as_bytes(),
+            pq_shared.as_bytes(),
+            peer_classical_pk.as_bytes(),
+            self.classical_public.as_bytes(),
+            pq_ciphertext,
+        )?;
         
         Ok(HybridSharedSecret { data: combined })
     }
+
+    /// HKDF combiner over both shared secrets, bound to the X25519 keys
+    /// (encapsulator first) and the ML-KEM ciphertext
+    fn combine(
+        classical_shared: &[u8],
+        pq_shared: &[u8],
+        encapsulator_pk: &[u8],
+        decapsulator_pk: &[u8],
+        pq_ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut ikm = zeroize::Zeroizing::new(Vec::with_capacity(classical_shared.len() + pq_shared.len()));
+        ikm.extend_from_slice(classical_shared);
+        ikm.extend_from_slice(pq_shared);
+
+        let mut info = Vec::with_capacity(16 + 64 + pq_ciphertext.len());
+        info.extend_from_slice(b"UMBRA-HYBRID-KEM-v2");
+        info.extend_from_slice(encapsulator_pk);
+        info.extend_from_slice(decapsulator_pk);
+        info.extend_from_slice(pq_ciphertext);
+
+        let mut out = vec![0u8; 32];
+        Hkdf::<Sha256>::new(None, &ikm)
+            .expand(&info, &mut out)
+            .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+        Ok(out)
+    }
 }
 
 #[cfg(test)]
@@ -111,7 +209,7 @@ mod tests {
     fn test_pq_public_key() {
         let kem = HybridKem::generate().unwrap();
         let pq_pk = kem.pq_public_key().unwrap();
-        assert!(pq_pk.len() > 0);
+        assert!(!pq_pk.is_empty());
     }
     
     
@@ -135,4 +233,64 @@ mod tests {
         assert_eq!(alice_shared.as_bytes(), bob_shared.as_bytes());
         assert_eq!(alice_shared.as_bytes().len(), 32);
     }
+
+    #[test]
+    fn test_key_lengths() {
+        assert_eq!(HybridKem::PQ_PUBLIC_KEY_LEN, kyber768::public_key_bytes());
+        assert_eq!(HybridKem::PQ_SECRET_KEY_LEN, kyber768::secret_key_bytes());
+        assert_eq!(HybridKem::PQ_CIPHERTEXT_LEN, kyber768::ciphertext_bytes());
+    }
+
+    #[test]
+    fn test_from_secret_bytes() {
+        let alice = HybridKem::generate().unwrap();
+        let bob = HybridKem::generate().unwrap();
+        let restored = HybridKem::from_secret_bytes(*bob.classical_secret_bytes(), bob.pq_secret_key()).unwrap();
+
+        assert_eq!(restored.classical_public_key(), bob.classical_public_key());
+        assert_eq!(restored.pq_public_key().unwrap(), bob.pq_public_key().unwrap());
+
+        let (ciphertext, alice_shared) = alice
+            .encapsulate(bob.classical_public_key(), &bob.pq_public_key().unwrap())
+            .unwrap();
+        let restored_shared = restored.decapsulate(alice.classical_public_key(), &ciphertext).unwrap();
+        assert_eq!(alice_shared.as_bytes(), restored_shared.as_bytes());
+
+        assert!(HybridKem::from_secret_bytes([0; 32], &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_from_seed_deterministic() {
+        let a = HybridKem::from_seed([1; 32], &[2; HybridKem::PQ_SEED_LEN]).unwrap();
+        let b = HybridKem::from_seed([1; 32], &[2; HybridKem::PQ_SEED_LEN]).unwrap();
+        let c = HybridKem::from_seed([1; 32], &[3; HybridKem::PQ_SEED_LEN]).unwrap();
+        assert_eq!(a.pq_public_key().unwrap(), b.pq_public_key().unwrap());
+        assert_eq!(a.pq_secret_key(), b.pq_secret_key());
+        assert_ne!(a.pq_public_key().unwrap(), c.pq_public_key().unwrap());
+
+        // A working key pair, consistent with the secret-key layout
+        let restored = HybridKem::from_secret_bytes(*a.classical_secret_bytes(), a.pq_secret_key()).unwrap();
+        assert_eq!(restored.pq_public_key().unwrap(), a.pq_public_key().unwrap());
+        let sender = HybridKem::generate().unwrap();
+        let (ciphertext, shared) = sender.encapsulate(a.classical_public_key(), &a.pq_public_key().unwrap()).unwrap();
+        let opened = a.decapsulate(sender.classical_public_key(), &ciphertext).unwrap();
+        assert_eq!(shared.as_bytes(), opened.as_bytes());
+    }
+
+    #[test]
+    fn test_hybrid_kem_binds_ciphertext() {
+        let alice = HybridKem::generate().unwrap();
+        let bob = HybridKem::generate().unwrap();
+        let bob_pq_pk = bob.pq_public_key().unwrap();
+
+        let (mut ciphertext, alice_shared) = alice.encapsulate(
+            bob.classical_public_key(),
+            &bob_pq_pk
+        ).unwrap();
+        ciphertext[0] ^= 0xFF;
+
+        // Kyber decapsulation never errors, the combined secret just differs
+        let bob_shared = bob.decapsulate(alice.classical_public_key(), &ciphertext).unwrap();
+        assert_ne!(alice_shared.as_bytes(), bob_shared.as_bytes());
+    }
 }
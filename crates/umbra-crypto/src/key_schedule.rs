@@ -0,0 +1,236 @@
+// Handshake key schedule: transcript hash + HKDF-SHA256
+// Every derived key is bound to both identities, both ephemeral keys and the ML-KEM exchange
+
+use crate::error::{CryptoError, Result};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Protocol/version label, first thing hashed into every transcript
+pub const PROTOCOL_LABEL: &[u8] = b"UMBRA-HANDSHAKE-v1";
+
+const INITIATOR_TO_RESPONDER: &[u8] = b"UMBRA-HS-KEY i2r";
+const RESPONDER_TO_INITIATOR: &[u8] = b"UMBRA-HS-KEY r2i";
+const SESSION_INFO: &[u8] = b"UMBRA-HS-KEY session";
+const CONFIRM_INFO: &[u8] = b"UMBRA-HS-KEY confirm";
+const INITIATOR_FINISHED: &[u8] = b"initiator finished";
+const RESPONDER_FINISHED: &[u8] = b"responder finished";
+
+/// Which side of the handshake we are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRole {
+    Initiator,
+    Responder,
+}
+
+/// Running hash over length-prefixed, labelled handshake fields
+#[derive(Clone)]
+pub struct Transcript {
+    hasher: Sha256,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        let mut transcript = Self { hasher: Sha256::new() };
+        transcript.append(b"protocol", PROTOCOL_LABEL);
+        transcript
+    }
+
+    /// Append a field (label and value are both length-prefixed, so fields can't run together)
+    pub fn append(&mut self, label: &[u8], data: &[u8]) {
+        self.hasher.update((label.len() as u32).to_be_bytes());
+        self.hasher.update(label);
+        self.hasher.update((data.len() as u64).to_be_bytes());
+        self.hasher.update(data);
+    }
+
+    /// Hash of everything appended so far
+    pub fn hash(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keys derived from a completed handshake
+pub struct SessionKeys {
+    role: HandshakeRole,
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    session_key: [u8; 32],
+    confirm_key: [u8; 32],
+    transcript_hash: [u8; 32],
+}
+
+impl SessionKeys {
+    /// HKDF-Extract(salt = transcript hash, ikm = hybrid shared secret), then one
+    /// Expand per purpose and direction
+    pub fn derive(shared_secret: &[u8], transcript_hash: [u8; 32], role: HandshakeRole) -> Result<Self> {
+        let hk = Hkdf::<Sha256>::new(Some(&transcript_hash), shared_secret);
+        let expand = |info: &[u8]| -> Result<[u8; 32]> {
+            let mut okm = [0u8; 32];
+            hk.expand(info, &mut okm)
+                .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+            Ok(okm)
+        };
+
+        let i2r = expand(INITIATOR_TO_RESPONDER)?;
+        let r2i = expand(RESPONDER_TO_INITIATOR)?;
+        let (send_key, recv_key) = match role {
+            HandshakeRole::Initiator => (i2r, r2i),
+            HandshakeRole::Responder => (r2i, i2r),
+        };
+
+        Ok(Self {
+            role,
+            send_key,
+            recv_key,
+            session_key: expand(SESSION_INFO)?,
+            confirm_key: expand(CONFIRM_INFO)?,
+            transcript_hash,
+        })
+    }
+
+    pub fn role(&self) -> HandshakeRole {
+        self.role
+    }
+
+    /// Key for traffic we send (the peer's `recv_key`)
+    pub fn send_key(&self) -> &[u8; 32] {
+        &self.send_key
+    }
+
+    /// Key for traffic we receive (the peer's `send_key`)
+    pub fn recv_key(&self) -> &[u8; 32] {
+        &self.recv_key
+    }
+
+    /// Direction-independent secret (identical on both sides, seeds the Double Ratchet)
+    pub fn session_key(&self) -> [u8; 32] {
+        self.session_key
+    }
+
+    pub fn transcript_hash(&self) -> &[u8; 32] {
+        &self.transcript_hash
+    }
+
+    /// Our key-confirmation MAC over the transcript
+    pub fn confirmation_mac(&self) -> [u8; 32] {
+        Self::mac(&self.confirm_key, &self.transcript_hash, self.role)
+    }
+
+    /// Check the peer's key-confirmation MAC (constant time)
+    pub fn verify_peer_confirmation(&self, mac: &[u8]) -> Result<()> {
+        let peer_role = match self.role {
+            HandshakeRole::Initiator => HandshakeRole::Responder,
+            HandshakeRole::Responder => HandshakeRole::Initiator,
+        };
+
+        let mut hmac = <HmacSha256 as Mac>::new_from_slice(&self.confirm_key)
+            .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+        hmac.update(Self::finished_label(peer_role));
+        hmac.update(&self.transcript_hash);
+        hmac.verify_slice(mac)
+            .map_err(|_| CryptoError::KeyDerivation("Key confirmation failed".to_string()))
+    }
+
+    fn mac(confirm_key: &[u8; 32], transcript_hash: &[u8; 32], role: HandshakeRole) -> [u8; 32] {
+        let mut hmac = <HmacSha256 as Mac>::new_from_slice(confirm_key)
+            .expect("HMAC accepts any key length");
+        hmac.update(Self::finished_label(role));
+        hmac.update(transcript_hash);
+        hmac.finalize().into_bytes().into()
+    }
+
+    fn finished_label(role: HandshakeRole) -> &'static [u8] {
+        match role {
+            HandshakeRole::Initiator => INITIATOR_FINISHED,
+            HandshakeRole::Responder => RESPONDER_FINISHED,
+        }
+    }
+}
+
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.send_key.zeroize();
+        self.recv_key.zeroize();
+        self.session_key.zeroize();
+        self.confirm_key.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(secret: &[u8], transcript: [u8; 32]) -> (SessionKeys, SessionKeys) {
+        (
+            SessionKeys::derive(secret, transcript, HandshakeRole::Initiator).unwrap(),
+            SessionKeys::derive(secret, transcript, HandshakeRole::Responder).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_directional_keys_mirror() {
+        let (alice, bob) = pair(b"shared", [1u8; 32]);
+
+        assert_eq!(alice.send_key(), bob.recv_key());
+        assert_eq!(alice.recv_key(), bob.send_key());
+        assert_ne!(alice.send_key(), alice.recv_key());
+        assert_eq!(alice.session_key(), bob.session_key());
+    }
+
+    #[test]
+    fn test_transcript_binds_keys() {
+        let (a, _) = pair(b"shared", [1u8; 32]);
+        let (b, _) = pair(b"shared", [2u8; 32]);
+
+        assert_ne!(a.session_key(), b.session_key());
+        assert_ne!(a.send_key(), b.send_key());
+    }
+
+    #[test]
+    fn test_confirmation_roundtrip() {
+        let (alice, bob) = pair(b"shared", [1u8; 32]);
+
+        assert!(bob.verify_peer_confirmation(&alice.confirmation_mac()).is_ok());
+        assert!(alice.verify_peer_confirmation(&bob.confirmation_mac()).is_ok());
+    }
+
+    #[test]
+    fn test_reflected_confirmation_rejected() {
+        let (alice, bob) = pair(b"shared", [1u8; 32]);
+
+        // A party's own MAC must never verify as the peer's
+        assert!(alice.verify_peer_confirmation(&alice.confirmation_mac()).is_err());
+        assert!(bob.verify_peer_confirmation(&bob.confirmation_mac()).is_err());
+    }
+
+    #[test]
+    fn test_confirmation_wrong_secret_rejected() {
+        let alice = SessionKeys::derive(b"shared", [1u8; 32], HandshakeRole::Initiator).unwrap();
+        let bob = SessionKeys::derive(b"other", [1u8; 32], HandshakeRole::Responder).unwrap();
+
+        assert!(bob.verify_peer_confirmation(&alice.confirmation_mac()).is_err());
+    }
+
+    #[test]
+    fn test_transcript_fields_unambiguous() {
+        let mut a = Transcript::new();
+        a.append(b"x", b"ab");
+        a.append(b"y", b"c");
+
+        let mut b = Transcript::new();
+        b.append(b"x", b"a");
+        b.append(b"y", b"bc");
+
+        assert_ne!(a.hash(), b.hash());
+    }
+}
@@ -5,22 +5,41 @@ pub mod aead;
 pub mod chat_crypto;
 pub mod session;
 pub mod handshake;
+pub mod key_schedule;
+pub mod ratchet;
+pub mod prekey;
+pub mod onion;
+pub mod cell;
+pub mod merkle;
+pub mod stream;
 
 pub use error::{CryptoError, Result};
 pub use kem::{HybridKem, HybridSharedSecret};
-pub use identity::{IdentityKey, HybridSignature};
+pub use identity::{IdentityKey, HybridSignature, HybridVerifyingKey, PqPolicy};
 pub use aead::Envelope;
 pub use chat_crypto::ChatCrypto;
 pub use session::{SessionManager, SessionKey};
-pub use handshake::{Handshake, HandshakeInit, HandshakeResp};
+pub use handshake::{Handshake, HandshakeFinish, HandshakeInit, HandshakeResp};
+pub use key_schedule::{HandshakeRole, SessionKeys, Transcript};
+pub use ratchet::{DoubleRatchet, Opened, RatchetConfig, RatchetHeader, RatchetMessage, RatchetRole};
+pub use prekey::{OneTimePrekey, PrekeyBundle, PrekeyInit, PrekeyStore};
+pub use onion::{HopKeys, OnionCreate, OnionPublicKey};
+pub use merkle::MerkleTree;
+pub use stream::{DecryptReader, EncryptWriter, StreamChunks, StreamDecryptor, StreamEncryptor};
 
 /// Re-export commonly used types
 pub mod prelude {
     pub use crate::error::{CryptoError, Result};
     pub use crate::kem::{HybridKem, HybridSharedSecret};
-    pub use crate::identity::{IdentityKey, HybridSignature};
+    pub use crate::identity::{IdentityKey, HybridSignature, HybridVerifyingKey, PqPolicy};
     pub use crate::aead::Envelope;
     pub use crate::chat_crypto::ChatCrypto;
     pub use crate::session::{SessionManager, SessionKey};
-    pub use crate::handshake::{Handshake, HandshakeInit, HandshakeResp};
+    pub use crate::handshake::{Handshake, HandshakeFinish, HandshakeInit, HandshakeResp};
+    pub use crate::key_schedule::{HandshakeRole, SessionKeys, Transcript};
+    pub use crate::ratchet::{DoubleRatchet, Opened, RatchetConfig, RatchetHeader, RatchetMessage, RatchetRole};
+    pub use crate::prekey::{OneTimePrekey, PrekeyBundle, PrekeyInit, PrekeyStore};
+    pub use crate::onion::{HopKeys, OnionCreate, OnionPublicKey};
+    pub use crate::merkle::MerkleTree;
+    pub use crate::stream::{DecryptReader, EncryptWriter, StreamChunks, StreamDecryptor, StreamEncryptor};
 }
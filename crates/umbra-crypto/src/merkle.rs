@@ -0,0 +1,135 @@
+// BLAKE3 Merkle trees over file chunks
+// Leaves and inner nodes are hashed under different derive-key contexts, so a leaf can never pass
+// for a node. A node without a sibling moves up a level unchanged.
+
+/// Hash of one chunk, as a tree leaf
+pub fn leaf_hash(chunk: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_derive_key("UMBRA merkle leaf v1");
+    hasher.update(chunk);
+    *hasher.finalize().as_bytes()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_derive_key("UMBRA merkle node v1");
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+/// Every level of a tree, leaves first, so proofs can be cut for any chunk
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Tree over `leaves` (at least one)
+    pub fn from_leaves(leaves: Vec<[u8; 32]>) -> Self {
+        assert!(!leaves.is_empty(), "a Merkle tree needs at least one leaf");
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<[u8; 32]> {
+        self.levels[0].get(index).copied()
+    }
+
+    /// Sibling hashes from leaf `index` up to the root
+    pub fn proof(&self, index: usize) -> Option<Vec<[u8; 32]>> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+        let mut proof = Vec::new();
+        let mut i = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = i ^ 1;
+            if sibling < level.len() {
+                proof.push(level[sibling]);
+            }
+            i /= 2;
+        }
+        Some(proof)
+    }
+}
+
+/// Check that `leaf` is leaf `index` of the `leaf_count`-leaf tree with this `root`
+pub fn verify_proof(root: &[u8; 32], index: usize, leaf_count: usize, leaf: &[u8; 32], proof: &[[u8; 32]]) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+    let mut hash = *leaf;
+    let mut siblings = proof.iter();
+    let (mut i, mut width) = (index, leaf_count);
+    while width > 1 {
+        let sibling = i ^ 1;
+        if sibling < width {
+            let Some(sibling_hash) = siblings.next() else {
+                return false;
+            };
+            hash = if i % 2 == 0 { node_hash(&hash, sibling_hash) } else { node_hash(sibling_hash, &hash) };
+        }
+        i /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<[u8; 32]> {
+        (0..n).map(|i| leaf_hash(&i.to_be_bytes())).collect()
+    }
+
+    #[test]
+    fn test_every_proof_verifies() {
+        for n in [1, 2, 3, 5, 8, 13] {
+            let tree = MerkleTree::from_leaves(leaves(n));
+            for (i, leaf) in leaves(n).iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(verify_proof(&tree.root(), i, n, leaf, &proof), "leaf {} of {}", i, n);
+            }
+            assert!(tree.proof(n).is_none());
+        }
+    }
+
+    #[test]
+    fn test_wrong_leaf_or_position_rejected() {
+        let tree = MerkleTree::from_leaves(leaves(7));
+        let proof = tree.proof(2).unwrap();
+        let leaf = leaves(7)[2];
+
+        assert!(!verify_proof(&tree.root(), 2, 7, &leaf_hash(b"forged"), &proof));
+        assert!(!verify_proof(&tree.root(), 3, 7, &leaf, &proof));
+        assert!(!verify_proof(&tree.root(), 7, 7, &leaf, &proof));
+        assert!(!verify_proof(&tree.root(), 2, 7, &leaf, &proof[..proof.len() - 1]));
+    }
+
+    #[test]
+    fn test_single_leaf_root() {
+        let leaf = leaf_hash(b"only chunk");
+        let tree = MerkleTree::from_leaves(vec![leaf]);
+        assert_eq!(tree.root(), leaf);
+        assert!(verify_proof(&leaf, 0, 1, &leaf, &[]));
+    }
+}
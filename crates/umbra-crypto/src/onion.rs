@@ -0,0 +1,198 @@
+// Onion routing hop keys
+// The client encapsulates to a relay's published onion key (X25519 + Kyber768) from a fresh
+// ephemeral key per hop. Both sides derive one key per direction over the transcript, and the
+// relay proves it holds the onion key with a confirmation MAC. Relay cells are layered with
+// `cell::CellLayer` under keys from the same session, so a relay can only peel (or add) its own
+// layer; messages sealed to a recipient are ChaCha20-Poly1305 envelopes.
+
+use crate::aead::Envelope;
+use crate::cell::CellLayer;
+use crate::error::Result;
+use crate::kem::HybridKem;
+use crate::key_schedule::{HandshakeRole, SessionKeys, Transcript};
+use x25519_dalek::PublicKey;
+use zeroize::Zeroizing;
+
+/// Public half of a node's onion key, as published in its relay descriptor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OnionPublicKey {
+    pub classical: [u8; 32],
+    pub pq: Vec<u8>,
+}
+
+impl OnionPublicKey {
+    pub fn of(onion_key: &HybridKem) -> Result<Self> {
+        Ok(Self {
+            classical: *onion_key.classical_public_key().as_bytes(),
+            pq: onion_key.pq_public_key()?,
+        })
+    }
+}
+
+/// What the client sends to set up a hop: its ephemeral key and the Kyber ciphertext
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OnionCreate {
+    pub ephemeral_key: [u8; 32],
+    pub pq_ciphertext: Vec<u8>,
+}
+
+/// Keys shared by the client and one relay of a circuit
+pub struct HopKeys {
+    keys: SessionKeys,
+    send: Envelope,
+    recv: Envelope,
+    cells: CellLayer,
+}
+
+impl HopKeys {
+    /// Client side: set up a hop to the holder of `onion_key`.
+    /// The hop is usable once the relay's confirmation checks out.
+    pub fn create(onion_key: &OnionPublicKey) -> Result<(OnionCreate, Self)> {
+        let ephemeral = HybridKem::generate()?;
+        let (pq_ciphertext, shared) = ephemeral.encapsulate(&PublicKey::from(onion_key.classical), &onion_key.pq)?;
+        let create = OnionCreate {
+            ephemeral_key: *ephemeral.classical_public_key().as_bytes(),
+            pq_ciphertext,
+        };
+        let hop = Self::derive(shared.as_bytes(), onion_key, &create, HandshakeRole::Initiator)?;
+        Ok((create, hop))
+    }
+
+    /// Relay side: derive the hop from a client's `create`
+    pub fn accept(onion_key: &HybridKem, create: &OnionCreate) -> Result<Self> {
+        let shared = onion_key.decapsulate(&PublicKey::from(create.ephemeral_key), &create.pq_ciphertext)?;
+        Self::derive(shared.as_bytes(), &OnionPublicKey::of(onion_key)?, create, HandshakeRole::Responder)
+    }
+
+    fn derive(shared: &[u8], onion_key: &OnionPublicKey, create: &OnionCreate, role: HandshakeRole) -> Result<Self> {
+        let mut transcript = Transcript::new();
+        transcript.append(b"onion key", &onion_key.classical);
+        transcript.append(b"onion pq key", &onion_key.pq);
+        transcript.append(b"onion ephemeral key", &create.ephemeral_key);
+        transcript.append(b"onion pq ciphertext", &create.pq_ciphertext);
+
+        let keys = SessionKeys::derive(shared, transcript.hash(), role)?;
+        let send = Envelope::new(keys.send_key())?;
+        let recv = Envelope::new(keys.recv_key())?;
+        let cells = CellLayer::new(keys.send_key(), keys.recv_key());
+        Ok(Self { keys, send, recv, cells })
+    }
+
+    /// Add our layer (client: toward the relay; relay: toward the client)
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.send.encrypt(plaintext)
+    }
+
+    /// Peel the other side's layer
+    pub fn open(&self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        self.recv.decrypt(data)
+    }
+
+    /// Add our layer to a relay cell (client: toward the relay; relay: toward the client)
+    pub fn wrap_cell(&mut self, cell: &[u8]) -> Result<Vec<u8>> {
+        self.cells.wrap(cell)
+    }
+
+    /// Peel the other side's layer off a relay cell; replays are refused
+    pub fn peel_cell(&mut self, cell: &[u8]) -> Result<Vec<u8>> {
+        self.cells.peel(cell)
+    }
+
+    /// Relay's proof that it holds the onion key
+    pub fn confirmation(&self) -> [u8; 32] {
+        self.keys.confirmation_mac()
+    }
+
+    /// Client: check the relay's confirmation
+    pub fn verify_confirmation(&self, mac: &[u8]) -> Result<()> {
+        self.keys.verify_peer_confirmation(mac)
+    }
+}
+
+/// Encrypt `plaintext` so only the holder of `onion_key` can read it (the exit of a circuit
+/// delivers it without learning who sent it)
+pub fn seal_to(onion_key: &OnionPublicKey, plaintext: &[u8]) -> Result<(OnionCreate, Vec<u8>)> {
+    let (create, hop) = HopKeys::create(onion_key)?;
+    let ciphertext = hop.seal(plaintext)?;
+    Ok((create, ciphertext))
+}
+
+/// Open what `seal_to` sealed for our onion key
+pub fn open_sealed(onion_key: &HybridKem, create: &OnionCreate, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    HopKeys::accept(onion_key, create)?.open(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hop_roundtrip() {
+        let relay_key = HybridKem::generate().unwrap();
+        let (create, client) = HopKeys::create(&OnionPublicKey::of(&relay_key).unwrap()).unwrap();
+        let relay = HopKeys::accept(&relay_key, &create).unwrap();
+        client.verify_confirmation(&relay.confirmation()).unwrap();
+
+        let forward = client.seal(b"toward the exit").unwrap();
+        assert_eq!(&**relay.open(&forward).unwrap(), b"toward the exit");
+        let backward = relay.seal(b"toward the client").unwrap();
+        assert_eq!(&**client.open(&backward).unwrap(), b"toward the client");
+
+        // Directions use different keys
+        assert!(relay.open(&backward).is_err());
+    }
+
+    #[test]
+    fn test_impostor_relay_fails_confirmation() {
+        let relay_key = HybridKem::generate().unwrap();
+        let (create, client) = HopKeys::create(&OnionPublicKey::of(&relay_key).unwrap()).unwrap();
+
+        let impostor = HopKeys::accept(&HybridKem::generate().unwrap(), &create).unwrap();
+        assert!(client.verify_confirmation(&impostor.confirmation()).is_err());
+    }
+
+    #[test]
+    fn test_three_layers_peeled_in_order() {
+        let relay_keys: Vec<HybridKem> = (0..3).map(|_| HybridKem::generate().unwrap()).collect();
+        let mut client_hops = Vec::new();
+        let mut relay_hops = Vec::new();
+        for key in &relay_keys {
+            let (create, hop) = HopKeys::create(&OnionPublicKey::of(key).unwrap()).unwrap();
+            relay_hops.push(HopKeys::accept(key, &create).unwrap());
+            client_hops.push(hop);
+        }
+
+        // Innermost layer for the last hop
+        let mut cell = b"deliver".to_vec();
+        for hop in client_hops.iter().rev() {
+            cell = hop.seal(&cell).unwrap();
+        }
+        for (i, relay) in relay_hops.iter().enumerate() {
+            // Nobody can skip ahead
+            if i + 1 < relay_hops.len() {
+                assert!(relay_hops[i + 1].open(&cell).is_err());
+            }
+            cell = relay.open(&cell).unwrap().to_vec();
+        }
+        assert_eq!(cell, b"deliver");
+
+        // And back: each relay adds a layer, the client peels them from the first hop out
+        let mut cell = b"delivered".to_vec();
+        for relay in relay_hops.iter().rev() {
+            cell = relay.seal(&cell).unwrap();
+        }
+        for hop in &client_hops {
+            cell = hop.open(&cell).unwrap().to_vec();
+        }
+        assert_eq!(cell, b"delivered");
+    }
+
+    #[test]
+    fn test_sealed_for_recipient_only() {
+        let recipient = HybridKem::generate().unwrap();
+        let (create, ciphertext) = seal_to(&OnionPublicKey::of(&recipient).unwrap(), b"sealed").unwrap();
+
+        assert_eq!(&**open_sealed(&recipient, &create, &ciphertext).unwrap(), b"sealed");
+        assert!(open_sealed(&HybridKem::generate().unwrap(), &create, &ciphertext).is_err());
+    }
+}
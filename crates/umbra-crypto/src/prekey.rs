@@ -0,0 +1,463 @@
+// Prekey bundles for asynchronous session setup (X3DH / PQXDH style)
+// A bundle publishes a signed X25519 + Kyber768 prekey and a batch of one-time X25519
+// prekeys, so a peer can derive session keys and send a first message while we're offline.
+// Authentication follows the live handshake: the bundle and the initiator's first message
+// are signed with the hybrid identity over the transcript, instead of X3DH's identity DH.
+//
+// shared = HybridKem(DH(EK, SPK), Kyber(PQPK)) || DH(EK, OPK)   (OPK when one was offered)
+
+use crate::error::{CryptoError, Result};
+use crate::identity::{HybridSignature, HybridVerifyingKey, IdentityKey, PqPolicy};
+use crate::kem::HybridKem;
+use crate::key_schedule::{HandshakeRole, SessionKeys, Transcript};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// One-time prekeys kept published
+pub const DEFAULT_ONE_TIME_PREKEYS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OneTimePrekey {
+    pub id: u32,
+    pub public_key: [u8; 32],
+}
+
+/// Everything a peer needs to start a session with us while we're offline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrekeyBundle {
+    pub verify_key: [u8; 32], // Ed25519 public key
+    pub pq_verify_key: Vec<u8>, // Dilithium3 public key
+    pub signed_prekey_id: u32,
+    pub signed_prekey: [u8; 32], // X25519
+    pub pq_prekey: Vec<u8>, // Kyber768, last-resort (shares the signed prekey's id)
+    pub one_time_prekeys: Vec<OneTimePrekey>,
+    pub signature: Vec<u8>, // Ed25519 over the bundle transcript
+    pub pq_signature: Vec<u8>, // Dilithium3 over the bundle transcript
+}
+
+impl PrekeyBundle {
+    /// Check the identity signature; returns the owner's verifying key
+    pub fn verify(&self) -> Result<HybridVerifyingKey> {
+        let verify_key = HybridVerifyingKey::from_bytes(&self.verify_key, &self.pq_verify_key)?;
+        let signature = hybrid_signature(&self.signature, &self.pq_signature);
+        verify_key.verify(&self.transcript().hash(), &signature, PqPolicy::RequirePq)?;
+        Ok(verify_key)
+    }
+
+    fn transcript(&self) -> Transcript {
+        let mut transcript = Transcript::new();
+        transcript.append(b"bundle verify_key", &self.verify_key);
+        transcript.append(b"bundle pq_verify_key", &self.pq_verify_key);
+        transcript.append(b"bundle signed_prekey_id", &self.signed_prekey_id.to_be_bytes());
+        transcript.append(b"bundle signed_prekey", &self.signed_prekey);
+        transcript.append(b"bundle pq_prekey", &self.pq_prekey);
+        for prekey in &self.one_time_prekeys {
+            transcript.append(b"bundle one_time_prekey_id", &prekey.id.to_be_bytes());
+            transcript.append(b"bundle one_time_prekey", &prekey.public_key);
+        }
+        transcript
+    }
+}
+
+/// Header of the first message to a peer, sent alongside the first ciphertext
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrekeyInit {
+    pub verify_key: [u8; 32], // Initiator's Ed25519 public key
+    pub pq_verify_key: Vec<u8>, // Initiator's Dilithium3 public key
+    pub signed_prekey_id: u32,
+    pub one_time_prekey_id: Option<u32>,
+    pub ephemeral_key: [u8; 32], // X25519
+    pub pq_ct: Vec<u8>, // Kyber768 ciphertext to the bundle's pq_prekey
+    pub signature: Vec<u8>,
+    pub pq_signature: Vec<u8>,
+}
+
+/// Initiator side: derive session keys against `bundle` (which must already be verified)
+pub fn initiate(identity: &IdentityKey, bundle: &PrekeyBundle) -> Result<(PrekeyInit, SessionKeys)> {
+    let ephemeral = HybridKem::generate()?;
+    let signed_prekey = PublicKey::from(bundle.signed_prekey);
+    let (pq_ct, hybrid_secret) = ephemeral.encapsulate(&signed_prekey, &bundle.pq_prekey)?;
+
+    let mut shared = Zeroizing::new(hybrid_secret.as_bytes().to_vec());
+    let one_time = bundle.one_time_prekeys.get(rand::random::<usize>() % bundle.one_time_prekeys.len().max(1));
+    if let Some(prekey) = one_time {
+        let secret = StaticSecret::from(*ephemeral.classical_secret_bytes());
+        shared.extend_from_slice(secret.diffie_hellman(&PublicKey::from(prekey.public_key)).as_bytes());
+    }
+
+    let mut init = PrekeyInit {
+        verify_key: identity.verifying_key().to_bytes(),
+        pq_verify_key: identity.pq_verifying_key(),
+        signed_prekey_id: bundle.signed_prekey_id,
+        one_time_prekey_id: one_time.map(|p| p.id),
+        ephemeral_key: *ephemeral.classical_public_key().as_bytes(),
+        pq_ct,
+        signature: Vec::new(),
+        pq_signature: Vec::new(),
+    };
+
+    let transcript_hash = init_transcript(bundle, &init).hash();
+    let signature = identity.sign(&transcript_hash)?;
+    init.signature = signature.classical;
+    init.pq_signature = signature.pq.unwrap_or_default();
+
+    let keys = SessionKeys::derive(&shared, transcript_hash, HandshakeRole::Initiator)?;
+    Ok((init, keys))
+}
+
+struct SignedPrekey {
+    id: u32,
+    kem: HybridKem,
+}
+
+impl SignedPrekey {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.id.to_be_bytes());
+        bytes.extend_from_slice(self.kem.classical_secret_bytes().as_ref());
+        bytes.extend_from_slice(self.kem.pq_secret_key());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self> {
+        let id = reader.u32()?;
+        let classical = Zeroizing::new(reader.array()?);
+        let kem = HybridKem::from_secret_bytes(*classical, reader.take(HybridKem::PQ_SECRET_KEY_LEN)?)?;
+        Ok(Self { id, kem })
+    }
+}
+
+/// Cursor over stored prekeys
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(CryptoError::InvalidKeyLength { expected: len, got: self.0.len() });
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("N bytes"))
+    }
+}
+
+/// Our prekey secrets; publishes bundles and answers `PrekeyInit`s
+pub struct PrekeyStore {
+    identity: IdentityKey,
+    signed_prekey: SignedPrekey,
+    /// Kept after a rotation so first messages still in flight can be opened
+    previous_signed_prekey: Option<SignedPrekey>,
+    one_time_prekeys: HashMap<u32, StaticSecret>,
+    next_id: u32,
+}
+
+impl PrekeyStore {
+    pub fn new(identity: IdentityKey) -> Result<Self> {
+        let mut store = Self {
+            identity,
+            signed_prekey: SignedPrekey { id: 0, kem: HybridKem::generate()? },
+            previous_signed_prekey: None,
+            one_time_prekeys: HashMap::new(),
+            next_id: 1,
+        };
+        store.replenish(DEFAULT_ONE_TIME_PREKEYS);
+        Ok(store)
+    }
+
+    pub fn identity(&self) -> &IdentityKey {
+        &self.identity
+    }
+
+    pub fn one_time_prekey_count(&self) -> usize {
+        self.one_time_prekeys.len()
+    }
+
+    /// Top the one-time prekeys back up to `target`
+    pub fn replenish(&mut self, target: usize) {
+        while self.one_time_prekeys.len() < target {
+            let id = self.allocate_id();
+            self.one_time_prekeys.insert(id, StaticSecret::random_from_rng(rand::thread_rng()));
+        }
+    }
+
+    /// Replace the signed prekey; the previous one keeps working until the next rotation
+    pub fn rotate_signed_prekey(&mut self) -> Result<()> {
+        let id = self.allocate_id();
+        let previous = std::mem::replace(&mut self.signed_prekey, SignedPrekey { id, kem: HybridKem::generate()? });
+        self.previous_signed_prekey = Some(previous);
+        Ok(())
+    }
+
+    /// Signed bundle of the current prekeys
+    pub fn bundle(&self) -> Result<PrekeyBundle> {
+        let mut one_time_prekeys: Vec<OneTimePrekey> = self
+            .one_time_prekeys
+            .iter()
+            .map(|(id, secret)| OneTimePrekey { id: *id, public_key: *PublicKey::from(secret).as_bytes() })
+            .collect();
+        one_time_prekeys.sort_by_key(|p| p.id);
+
+        let mut bundle = PrekeyBundle {
+            verify_key: self.identity.verifying_key().to_bytes(),
+            pq_verify_key: self.identity.pq_verifying_key(),
+            signed_prekey_id: self.signed_prekey.id,
+            signed_prekey: *self.signed_prekey.kem.classical_public_key().as_bytes(),
+            pq_prekey: self.signed_prekey.kem.pq_public_key()?,
+            one_time_prekeys,
+            signature: Vec::new(),
+            pq_signature: Vec::new(),
+        };
+        let signature = self.identity.sign(&bundle.transcript().hash())?;
+        bundle.signature = signature.classical;
+        bundle.pq_signature = signature.pq.unwrap_or_default();
+        Ok(bundle)
+    }
+
+    /// Responder side: verify `init`, consume its one-time prekey and derive the session keys.
+    /// Returns the initiator's verifying key alongside the keys.
+    pub fn accept(&mut self, init: &PrekeyInit) -> Result<(HybridVerifyingKey, SessionKeys)> {
+        let signed_prekey = [Some(&self.signed_prekey), self.previous_signed_prekey.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.id == init.signed_prekey_id)
+            .ok_or_else(|| CryptoError::KeyDerivation(format!("Unknown signed prekey {}", init.signed_prekey_id)))?;
+
+        // The transcript covers the bundle as the initiator saw it
+        let mut bundle = PrekeyBundle {
+            verify_key: self.identity.verifying_key().to_bytes(),
+            pq_verify_key: self.identity.pq_verifying_key(),
+            signed_prekey_id: signed_prekey.id,
+            signed_prekey: *signed_prekey.kem.classical_public_key().as_bytes(),
+            pq_prekey: signed_prekey.kem.pq_public_key()?,
+            one_time_prekeys: Vec::new(),
+            signature: Vec::new(),
+            pq_signature: Vec::new(),
+        };
+        let one_time_secret = match init.one_time_prekey_id {
+            Some(id) => {
+                let secret = self
+                    .one_time_prekeys
+                    .get(&id)
+                    .ok_or_else(|| CryptoError::KeyDerivation(format!("One-time prekey {} already used", id)))?;
+                bundle.one_time_prekeys.push(OneTimePrekey { id, public_key: *PublicKey::from(secret).as_bytes() });
+                Some(secret)
+            }
+            None => None,
+        };
+
+        let peer_key = HybridVerifyingKey::from_bytes(&init.verify_key, &init.pq_verify_key)?;
+        let transcript_hash = init_transcript(&bundle, init).hash();
+        peer_key.verify(&transcript_hash, &hybrid_signature(&init.signature, &init.pq_signature), PqPolicy::RequirePq)?;
+
+        let ephemeral = PublicKey::from(init.ephemeral_key);
+        let hybrid_secret = signed_prekey.kem.decapsulate(&ephemeral, &init.pq_ct)?;
+        let mut shared = Zeroizing::new(hybrid_secret.as_bytes().to_vec());
+        if let Some(secret) = one_time_secret {
+            shared.extend_from_slice(secret.diffie_hellman(&ephemeral).as_bytes());
+        }
+        let keys = SessionKeys::derive(&shared, transcript_hash, HandshakeRole::Responder)?;
+
+        // Only consumed once the message checks out, so a forged init can't burn prekeys
+        if let Some(id) = init.one_time_prekey_id {
+            self.one_time_prekeys.remove(&id);
+        }
+        Ok((peer_key, keys))
+    }
+
+    /// Secrets and id counter for storage:
+    /// next id || signed prekey || has previous (u8) || [previous] || count (u32) || (id || X25519 secret)*.
+    /// Each signed prekey is id || X25519 secret || Kyber768 secret.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        bytes.extend_from_slice(&self.next_id.to_be_bytes());
+        self.signed_prekey.encode(&mut bytes);
+        match &self.previous_signed_prekey {
+            Some(previous) => {
+                bytes.push(1);
+                previous.encode(&mut bytes);
+            }
+            None => bytes.push(0),
+        }
+        let mut ids: Vec<&u32> = self.one_time_prekeys.keys().collect();
+        ids.sort();
+        bytes.extend_from_slice(&(ids.len() as u32).to_be_bytes());
+        for id in ids {
+            bytes.extend_from_slice(&id.to_be_bytes());
+            bytes.extend_from_slice(self.one_time_prekeys[id].as_bytes());
+        }
+        bytes
+    }
+
+    /// Inverse of `to_bytes`, for the identity the prekeys were signed with
+    pub fn from_bytes(identity: IdentityKey, bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        let next_id = reader.u32()?;
+        let signed_prekey = SignedPrekey::decode(&mut reader)?;
+        let previous_signed_prekey = match reader.take(1)?[0] {
+            0 => None,
+            1 => Some(SignedPrekey::decode(&mut reader)?),
+            flag => return Err(CryptoError::KeyDerivation(format!("Invalid previous prekey flag {}", flag))),
+        };
+        let count = reader.u32()? as usize;
+        let mut one_time_prekeys = HashMap::new();
+        for _ in 0..count {
+            let id = reader.u32()?;
+            one_time_prekeys.insert(id, StaticSecret::from(reader.array()?));
+        }
+        if !reader.0.is_empty() {
+            return Err(CryptoError::KeyDerivation("Trailing bytes after prekeys".to_string()));
+        }
+        Ok(Self { identity, signed_prekey, previous_signed_prekey, one_time_prekeys, next_id })
+    }
+
+    fn allocate_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+}
+
+/// The responder's prekeys the initiator used (only the chosen one-time prekey), then the init
+fn init_transcript(bundle: &PrekeyBundle, init: &PrekeyInit) -> Transcript {
+    let mut transcript = Transcript::new();
+    transcript.append(b"prekey verify_key", &bundle.verify_key);
+    transcript.append(b"prekey pq_verify_key", &bundle.pq_verify_key);
+    transcript.append(b"prekey signed_prekey_id", &bundle.signed_prekey_id.to_be_bytes());
+    transcript.append(b"prekey signed_prekey", &bundle.signed_prekey);
+    transcript.append(b"prekey pq_prekey", &bundle.pq_prekey);
+    if let Some(id) = init.one_time_prekey_id {
+        let public_key = bundle.one_time_prekeys.iter().find(|p| p.id == id).map(|p| p.public_key).unwrap_or_default();
+        transcript.append(b"prekey one_time_prekey_id", &id.to_be_bytes());
+        transcript.append(b"prekey one_time_prekey", &public_key);
+    }
+    transcript.append(b"init verify_key", &init.verify_key);
+    transcript.append(b"init pq_verify_key", &init.pq_verify_key);
+    transcript.append(b"init ephemeral_key", &init.ephemeral_key);
+    transcript.append(b"init pq_ct", &init.pq_ct);
+    transcript
+}
+
+fn hybrid_signature(classical: &[u8], pq: &[u8]) -> HybridSignature {
+    HybridSignature {
+        classical: classical.to_vec(),
+        pq: (!pq.is_empty()).then(|| pq.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> PrekeyStore {
+        PrekeyStore::new(IdentityKey::generate().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_prekey_session_keys_match() {
+        let mut bob = store();
+        let bundle = bob.bundle().unwrap();
+        let bob_key = bundle.verify().unwrap();
+        assert_eq!(bob_key, bob.identity().hybrid_verifying_key());
+        assert_eq!(bundle.one_time_prekeys.len(), DEFAULT_ONE_TIME_PREKEYS);
+
+        let alice = IdentityKey::generate().unwrap();
+        let (init, alice_keys) = initiate(&alice, &bundle).unwrap();
+        let (alice_key, bob_keys) = bob.accept(&init).unwrap();
+
+        assert_eq!(alice_key, alice.hybrid_verifying_key());
+        assert_eq!(alice_keys.session_key(), bob_keys.session_key());
+        assert_eq!(alice_keys.send_key(), bob_keys.recv_key());
+        assert_eq!(bob.one_time_prekey_count(), DEFAULT_ONE_TIME_PREKEYS - 1);
+    }
+
+    #[test]
+    fn test_store_survives_encoding() {
+        let mut bob = store();
+        bob.rotate_signed_prekey().unwrap();
+        let bundle = bob.bundle().unwrap();
+        let alice = IdentityKey::generate().unwrap();
+        let (init, alice_keys) = initiate(&alice, &bundle).unwrap();
+
+        let mut restored = PrekeyStore::from_bytes(bob.identity().clone(), &bob.to_bytes()).unwrap();
+        let restored_bundle = restored.bundle().unwrap();
+        assert_eq!(restored_bundle.signed_prekey, bundle.signed_prekey);
+        assert_eq!(restored_bundle.pq_prekey, bundle.pq_prekey);
+        assert_eq!(restored_bundle.one_time_prekeys, bundle.one_time_prekeys);
+        let (_, bob_keys) = restored.accept(&init).unwrap();
+        assert_eq!(bob_keys.send_key(), alice_keys.recv_key());
+
+        let bytes = bob.to_bytes();
+        assert!(PrekeyStore::from_bytes(bob.identity().clone(), &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_one_time_prekey_single_use() {
+        let mut bob = store();
+        let bundle = bob.bundle().unwrap();
+        let (init, _) = initiate(&IdentityKey::generate().unwrap(), &bundle).unwrap();
+
+        bob.accept(&init).unwrap();
+        assert!(bob.accept(&init).is_err());
+    }
+
+    #[test]
+    fn test_without_one_time_prekeys() {
+        let mut bob = store();
+        let mut bundle = bob.bundle().unwrap();
+        bundle.one_time_prekeys.clear();
+
+        let (init, alice_keys) = initiate(&IdentityKey::generate().unwrap(), &bundle).unwrap();
+        assert_eq!(init.one_time_prekey_id, None);
+        let (_, bob_keys) = bob.accept(&init).unwrap();
+        assert_eq!(alice_keys.session_key(), bob_keys.session_key());
+    }
+
+    #[test]
+    fn test_tampered_bundle_rejected() {
+        let bob = store();
+        let mut bundle = bob.bundle().unwrap();
+        bundle.signed_prekey[0] ^= 0xFF;
+        assert!(bundle.verify().is_err());
+
+        let mut bundle = bob.bundle().unwrap();
+        bundle.one_time_prekeys.pop();
+        assert!(bundle.verify().is_err());
+    }
+
+    #[test]
+    fn test_tampered_init_rejected() {
+        let mut bob = store();
+        let bundle = bob.bundle().unwrap();
+        let (mut init, _) = initiate(&IdentityKey::generate().unwrap(), &bundle).unwrap();
+        init.ephemeral_key[0] ^= 0xFF;
+
+        assert!(bob.accept(&init).is_err());
+        // A rejected init leaves the one-time prekey in place
+        assert_eq!(bob.one_time_prekey_count(), DEFAULT_ONE_TIME_PREKEYS);
+    }
+
+    #[test]
+    fn test_rotation_keeps_previous_prekey() {
+        let mut bob = store();
+        let old_bundle = bob.bundle().unwrap();
+        bob.rotate_signed_prekey().unwrap();
+        let new_bundle = bob.bundle().unwrap();
+        assert_ne!(old_bundle.signed_prekey_id, new_bundle.signed_prekey_id);
+
+        let (init, alice_keys) = initiate(&IdentityKey::generate().unwrap(), &old_bundle).unwrap();
+        let (_, bob_keys) = bob.accept(&init).unwrap();
+        assert_eq!(alice_keys.session_key(), bob_keys.session_key());
+
+        bob.rotate_signed_prekey().unwrap();
+        let (init, _) = initiate(&IdentityKey::generate().unwrap(), &old_bundle).unwrap();
+        assert!(bob.accept(&init).is_err());
+    }
+}
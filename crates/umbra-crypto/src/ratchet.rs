@@ -0,0 +1,842 @@
+// Double Ratchet (symmetric chains + X25519 DH ratchet + periodic ML-KEM steps)
+// Seeded from the hybrid handshake session key, one fresh key per message
+
+use crate::error::{CryptoError, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use pqcrypto_kyber::kyber768;
+use pqcrypto_traits::kem::{Ciphertext as PqCiphertext, PublicKey as PqPublicKey, SecretKey as PqSecretKey, SharedSecret as PqSharedSecret};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+type HmacSha256 = Hmac<Sha256>;
+/// A skipped message key's slot: (sender ratchet key, message number)
+type KeyId = ([u8; 32], u32);
+
+/// Max message keys skipped within a single receiving chain
+pub const MAX_SKIP: u32 = 1000;
+/// Max skipped message keys kept across all chains (oldest evicted first)
+pub const MAX_SKIPPED_KEYS: usize = 2000;
+/// Default number of DH ratchet steps between ML-KEM ratchet steps
+pub const DEFAULT_PQ_INTERVAL: u32 = 20;
+
+const INIT_INFO: &[u8] = b"UMBRA-DR-INIT-v1";
+const ROOT_INFO: &[u8] = b"UMBRA-DR-ROOT-v1";
+const MSG_INFO: &[u8] = b"UMBRA-DR-MSG-v1";
+
+/// Which side of the session we are (decides the initial chains)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RatchetRole {
+    Initiator,
+    Responder,
+}
+
+/// Ratchet tuning knobs
+#[derive(Clone, Debug)]
+pub struct RatchetConfig {
+    /// Max keys skipped in one chain before a message is rejected
+    pub max_skip: u32,
+    /// Max skipped keys stored in total
+    pub max_skipped_keys: usize,
+    /// Mix an ML-KEM-768 secret into the root chain every N DH steps (None = DH only)
+    pub pq_interval: Option<u32>,
+}
+
+impl Default for RatchetConfig {
+    fn default() -> Self {
+        Self {
+            max_skip: MAX_SKIP,
+            max_skipped_keys: MAX_SKIPPED_KEYS,
+            pq_interval: Some(DEFAULT_PQ_INTERVAL),
+        }
+    }
+}
+
+/// Per-message header, sent in clear and authenticated as AEAD associated data
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatchetHeader {
+    /// Sender's current ratchet public key
+    pub dh: [u8; 32],
+    /// Messages sent in the sender's previous sending chain
+    pub pn: u32,
+    /// Message number in the current sending chain
+    pub n: u32,
+    /// Fresh ML-KEM-768 public key offered for the next PQ step
+    pub kem_pk: Option<Vec<u8>>,
+    /// ML-KEM-768 ciphertext answering our last offered key
+    pub kem_ct: Option<Vec<u8>>,
+}
+
+impl RatchetHeader {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self)
+            .map_err(|e| CryptoError::Encryption(format!("Header encode failed: {}", e)))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes)
+            .map_err(|e| CryptoError::Decryption(format!("Header decode failed: {}", e)))
+    }
+}
+
+/// Encrypted message produced by the ratchet
+#[derive(Clone, Debug)]
+pub struct RatchetMessage {
+    pub header: RatchetHeader,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Double Ratchet session state
+///
+/// Both sides are seeded from the same 32-byte handshake secret. The
+/// responder's first ratchet key is derived from that secret so the
+/// initiator can ratchet immediately and either side can send first.
+#[derive(Clone)]
+pub struct DoubleRatchet {
+    config: RatchetConfig,
+    root_key: [u8; 32],
+    dh_self: StaticSecret,
+    dh_remote: Option<PublicKey>,
+    chain_send: Option<[u8; 32]>,
+    chain_recv: Option<[u8; 32]>,
+    n_send: u32,
+    n_recv: u32,
+    prev_send: u32,
+    /// DH steps taken on our sending side (drives the PQ interval)
+    dh_steps: u32,
+    /// Our outstanding ML-KEM secret key, waiting for the peer's ciphertext
+    kem_secret: Option<Vec<u8>>,
+    /// KEM data attached to every header of the current sending chain
+    kem_pk_out: Option<Vec<u8>>,
+    kem_ct_out: Option<Vec<u8>>,
+    skipped: HashMap<KeyId, [u8; 32]>,
+    skipped_order: VecDeque<KeyId>,
+}
+
+impl DoubleRatchet {
+    pub fn new(shared_secret: &[u8; 32], role: RatchetRole) -> Result<Self> {
+        Self::with_config(shared_secret, role, RatchetConfig::default())
+    }
+
+    pub fn with_config(shared_secret: &[u8; 32], role: RatchetRole, config: RatchetConfig) -> Result<Self> {
+        // root key || responder's initial ratchet secret || responder's first sending chain
+        let mut okm = Zeroizing::new([0u8; 96]);
+        Hkdf::<Sha256>::new(None, shared_secret)
+            .expand(INIT_INFO, &mut okm[..])
+            .map_err(|e| CryptoError::KeyDerivation(format!("Ratchet init: {}", e)))?;
+
+        let mut root_key = [0u8; 32];
+        root_key.copy_from_slice(&okm[..32]);
+        let mut responder_secret = [0u8; 32];
+        responder_secret.copy_from_slice(&okm[32..64]);
+        let mut responder_chain = [0u8; 32];
+        responder_chain.copy_from_slice(&okm[64..]);
+
+        let responder_dh = StaticSecret::from(responder_secret);
+        responder_secret.zeroize();
+
+        let mut ratchet = Self {
+            config,
+            root_key,
+            dh_self: responder_dh,
+            dh_remote: None,
+            chain_send: None,
+            chain_recv: None,
+            n_send: 0,
+            n_recv: 0,
+            prev_send: 0,
+            dh_steps: 0,
+            kem_secret: None,
+            kem_pk_out: None,
+            kem_ct_out: None,
+            skipped: HashMap::new(),
+            skipped_order: VecDeque::new(),
+        };
+
+        match role {
+            RatchetRole::Responder => {
+                ratchet.chain_send = Some(responder_chain);
+            }
+            RatchetRole::Initiator => {
+                // Receive on the responder's initial chain, then ratchet our sending side
+                ratchet.dh_remote = Some(PublicKey::from(&ratchet.dh_self));
+                ratchet.chain_recv = Some(responder_chain);
+                let remote = PublicKey::from(&ratchet.dh_self);
+                let step = ratchet.send_step(&ratchet.root_key, &remote, false, None)?;
+                ratchet.apply_send(step);
+            }
+        }
+        responder_chain.zeroize();
+
+        Ok(ratchet)
+    }
+
+    /// Our current ratchet public key
+    pub fn public_key(&self) -> [u8; 32] {
+        *PublicKey::from(&self.dh_self).as_bytes()
+    }
+
+    /// Number of skipped message keys currently stored
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.len()
+    }
+
+    /// Encrypt with a fresh message key
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage> {
+        let chain = self.chain_send
+            .ok_or_else(|| CryptoError::Encryption("No sending chain".to_string()))?;
+        let (next_chain, message_key) = kdf_chain(&chain);
+        self.chain_send = Some(next_chain);
+
+        let header = RatchetHeader {
+            dh: self.public_key(),
+            pn: self.prev_send,
+            n: self.n_send,
+            kem_pk: self.kem_pk_out.clone(),
+            kem_ct: self.kem_ct_out.clone(),
+        };
+        self.n_send += 1;
+
+        let ciphertext = seal(&message_key, &header, plaintext)?;
+        Ok(RatchetMessage { header, ciphertext })
+    }
+
+    /// Decrypt a message; state only advances if authentication succeeds
+    pub fn decrypt(&mut self, message: &RatchetMessage) -> Result<Zeroizing<Vec<u8>>> {
+        let opened = self.open(message)?;
+        Ok(self.commit(opened))
+    }
+
+    /// Decrypt without advancing; the new receiving state is held in the result until
+    /// `commit`, so callers can run their own checks first. Commit (or drop) each opened
+    /// message before opening the next.
+    pub fn open(&self, message: &RatchetMessage) -> Result<Opened> {
+        let header = &message.header;
+
+        // Work out the new receiving state on the side, and only commit it once the message opens
+        let mut staged = Staged {
+            chain_recv: self.chain_recv,
+            n_recv: self.n_recv,
+            skipped: Vec::new(),
+            step: None,
+            consumed: None,
+        };
+
+        if let Some(key) = self.skipped.get(&(header.dh, header.n)) {
+            let plaintext = open(key, header, &message.ciphertext)?;
+            staged.consumed = Some((header.dh, header.n));
+            return Ok(Opened { plaintext, staged });
+        }
+
+        if self.dh_remote.map(|pk| *pk.as_bytes()) != Some(header.dh) {
+            staged.skip(self.config.max_skip, self.dh_remote, header.pn)?;
+            let (step, chain_recv) = self.receive_step(header)?;
+            staged.chain_recv = Some(chain_recv);
+            staged.n_recv = 0;
+            staged.step = Some(step);
+        }
+        let remote = staged.step.as_ref().map(|step| step.remote).or(self.dh_remote);
+        staged.skip(self.config.max_skip, remote, header.n)?;
+
+        let chain = staged.chain_recv
+            .ok_or_else(|| CryptoError::Decryption("No receiving chain".to_string()))?;
+        let (next_chain, mut message_key) = kdf_chain(&chain);
+        let plaintext = open(&message_key, header, &message.ciphertext);
+        message_key.zeroize();
+        let plaintext = plaintext?;
+
+        staged.chain_recv = Some(next_chain);
+        staged.n_recv += 1;
+        Ok(Opened { plaintext, staged })
+    }
+
+    /// Advance past an opened message and hand back its plaintext
+    pub fn commit(&mut self, opened: Opened) -> Zeroizing<Vec<u8>> {
+        let Opened { plaintext, mut staged } = opened;
+        self.apply(&mut staged);
+        plaintext
+    }
+
+    fn apply(&mut self, staged: &mut Staged) {
+        if let Some(id) = staged.consumed.take() {
+            if let Some(mut key) = self.take_skipped(&id.0, id.1) {
+                key.zeroize();
+            }
+            return;
+        }
+        if let Some(step) = staged.step.take() {
+            if step.used_kem {
+                if let Some(mut secret) = self.kem_secret.take() {
+                    secret.zeroize();
+                }
+            }
+            self.dh_remote = Some(step.remote);
+            self.prev_send = self.n_send;
+            self.n_send = 0;
+            self.apply_send(step.send);
+        }
+        self.chain_recv = staged.chain_recv;
+        self.n_recv = staged.n_recv;
+        for (id, key) in std::mem::take(&mut staged.skipped) {
+            self.store_skipped(id, key);
+        }
+    }
+
+    /// DH ratchet step triggered by a new remote ratchet key; returns the step and the new
+    /// receiving chain without touching our state
+    fn receive_step(&self, header: &RatchetHeader) -> Result<(ReceiveStep, [u8; 32])> {
+        let remote = PublicKey::from(header.dh);
+
+        let kem_shared = match &header.kem_ct {
+            Some(ct) => {
+                let secret = self.kem_secret.as_ref()
+                    .ok_or_else(|| CryptoError::PostQuantum("Unexpected KEM ciphertext".to_string()))?;
+                Some(kem_decapsulate(secret, ct)?)
+            }
+            None => None,
+        };
+
+        let dh_out = self.dh_self.diffie_hellman(&remote);
+        let (root_key, chain_recv) = kdf_root(
+            &self.root_key,
+            dh_out.as_bytes(),
+            kem_shared.as_ref().map(|s| s.as_slice()),
+        )?;
+
+        let used_kem = header.kem_ct.is_some();
+        let kem_outstanding = self.kem_secret.is_some() && !used_kem;
+        let send = self.send_step(&root_key, &remote, kem_outstanding, header.kem_pk.as_deref())?;
+        Ok((ReceiveStep { remote, used_kem, send }, chain_recv))
+    }
+
+    /// Fresh ratchet key and sending chain, with an ML-KEM step when due
+    fn send_step(
+        &self,
+        root_key: &[u8; 32],
+        remote: &PublicKey,
+        kem_outstanding: bool,
+        peer_kem_pk: Option<&[u8]>,
+    ) -> Result<SendStep> {
+        let dh_self = StaticSecret::random_from_rng(rand::thread_rng());
+
+        // Answer the peer's offered KEM key, if any
+        let (kem_ct_out, kem_shared) = match peer_kem_pk {
+            Some(pk) => {
+                let (ct, shared) = kem_encapsulate(pk)?;
+                (Some(ct), Some(shared))
+            }
+            None => (None, None),
+        };
+
+        // Offer a fresh KEM key every `pq_interval` steps (one outstanding at a time)
+        let mut kem_secret = None;
+        let mut kem_pk_out = None;
+        if let Some(interval) = self.config.pq_interval {
+            if interval > 0 && self.dh_steps % interval == 0 && !kem_outstanding {
+                let (pk, sk) = kyber768::keypair();
+                kem_secret = Some(Zeroizing::new(sk.as_bytes().to_vec()));
+                kem_pk_out = Some(pk.as_bytes().to_vec());
+            }
+        }
+
+        let dh_out = dh_self.diffie_hellman(remote);
+        let (root_key, chain_send) = kdf_root(
+            root_key,
+            dh_out.as_bytes(),
+            kem_shared.as_ref().map(|s| s.as_slice()),
+        )?;
+
+        Ok(SendStep {
+            root_key: Zeroizing::new(root_key),
+            chain_send: Zeroizing::new(chain_send),
+            dh_self,
+            kem_secret,
+            kem_pk_out,
+            kem_ct_out,
+        })
+    }
+
+    fn apply_send(&mut self, step: SendStep) {
+        self.root_key = *step.root_key;
+        self.chain_send = Some(*step.chain_send);
+        self.dh_self = step.dh_self;
+        if let Some(mut secret) = step.kem_secret {
+            self.kem_secret = Some(std::mem::take(&mut *secret));
+        }
+        self.kem_pk_out = step.kem_pk_out;
+        self.kem_ct_out = step.kem_ct_out;
+        self.dh_steps = self.dh_steps.wrapping_add(1);
+    }
+
+    fn store_skipped(&mut self, id: KeyId, key: [u8; 32]) {
+        if self.skipped.insert(id, key).is_none() {
+            self.skipped_order.push_back(id);
+        }
+
+        while self.skipped.len() > self.config.max_skipped_keys {
+            match self.skipped_order.pop_front() {
+                Some(oldest) => {
+                    if let Some(mut key) = self.skipped.remove(&oldest) {
+                        key.zeroize();
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn take_skipped(&mut self, dh: &[u8; 32], n: u32) -> Option<[u8; 32]> {
+        let key = self.skipped.remove(&(*dh, n))?;
+        self.skipped_order.retain(|id| id != &(*dh, n));
+        Some(key)
+    }
+}
+
+/// Sending-side state produced by a DH ratchet step
+struct SendStep {
+    root_key: Zeroizing<[u8; 32]>,
+    chain_send: Zeroizing<[u8; 32]>,
+    dh_self: StaticSecret,
+    /// Newly offered ML-KEM secret, if this step offers one
+    kem_secret: Option<Zeroizing<Vec<u8>>>,
+    kem_pk_out: Option<Vec<u8>>,
+    kem_ct_out: Option<Vec<u8>>,
+}
+
+struct ReceiveStep {
+    remote: PublicKey,
+    /// The header answered our outstanding ML-KEM key
+    used_kem: bool,
+    send: SendStep,
+}
+
+/// A decrypted message whose receiving-state changes wait for `DoubleRatchet::commit`
+#[must_use = "the ratchet only advances once the message is committed"]
+pub struct Opened {
+    pub plaintext: Zeroizing<Vec<u8>>,
+    staged: Staged,
+}
+
+/// Receiving-side changes for one message, applied only once it authenticates
+struct Staged {
+    chain_recv: Option<[u8; 32]>,
+    n_recv: u32,
+    skipped: Vec<(KeyId, [u8; 32])>,
+    step: Option<ReceiveStep>,
+    /// Skipped key the message was opened with
+    consumed: Option<KeyId>,
+}
+
+impl Staged {
+    /// Derive the message keys up to `until` so out-of-order messages still decrypt
+    fn skip(&mut self, max_skip: u32, remote: Option<PublicKey>, until: u32) -> Result<()> {
+        if until <= self.n_recv {
+            return Ok(());
+        }
+        if until - self.n_recv > max_skip {
+            return Err(CryptoError::Decryption(format!(
+                "Too many skipped messages ({} > {})",
+                until - self.n_recv,
+                max_skip
+            )));
+        }
+
+        let (Some(mut chain), Some(remote)) = (self.chain_recv, remote) else {
+            return Ok(());
+        };
+        let remote = *remote.as_bytes();
+
+        while self.n_recv < until {
+            let (next_chain, message_key) = kdf_chain(&chain);
+            chain = next_chain;
+            self.skipped.push(((remote, self.n_recv), message_key));
+            self.n_recv += 1;
+        }
+        self.chain_recv = Some(chain);
+
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if let Some(chain) = self.chain_recv.as_mut() {
+            chain.zeroize();
+        }
+        for (_, key) in self.skipped.iter_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+impl Drop for DoubleRatchet {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        if let Some(chain) = self.chain_send.as_mut() {
+            chain.zeroize();
+        }
+        if let Some(chain) = self.chain_recv.as_mut() {
+            chain.zeroize();
+        }
+        if let Some(secret) = self.kem_secret.as_mut() {
+            secret.zeroize();
+        }
+        for (_, key) in self.skipped.iter_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+/// KDF_RK: HKDF keyed by the root key over DH output (|| ML-KEM secret)
+fn kdf_root(root_key: &[u8; 32], dh_out: &[u8], kem_shared: Option<&[u8]>) -> Result<([u8; 32], [u8; 32])> {
+    let mut ikm = Zeroizing::new(Vec::with_capacity(64));
+    ikm.extend_from_slice(dh_out);
+    if let Some(shared) = kem_shared {
+        ikm.extend_from_slice(shared);
+    }
+
+    let mut okm = Zeroizing::new([0u8; 64]);
+    Hkdf::<Sha256>::new(Some(root_key), &ikm)
+        .expand(ROOT_INFO, &mut okm[..])
+        .map_err(|e| CryptoError::KeyDerivation(format!("Root KDF: {}", e)))?;
+
+    let mut root = [0u8; 32];
+    let mut chain = [0u8; 32];
+    root.copy_from_slice(&okm[..32]);
+    chain.copy_from_slice(&okm[32..]);
+    Ok((root, chain))
+}
+
+/// KDF_CK: HMAC-SHA256 chain step, returns (next chain key, message key)
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let step = |label: u8| -> [u8; 32] {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(chain_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(&[label]);
+        mac.finalize().into_bytes().into()
+    };
+    (step(0x02), step(0x01))
+}
+
+/// Expand a message key into an AEAD key and nonce (never reused)
+fn message_cipher(message_key: &[u8; 32]) -> Result<(ChaCha20Poly1305, [u8; 12])> {
+    let mut okm = Zeroizing::new([0u8; 44]);
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(MSG_INFO, &mut okm[..])
+        .map_err(|e| CryptoError::KeyDerivation(format!("Message KDF: {}", e)))?;
+
+    let cipher = ChaCha20Poly1305::new_from_slice(&okm[..32])
+        .map_err(|e| CryptoError::Encryption(format!("Key init failed: {}", e)))?;
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&okm[32..]);
+    Ok((cipher, nonce))
+}
+
+fn seal(message_key: &[u8; 32], header: &RatchetHeader, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let (cipher, nonce) = message_cipher(message_key)?;
+    let aad = header.to_bytes()?;
+    cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+        .map_err(|e| CryptoError::Encryption(format!("AEAD encrypt failed: {}", e)))
+}
+
+fn open(message_key: &[u8; 32], header: &RatchetHeader, ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let (cipher, nonce) = message_cipher(message_key)?;
+    let aad = header.to_bytes()?;
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: ciphertext, aad: &aad })
+        .map(Zeroizing::new)
+        .map_err(|e| CryptoError::Decryption(format!("AEAD decrypt failed: {}", e)))
+}
+
+fn kem_encapsulate(peer_pk: &[u8]) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+    let pk = kyber768::PublicKey::from_bytes(peer_pk)
+        .map_err(|_| CryptoError::PostQuantum("Invalid PQ public key".to_string()))?;
+    let (shared, ct) = kyber768::encapsulate(&pk);
+    Ok((ct.as_bytes().to_vec(), Zeroizing::new(shared.as_bytes().to_vec())))
+}
+
+fn kem_decapsulate(secret: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let sk = kyber768::SecretKey::from_bytes(secret)
+        .map_err(|_| CryptoError::PostQuantum("Invalid secret key".to_string()))?;
+    let ct = kyber768::Ciphertext::from_bytes(ciphertext)
+        .map_err(|_| CryptoError::PostQuantum("Invalid ciphertext".to_string()))?;
+    let shared = kyber768::decapsulate(&ct, &sk);
+    Ok(Zeroizing::new(shared.as_bytes().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [7u8; 32];
+
+    fn pair() -> (DoubleRatchet, DoubleRatchet) {
+        let alice = DoubleRatchet::new(&SECRET, RatchetRole::Initiator).unwrap();
+        let bob = DoubleRatchet::new(&SECRET, RatchetRole::Responder).unwrap();
+        (alice, bob)
+    }
+
+    fn roundtrip(from: &mut DoubleRatchet, to: &mut DoubleRatchet, text: &[u8]) {
+        let msg = from.encrypt(text).unwrap();
+        assert_eq!(&**to.decrypt(&msg).unwrap(), text);
+    }
+
+    #[test]
+    fn test_initiator_sends_first() {
+        let (mut alice, mut bob) = pair();
+        roundtrip(&mut alice, &mut bob, b"hi bob");
+        roundtrip(&mut bob, &mut alice, b"hi alice");
+        roundtrip(&mut alice, &mut bob, b"again");
+    }
+
+    #[test]
+    fn test_responder_sends_first() {
+        let (mut alice, mut bob) = pair();
+        roundtrip(&mut bob, &mut alice, b"bob first");
+        roundtrip(&mut alice, &mut bob, b"reply");
+        roundtrip(&mut bob, &mut alice, b"reply 2");
+    }
+
+    #[test]
+    fn test_per_message_keys() {
+        let (mut alice, _) = pair();
+        let m1 = alice.encrypt(b"same").unwrap();
+        let m2 = alice.encrypt(b"same").unwrap();
+
+        assert_ne!(m1.ciphertext, m2.ciphertext);
+        assert_eq!(m1.header.n + 1, m2.header.n);
+    }
+
+    #[test]
+    fn test_dh_key_changes_each_turn() {
+        let (mut alice, mut bob) = pair();
+        let a1 = alice.public_key();
+        roundtrip(&mut alice, &mut bob, b"1");
+        roundtrip(&mut bob, &mut alice, b"2");
+        roundtrip(&mut alice, &mut bob, b"3");
+
+        assert_ne!(alice.public_key(), a1);
+    }
+
+    #[test]
+    fn test_out_of_order_delivery() {
+        let (mut alice, mut bob) = pair();
+        let msgs: Vec<_> = (0..5u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+
+        assert_eq!(&**bob.decrypt(&msgs[3]).unwrap(), &[3]);
+        assert_eq!(bob.skipped_count(), 3);
+        assert_eq!(&**bob.decrypt(&msgs[0]).unwrap(), &[0]);
+        assert_eq!(&**bob.decrypt(&msgs[4]).unwrap(), &[4]);
+        assert_eq!(&**bob.decrypt(&msgs[2]).unwrap(), &[2]);
+        assert_eq!(&**bob.decrypt(&msgs[1]).unwrap(), &[1]);
+        assert_eq!(bob.skipped_count(), 0);
+    }
+
+    #[test]
+    fn test_out_of_order_across_ratchet_steps() {
+        let (mut alice, mut bob) = pair();
+        let early = alice.encrypt(b"old chain").unwrap();
+        roundtrip(&mut alice, &mut bob, b"sync");
+        roundtrip(&mut bob, &mut alice, b"turn");
+        let late = alice.encrypt(b"new chain").unwrap();
+
+        assert_eq!(&**bob.decrypt(&late).unwrap(), b"new chain");
+        assert_eq!(&**bob.decrypt(&early).unwrap(), b"old chain");
+    }
+
+    #[test]
+    fn test_too_many_skipped_rejected() {
+        let (mut alice, mut bob) = pair();
+        let first = alice.encrypt(b"0").unwrap();
+        let mut last = first.clone();
+        for _ in 0..=MAX_SKIP {
+            last = alice.encrypt(b"x").unwrap();
+        }
+
+        assert!(bob.decrypt(&last).is_err());
+        assert_eq!(bob.skipped_count(), 0);
+        // State untouched, the in-order message still works
+        assert_eq!(&**bob.decrypt(&first).unwrap(), b"0");
+    }
+
+    #[test]
+    fn test_skipped_keys_bounded() {
+        let config = RatchetConfig { max_skipped_keys: 10, ..RatchetConfig::default() };
+        let mut alice = DoubleRatchet::new(&SECRET, RatchetRole::Initiator).unwrap();
+        let mut bob = DoubleRatchet::with_config(&SECRET, RatchetRole::Responder, config).unwrap();
+
+        let msgs: Vec<_> = (0..50u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        bob.decrypt(&msgs[49]).unwrap();
+
+        assert_eq!(bob.skipped_count(), 10);
+        // Oldest keys were evicted, newest are still usable
+        assert!(bob.decrypt(&msgs[0]).is_err());
+        assert_eq!(&**bob.decrypt(&msgs[48]).unwrap(), &[48]);
+    }
+
+    #[test]
+    fn test_replay_rejected() {
+        let (mut alice, mut bob) = pair();
+        let msg = alice.encrypt(b"once").unwrap();
+
+        bob.decrypt(&msg).unwrap();
+        assert!(bob.decrypt(&msg).is_err());
+    }
+
+    #[test]
+    fn test_tampered_header_rejected_without_state_change() {
+        let (mut alice, mut bob) = pair();
+        let msg = alice.encrypt(b"payload").unwrap();
+
+        let mut forged = msg.clone();
+        forged.header.dh[0] ^= 0xFF;
+        assert!(bob.decrypt(&forged).is_err());
+
+        let mut bumped = msg.clone();
+        bumped.header.n += 1;
+        assert!(bob.decrypt(&bumped).is_err());
+        assert_eq!(bob.skipped_count(), 0);
+
+        assert_eq!(&**bob.decrypt(&msg).unwrap(), b"payload");
+    }
+
+    #[test]
+    fn test_opened_not_committed() {
+        let (mut alice, mut bob) = pair();
+        let first = alice.encrypt(b"first").unwrap();
+        let second = alice.encrypt(b"second").unwrap();
+
+        // Opening alone changes nothing, so the message can be opened again
+        let opened = bob.open(&second).unwrap();
+        assert_eq!(&**opened.plaintext, b"second");
+        drop(opened);
+        assert_eq!(bob.skipped_count(), 0);
+
+        assert_eq!(&**bob.decrypt(&second).unwrap(), b"second");
+        let opened = bob.open(&first).unwrap();
+        assert_eq!(bob.skipped_count(), 1);
+        assert_eq!(&**bob.commit(opened), b"first");
+        assert_eq!(bob.skipped_count(), 0);
+        assert!(bob.decrypt(&first).is_err());
+    }
+
+    #[test]
+    fn test_wrong_secret_fails() {
+        let mut alice = DoubleRatchet::new(&SECRET, RatchetRole::Initiator).unwrap();
+        let mut eve = DoubleRatchet::new(&[8u8; 32], RatchetRole::Responder).unwrap();
+
+        let msg = alice.encrypt(b"secret").unwrap();
+        assert!(eve.decrypt(&msg).is_err());
+    }
+
+    #[test]
+    fn test_forward_secrecy() {
+        let (mut alice, mut bob) = pair();
+        let captured: Vec<_> = (0..3u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        for msg in &captured {
+            bob.decrypt(msg).unwrap();
+        }
+        roundtrip(&mut bob, &mut alice, b"ack");
+
+        // Attacker steals both states after delivery
+        let mut stolen_bob = bob.clone();
+        let mut stolen_alice = alice.clone();
+
+        for msg in &captured {
+            assert!(stolen_bob.decrypt(msg).is_err(), "past messages must stay secret");
+            assert!(stolen_alice.decrypt(msg).is_err());
+        }
+    }
+
+    #[test]
+    fn test_post_compromise_security() {
+        let (mut alice, mut bob) = pair();
+        roundtrip(&mut alice, &mut bob, b"before");
+
+        // Full compromise of Bob's state, attacker keeps passively decrypting
+        let mut eve = bob.clone();
+        let m1 = alice.encrypt(b"still exposed").unwrap();
+        bob.decrypt(&m1).unwrap();
+        assert!(eve.decrypt(&m1).is_ok());
+
+        // Bob's current ratchet key was stolen too, so the next turn is still readable
+        roundtrip(&mut bob, &mut alice, b"turn");
+        let m2 = alice.encrypt(b"still exposed").unwrap();
+        bob.decrypt(&m2).unwrap();
+        assert!(eve.decrypt(&m2).is_ok());
+
+        // Bob then ratchets with fresh randomness the attacker never saw
+        roundtrip(&mut bob, &mut alice, b"heal");
+        let m3 = alice.encrypt(b"healed").unwrap();
+        assert_eq!(&**bob.decrypt(&m3).unwrap(), b"healed");
+        assert!(eve.decrypt(&m3).is_err(), "session must heal after a DH round trip");
+    }
+
+    #[test]
+    fn test_pq_ratchet_step() {
+        let config = RatchetConfig { pq_interval: Some(1), ..RatchetConfig::default() };
+        let mut alice = DoubleRatchet::with_config(&SECRET, RatchetRole::Initiator, config.clone()).unwrap();
+        let mut bob = DoubleRatchet::with_config(&SECRET, RatchetRole::Responder, config).unwrap();
+
+        let m1 = alice.encrypt(b"offer").unwrap();
+        assert!(m1.header.kem_pk.is_some());
+        bob.decrypt(&m1).unwrap();
+
+        let m2 = bob.encrypt(b"answer").unwrap();
+        assert!(m2.header.kem_ct.is_some());
+        assert_eq!(&**alice.decrypt(&m2).unwrap(), b"answer");
+
+        for i in 0..5u8 {
+            roundtrip(&mut alice, &mut bob, &[i]);
+            roundtrip(&mut bob, &mut alice, &[i]);
+        }
+    }
+
+    #[test]
+    fn test_pq_ratchet_disabled() {
+        let config = RatchetConfig { pq_interval: None, ..RatchetConfig::default() };
+        let mut alice = DoubleRatchet::with_config(&SECRET, RatchetRole::Initiator, config.clone()).unwrap();
+        let mut bob = DoubleRatchet::with_config(&SECRET, RatchetRole::Responder, config).unwrap();
+
+        let m1 = alice.encrypt(b"dh only").unwrap();
+        assert!(m1.header.kem_pk.is_none());
+        bob.decrypt(&m1).unwrap();
+        let m2 = bob.encrypt(b"dh only").unwrap();
+        assert!(m2.header.kem_ct.is_none());
+        alice.decrypt(&m2).unwrap();
+    }
+
+    #[test]
+    fn test_pq_step_binds_kem_secret() {
+        let config = RatchetConfig { pq_interval: Some(1), ..RatchetConfig::default() };
+        let mut alice = DoubleRatchet::with_config(&SECRET, RatchetRole::Initiator, config.clone()).unwrap();
+        let mut bob = DoubleRatchet::with_config(&SECRET, RatchetRole::Responder, config).unwrap();
+
+        bob.decrypt(&alice.encrypt(b"offer").unwrap()).unwrap();
+        let mut m2 = bob.encrypt(b"answer").unwrap();
+
+        // Swapping the KEM ciphertext must break the chain, not silently downgrade
+        let (other_pk, _) = kyber768::keypair();
+        let (forged_ct, _) = kem_encapsulate(other_pk.as_bytes()).unwrap();
+        m2.header.kem_ct = Some(forged_ct);
+        assert!(alice.decrypt(&m2).is_err());
+    }
+
+    #[test]
+    fn test_header_serialization_roundtrip() {
+        let (mut alice, _) = pair();
+        let msg = alice.encrypt(b"hdr").unwrap();
+
+        let bytes = msg.header.to_bytes().unwrap();
+        assert_eq!(RatchetHeader::from_bytes(&bytes).unwrap(), msg.header);
+        assert!(RatchetHeader::from_bytes(&[1, 2, 3]).is_err());
+    }
+}
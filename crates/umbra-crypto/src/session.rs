@@ -1,35 +1,68 @@
 // Simple session key management
-// No bullshit, just keys mapped to peers
+// No bullshit, just a Double Ratchet per peer
 
-use crate::error::Result;
-use crate::identity::IdentityKey;
+use crate::error::{CryptoError, Result};
+use crate::identity::{HybridSignature, HybridVerifyingKey, IdentityKey, PqPolicy};
+use crate::ratchet::{DoubleRatchet, Opened, RatchetMessage, RatchetRole};
 use ed25519_dalek::VerifyingKey;
 use libp2p::PeerId;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
-use zeroize::Zeroize;
+use zeroize::Zeroizing;
 
 const SESSION_TIMEOUT: Duration = Duration::from_secs(24 * 3600); // 24 hours
 const MAX_SESSIONS: usize = 1000; // Memory limit
 
-/// Session key for a peer
+/// Ratcheting session for a peer, seeded from a 32-byte session secret
 pub struct SessionKey {
-    key: [u8; 32],
+    ratchet: DoubleRatchet,
+    id: [u8; 32],
     created: Instant,
     msg_count: u64,
 }
 
 impl SessionKey {
-    fn new(key: [u8; 32]) -> Self {
-        Self {
-            key,
+    fn new(seed: [u8; 32], role: RatchetRole) -> Result<Self> {
+        let ratchet = DoubleRatchet::new(&seed, role)?;
+
+        // Public fingerprint of the seed (safe to log/compare, never a key)
+        let mut hasher = Sha256::new();
+        hasher.update(b"umbra-session-id-v1");
+        hasher.update(seed);
+
+        Ok(Self {
+            ratchet,
+            id: hasher.finalize().into(),
             created: Instant::now(),
             msg_count: 0,
-        }
+        })
+    }
+
+    /// Session fingerprint (identical on both sides of a session)
+    pub fn id(&self) -> &[u8; 32] {
+        &self.id
+    }
+
+    /// Encrypt with the next per-message key
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage> {
+        let message = self.ratchet.encrypt(plaintext)?;
+        self.increment();
+        Ok(message)
+    }
+
+    /// Decrypt a peer's message (state only advances on success)
+    pub fn decrypt(&mut self, message: &RatchetMessage) -> Result<Zeroizing<Vec<u8>>> {
+        self.ratchet.decrypt(message)
     }
 
-    pub fn key(&self) -> &[u8; 32] {
-        &self.key
+    /// Decrypt a peer's message without advancing; see `DoubleRatchet::open`
+    pub fn open(&self, message: &RatchetMessage) -> Result<Opened> {
+        self.ratchet.open(message)
+    }
+
+    pub fn commit(&mut self, opened: Opened) -> Zeroizing<Vec<u8>> {
+        self.ratchet.commit(opened)
     }
 
     pub fn age(&self) -> Duration {
@@ -44,14 +77,9 @@ impl SessionKey {
         self.msg_count
     }
 
+    /// Per-message keys come from the ratchet, so only age forces a new session
     pub fn should_rotate(&self) -> bool {
-        self.msg_count >= 1000 || self.age() >= SESSION_TIMEOUT
-    }
-}
-
-impl Drop for SessionKey {
-    fn drop(&mut self) {
-        self.key.zeroize();
+        self.age() >= SESSION_TIMEOUT
     }
 }
 
@@ -60,19 +88,30 @@ pub struct SessionManager {
     identity: IdentityKey,
     // Note: KEM instance removed as it's created per-handshake, not reused globally
     sessions: HashMap<PeerId, SessionKey>,
-    peer_keys: HashMap<PeerId, VerifyingKey>,
+    peer_keys: HashMap<PeerId, HybridVerifyingKey>,
     local_peer_id: PeerId,
+    pq_policy: PqPolicy,
 }
 
 impl SessionManager {
     pub fn new(local_peer_id: PeerId) -> Result<Self> {
-        let identity = IdentityKey::generate()?;
-        Ok(Self {
+        Ok(Self::with_identity(local_peer_id, IdentityKey::generate()?))
+    }
+
+    /// Use an identity loaded from storage, so peers see the same keys across restarts
+    pub fn with_identity(local_peer_id: PeerId, identity: IdentityKey) -> Self {
+        Self {
             identity,
             sessions: HashMap::new(),
             peer_keys: HashMap::new(),
             local_peer_id,
-        })
+            pq_policy: PqPolicy::default(),
+        }
+    }
+
+    /// Our identity (shared with the handshake so peers verify one key pair)
+    pub fn identity(&self) -> &IdentityKey {
+        &self.identity
     }
 
     /// Get our public identity key (for sharing with peers)
@@ -80,18 +119,42 @@ impl SessionManager {
         self.identity.verifying_key()
     }
 
-    /// Register a peer's public key
+    /// Get our Ed25519 + Dilithium3 public keys
+    pub fn hybrid_public_key(&self) -> HybridVerifyingKey {
+        self.identity.hybrid_verifying_key()
+    }
+
+    pub fn pq_policy(&self) -> PqPolicy {
+        self.pq_policy
+    }
+
+    /// Set whether classical-only peers are accepted by `verify_hybrid`
+    pub fn set_pq_policy(&mut self, policy: PqPolicy) {
+        self.pq_policy = policy;
+    }
+
+    /// Register a peer's public key (classical-only)
     pub fn register_peer(&mut self, peer: PeerId, verify_key: VerifyingKey) {
+        self.peer_keys.insert(peer, HybridVerifyingKey::classical_only(verify_key));
+    }
+
+    /// Register a peer's Ed25519 + Dilithium3 public keys
+    pub fn register_peer_hybrid(&mut self, peer: PeerId, verify_key: HybridVerifyingKey) {
         self.peer_keys.insert(peer, verify_key);
     }
 
     /// Get peer's public key for verification
     pub fn get_peer_key(&self, peer: &PeerId) -> Option<&VerifyingKey> {
+        self.peer_keys.get(peer).map(|k| k.classical())
+    }
+
+    /// Get peer's full hybrid public key
+    pub fn get_peer_hybrid_key(&self, peer: &PeerId) -> Option<&HybridVerifyingKey> {
         self.peer_keys.get(peer)
     }
 
     /// Sign data with our identity key (returns hybrid signature)
-    pub fn sign(&self, data: &[u8]) -> Result<crate::identity::HybridSignature> {
+    pub fn sign(&self, data: &[u8]) -> Result<HybridSignature> {
         self.identity.sign(data)
     }
 
@@ -103,71 +166,60 @@ impl SessionManager {
                 "Peer key not registered".to_string()
             ))?;
         
-        peer_key.verify(data, signature)
+        peer_key.classical().verify(data, signature)
             .map_err(|e| crate::error::CryptoError::InvalidSignature(e.to_string()))?;
         
         Ok(())
     }
 
-    /// Get or create session for peer
+    /// Verify a hybrid signature from a peer (Ed25519 and Dilithium3 must both pass)
+    pub fn verify_hybrid(&self, peer: &PeerId, data: &[u8], signature: &HybridSignature) -> Result<()> {
+        let peer_key = self.peer_keys.get(peer)
+            .ok_or(crate::error::CryptoError::KeyDerivation(
+                "Peer key not registered".to_string()
+            ))?;
+
+        peer_key.verify(data, signature, self.pq_policy)
+    }
+
+    /// Live session for peer, set up by a handshake or prekey exchange
     pub fn get_session(&mut self, peer: PeerId) -> Result<&mut SessionKey> {
-        // Check if exists and still valid
-        if let Some(session) = self.sessions.get(&peer) {
-            if !session.should_rotate() {
-                // Safe: we just checked the session exists
-                return Ok(self.sessions.get_mut(&peer)
-                    .expect("session exists from previous get check"));
-            }
-            // Expired, remove it
+        // An expired session needs a fresh handshake, never a fallback key
+        if self.sessions.get(&peer).is_some_and(|s| s.should_rotate()) {
             self.sessions.remove(&peer);
         }
 
-        // For now, derive from peer ID (handshake will replace this)
-        // TODO: Initiate handshake here instead
-        let key = self.derive_session_key(&peer);
-        self.sessions.insert(peer, SessionKey::new(key));
-
-        // Enforce memory limit
-        if self.sessions.len() > MAX_SESSIONS {
-            self.evict_oldest();
-        }
+        self.sessions.get_mut(&peer)
+            .ok_or_else(|| CryptoError::NoSession(peer.to_string()))
+    }
 
-        // Safe: we just inserted the session above
-        Ok(self.sessions.get_mut(&peer)
-            .expect("session exists from insert above"))
+    /// Whether we hold a live session for peer
+    pub fn has_session(&self, peer: &PeerId) -> bool {
+        self.sessions.get(peer).is_some_and(|s| !s.should_rotate())
     }
 
-    /// Set session key from handshake (replaces symmetric derivation)
-    pub fn set_session_key(&mut self, peer: PeerId, key: [u8; 32]) {
-        self.sessions.insert(peer, SessionKey::new(key));
+    /// Seed the peer's ratchet from a handshake or prekey session key
+    pub fn set_session_key(&mut self, peer: PeerId, key: [u8; 32]) -> Result<()> {
+        let key = Zeroizing::new(key);
+        let session = SessionKey::new(*key, self.role_for(&peer))?;
+        self.sessions.insert(peer, session);
         
         // Enforce memory limit
         if self.sessions.len() > MAX_SESSIONS {
             self.evict_oldest();
         }
+
+        Ok(())
     }
 
-    /// Temporary: derive key from both peer IDs (symmetric)
-    /// TODO: Replace with proper handshake key exchange
-    fn derive_session_key(&self, peer: &PeerId) -> [u8; 32] {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(b"umbra-session-v1");
-        
-        // Create symmetric key by ordering peer IDs
-        let peer1 = self.local_peer_id.to_bytes();
-        let peer2 = peer.to_bytes();
-        
-        // Sort to ensure same order on both sides
-        if peer1.as_slice() < peer2.as_slice() {
-            hasher.update(&peer1);
-            hasher.update(&peer2);
+    /// Ratchet role from PeerId order, so both sides agree without extra messages
+    /// (even when both ends start a handshake at the same time)
+    fn role_for(&self, peer: &PeerId) -> RatchetRole {
+        if self.local_peer_id.to_bytes() < peer.to_bytes() {
+            RatchetRole::Initiator
         } else {
-            hasher.update(&peer2);
-            hasher.update(&peer1);
+            RatchetRole::Responder
         }
-        
-        hasher.finalize().into()
     }
 
     /// Remove oldest session when over limit
@@ -196,10 +248,17 @@ impl SessionManager {
 mod tests {
     use super::*;
 
+    /// A peer with a session seeded from a fresh random key
+    fn seeded(mgr: &mut SessionManager) -> PeerId {
+        let peer = PeerId::random();
+        mgr.set_session_key(peer, rand::random()).unwrap();
+        peer
+    }
+
     #[test]
     fn test_session_creation() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         let session = mgr.get_session(peer).unwrap();
         assert_eq!(session.msg_count, 0);
@@ -208,10 +267,10 @@ mod tests {
     #[test]
     fn test_session_reuse() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
-        let key1 = *mgr.get_session(peer).unwrap().key();
-        let key2 = *mgr.get_session(peer).unwrap().key();
+        let key1 = *mgr.get_session(peer).unwrap().id();
+        let key2 = *mgr.get_session(peer).unwrap().id();
         
         assert_eq!(key1, key2); // Same session
     }
@@ -219,33 +278,42 @@ mod tests {
     #[test]
     fn test_different_peers_different_keys() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer1 = PeerId::random();
-        let peer2 = PeerId::random();
+        let peer1 = seeded(&mut mgr);
+        let peer2 = seeded(&mut mgr);
         
-        let key1 = *mgr.get_session(peer1).unwrap().key();
-        let key2 = *mgr.get_session(peer2).unwrap().key();
+        let key1 = *mgr.get_session(peer1).unwrap().id();
+        let key2 = *mgr.get_session(peer2).unwrap().id();
         
         assert_ne!(key1, key2);
     }
 
+    fn expire(session: &mut SessionKey) {
+        session.created = Instant::now()
+            .checked_sub(SESSION_TIMEOUT)
+            .expect("monotonic clock is older than the session timeout");
+    }
+
     #[test]
-    fn test_rotation_on_count() {
+    fn test_no_rotation_on_count() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
+        // Ratchet gives per-message keys, message count alone never expires a session
         let session = mgr.get_session(peer).unwrap();
         session.msg_count = 1000;
-        
+        assert!(!session.should_rotate());
+
+        expire(session);
         assert!(session.should_rotate());
     }
 
     #[test]
     fn test_cleanup() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         let session = mgr.get_session(peer).unwrap();
-        session.msg_count = 1000; // Force expiry
+        expire(session); // Force expiry
         
         mgr.cleanup();
         assert_eq!(mgr.session_count(), 0);
@@ -270,10 +338,42 @@ mod tests {
         assert!(mgr.peer_keys.contains_key(&peer));
     }
 
+    #[test]
+    fn test_verify_hybrid() {
+        let alice = SessionManager::new(PeerId::random()).unwrap();
+        let mut bob = SessionManager::new(PeerId::random()).unwrap();
+        let alice_peer = PeerId::random();
+        bob.register_peer_hybrid(alice_peer, alice.hybrid_public_key());
+
+        let sig = alice.sign(b"data").unwrap();
+        assert!(bob.verify_hybrid(&alice_peer, b"data", &sig).is_ok());
+
+        // Both halves are required
+        let mut tampered = sig.clone();
+        if let Some(pq) = tampered.pq.as_mut() {
+            pq[0] ^= 0xFF;
+        }
+        assert!(bob.verify_hybrid(&alice_peer, b"data", &tampered).is_err());
+    }
+
+    #[test]
+    fn test_verify_hybrid_classical_only_peer_policy() {
+        let alice = SessionManager::new(PeerId::random()).unwrap();
+        let mut bob = SessionManager::new(PeerId::random()).unwrap();
+        let alice_peer = PeerId::random();
+        bob.register_peer(alice_peer, *alice.public_key());
+
+        let sig = alice.sign(b"data").unwrap();
+        assert!(bob.verify_hybrid(&alice_peer, b"data", &sig).is_ok());
+
+        bob.set_pq_policy(PqPolicy::RequirePq);
+        assert!(bob.verify_hybrid(&alice_peer, b"data", &sig).is_err());
+    }
+
     #[test]
     fn test_session_increment() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         let session = mgr.get_session(peer).unwrap();
         assert_eq!(session.msg_count, 0);
@@ -288,7 +388,7 @@ mod tests {
     #[test]
     fn test_session_age() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         let session = mgr.get_session(peer).unwrap();
         let age = session.age();
@@ -303,8 +403,7 @@ mod tests {
         
         // Create MAX_SESSIONS + 1 sessions
         for _ in 0..=MAX_SESSIONS {
-            let peer = PeerId::random();
-            mgr.get_session(peer).unwrap();
+            seeded(&mut mgr);
         }
         
         // Should have evicted oldest
@@ -314,19 +413,20 @@ mod tests {
     #[test]
     fn test_session_expiry_and_cleanup() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         // Get initial session
         let session = mgr.get_session(peer).unwrap();
-        session.msg_count = 1000; // Mark for expiry
+        session.msg_count = 1000;
+        expire(session); // Mark for expiry
         
         // Cleanup should remove it
         mgr.cleanup();
         assert_eq!(mgr.session_count(), 0);
         
-        // New session created on next access
-        let session2 = mgr.get_session(peer).unwrap();
-        assert_eq!(session2.msg_count, 0); // Fresh session
+        // No fallback session: the peer needs a new handshake
+        assert!(matches!(mgr.get_session(peer), Err(CryptoError::NoSession(_))));
+        assert!(!mgr.has_session(&peer));
     }
 
     // Handshake methods removed - now in HandshakeBehaviour
@@ -345,7 +445,7 @@ mod tests {
     #[test]
     fn test_handshake_respond_without_peer_key() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         let init = HandshakeInit {
             peer_id: peer.to_bytes(),
@@ -363,18 +463,14 @@ mod tests {
     #[test]
     fn test_multiple_peer_sessions() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peers: Vec<_> = (0..10).map(|_| PeerId::random()).collect();
-        
         // Create sessions for all peers
-        for peer in &peers {
-            mgr.get_session(*peer).unwrap();
-        }
+        let peers: Vec<_> = (0..10).map(|_| seeded(&mut mgr)).collect();
         
         assert_eq!(mgr.session_count(), 10);
         
         // Each should have unique key
         let keys: Vec<_> = peers.iter()
-            .map(|p| *mgr.get_session(*p).unwrap().key())
+            .map(|p| *mgr.get_session(*p).unwrap().id())
             .collect();
         
         for i in 0..keys.len() {
@@ -387,14 +483,56 @@ mod tests {
     #[test]
     fn test_session_key_deterministic() {
         let mut mgr = SessionManager::new(PeerId::random()).unwrap();
-        let peer = PeerId::random();
+        let peer = seeded(&mut mgr);
         
         // Same peer should get same key (until rotation)
-        let key1 = *mgr.get_session(peer).unwrap().key();
-        let key2 = *mgr.get_session(peer).unwrap().key();
-        let key3 = *mgr.get_session(peer).unwrap().key();
+        let key1 = *mgr.get_session(peer).unwrap().id();
+        let key2 = *mgr.get_session(peer).unwrap().id();
+        let key3 = *mgr.get_session(peer).unwrap().id();
         
         assert_eq!(key1, key2);
         assert_eq!(key2, key3);
     }
+
+    #[test]
+    fn test_no_session_without_handshake() {
+        let mut mgr = SessionManager::new(PeerId::random()).unwrap();
+        let peer = PeerId::random();
+
+        assert!(matches!(mgr.get_session(peer), Err(CryptoError::NoSession(_))));
+        assert!(!mgr.has_session(&peer));
+        assert_eq!(mgr.session_count(), 0);
+    }
+
+    #[test]
+    fn test_expired_session_not_replaced() {
+        let mut mgr = SessionManager::new(PeerId::random()).unwrap();
+        let peer = seeded(&mut mgr);
+        assert!(mgr.has_session(&peer));
+
+        expire(mgr.get_session(peer).unwrap());
+        assert!(!mgr.has_session(&peer));
+        assert!(mgr.get_session(peer).is_err());
+        assert_eq!(mgr.session_count(), 0);
+    }
+
+    #[test]
+    fn test_handshake_key_ratchets_between_peers() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = SessionManager::new(alice_peer).unwrap();
+        let mut bob = SessionManager::new(bob_peer).unwrap();
+
+        alice.set_session_key(bob_peer, [9u8; 32]).unwrap();
+        bob.set_session_key(alice_peer, [9u8; 32]).unwrap();
+        assert_eq!(alice.get_session(bob_peer).unwrap().id(), bob.get_session(alice_peer).unwrap().id());
+
+        for i in 0..3u8 {
+            let msg = alice.get_session(bob_peer).unwrap().encrypt(&[i]).unwrap();
+            assert_eq!(&**bob.get_session(alice_peer).unwrap().decrypt(&msg).unwrap(), &[i]);
+
+            let reply = bob.get_session(alice_peer).unwrap().encrypt(&[i, i]).unwrap();
+            assert_eq!(&**alice.get_session(bob_peer).unwrap().decrypt(&reply).unwrap(), &[i, i]);
+        }
+    }
 }
@@ -0,0 +1,611 @@
+// STREAM chunked AEAD for payloads too large to hold in memory
+// A stream opens with a header: a random salt, from which the stream's own key is derived from
+// the caller's, and the chunk size. Chunk `i` is sealed under a nonce holding `i` and a flag set
+// only on the last chunk, with the header as associated data. Chunks can't be reordered or
+// dropped without breaking a tag, and a stream cut off at a chunk boundary has no chunk sealed
+// as last, so truncation is caught as well (Hoang, Reyhanitabar, Rogaway and Vizár, "Online
+// Authenticated-Encryption and its Nonce-Reuse Misuse-Resistance").
+//
+// Every chunk but the last is exactly the chunk size, so a reader tells the last one apart by
+// hitting the end of the input before a full chunk and one more byte.
+//
+// `StreamChunks` seals and opens the same chunks out of order, for transfers whose chunks come
+// from several peers in any order (file transfer): both ends build the header from an id they
+// already share instead of a random salt, and each knows how many chunks there are.
+
+use crate::error::{CryptoError, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+const STREAM_INFO: &[u8] = b"UMBRA-STREAM-v1";
+
+const SALT_LEN: usize = 32;
+
+/// Bytes a stream starts with: salt and chunk size
+pub const HEADER_LEN: usize = SALT_LEN + 4;
+
+/// Bytes each chunk grows by when sealed
+pub const TAG_LEN: usize = 16;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk size a header may ask for, so a forged header can't make a reader allocate
+/// without bound
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+struct StreamCipher {
+    cipher: ChaCha20Poly1305,
+    header: [u8; HEADER_LEN],
+    chunk_size: usize,
+    counter: u64,
+}
+
+impl StreamCipher {
+    fn new(key: &[u8; 32], header: [u8; HEADER_LEN]) -> Result<Self> {
+        let chunk_size = u32::from_be_bytes(header[SALT_LEN..].try_into().expect("four bytes")) as usize;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(CryptoError::Decryption(format!("Invalid chunk size {}", chunk_size)));
+        }
+
+        let mut stream_key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(Some(&header[..SALT_LEN]), key)
+            .expand(STREAM_INFO, &mut stream_key[..])
+            .map_err(|e| CryptoError::KeyDerivation(format!("Stream KDF: {}", e)))?;
+        let cipher = ChaCha20Poly1305::new_from_slice(&stream_key[..])
+            .map_err(|e| CryptoError::Encryption(format!("Key init failed: {}", e)))?;
+        Ok(Self { cipher, header, chunk_size, counter: 0 })
+    }
+
+    fn nonce(counter: u64, last: bool) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+        nonce[11] = u8::from(last);
+        Nonce::from(nonce)
+    }
+
+    fn seal_at(&self, counter: u64, chunk: &[u8], last: bool) -> Result<Vec<u8>> {
+        self.cipher
+            .encrypt(&Self::nonce(counter, last), Payload { msg: chunk, aad: &self.header })
+            .map_err(|e| CryptoError::Encryption(format!("Stream encrypt failed: {}", e)))
+    }
+
+    fn open_at(&self, counter: u64, sealed: &[u8], last: bool) -> Result<Zeroizing<Vec<u8>>> {
+        self.cipher
+            .decrypt(&Self::nonce(counter, last), Payload { msg: sealed, aad: &self.header })
+            .map(Zeroizing::new)
+            .map_err(|e| CryptoError::Decryption(format!("Stream chunk {} failed: {}", counter, e)))
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>> {
+        let sealed = self.seal_at(self.counter, chunk, last)?;
+        self.advance()?;
+        Ok(sealed)
+    }
+
+    fn open(&mut self, sealed: &[u8], last: bool) -> Result<Zeroizing<Vec<u8>>> {
+        let chunk = self.open_at(self.counter, sealed, last)?;
+        self.advance()?;
+        Ok(chunk)
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        self.counter = self.counter
+            .checked_add(1)
+            .ok_or_else(|| CryptoError::Encryption("Stream too long".to_string()))?;
+        Ok(())
+    }
+}
+
+/// Seals a stream chunk by chunk; the last chunk is sealed by `seal_last`, which ends it
+pub struct StreamEncryptor {
+    cipher: StreamCipher,
+}
+
+impl StreamEncryptor {
+    pub fn new(key: &[u8; 32], chunk_size: usize) -> Result<Self> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(CryptoError::Encryption(format!("Invalid chunk size {}", chunk_size)));
+        }
+        let mut header = [0u8; HEADER_LEN];
+        header[..SALT_LEN].copy_from_slice(&rand::random::<[u8; SALT_LEN]>());
+        header[SALT_LEN..].copy_from_slice(&(chunk_size as u32).to_be_bytes());
+        Ok(Self { cipher: StreamCipher::new(key, header)? })
+    }
+
+    /// Goes out ahead of the first chunk
+    pub fn header(&self) -> &[u8; HEADER_LEN] {
+        &self.cipher.header
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.cipher.chunk_size
+    }
+
+    /// Seal the next chunk, which must be exactly the chunk size
+    pub fn seal_chunk(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        if chunk.len() != self.cipher.chunk_size {
+            return Err(CryptoError::Encryption(format!("Chunk of {} bytes, expected {}", chunk.len(), self.cipher.chunk_size)));
+        }
+        self.cipher.seal(chunk, false)
+    }
+
+    /// Seal the last chunk, up to the chunk size (empty for an empty stream)
+    pub fn seal_last(mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        if chunk.len() > self.cipher.chunk_size {
+            return Err(CryptoError::Encryption(format!("Chunk of {} bytes, at most {}", chunk.len(), self.cipher.chunk_size)));
+        }
+        self.cipher.seal(chunk, true)
+    }
+}
+
+/// Opens a stream's chunks in order; the last is opened by `open_last`, which ends it
+pub struct StreamDecryptor {
+    cipher: StreamCipher,
+}
+
+impl StreamDecryptor {
+    pub fn new(key: &[u8; 32], header: &[u8]) -> Result<Self> {
+        let header: [u8; HEADER_LEN] = header.try_into().map_err(|_| CryptoError::InvalidKeyLength {
+            expected: HEADER_LEN,
+            got: header.len(),
+        })?;
+        Ok(Self { cipher: StreamCipher::new(key, header)? })
+    }
+
+    /// Bytes of every sealed chunk but the last
+    pub fn sealed_chunk_len(&self) -> usize {
+        self.cipher.chunk_size + TAG_LEN
+    }
+
+    pub fn open_chunk(&mut self, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if sealed.len() != self.sealed_chunk_len() {
+            return Err(CryptoError::Decryption(format!("Sealed chunk of {} bytes", sealed.len())));
+        }
+        self.cipher.open(sealed, false)
+    }
+
+    pub fn open_last(mut self, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if sealed.len() > self.sealed_chunk_len() {
+            return Err(CryptoError::Decryption(format!("Sealed chunk of {} bytes", sealed.len())));
+        }
+        self.cipher.open(sealed, true)
+    }
+}
+
+/// Seals and opens the chunks of a stream in any order. Chunk `i` is sealed just as the `i`th
+/// chunk of a `StreamEncryptor` would be, so it is still bound to its position, and the last
+/// chunk to being the last.
+pub struct StreamChunks {
+    cipher: StreamCipher,
+    chunk_count: u64,
+}
+
+impl StreamChunks {
+    /// `salt` stands in for the header's random salt: an id both ends already share, which
+    /// must never be used for two different streams under the same key
+    pub fn new(key: &[u8; 32], salt: &[u8; 32], chunk_size: usize, chunk_count: u64) -> Result<Self> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE || chunk_count == 0 {
+            return Err(CryptoError::Encryption(format!("Invalid stream of {} chunks of {} bytes", chunk_count, chunk_size)));
+        }
+        let mut header = [0u8; HEADER_LEN];
+        header[..SALT_LEN].copy_from_slice(salt);
+        header[SALT_LEN..].copy_from_slice(&(chunk_size as u32).to_be_bytes());
+        Ok(Self { cipher: StreamCipher::new(key, header)?, chunk_count })
+    }
+
+    /// Seal chunk `index`: exactly the chunk size, or up to it for the last chunk
+    pub fn seal(&self, index: u64, chunk: &[u8]) -> Result<Vec<u8>> {
+        let last = self.is_last(index, chunk.len())
+            .ok_or_else(|| CryptoError::Encryption(format!("Chunk {} of {} bytes", index, chunk.len())))?;
+        self.cipher.seal_at(index, chunk, last)
+    }
+
+    pub fn open(&self, index: u64, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let last = sealed.len().checked_sub(TAG_LEN)
+            .and_then(|len| self.is_last(index, len))
+            .ok_or_else(|| CryptoError::Decryption(format!("Sealed chunk {} of {} bytes", index, sealed.len())))?;
+        self.cipher.open_at(index, sealed, last)
+    }
+
+    /// Whether chunk `index` is the last, if `len` bytes is a valid length for it
+    fn is_last(&self, index: u64, len: usize) -> Option<bool> {
+        let last = index.checked_add(1)? == self.chunk_count;
+        let valid = index < self.chunk_count
+            && if last { len <= self.cipher.chunk_size } else { len == self.cipher.chunk_size };
+        valid.then_some(last)
+    }
+}
+
+/// Plaintext waiting for a full chunk, shared by the sync and async writers
+struct Sealer {
+    encryptor: StreamEncryptor,
+    buffer: Zeroizing<Vec<u8>>,
+}
+
+impl Sealer {
+    /// Take what fits of `data`; returns how much, and a chunk to write out if one was sealed.
+    /// A full chunk is only sealed once more data turns up, as it may yet be the last.
+    fn push(&mut self, data: &[u8]) -> Result<(usize, Option<Vec<u8>>)> {
+        let chunk_size = self.encryptor.chunk_size();
+        let mut sealed = None;
+        if self.buffer.len() == chunk_size && !data.is_empty() {
+            sealed = Some(self.encryptor.seal_chunk(&self.buffer)?);
+            self.buffer.clear();
+        }
+        let take = data.len().min(chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..take]);
+        Ok((take, sealed))
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        self.encryptor.seal_last(&self.buffer)
+    }
+}
+
+/// Sealed input read ahead of the chunk being opened, shared by the sync and async readers
+struct Opener {
+    decryptor: Option<StreamDecryptor>,
+    sealed: Vec<u8>,
+}
+
+impl Opener {
+    /// Bytes to have read before the next chunk can be opened: a whole one and a byte more
+    /// shows it isn't the last. `None` once the last chunk is out.
+    fn wanted(&self) -> Option<usize> {
+        self.decryptor.as_ref().map(|decryptor| decryptor.sealed_chunk_len() + 1)
+    }
+
+    /// Open the next chunk, once `wanted` bytes are in or the input has ended
+    fn open(&mut self) -> Result<Zeroizing<Vec<u8>>> {
+        let decryptor = self.decryptor.as_mut().expect("stream not over");
+        let len = decryptor.sealed_chunk_len();
+        if self.sealed.len() > len {
+            let chunk = decryptor.open_chunk(&self.sealed[..len])?;
+            self.sealed.drain(..len);
+            Ok(chunk)
+        } else {
+            let chunk = self.decryptor.take().expect("checked above").open_last(&self.sealed)?;
+            self.sealed.clear();
+            Ok(chunk)
+        }
+    }
+}
+
+fn io_error(e: CryptoError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Encrypts everything written to it into `W`. `finish` must be called at the end: a stream
+/// dropped without it is missing its last chunk, and readers take it as truncated.
+pub struct EncryptWriter<W: Write> {
+    writer: W,
+    sealer: Sealer,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Start a stream into `writer`, header first
+    pub fn new(key: &[u8; 32], chunk_size: usize, mut writer: W) -> io::Result<Self> {
+        let encryptor = StreamEncryptor::new(key, chunk_size).map_err(io_error)?;
+        writer.write_all(encryptor.header())?;
+        let buffer = Zeroizing::new(Vec::with_capacity(chunk_size));
+        Ok(Self { writer, sealer: Sealer { encryptor, buffer } })
+    }
+
+    /// Seal the last chunk and hand back the writer
+    pub fn finish(self) -> io::Result<W> {
+        let Self { mut writer, sealer } = self;
+        writer.write_all(&sealer.finish().map_err(io_error)?)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (taken, sealed) = self.sealer.push(buf).map_err(io_error)?;
+        if let Some(sealed) = sealed {
+            self.writer.write_all(&sealed)?;
+        }
+        Ok(taken)
+    }
+
+    /// Flushes what's sealed; the chunk being filled stays buffered until it's full or finished
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts a stream read from `R`. Every byte it yields has been authenticated, but a stream
+/// is only known to be whole once a read returns 0.
+pub struct DecryptReader<R: Read> {
+    reader: R,
+    opener: Opener,
+    plaintext: Zeroizing<Vec<u8>>,
+    pos: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Read the header off `reader`
+    pub fn new(key: &[u8; 32], mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let decryptor = StreamDecryptor::new(key, &header).map_err(io_error)?;
+        Ok(Self {
+            reader,
+            opener: Opener { decryptor: Some(decryptor), sealed: Vec::new() },
+            plaintext: Zeroizing::new(Vec::new()),
+            pos: 0,
+        })
+    }
+
+    /// Open the next chunk; false at the end of the stream
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let Some(wanted) = self.opener.wanted() else {
+            return Ok(false);
+        };
+        while self.opener.sealed.len() < wanted {
+            let start = self.opener.sealed.len();
+            self.opener.sealed.resize(wanted, 0);
+            let read = self.reader.read(&mut self.opener.sealed[start..]);
+            self.opener.sealed.truncate(start + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.plaintext = self.opener.open().map_err(io_error)?;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plaintext.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..len].copy_from_slice(&self.plaintext[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// `EncryptWriter` over an async writer, such as a libp2p stream
+pub struct AsyncEncryptWriter<W: AsyncWrite + Unpin> {
+    writer: W,
+    sealer: Sealer,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncryptWriter<W> {
+    pub async fn new(key: &[u8; 32], chunk_size: usize, mut writer: W) -> io::Result<Self> {
+        let encryptor = StreamEncryptor::new(key, chunk_size).map_err(io_error)?;
+        writer.write_all(encryptor.header()).await?;
+        let buffer = Zeroizing::new(Vec::with_capacity(chunk_size));
+        Ok(Self { writer, sealer: Sealer { encryptor, buffer } })
+    }
+
+    pub async fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let (taken, sealed) = self.sealer.push(data).map_err(io_error)?;
+            if let Some(sealed) = sealed {
+                self.writer.write_all(&sealed).await?;
+            }
+            data = &data[taken..];
+        }
+        Ok(())
+    }
+
+    /// Seal the last chunk and hand back the writer, flushed but still open
+    pub async fn finish(self) -> io::Result<W> {
+        let Self { mut writer, sealer } = self;
+        writer.write_all(&sealer.finish().map_err(io_error)?).await?;
+        writer.flush().await?;
+        Ok(writer)
+    }
+}
+
+/// `DecryptReader` over an async reader, yielding a chunk at a time
+pub struct AsyncDecryptReader<R: AsyncRead + Unpin> {
+    reader: R,
+    opener: Opener,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecryptReader<R> {
+    pub async fn new(key: &[u8; 32], mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header).await?;
+        let decryptor = StreamDecryptor::new(key, &header).map_err(io_error)?;
+        Ok(Self { reader, opener: Opener { decryptor: Some(decryptor), sealed: Vec::new() } })
+    }
+
+    /// The next chunk's plaintext, or `None` once the whole stream has been read
+    pub async fn read_chunk(&mut self) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+        let Some(wanted) = self.opener.wanted() else {
+            return Ok(None);
+        };
+        while self.opener.sealed.len() < wanted {
+            let start = self.opener.sealed.len();
+            self.opener.sealed.resize(wanted, 0);
+            let read = self.reader.read(&mut self.opener.sealed[start..]).await;
+            self.opener.sealed.truncate(start + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.opener.open().map(Some).map_err(io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7u8; 32];
+    const CHUNK: usize = 64;
+
+    fn seal(data: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptWriter::new(&KEY, CHUNK, Vec::new()).unwrap();
+        // Odd-sized writes, to cross chunk boundaries mid-write
+        for piece in data.chunks(23) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn open(sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        DecryptReader::new(&KEY, sealed)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 3 * CHUNK, 3 * CHUNK + 17] {
+            let sealed = seal(&data(len));
+            let chunks = len.div_ceil(CHUNK).max(1);
+            assert_eq!(sealed.len(), HEADER_LEN + len + chunks * TAG_LEN, "{} bytes", len);
+            assert_eq!(open(&sealed).unwrap(), data(len), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_truncation_detected() {
+        let sealed = seal(&data(3 * CHUNK + 10));
+        let sealed_chunk = CHUNK + TAG_LEN;
+
+        // Cut at a chunk boundary: what's left ends on a chunk not sealed as last
+        assert!(open(&sealed[..HEADER_LEN + 2 * sealed_chunk]).is_err());
+        assert!(open(&sealed[..sealed.len() - 1]).is_err());
+        assert!(open(&sealed[..HEADER_LEN]).is_err());
+        assert!(open(&sealed[..HEADER_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn test_reordered_or_extended_stream_detected() {
+        let sealed = seal(&data(3 * CHUNK + 10));
+        let sealed_chunk = CHUNK + TAG_LEN;
+        let (first, second) = (HEADER_LEN, HEADER_LEN + sealed_chunk);
+
+        let mut swapped = sealed.clone();
+        swapped[first..second].copy_from_slice(&sealed[second..second + sealed_chunk]);
+        swapped[second..second + sealed_chunk].copy_from_slice(&sealed[first..second]);
+        assert!(open(&swapped).is_err());
+
+        let mut extended = sealed.clone();
+        extended.extend_from_slice(&sealed[first..second]);
+        assert!(open(&extended).is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_or_header_rejected() {
+        let sealed = seal(&data(100));
+        assert!(DecryptReader::new(&[8u8; 32], sealed.as_slice())
+            .and_then(|mut reader| reader.read_to_end(&mut Vec::new()))
+            .is_err());
+
+        let mut salted = sealed.clone();
+        salted[0] ^= 1;
+        assert!(open(&salted).is_err());
+
+        // A header claiming a huge chunk size is refused before anything is read
+        let mut huge = sealed.clone();
+        huge[SALT_LEN..HEADER_LEN].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(DecryptReader::new(&KEY, huge.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_chunk_sizes_enforced() {
+        let mut encryptor = StreamEncryptor::new(&KEY, CHUNK).unwrap();
+        assert!(encryptor.seal_chunk(&[0u8; CHUNK - 1]).is_err());
+        assert!(encryptor.seal_chunk(&[0u8; CHUNK]).is_ok());
+        assert!(StreamEncryptor::new(&KEY, CHUNK).unwrap().seal_last(&[0u8; CHUNK + 1]).is_err());
+        assert!(StreamEncryptor::new(&KEY, 0).is_err());
+        assert!(StreamEncryptor::new(&KEY, MAX_CHUNK_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn test_chunks_out_of_order() {
+        let plaintext = data(3 * CHUNK + 10);
+        let chunks = StreamChunks::new(&KEY, &[2u8; 32], CHUNK, 4).unwrap();
+        let pieces: Vec<&[u8]> = plaintext.chunks(CHUNK).collect();
+
+        let sealed: Vec<Vec<u8>> = pieces.iter().enumerate()
+            .map(|(i, piece)| chunks.seal(i as u64, piece).unwrap())
+            .collect();
+        for i in [3, 0, 2, 1] {
+            assert_eq!(&**chunks.open(i as u64, &sealed[i]).unwrap(), pieces[i]);
+        }
+
+        // The same chunks, read as a stream, open with a reader given the same header
+        let mut header = [0u8; HEADER_LEN];
+        header[..SALT_LEN].copy_from_slice(&[2u8; 32]);
+        header[SALT_LEN..].copy_from_slice(&(CHUNK as u32).to_be_bytes());
+        let stream = [header.to_vec(), sealed.concat()].concat();
+        assert_eq!(open(&stream).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_chunks_bound_to_position_salt_and_key() {
+        let chunks = StreamChunks::new(&KEY, &[2u8; 32], CHUNK, 4).unwrap();
+        let sealed = chunks.seal(1, &[9u8; CHUNK]).unwrap();
+
+        assert!(chunks.open(2, &sealed).is_err());
+        assert!(StreamChunks::new(&KEY, &[3u8; 32], CHUNK, 4).unwrap().open(1, &sealed).is_err());
+        assert!(StreamChunks::new(&[8u8; 32], &[2u8; 32], CHUNK, 4).unwrap().open(1, &sealed).is_err());
+        // A full chunk that isn't the last can't pass for the last of a shorter stream
+        assert!(StreamChunks::new(&KEY, &[2u8; 32], CHUNK, 2).unwrap().open(1, &sealed).is_err());
+
+        // Lengths and indices out of place
+        assert!(chunks.seal(0, &[0u8; CHUNK - 1]).is_err());
+        assert!(chunks.seal(3, &[0u8; CHUNK + 1]).is_err());
+        assert!(chunks.seal(4, &[0u8; 1]).is_err());
+        assert!(chunks.seal(3, &[]).is_ok());
+        assert!(StreamChunks::new(&KEY, &[2u8; 32], CHUNK, 0).is_err());
+    }
+
+    #[test]
+    fn test_async_roundtrip() {
+        futures::executor::block_on(async {
+            let plaintext = data(5 * CHUNK + 3);
+            let mut writer = AsyncEncryptWriter::new(&KEY, CHUNK, futures::io::Cursor::new(Vec::new())).await.unwrap();
+            for piece in plaintext.chunks(100) {
+                writer.write_all(piece).await.unwrap();
+            }
+            let sealed = writer.finish().await.unwrap().into_inner();
+            assert_eq!(open(&sealed).unwrap(), plaintext);
+
+            let mut reader = AsyncDecryptReader::new(&KEY, sealed.as_slice()).await.unwrap();
+            let mut opened = Vec::new();
+            while let Some(chunk) = reader.read_chunk().await.unwrap() {
+                opened.extend_from_slice(&chunk);
+            }
+            assert_eq!(opened, plaintext);
+
+            let mut reader = AsyncDecryptReader::new(&KEY, &sealed[..sealed.len() - TAG_LEN - 3]).await.unwrap();
+            let mut result = Ok(Some(Zeroizing::new(Vec::new())));
+            while let Ok(Some(_)) = result {
+                result = reader.read_chunk().await;
+            }
+            assert!(result.is_err(), "truncated stream read to the end");
+        });
+    }
+}
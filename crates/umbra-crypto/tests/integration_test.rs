@@ -42,19 +42,21 @@ fn test_pure_rust_pq_crypto() {
     let alice_hs = handshake::Handshake::new(alice_id.clone()).unwrap();
     let bob_hs = handshake::Handshake::new(bob_id.clone()).unwrap();
     
-    let init = alice_hs.initiate(PeerId::random()).unwrap();
+    let (alice_peer, bob_peer) = (PeerId::random(), PeerId::random());
+    let init = alice_hs.initiate(alice_peer).unwrap();
     println!("   ✅ Handshake initiated");
     
     let (resp, bob_key) = bob_hs.respond(
-        PeerId::random(), 
+        bob_peer,
+        alice_peer,
         &init, 
-        alice_id.verifying_key()
+        &alice_id.hybrid_verifying_key()
     ).unwrap();
     println!("   ✅ Handshake response created");
     
-    let alice_key = alice_hs.complete(&resp, bob_id.verifying_key()).unwrap();
+    let alice_key = alice_hs.complete(bob_peer, &init, &resp, &bob_id.hybrid_verifying_key()).unwrap();
     
-    assert_eq!(alice_key, bob_key);
+    assert_eq!(alice_key.session_key(), bob_key.session_key());
     println!("   ✅ Handshake complete - keys match!\n");
     
     println!("✅ All integration tests passed!");
@@ -97,7 +97,7 @@ mod tests {
 
     #[test]
     fn test_different_secrets_different_proofs() {
-        let prover = Prover::setup().unwrap();
+        let _prover = Prover::setup().unwrap();
         let id1 = field_to_bytes(&bytes_to_field(&[1u8; 32]).unwrap().pow([5u64]));
         let id2 = field_to_bytes(&bytes_to_field(&[2u8; 32]).unwrap().pow([5u64]));
         assert_ne!(id1, id2);
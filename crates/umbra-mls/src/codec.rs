@@ -0,0 +1,227 @@
+// TLS presentation language encoding as used by RFC 9420
+// Integers are big-endian; vectors carry a variable-length (QUIC-style) byte-length prefix
+
+use crate::error::{MlsError, Result};
+
+/// Largest vector length the 4-byte varint can carry
+pub const MAX_VARINT: usize = (1 << 30) - 1;
+
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self>;
+
+    /// Decode a value that must span the whole buffer
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let value = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(value)
+    }
+}
+
+/// Cursor over an input buffer
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.buf.len() {
+            return Err(MlsError::Decode("Unexpected end of input".to_string()));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    /// Everything not yet consumed
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.buf)
+    }
+
+    pub fn finish(&self) -> Result<()> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(MlsError::Decode(format!("{} trailing bytes", self.buf.len())))
+        }
+    }
+}
+
+/// Write a vector length prefix (RFC 9420 §2.1.2)
+pub fn encode_varint(len: usize, out: &mut Vec<u8>) {
+    assert!(len <= MAX_VARINT, "vector too long for MLS varint");
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&((len as u16) | 0x4000).to_be_bytes());
+    } else {
+        out.extend_from_slice(&((len as u32) | 0x8000_0000).to_be_bytes());
+    }
+}
+
+/// Read a vector length prefix, rejecting non-minimal encodings
+pub fn decode_varint(reader: &mut Reader<'_>) -> Result<usize> {
+    let first = reader.take(1)?[0];
+    let (len, min) = match first >> 6 {
+        0 => return Ok(first as usize),
+        1 => {
+            let rest = reader.take(1)?[0];
+            ((((first & 0x3f) as usize) << 8) | rest as usize, 1 << 6)
+        }
+        2 => {
+            let rest = reader.take(3)?;
+            let value = u32::from_be_bytes([first & 0x3f, rest[0], rest[1], rest[2]]);
+            (value as usize, 1 << 14)
+        }
+        _ => return Err(MlsError::Decode("Invalid varint prefix".to_string())),
+    };
+
+    if len < min {
+        return Err(MlsError::Decode("Non-minimal varint".to_string()));
+    }
+    Ok(len)
+}
+
+/// `opaque data<V>`
+pub fn encode_opaque(data: &[u8], out: &mut Vec<u8>) {
+    encode_varint(data.len(), out);
+    out.extend_from_slice(data);
+}
+
+pub fn decode_opaque(reader: &mut Reader<'_>) -> Result<Vec<u8>> {
+    let len = decode_varint(reader)?;
+    Ok(reader.take(len)?.to_vec())
+}
+
+/// `T items<V>`: the prefix counts bytes, not items
+pub fn encode_vec<T: Encode>(items: &[T], out: &mut Vec<u8>) {
+    let mut body = Vec::new();
+    for item in items {
+        item.encode(&mut body);
+    }
+    encode_opaque(&body, out);
+}
+
+pub fn decode_vec<T: Decode>(reader: &mut Reader<'_>) -> Result<Vec<T>> {
+    let len = decode_varint(reader)?;
+    let mut inner = Reader::new(reader.take(len)?);
+    let mut items = Vec::new();
+    while !inner.is_empty() {
+        items.push(T::decode(&mut inner)?);
+    }
+    Ok(items)
+}
+
+/// `optional<T>`: a presence byte followed by the value
+pub fn encode_optional<T: Encode>(value: &Option<T>, out: &mut Vec<u8>) {
+    match value {
+        Some(v) => {
+            out.push(1);
+            v.encode(out);
+        }
+        None => out.push(0),
+    }
+}
+
+pub fn decode_optional<T: Decode>(reader: &mut Reader<'_>) -> Result<Option<T>> {
+    match reader.take(1)?[0] {
+        0 => Ok(None),
+        1 => Ok(Some(T::decode(reader)?)),
+        other => Err(MlsError::Decode(format!("Invalid optional marker {}", other))),
+    }
+}
+
+macro_rules! impl_uint {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+                let bytes = reader.take(std::mem::size_of::<$ty>())?;
+                Ok(<$ty>::from_be_bytes(bytes.try_into().expect("length checked")))
+            }
+        }
+    )*};
+}
+
+impl_uint!(u8, u16, u32, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint_hex(len: usize) -> String {
+        let mut out = Vec::new();
+        encode_varint(len, &mut out);
+        hex::encode(out)
+    }
+
+    #[test]
+    fn test_varint_vectors() {
+        // Examples from RFC 9000 §16 restricted to the sizes MLS allows
+        assert_eq!(varint_hex(37), "25");
+        assert_eq!(varint_hex(15293), "7bbd");
+        assert_eq!(varint_hex(494878333), "9d7f3e7d");
+        assert_eq!(varint_hex(63), "3f");
+        assert_eq!(varint_hex(64), "4040");
+
+        for len in [0, 37, 63, 64, 15293, 16383, 16384, 494878333, MAX_VARINT] {
+            let mut out = Vec::new();
+            encode_varint(len, &mut out);
+            let mut reader = Reader::new(&out);
+            assert_eq!(decode_varint(&mut reader).unwrap(), len);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_non_minimal_varint_rejected() {
+        // 37 in two bytes
+        assert!(decode_varint(&mut Reader::new(&[0x40, 0x25])).is_err());
+        // 8-byte QUIC form is not allowed in MLS
+        assert!(decode_varint(&mut Reader::new(&[0xc0, 0, 0, 0, 0, 0, 0, 1])).is_err());
+    }
+
+    #[test]
+    fn test_vec_roundtrip() {
+        let items: Vec<u16> = vec![1, 0x0203, 0xffff];
+        let mut out = Vec::new();
+        encode_vec(&items, &mut out);
+        assert_eq!(hex::encode(&out), "0600010203ffff");
+
+        let decoded: Vec<u16> = decode_vec(&mut Reader::new(&out)).unwrap();
+        assert_eq!(decoded, items);
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let mut out = Vec::new();
+        encode_opaque(b"hello", &mut out);
+        out.pop();
+        assert!(decode_opaque(&mut Reader::new(&out)).is_err());
+        assert!(u32::from_bytes(&[1, 2, 3]).is_err());
+        assert!(u16::from_bytes(&[1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,574 @@
+// Ciphersuite primitives: KDF/MAC/AEAD, HPKE path-secret encryption and leaf signatures
+// Labelled helpers follow RFC 9420 §5.1 so every derivation is domain-separated with "MLS 1.0 "
+//
+// Besides the standard X25519/Ed25519 suite there is a private-use hybrid suite: path secrets
+// are encrypted with umbra's X25519+Kyber768 `HybridKem` and every signature is an
+// Ed25519+Dilithium3 `HybridSignature`. Node keys derived from a node secret seed both the
+// X25519 and the Kyber768 key generation, so anyone holding the secret derives the same pair.
+
+use crate::codec::{decode_opaque, encode_opaque, Decode, Encode, Reader};
+use crate::error::{MlsError, Result};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use hpke::aead::ChaCha20Poly1305 as HpkeChaCha;
+use hpke::kdf::HkdfSha256;
+use hpke::kem::X25519HkdfSha256;
+use hpke::{Deserializable, Kem as _, OpModeR, OpModeS, Serializable};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use umbra_crypto::{HybridKem, HybridSignature, HybridVerifyingKey, IdentityKey, PqPolicy};
+use x25519_dalek::PublicKey as X25519PublicKey;
+use zeroize::Zeroizing;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Prefix for every labelled derivation, signature and encryption
+const LABEL_PREFIX: &[u8] = b"MLS 1.0 ";
+
+pub type Secret = Zeroizing<Vec<u8>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    /// MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519
+    Curve25519ChaCha20,
+    /// X25519+Kyber768 / ChaCha20Poly1305 / SHA256 / Ed25519+Dilithium3 (private-use id)
+    HybridKyber768ChaCha20,
+}
+
+impl CipherSuite {
+    pub fn id(self) -> u16 {
+        match self {
+            Self::Curve25519ChaCha20 => 0x0003,
+            Self::HybridKyber768ChaCha20 => 0xF0A1,
+        }
+    }
+
+    pub fn from_id(id: u16) -> Result<Self> {
+        match id {
+            0x0003 => Ok(Self::Curve25519ChaCha20),
+            0xF0A1 => Ok(Self::HybridKyber768ChaCha20),
+            other => Err(MlsError::UnsupportedCipherSuite(other)),
+        }
+    }
+
+    /// Whether path secrets and signatures use the post-quantum hybrids
+    pub fn is_hybrid(self) -> bool {
+        matches!(self, Self::HybridKyber768ChaCha20)
+    }
+
+    /// Output length of the hash / KDF (`Nh`)
+    pub fn hash_len(self) -> usize {
+        32
+    }
+
+    pub fn aead_key_len(self) -> usize {
+        32
+    }
+
+    pub fn aead_nonce_len(self) -> usize {
+        12
+    }
+
+    pub fn hash(self, data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+
+    pub fn extract(self, salt: &[u8], ikm: &[u8]) -> Secret {
+        let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), ikm);
+        Zeroizing::new(prk.to_vec())
+    }
+
+    pub fn expand(self, prk: &[u8], info: &[u8], len: usize) -> Result<Secret> {
+        let hk = Hkdf::<Sha256>::from_prk(prk).map_err(|e| MlsError::Crypto(e.to_string()))?;
+        let mut okm = Zeroizing::new(vec![0u8; len]);
+        hk.expand(info, &mut okm)
+            .map_err(|e| MlsError::Crypto(e.to_string()))?;
+        Ok(okm)
+    }
+
+    /// ExpandWithLabel(Secret, Label, Context, Length)
+    pub fn expand_with_label(self, secret: &[u8], label: &str, context: &[u8], len: usize) -> Result<Secret> {
+        let mut info = Vec::new();
+        (len as u16).encode(&mut info);
+        encode_opaque(&[LABEL_PREFIX, label.as_bytes()].concat(), &mut info);
+        encode_opaque(context, &mut info);
+        self.expand(secret, &info, len)
+    }
+
+    /// DeriveSecret(Secret, Label)
+    pub fn derive_secret(self, secret: &[u8], label: &str) -> Result<Secret> {
+        self.expand_with_label(secret, label, &[], self.hash_len())
+    }
+
+    /// RefHash(label, value); the label already carries its "MLS 1.0 " prefix
+    pub fn ref_hash(self, label: &str, value: &[u8]) -> Vec<u8> {
+        let mut input = Vec::new();
+        encode_opaque(label.as_bytes(), &mut input);
+        encode_opaque(value, &mut input);
+        self.hash(&input)
+    }
+
+    pub fn mac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Constant-time MAC check
+    pub fn verify_mac(self, key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac.verify_slice(tag).is_ok()
+    }
+
+    pub fn seal(self, key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(|e| MlsError::Crypto(e.to_string()))?;
+        cipher
+            .encrypt(nonce.into(), Payload { msg: plaintext, aad })
+            .map_err(|e| MlsError::Crypto(e.to_string()))
+    }
+
+    pub fn open(self, key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(|e| MlsError::Crypto(e.to_string()))?;
+        cipher
+            .decrypt(nonce.into(), Payload { msg: ciphertext, aad })
+            .map_err(|_| MlsError::Crypto("AEAD decryption failed".to_string()))
+    }
+
+    /// DeriveKeyPair for TreeKEM node keys. The hybrid suite derives the X25519 secret and the
+    /// Kyber768 key generation seed (d || z) from `ikm`, so both halves are deterministic
+    pub fn derive_key_pair(self, ikm: &[u8]) -> Result<HpkeKeyPair> {
+        match self {
+            Self::Curve25519ChaCha20 => {
+                let (sk, pk) = X25519HkdfSha256::derive_keypair(ikm);
+                Ok(HpkeKeyPair {
+                    public: pk.to_bytes().to_vec(),
+                    private: Zeroizing::new(sk.to_bytes().to_vec()),
+                })
+            }
+            Self::HybridKyber768ChaCha20 => {
+                let classical = self.expand_with_label(ikm, "hybrid x25519", &[], HybridKem::CLASSICAL_KEY_LEN)?;
+                let seed = self.expand_with_label(ikm, "hybrid kyber768", &[], HybridKem::PQ_SEED_LEN)?;
+                let seed: &[u8; HybridKem::PQ_SEED_LEN] = seed.as_slice().try_into().expect("expanded to seed length");
+                let kem = HybridKem::from_seed(to_key(&classical)?, seed).map_err(crypto_error)?;
+                Ok(hybrid_key_pair(&kem))
+            }
+        }
+    }
+
+    /// The key pair `derive_key_pair(ikm)` produces, checked against the published `public`
+    pub fn recover_key_pair(self, ikm: &[u8], public: &[u8]) -> Result<HpkeKeyPair> {
+        let pair = self.derive_key_pair(ikm)?;
+        if pair.public != public {
+            return Err(MlsError::Crypto("Node secret does not match the public key".to_string()));
+        }
+        Ok(pair)
+    }
+
+    pub fn generate_key_pair(self) -> Result<HpkeKeyPair> {
+        match self {
+            Self::Curve25519ChaCha20 => {
+                let ikm = Zeroizing::new(rand::random::<[u8; 32]>());
+                self.derive_key_pair(ikm.as_ref())
+            }
+            Self::HybridKyber768ChaCha20 => Ok(hybrid_key_pair(&HybridKem::generate().map_err(crypto_error)?)),
+        }
+    }
+
+    /// EncryptWithLabel(PublicKey, Label, Context, Plaintext)
+    pub fn encrypt_with_label(
+        self,
+        public_key: &[u8],
+        label: &str,
+        context: &[u8],
+        plaintext: &[u8],
+    ) -> Result<HpkeCiphertext> {
+        if self.is_hybrid() {
+            return self.hybrid_encrypt(public_key, &encrypt_context(label, context), plaintext);
+        }
+        let pk = <X25519HkdfSha256 as hpke::Kem>::PublicKey::from_bytes(public_key)
+            .map_err(|e| MlsError::Crypto(e.to_string()))?;
+        let info = encrypt_context(label, context);
+        let (encapped, ciphertext) = hpke::single_shot_seal::<HpkeChaCha, HkdfSha256, X25519HkdfSha256, _>(
+            &OpModeS::Base,
+            &pk,
+            &info,
+            plaintext,
+            &[],
+            &mut OsRng,
+        )
+        .map_err(|e| MlsError::Crypto(e.to_string()))?;
+
+        Ok(HpkeCiphertext {
+            kem_output: encapped.to_bytes().to_vec(),
+            ciphertext,
+        })
+    }
+
+    /// DecryptWithLabel(PrivateKey, Label, Context, KEMOutput, Ciphertext)
+    pub fn decrypt_with_label(
+        self,
+        private_key: &[u8],
+        label: &str,
+        context: &[u8],
+        ciphertext: &HpkeCiphertext,
+    ) -> Result<Secret> {
+        if self.is_hybrid() {
+            return self.hybrid_decrypt(private_key, &encrypt_context(label, context), ciphertext);
+        }
+        let sk = <X25519HkdfSha256 as hpke::Kem>::PrivateKey::from_bytes(private_key)
+            .map_err(|e| MlsError::Crypto(e.to_string()))?;
+        let encapped = <X25519HkdfSha256 as hpke::Kem>::EncappedKey::from_bytes(&ciphertext.kem_output)
+            .map_err(|e| MlsError::Crypto(e.to_string()))?;
+        let info = encrypt_context(label, context);
+        let plaintext = hpke::single_shot_open::<HpkeChaCha, HkdfSha256, X25519HkdfSha256>(
+            &OpModeR::Base,
+            &sk,
+            &encapped,
+            &info,
+            &ciphertext.ciphertext,
+            &[],
+        )
+        .map_err(|_| MlsError::Crypto("HPKE decryption failed".to_string()))?;
+        Ok(Zeroizing::new(plaintext))
+    }
+
+    pub fn generate_signature_key_pair(self) -> Result<SignatureKeyPair> {
+        match self {
+            Self::Curve25519ChaCha20 => {
+                let signing = SigningKey::from_bytes(&rand::random());
+                Ok(SignatureKeyPair {
+                    suite: self,
+                    public: signing.verifying_key().to_bytes().to_vec(),
+                    private: SigningSecret::Ed25519(Zeroizing::new(signing.to_bytes().to_vec())),
+                })
+            }
+            Self::HybridKyber768ChaCha20 => Ok(SignatureKeyPair::from_identity(
+                IdentityKey::generate().map_err(crypto_error)?,
+            )),
+        }
+    }
+
+    /// VerifyWithLabel(VerificationKey, Label, Content, SignatureValue)
+    pub fn verify_with_label(self, public_key: &[u8], label: &str, content: &[u8], signature: &[u8]) -> Result<()> {
+        if self.is_hybrid() {
+            return verify_hybrid(public_key, &sign_content(label, content), signature);
+        }
+        let key_bytes: [u8; 32] = public_key.try_into().map_err(|_| MlsError::InvalidSignature)?;
+        let key = VerifyingKey::from_bytes(&key_bytes).map_err(|_| MlsError::InvalidSignature)?;
+        let signature = ed25519_dalek::Signature::from_slice(signature).map_err(|_| MlsError::InvalidSignature)?;
+        key.verify_strict(&sign_content(label, content), &signature)
+            .map_err(|_| MlsError::InvalidSignature)
+    }
+}
+
+impl CipherSuite {
+    /// HPKE-style single-shot encryption: an ephemeral HybridKem encapsulates to the recipient's
+    /// X25519 and Kyber768 keys, and the combined secret keys the AEAD under the EncryptContext
+    fn hybrid_encrypt(self, public_key: &[u8], info: &[u8], plaintext: &[u8]) -> Result<HpkeCiphertext> {
+        let (classical, pq) = split_hybrid_public(public_key)?;
+        let ephemeral = HybridKem::generate().map_err(crypto_error)?;
+        let (pq_ciphertext, shared) = ephemeral.encapsulate(&classical, pq).map_err(crypto_error)?;
+
+        let (key, nonce) = self.hybrid_key_nonce(shared.as_bytes(), info)?;
+        let mut kem_output = ephemeral.classical_public_key().as_bytes().to_vec();
+        kem_output.extend_from_slice(&pq_ciphertext);
+        Ok(HpkeCiphertext {
+            kem_output,
+            ciphertext: self.seal(&key, &nonce, &[], plaintext)?,
+        })
+    }
+
+    fn hybrid_decrypt(self, private_key: &[u8], info: &[u8], ciphertext: &HpkeCiphertext) -> Result<Secret> {
+        let failed = || MlsError::Crypto("HPKE decryption failed".to_string());
+        if private_key.len() != HybridKem::CLASSICAL_KEY_LEN + HybridKem::PQ_SECRET_KEY_LEN
+            || ciphertext.kem_output.len() != HybridKem::CLASSICAL_KEY_LEN + HybridKem::PQ_CIPHERTEXT_LEN
+        {
+            return Err(failed());
+        }
+        let (classical_secret, pq_secret) = private_key.split_at(HybridKem::CLASSICAL_KEY_LEN);
+        let kem = HybridKem::from_secret_bytes(to_key(classical_secret)?, pq_secret).map_err(crypto_error)?;
+
+        let (ephemeral, pq_ciphertext) = ciphertext.kem_output.split_at(HybridKem::CLASSICAL_KEY_LEN);
+        let shared = kem
+            .decapsulate(&X25519PublicKey::from(to_key(ephemeral)?), pq_ciphertext)
+            .map_err(|_| failed())?;
+
+        let (key, nonce) = self.hybrid_key_nonce(shared.as_bytes(), info)?;
+        let plaintext = self.open(&key, &nonce, &[], &ciphertext.ciphertext).map_err(|_| failed())?;
+        Ok(Zeroizing::new(plaintext))
+    }
+
+    fn hybrid_key_nonce(self, shared: &[u8], info: &[u8]) -> Result<(Secret, Secret)> {
+        Ok((
+            self.expand_with_label(shared, "hybrid key", info, self.aead_key_len())?,
+            self.expand_with_label(shared, "hybrid nonce", info, self.aead_nonce_len())?,
+        ))
+    }
+}
+
+fn crypto_error(e: umbra_crypto::CryptoError) -> MlsError {
+    MlsError::Crypto(e.to_string())
+}
+
+fn to_key(bytes: &[u8]) -> Result<[u8; 32]> {
+    bytes.try_into().map_err(|_| MlsError::Crypto("Invalid X25519 key length".to_string()))
+}
+
+/// public = X25519 || Kyber768, private = X25519 secret || Kyber768 secret
+fn hybrid_key_pair(kem: &HybridKem) -> HpkeKeyPair {
+    let mut public = kem.classical_public_key().as_bytes().to_vec();
+    public.extend_from_slice(&kem.pq_public_key().expect("stored public key"));
+    let mut private = Zeroizing::new(kem.classical_secret_bytes().to_vec());
+    private.extend_from_slice(kem.pq_secret_key());
+    HpkeKeyPair { public, private }
+}
+
+/// The X25519 and Kyber768 halves of a hybrid public key
+fn split_hybrid_public(public_key: &[u8]) -> Result<(X25519PublicKey, &[u8])> {
+    let end = HybridKem::CLASSICAL_KEY_LEN + HybridKem::PQ_PUBLIC_KEY_LEN;
+    if public_key.len() != end {
+        return Err(MlsError::Crypto("Invalid hybrid public key".to_string()));
+    }
+    let classical = X25519PublicKey::from(to_key(&public_key[..HybridKem::CLASSICAL_KEY_LEN])?);
+    Ok((classical, &public_key[HybridKem::CLASSICAL_KEY_LEN..end]))
+}
+
+/// Hybrid keys are Ed25519 || Dilithium3 and signatures opaque(Ed25519) || opaque(Dilithium3);
+/// both halves must verify
+fn verify_hybrid(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    if public_key.len() <= 32 {
+        return Err(MlsError::InvalidSignature);
+    }
+    let (classical, pq) = public_key.split_at(32);
+    let key = HybridVerifyingKey::from_bytes(classical.try_into().expect("split at 32"), pq)
+        .map_err(|_| MlsError::InvalidSignature)?;
+
+    let mut reader = Reader::new(signature);
+    let signature = HybridSignature {
+        classical: decode_opaque(&mut reader).map_err(|_| MlsError::InvalidSignature)?,
+        pq: Some(decode_opaque(&mut reader).map_err(|_| MlsError::InvalidSignature)?),
+    };
+    reader.finish().map_err(|_| MlsError::InvalidSignature)?;
+
+    key.verify(message, &signature, PqPolicy::RequirePq)
+        .map_err(|_| MlsError::InvalidSignature)
+}
+
+/// SignContent / EncryptContext share the same shape
+fn labelled(label: &str, content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_opaque(&[LABEL_PREFIX, label.as_bytes()].concat(), &mut out);
+    encode_opaque(content, &mut out);
+    out
+}
+
+fn sign_content(label: &str, content: &[u8]) -> Vec<u8> {
+    labelled(label, content)
+}
+
+fn encrypt_context(label: &str, context: &[u8]) -> Vec<u8> {
+    labelled(label, context)
+}
+
+impl Encode for CipherSuite {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.id().encode(out);
+    }
+}
+
+impl Decode for CipherSuite {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Self::from_id(u16::decode(reader)?)
+    }
+}
+
+/// TreeKEM node key pair
+#[derive(Clone)]
+pub struct HpkeKeyPair {
+    pub public: Vec<u8>,
+    pub private: Secret,
+}
+
+/// A member's leaf signing key
+#[derive(Clone)]
+pub struct SignatureKeyPair {
+    suite: CipherSuite,
+    public: Vec<u8>,
+    private: SigningSecret,
+}
+
+#[derive(Clone)]
+enum SigningSecret {
+    Ed25519(Secret),
+    Hybrid(Box<IdentityKey>),
+}
+
+impl SignatureKeyPair {
+    /// Sign for the hybrid suite with an existing Ed25519+Dilithium3 identity
+    pub fn from_identity(identity: IdentityKey) -> Self {
+        let mut public = identity.verifying_key().to_bytes().to_vec();
+        public.extend_from_slice(&identity.pq_verifying_key());
+        Self {
+            suite: CipherSuite::HybridKyber768ChaCha20,
+            public,
+            private: SigningSecret::Hybrid(Box::new(identity)),
+        }
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.suite
+    }
+
+    /// SignWithLabel(SignatureKey, Label, Content)
+    pub fn sign_with_label(&self, label: &str, content: &[u8]) -> Result<Vec<u8>> {
+        match &self.private {
+            SigningSecret::Ed25519(private) => {
+                let seed: [u8; 32] = private.as_slice()
+                    .try_into()
+                    .map_err(|_| MlsError::Crypto("Invalid signing key".to_string()))?;
+                let signing = SigningKey::from_bytes(&seed);
+                Ok(signing.sign(&sign_content(label, content)).to_bytes().to_vec())
+            }
+            SigningSecret::Hybrid(identity) => {
+                let signature = identity.sign(&sign_content(label, content)).map_err(crypto_error)?;
+                let mut out = Vec::new();
+                encode_opaque(&signature.classical, &mut out);
+                encode_opaque(signature.pq.as_deref().unwrap_or_default(), &mut out);
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// HPKECiphertext
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HpkeCiphertext {
+    pub kem_output: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl Encode for HpkeCiphertext {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.kem_output, out);
+        encode_opaque(&self.ciphertext, out);
+    }
+}
+
+impl Decode for HpkeCiphertext {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            kem_output: decode_opaque(reader)?,
+            ciphertext: decode_opaque(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    const HYBRID: CipherSuite = CipherSuite::HybridKyber768ChaCha20;
+
+    #[test]
+    fn test_hpke_roundtrip() {
+        let keys = SUITE.derive_key_pair(b"node secret").unwrap();
+        let ct = SUITE.encrypt_with_label(&keys.public, "UpdatePathNode", b"ctx", b"path secret").unwrap();
+
+        let pt = SUITE.decrypt_with_label(&keys.private, "UpdatePathNode", b"ctx", &ct).unwrap();
+        assert_eq!(pt.as_slice(), b"path secret");
+
+        // Label and context are both bound
+        assert!(SUITE.decrypt_with_label(&keys.private, "Welcome", b"ctx", &ct).is_err());
+        assert!(SUITE.decrypt_with_label(&keys.private, "UpdatePathNode", b"other", &ct).is_err());
+    }
+
+    #[test]
+    fn test_derive_key_pair_deterministic() {
+        let a = SUITE.derive_key_pair(b"same").unwrap();
+        let b = SUITE.derive_key_pair(b"same").unwrap();
+        let c = SUITE.derive_key_pair(b"different").unwrap();
+        assert_eq!(a.public, b.public);
+        assert_ne!(a.public, c.public);
+        assert_eq!(SUITE.recover_key_pair(b"same", &a.public).unwrap().private, a.private);
+        assert!(SUITE.recover_key_pair(b"different", &a.public).is_err());
+    }
+
+    #[test]
+    fn test_hybrid_encryption_roundtrip() {
+        let keys = HYBRID.generate_key_pair().unwrap();
+        let ct = HYBRID.encrypt_with_label(&keys.public, "UpdatePathNode", b"ctx", b"path secret").unwrap();
+
+        let pt = HYBRID.decrypt_with_label(&keys.private, "UpdatePathNode", b"ctx", &ct).unwrap();
+        assert_eq!(pt.as_slice(), b"path secret");
+        assert!(HYBRID.decrypt_with_label(&keys.private, "Welcome", b"ctx", &ct).is_err());
+
+        let other = HYBRID.generate_key_pair().unwrap();
+        assert!(HYBRID.decrypt_with_label(&other.private, "UpdatePathNode", b"ctx", &ct).is_err());
+    }
+
+    #[test]
+    fn test_hybrid_node_keys_deterministic() {
+        // Node secrets are Nh bytes long
+        let derived = HYBRID.derive_key_pair(&[1; 32]).unwrap();
+        let recovered = HYBRID.recover_key_pair(&[1; 32], &derived.public).unwrap();
+        assert_eq!(recovered.public, derived.public);
+        assert_eq!(recovered.private, derived.private);
+
+        // Both halves follow from the node secret, and the public key is just the two keys
+        let again = HYBRID.derive_key_pair(&[1; 32]).unwrap();
+        assert_eq!(again.public, derived.public);
+        assert_eq!(derived.public.len(), HybridKem::CLASSICAL_KEY_LEN + HybridKem::PQ_PUBLIC_KEY_LEN);
+        assert_ne!(HYBRID.derive_key_pair(&[2; 32]).unwrap().public, derived.public);
+
+        // Another secret doesn't match, and the derived key decrypts what was sent to the node
+        assert!(HYBRID.recover_key_pair(&[2; 32], &derived.public).is_err());
+        let ct = HYBRID.encrypt_with_label(&derived.public, "UpdatePathNode", b"", b"secret").unwrap();
+        assert!(HYBRID.decrypt_with_label(&recovered.private, "UpdatePathNode", b"", &ct).is_ok());
+    }
+
+    #[test]
+    fn test_hybrid_signatures() {
+        let keys = HYBRID.generate_signature_key_pair().unwrap();
+        assert_eq!(keys.cipher_suite(), HYBRID);
+        let sig = keys.sign_with_label("LeafNodeTBS", b"content").unwrap();
+
+        assert!(HYBRID.verify_with_label(keys.public_key(), "LeafNodeTBS", b"content", &sig).is_ok());
+        assert!(HYBRID.verify_with_label(keys.public_key(), "LeafNodeTBS", b"tampered", &sig).is_err());
+
+        // Dropping the Dilithium3 half is not accepted
+        let mut reader = Reader::new(&sig);
+        let classical = decode_opaque(&mut reader).unwrap();
+        let mut stripped = Vec::new();
+        encode_opaque(&classical, &mut stripped);
+        encode_opaque(&[], &mut stripped);
+        assert!(HYBRID.verify_with_label(keys.public_key(), "LeafNodeTBS", b"content", &stripped).is_err());
+        assert!(HYBRID.verify_with_label(&keys.public_key()[..32], "LeafNodeTBS", b"content", &sig).is_err());
+    }
+
+    #[test]
+    fn test_signature_labels() {
+        let keys = SUITE.generate_signature_key_pair().unwrap();
+        let sig = keys.sign_with_label("LeafNodeTBS", b"content").unwrap();
+
+        assert!(SUITE.verify_with_label(keys.public_key(), "LeafNodeTBS", b"content", &sig).is_ok());
+        assert!(SUITE.verify_with_label(keys.public_key(), "GroupInfoTBS", b"content", &sig).is_err());
+        assert!(SUITE.verify_with_label(keys.public_key(), "LeafNodeTBS", b"tampered", &sig).is_err());
+    }
+
+    #[test]
+    fn test_unknown_suite_rejected() {
+        assert!(CipherSuite::from_id(0x0001).is_err());
+        assert_eq!(CipherSuite::from_bytes(&[0x00, 0x03]).unwrap(), SUITE);
+        assert_eq!(CipherSuite::from_bytes(&[0xF0, 0xA1]).unwrap(), HYBRID);
+    }
+}
@@ -0,0 +1,66 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MlsError {
+    #[error("Member already in group")]
+    MemberExists,
+
+    #[error("Member not found")]
+    MemberNotFound,
+
+    #[error("Cannot remove self from group")]
+    CannotRemoveSelf,
+
+    #[error("Only group admins can do that")]
+    NotAdmin,
+
+    #[error("Decode error: {0}")]
+    Decode(String),
+
+    #[error("Unsupported ciphersuite: {0:#06x}")]
+    UnsupportedCipherSuite(u16),
+
+    #[error("Crypto error: {0}")]
+    Crypto(String),
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Invalid membership tag")]
+    InvalidMembershipTag,
+
+    #[error("Invalid confirmation tag")]
+    InvalidConfirmationTag,
+
+    #[error("Wrong group")]
+    WrongGroup,
+
+    #[error("Wrong epoch: expected {expected}, got {got}")]
+    WrongEpoch { expected: u64, got: u64 },
+
+    #[error("Invalid proposal: {0}")]
+    InvalidProposal(String),
+
+    #[error("Invalid commit: {0}")]
+    InvalidCommit(String),
+
+    #[error("Invalid tree: {0}")]
+    InvalidTree(String),
+
+    #[error("Unknown proposal reference")]
+    UnknownProposal,
+
+    #[error("Welcome not addressed to this key package")]
+    WelcomeNotForUs,
+
+    #[error("Generation {0} too far in the past or future")]
+    InvalidGeneration(u32),
+
+    #[error("No longer a member of this group")]
+    Removed,
+
+    #[error("Unexpected message: {0}")]
+    UnexpectedMessage(String),
+}
+
+pub type Result<T> = std::result::Result<T, MlsError>;
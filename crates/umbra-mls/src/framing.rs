@@ -0,0 +1,403 @@
+// Message framing: FramedContent, PublicMessage, PrivateMessage and the MLSMessage envelope
+// (RFC 9420 §6)
+
+use crate::codec::{decode_opaque, encode_opaque, Decode, Encode, Reader};
+use crate::crypto::{CipherSuite, SignatureKeyPair};
+use crate::error::{MlsError, Result};
+use crate::key_schedule::GroupContext;
+use crate::messages::{Commit, GroupInfo, KeyPackage, Proposal, Welcome};
+use crate::tree::PROTOCOL_VERSION_MLS10;
+use crate::tree_math::LeafIndex;
+
+pub const WIRE_FORMAT_PUBLIC_MESSAGE: u16 = 1;
+pub const WIRE_FORMAT_PRIVATE_MESSAGE: u16 = 2;
+pub const WIRE_FORMAT_WELCOME: u16 = 3;
+pub const WIRE_FORMAT_GROUP_INFO: u16 = 4;
+pub const WIRE_FORMAT_KEY_PACKAGE: u16 = 5;
+
+const SENDER_MEMBER: u8 = 1;
+
+const CONTENT_APPLICATION: u8 = 1;
+const CONTENT_PROPOSAL: u8 = 2;
+const CONTENT_COMMIT: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    Application(Vec<u8>),
+    Proposal(Proposal),
+    Commit(Commit),
+}
+
+impl Content {
+    fn content_type(&self) -> u8 {
+        match self {
+            Self::Application(_) => CONTENT_APPLICATION,
+            Self::Proposal(_) => CONTENT_PROPOSAL,
+            Self::Commit(_) => CONTENT_COMMIT,
+        }
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Application(data) => encode_opaque(data, out),
+            Self::Proposal(proposal) => proposal.encode(out),
+            Self::Commit(commit) => commit.encode(out),
+        }
+    }
+
+    fn decode_body(content_type: u8, reader: &mut Reader<'_>) -> Result<Self> {
+        match content_type {
+            CONTENT_APPLICATION => Ok(Self::Application(decode_opaque(reader)?)),
+            CONTENT_PROPOSAL => Ok(Self::Proposal(Proposal::decode(reader)?)),
+            CONTENT_COMMIT => Ok(Self::Commit(Commit::decode(reader)?)),
+            other => Err(MlsError::Decode(format!("Invalid content type {}", other))),
+        }
+    }
+}
+
+/// Content plus who sent it, for which group and epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramedContent {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub sender: LeafIndex,
+    pub authenticated_data: Vec<u8>,
+    pub content: Content,
+}
+
+impl Encode for FramedContent {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.group_id, out);
+        self.epoch.encode(out);
+        // Only member senders are supported
+        SENDER_MEMBER.encode(out);
+        self.sender.encode(out);
+        encode_opaque(&self.authenticated_data, out);
+        self.content.content_type().encode(out);
+        self.content.encode_body(out);
+    }
+}
+
+impl Decode for FramedContent {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let group_id = decode_opaque(reader)?;
+        let epoch = u64::decode(reader)?;
+        let sender_type = u8::decode(reader)?;
+        if sender_type != SENDER_MEMBER {
+            return Err(MlsError::Decode(format!("Unsupported sender type {}", sender_type)));
+        }
+        let sender = u32::decode(reader)?;
+        let authenticated_data = decode_opaque(reader)?;
+        let content_type = u8::decode(reader)?;
+        Ok(Self {
+            group_id,
+            epoch,
+            sender,
+            authenticated_data,
+            content: Content::decode_body(content_type, reader)?,
+        })
+    }
+}
+
+/// Signature, plus the confirmation tag for commits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramedContentAuthData {
+    pub signature: Vec<u8>,
+    pub confirmation_tag: Option<Vec<u8>>,
+}
+
+impl FramedContentAuthData {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.signature, out);
+        if let Some(tag) = &self.confirmation_tag {
+            encode_opaque(tag, out);
+        }
+    }
+
+    fn decode(content: &Content, reader: &mut Reader<'_>) -> Result<Self> {
+        let signature = decode_opaque(reader)?;
+        let confirmation_tag = match content {
+            Content::Commit(_) => Some(decode_opaque(reader)?),
+            _ => None,
+        };
+        Ok(Self { signature, confirmation_tag })
+    }
+}
+
+/// FramedContentTBS
+pub fn content_tbs(wire_format: u16, content: &FramedContent, context: &GroupContext) -> Vec<u8> {
+    let mut out = Vec::new();
+    PROTOCOL_VERSION_MLS10.encode(&mut out);
+    wire_format.encode(&mut out);
+    content.encode(&mut out);
+    context.encode(&mut out);
+    out
+}
+
+pub fn sign_content(
+    signer: &SignatureKeyPair,
+    wire_format: u16,
+    content: &FramedContent,
+    context: &GroupContext,
+) -> Result<Vec<u8>> {
+    signer.sign_with_label("FramedContentTBS", &content_tbs(wire_format, content, context))
+}
+
+pub fn verify_content(
+    suite: CipherSuite,
+    signature_key: &[u8],
+    wire_format: u16,
+    content: &FramedContent,
+    context: &GroupContext,
+    signature: &[u8],
+) -> Result<()> {
+    suite.verify_with_label(
+        signature_key,
+        "FramedContentTBS",
+        &content_tbs(wire_format, content, context),
+        signature,
+    )
+}
+
+/// AuthenticatedContent, the input to proposal references
+pub fn authenticated_content(wire_format: u16, content: &FramedContent, auth: &FramedContentAuthData) -> Vec<u8> {
+    let mut out = Vec::new();
+    wire_format.encode(&mut out);
+    content.encode(&mut out);
+    auth.encode(&mut out);
+    out
+}
+
+/// ConfirmedTranscriptHashInput for a commit
+pub fn confirmed_transcript_input(wire_format: u16, content: &FramedContent, signature: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    wire_format.encode(&mut out);
+    content.encode(&mut out);
+    encode_opaque(signature, &mut out);
+    out
+}
+
+/// Handshake messages sent in the clear, authenticated by signature and membership tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicMessage {
+    pub content: FramedContent,
+    pub auth: FramedContentAuthData,
+    pub membership_tag: Vec<u8>,
+}
+
+impl PublicMessage {
+    /// AuthenticatedContentTBM, the input to the membership tag
+    fn tbm(&self, context: &GroupContext) -> Vec<u8> {
+        let mut out = content_tbs(WIRE_FORMAT_PUBLIC_MESSAGE, &self.content, context);
+        self.auth.encode(&mut out);
+        out
+    }
+
+    pub fn new(
+        suite: CipherSuite,
+        content: FramedContent,
+        auth: FramedContentAuthData,
+        membership_key: &[u8],
+        context: &GroupContext,
+    ) -> Self {
+        let mut message = Self {
+            content,
+            auth,
+            membership_tag: Vec::new(),
+        };
+        message.membership_tag = suite.mac(membership_key, &message.tbm(context));
+        message
+    }
+
+    pub fn verify_membership_tag(&self, suite: CipherSuite, membership_key: &[u8], context: &GroupContext) -> Result<()> {
+        if suite.verify_mac(membership_key, &self.tbm(context), &self.membership_tag) {
+            Ok(())
+        } else {
+            Err(MlsError::InvalidMembershipTag)
+        }
+    }
+
+    pub fn authenticated_content(&self) -> Vec<u8> {
+        authenticated_content(WIRE_FORMAT_PUBLIC_MESSAGE, &self.content, &self.auth)
+    }
+}
+
+impl Encode for PublicMessage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.content.encode(out);
+        self.auth.encode(out);
+        encode_opaque(&self.membership_tag, out);
+    }
+}
+
+impl Decode for PublicMessage {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let content = FramedContent::decode(reader)?;
+        let auth = FramedContentAuthData::decode(&content.content, reader)?;
+        Ok(Self {
+            content,
+            auth,
+            membership_tag: decode_opaque(reader)?,
+        })
+    }
+}
+
+/// Encrypted application message; the sender is hidden inside `encrypted_sender_data`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivateMessage {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub content_type: u8,
+    pub authenticated_data: Vec<u8>,
+    pub encrypted_sender_data: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl PrivateMessage {
+    /// PrivateContentAAD
+    pub fn content_aad(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_opaque(&self.group_id, &mut out);
+        self.epoch.encode(&mut out);
+        self.content_type.encode(&mut out);
+        encode_opaque(&self.authenticated_data, &mut out);
+        out
+    }
+
+    /// SenderDataAAD
+    pub fn sender_data_aad(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_opaque(&self.group_id, &mut out);
+        self.epoch.encode(&mut out);
+        self.content_type.encode(&mut out);
+        out
+    }
+
+    pub fn is_application(&self) -> bool {
+        self.content_type == CONTENT_APPLICATION
+    }
+
+    pub(crate) fn application_content_type() -> u8 {
+        CONTENT_APPLICATION
+    }
+}
+
+impl Encode for PrivateMessage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.group_id, out);
+        self.epoch.encode(out);
+        self.content_type.encode(out);
+        encode_opaque(&self.authenticated_data, out);
+        encode_opaque(&self.encrypted_sender_data, out);
+        encode_opaque(&self.ciphertext, out);
+    }
+}
+
+impl Decode for PrivateMessage {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            group_id: decode_opaque(reader)?,
+            epoch: u64::decode(reader)?,
+            content_type: u8::decode(reader)?,
+            authenticated_data: decode_opaque(reader)?,
+            encrypted_sender_data: decode_opaque(reader)?,
+            ciphertext: decode_opaque(reader)?,
+        })
+    }
+}
+
+/// SenderData, encrypted under a key derived from a sample of the ciphertext
+pub struct SenderData {
+    pub leaf_index: LeafIndex,
+    pub generation: u32,
+    pub reuse_guard: [u8; 4],
+}
+
+impl Encode for SenderData {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.leaf_index.encode(out);
+        self.generation.encode(out);
+        out.extend_from_slice(&self.reuse_guard);
+    }
+}
+
+impl Decode for SenderData {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            leaf_index: u32::decode(reader)?,
+            generation: u32::decode(reader)?,
+            reuse_guard: reader.take(4)?.try_into().expect("length checked"),
+        })
+    }
+}
+
+/// PrivateMessageContent for application data: data, signature, zero padding
+pub fn encode_application_content(data: &[u8], signature: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_opaque(data, &mut out);
+    encode_opaque(signature, &mut out);
+    out
+}
+
+pub fn decode_application_content(bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut reader = Reader::new(bytes);
+    let data = decode_opaque(&mut reader)?;
+    let signature = decode_opaque(&mut reader)?;
+    if reader.rest().iter().any(|&b| b != 0) {
+        return Err(MlsError::Decode("Non-zero padding".to_string()));
+    }
+    Ok((data, signature))
+}
+
+/// The outer MLSMessage envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MlsMessage {
+    Public(PublicMessage),
+    Private(PrivateMessage),
+    Welcome(Welcome),
+    GroupInfo(GroupInfo),
+    KeyPackage(KeyPackage),
+}
+
+impl MlsMessage {
+    pub fn wire_format(&self) -> u16 {
+        match self {
+            Self::Public(_) => WIRE_FORMAT_PUBLIC_MESSAGE,
+            Self::Private(_) => WIRE_FORMAT_PRIVATE_MESSAGE,
+            Self::Welcome(_) => WIRE_FORMAT_WELCOME,
+            Self::GroupInfo(_) => WIRE_FORMAT_GROUP_INFO,
+            Self::KeyPackage(_) => WIRE_FORMAT_KEY_PACKAGE,
+        }
+    }
+}
+
+impl Encode for MlsMessage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        PROTOCOL_VERSION_MLS10.encode(out);
+        self.wire_format().encode(out);
+        match self {
+            Self::Public(m) => m.encode(out),
+            Self::Private(m) => m.encode(out),
+            Self::Welcome(m) => m.encode(out),
+            Self::GroupInfo(m) => m.encode(out),
+            Self::KeyPackage(m) => m.encode(out),
+        }
+    }
+}
+
+impl Decode for MlsMessage {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let version = u16::decode(reader)?;
+        if version != PROTOCOL_VERSION_MLS10 {
+            return Err(MlsError::Decode(format!("Unsupported protocol version {}", version)));
+        }
+        match u16::decode(reader)? {
+            WIRE_FORMAT_PUBLIC_MESSAGE => Ok(Self::Public(PublicMessage::decode(reader)?)),
+            WIRE_FORMAT_PRIVATE_MESSAGE => Ok(Self::Private(PrivateMessage::decode(reader)?)),
+            WIRE_FORMAT_WELCOME => Ok(Self::Welcome(Welcome::decode(reader)?)),
+            WIRE_FORMAT_GROUP_INFO => Ok(Self::GroupInfo(GroupInfo::decode(reader)?)),
+            WIRE_FORMAT_KEY_PACKAGE => Ok(Self::KeyPackage(KeyPackage::decode(reader)?)),
+            other => Err(MlsError::Decode(format!("Unknown wire format {}", other))),
+        }
+    }
+}
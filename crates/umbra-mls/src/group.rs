@@ -1,134 +1,1339 @@
+// MLS group state machine: create, propose, commit, process and join via Welcome
+// Handshake messages (proposals, commits) travel as PublicMessage; application data as PrivateMessage
+
+use crate::codec::{Decode, Encode};
+use crate::crypto::{CipherSuite, HpkeKeyPair, Secret, SignatureKeyPair};
 use crate::error::{MlsError, Result};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::framing::{
+    self, Content, FramedContent, FramedContentAuthData, MlsMessage, PrivateMessage, PublicMessage, SenderData,
+    WIRE_FORMAT_PRIVATE_MESSAGE, WIRE_FORMAT_PUBLIC_MESSAGE,
+};
+use crate::key_schedule::{self, EpochSecrets, GroupContext};
+use crate::messages::{
+    Commit, EncryptedGroupSecrets, GroupInfo, GroupSecrets, KeyPackage, KeyPackageBundle, Proposal, ProposalOrRef,
+    UpdatePath, UpdatePathNode, Welcome,
+};
+use crate::roles::{self, GroupMetadata, GroupRoles, Role};
+use crate::secret_tree::SecretTree;
+use crate::tree::{Credential, Extension, LeafNode, LeafNodeSource, Lifetime, RatchetTree, EXTENSION_RATCHET_TREE};
+use crate::tree_math::{self, LeafIndex, NodeIndex};
+use std::collections::{HashMap, HashSet};
+
+/// Exporter label for the per-epoch topic chat key
+pub const TOPIC_KEY_LABEL: &str = "umbra topic key";
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GroupId(pub Vec<u8>);
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Member {
-    pub id: Vec<u8>,
-    pub public_key: Vec<u8>,
+    pub leaf_index: LeafIndex,
+    pub identity: Vec<u8>,
+    pub signature_key: Vec<u8>,
+    pub role: Role,
+}
+
+/// What an incoming message did to the group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessedMessage {
+    Application { sender: LeafIndex, data: Vec<u8> },
+    Proposal { sender: LeafIndex, reference: Vec<u8> },
+    Commit { sender: LeafIndex, epoch: u64 },
+    /// The commit removed us; the group is no longer usable
+    Removed { sender: LeafIndex },
+}
+
+/// A commit to broadcast to the group, and a Welcome for any members it added
+pub struct CommitOutput {
+    pub commit: MlsMessage,
+    pub welcome: Option<MlsMessage>,
+}
+
+struct PendingProposal {
+    reference: Vec<u8>,
+    sender: LeafIndex,
+    proposal: Proposal,
+}
+
+/// Result of applying a commit's proposals to a copy of the tree
+struct AppliedProposals {
+    tree: RatchetTree,
+    joiners: Vec<(LeafIndex, KeyPackage)>,
+    removed: HashSet<LeafIndex>,
+    /// GroupContext extensions for the new epoch
+    extensions: Vec<Extension>,
 }
 
-#[derive(Clone, Debug)]
 pub struct Group {
-    id: GroupId,
-    epoch: u64,
-    members: HashMap<Vec<u8>, Member>,
-    my_id: Vec<u8>,
+    suite: CipherSuite,
+    context: GroupContext,
+    tree: RatchetTree,
+    own_leaf: LeafIndex,
+    signer: SignatureKeyPair,
+    /// Private keys for our leaf and the parent nodes we know, by node index
+    node_keys: HashMap<NodeIndex, HpkeKeyPair>,
+    secrets: EpochSecrets,
+    secret_tree: SecretTree,
+    interim_transcript_hash: Vec<u8>,
+    pending: Vec<PendingProposal>,
+    /// Leaf key from an Update we proposed but nobody has committed yet
+    pending_update: Option<HpkeKeyPair>,
+    active: bool,
 }
 
 impl Group {
-    pub fn create(creator_id: Vec<u8>, creator_pk: Vec<u8>) -> Result<Self> {
-        let group_id = GroupId(rand::random::<[u8; 32]>().to_vec());
-        
-        let mut members = HashMap::new();
-        members.insert(
-            creator_id.clone(),
-            Member {
-                id: creator_id.clone(),
-                public_key: creator_pk,
+    /// Start a one-member group with a random id
+    pub fn create(credential: Credential, signer: SignatureKeyPair) -> Result<Self> {
+        Self::create_with_id(rand::random::<[u8; 32]>().to_vec(), credential, signer)
+    }
+
+    /// Start a one-member group with a caller-chosen id (e.g. the chat topic)
+    pub fn create_with_id(group_id: Vec<u8>, credential: Credential, signer: SignatureKeyPair) -> Result<Self> {
+        let suite = signer.cipher_suite();
+        let leaf_keys = suite.generate_key_pair()?;
+        let leaf = LeafNode::new(
+            suite,
+            leaf_keys.public.clone(),
+            credential,
+            LeafNodeSource::KeyPackage(Lifetime::from_now(crate::messages::KEY_PACKAGE_LIFETIME_SECS)),
+            &signer,
+            &[],
+            0,
+        )?;
+        let tree = RatchetTree::new(suite, leaf);
+
+        let context = GroupContext {
+            cipher_suite: suite,
+            group_id,
+            epoch: 0,
+            tree_hash: tree.tree_hash(),
+            confirmed_transcript_hash: Vec::new(),
+            // The creator starts out as the only admin
+            extensions: vec![GroupRoles::with_admin(signer.public_key()).to_extension()],
+        };
+        let init_secret = key_schedule::random_secret(suite);
+        let secrets = EpochSecrets::derive(suite, &init_secret, &vec![0u8; suite.hash_len()], &context)?;
+        let confirmation_tag = suite.mac(&secrets.confirmation_key, &context.confirmed_transcript_hash);
+        let interim_transcript_hash =
+            key_schedule::interim_transcript_hash(suite, &context.confirmed_transcript_hash, &confirmation_tag);
+
+        let mut node_keys = HashMap::new();
+        node_keys.insert(0, leaf_keys);
+
+        Ok(Self {
+            suite,
+            secret_tree: SecretTree::new(suite, secrets.encryption_secret.clone(), 1),
+            context,
+            tree,
+            own_leaf: 0,
+            signer,
+            node_keys,
+            secrets,
+            interim_transcript_hash,
+            pending: Vec::new(),
+            pending_update: None,
+            active: true,
+        })
+    }
+
+    /// Join the group a Welcome describes, using the KeyPackage it was addressed to
+    pub fn join(welcome: &Welcome, bundle: KeyPackageBundle) -> Result<Self> {
+        let suite = bundle.signer.cipher_suite();
+        if welcome.cipher_suite != suite {
+            return Err(MlsError::UnsupportedCipherSuite(welcome.cipher_suite.id()));
+        }
+
+        let kp_ref = bundle.key_package.reference();
+        let entry = welcome
+            .secrets
+            .iter()
+            .find(|s| s.new_member == kp_ref)
+            .ok_or(MlsError::WelcomeNotForUs)?;
+        let group_secrets = GroupSecrets::from_bytes(&suite.decrypt_with_label(
+            &bundle.init_private,
+            "Welcome",
+            &welcome.encrypted_group_info,
+            &entry.encrypted_group_secrets,
+        )?)?;
+
+        let welcome_secret = key_schedule::welcome_secret_from_joiner(suite, &group_secrets.joiner_secret)?;
+        let (key, nonce) = key_schedule::welcome_key_nonce(suite, &welcome_secret)?;
+        let group_info = GroupInfo::from_bytes(&suite.open(&key, &nonce, &[], &welcome.encrypted_group_info)?)?;
+        let context = group_info.group_context.clone();
+        if context.cipher_suite != suite {
+            return Err(MlsError::UnsupportedCipherSuite(context.cipher_suite.id()));
+        }
+
+        let tree_bytes = group_info
+            .extension(EXTENSION_RATCHET_TREE)
+            .ok_or_else(|| MlsError::InvalidTree("Welcome carries no ratchet tree".to_string()))?;
+        let tree = RatchetTree::decode_nodes(suite, tree_bytes)?;
+        if tree.tree_hash() != context.tree_hash {
+            return Err(MlsError::InvalidTree("Tree hash does not match GroupInfo".to_string()));
+        }
+        tree.verify_leaves(&context.group_id)?;
+        tree.verify_parent_hashes()?;
+        GroupRoles::from_extensions(&context.extensions)?;
+        GroupMetadata::from_extensions(&context.extensions)?;
+
+        let signer_leaf = tree.leaf(group_info.signer).ok_or(MlsError::MemberNotFound)?;
+        suite.verify_with_label(&signer_leaf.signature_key, "GroupInfoTBS", &group_info.tbs(), &group_info.signature)?;
+
+        let own_leaf = tree
+            .find_leaf(|leaf| *leaf == bundle.key_package.leaf_node)
+            .ok_or_else(|| MlsError::InvalidTree("Our leaf is not in the tree".to_string()))?;
+
+        let mut node_keys = HashMap::new();
+        node_keys.insert(
+            tree_math::leaf_to_node(own_leaf),
+            HpkeKeyPair {
+                public: bundle.key_package.leaf_node.encryption_key.clone(),
+                private: bundle.encryption_private.clone(),
             },
         );
-        
+
+        // The committer's path secret for our common ancestor unlocks every node above it
+        if let Some(path_secret) = group_secrets.path_secret {
+            let ancestor = tree_math::common_ancestor(
+                tree_math::leaf_to_node(own_leaf),
+                tree_math::leaf_to_node(group_info.signer),
+            );
+            let path: Vec<NodeIndex> = tree
+                .filtered_direct_path(group_info.signer)
+                .into_iter()
+                .map(|(node, _)| node)
+                .collect();
+            let start = path
+                .iter()
+                .position(|&n| n == ancestor)
+                .ok_or_else(|| MlsError::InvalidTree("Path secret for a node off the committer's path".to_string()))?;
+            let published = path[start..]
+                .iter()
+                .map(|&node| tree.node(node).map(|n| n.encryption_key().to_vec()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| MlsError::InvalidTree("Blank node on the committer's path".to_string()))?;
+            let (keys, _) = derive_path_keys(suite, path_secret, &path[start..], Some(&published))
+                .map_err(|_| MlsError::InvalidTree("Path secret does not match the tree".to_string()))?;
+            node_keys.extend(keys);
+        }
+
+        let secrets = EpochSecrets::from_joiner(suite, group_secrets.joiner_secret, &context)?;
+        if !suite.verify_mac(&secrets.confirmation_key, &context.confirmed_transcript_hash, &group_info.confirmation_tag) {
+            return Err(MlsError::InvalidConfirmationTag);
+        }
+        let interim_transcript_hash = key_schedule::interim_transcript_hash(
+            suite,
+            &context.confirmed_transcript_hash,
+            &group_info.confirmation_tag,
+        );
+
         Ok(Self {
-            id: group_id,
-            epoch: 0,
-            members,
-            my_id: creator_id,
+            suite,
+            secret_tree: SecretTree::new(suite, secrets.encryption_secret.clone(), tree.n_leaves()),
+            context,
+            tree,
+            own_leaf,
+            signer: bundle.signer,
+            node_keys,
+            secrets,
+            interim_transcript_hash,
+            pending: Vec::new(),
+            pending_update: None,
+            active: true,
         })
     }
-    
-    pub fn id(&self) -> &GroupId {
-        &self.id
+
+    pub fn id(&self) -> GroupId {
+        GroupId(self.context.group_id.clone())
     }
-    
+
     pub fn epoch(&self) -> u64 {
-        self.epoch
-    }
-    
-    pub fn add_member(&mut self, member_id: Vec<u8>, public_key: Vec<u8>) -> Result<()> {
-        if self.members.contains_key(&member_id) {
-            return Err(MlsError::MemberExists);
-        }
-        
-        self.members.insert(
-            member_id.clone(),
-            Member {
-                id: member_id,
-                public_key,
-            },
-        );
-        
-        self.epoch += 1;
-        Ok(())
+        self.context.epoch
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.suite
     }
-    
-    pub fn remove_member(&mut self, member_id: &[u8]) -> Result<()> {
-        if !self.members.contains_key(member_id) {
+
+    pub fn own_leaf_index(&self) -> LeafIndex {
+        self.own_leaf
+    }
+
+    /// False once a commit has removed us
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn member_count(&self) -> usize {
+        self.tree.leaves().count()
+    }
+
+    pub fn members(&self) -> Vec<Member> {
+        let roles = self.roles();
+        self.tree
+            .leaves()
+            .map(|(leaf_index, leaf)| Member {
+                leaf_index,
+                identity: leaf.credential.identity.clone(),
+                signature_key: leaf.signature_key.clone(),
+                role: roles.role(&leaf.signature_key),
+            })
+            .collect()
+    }
+
+    pub fn role(&self, leaf_index: LeafIndex) -> Option<Role> {
+        self.tree.leaf(leaf_index).map(|leaf| self.roles().role(&leaf.signature_key))
+    }
+
+    pub fn is_admin(&self, leaf_index: LeafIndex) -> bool {
+        self.role(leaf_index) == Some(Role::Admin)
+    }
+
+    /// Room name and topic as of this epoch
+    pub fn metadata(&self) -> GroupMetadata {
+        GroupMetadata::from_extensions(&self.context.extensions).unwrap_or_default()
+    }
+
+    /// Admin-only: commit a role change for another member (or ourselves)
+    pub fn set_role(&mut self, leaf_index: LeafIndex, role: Role) -> Result<CommitOutput> {
+        let leaf = self.tree.leaf(leaf_index).ok_or(MlsError::MemberNotFound)?;
+        let mut roles = self.roles();
+        roles.set_role(&leaf.signature_key, role);
+        let extensions = roles::replace_extension(&self.context.extensions, roles.to_extension());
+        self.commit(vec![Proposal::GroupContextExtensions(extensions)])
+    }
+
+    pub fn promote(&mut self, leaf_index: LeafIndex) -> Result<CommitOutput> {
+        self.set_role(leaf_index, Role::Admin)
+    }
+
+    pub fn demote(&mut self, leaf_index: LeafIndex) -> Result<CommitOutput> {
+        self.set_role(leaf_index, Role::Member)
+    }
+
+    /// Admin-only: commit a new room name and topic
+    pub fn set_metadata(&mut self, metadata: GroupMetadata) -> Result<CommitOutput> {
+        let extensions = roles::replace_extension(&self.context.extensions, metadata.to_extension());
+        self.commit(vec![Proposal::GroupContextExtensions(extensions)])
+    }
+
+    /// SignWithLabel under our leaf signature key, for group control messages sent outside MLS
+    pub fn sign_with_label(&self, label: &str, content: &[u8]) -> Result<Vec<u8>> {
+        self.signer.sign_with_label(label, content)
+    }
+
+    pub fn signature_key(&self) -> &[u8] {
+        self.signer.public_key()
+    }
+
+    pub fn member_index(&self, identity: &[u8]) -> Option<LeafIndex> {
+        self.tree.find_leaf(|leaf| leaf.credential.identity == identity)
+    }
+
+    /// Value all members share for this epoch, for out-of-band comparison
+    pub fn epoch_authenticator(&self) -> &[u8] {
+        &self.secrets.epoch_authenticator
+    }
+
+    /// MLS-Exporter(label, context, len)
+    pub fn export_secret(&self, label: &str, context: &[u8], len: usize) -> Result<Secret> {
+        self.secrets.export(self.suite, label, context, len)
+    }
+
+    /// Symmetric key for topic chat in this epoch; rotates with every commit
+    pub fn topic_key(&self) -> Result<[u8; 32]> {
+        let key = self.export_secret(TOPIC_KEY_LABEL, &self.context.group_id, 32)?;
+        Ok(key.as_slice().try_into().expect("exported 32 bytes"))
+    }
+
+    /// Commit an Add for `key_package`
+    pub fn add_member(&mut self, key_package: KeyPackage) -> Result<CommitOutput> {
+        self.commit(vec![Proposal::Add(key_package)])
+    }
+
+    /// Commit a Remove for another member
+    pub fn remove_member(&mut self, leaf_index: LeafIndex) -> Result<CommitOutput> {
+        if leaf_index == self.own_leaf {
+            return Err(MlsError::CannotRemoveSelf);
+        }
+        if self.tree.leaf(leaf_index).is_none() {
             return Err(MlsError::MemberNotFound);
         }
-        
-        if member_id == self.my_id.as_slice() {
-            return Err(MlsError::CannotRemoveSelf);
+        self.commit(vec![Proposal::Remove(leaf_index)])
+    }
+
+    /// Empty commit: refreshes our path keys and moves everyone to a new epoch
+    pub fn rekey(&mut self) -> Result<CommitOutput> {
+        self.commit(Vec::new())
+    }
+
+    pub fn propose_add(&mut self, key_package: KeyPackage) -> Result<MlsMessage> {
+        key_package.verify(self.suite)?;
+        self.propose(Proposal::Add(key_package))
+    }
+
+    pub fn propose_remove(&mut self, leaf_index: LeafIndex) -> Result<MlsMessage> {
+        if self.tree.leaf(leaf_index).is_none() {
+            return Err(MlsError::MemberNotFound);
         }
-        
-        self.members.remove(member_id);
-        self.epoch += 1;
-        Ok(())
+        self.propose(Proposal::Remove(leaf_index))
+    }
+
+    /// Propose a fresh leaf key for ourselves; another member's commit applies it
+    pub fn propose_update(&mut self) -> Result<MlsMessage> {
+        let keys = self.suite.generate_key_pair()?;
+        let own = self.tree.leaf(self.own_leaf).ok_or(MlsError::Removed)?;
+        let leaf = LeafNode::new(
+            self.suite,
+            keys.public.clone(),
+            own.credential.clone(),
+            LeafNodeSource::Update,
+            &self.signer,
+            &self.context.group_id,
+            self.own_leaf,
+        )?;
+        let message = self.propose(Proposal::Update(leaf))?;
+        self.pending_update = Some(keys);
+        Ok(message)
+    }
+
+    fn propose(&mut self, proposal: Proposal) -> Result<MlsMessage> {
+        self.ensure_active()?;
+        self.validate_proposal(&proposal, self.own_leaf)?;
+        let content = self.framed(Content::Proposal(proposal.clone()));
+        let signature = framing::sign_content(&self.signer, WIRE_FORMAT_PUBLIC_MESSAGE, &content, &self.context)?;
+        let auth = FramedContentAuthData { signature, confirmation_tag: None };
+        let message = PublicMessage::new(self.suite, content, auth, &self.secrets.membership_key, &self.context);
+
+        self.pending.push(PendingProposal {
+            reference: self.proposal_ref(&message),
+            sender: self.own_leaf,
+            proposal,
+        });
+        Ok(MlsMessage::Public(message))
+    }
+
+    /// Commit every pending proposal plus `proposals`, always with a path update
+    pub fn commit(&mut self, proposals: Vec<Proposal>) -> Result<CommitOutput> {
+        self.ensure_active()?;
+        let suite = self.suite;
+
+        // We can't commit our own Update; our path replaces the leaf anyway
+        let mut list: Vec<(ProposalOrRef, LeafIndex, Proposal)> = self
+            .pending
+            .iter()
+            .filter(|p| !(p.sender == self.own_leaf && matches!(p.proposal, Proposal::Update(_))))
+            .map(|p| (ProposalOrRef::Reference(p.reference.clone()), p.sender, p.proposal.clone()))
+            .collect();
+        list.extend(
+            proposals
+                .into_iter()
+                .map(|p| (ProposalOrRef::Proposal(Box::new(p.clone())), self.own_leaf, p)),
+        );
+
+        let applied = self.apply_proposals(&list, self.own_leaf)?;
+        let mut tree = applied.tree;
+
+        // Fresh path secrets up our filtered direct path
+        let filtered = tree.filtered_direct_path(self.own_leaf);
+        let path_nodes: Vec<NodeIndex> = filtered.iter().map(|(node, _)| *node).collect();
+        let leaf_secret = key_schedule::random_secret(suite);
+        let leaf_keys = suite.derive_key_pair(&suite.derive_secret(&leaf_secret, "node")?)?;
+        let (path_keys, commit_secret) =
+            derive_path_keys(suite, suite.derive_secret(&leaf_secret, "path")?, &path_nodes, None)?;
+        let path_secrets = path_secret_chain(suite, &leaf_secret, path_nodes.len())?;
+
+        let public_keys: Vec<Vec<u8>> = path_keys.iter().map(|(_, k)| k.public.clone()).collect();
+        let parent_hash = tree.apply_path(self.own_leaf, &public_keys)?;
+        let own = self.tree.leaf(self.own_leaf).ok_or(MlsError::Removed)?;
+        let leaf_node = LeafNode::new(
+            suite,
+            leaf_keys.public.clone(),
+            own.credential.clone(),
+            LeafNodeSource::Commit(parent_hash),
+            &self.signer,
+            &self.context.group_id,
+            self.own_leaf,
+        )?;
+        tree.set_leaf(self.own_leaf, leaf_node.clone());
+
+        let mut provisional = GroupContext {
+            epoch: self.context.epoch + 1,
+            tree_hash: tree.tree_hash(),
+            extensions: applied.extensions.clone(),
+            ..self.context.clone()
+        };
+
+        // Encrypt each path secret to the copath resolution, skipping the members we're adding
+        let joiner_nodes: HashSet<NodeIndex> = applied.joiners.iter().map(|(l, _)| tree_math::leaf_to_node(*l)).collect();
+        let context_bytes = provisional.to_bytes();
+        let mut update_nodes = Vec::with_capacity(filtered.len());
+        for (i, (_, copath_child)) in filtered.iter().enumerate() {
+            let mut encrypted = Vec::new();
+            for recipient in tree.resolution(*copath_child) {
+                if joiner_nodes.contains(&recipient) {
+                    continue;
+                }
+                let key = tree.node(recipient).expect("resolution is non-blank").encryption_key();
+                encrypted.push(suite.encrypt_with_label(key, "UpdatePathNode", &context_bytes, &path_secrets[i])?);
+            }
+            update_nodes.push(UpdatePathNode {
+                encryption_key: public_keys[i].clone(),
+                encrypted_path_secret: encrypted,
+            });
+        }
+
+        let commit = Commit {
+            proposals: list.into_iter().map(|(p, _, _)| p).collect(),
+            path: Some(UpdatePath { leaf_node, nodes: update_nodes }),
+        };
+        let content = self.framed(Content::Commit(commit));
+        let signature = framing::sign_content(&self.signer, WIRE_FORMAT_PUBLIC_MESSAGE, &content, &self.context)?;
+
+        provisional.confirmed_transcript_hash = key_schedule::confirmed_transcript_hash(
+            suite,
+            &self.interim_transcript_hash,
+            &framing::confirmed_transcript_input(WIRE_FORMAT_PUBLIC_MESSAGE, &content, &signature),
+        );
+        let secrets = EpochSecrets::derive(suite, &self.secrets.init_secret, &commit_secret, &provisional)?;
+        let confirmation_tag = suite.mac(&secrets.confirmation_key, &provisional.confirmed_transcript_hash);
+
+        let auth = FramedContentAuthData {
+            signature,
+            confirmation_tag: Some(confirmation_tag.clone()),
+        };
+        let message = PublicMessage::new(suite, content, auth, &self.secrets.membership_key, &self.context);
+
+        let welcome = if applied.joiners.is_empty() {
+            None
+        } else {
+            Some(self.welcome(&tree, &provisional, &secrets, &confirmation_tag, &applied.joiners, &path_nodes, &path_secrets)?)
+        };
+
+        let mut node_keys: HashMap<NodeIndex, HpkeKeyPair> = path_keys.into_iter().collect();
+        node_keys.insert(tree_math::leaf_to_node(self.own_leaf), leaf_keys);
+        self.node_keys = node_keys;
+        self.pending_update = None;
+        self.install_epoch(tree, provisional, secrets, &confirmation_tag);
+
+        Ok(CommitOutput {
+            commit: MlsMessage::Public(message),
+            welcome: welcome.map(MlsMessage::Welcome),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn welcome(
+        &self,
+        tree: &RatchetTree,
+        context: &GroupContext,
+        secrets: &EpochSecrets,
+        confirmation_tag: &[u8],
+        joiners: &[(LeafIndex, KeyPackage)],
+        path_nodes: &[NodeIndex],
+        path_secrets: &[Secret],
+    ) -> Result<Welcome> {
+        let suite = self.suite;
+        let mut group_info = GroupInfo {
+            group_context: context.clone(),
+            extensions: vec![Extension {
+                extension_type: EXTENSION_RATCHET_TREE,
+                data: tree.encode_nodes(),
+            }],
+            confirmation_tag: confirmation_tag.to_vec(),
+            signer: self.own_leaf,
+            signature: Vec::new(),
+        };
+        group_info.signature = self.signer.sign_with_label("GroupInfoTBS", &group_info.tbs())?;
+
+        let (key, nonce) = key_schedule::welcome_key_nonce(suite, &secrets.welcome_secret)?;
+        let encrypted_group_info = suite.seal(&key, &nonce, &[], &group_info.to_bytes())?;
+
+        let own_node = tree_math::leaf_to_node(self.own_leaf);
+        let mut encrypted_secrets = Vec::with_capacity(joiners.len());
+        for (leaf, key_package) in joiners {
+            let ancestor = tree_math::common_ancestor(tree_math::leaf_to_node(*leaf), own_node);
+            let path_secret = path_nodes
+                .iter()
+                .position(|&n| n == ancestor)
+                .map(|i| path_secrets[i].clone());
+            let group_secrets = GroupSecrets {
+                joiner_secret: secrets.joiner_secret.clone(),
+                path_secret,
+            };
+            encrypted_secrets.push(EncryptedGroupSecrets {
+                new_member: key_package.reference(),
+                encrypted_group_secrets: suite.encrypt_with_label(
+                    &key_package.init_key,
+                    "Welcome",
+                    &encrypted_group_info,
+                    &group_secrets.to_bytes(),
+                )?,
+            });
+        }
+
+        Ok(Welcome {
+            cipher_suite: suite,
+            secrets: encrypted_secrets,
+            encrypted_group_info,
+        })
     }
-    
-    pub fn rekey(&mut self) -> Result<()> {
-        self.epoch += 1;
+
+    /// Handle a proposal, commit or application message from another member
+    pub fn process_message(&mut self, message: &MlsMessage) -> Result<ProcessedMessage> {
+        self.ensure_active()?;
+        match message {
+            MlsMessage::Public(public) => self.process_public(public),
+            MlsMessage::Private(private) => self.decrypt_application(private),
+            other => Err(MlsError::UnexpectedMessage(format!("wire format {}", other.wire_format()))),
+        }
+    }
+
+    /// The proposals a commit would apply, resolved against our pending ones but not applied
+    /// (and not verified: `process_message` still decides whether the commit is valid)
+    pub fn commit_proposals(&self, message: &MlsMessage) -> Result<Vec<Proposal>> {
+        let MlsMessage::Public(PublicMessage { content: FramedContent { content: Content::Commit(commit), .. }, .. }) = message
+        else {
+            return Err(MlsError::UnexpectedMessage("Not a commit".to_string()));
+        };
+        commit
+            .proposals
+            .iter()
+            .map(|item| match item {
+                ProposalOrRef::Proposal(p) => Ok((**p).clone()),
+                ProposalOrRef::Reference(r) => self
+                    .pending
+                    .iter()
+                    .find(|p| &p.reference == r)
+                    .map(|p| p.proposal.clone())
+                    .ok_or(MlsError::UnknownProposal),
+            })
+            .collect()
+    }
+
+    fn process_public(&mut self, message: &PublicMessage) -> Result<ProcessedMessage> {
+        let content = &message.content;
+        self.check_epoch(&content.group_id, content.epoch)?;
+        if content.sender == self.own_leaf {
+            return Err(MlsError::UnexpectedMessage("Message from our own leaf".to_string()));
+        }
+        let sender_leaf = self.tree.leaf(content.sender).ok_or(MlsError::MemberNotFound)?;
+
+        message.verify_membership_tag(self.suite, &self.secrets.membership_key, &self.context)?;
+        framing::verify_content(
+            self.suite,
+            &sender_leaf.signature_key,
+            WIRE_FORMAT_PUBLIC_MESSAGE,
+            content,
+            &self.context,
+            &message.auth.signature,
+        )?;
+
+        match &content.content {
+            Content::Proposal(proposal) => {
+                self.validate_proposal(proposal, content.sender)?;
+                let reference = self.proposal_ref(message);
+                self.pending.push(PendingProposal {
+                    reference: reference.clone(),
+                    sender: content.sender,
+                    proposal: proposal.clone(),
+                });
+                Ok(ProcessedMessage::Proposal { sender: content.sender, reference })
+            }
+            Content::Commit(commit) => self.process_commit(message, commit),
+            Content::Application(_) => Err(MlsError::UnexpectedMessage(
+                "Application data must be sent as a PrivateMessage".to_string(),
+            )),
+        }
+    }
+
+    fn process_commit(&mut self, message: &PublicMessage, commit: &Commit) -> Result<ProcessedMessage> {
+        let suite = self.suite;
+        let committer = message.content.sender;
+
+        let mut list = Vec::with_capacity(commit.proposals.len());
+        for item in &commit.proposals {
+            match item {
+                ProposalOrRef::Proposal(p) => list.push((item.clone(), committer, (**p).clone())),
+                ProposalOrRef::Reference(r) => {
+                    let pending = self.pending.iter().find(|p| &p.reference == r).ok_or(MlsError::UnknownProposal)?;
+                    list.push((item.clone(), pending.sender, pending.proposal.clone()));
+                }
+            }
+        }
+
+        let applied = self.apply_proposals(&list, committer)?;
+        if applied.removed.contains(&self.own_leaf) {
+            self.active = false;
+            return Ok(ProcessedMessage::Removed { sender: committer });
+        }
+
+        let path = commit
+            .path
+            .as_ref()
+            .ok_or_else(|| MlsError::InvalidCommit("Commit without an UpdatePath".to_string()))?;
+        if !matches!(path.leaf_node.source, LeafNodeSource::Commit(_)) {
+            return Err(MlsError::InvalidCommit("Path leaf must have commit source".to_string()));
+        }
+        path.leaf_node.verify(suite, &self.context.group_id, committer)?;
+        if self.tree.leaf(committer).map(|l| &l.signature_key) != Some(&path.leaf_node.signature_key) {
+            return Err(MlsError::InvalidCommit("Commit changes the committer's signature key".to_string()));
+        }
+
+        let mut tree = applied.tree;
+        let filtered = tree.filtered_direct_path(committer);
+        let public_keys: Vec<Vec<u8>> = path.nodes.iter().map(|n| n.encryption_key.clone()).collect();
+        let parent_hash = tree.apply_path(committer, &public_keys)?;
+        if path.leaf_node.parent_hash() != Some(parent_hash.as_slice()) {
+            return Err(MlsError::InvalidCommit("Parent hash mismatch".to_string()));
+        }
+        tree.set_leaf(committer, path.leaf_node.clone());
+
+        let mut provisional = GroupContext {
+            epoch: self.context.epoch + 1,
+            tree_hash: tree.tree_hash(),
+            extensions: applied.extensions.clone(),
+            ..self.context.clone()
+        };
+
+        // Our keys as of the proposals: an Update we proposed may have just replaced our leaf
+        let own_node = tree_math::leaf_to_node(self.own_leaf);
+        let mut known = self.node_keys.clone();
+        if let Some(update) = &self.pending_update {
+            if tree.node(own_node).map(|n| n.encryption_key()) == Some(update.public.as_slice()) {
+                known.insert(own_node, update.clone());
+            }
+        }
+
+        // Find the path node above us and the ciphertext addressed to a node we hold a key for
+        let index = filtered
+            .iter()
+            .position(|(node, _)| tree_math::is_ancestor(*node, own_node))
+            .ok_or_else(|| MlsError::InvalidCommit("Commit path does not cover us".to_string()))?;
+        let joiner_nodes: HashSet<NodeIndex> = applied.joiners.iter().map(|(l, _)| tree_math::leaf_to_node(*l)).collect();
+        let recipients: Vec<NodeIndex> = tree
+            .resolution(filtered[index].1)
+            .into_iter()
+            .filter(|n| !joiner_nodes.contains(n))
+            .collect();
+        let ciphertexts = &path.nodes[index].encrypted_path_secret;
+        if ciphertexts.len() != recipients.len() {
+            return Err(MlsError::InvalidCommit("Wrong number of path secret ciphertexts".to_string()));
+        }
+        let (slot, keys) = recipients
+            .iter()
+            .enumerate()
+            .find_map(|(i, node)| {
+                known
+                    .get(node)
+                    .filter(|k| tree.node(*node).map(|n| n.encryption_key()) == Some(k.public.as_slice()))
+                    .map(|k| (i, k))
+            })
+            .ok_or_else(|| MlsError::InvalidCommit("No key for any path secret recipient".to_string()))?;
+
+        let path_secret = suite.decrypt_with_label(&keys.private, "UpdatePathNode", &provisional.to_bytes(), &ciphertexts[slot])?;
+        let upper: Vec<NodeIndex> = filtered[index..].iter().map(|(node, _)| *node).collect();
+        let (new_keys, commit_secret) = derive_path_keys(suite, path_secret, &upper, Some(&public_keys[index..]))
+            .map_err(|_| MlsError::InvalidCommit("Path secret does not match the public key".to_string()))?;
+
+        provisional.confirmed_transcript_hash = key_schedule::confirmed_transcript_hash(
+            suite,
+            &self.interim_transcript_hash,
+            &framing::confirmed_transcript_input(WIRE_FORMAT_PUBLIC_MESSAGE, &message.content, &message.auth.signature),
+        );
+        let secrets = EpochSecrets::derive(suite, &self.secrets.init_secret, &commit_secret, &provisional)?;
+        let confirmation_tag = message
+            .auth
+            .confirmation_tag
+            .as_ref()
+            .ok_or(MlsError::InvalidConfirmationTag)?;
+        if !suite.verify_mac(&secrets.confirmation_key, &provisional.confirmed_transcript_hash, confirmation_tag) {
+            return Err(MlsError::InvalidConfirmationTag);
+        }
+
+        // Keep only keys whose public half is still in the tree
+        known.extend(new_keys);
+        known.retain(|node, pair| tree.node(*node).map(|n| n.encryption_key()) == Some(pair.public.as_slice()));
+        self.node_keys = known;
+        self.pending_update = None;
+
+        let confirmation_tag = confirmation_tag.clone();
+        self.install_epoch(tree, provisional, secrets, &confirmation_tag);
+        Ok(ProcessedMessage::Commit {
+            sender: committer,
+            epoch: self.context.epoch,
+        })
+    }
+
+    /// Encrypt application data for the current epoch
+    pub fn encrypt_application_message(&mut self, data: &[u8]) -> Result<MlsMessage> {
+        self.ensure_active()?;
+        let suite = self.suite;
+        let content = self.framed(Content::Application(data.to_vec()));
+        let signature = framing::sign_content(&self.signer, WIRE_FORMAT_PRIVATE_MESSAGE, &content, &self.context)?;
+
+        let key = self.secret_tree.next_key(self.own_leaf)?;
+        let reuse_guard: [u8; 4] = rand::random();
+        let nonce = apply_reuse_guard(&key.nonce, &reuse_guard);
+
+        let mut message = PrivateMessage {
+            group_id: self.context.group_id.clone(),
+            epoch: self.context.epoch,
+            content_type: PrivateMessage::application_content_type(),
+            authenticated_data: Vec::new(),
+            encrypted_sender_data: Vec::new(),
+            ciphertext: Vec::new(),
+        };
+        message.ciphertext = suite.seal(
+            &key.key,
+            &nonce,
+            &message.content_aad(),
+            &framing::encode_application_content(data, &signature),
+        )?;
+
+        let sender_data = SenderData {
+            leaf_index: self.own_leaf,
+            generation: key.generation,
+            reuse_guard,
+        };
+        let (sd_key, sd_nonce) = self.sender_data_key(&message.ciphertext)?;
+        message.encrypted_sender_data = suite.seal(&sd_key, &sd_nonce, &message.sender_data_aad(), &sender_data.to_bytes())?;
+
+        Ok(MlsMessage::Private(message))
+    }
+
+    fn decrypt_application(&mut self, message: &PrivateMessage) -> Result<ProcessedMessage> {
+        let suite = self.suite;
+        self.check_epoch(&message.group_id, message.epoch)?;
+        if !message.is_application() {
+            return Err(MlsError::UnexpectedMessage("Only application data is sent encrypted".to_string()));
+        }
+
+        let (sd_key, sd_nonce) = self.sender_data_key(&message.ciphertext)?;
+        let sender_data = SenderData::from_bytes(&suite.open(
+            &sd_key,
+            &sd_nonce,
+            &message.sender_data_aad(),
+            &message.encrypted_sender_data,
+        )?)?;
+        if sender_data.leaf_index == self.own_leaf {
+            return Err(MlsError::UnexpectedMessage("Message from our own leaf".to_string()));
+        }
+        let sender_leaf = self.tree.leaf(sender_data.leaf_index).ok_or(MlsError::MemberNotFound)?;
+        let signature_key = sender_leaf.signature_key.clone();
+
+        let key = self.secret_tree.key_for(sender_data.leaf_index, sender_data.generation)?;
+        let nonce = apply_reuse_guard(&key.nonce, &sender_data.reuse_guard);
+        let plaintext = suite.open(&key.key, &nonce, &message.content_aad(), &message.ciphertext)?;
+        let (data, signature) = framing::decode_application_content(&plaintext)?;
+
+        let content = FramedContent {
+            group_id: message.group_id.clone(),
+            epoch: message.epoch,
+            sender: sender_data.leaf_index,
+            authenticated_data: message.authenticated_data.clone(),
+            content: Content::Application(data),
+        };
+        framing::verify_content(suite, &signature_key, WIRE_FORMAT_PRIVATE_MESSAGE, &content, &self.context, &signature)?;
+
+        let Content::Application(data) = content.content else { unreachable!() };
+        Ok(ProcessedMessage::Application {
+            sender: sender_data.leaf_index,
+            data,
+        })
+    }
+
+    fn sender_data_key(&self, ciphertext: &[u8]) -> Result<(Secret, Secret)> {
+        let suite = self.suite;
+        let sample = &ciphertext[..ciphertext.len().min(suite.hash_len())];
+        Ok((
+            suite.expand_with_label(&self.secrets.sender_data_secret, "key", sample, suite.aead_key_len())?,
+            suite.expand_with_label(&self.secrets.sender_data_secret, "nonce", sample, suite.aead_nonce_len())?,
+        ))
+    }
+
+    /// Validate and apply proposals in RFC order (updates, removes, adds) to a copy of the tree
+    fn apply_proposals(&self, list: &[(ProposalOrRef, LeafIndex, Proposal)], committer: LeafIndex) -> Result<AppliedProposals> {
+        let mut tree = self.tree.clone();
+        let mut updated = HashSet::new();
+        let mut removed = HashSet::new();
+
+        for (_, sender, proposal) in list {
+            self.validate_proposal(proposal, *sender)?;
+        }
+
+        for (_, sender, proposal) in list {
+            if let Proposal::Update(leaf) = proposal {
+                if *sender == committer || !updated.insert(*sender) {
+                    return Err(MlsError::InvalidProposal("Invalid Update in commit".to_string()));
+                }
+                tree.update_leaf(*sender, leaf.clone());
+            }
+        }
+
+        for (_, _, proposal) in list {
+            if let Proposal::Remove(leaf) = proposal {
+                if *leaf == committer || updated.contains(leaf) || !removed.insert(*leaf) {
+                    return Err(MlsError::InvalidProposal("Invalid Remove in commit".to_string()));
+                }
+                tree.remove_leaf(*leaf);
+            }
+        }
+
+        let mut joiners = Vec::new();
+        for (_, _, proposal) in list {
+            if let Proposal::Add(key_package) = proposal {
+                let identity = &key_package.leaf_node.credential.identity;
+                if tree.find_leaf(|l| &l.credential.identity == identity).is_some() {
+                    return Err(MlsError::MemberExists);
+                }
+                let index = tree.add_leaf(key_package.leaf_node.clone());
+                joiners.push((index, key_package.clone()));
+            }
+        }
+
+        let mut replacements = list.iter().filter_map(|(_, _, proposal)| match proposal {
+            Proposal::GroupContextExtensions(extensions) => Some(extensions),
+            _ => None,
+        });
+        let mut extensions = replacements.next().unwrap_or(&self.context.extensions).clone();
+        if replacements.next().is_some() {
+            return Err(MlsError::InvalidProposal("More than one GroupContextExtensions".to_string()));
+        }
+
+        // Admins who left lose the role; someone has to keep it
+        let mut roles = GroupRoles::from_extensions(&extensions)?;
+        roles.retain_members(|key| tree.find_leaf(|l| l.signature_key == key).is_some());
+        if roles.admin_keys().is_empty() {
+            return Err(MlsError::InvalidProposal("Group would be left without an admin".to_string()));
+        }
+        extensions = roles::replace_extension(&extensions, roles.to_extension());
+
+        Ok(AppliedProposals { tree, joiners, removed, extensions })
+    }
+
+    fn validate_proposal(&self, proposal: &Proposal, sender: LeafIndex) -> Result<()> {
+        // Membership and room changes are admin-only; leaving is always allowed
+        let restricted = match proposal {
+            Proposal::Add(_) | Proposal::GroupContextExtensions(_) => true,
+            Proposal::Remove(leaf) => *leaf != sender,
+            Proposal::Update(_) => false,
+        };
+        if restricted && !self.is_admin(sender) {
+            return Err(MlsError::NotAdmin);
+        }
+
+        match proposal {
+            Proposal::Add(key_package) => key_package.verify(self.suite),
+            Proposal::Update(leaf) => {
+                if !matches!(leaf.source, LeafNodeSource::Update) {
+                    return Err(MlsError::InvalidProposal("Update leaf has wrong source".to_string()));
+                }
+                let current = self.tree.leaf(sender).ok_or(MlsError::MemberNotFound)?;
+                if current.credential != leaf.credential {
+                    return Err(MlsError::InvalidProposal("Update changes the credential".to_string()));
+                }
+                if current.signature_key != leaf.signature_key {
+                    return Err(MlsError::InvalidProposal("Update changes the signature key".to_string()));
+                }
+                leaf.verify(self.suite, &self.context.group_id, sender)
+            }
+            Proposal::Remove(leaf) => {
+                if self.tree.leaf(*leaf).is_none() {
+                    return Err(MlsError::MemberNotFound);
+                }
+                Ok(())
+            }
+            Proposal::GroupContextExtensions(extensions) => {
+                if !extensions.iter().any(|e| e.extension_type == roles::EXTENSION_GROUP_ROLES) {
+                    return Err(MlsError::InvalidProposal("Extensions drop the group roles".to_string()));
+                }
+                GroupRoles::from_extensions(extensions)?;
+                GroupMetadata::from_extensions(extensions)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn roles(&self) -> GroupRoles {
+        GroupRoles::from_extensions(&self.context.extensions).unwrap_or_default()
+    }
+
+    fn install_epoch(&mut self, tree: RatchetTree, context: GroupContext, secrets: EpochSecrets, confirmation_tag: &[u8]) {
+        self.interim_transcript_hash =
+            key_schedule::interim_transcript_hash(self.suite, &context.confirmed_transcript_hash, confirmation_tag);
+        self.secret_tree = SecretTree::new(self.suite, secrets.encryption_secret.clone(), tree.n_leaves());
+        self.tree = tree;
+        self.context = context;
+        self.secrets = secrets;
+        self.pending.clear();
+    }
+
+    fn framed(&self, content: Content) -> FramedContent {
+        FramedContent {
+            group_id: self.context.group_id.clone(),
+            epoch: self.context.epoch,
+            sender: self.own_leaf,
+            authenticated_data: Vec::new(),
+            content,
+        }
+    }
+
+    fn proposal_ref(&self, message: &PublicMessage) -> Vec<u8> {
+        self.suite.ref_hash("MLS 1.0 Proposal Reference", &message.authenticated_content())
+    }
+
+    fn check_epoch(&self, group_id: &[u8], epoch: u64) -> Result<()> {
+        if group_id != self.context.group_id {
+            return Err(MlsError::WrongGroup);
+        }
+        if epoch != self.context.epoch {
+            return Err(MlsError::WrongEpoch {
+                expected: self.context.epoch,
+                got: epoch,
+            });
+        }
         Ok(())
     }
-    
-    pub fn member_count(&self) -> usize {
-        self.members.len()
+
+    fn ensure_active(&self) -> Result<()> {
+        if self.active {
+            Ok(())
+        } else {
+            Err(MlsError::Removed)
+        }
+    }
+}
+
+/// Node key pairs for `nodes`, starting from the path secret of the first one, plus the
+/// commit_secret that follows the last. Receivers pass the committer's `published` keys and
+/// get each derived pair checked against them
+fn derive_path_keys(
+    suite: CipherSuite,
+    first_secret: Secret,
+    nodes: &[NodeIndex],
+    published: Option<&[Vec<u8>]>,
+) -> Result<(Vec<(NodeIndex, HpkeKeyPair)>, Secret)> {
+    let mut secret = first_secret;
+    let mut keys = Vec::with_capacity(nodes.len());
+    for (i, &node) in nodes.iter().enumerate() {
+        let node_secret = suite.derive_secret(&secret, "node")?;
+        let pair = match published {
+            Some(public) => suite.recover_key_pair(&node_secret, &public[i])?,
+            None => suite.derive_key_pair(&node_secret)?,
+        };
+        keys.push((node, pair));
+        secret = suite.derive_secret(&secret, "path")?;
+    }
+    Ok((keys, secret))
+}
+
+/// path_secret[i] for each of `len` path nodes above a leaf
+fn path_secret_chain(suite: CipherSuite, leaf_secret: &[u8], len: usize) -> Result<Vec<Secret>> {
+    let mut secrets: Vec<Secret> = Vec::with_capacity(len);
+    for i in 0..len {
+        let prev: &[u8] = if i == 0 { leaf_secret } else { &secrets[i - 1] };
+        let next = suite.derive_secret(prev, "path")?;
+        secrets.push(next);
     }
-    
-    pub fn members(&self) -> Vec<&Member> {
-        self.members.values().collect()
+    Ok(secrets)
+}
+
+fn apply_reuse_guard(nonce: &[u8], guard: &[u8; 4]) -> Vec<u8> {
+    let mut nonce = nonce.to_vec();
+    for (n, g) in nonce.iter_mut().zip(guard) {
+        *n ^= g;
     }
+    nonce
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    fn bundle(name: &str) -> KeyPackageBundle {
+        KeyPackageBundle::new(Credential::basic(name.as_bytes()), SUITE.generate_signature_key_pair().unwrap()).unwrap()
+    }
+
+    fn create(name: &str) -> Group {
+        Group::create(Credential::basic(name.as_bytes()), SUITE.generate_signature_key_pair().unwrap()).unwrap()
+    }
+
+    /// Add `name` to `group`, returning the joiner's state; every other member processes the commit
+    fn add(group: &mut Group, others: &mut [&mut Group], name: &str) -> Group {
+        let joiner = bundle(name);
+        let output = group.add_member(joiner.key_package.clone()).unwrap();
+        for other in others.iter_mut() {
+            other.process_message(&output.commit).unwrap();
+        }
+        let Some(MlsMessage::Welcome(welcome)) = output.welcome else { panic!("no welcome") };
+        Group::join(&welcome, joiner).unwrap()
+    }
+
+    fn assert_in_sync(groups: &[&Group]) {
+        let first = groups[0];
+        for g in &groups[1..] {
+            assert_eq!(g.epoch(), first.epoch());
+            assert_eq!(g.epoch_authenticator(), first.epoch_authenticator());
+            assert_eq!(g.topic_key().unwrap(), first.topic_key().unwrap());
+        }
+    }
+
     #[test]
     fn test_group_creation() {
-        let group = Group::create(vec![1, 2, 3], vec![4, 5, 6]).unwrap();
+        let group = create("alice");
         assert_eq!(group.epoch(), 0);
         assert_eq!(group.member_count(), 1);
+        assert_eq!(group.members()[0].identity, b"alice");
     }
-    
+
     #[test]
     fn test_add_member() {
-        let mut group = Group::create(vec![1], vec![2]).unwrap();
-        group.add_member(vec![3], vec![4]).unwrap();
-        
-        assert_eq!(group.member_count(), 2);
-        assert_eq!(group.epoch(), 1);
+        let mut alice = create("alice");
+        let bob = add(&mut alice, &mut [], "bob");
+
+        assert_eq!(alice.member_count(), 2);
+        assert_eq!(alice.epoch(), 1);
+        assert_eq!(bob.own_leaf_index(), 1);
+        assert_in_sync(&[&alice, &bob]);
     }
-    
+
     #[test]
     fn test_remove_member() {
-        let mut group = Group::create(vec![1], vec![2]).unwrap();
-        group.add_member(vec![3], vec![4]).unwrap();
-        group.remove_member(&[3]).unwrap();
-        
-        assert_eq!(group.member_count(), 1);
-        assert_eq!(group.epoch(), 2);
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let mut carol = add(&mut alice, &mut [&mut bob], "carol");
+        assert_in_sync(&[&alice, &bob, &carol]);
+
+        let output = alice.remove_member(1).unwrap();
+        assert_eq!(bob.process_message(&output.commit).unwrap(), ProcessedMessage::Removed { sender: 0 });
+        assert!(!bob.is_active());
+        carol.process_message(&output.commit).unwrap();
+
+        assert_eq!(alice.member_count(), 2);
+        assert_eq!(alice.epoch(), 3);
+        assert_in_sync(&[&alice, &carol]);
+        assert!(alice.remove_member(0).is_err());
+        assert!(alice.remove_member(5).is_err());
     }
-    
+
     #[test]
     fn test_rekey() {
-        let mut group = Group::create(vec![1], vec![2]).unwrap();
-        let initial_epoch = group.epoch();
-        
-        group.rekey().unwrap();
-        assert_eq!(group.epoch(), initial_epoch + 1);
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let before = alice.topic_key().unwrap();
+
+        let output = bob.rekey().unwrap();
+        alice.process_message(&output.commit).unwrap();
+
+        assert_eq!(alice.epoch(), 2);
+        assert_ne!(alice.topic_key().unwrap(), before);
+        assert_in_sync(&[&alice, &bob]);
+    }
+
+    #[test]
+    fn test_application_messages() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let mut carol = add(&mut alice, &mut [&mut bob], "carol");
+
+        let msg = bob.encrypt_application_message(b"hello group").unwrap();
+        let bytes = msg.to_bytes();
+        let decoded = MlsMessage::from_bytes(&bytes).unwrap();
+
+        for member in [&mut alice, &mut carol] {
+            assert_eq!(
+                member.process_message(&decoded).unwrap(),
+                ProcessedMessage::Application { sender: 1, data: b"hello group".to_vec() }
+            );
+        }
+
+        // Replays are refused
+        assert!(alice.process_message(&decoded).is_err());
+    }
+
+    #[test]
+    fn test_old_epoch_message_rejected() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+
+        let stale = bob.encrypt_application_message(b"late").unwrap();
+        let output = alice.rekey().unwrap();
+        bob.process_message(&output.commit).unwrap();
+
+        assert!(matches!(alice.process_message(&stale), Err(MlsError::WrongEpoch { .. })));
+    }
+
+    #[test]
+    fn test_update_proposal_committed_by_other() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let mut carol = add(&mut alice, &mut [&mut bob], "carol");
+
+        let proposal = carol.propose_update().unwrap();
+        alice.process_message(&proposal).unwrap();
+        bob.process_message(&proposal).unwrap();
+
+        let output = alice.commit(Vec::new()).unwrap();
+        bob.process_message(&output.commit).unwrap();
+        carol.process_message(&output.commit).unwrap();
+        assert_in_sync(&[&alice, &bob, &carol]);
+
+        // Carol can still decrypt under her new leaf key
+        let msg = alice.encrypt_application_message(b"after update").unwrap();
+        assert!(matches!(carol.process_message(&msg).unwrap(), ProcessedMessage::Application { .. }));
+    }
+
+    #[test]
+    fn test_proposal_by_reference() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let dave = bundle("dave");
+        assert!(matches!(bob.propose_add(dave.key_package.clone()), Err(MlsError::NotAdmin)));
+
+        let output = alice.promote(1).unwrap();
+        bob.process_message(&output.commit).unwrap();
+        let proposal = bob.propose_add(dave.key_package.clone()).unwrap();
+        alice.process_message(&proposal).unwrap();
+
+        let output = alice.commit(Vec::new()).unwrap();
+        bob.process_message(&output.commit).unwrap();
+        let Some(MlsMessage::Welcome(welcome)) = output.welcome else { panic!("no welcome") };
+        let dave = Group::join(&welcome, dave).unwrap();
+
+        assert_eq!(alice.member_count(), 3);
+        assert_in_sync(&[&alice, &bob, &dave]);
+    }
+
+    #[test]
+    fn test_duplicate_member_rejected() {
+        let mut alice = create("alice");
+        let _bob = add(&mut alice, &mut [], "bob");
+        assert!(matches!(alice.add_member(bundle("bob").key_package), Err(MlsError::MemberExists)));
+    }
+
+    #[test]
+    fn test_tampered_commit_rejected() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let _carol = add(&mut alice, &mut [&mut bob], "carol");
+
+        let output = alice.rekey().unwrap();
+        let MlsMessage::Public(mut public) = output.commit else { panic!("commit is public") };
+        public.auth.confirmation_tag = Some(vec![0; 32]);
+        assert!(bob.process_message(&MlsMessage::Public(public)).is_err());
+        assert_eq!(bob.epoch(), 2);
+    }
+
+    #[test]
+    fn test_welcome_for_someone_else() {
+        let mut alice = create("alice");
+        let output = alice.add_member(bundle("bob").key_package).unwrap();
+        let Some(MlsMessage::Welcome(welcome)) = output.welcome else { panic!("no welcome") };
+        assert!(matches!(Group::join(&welcome, bundle("eve")), Err(MlsError::WelcomeNotForUs)));
+    }
+
+    #[test]
+    fn test_larger_group_with_churn() {
+        let mut alice = create("alice");
+        let mut members: Vec<Group> = Vec::new();
+        for i in 0..6 {
+            let name = format!("member{}", i);
+            let mut others: Vec<&mut Group> = members.iter_mut().collect();
+            let joined = add(&mut alice, &mut others, &name);
+            members.push(joined);
+        }
+
+        // Remove two members from the middle, then have someone else commit
+        let output = alice.remove_member(2).unwrap();
+        for m in members.iter_mut() {
+            m.process_message(&output.commit).unwrap();
+        }
+        members.retain(|m| m.is_active());
+
+        let output = members[3].rekey().unwrap();
+        alice.process_message(&output.commit).unwrap();
+        for (i, m) in members.iter_mut().enumerate() {
+            if i != 3 {
+                m.process_message(&output.commit).unwrap();
+            }
+        }
+
+        let mut all: Vec<&Group> = vec![&alice];
+        all.extend(members.iter());
+        assert_in_sync(&all);
+        assert_eq!(alice.member_count(), 6);
+    }
+
+    #[test]
+    fn test_only_admins_change_membership() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let mut carol = add(&mut alice, &mut [&mut bob], "carol");
+        assert!(alice.is_admin(0));
+        assert_eq!(bob.role(1), Some(Role::Member));
+
+        assert!(matches!(bob.add_member(bundle("dave").key_package), Err(MlsError::NotAdmin)));
+        assert!(matches!(bob.remove_member(2), Err(MlsError::NotAdmin)));
+        assert!(matches!(bob.promote(1), Err(MlsError::NotAdmin)));
+        assert!(matches!(bob.set_metadata(GroupMetadata::default()), Err(MlsError::NotAdmin)));
+        assert_eq!(bob.epoch(), 2);
+
+        let output = alice.promote(1).unwrap();
+        bob.process_message(&output.commit).unwrap();
+        carol.process_message(&output.commit).unwrap();
+        assert!(carol.is_admin(1));
+
+        // Bob can now remove, and demote the founder
+        let output = bob.demote(0).unwrap();
+        alice.process_message(&output.commit).unwrap();
+        carol.process_message(&output.commit).unwrap();
+        assert!(!alice.is_admin(0));
+        assert!(matches!(alice.remove_member(2), Err(MlsError::NotAdmin)));
+
+        let output = bob.remove_member(2).unwrap();
+        alice.process_message(&output.commit).unwrap();
+        assert_in_sync(&[&alice, &bob]);
+        assert_eq!(alice.members().iter().filter(|m| m.role == Role::Admin).count(), 1);
+    }
+
+    #[test]
+    fn test_last_admin_stays() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        assert!(alice.demote(0).is_err());
+
+        // Leaving is fine for members, but not for the only admin
+        let leave = bob.propose_remove(1).unwrap();
+        alice.process_message(&leave).unwrap();
+        alice.commit(Vec::new()).unwrap();
+        assert_eq!(alice.member_count(), 1);
+
+        let mut carol = add(&mut alice, &mut [], "carol");
+        let leave = alice.propose_remove(0).unwrap();
+        carol.process_message(&leave).unwrap();
+        assert!(carol.commit(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_metadata_reaches_joiners() {
+        let mut alice = create("alice");
+        let mut bob = add(&mut alice, &mut [], "bob");
+        let metadata = GroupMetadata {
+            name: "umbra-dev".to_string(),
+            topic: "release planning".to_string(),
+        };
+
+        let output = alice.set_metadata(metadata.clone()).unwrap();
+        bob.process_message(&output.commit).unwrap();
+        assert_eq!(bob.metadata(), metadata);
+
+        let carol = add(&mut alice, &mut [&mut bob], "carol");
+        assert_eq!(carol.metadata(), metadata);
+        assert!(carol.is_admin(0));
+        assert!(!carol.is_admin(2));
+        assert_in_sync(&[&alice, &bob, &carol]);
     }
 }
@@ -0,0 +1,186 @@
+// Epoch key schedule, GroupContext and transcript hashes (RFC 9420 §8)
+
+use crate::codec::{decode_opaque, decode_vec, encode_opaque, encode_vec, Decode, Encode, Reader};
+use crate::crypto::{CipherSuite, Secret};
+use crate::error::{MlsError, Result};
+use crate::tree::{Extension, PROTOCOL_VERSION_MLS10};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+/// State every member agrees on for an epoch; bound into every derivation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupContext {
+    pub cipher_suite: CipherSuite,
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub tree_hash: Vec<u8>,
+    pub confirmed_transcript_hash: Vec<u8>,
+    pub extensions: Vec<Extension>,
+}
+
+impl Encode for GroupContext {
+    fn encode(&self, out: &mut Vec<u8>) {
+        PROTOCOL_VERSION_MLS10.encode(out);
+        self.cipher_suite.encode(out);
+        encode_opaque(&self.group_id, out);
+        self.epoch.encode(out);
+        encode_opaque(&self.tree_hash, out);
+        encode_opaque(&self.confirmed_transcript_hash, out);
+        encode_vec(&self.extensions, out);
+    }
+}
+
+impl Decode for GroupContext {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let version = u16::decode(reader)?;
+        if version != PROTOCOL_VERSION_MLS10 {
+            return Err(MlsError::Decode(format!("Unsupported protocol version {}", version)));
+        }
+        Ok(Self {
+            cipher_suite: CipherSuite::decode(reader)?,
+            group_id: decode_opaque(reader)?,
+            epoch: u64::decode(reader)?,
+            tree_hash: decode_opaque(reader)?,
+            confirmed_transcript_hash: decode_opaque(reader)?,
+            extensions: decode_vec(reader)?,
+        })
+    }
+}
+
+/// Secrets derived for one epoch
+pub struct EpochSecrets {
+    pub joiner_secret: Secret,
+    pub welcome_secret: Secret,
+    pub sender_data_secret: Secret,
+    pub encryption_secret: Secret,
+    pub exporter_secret: Secret,
+    pub external_secret: Secret,
+    pub confirmation_key: Secret,
+    pub membership_key: Secret,
+    pub resumption_psk: Secret,
+    pub epoch_authenticator: Secret,
+    pub init_secret: Secret,
+}
+
+impl EpochSecrets {
+    /// Run the schedule from the previous epoch's init_secret and this commit's commit_secret
+    pub fn derive(suite: CipherSuite, init_secret: &[u8], commit_secret: &[u8], context: &GroupContext) -> Result<Self> {
+        let prk = suite.extract(init_secret, commit_secret);
+        let joiner_secret = suite.expand_with_label(&prk, "joiner", &context.to_bytes(), suite.hash_len())?;
+        Self::from_joiner(suite, joiner_secret, context)
+    }
+
+    /// Joiners start here, with the joiner_secret from their GroupSecrets
+    pub fn from_joiner(suite: CipherSuite, joiner_secret: Secret, context: &GroupContext) -> Result<Self> {
+        // No PSKs are used, so psk_secret is all zeroes
+        let member_secret = suite.extract(&joiner_secret, &vec![0u8; suite.hash_len()]);
+        let welcome_secret = suite.derive_secret(&member_secret, "welcome")?;
+        let epoch_secret = suite.expand_with_label(&member_secret, "epoch", &context.to_bytes(), suite.hash_len())?;
+        let derive = |label: &str| suite.derive_secret(&epoch_secret, label);
+
+        Ok(Self {
+            joiner_secret,
+            welcome_secret,
+            sender_data_secret: derive("sender data")?,
+            encryption_secret: derive("encryption")?,
+            exporter_secret: derive("exporter")?,
+            external_secret: derive("external")?,
+            confirmation_key: derive("confirm")?,
+            membership_key: derive("membership")?,
+            resumption_psk: derive("resumption")?,
+            epoch_authenticator: derive("authentication")?,
+            init_secret: derive("init")?,
+        })
+    }
+
+    /// MLS-Exporter(Label, Context, Length)
+    pub fn export(&self, suite: CipherSuite, label: &str, context: &[u8], len: usize) -> Result<Secret> {
+        let secret = suite.derive_secret(&self.exporter_secret, label)?;
+        suite.expand_with_label(&secret, "exported", &suite.hash(context), len)
+    }
+}
+
+/// Key and nonce protecting the GroupInfo inside a Welcome
+pub fn welcome_key_nonce(suite: CipherSuite, welcome_secret: &[u8]) -> Result<(Secret, Secret)> {
+    Ok((
+        suite.expand_with_label(welcome_secret, "key", &[], suite.aead_key_len())?,
+        suite.expand_with_label(welcome_secret, "nonce", &[], suite.aead_nonce_len())?,
+    ))
+}
+
+/// Welcome secret straight from a joiner_secret (the joiner needs it before it has a context)
+pub fn welcome_secret_from_joiner(suite: CipherSuite, joiner_secret: &[u8]) -> Result<Secret> {
+    let member_secret = suite.extract(joiner_secret, &vec![0u8; suite.hash_len()]);
+    suite.derive_secret(&member_secret, "welcome")
+}
+
+/// confirmed_transcript_hash = Hash(interim_transcript_hash || ConfirmedTranscriptHashInput)
+pub fn confirmed_transcript_hash(suite: CipherSuite, interim: &[u8], confirmed_input: &[u8]) -> Vec<u8> {
+    suite.hash(&[interim, confirmed_input].concat())
+}
+
+/// interim_transcript_hash = Hash(confirmed_transcript_hash || InterimTranscriptHashInput)
+pub fn interim_transcript_hash(suite: CipherSuite, confirmed: &[u8], confirmation_tag: &[u8]) -> Vec<u8> {
+    let mut input = confirmed.to_vec();
+    encode_opaque(confirmation_tag, &mut input);
+    suite.hash(&input)
+}
+
+pub fn random_secret(suite: CipherSuite) -> Secret {
+    let mut secret = Zeroizing::new(vec![0u8; suite.hash_len()]);
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    fn context(epoch: u64) -> GroupContext {
+        GroupContext {
+            cipher_suite: SUITE,
+            group_id: b"group".to_vec(),
+            epoch,
+            tree_hash: vec![1; 32],
+            confirmed_transcript_hash: vec![2; 32],
+            extensions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_joiner_matches_member() {
+        let ctx = context(1);
+        let member = EpochSecrets::derive(SUITE, &[3; 32], &[4; 32], &ctx).unwrap();
+        let joiner = EpochSecrets::from_joiner(SUITE, member.joiner_secret.clone(), &ctx).unwrap();
+
+        assert_eq!(member.epoch_authenticator, joiner.epoch_authenticator);
+        assert_eq!(member.init_secret, joiner.init_secret);
+        assert_eq!(member.welcome_secret, welcome_secret_from_joiner(SUITE, &member.joiner_secret).unwrap());
+    }
+
+    #[test]
+    fn test_context_binds_epoch() {
+        let a = EpochSecrets::derive(SUITE, &[3; 32], &[4; 32], &context(1)).unwrap();
+        let b = EpochSecrets::derive(SUITE, &[3; 32], &[4; 32], &context(2)).unwrap();
+        assert_ne!(a.encryption_secret, b.encryption_secret);
+    }
+
+    #[test]
+    fn test_exporter_labels_distinct() {
+        let secrets = EpochSecrets::derive(SUITE, &[3; 32], &[4; 32], &context(1)).unwrap();
+        let a = secrets.export(SUITE, "umbra topic", b"room", 32).unwrap();
+        let b = secrets.export(SUITE, "umbra topic", b"other room", 32).unwrap();
+        let c = secrets.export(SUITE, "something else", b"room", 32).unwrap();
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 32);
+    }
+
+    #[test]
+    fn test_group_context_roundtrip() {
+        let ctx = context(7);
+        assert_eq!(GroupContext::from_bytes(&ctx.to_bytes()).unwrap(), ctx);
+    }
+}
@@ -1,16 +1,22 @@
-/// MLS group state (stub for W7-W9)
-pub struct GroupState {
-    pub epoch: u64,
-}
+// MLS (RFC 9420) group key agreement for Umbra group chat
+// TreeKEM ratchet tree, proposals/commits, Welcome and the epoch key schedule
 
-impl Default for GroupState {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+pub mod codec;
+pub mod crypto;
+pub mod error;
+pub mod framing;
+pub mod group;
+pub mod key_schedule;
+pub mod messages;
+pub mod roles;
+pub mod secret_tree;
+pub mod tree;
+pub mod tree_math;
 
-impl GroupState {
-    pub fn new() -> Self {
-        Self { epoch: 0 }
-    }
-}
+pub use crypto::{CipherSuite, SignatureKeyPair};
+pub use error::{MlsError, Result};
+pub use framing::MlsMessage;
+pub use group::{CommitOutput, Group, GroupId, Member, ProcessedMessage};
+pub use messages::{KeyPackage, KeyPackageBundle, Proposal, Welcome};
+pub use roles::{GroupMetadata, Role};
+pub use tree::Credential;
@@ -0,0 +1,468 @@
+// KeyPackages, proposals, commits and Welcome messages (RFC 9420 §10-12)
+
+use crate::codec::{
+    decode_opaque, decode_optional, decode_vec, encode_opaque, encode_optional, encode_vec, Decode, Encode, Reader,
+};
+use crate::crypto::{CipherSuite, HpkeCiphertext, Secret, SignatureKeyPair};
+use crate::error::{MlsError, Result};
+use crate::key_schedule::GroupContext;
+use crate::tree::{Credential, Extension, LeafNode, LeafNodeSource, Lifetime, PROTOCOL_VERSION_MLS10};
+use crate::tree_math::LeafIndex;
+use zeroize::Zeroizing;
+
+/// KeyPackages we hand out are good for 90 days
+pub const KEY_PACKAGE_LIFETIME_SECS: u64 = 90 * 24 * 3600;
+
+/// A signed, single-use offer to be added to a group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPackage {
+    pub cipher_suite: CipherSuite,
+    pub init_key: Vec<u8>,
+    pub leaf_node: LeafNode,
+    pub extensions: Vec<Extension>,
+    pub signature: Vec<u8>,
+}
+
+impl KeyPackage {
+    fn tbs(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_content(&mut out);
+        out
+    }
+
+    fn encode_content(&self, out: &mut Vec<u8>) {
+        PROTOCOL_VERSION_MLS10.encode(out);
+        self.cipher_suite.encode(out);
+        encode_opaque(&self.init_key, out);
+        self.leaf_node.encode(out);
+        encode_vec(&self.extensions, out);
+    }
+
+    /// Check both signatures and the lifetime
+    pub fn verify(&self, suite: CipherSuite) -> Result<()> {
+        if self.cipher_suite != suite {
+            return Err(MlsError::InvalidProposal("KeyPackage ciphersuite mismatch".to_string()));
+        }
+        match self.leaf_node.source {
+            LeafNodeSource::KeyPackage(lifetime) if lifetime.is_current() => {}
+            LeafNodeSource::KeyPackage(_) => {
+                return Err(MlsError::InvalidProposal("KeyPackage expired".to_string()))
+            }
+            _ => return Err(MlsError::InvalidProposal("KeyPackage leaf has wrong source".to_string())),
+        }
+        if self.init_key == self.leaf_node.encryption_key {
+            return Err(MlsError::InvalidProposal("init_key reused as encryption_key".to_string()));
+        }
+        self.leaf_node.verify(suite, &[], 0)?;
+        suite.verify_with_label(&self.leaf_node.signature_key, "KeyPackageTBS", &self.tbs(), &self.signature)
+    }
+
+    /// KeyPackageRef: how Welcome messages address this package
+    pub fn reference(&self) -> Vec<u8> {
+        self.cipher_suite.ref_hash("MLS 1.0 KeyPackage Reference", &self.to_bytes())
+    }
+}
+
+impl Encode for KeyPackage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_content(out);
+        encode_opaque(&self.signature, out);
+    }
+}
+
+impl Decode for KeyPackage {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let version = u16::decode(reader)?;
+        if version != PROTOCOL_VERSION_MLS10 {
+            return Err(MlsError::Decode(format!("Unsupported protocol version {}", version)));
+        }
+        Ok(Self {
+            cipher_suite: CipherSuite::decode(reader)?,
+            init_key: decode_opaque(reader)?,
+            leaf_node: LeafNode::decode(reader)?,
+            extensions: decode_vec(reader)?,
+            signature: decode_opaque(reader)?,
+        })
+    }
+}
+
+/// A KeyPackage together with the private keys needed to join with it
+pub struct KeyPackageBundle {
+    pub key_package: KeyPackage,
+    pub(crate) init_private: Secret,
+    pub(crate) encryption_private: Secret,
+    pub(crate) signer: SignatureKeyPair,
+}
+
+impl KeyPackageBundle {
+    pub fn new(credential: Credential, signer: SignatureKeyPair) -> Result<Self> {
+        let suite = signer.cipher_suite();
+        let init = suite.generate_key_pair()?;
+        let encryption = suite.generate_key_pair()?;
+        let leaf_node = LeafNode::new(
+            suite,
+            encryption.public,
+            credential,
+            LeafNodeSource::KeyPackage(Lifetime::from_now(KEY_PACKAGE_LIFETIME_SECS)),
+            &signer,
+            &[],
+            0,
+        )?;
+
+        let mut key_package = KeyPackage {
+            cipher_suite: suite,
+            init_key: init.public,
+            leaf_node,
+            extensions: Vec::new(),
+            signature: Vec::new(),
+        };
+        key_package.signature = signer.sign_with_label("KeyPackageTBS", &key_package.tbs())?;
+
+        Ok(Self {
+            key_package,
+            init_private: init.private,
+            encryption_private: encryption.private,
+            signer,
+        })
+    }
+
+    /// SignWithLabel under the KeyPackage's leaf signature key, e.g. for a join request
+    pub fn sign_with_label(&self, label: &str, content: &[u8]) -> Result<Vec<u8>> {
+        self.signer.sign_with_label(label, content)
+    }
+}
+
+pub const PROPOSAL_ADD: u16 = 1;
+pub const PROPOSAL_UPDATE: u16 = 2;
+pub const PROPOSAL_REMOVE: u16 = 3;
+pub const PROPOSAL_GROUP_CONTEXT_EXTENSIONS: u16 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proposal {
+    Add(KeyPackage),
+    Update(LeafNode),
+    Remove(LeafIndex),
+    /// Replace the GroupContext extensions (roles, metadata) from the next epoch on
+    GroupContextExtensions(Vec<Extension>),
+}
+
+impl Proposal {
+    pub fn proposal_type(&self) -> u16 {
+        match self {
+            Self::Add(_) => PROPOSAL_ADD,
+            Self::Update(_) => PROPOSAL_UPDATE,
+            Self::Remove(_) => PROPOSAL_REMOVE,
+            Self::GroupContextExtensions(_) => PROPOSAL_GROUP_CONTEXT_EXTENSIONS,
+        }
+    }
+}
+
+impl Encode for Proposal {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.proposal_type().encode(out);
+        match self {
+            Self::Add(key_package) => key_package.encode(out),
+            Self::Update(leaf_node) => leaf_node.encode(out),
+            Self::Remove(removed) => removed.encode(out),
+            Self::GroupContextExtensions(extensions) => encode_vec(extensions, out),
+        }
+    }
+}
+
+impl Decode for Proposal {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        match u16::decode(reader)? {
+            PROPOSAL_ADD => Ok(Self::Add(KeyPackage::decode(reader)?)),
+            PROPOSAL_UPDATE => Ok(Self::Update(LeafNode::decode(reader)?)),
+            PROPOSAL_REMOVE => Ok(Self::Remove(u32::decode(reader)?)),
+            PROPOSAL_GROUP_CONTEXT_EXTENSIONS => Ok(Self::GroupContextExtensions(decode_vec(reader)?)),
+            other => Err(MlsError::Decode(format!("Unsupported proposal type {}", other))),
+        }
+    }
+}
+
+/// A commit carries proposals inline or by reference to ones sent earlier in the epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProposalOrRef {
+    Proposal(Box<Proposal>),
+    Reference(Vec<u8>),
+}
+
+impl Encode for ProposalOrRef {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Proposal(proposal) => {
+                1u8.encode(out);
+                proposal.encode(out);
+            }
+            Self::Reference(reference) => {
+                2u8.encode(out);
+                encode_opaque(reference, out);
+            }
+        }
+    }
+}
+
+impl Decode for ProposalOrRef {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        match u8::decode(reader)? {
+            1 => Ok(Self::Proposal(Box::new(Proposal::decode(reader)?))),
+            2 => Ok(Self::Reference(decode_opaque(reader)?)),
+            other => Err(MlsError::Decode(format!("Invalid ProposalOrRef type {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdatePathNode {
+    pub encryption_key: Vec<u8>,
+    pub encrypted_path_secret: Vec<HpkeCiphertext>,
+}
+
+impl Encode for UpdatePathNode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.encryption_key, out);
+        encode_vec(&self.encrypted_path_secret, out);
+    }
+}
+
+impl Decode for UpdatePathNode {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            encryption_key: decode_opaque(reader)?,
+            encrypted_path_secret: decode_vec(reader)?,
+        })
+    }
+}
+
+/// New keys for the committer's leaf and filtered direct path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdatePath {
+    pub leaf_node: LeafNode,
+    pub nodes: Vec<UpdatePathNode>,
+}
+
+impl Encode for UpdatePath {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.leaf_node.encode(out);
+        encode_vec(&self.nodes, out);
+    }
+}
+
+impl Decode for UpdatePath {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            leaf_node: LeafNode::decode(reader)?,
+            nodes: decode_vec(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub proposals: Vec<ProposalOrRef>,
+    pub path: Option<UpdatePath>,
+}
+
+impl Encode for Commit {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_vec(&self.proposals, out);
+        encode_optional(&self.path, out);
+    }
+}
+
+impl Decode for Commit {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            proposals: decode_vec(reader)?,
+            path: decode_optional(reader)?,
+        })
+    }
+}
+
+/// Signed description of the new epoch, encrypted inside a Welcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub group_context: GroupContext,
+    pub extensions: Vec<Extension>,
+    pub confirmation_tag: Vec<u8>,
+    pub signer: LeafIndex,
+    pub signature: Vec<u8>,
+}
+
+impl GroupInfo {
+    pub fn tbs(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_content(&mut out);
+        out
+    }
+
+    fn encode_content(&self, out: &mut Vec<u8>) {
+        self.group_context.encode(out);
+        encode_vec(&self.extensions, out);
+        encode_opaque(&self.confirmation_tag, out);
+        self.signer.encode(out);
+    }
+
+    pub fn extension(&self, extension_type: u16) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|e| e.extension_type == extension_type)
+            .map(|e| e.data.as_slice())
+    }
+}
+
+impl Encode for GroupInfo {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_content(out);
+        encode_opaque(&self.signature, out);
+    }
+}
+
+impl Decode for GroupInfo {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            group_context: GroupContext::decode(reader)?,
+            extensions: decode_vec(reader)?,
+            confirmation_tag: decode_opaque(reader)?,
+            signer: u32::decode(reader)?,
+            signature: decode_opaque(reader)?,
+        })
+    }
+}
+
+/// Per-joiner secrets, HPKE-encrypted to the KeyPackage init_key
+pub struct GroupSecrets {
+    pub joiner_secret: Secret,
+    pub path_secret: Option<Secret>,
+}
+
+impl Encode for GroupSecrets {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.joiner_secret, out);
+        match &self.path_secret {
+            Some(secret) => {
+                out.push(1);
+                encode_opaque(secret, out);
+            }
+            None => out.push(0),
+        }
+        // No PSKs
+        encode_opaque(&[], out);
+    }
+}
+
+impl Decode for GroupSecrets {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let joiner_secret = Zeroizing::new(decode_opaque(reader)?);
+        let path_secret = match u8::decode(reader)? {
+            0 => None,
+            1 => Some(Zeroizing::new(decode_opaque(reader)?)),
+            other => return Err(MlsError::Decode(format!("Invalid optional marker {}", other))),
+        };
+        if !decode_opaque(reader)?.is_empty() {
+            return Err(MlsError::Decode("PSKs are not supported".to_string()));
+        }
+        Ok(Self { joiner_secret, path_secret })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedGroupSecrets {
+    pub new_member: Vec<u8>,
+    pub encrypted_group_secrets: HpkeCiphertext,
+}
+
+impl Encode for EncryptedGroupSecrets {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.new_member, out);
+        self.encrypted_group_secrets.encode(out);
+    }
+}
+
+impl Decode for EncryptedGroupSecrets {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            new_member: decode_opaque(reader)?,
+            encrypted_group_secrets: HpkeCiphertext::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Welcome {
+    pub cipher_suite: CipherSuite,
+    pub secrets: Vec<EncryptedGroupSecrets>,
+    pub encrypted_group_info: Vec<u8>,
+}
+
+impl Encode for Welcome {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.cipher_suite.encode(out);
+        encode_vec(&self.secrets, out);
+        encode_opaque(&self.encrypted_group_info, out);
+    }
+}
+
+impl Decode for Welcome {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            cipher_suite: CipherSuite::decode(reader)?,
+            secrets: decode_vec(reader)?,
+            encrypted_group_info: decode_opaque(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    fn bundle(name: &str) -> KeyPackageBundle {
+        KeyPackageBundle::new(Credential::basic(name.as_bytes()), SUITE.generate_signature_key_pair().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_key_package_roundtrip_and_verify() {
+        let kp = bundle("alice").key_package;
+        let decoded = KeyPackage::from_bytes(&kp.to_bytes()).unwrap();
+        assert_eq!(decoded, kp);
+        decoded.verify(SUITE).unwrap();
+        assert_eq!(decoded.reference(), kp.reference());
+    }
+
+    #[test]
+    fn test_tampered_key_package_rejected() {
+        let mut kp = bundle("alice").key_package;
+        kp.init_key = SUITE.generate_key_pair().unwrap().public;
+        assert!(kp.verify(SUITE).is_err());
+
+        let mut kp = bundle("alice").key_package;
+        kp.leaf_node.credential = Credential::basic(b"mallory".to_vec());
+        assert!(kp.verify(SUITE).is_err());
+    }
+
+    #[test]
+    fn test_proposal_roundtrip() {
+        let proposals = vec![
+            ProposalOrRef::Proposal(Box::new(Proposal::Add(bundle("bob").key_package))),
+            ProposalOrRef::Proposal(Box::new(Proposal::Remove(3))),
+            ProposalOrRef::Reference(vec![9; 32]),
+        ];
+        let commit = Commit { proposals, path: None };
+        assert_eq!(Commit::from_bytes(&commit.to_bytes()).unwrap(), commit);
+    }
+
+    #[test]
+    fn test_group_secrets_roundtrip() {
+        let secrets = GroupSecrets {
+            joiner_secret: Zeroizing::new(vec![1; 32]),
+            path_secret: Some(Zeroizing::new(vec![2; 32])),
+        };
+        let decoded = GroupSecrets::from_bytes(&secrets.to_bytes()).unwrap();
+        assert_eq!(decoded.joiner_secret, secrets.joiner_secret);
+        assert_eq!(decoded.path_secret, secrets.path_secret);
+    }
+}
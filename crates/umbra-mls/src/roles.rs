@@ -0,0 +1,187 @@
+// Room roles and metadata
+// Both live in the GroupContext as extensions, so every change goes through a signed,
+// confirmed commit and joiners receive the current state in the Welcome's GroupInfo
+
+use crate::codec::{decode_opaque, encode_opaque, Decode, Encode, Reader};
+use crate::error::{MlsError, Result};
+use crate::tree::Extension;
+
+/// GroupContext extension listing admin signature keys (private-use range)
+pub const EXTENSION_GROUP_ROLES: u16 = 0xF000;
+
+/// GroupContext extension carrying the room name and topic (private-use range)
+pub const EXTENSION_GROUP_METADATA: u16 = 0xF001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Member,
+    Admin,
+}
+
+/// Admins by leaf signature key, which stays put when leaf indices are reused
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupRoles {
+    admins: Vec<Vec<u8>>,
+}
+
+impl GroupRoles {
+    pub fn with_admin(signature_key: &[u8]) -> Self {
+        Self {
+            admins: vec![signature_key.to_vec()],
+        }
+    }
+
+    pub fn role(&self, signature_key: &[u8]) -> Role {
+        if self.admins.iter().any(|k| k == signature_key) {
+            Role::Admin
+        } else {
+            Role::Member
+        }
+    }
+
+    pub fn set_role(&mut self, signature_key: &[u8], role: Role) {
+        self.admins.retain(|k| k != signature_key);
+        if role == Role::Admin {
+            self.admins.push(signature_key.to_vec());
+        }
+    }
+
+    /// Drop admins whose key no longer belongs to a member
+    pub fn retain_members(&mut self, mut is_member: impl FnMut(&[u8]) -> bool) {
+        self.admins.retain(|k| is_member(k));
+    }
+
+    pub fn admin_keys(&self) -> &[Vec<u8>] {
+        &self.admins
+    }
+
+    pub fn from_extensions(extensions: &[Extension]) -> Result<Self> {
+        match find_extension(extensions, EXTENSION_GROUP_ROLES) {
+            Some(data) => Self::from_bytes(data),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn to_extension(&self) -> Extension {
+        Extension {
+            extension_type: EXTENSION_GROUP_ROLES,
+            data: self.to_bytes(),
+        }
+    }
+}
+
+impl Encode for GroupRoles {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut keys = Vec::new();
+        for key in &self.admins {
+            encode_opaque(key, &mut keys);
+        }
+        encode_opaque(&keys, out);
+    }
+}
+
+impl Decode for GroupRoles {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let keys = decode_opaque(reader)?;
+        let mut inner = Reader::new(&keys);
+        let mut admins = Vec::new();
+        while !inner.is_empty() {
+            admins.push(decode_opaque(&mut inner)?);
+        }
+        Ok(Self { admins })
+    }
+}
+
+/// Human-facing room details
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupMetadata {
+    pub name: String,
+    pub topic: String,
+}
+
+impl GroupMetadata {
+    pub fn from_extensions(extensions: &[Extension]) -> Result<Self> {
+        match find_extension(extensions, EXTENSION_GROUP_METADATA) {
+            Some(data) => Self::from_bytes(data),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn to_extension(&self) -> Extension {
+        Extension {
+            extension_type: EXTENSION_GROUP_METADATA,
+            data: self.to_bytes(),
+        }
+    }
+}
+
+impl Encode for GroupMetadata {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(self.name.as_bytes(), out);
+        encode_opaque(self.topic.as_bytes(), out);
+    }
+}
+
+impl Decode for GroupMetadata {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| MlsError::Decode("Metadata is not UTF-8".to_string()));
+        Ok(Self {
+            name: text(decode_opaque(reader)?)?,
+            topic: text(decode_opaque(reader)?)?,
+        })
+    }
+}
+
+fn find_extension(extensions: &[Extension], extension_type: u16) -> Option<&[u8]> {
+    extensions
+        .iter()
+        .find(|e| e.extension_type == extension_type)
+        .map(|e| e.data.as_slice())
+}
+
+/// `extensions` with `extension` added, replacing any of the same type
+pub fn replace_extension(extensions: &[Extension], extension: Extension) -> Vec<Extension> {
+    let mut out: Vec<Extension> = extensions
+        .iter()
+        .filter(|e| e.extension_type != extension.extension_type)
+        .cloned()
+        .collect();
+    out.push(extension);
+    out.sort_by_key(|e| e.extension_type);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_roundtrip() {
+        let mut roles = GroupRoles::with_admin(b"alice");
+        roles.set_role(b"bob", Role::Admin);
+        assert_eq!(GroupRoles::from_bytes(&roles.to_bytes()).unwrap(), roles);
+
+        roles.set_role(b"alice", Role::Member);
+        assert_eq!(roles.role(b"alice"), Role::Member);
+        assert_eq!(roles.role(b"bob"), Role::Admin);
+
+        roles.retain_members(|k| k != b"bob");
+        assert!(roles.admin_keys().is_empty());
+    }
+
+    #[test]
+    fn test_metadata_extension() {
+        let metadata = GroupMetadata {
+            name: "umbra".to_string(),
+            topic: "post-quantum chat".to_string(),
+        };
+        let extensions = replace_extension(&[GroupRoles::with_admin(b"a").to_extension()], metadata.to_extension());
+        assert_eq!(GroupMetadata::from_extensions(&extensions).unwrap(), metadata);
+        assert_eq!(GroupRoles::from_extensions(&extensions).unwrap().role(b"a"), Role::Admin);
+
+        let renamed = GroupMetadata { name: "renamed".to_string(), ..metadata };
+        let extensions = replace_extension(&extensions, renamed.to_extension());
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(GroupMetadata::from_extensions(&extensions).unwrap().name, "renamed");
+    }
+}
@@ -0,0 +1,201 @@
+// Secret tree: per-member application ratchets derived from the epoch's encryption_secret
+// (RFC 9420 §9). Consumed node secrets are deleted as the tree is walked.
+
+use crate::crypto::{CipherSuite, Secret};
+use crate::error::{MlsError, Result};
+use crate::tree_math::{self, LeafIndex, NodeIndex};
+use std::collections::HashMap;
+
+/// How far ahead of the next expected generation a message may be
+pub const MAX_FORWARD_DISTANCE: u32 = 1000;
+
+/// Cap on stored out-of-order keys per sender
+pub const MAX_SKIPPED_KEYS: usize = 1000;
+
+/// Key and nonce for one message
+pub struct MessageKey {
+    pub generation: u32,
+    pub key: Secret,
+    pub nonce: Secret,
+}
+
+struct Ratchet {
+    next_generation: u32,
+    secret: Secret,
+    skipped: HashMap<u32, (Secret, Secret)>,
+}
+
+impl Ratchet {
+    fn key_nonce(&self, suite: CipherSuite) -> Result<(Secret, Secret)> {
+        let ctx = self.next_generation.to_be_bytes();
+        Ok((
+            suite.expand_with_label(&self.secret, "key", &ctx, suite.aead_key_len())?,
+            suite.expand_with_label(&self.secret, "nonce", &ctx, suite.aead_nonce_len())?,
+        ))
+    }
+
+    fn advance(&mut self, suite: CipherSuite) -> Result<()> {
+        let ctx = self.next_generation.to_be_bytes();
+        self.secret = suite.expand_with_label(&self.secret, "secret", &ctx, suite.hash_len())?;
+        self.next_generation += 1;
+        Ok(())
+    }
+}
+
+pub struct SecretTree {
+    suite: CipherSuite,
+    n_leaves: u32,
+    nodes: HashMap<NodeIndex, Secret>,
+    ratchets: HashMap<LeafIndex, Ratchet>,
+}
+
+impl SecretTree {
+    pub fn new(suite: CipherSuite, encryption_secret: Secret, n_leaves: u32) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(tree_math::root(n_leaves), encryption_secret);
+        Self {
+            suite,
+            n_leaves,
+            nodes,
+            ratchets: HashMap::new(),
+        }
+    }
+
+    /// Key for the next message we send as `leaf`
+    pub fn next_key(&mut self, leaf: LeafIndex) -> Result<MessageKey> {
+        let suite = self.suite;
+        let ratchet = self.ratchet(leaf)?;
+        let (key, nonce) = ratchet.key_nonce(suite)?;
+        let generation = ratchet.next_generation;
+        ratchet.advance(suite)?;
+        Ok(MessageKey { generation, key, nonce })
+    }
+
+    /// Key for a received message, tolerating reordering within the skip window
+    pub fn key_for(&mut self, leaf: LeafIndex, generation: u32) -> Result<MessageKey> {
+        let suite = self.suite;
+        let ratchet = self.ratchet(leaf)?;
+
+        if generation < ratchet.next_generation {
+            let (key, nonce) = ratchet
+                .skipped
+                .remove(&generation)
+                .ok_or(MlsError::InvalidGeneration(generation))?;
+            return Ok(MessageKey { generation, key, nonce });
+        }
+
+        if generation - ratchet.next_generation > MAX_FORWARD_DISTANCE {
+            return Err(MlsError::InvalidGeneration(generation));
+        }
+
+        while ratchet.next_generation < generation {
+            let keys = ratchet.key_nonce(suite)?;
+            if ratchet.skipped.len() >= MAX_SKIPPED_KEYS {
+                let oldest = *ratchet.skipped.keys().min().expect("non-empty");
+                ratchet.skipped.remove(&oldest);
+            }
+            ratchet.skipped.insert(ratchet.next_generation, keys);
+            ratchet.advance(suite)?;
+        }
+
+        let (key, nonce) = ratchet.key_nonce(suite)?;
+        ratchet.advance(suite)?;
+        Ok(MessageKey { generation, key, nonce })
+    }
+
+    fn ratchet(&mut self, leaf: LeafIndex) -> Result<&mut Ratchet> {
+        if leaf >= self.n_leaves {
+            return Err(MlsError::MemberNotFound);
+        }
+        if !self.ratchets.contains_key(&leaf) {
+            let leaf_secret = self.leaf_secret(leaf)?;
+            let secret = self.suite.expand_with_label(&leaf_secret, "application", &[], self.suite.hash_len())?;
+            self.ratchets.insert(
+                leaf,
+                Ratchet {
+                    next_generation: 0,
+                    secret,
+                    skipped: HashMap::new(),
+                },
+            );
+        }
+        Ok(self.ratchets.get_mut(&leaf).expect("inserted above"))
+    }
+
+    /// Walk down from the nearest stored ancestor, keeping siblings and deleting what was used
+    fn leaf_secret(&mut self, leaf: LeafIndex) -> Result<Secret> {
+        let target = tree_math::leaf_to_node(leaf);
+        let mut path = vec![target];
+        path.extend(tree_math::direct_path(target, self.n_leaves));
+
+        let start = path
+            .iter()
+            .position(|n| self.nodes.contains_key(n))
+            .ok_or_else(|| MlsError::Crypto("Secret tree leaf already consumed".to_string()))?;
+
+        for i in (1..=start).rev() {
+            let node = path[i];
+            let secret = self.nodes.remove(&node).expect("present");
+            let left = tree_math::left(node);
+            let right = tree_math::right(node);
+            self.nodes.insert(left, self.suite.expand_with_label(&secret, "tree", b"left", self.suite.hash_len())?);
+            self.nodes.insert(right, self.suite.expand_with_label(&secret, "tree", b"right", self.suite.hash_len())?);
+        }
+
+        Ok(self.nodes.remove(&target).expect("derived above"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zeroize::Zeroizing;
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    fn tree() -> SecretTree {
+        SecretTree::new(SUITE, Zeroizing::new(vec![7; 32]), 4)
+    }
+
+    #[test]
+    fn test_sender_and_receiver_agree() {
+        let mut sender = tree();
+        let mut receiver = tree();
+
+        for generation in 0..3 {
+            let sent = sender.next_key(2).unwrap();
+            let received = receiver.key_for(2, generation).unwrap();
+            assert_eq!(sent.generation, generation);
+            assert_eq!(sent.key, received.key);
+            assert_eq!(sent.nonce, received.nonce);
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_and_replay() {
+        let mut sender = tree();
+        let mut receiver = tree();
+        let keys: Vec<MessageKey> = (0..3).map(|_| sender.next_key(1).unwrap()).collect();
+
+        assert_eq!(receiver.key_for(1, 2).unwrap().key, keys[2].key);
+        assert_eq!(receiver.key_for(1, 0).unwrap().key, keys[0].key);
+        // Each key is handed out once
+        assert!(receiver.key_for(1, 0).is_err());
+        assert_eq!(receiver.key_for(1, 1).unwrap().key, keys[1].key);
+    }
+
+    #[test]
+    fn test_members_have_distinct_ratchets() {
+        let mut tree = tree();
+        let a = tree.next_key(0).unwrap();
+        let b = tree.next_key(1).unwrap();
+        assert_ne!(a.key, b.key);
+        assert!(tree.next_key(4).is_err());
+    }
+
+    #[test]
+    fn test_forward_distance_bounded() {
+        let mut tree = tree();
+        assert!(tree.key_for(0, MAX_FORWARD_DISTANCE + 1).is_err());
+    }
+}
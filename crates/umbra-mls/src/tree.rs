@@ -0,0 +1,735 @@
+// Ratchet tree: leaf/parent nodes, resolutions, tree hashes and parent hashes (RFC 9420 §7)
+
+use crate::codec::{
+    decode_opaque, decode_optional, decode_vec, encode_opaque, encode_optional, encode_vec, Decode, Encode, Reader,
+};
+use crate::crypto::{CipherSuite, SignatureKeyPair};
+use crate::error::{MlsError, Result};
+use crate::tree_math::{self, LeafIndex, NodeIndex};
+use std::collections::HashSet;
+
+pub const PROTOCOL_VERSION_MLS10: u16 = 1;
+pub const CREDENTIAL_BASIC: u16 = 1;
+pub const EXTENSION_RATCHET_TREE: u16 = 2;
+
+/// BasicCredential: an opaque application identity (Umbra puts the PeerId bytes here)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub identity: Vec<u8>,
+}
+
+impl Credential {
+    pub fn basic(identity: impl Into<Vec<u8>>) -> Self {
+        Self { identity: identity.into() }
+    }
+}
+
+impl Encode for Credential {
+    fn encode(&self, out: &mut Vec<u8>) {
+        CREDENTIAL_BASIC.encode(out);
+        encode_opaque(&self.identity, out);
+    }
+}
+
+impl Decode for Credential {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        match u16::decode(reader)? {
+            CREDENTIAL_BASIC => Ok(Self { identity: decode_opaque(reader)? }),
+            other => Err(MlsError::Decode(format!("Unsupported credential type {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub extension_type: u16,
+    pub data: Vec<u8>,
+}
+
+impl Encode for Extension {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.extension_type.encode(out);
+        encode_opaque(&self.data, out);
+    }
+}
+
+impl Decode for Extension {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            extension_type: u16::decode(reader)?,
+            data: decode_opaque(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub versions: Vec<u16>,
+    pub cipher_suites: Vec<u16>,
+    pub extensions: Vec<u16>,
+    pub proposals: Vec<u16>,
+    pub credentials: Vec<u16>,
+}
+
+impl Capabilities {
+    pub fn for_suite(suite: CipherSuite) -> Self {
+        Self {
+            versions: vec![PROTOCOL_VERSION_MLS10],
+            cipher_suites: vec![suite.id()],
+            extensions: Vec::new(),
+            proposals: Vec::new(),
+            credentials: vec![CREDENTIAL_BASIC],
+        }
+    }
+}
+
+impl Encode for Capabilities {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_vec(&self.versions, out);
+        encode_vec(&self.cipher_suites, out);
+        encode_vec(&self.extensions, out);
+        encode_vec(&self.proposals, out);
+        encode_vec(&self.credentials, out);
+    }
+}
+
+impl Decode for Capabilities {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            versions: decode_vec(reader)?,
+            cipher_suites: decode_vec(reader)?,
+            extensions: decode_vec(reader)?,
+            proposals: decode_vec(reader)?,
+            credentials: decode_vec(reader)?,
+        })
+    }
+}
+
+/// Validity window of a KeyPackage, seconds since the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lifetime {
+    pub not_before: u64,
+    pub not_after: u64,
+}
+
+impl Lifetime {
+    /// Valid from an hour ago (clock skew) for the given number of seconds
+    pub fn from_now(duration_secs: u64) -> Self {
+        let now = unix_now();
+        Self {
+            not_before: now.saturating_sub(3600),
+            not_after: now + duration_secs,
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        let now = unix_now();
+        self.not_before <= now && now <= self.not_after
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeafNodeSource {
+    KeyPackage(Lifetime),
+    Update,
+    Commit(Vec<u8>),
+}
+
+impl Encode for LeafNodeSource {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::KeyPackage(lifetime) => {
+                1u8.encode(out);
+                lifetime.not_before.encode(out);
+                lifetime.not_after.encode(out);
+            }
+            Self::Update => 2u8.encode(out),
+            Self::Commit(parent_hash) => {
+                3u8.encode(out);
+                encode_opaque(parent_hash, out);
+            }
+        }
+    }
+}
+
+impl Decode for LeafNodeSource {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        match u8::decode(reader)? {
+            1 => Ok(Self::KeyPackage(Lifetime {
+                not_before: u64::decode(reader)?,
+                not_after: u64::decode(reader)?,
+            })),
+            2 => Ok(Self::Update),
+            3 => Ok(Self::Commit(decode_opaque(reader)?)),
+            other => Err(MlsError::Decode(format!("Invalid leaf node source {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafNode {
+    pub encryption_key: Vec<u8>,
+    pub signature_key: Vec<u8>,
+    pub credential: Credential,
+    pub capabilities: Capabilities,
+    pub source: LeafNodeSource,
+    pub extensions: Vec<Extension>,
+    pub signature: Vec<u8>,
+}
+
+impl LeafNode {
+    /// Build and sign a leaf; `group_id`/`leaf_index` are only bound for update and commit leaves
+    pub fn new(
+        suite: CipherSuite,
+        encryption_key: Vec<u8>,
+        credential: Credential,
+        source: LeafNodeSource,
+        signer: &SignatureKeyPair,
+        group_id: &[u8],
+        leaf_index: LeafIndex,
+    ) -> Result<Self> {
+        let mut leaf = Self {
+            encryption_key,
+            signature_key: signer.public_key().to_vec(),
+            credential,
+            capabilities: Capabilities::for_suite(suite),
+            source,
+            extensions: Vec::new(),
+            signature: Vec::new(),
+        };
+        leaf.signature = signer.sign_with_label("LeafNodeTBS", &leaf.tbs(group_id, leaf_index))?;
+        Ok(leaf)
+    }
+
+    fn tbs(&self, group_id: &[u8], leaf_index: LeafIndex) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_content(&mut out);
+        if !matches!(self.source, LeafNodeSource::KeyPackage(_)) {
+            encode_opaque(group_id, &mut out);
+            leaf_index.encode(&mut out);
+        }
+        out
+    }
+
+    fn encode_content(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.encryption_key, out);
+        encode_opaque(&self.signature_key, out);
+        self.credential.encode(out);
+        self.capabilities.encode(out);
+        self.source.encode(out);
+        encode_vec(&self.extensions, out);
+    }
+
+    pub fn verify(&self, suite: CipherSuite, group_id: &[u8], leaf_index: LeafIndex) -> Result<()> {
+        if !self.capabilities.cipher_suites.contains(&suite.id()) {
+            return Err(MlsError::InvalidTree("Leaf does not support the group ciphersuite".to_string()));
+        }
+        suite.verify_with_label(&self.signature_key, "LeafNodeTBS", &self.tbs(group_id, leaf_index), &self.signature)
+    }
+
+    pub fn parent_hash(&self) -> Option<&[u8]> {
+        match &self.source {
+            LeafNodeSource::Commit(hash) => Some(hash),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for LeafNode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_content(out);
+        encode_opaque(&self.signature, out);
+    }
+}
+
+impl Decode for LeafNode {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            encryption_key: decode_opaque(reader)?,
+            signature_key: decode_opaque(reader)?,
+            credential: Credential::decode(reader)?,
+            capabilities: Capabilities::decode(reader)?,
+            source: LeafNodeSource::decode(reader)?,
+            extensions: decode_vec(reader)?,
+            signature: decode_opaque(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentNode {
+    pub encryption_key: Vec<u8>,
+    pub parent_hash: Vec<u8>,
+    pub unmerged_leaves: Vec<LeafIndex>,
+}
+
+impl Encode for ParentNode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_opaque(&self.encryption_key, out);
+        encode_opaque(&self.parent_hash, out);
+        encode_vec(&self.unmerged_leaves, out);
+    }
+}
+
+impl Decode for ParentNode {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(Self {
+            encryption_key: decode_opaque(reader)?,
+            parent_hash: decode_opaque(reader)?,
+            unmerged_leaves: decode_vec(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Leaf(LeafNode),
+    Parent(ParentNode),
+}
+
+impl Node {
+    pub fn encryption_key(&self) -> &[u8] {
+        match self {
+            Self::Leaf(leaf) => &leaf.encryption_key,
+            Self::Parent(parent) => &parent.encryption_key,
+        }
+    }
+
+    fn parent_hash(&self) -> Option<&[u8]> {
+        match self {
+            Self::Leaf(leaf) => leaf.parent_hash(),
+            Self::Parent(parent) => Some(&parent.parent_hash),
+        }
+    }
+}
+
+impl Encode for Node {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Leaf(leaf) => {
+                1u8.encode(out);
+                leaf.encode(out);
+            }
+            Self::Parent(parent) => {
+                2u8.encode(out);
+                parent.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Node {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self> {
+        match u8::decode(reader)? {
+            1 => Ok(Self::Leaf(LeafNode::decode(reader)?)),
+            2 => Ok(Self::Parent(ParentNode::decode(reader)?)),
+            other => Err(MlsError::Decode(format!("Invalid node type {}", other))),
+        }
+    }
+}
+
+/// The public ratchet tree shared by every member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RatchetTree {
+    suite: CipherSuite,
+    nodes: Vec<Option<Node>>,
+}
+
+impl RatchetTree {
+    pub fn new(suite: CipherSuite, leaf: LeafNode) -> Self {
+        Self {
+            suite,
+            nodes: vec![Some(Node::Leaf(leaf))],
+        }
+    }
+
+    pub fn n_leaves(&self) -> u32 {
+        (self.nodes.len() as u32).div_ceil(2)
+    }
+
+    pub fn root(&self) -> NodeIndex {
+        tree_math::root(self.n_leaves())
+    }
+
+    pub fn node(&self, index: NodeIndex) -> Option<&Node> {
+        self.nodes.get(index as usize).and_then(|n| n.as_ref())
+    }
+
+    pub fn leaf(&self, index: LeafIndex) -> Option<&LeafNode> {
+        match self.node(tree_math::leaf_to_node(index)) {
+            Some(Node::Leaf(leaf)) => Some(leaf),
+            _ => None,
+        }
+    }
+
+    pub fn parent_node(&self, index: NodeIndex) -> Option<&ParentNode> {
+        match self.node(index) {
+            Some(Node::Parent(parent)) => Some(parent),
+            _ => None,
+        }
+    }
+
+    /// Occupied leaves in index order
+    pub fn leaves(&self) -> impl Iterator<Item = (LeafIndex, &LeafNode)> {
+        (0..self.n_leaves()).filter_map(|i| self.leaf(i).map(|leaf| (i, leaf)))
+    }
+
+    pub fn find_leaf(&self, predicate: impl Fn(&LeafNode) -> bool) -> Option<LeafIndex> {
+        self.leaves().find(|(_, leaf)| predicate(leaf)).map(|(i, _)| i)
+    }
+
+    /// Put a new member in the leftmost free leaf, growing the tree if it is full
+    pub fn add_leaf(&mut self, leaf: LeafNode) -> LeafIndex {
+        let index = match (0..self.n_leaves()).find(|&i| self.leaf(i).is_none()) {
+            Some(i) => i,
+            None => {
+                let n = self.n_leaves();
+                self.nodes.resize(tree_math::node_width(n * 2) as usize, None);
+                n
+            }
+        };
+
+        let node = tree_math::leaf_to_node(index);
+        self.nodes[node as usize] = Some(Node::Leaf(leaf));
+        for p in tree_math::direct_path(node, self.n_leaves()) {
+            if let Some(Node::Parent(parent)) = &mut self.nodes[p as usize] {
+                parent.unmerged_leaves.push(index);
+            }
+        }
+        index
+    }
+
+    /// Replace a member's leaf (Update proposal) and blank its direct path
+    pub fn update_leaf(&mut self, index: LeafIndex, leaf: LeafNode) {
+        self.nodes[tree_math::leaf_to_node(index) as usize] = Some(Node::Leaf(leaf));
+        self.blank_path(index);
+    }
+
+    pub fn remove_leaf(&mut self, index: LeafIndex) {
+        self.nodes[tree_math::leaf_to_node(index) as usize] = None;
+        self.blank_path(index);
+        self.truncate();
+    }
+
+    fn blank_path(&mut self, index: LeafIndex) {
+        for p in tree_math::direct_path(tree_math::leaf_to_node(index), self.n_leaves()) {
+            self.nodes[p as usize] = None;
+        }
+    }
+
+    /// Drop the right half of the tree while it is entirely blank
+    fn truncate(&mut self) {
+        while self.n_leaves() > 1 {
+            let half = self.n_leaves() / 2;
+            let right_start = tree_math::leaf_to_node(half) as usize;
+            if self.nodes[right_start..].iter().any(|n| n.is_some()) {
+                break;
+            }
+            self.nodes.truncate(tree_math::node_width(half) as usize);
+        }
+    }
+
+    /// Non-blank nodes that together cover the subtree under `index`
+    pub fn resolution(&self, index: NodeIndex) -> Vec<NodeIndex> {
+        match self.node(index) {
+            Some(Node::Leaf(_)) => vec![index],
+            Some(Node::Parent(parent)) => {
+                let mut res = vec![index];
+                res.extend(parent.unmerged_leaves.iter().map(|&l| tree_math::leaf_to_node(l)));
+                res
+            }
+            None if tree_math::is_leaf(index) => Vec::new(),
+            None => {
+                let mut res = self.resolution(tree_math::left(index));
+                res.extend(self.resolution(tree_math::right(index)));
+                res
+            }
+        }
+    }
+
+    /// Direct path of a leaf minus the nodes whose copath child has an empty resolution,
+    /// paired with that copath child
+    pub fn filtered_direct_path(&self, leaf: LeafIndex) -> Vec<(NodeIndex, NodeIndex)> {
+        let n = self.n_leaves();
+        let node = tree_math::leaf_to_node(leaf);
+        tree_math::direct_path(node, n)
+            .into_iter()
+            .zip(tree_math::copath(node, n))
+            .filter(|(_, copath_child)| !self.resolution(*copath_child).is_empty())
+            .collect()
+    }
+
+    /// Blank a committer's direct path, install the new keys on its filtered direct path and
+    /// compute their parent hashes; returns the parent hash the committer's leaf must carry
+    pub fn apply_path(&mut self, leaf: LeafIndex, public_keys: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let path = self.filtered_direct_path(leaf);
+        if path.len() != public_keys.len() {
+            return Err(MlsError::InvalidCommit(format!(
+                "UpdatePath has {} nodes, expected {}",
+                public_keys.len(),
+                path.len()
+            )));
+        }
+
+        self.blank_path(leaf);
+        for ((node, _), key) in path.iter().zip(public_keys) {
+            self.nodes[*node as usize] = Some(Node::Parent(ParentNode {
+                encryption_key: key.clone(),
+                parent_hash: Vec::new(),
+                unmerged_leaves: Vec::new(),
+            }));
+        }
+
+        // Chain parent hashes from the root down to the leaf
+        let mut parent_hash = Vec::new();
+        for (node, copath_child) in path.iter().rev() {
+            if let Some(Node::Parent(parent)) = &mut self.nodes[*node as usize] {
+                parent.parent_hash = parent_hash;
+            }
+            let parent = self.parent_node(*node).expect("just set");
+            parent_hash = self.compute_parent_hash(parent, *copath_child, &HashSet::new());
+        }
+        Ok(parent_hash)
+    }
+
+    /// Install a leaf without touching its direct path (commit leaves)
+    pub fn set_leaf(&mut self, index: LeafIndex, leaf: LeafNode) {
+        self.nodes[tree_math::leaf_to_node(index) as usize] = Some(Node::Leaf(leaf));
+    }
+
+    fn compute_parent_hash(&self, parent: &ParentNode, sibling: NodeIndex, excluded: &HashSet<LeafIndex>) -> Vec<u8> {
+        let mut input = Vec::new();
+        encode_opaque(&parent.encryption_key, &mut input);
+        encode_opaque(&parent.parent_hash, &mut input);
+        encode_opaque(&self.hash_node(sibling, excluded), &mut input);
+        self.suite.hash(&input)
+    }
+
+    pub fn tree_hash(&self) -> Vec<u8> {
+        self.hash_node(self.root(), &HashSet::new())
+    }
+
+    /// TreeHashInput, treating `excluded` leaves as blank and dropping them from unmerged lists
+    fn hash_node(&self, index: NodeIndex, excluded: &HashSet<LeafIndex>) -> Vec<u8> {
+        let mut input = Vec::new();
+        if let Some(leaf_index) = tree_math::node_to_leaf(index) {
+            1u8.encode(&mut input);
+            leaf_index.encode(&mut input);
+            let leaf = self.leaf(leaf_index).filter(|_| !excluded.contains(&leaf_index));
+            encode_optional(&leaf.cloned(), &mut input);
+        } else {
+            2u8.encode(&mut input);
+            let parent = self.parent_node(index).map(|p| ParentNode {
+                unmerged_leaves: p.unmerged_leaves.iter().copied().filter(|l| !excluded.contains(l)).collect(),
+                ..p.clone()
+            });
+            encode_optional(&parent, &mut input);
+            encode_opaque(&self.hash_node(tree_math::left(index), excluded), &mut input);
+            encode_opaque(&self.hash_node(tree_math::right(index), excluded), &mut input);
+        }
+        self.suite.hash(&input)
+    }
+
+    /// Every non-blank parent must be vouched for by a parent hash below it (RFC 9420 §7.9.2)
+    pub fn verify_parent_hashes(&self) -> Result<()> {
+        for index in (1..self.nodes.len() as u32).step_by(2) {
+            let Some(parent) = self.parent_node(index) else { continue };
+            let excluded: HashSet<LeafIndex> = parent.unmerged_leaves.iter().copied().collect();
+            let unmerged_nodes: HashSet<NodeIndex> =
+                excluded.iter().map(|&l| tree_math::leaf_to_node(l)).collect();
+
+            let valid = [
+                (tree_math::left(index), tree_math::right(index)),
+                (tree_math::right(index), tree_math::left(index)),
+            ]
+            .into_iter()
+            .any(|(child, sibling)| {
+                let expected = self.compute_parent_hash(parent, sibling, &excluded);
+                self.resolution(child)
+                    .into_iter()
+                    .filter(|d| !unmerged_nodes.contains(d))
+                    .any(|d| self.node(d).and_then(Node::parent_hash) == Some(expected.as_slice()))
+            });
+
+            if !valid {
+                return Err(MlsError::InvalidTree(format!("Parent hash mismatch at node {}", index)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every leaf signature against its position in the group
+    pub fn verify_leaves(&self, group_id: &[u8]) -> Result<()> {
+        for (index, leaf) in self.leaves() {
+            leaf.verify(self.suite, group_id, index)?;
+        }
+        Ok(())
+    }
+
+    /// Ratchet tree extension payload, with trailing blanks trimmed
+    pub fn encode_nodes(&self) -> Vec<u8> {
+        let len = self.nodes.iter().rposition(|n| n.is_some()).map_or(0, |i| i + 1);
+        let mut out = Vec::new();
+        let mut body = Vec::new();
+        for node in &self.nodes[..len] {
+            encode_optional(node, &mut body);
+        }
+        encode_opaque(&body, &mut out);
+        out
+    }
+
+    pub fn decode_nodes(suite: CipherSuite, bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let body = decode_opaque(&mut reader)?;
+        reader.finish()?;
+
+        let mut inner = Reader::new(&body);
+        let mut nodes = Vec::new();
+        while !inner.is_empty() {
+            nodes.push(decode_optional::<Node>(&mut inner)?);
+        }
+        if nodes.is_empty() || nodes.last().is_some_and(|n| n.is_none()) {
+            return Err(MlsError::InvalidTree("Malformed node list".to_string()));
+        }
+
+        // Pad back out to a full tree
+        let n_leaves = (nodes.len() as u32).div_ceil(2).next_power_of_two();
+        nodes.resize(tree_math::node_width(n_leaves) as usize, None);
+
+        for (i, node) in nodes.iter().enumerate() {
+            let expected_leaf = tree_math::is_leaf(i as u32);
+            match node {
+                Some(Node::Leaf(_)) if !expected_leaf => return Err(MlsError::InvalidTree("Leaf in parent slot".to_string())),
+                Some(Node::Parent(_)) if expected_leaf => return Err(MlsError::InvalidTree("Parent in leaf slot".to_string())),
+                _ => {}
+            }
+        }
+        Ok(Self { suite, nodes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    fn leaf(name: &str) -> LeafNode {
+        let signer = SUITE.generate_signature_key_pair().unwrap();
+        LeafNode::new(
+            SUITE,
+            SUITE.generate_key_pair().unwrap().public,
+            Credential::basic(name.as_bytes()),
+            LeafNodeSource::KeyPackage(Lifetime::from_now(3600)),
+            &signer,
+            &[],
+            0,
+        )
+        .unwrap()
+    }
+
+    fn tree_of(n: usize) -> RatchetTree {
+        let mut tree = RatchetTree::new(SUITE, leaf("0"));
+        for i in 1..n {
+            tree.add_leaf(leaf(&i.to_string()));
+        }
+        tree
+    }
+
+    #[test]
+    fn test_add_grows_and_fills_holes() {
+        let mut tree = tree_of(3);
+        assert_eq!(tree.n_leaves(), 4);
+
+        tree.remove_leaf(1);
+        assert_eq!(tree.add_leaf(leaf("x")), 1);
+        assert_eq!(tree.add_leaf(leaf("y")), 3);
+        assert_eq!(tree.add_leaf(leaf("z")), 4);
+        assert_eq!(tree.n_leaves(), 8);
+    }
+
+    #[test]
+    fn test_remove_truncates() {
+        let mut tree = tree_of(5);
+        assert_eq!(tree.n_leaves(), 8);
+        tree.remove_leaf(4);
+        assert_eq!(tree.n_leaves(), 4);
+        tree.remove_leaf(3);
+        tree.remove_leaf(2);
+        assert_eq!(tree.n_leaves(), 2);
+    }
+
+    #[test]
+    fn test_resolution_and_filtered_path() {
+        let mut tree = tree_of(3);
+        // No parents set yet: the root resolves to every occupied leaf
+        assert_eq!(tree.resolution(3), vec![0, 2, 4]);
+
+        tree.apply_path(0, &[vec![1], vec![3]]).unwrap();
+        assert_eq!(tree.resolution(3), vec![3]);
+
+        // A member added under a populated parent shows up as unmerged
+        assert_eq!(tree.add_leaf(leaf("late")), 3);
+        assert_eq!(tree.resolution(3), vec![3, 6]);
+
+        assert_eq!(tree.filtered_direct_path(2), vec![(5, 6), (3, 1)]);
+        tree.remove_leaf(3);
+        // Leaf 3 is gone, so node 5 drops out of leaf 2's filtered path
+        assert_eq!(tree.filtered_direct_path(2), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn test_parent_hash_chain_verifies() {
+        let mut tree = tree_of(3);
+        let parent_hash = tree.apply_path(2, &[SUITE.generate_key_pair().unwrap().public]).unwrap();
+
+        // Without a commit leaf carrying the hash, the parent is unanchored
+        assert!(tree.verify_parent_hashes().is_err());
+
+        let signer = SUITE.generate_signature_key_pair().unwrap();
+        let committer = LeafNode::new(
+            SUITE,
+            SUITE.generate_key_pair().unwrap().public,
+            Credential::basic(b"2".to_vec()),
+            LeafNodeSource::Commit(parent_hash),
+            &signer,
+            b"group",
+            2,
+        )
+        .unwrap();
+        tree.set_leaf(2, committer);
+        tree.verify_parent_hashes().unwrap();
+        tree.verify_leaves(b"group").unwrap();
+        assert!(tree.verify_leaves(b"other group").is_err());
+
+        // Adding a member under the path leaves the hashes valid
+        tree.add_leaf(leaf("late"));
+        tree.verify_parent_hashes().unwrap();
+    }
+
+    #[test]
+    fn test_tree_hash_changes_with_content() {
+        let tree = tree_of(3);
+        let mut other = tree.clone();
+        assert_eq!(tree.tree_hash(), other.tree_hash());
+
+        other.remove_leaf(1);
+        assert_ne!(tree.tree_hash(), other.tree_hash());
+    }
+
+    #[test]
+    fn test_node_list_roundtrip() {
+        let mut tree = tree_of(5);
+        tree.remove_leaf(3);
+        let decoded = RatchetTree::decode_nodes(SUITE, &tree.encode_nodes()).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(decoded.tree_hash(), tree.tree_hash());
+    }
+}
@@ -0,0 +1,177 @@
+// Array-based left-balanced binary tree arithmetic (RFC 9420 Appendix C)
+// Leaves sit at even node indices, parents at odd ones; the tree always has a power-of-two width
+
+/// Index of a leaf among the leaves (not a node index)
+pub type LeafIndex = u32;
+
+/// Index of a node in the array representation
+pub type NodeIndex = u32;
+
+pub fn leaf_to_node(leaf: LeafIndex) -> NodeIndex {
+    leaf * 2
+}
+
+pub fn node_to_leaf(node: NodeIndex) -> Option<LeafIndex> {
+    is_leaf(node).then_some(node / 2)
+}
+
+pub fn is_leaf(node: NodeIndex) -> bool {
+    node & 1 == 0
+}
+
+/// Number of trailing one bits: 0 for leaves, height for parents
+pub fn level(x: NodeIndex) -> u32 {
+    x.trailing_ones()
+}
+
+/// Number of nodes needed for `n_leaves` leaves
+pub fn node_width(n_leaves: u32) -> u32 {
+    if n_leaves == 0 {
+        0
+    } else {
+        2 * (n_leaves - 1) + 1
+    }
+}
+
+pub fn root(n_leaves: u32) -> NodeIndex {
+    let w = node_width(n_leaves);
+    (1 << (u32::BITS - 1 - w.leading_zeros())) - 1
+}
+
+pub fn left(x: NodeIndex) -> NodeIndex {
+    let k = level(x);
+    assert!(k > 0, "leaf has no children");
+    x ^ (1 << (k - 1))
+}
+
+pub fn right(x: NodeIndex) -> NodeIndex {
+    let k = level(x);
+    assert!(k > 0, "leaf has no children");
+    x ^ (3 << (k - 1))
+}
+
+pub fn parent(x: NodeIndex, n_leaves: u32) -> NodeIndex {
+    assert!(x != root(n_leaves), "root has no parent");
+    let k = level(x);
+    let b = (x >> (k + 1)) & 1;
+    (x | (1 << k)) ^ (b << (k + 1))
+}
+
+pub fn sibling(x: NodeIndex, n_leaves: u32) -> NodeIndex {
+    let p = parent(x, n_leaves);
+    if x < p {
+        right(p)
+    } else {
+        left(p)
+    }
+}
+
+/// Ancestors of `x` from its parent up to and including the root
+pub fn direct_path(x: NodeIndex, n_leaves: u32) -> Vec<NodeIndex> {
+    let r = root(n_leaves);
+    let mut path = Vec::new();
+    let mut node = x;
+    while node != r {
+        node = parent(node, n_leaves);
+        path.push(node);
+    }
+    path
+}
+
+/// Siblings of `x` and of each ancestor below the root
+pub fn copath(x: NodeIndex, n_leaves: u32) -> Vec<NodeIndex> {
+    let r = root(n_leaves);
+    let mut path = Vec::new();
+    let mut node = x;
+    while node != r {
+        path.push(sibling(node, n_leaves));
+        node = parent(node, n_leaves);
+    }
+    path
+}
+
+/// Whether `ancestor` is `x` or one of its ancestors
+pub fn is_ancestor(ancestor: NodeIndex, x: NodeIndex) -> bool {
+    let k = level(ancestor);
+    level(x) <= k && (x >> (k + 1)) == (ancestor >> (k + 1))
+}
+
+/// Lowest node whose subtree contains both `x` and `y`
+pub fn common_ancestor(x: NodeIndex, y: NodeIndex) -> NodeIndex {
+    if is_ancestor(x, y) {
+        return x;
+    }
+    if is_ancestor(y, x) {
+        return y;
+    }
+
+    let (mut xn, mut yn) = (x, y);
+    let mut k = 0;
+    while xn != yn {
+        xn >>= 1;
+        yn >>= 1;
+        k += 1;
+    }
+    (xn << k) + (1 << (k - 1)) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eight_leaf_tree() {
+        //                 X(7)
+        //       X(3)                X(11)
+        //   X(1)     X(5)      X(9)      X(13)
+        // 0     2   4    6    8   10   12    14
+        let n = 8;
+        assert_eq!(root(n), 7);
+        assert_eq!(node_width(n), 15);
+        assert_eq!(level(7), 3);
+        assert_eq!(level(5), 1);
+        assert_eq!(level(4), 0);
+        assert_eq!(left(7), 3);
+        assert_eq!(right(7), 11);
+        assert_eq!(left(1), 0);
+        assert_eq!(right(13), 14);
+        assert_eq!(parent(0, n), 1);
+        assert_eq!(parent(5, n), 3);
+        assert_eq!(parent(11, n), 7);
+        assert_eq!(sibling(1, n), 5);
+        assert_eq!(sibling(10, n), 8);
+        assert_eq!(sibling(3, n), 11);
+        assert_eq!(direct_path(4, n), vec![5, 3, 7]);
+        assert_eq!(copath(4, n), vec![6, 1, 11]);
+        assert!(direct_path(7, n).is_empty());
+    }
+
+    #[test]
+    fn test_roots() {
+        assert_eq!(root(1), 0);
+        assert_eq!(root(2), 1);
+        assert_eq!(root(4), 3);
+        assert_eq!(root(16), 15);
+        assert_eq!(root(32), 31);
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        assert_eq!(common_ancestor(0, 2), 1);
+        assert_eq!(common_ancestor(0, 6), 3);
+        assert_eq!(common_ancestor(4, 12), 7);
+        assert_eq!(common_ancestor(8, 9), 9);
+        assert_eq!(common_ancestor(5, 3), 3);
+        assert_eq!(common_ancestor(6, 6), 6);
+    }
+
+    #[test]
+    fn test_ancestry() {
+        assert!(is_ancestor(7, 12));
+        assert!(is_ancestor(3, 5));
+        assert!(is_ancestor(5, 5));
+        assert!(!is_ancestor(11, 4));
+        assert!(!is_ancestor(1, 4));
+        assert!(!is_ancestor(5, 7));
+    }
+}
@@ -0,0 +1,70 @@
+// Group lifecycle under the post-quantum hybrid ciphersuite (HybridKem path secrets,
+// HybridSignature leaf and commit signatures)
+
+use umbra_crypto::IdentityKey;
+use umbra_mls::{CipherSuite, Credential, Group, KeyPackageBundle, MlsMessage, ProcessedMessage, SignatureKeyPair};
+
+const SUITE: CipherSuite = CipherSuite::HybridKyber768ChaCha20;
+
+fn bundle(name: &str) -> KeyPackageBundle {
+    KeyPackageBundle::new(Credential::basic(name.as_bytes()), SUITE.generate_signature_key_pair().unwrap()).unwrap()
+}
+
+fn add(group: &mut Group, others: &mut [&mut Group], name: &str) -> Group {
+    let joiner = bundle(name);
+    let output = group.add_member(joiner.key_package.clone()).unwrap();
+    for other in others.iter_mut() {
+        other.process_message(&output.commit).unwrap();
+    }
+    let Some(MlsMessage::Welcome(welcome)) = output.welcome else { panic!("no welcome") };
+    Group::join(&welcome, joiner).unwrap()
+}
+
+fn assert_in_sync(groups: &[&Group]) {
+    for g in &groups[1..] {
+        assert_eq!(g.epoch(), groups[0].epoch());
+        assert_eq!(g.epoch_authenticator(), groups[0].epoch_authenticator());
+    }
+}
+
+#[test]
+fn test_hybrid_group_lifecycle() {
+    let signer = SignatureKeyPair::from_identity(IdentityKey::generate().unwrap());
+    let mut alice = Group::create(Credential::basic(b"alice"), signer).unwrap();
+    assert_eq!(alice.cipher_suite(), SUITE);
+
+    let mut bob = add(&mut alice, &mut [], "bob");
+    let mut carol = add(&mut alice, &mut [&mut bob], "carol");
+    let mut dave = add(&mut alice, &mut [&mut bob, &mut carol], "dave");
+    assert_in_sync(&[&alice, &bob, &carol, &dave]);
+
+    // A commit from a non-creator reaches everyone through hybrid path secrets
+    let output = carol.rekey().unwrap();
+    for member in [&mut alice, &mut bob, &mut dave] {
+        member.process_message(&output.commit).unwrap();
+    }
+    assert_in_sync(&[&alice, &bob, &carol, &dave]);
+
+    let message = dave.encrypt_application_message(b"post-quantum hello").unwrap();
+    assert_eq!(
+        bob.process_message(&message).unwrap(),
+        ProcessedMessage::Application { sender: 3, data: b"post-quantum hello".to_vec() }
+    );
+
+    let output = alice.remove_member(1).unwrap();
+    assert_eq!(bob.process_message(&output.commit).unwrap(), ProcessedMessage::Removed { sender: 0 });
+    carol.process_message(&output.commit).unwrap();
+    dave.process_message(&output.commit).unwrap();
+    assert_in_sync(&[&alice, &carol, &dave]);
+    assert_eq!(alice.member_count(), 3);
+}
+
+#[test]
+fn test_classical_key_package_rejected() {
+    let mut alice = Group::create(Credential::basic(b"alice"), SUITE.generate_signature_key_pair().unwrap()).unwrap();
+    let classical = CipherSuite::Curve25519ChaCha20.generate_signature_key_pair().unwrap();
+    let bob = KeyPackageBundle::new(Credential::basic(b"bob"), classical).unwrap();
+
+    assert!(alice.add_member(bob.key_package).is_err());
+    assert_eq!(alice.member_count(), 1);
+}
@@ -0,0 +1,194 @@
+// Known-answer tests for the MLS primitives
+// The DeriveKeyPair vectors are RFC 9180's own (Appendix A, DHKEM(X25519, HKDF-SHA256)). The
+// label, key-schedule and secret-tree values were computed independently (Python hmac/hashlib)
+// from the RFC 9420 definitions: they pin the implementation down but aren't the mlswg
+// interop vectors. Those couldn't be fetched into this tree; `test_mlswg_crypto_basics` runs
+// their crypto-basics.json once it's copied to tests/vectors/ (cargo test -- --ignored)
+
+use umbra_mls::codec::Encode;
+use umbra_mls::key_schedule::{EpochSecrets, GroupContext};
+use umbra_mls::secret_tree::SecretTree;
+use umbra_mls::crypto::HpkeCiphertext;
+use umbra_mls::CipherSuite;
+use zeroize::Zeroizing;
+
+const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+fn secret() -> Vec<u8> {
+    (0u8..32).collect()
+}
+
+fn context() -> GroupContext {
+    GroupContext {
+        cipher_suite: SUITE,
+        group_id: b"group".to_vec(),
+        epoch: 1,
+        tree_hash: vec![1; 32],
+        confirmed_transcript_hash: vec![2; 32],
+        extensions: Vec::new(),
+    }
+}
+
+#[test]
+fn test_expand_with_label() {
+    let okm = SUITE.expand_with_label(&secret(), "test", b"ctx", 32).unwrap();
+    assert_eq!(hex::encode(okm.as_slice()), "1733dc4c55f96e6bd6949bb04162ff324d0d2d3676f5009fdd50e0bd9e668e69");
+
+    let derived = SUITE.derive_secret(&secret(), "welcome").unwrap();
+    assert_eq!(hex::encode(derived.as_slice()), "9f040463f1c1e8a6d74ff44ccb041b463bd8e19003226ea294a8a943a785020f");
+}
+
+#[test]
+fn test_ref_hash() {
+    assert_eq!(
+        hex::encode(SUITE.ref_hash("MLS 1.0 KeyPackage Reference", b"value")),
+        "73472a0b7c15730e0538a0b71f6a0b3da7731a8102d39e350f75c5c25b812a75"
+    );
+}
+
+#[test]
+fn test_group_context_encoding() {
+    assert_eq!(
+        hex::encode(context().to_bytes()),
+        "000100030567726f75700000000000000001\
+         200101010101010101010101010101010101010101010101010101010101010101\
+         200202020202020202020202020202020202020202020202020202020202020202\
+         00"
+    );
+}
+
+#[test]
+fn test_key_schedule() {
+    let secrets = EpochSecrets::derive(SUITE, &[3; 32], &[4; 32], &context()).unwrap();
+    let hex_of = |s: &[u8]| hex::encode(s);
+
+    assert_eq!(hex_of(&secrets.joiner_secret), "7df62971afccadf46848fe8bcb800f6530b8c9f627737d4ade85ada933cae93c");
+    assert_eq!(hex_of(&secrets.welcome_secret), "8d8bed32de5cb263a9633f2c5f67fc16074ef5dbaac2de539bb750abcd5a4153");
+    assert_eq!(hex_of(&secrets.sender_data_secret), "d5c62c12f5c67f6303e8baf5a786230c2f74b488fb6b1343b66c1c7374491e6e");
+    assert_eq!(hex_of(&secrets.encryption_secret), "ad28348111033275156b806739508ed4149bc8a91cd0acba23a9edf8ccffdca0");
+    assert_eq!(hex_of(&secrets.exporter_secret), "6ea8a8759e298b7eb83b1f2c818f7f926eb32aed0d9b62b8e7048df9f8e89263");
+    assert_eq!(hex_of(&secrets.confirmation_key), "93ada4a26d847f973d1205c409f194c94216e6be624826abb38f35af825896e6");
+    assert_eq!(hex_of(&secrets.membership_key), "b53e226ed2b99f8fe96a9500fb5b3ef20b9b852f4e9ccf14a50d74d256ac0218");
+    assert_eq!(hex_of(&secrets.epoch_authenticator), "e077c69ab7070ddbfcf9296619d6c048555cac031c6ac197b4457570ca730fa1");
+    assert_eq!(hex_of(&secrets.init_secret), "706569f3de9007cfca589b646e77e8cb1608da50c2b4843af25309e6e80d3b52");
+
+    let exported = secrets.export(SUITE, "umbra topic key", b"group", 32).unwrap();
+    assert_eq!(hex_of(&exported), "f2971b9750e20de0be3d46bf9f4ef7800af470882545c89a437b3e1cf2b544ce");
+}
+
+#[test]
+fn test_secret_tree() {
+    let mut tree = SecretTree::new(SUITE, Zeroizing::new(vec![7; 32]), 4);
+
+    let first = tree.next_key(1).unwrap();
+    assert_eq!(hex::encode(first.key.as_slice()), "7c67caaaf95a517a239bb750a230a6feb9e9e81c17da7f2b0b36537bac25c16d");
+    assert_eq!(hex::encode(first.nonce.as_slice()), "aef100fadfd5326c15a3e072");
+
+    let second = tree.next_key(1).unwrap();
+    assert_eq!(second.generation, 1);
+    assert_eq!(hex::encode(second.key.as_slice()), "a3c3db6e72c84907aba520ef96774a95a7186a71d5728ab4a8ba7fa9fec2779d");
+}
+
+/// (ikm, sk, pk) for the receiver and ephemeral keys of RFC 9180 A.1.1 and the four
+/// DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20Poly1305 vectors (A.2.1-A.2.4)
+const RFC9180_X25519_KEYS: [(&str, &str, &str); 9] = [
+    (
+        "6db9df30aa07dd42ee5e8181afdb977e538f5e1fec8a06223f33f7013e525037",
+        "4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8",
+        "3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d",
+    ),
+    (
+        "1ac01f181fdf9f352797655161c58b75c656a6cc2716dcb66372da835542e1df",
+        "8057991eef8f1f1af18f4a9491d16a1ce333f695d4db8e38da75975c4478e0fb",
+        "4310ee97d88cc1f088a5576c77ab0cf5c3ac797f3d95139c6c84b5429c59662a",
+    ),
+    (
+        "909a9b35d3dc4713a5e72a4da274b55d3d3821a37e5d099e74a647db583a904b",
+        "f4ec9b33b792c372c1d2c2063507b684ef925b8c75a42dbcbf57d63ccd381600",
+        "1afa08d3dec047a643885163f1180476fa7ddb54c6a8029ea33f95796bf2ac4a",
+    ),
+    (
+        "26b923eade72941c8a85b09986cdfa3f1296852261adedc52d58d2930269812b",
+        "77d114e0212be51cb1d76fa99dd41cfd4d0166b08caa09074430a6c59ef17879",
+        "13640af826b722fc04feaa4de2f28fbd5ecc03623b317834e7ff4120dbe73062",
+    ),
+    (
+        "35706a0b09fb26fb45c39c2f5079c709c7cf98e43afa973f14d88ece7e29c2e3",
+        "0c35fdf49df7aa01cd330049332c40411ebba36e0c718ebc3edf5845795f6321",
+        "2261299c3f40a9afc133b969a97f05e95be2c514e54f3de26cbe5644ac735b04",
+    ),
+    (
+        "64835d5ee64aa7aad57c6f2e4f758f7696617f8829e70bc9ac7a5ef95d1c756c",
+        "3ca22a6d1cda1bb9480949ec5329d3bf0b080ca4c45879c95eddb55c70b80b82",
+        "1a478716d63cb2e16786ee93004486dc151e988b34b475043d3e0175bdb01c44",
+    ),
+    (
+        "938d3daa5a8904540bc24f48ae90eed3f4f7f11839560597b55e7c9598c996c0",
+        "c94619e1af28971c8fa7957192b7e62a71ca2dcdde0a7cc4a8a9e741d600ab13",
+        "f7674cc8cd7baa5872d1f33dbaffe3314239f6197ddf5ded1746760bfc847e0e",
+    ),
+    (
+        "f3304ddcf15848488271f12b75ecaf72301faabf6ad283654a14c398832eb184",
+        "7b36a42822e75bf3362dfabbe474b3016236408becb83b859a6909e22803cb0c",
+        "a5099431c35c491ec62ca91df1525d6349cb8aa170c51f9581f8627be6334851",
+    ),
+    (
+        "49d6eac8c6c558c953a0a252929a818745bb08cd3d29e15f9f5db5eb2e7d4b84",
+        "5e6dd73e82b856339572b7245d3cbb073a7561c0bee52873490e305cbb710410",
+        "656a2e00dc9990fd189e6e473459392df556e9a2758754a09db3f51179a3fc02",
+    ),
+];
+
+#[test]
+fn test_derive_key_pair_rfc9180() {
+    for (ikm, private, public) in RFC9180_X25519_KEYS {
+        let ikm = hex::decode(ikm).unwrap();
+        let keys = SUITE.derive_key_pair(&ikm).unwrap();
+        assert_eq!(hex::encode(keys.private.as_slice()), private);
+        assert_eq!(hex::encode(&keys.public), public);
+
+        let recovered = SUITE.recover_key_pair(&ikm, &keys.public).unwrap();
+        assert_eq!(recovered.private.as_slice(), keys.private.as_slice());
+    }
+}
+
+/// mlswg/mls-implementations test-vectors/crypto-basics.json, for the suites we implement
+/// (only 0x0003 of theirs)
+#[test]
+#[ignore = "needs the mlswg crypto-basics.json in tests/vectors"]
+fn test_mlswg_crypto_basics() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/crypto-basics.json");
+    let json = std::fs::read_to_string(path).expect("crypto-basics.json from mlswg/mls-implementations");
+    let vectors: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+    let mut checked = 0;
+    for vector in vectors.as_array().unwrap() {
+        let Ok(suite) = CipherSuite::from_id(vector["cipher_suite"].as_u64().unwrap() as u16) else {
+            continue;
+        };
+        let bytes = |value: &serde_json::Value| hex::decode(value.as_str().unwrap()).unwrap();
+        let text = |value: &serde_json::Value| value.as_str().unwrap().to_string();
+
+        let v = &vector["ref_hash"];
+        assert_eq!(suite.ref_hash(&text(&v["label"]), &bytes(&v["value"])), bytes(&v["out"]));
+
+        let v = &vector["expand_with_label"];
+        let length = v["length"].as_u64().unwrap() as usize;
+        let out = suite.expand_with_label(&bytes(&v["secret"]), &text(&v["label"]), &bytes(&v["context"]), length).unwrap();
+        assert_eq!(out.as_slice(), bytes(&v["out"]));
+
+        let v = &vector["derive_secret"];
+        let out = suite.derive_secret(&bytes(&v["secret"]), &text(&v["label"])).unwrap();
+        assert_eq!(out.as_slice(), bytes(&v["out"]));
+
+        let v = &vector["sign_with_label"];
+        suite.verify_with_label(&bytes(&v["pub"]), &text(&v["label"]), &bytes(&v["content"]), &bytes(&v["signature"])).unwrap();
+
+        let v = &vector["encrypt_with_label"];
+        let ciphertext = HpkeCiphertext { kem_output: bytes(&v["kem_output"]), ciphertext: bytes(&v["ciphertext"]) };
+        let plaintext = suite.decrypt_with_label(&bytes(&v["priv"]), &text(&v["label"]), &bytes(&v["context"]), &ciphertext).unwrap();
+        assert_eq!(plaintext.as_slice(), bytes(&v["plaintext"]));
+        checked += 1;
+    }
+    assert!(checked > 0, "no vectors for a suite we implement");
+}
@@ -1,16 +1,988 @@
-/// Onion circuit management (stub for W3-W6)
+// Onion-routed circuits over /umbra/circuit/1
+// A client builds a circuit one hop at a time: it creates the first hop directly, then asks the
+// current last hop to extend to the next one. Relay cells carry one onion layer per hop, so each
+// relay learns only its two neighbours. Relay cells are the same size on every link, and every
+// hop refuses a cell whose counter it has seen before (`umbra_crypto::cell`). The exit hands the
+// recipient a message sealed to the
+// recipient's onion key: it learns who receives, but not who sent.
+// Mixes also pass Sphinx packets along outside any circuit, each held for a delay its sender
+// picked, so packets leave a mix in a different order than they came in. Sphinx packets are
+// built to a mix key that rotates every MIX_KEY_LIFETIME; the replay cache only has to cover
+// the current and previous key, which bounds it.
+
+use crate::codec::LengthPrefixedCodec;
+use crate::requests::{RequestEvent, RequestProtocol, Requests};
+use crate::direct::DeliveryStatus;
+use crate::error::{NetError, Result};
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport};
+use libp2p::swarm::FromSwarm;
+use libp2p::{PeerId, StreamProtocol};
+use prost::Message;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::task::Context;
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+use tracing::{debug, warn};
+use umbra_crypto::cell::{cell_command, new_cell};
+use umbra_crypto::onion::{self, HopKeys, OnionCreate, OnionPublicKey};
+use umbra_crypto::HybridKem;
+use umbra_wire::circuit::{
+    cell, relay_command, Cell, CellAck, Created, Deliver, Delivered, Destroy, Extend, Extended, RelayCommand,
+    Sealed,
+};
+use umbra_wire::error::WireError;
+use umbra_wire::sphinx::{mix_keypair, Hop, Processed, ReplayCache, SphinxPacket, MAX_DELAY};
+use zeroize::Zeroizing;
+
+/// Stream protocol for circuit cells
+pub const CIRCUIT_PROTOCOL: StreamProtocol = StreamProtocol::new("/umbra/circuit/1");
+
+/// Hops in the circuits `P2PNode` routes direct messages over
+pub const CIRCUIT_HOPS: usize = 3;
+
+/// Relays kept as first hops for all our circuits
+pub const ENTRY_GUARDS: usize = 3;
+
+/// Mixes a Sphinx packet goes through before reaching its recipient
+pub const MIX_HOPS: usize = 3;
+
+/// Mean of the exponentially distributed delay each mix holds a packet for
+pub const MIX_DELAY: Duration = Duration::from_millis(500);
+
+/// How long a mix key is used for new packets; the previous one is still accepted for as long again
+pub const MIX_KEY_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Packets a mix holds at once; more are dropped
+pub const MAX_HELD: usize = 1024;
+
+/// Replay tags kept per mix key; reaching it rotates the key early
+pub const MAX_REPLAY_TAGS: usize = 100_000;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events emitted by the circuit protocol
+#[derive(Debug)]
+pub enum CircuitEvent {
+    /// Every hop of `circuit` answered; it can carry messages
+    Built { circuit: u64 },
+    /// `circuit` failed to build or was torn down
+    Closed { circuit: u64, reason: String },
+    /// The exit's delivery result for a message sent over `circuit`
+    Delivered {
+        circuit: u64,
+        message_id: u64,
+        status: DeliveryStatus,
+    },
+    /// An exit handed us a message sealed to our onion key, or a mix packet for us
+    /// arrived (encoded `EncryptedMessage`)
+    Received { data: Vec<u8> },
+    /// The first mix refused a packet we sent with `send_mix`
+    MixFailed { message_id: u64, recipient: PeerId, reason: String },
+    /// Our mix key changed; a published descriptor needs republishing
+    MixKeyRotated,
+}
+
+/// One of our own circuits
 pub struct Circuit {
-    pub hops: Vec<String>,
+    id: u64,
+    path: Vec<(PeerId, OnionPublicKey)>,
+    hops: Vec<HopKeys>,
+    /// Keys for the hop being created or extended to
+    pending: Option<HopKeys>,
+}
+
+impl Circuit {
+    /// Circuit id on the link to the first hop
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Relays in order, first hop first
+    pub fn path(&self) -> Vec<PeerId> {
+        self.path.iter().map(|(peer, _)| *peer).collect()
+    }
+
+    pub fn is_built(&self) -> bool {
+        self.pending.is_none() && self.hops.len() == self.path.len()
+    }
+
+    fn first_hop(&self) -> PeerId {
+        self.path[0].0
+    }
+
+    /// Onion-encrypt `command` for the current last hop
+    fn wrap(&mut self, command: &RelayCommand) -> Result<Vec<u8>> {
+        let mut cell = new_cell(&command.encode_to_vec()).map_err(|e| NetError::InvalidMessage(e.to_string()))?;
+        for hop in self.hops.iter_mut().rev() {
+            cell = hop.wrap_cell(&cell).map_err(|e| NetError::Crypto(e.to_string()))?;
+        }
+        Ok(cell)
+    }
+
+    /// Peel a cell coming back from the current last hop
+    fn unwrap(&mut self, cell: &[u8]) -> Result<RelayCommand> {
+        let mut cell = cell.to_vec();
+        for hop in &mut self.hops {
+            cell = hop.peel_cell(&cell).map_err(|e| NetError::Crypto(e.to_string()))?;
+        }
+        let command = cell_command(&cell).map_err(|e| NetError::InvalidMessage(e.to_string()))?;
+        RelayCommand::decode_from_bytes(command).map_err(|e| NetError::InvalidMessage(e.to_string()))
+    }
 }
 
-impl Default for Circuit {
-    fn default() -> Self {
-        Self::new()
+/// A circuit we relay for someone, keyed by the link (previous peer, circuit id) it came in on
+struct RelayedCircuit {
+    keys: HopKeys,
+    /// Link to the next hop, once extended
+    next: Option<(PeerId, u64)>,
+}
+
+/// What an outbound cell was for, to act on its ack
+enum Outbound {
+    /// Client: creating the first hop of one of our circuits
+    Create { circuit: u64 },
+    /// Client: a relay cell on one of our circuits
+    Forward { circuit: u64 },
+    /// Relay: extending the circuit from `from` with a new link `to`
+    Extend { from: (PeerId, u64), to: (PeerId, u64) },
+    /// Exit: handing a sealed message to its recipient
+    Deliver { from: (PeerId, u64), message_id: u64 },
+    /// Relay: passing a cell of the circuit from `from` along
+    Relay { from: (PeerId, u64) },
+    /// A mix packet: ours (message id, recipient) or one we're forwarding
+    Mix { ours: Option<(u64, PeerId)> },
+    Destroy,
+}
+
+/// X25519 key Sphinx packets are built to, and the tags of those we processed under it
+struct MixKey {
+    secret: Zeroizing<[u8; 32]>,
+    public: [u8; 32],
+    replays: ReplayCache,
+    /// When it stops being used for new packets
+    expires: Instant,
+}
+
+impl MixKey {
+    fn generate() -> Self {
+        let (secret, public) = mix_keypair();
+        Self {
+            secret,
+            public,
+            replays: ReplayCache::new(),
+            expires: Instant::now() + MIX_KEY_LIFETIME,
+        }
+    }
+
+    fn process(&mut self, packet: &SphinxPacket) -> std::result::Result<Processed, WireError> {
+        packet.process(&self.secret, &mut self.replays)
     }
 }
 
-impl Circuit {
-    pub fn new() -> Self {
-        Self { hops: vec![] }
+/// Mix packet waiting out its delay
+struct Held {
+    release_at: Instant,
+    next_hop: PeerId,
+    packet: Vec<u8>,
+}
+
+/// Client side of our own circuits, relay side of other peers' (once enabled), and
+/// receiving end of messages sealed to our onion key
+pub struct CircuitBehaviour {
+    requests: Requests,
+    onion_key: HybridKem,
+    onion_public: OnionPublicKey,
+    relaying: bool,
+    mixing: bool,
+    mix_key: MixKey,
+    /// Packets built just before a rotation are still on their way
+    previous_mix_key: Option<MixKey>,
+    /// Fires when the mix key is due to rotate
+    rotation_timer: Option<Pin<Box<Sleep>>>,
+    held: Vec<Held>,
+    /// Fires when the earliest held packet is due
+    release_timer: Option<Pin<Box<Sleep>>>,
+    circuits: HashMap<u64, Circuit>,
+    relayed: HashMap<(PeerId, u64), RelayedCircuit>,
+    /// Outgoing links of relayed circuits, back to the link they came in on
+    links: HashMap<(PeerId, u64), (PeerId, u64)>,
+    in_flight: HashMap<OutboundRequestId, Outbound>,
+    pending_events: VecDeque<CircuitEvent>,
+}
+
+fn accepted() -> CellAck {
+    CellAck::default()
+}
+
+fn rejected(error: impl Into<String>) -> CellAck {
+    CellAck { error: error.into(), created: None }
+}
+
+impl CircuitBehaviour {
+    pub fn new() -> Result<Self> {
+        Self::with_onion_key(HybridKem::generate().map_err(|e| NetError::Crypto(e.to_string()))?)
+    }
+
+    /// Build circuits to a persisted onion key, so published descriptors stay valid across restarts
+    pub fn with_onion_key(onion_key: HybridKem) -> Result<Self> {
+        let onion_public = OnionPublicKey::of(&onion_key).map_err(|e| NetError::Crypto(e.to_string()))?;
+        Ok(Self {
+            requests: request_response::Behaviour::with_codec(
+                LengthPrefixedCodec::default(),
+                [(CIRCUIT_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            onion_key,
+            onion_public,
+            relaying: false,
+            mixing: false,
+            mix_key: MixKey::generate(),
+            previous_mix_key: None,
+            rotation_timer: None,
+            held: Vec::new(),
+            release_timer: None,
+            circuits: HashMap::new(),
+            relayed: HashMap::new(),
+            links: HashMap::new(),
+            in_flight: HashMap::new(),
+            pending_events: VecDeque::new(),
+        })
+    }
+
+    /// Our onion key, to publish in our relay descriptor
+    pub fn onion_key(&self) -> &OnionPublicKey {
+        &self.onion_public
+    }
+
+    /// Our current mix key, to publish in our relay descriptor
+    pub fn mix_key(&self) -> [u8; 32] {
+        self.mix_key.public
+    }
+
+    /// Start a new mix key epoch; the old key keeps working until the next rotation
+    pub fn rotate_mix_key(&mut self) {
+        self.previous_mix_key = Some(std::mem::replace(&mut self.mix_key, MixKey::generate()));
+        self.rotation_timer = None;
+        self.pending_events.push_back(CircuitEvent::MixKeyRotated);
+    }
+
+    /// Accept and extend circuits for other peers
+    pub fn enable_relay(&mut self) {
+        self.relaying = true;
+    }
+
+    /// Forward Sphinx packets for other peers
+    pub fn enable_mix(&mut self) {
+        self.mixing = true;
+    }
+
+    pub fn is_mixing(&self) -> bool {
+        self.mixing
+    }
+
+    pub fn circuit(&self, circuit: u64) -> Option<&Circuit> {
+        self.circuits.get(&circuit)
+    }
+
+    /// Start building a circuit through `path` (first hop first); `Built` or `Closed` follows
+    pub fn build(&mut self, path: Vec<(PeerId, OnionPublicKey)>) -> Result<u64> {
+        let Some((first_hop, onion_key)) = path.first() else {
+            return Err(NetError::CircuitBuild("Empty path".to_string()));
+        };
+        let (create, keys) = HopKeys::create(onion_key).map_err(|e| NetError::CircuitBuild(e.to_string()))?;
+
+        let id = rand::random();
+        let first_hop = *first_hop;
+        self.circuits.insert(id, Circuit { id, path, hops: Vec::new(), pending: Some(keys) });
+        let cell = Cell { circuit_id: id, body: Some(cell::Body::Create((&create).into())) };
+        self.send_cell(first_hop, cell, Outbound::Create { circuit: id });
+        Ok(id)
+    }
+
+    /// Send an encoded `EncryptedMessage` to `recipient` through the exit of `circuit`;
+    /// the exit's result comes back as `Delivered { message_id, .. }`
+    pub fn send(
+        &mut self,
+        circuit: u64,
+        message_id: u64,
+        recipient: PeerId,
+        recipient_key: &OnionPublicKey,
+        message: &[u8],
+    ) -> Result<()> {
+        let Some(entry) = self.circuits.get_mut(&circuit).filter(|c| c.is_built()) else {
+            return Err(NetError::CircuitBuild(format!("Circuit {} is not built", circuit)));
+        };
+        let (create, ciphertext) = onion::seal_to(recipient_key, message).map_err(|e| NetError::Crypto(e.to_string()))?;
+        let command = RelayCommand {
+            command: Some(relay_command::Command::Deliver(Deliver {
+                message_id,
+                recipient: recipient.to_bytes(),
+                sealed: Some(Sealed { create: Some((&create).into()), ciphertext }),
+            })),
+        };
+        let cell = Cell { circuit_id: circuit, body: Some(cell::Body::Relay(entry.wrap(&command)?)) };
+        let first_hop = entry.first_hop();
+        self.send_cell(first_hop, cell, Outbound::Forward { circuit });
+        Ok(())
+    }
+
+    /// Send an encoded `EncryptedMessage` as a Sphinx packet through `route` (mixes first,
+    /// recipient last), with a random delay at every mix. Nothing comes back on success;
+    /// `MixFailed { message_id, .. }` follows if the first mix refuses it.
+    pub fn send_mix(&mut self, message_id: u64, route: &[(PeerId, [u8; 32])], message: &[u8]) -> Result<()> {
+        let (Some((first_hop, _)), Some((recipient, _))) = (route.first(), route.last()) else {
+            return Err(NetError::CircuitBuild("Empty route".to_string()));
+        };
+        let hops: Vec<Hop> = route.iter()
+            .map(|(peer, key)| Hop { address: peer.to_bytes(), key: *key, delay: mix_delay() })
+            .collect();
+        let packet = SphinxPacket::new(&hops, message).map_err(|e| NetError::InvalidMessage(e.to_string()))?;
+        let cell = Cell { circuit_id: 0, body: Some(cell::Body::Mix(packet.to_bytes())) };
+        self.send_cell(*first_hop, cell, Outbound::Mix { ours: Some((message_id, *recipient)) });
+        Ok(())
+    }
+
+    /// Tear down one of our circuits
+    pub fn destroy(&mut self, circuit: u64) {
+        if let Some(entry) = self.circuits.remove(&circuit) {
+            self.send_destroy(entry.first_hop(), circuit, "Closed by client");
+        }
+    }
+
+    fn send_cell(&mut self, peer: PeerId, cell: Cell, outbound: Outbound) {
+        let request_id = self.requests.send_request(&peer, cell.encode_to_vec());
+        self.in_flight.insert(request_id, outbound);
+    }
+
+    fn send_destroy(&mut self, peer: PeerId, circuit_id: u64, reason: &str) {
+        let cell = Cell {
+            circuit_id,
+            body: Some(cell::Body::Destroy(Destroy { reason: reason.to_string() })),
+        };
+        self.send_cell(peer, cell, Outbound::Destroy);
+    }
+
+    fn close(&mut self, circuit: u64, reason: String) {
+        if let Some(entry) = self.circuits.remove(&circuit) {
+            debug!("Circuit {} closed: {}", circuit, reason);
+            if !entry.hops.is_empty() {
+                self.send_destroy(entry.first_hop(), circuit, &reason);
+            }
+            self.pending_events.push_back(CircuitEvent::Closed { circuit, reason });
+        }
+    }
+
+    /// Drop a relayed circuit and tell both neighbours
+    fn teardown(&mut self, from: (PeerId, u64), reason: &str) {
+        if let Some(entry) = self.relayed.remove(&from) {
+            debug!("Tearing down relayed circuit from {}: {}", from.0, reason);
+            self.send_destroy(from.0, from.1, reason);
+            if let Some(next) = entry.next {
+                self.links.remove(&next);
+                self.send_destroy(next.0, next.1, reason);
+            }
+        }
+    }
+
+    /// Add our layer to a command going back toward the client of a relayed circuit
+    fn send_backward(&mut self, from: (PeerId, u64), command: RelayCommand) {
+        let Some(entry) = self.relayed.get_mut(&from) else {
+            return;
+        };
+        let sealed = new_cell(&command.encode_to_vec()).and_then(|cell| entry.keys.wrap_cell(&cell));
+        match sealed {
+            Ok(data) => {
+                let cell = Cell { circuit_id: from.1, body: Some(cell::Body::Relay(data)) };
+                self.send_cell(from.0, cell, Outbound::Relay { from });
+            }
+            Err(e) => warn!("Failed to seal relay cell: {}", e),
+        }
+    }
+
+    /// Extend one of our circuits to its next hop, or report it built
+    fn extend(&mut self, circuit: u64) {
+        let Some(entry) = self.circuits.get_mut(&circuit) else {
+            return;
+        };
+        let Some((next_hop, onion_key)) = entry.path.get(entry.hops.len()).cloned() else {
+            self.pending_events.push_back(CircuitEvent::Built { circuit });
+            return;
+        };
+
+        let (create, keys) = match HopKeys::create(&onion_key) {
+            Ok(created) => created,
+            Err(e) => return self.close(circuit, format!("Extend failed: {}", e)),
+        };
+        let command = RelayCommand {
+            command: Some(relay_command::Command::Extend(Extend {
+                next_hop: next_hop.to_bytes(),
+                create: Some((&create).into()),
+            })),
+        };
+        let data = match entry.wrap(&command) {
+            Ok(data) => data,
+            Err(e) => return self.close(circuit, format!("Extend failed: {}", e)),
+        };
+        entry.pending = Some(keys);
+        let first_hop = entry.first_hop();
+        let cell = Cell { circuit_id: circuit, body: Some(cell::Body::Relay(data)) };
+        self.send_cell(first_hop, cell, Outbound::Forward { circuit });
+    }
+
+    /// A new hop proved it holds its onion key
+    fn on_hop_created(&mut self, circuit: u64, confirmation: &[u8]) {
+        let Some(entry) = self.circuits.get_mut(&circuit) else {
+            return;
+        };
+        let Some(keys) = entry.pending.take() else {
+            return self.close(circuit, "Unexpected hop confirmation".to_string());
+        };
+        if keys.verify_confirmation(confirmation).is_err() {
+            let hop = entry.hops.len() + 1;
+            return self.close(circuit, format!("Hop {} failed key confirmation", hop));
+        }
+        entry.hops.push(keys);
+        self.extend(circuit);
+    }
+
+    /// Serve one inbound cell from `peer`
+    fn handle_cell(&mut self, peer: PeerId, request: &[u8]) -> CellAck {
+        let Ok(cell) = Cell::decode_from_bytes(request) else {
+            return rejected("Malformed cell");
+        };
+        let id = cell.circuit_id;
+        match cell.body {
+            Some(cell::Body::Create(create)) => self.on_create(peer, id, &create),
+            Some(cell::Body::Relay(data)) => self.on_relay(peer, id, &data),
+            Some(cell::Body::Destroy(destroy)) => {
+                self.on_destroy(peer, id, destroy.reason);
+                accepted()
+            }
+            Some(cell::Body::Deliver(sealed)) => self.on_sealed(&sealed),
+            Some(cell::Body::Mix(data)) => self.on_mix(&data),
+            None => rejected("Empty cell"),
+        }
+    }
+
+    fn on_create(&mut self, peer: PeerId, id: u64, create: &umbra_wire::circuit::Create) -> CellAck {
+        if !self.relaying {
+            return rejected("Not relaying");
+        }
+        if self.relayed.contains_key(&(peer, id)) {
+            return rejected("Circuit id in use");
+        }
+        let Ok(create) = OnionCreate::try_from(create) else {
+            return rejected("Malformed create");
+        };
+        match HopKeys::accept(&self.onion_key, &create) {
+            Ok(keys) => {
+                let confirmation = keys.confirmation().to_vec();
+                self.relayed.insert((peer, id), RelayedCircuit { keys, next: None });
+                CellAck { error: String::new(), created: Some(Created { confirmation }) }
+            }
+            Err(e) => rejected(format!("Create failed: {}", e)),
+        }
+    }
+
+    fn on_relay(&mut self, peer: PeerId, id: u64, data: &[u8]) -> CellAck {
+        // Coming back from the next hop of a circuit we relay: add our layer
+        if let Some(&from) = self.links.get(&(peer, id)) {
+            let Some(entry) = self.relayed.get_mut(&from) else {
+                return rejected("Unknown circuit");
+            };
+            return match entry.keys.wrap_cell(data) {
+                Ok(data) => {
+                    let cell = Cell { circuit_id: from.1, body: Some(cell::Body::Relay(data)) };
+                    self.send_cell(from.0, cell, Outbound::Relay { from });
+                    accepted()
+                }
+                Err(e) => rejected(format!("Seal failed: {}", e)),
+            };
+        }
+
+        // Coming back on one of our own circuits
+        if self.circuits.get(&id).is_some_and(|c| c.first_hop() == peer) {
+            self.on_backward(id, data);
+            return accepted();
+        }
+
+        // Going forward on a circuit we relay: peel our layer
+        let from = (peer, id);
+        let Some(entry) = self.relayed.get_mut(&from) else {
+            return rejected("Unknown circuit");
+        };
+        let data = match entry.keys.peel_cell(data) {
+            Ok(data) => data,
+            Err(_) => return rejected("Bad onion layer"),
+        };
+        if let Some((next, next_id)) = entry.next {
+            let cell = Cell { circuit_id: next_id, body: Some(cell::Body::Relay(data)) };
+            self.send_cell(next, cell, Outbound::Relay { from });
+            return accepted();
+        }
+
+        // We're the last hop
+        let command = cell_command(&data).ok().and_then(|c| RelayCommand::decode_from_bytes(c).ok());
+        match command.map(|c| c.command) {
+            Some(Some(relay_command::Command::Extend(extend))) => self.on_extend(from, extend),
+            Some(Some(relay_command::Command::Deliver(deliver))) => self.on_deliver(from, deliver),
+            Some(_) => rejected("Unexpected relay command"),
+            None => rejected("Malformed relay command"),
+        }
+    }
+
+    fn on_extend(&mut self, from: (PeerId, u64), extend: Extend) -> CellAck {
+        let (Ok(next_hop), Some(create)) = (PeerId::from_bytes(&extend.next_hop), extend.create) else {
+            return rejected("Malformed extend");
+        };
+        let to = (next_hop, rand::random());
+        let cell = Cell { circuit_id: to.1, body: Some(cell::Body::Create(create)) };
+        self.send_cell(next_hop, cell, Outbound::Extend { from, to });
+        accepted()
+    }
+
+    fn on_deliver(&mut self, from: (PeerId, u64), deliver: Deliver) -> CellAck {
+        let (Ok(recipient), Some(sealed)) = (PeerId::from_bytes(&deliver.recipient), deliver.sealed) else {
+            return rejected("Malformed deliver");
+        };
+        let cell = Cell { circuit_id: 0, body: Some(cell::Body::Deliver(sealed)) };
+        self.send_cell(recipient, cell, Outbound::Deliver { from, message_id: deliver.message_id });
+        accepted()
+    }
+
+    /// A message an exit delivered to us
+    fn on_sealed(&mut self, sealed: &Sealed) -> CellAck {
+        let Some(Ok(create)) = sealed.create.as_ref().map(OnionCreate::try_from) else {
+            return rejected("Malformed sealed message");
+        };
+        match onion::open_sealed(&self.onion_key, &create, &sealed.ciphertext) {
+            Ok(data) => {
+                self.pending_events.push_back(CircuitEvent::Received { data: data.to_vec() });
+                accepted()
+            }
+            Err(_) => rejected("Not sealed to us"),
+        }
+    }
+
+    /// Strip our layer of a Sphinx packet: hold it for the next mix, or take the message
+    fn on_mix(&mut self, data: &[u8]) -> CellAck {
+        let Ok(packet) = SphinxPacket::from_bytes(data) else {
+            return rejected("Malformed mix packet");
+        };
+        let mut result = self.mix_key.process(&packet);
+        if let (Err(WireError::PacketMac), Some(previous)) = (&result, self.previous_mix_key.as_mut()) {
+            result = previous.process(&packet);
+        }
+        if self.mix_key.replays.len() >= MAX_REPLAY_TAGS {
+            self.rotate_mix_key();
+        }
+        match result {
+            Ok(Processed::Forward { next_hop, delay, packet }) => {
+                if !self.mixing {
+                    return rejected("Not mixing");
+                }
+                let Ok(next_hop) = PeerId::from_bytes(&next_hop) else {
+                    return rejected("Malformed next hop");
+                };
+                if self.held.len() >= MAX_HELD {
+                    return rejected("Mix queue full");
+                }
+                self.held.push(Held { release_at: Instant::now() + delay, next_hop, packet: packet.to_bytes() });
+                accepted()
+            }
+            Ok(Processed::Deliver { message }) => {
+                self.pending_events.push_back(CircuitEvent::Received { data: message });
+                accepted()
+            }
+            Err(e) => rejected(format!("Mix packet refused: {}", e)),
+        }
+    }
+
+    /// Send on the held packets that are due, and arm the timer for the rest
+    fn release_held(&mut self, cx: &mut std::task::Context) {
+        loop {
+            let now = Instant::now();
+            let (due, held): (Vec<_>, Vec<_>) = std::mem::take(&mut self.held)
+                .into_iter()
+                .partition(|h| h.release_at <= now);
+            self.held = held;
+            for held in due {
+                let cell = Cell { circuit_id: 0, body: Some(cell::Body::Mix(held.packet)) };
+                self.send_cell(held.next_hop, cell, Outbound::Mix { ours: None });
+            }
+
+            let Some(next) = self.held.iter().map(|h| h.release_at).min() else {
+                self.release_timer = None;
+                return;
+            };
+            let timer = self.release_timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(next)));
+            timer.as_mut().reset(next);
+            if std::future::Future::poll(timer.as_mut(), cx).is_pending() {
+                return;
+            }
+        }
+    }
+
+    /// Rotate the mix key when it expires
+    fn poll_rotation(&mut self, cx: &mut std::task::Context) {
+        loop {
+            let expires = self.mix_key.expires;
+            let timer = self.rotation_timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(expires)));
+            if std::future::Future::poll(timer.as_mut(), cx).is_pending() {
+                return;
+            }
+            self.rotate_mix_key();
+        }
+    }
+
+    fn on_backward(&mut self, circuit: u64, data: &[u8]) {
+        let Some(entry) = self.circuits.get_mut(&circuit) else {
+            return;
+        };
+        match entry.unwrap(data).map(|c| c.command) {
+            Ok(Some(relay_command::Command::Extended(extended))) => {
+                self.on_hop_created(circuit, &extended.confirmation);
+            }
+            Ok(Some(relay_command::Command::Delivered(delivered))) => {
+                let status = if delivered.error.is_empty() {
+                    DeliveryStatus::Delivered
+                } else {
+                    DeliveryStatus::Failed(delivered.error)
+                };
+                self.pending_events.push_back(CircuitEvent::Delivered {
+                    circuit,
+                    message_id: delivered.message_id,
+                    status,
+                });
+            }
+            Ok(_) => warn!("Unexpected relay command on circuit {}", circuit),
+            Err(e) => self.close(circuit, format!("Undecryptable cell: {}", e)),
+        }
+    }
+
+    fn on_destroy(&mut self, peer: PeerId, id: u64, reason: String) {
+        if let Some(from) = self.links.remove(&(peer, id)) {
+            // From the next hop: pass it back toward the client
+            if self.relayed.remove(&from).is_some() {
+                self.send_destroy(from.0, from.1, &reason);
+            }
+        } else if self.circuits.get(&id).is_some_and(|c| c.first_hop() == peer) {
+            if let Some(entry) = self.circuits.remove(&id) {
+                debug!("Circuit {} through {:?} destroyed: {}", id, entry.path(), reason);
+            }
+            self.pending_events.push_back(CircuitEvent::Closed { circuit: id, reason });
+        } else if let Some(entry) = self.relayed.remove(&(peer, id)) {
+            // From the client side: pass it on
+            if let Some(next) = entry.next {
+                self.links.remove(&next);
+                self.send_destroy(next.0, next.1, &reason);
+            }
+        }
+    }
+
+    fn on_ack(&mut self, outbound: Outbound, result: std::result::Result<CellAck, String>) {
+        let result = result.and_then(|ack| if ack.error.is_empty() { Ok(ack) } else { Err(ack.error) });
+        match outbound {
+            Outbound::Create { circuit } => match result {
+                Ok(CellAck { created: Some(created), .. }) => self.on_hop_created(circuit, &created.confirmation),
+                Ok(_) => self.close(circuit, "Create not answered".to_string()),
+                Err(e) => self.close(circuit, format!("Create failed: {}", e)),
+            },
+            Outbound::Forward { circuit } => {
+                if let Err(e) = result {
+                    self.close(circuit, format!("First hop failed: {}", e));
+                }
+            }
+            Outbound::Extend { from, to } => match result {
+                Ok(CellAck { created: Some(created), .. }) => {
+                    let Some(entry) = self.relayed.get_mut(&from) else {
+                        return self.send_destroy(to.0, to.1, "Circuit gone");
+                    };
+                    entry.next = Some(to);
+                    self.links.insert(to, from);
+                    let command = RelayCommand {
+                        command: Some(relay_command::Command::Extended(Extended { confirmation: created.confirmation })),
+                    };
+                    self.send_backward(from, command);
+                }
+                Ok(_) => self.teardown(from, "Extend not answered"),
+                Err(e) => self.teardown(from, &format!("Extend failed: {}", e)),
+            },
+            Outbound::Deliver { from, message_id } => {
+                let error = result.err().unwrap_or_default();
+                let command = RelayCommand {
+                    command: Some(relay_command::Command::Delivered(Delivered { message_id, error })),
+                };
+                self.send_backward(from, command);
+            }
+            Outbound::Relay { from } => {
+                if let Err(e) = result {
+                    self.teardown(from, &format!("Link failed: {}", e));
+                }
+            }
+            Outbound::Mix { ours } => match (result, ours) {
+                (Err(reason), Some((message_id, recipient))) => {
+                    self.pending_events.push_back(CircuitEvent::MixFailed { message_id, recipient, reason });
+                }
+                (Err(e), None) => debug!("Next mix refused a packet: {}", e),
+                (Ok(_), _) => {}
+            },
+            Outbound::Destroy => {}
+        }
+    }
+
+    /// A neighbour went away: every circuit through it is dead
+    fn on_peer_gone(&mut self, peer: PeerId) {
+        let relayed: Vec<(PeerId, u64)> = self.relayed.iter()
+            .filter(|(from, entry)| from.0 == peer || entry.next.is_some_and(|next| next.0 == peer))
+            .map(|(from, _)| *from)
+            .collect();
+        for from in relayed {
+            self.teardown(from, "Neighbour disconnected");
+        }
+
+        let circuits: Vec<u64> = self.circuits.values()
+            .filter(|c| c.first_hop() == peer)
+            .map(|c| c.id)
+            .collect();
+        for circuit in circuits {
+            self.circuits.remove(&circuit);
+            self.pending_events.push_back(CircuitEvent::Closed { circuit, reason: "First hop disconnected".to_string() });
+        }
+    }
+
+    fn on_request_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    let ack = self.handle_cell(peer, &request);
+                    if !ack.error.is_empty() {
+                        debug!("Rejected cell from {}: {}", peer, ack.error);
+                    }
+                    let _ = self.requests.send_response(channel, ack.encode_to_vec());
+                }
+                request_response::Message::Response { request_id, response } => {
+                    if let Some(outbound) = self.in_flight.remove(&request_id) {
+                        let ack = CellAck::decode(response.as_slice()).map_err(|e| format!("Invalid ack: {}", e));
+                        self.on_ack(outbound, ack);
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure { request_id, error, .. } => {
+                if let Some(outbound) = self.in_flight.remove(&request_id) {
+                    self.on_ack(outbound, Err(error.to_string()));
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Inbound circuit stream from {} failed: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+}
+
+// Runs on request_response over /umbra/circuit/1
+impl RequestProtocol for CircuitBehaviour {
+    type Event = CircuitEvent;
+
+    fn requests(&mut self) -> &mut Requests {
+        &mut self.requests
+    }
+
+    fn on_event(&mut self, event: RequestEvent) {
+        self.on_request_event(event);
+    }
+
+    fn pop_event(&mut self) -> Option<CircuitEvent> {
+        self.pending_events.pop_front()
+    }
+
+    fn poll_tasks(&mut self, cx: &mut Context) {
+        self.release_held(cx);
+        self.poll_rotation(cx);
+    }
+
+    fn on_swarm_event(&mut self, event: &FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            if closed.remaining_established == 0 {
+                self.on_peer_gone(closed.peer_id);
+            }
+        }
+    }
+}
+
+/// Exponentially distributed, so how long a packet has waited says nothing about when it leaves;
+/// cut off at the longest delay mixes accept
+fn mix_delay() -> Duration {
+    let uniform: f64 = rand::random();
+    MIX_DELAY.mul_f64(-(1.0 - uniform).ln()).min(MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_cell(id: u64, create: &OnionCreate) -> Vec<u8> {
+        Cell { circuit_id: id, body: Some(cell::Body::Create(create.into())) }.encode_to_vec()
+    }
+
+    fn relay() -> CircuitBehaviour {
+        let mut relay = CircuitBehaviour::new().unwrap();
+        relay.enable_relay();
+        relay
+    }
+
+    #[test]
+    fn test_create_requires_relaying() {
+        let mut node = CircuitBehaviour::new().unwrap();
+        let (create, _) = HopKeys::create(node.onion_key()).unwrap();
+        let ack = node.handle_cell(PeerId::random(), &create_cell(1, &create));
+        assert_eq!(ack.error, "Not relaying");
+        assert!(ack.created.is_none());
+    }
+
+    #[test]
+    fn test_relay_peels_one_layer() {
+        let mut relay = relay();
+        let client = PeerId::random();
+        let (create, keys) = HopKeys::create(relay.onion_key()).unwrap();
+
+        let ack = relay.handle_cell(client, &create_cell(7, &create));
+        keys.verify_confirmation(&ack.created.unwrap().confirmation).unwrap();
+
+        // The same link and id can't be created twice
+        assert!(!relay.handle_cell(client, &create_cell(7, &create)).error.is_empty());
+
+        let mut circuit = Circuit {
+            id: 7,
+            path: vec![(PeerId::random(), relay.onion_key().clone())],
+            hops: vec![keys],
+            pending: None,
+        };
+        let command = RelayCommand {
+            command: Some(relay_command::Command::Extend(Extend {
+                next_hop: PeerId::random().to_bytes(),
+                create: Some((&create).into()),
+            })),
+        };
+        let cell = Cell { circuit_id: 7, body: Some(cell::Body::Relay(circuit.wrap(&command).unwrap())) };
+        assert!(relay.handle_cell(client, &cell.encode_to_vec()).error.is_empty());
+        assert!(matches!(relay.in_flight.values().next(), Some(Outbound::Extend { .. })));
+
+        // The same cell again is a replay
+        assert_eq!(relay.handle_cell(client, &cell.encode_to_vec()).error, "Bad onion layer");
+
+        // Another peer can't use the circuit, nor can a layer under the wrong key
+        assert_eq!(relay.handle_cell(PeerId::random(), &cell.encode_to_vec()).error, "Unknown circuit");
+        let forged = Cell { circuit_id: 7, body: Some(cell::Body::Relay(vec![0; 64])) };
+        assert_eq!(relay.handle_cell(client, &forged.encode_to_vec()).error, "Bad onion layer");
+    }
+
+    #[test]
+    fn test_sealed_delivery_only_for_recipient() {
+        let mut recipient = CircuitBehaviour::new().unwrap();
+        let mut other = CircuitBehaviour::new().unwrap();
+        let (create, ciphertext) = onion::seal_to(recipient.onion_key(), b"sealed").unwrap();
+        let cell = Cell {
+            circuit_id: 0,
+            body: Some(cell::Body::Deliver(Sealed { create: Some((&create).into()), ciphertext })),
+        }
+        .encode_to_vec();
+
+        assert_eq!(other.handle_cell(PeerId::random(), &cell).error, "Not sealed to us");
+        assert!(recipient.handle_cell(PeerId::random(), &cell).error.is_empty());
+        assert!(matches!(
+            recipient.pending_events.pop_front(),
+            Some(CircuitEvent::Received { data }) if data == b"sealed"
+        ));
+    }
+
+    #[test]
+    fn test_mix_forwards_and_delivers() {
+        let mut mix = relay();
+        mix.enable_mix();
+        let mut recipient = CircuitBehaviour::new().unwrap();
+        let mix_peer = PeerId::random();
+        let recipient_peer = PeerId::random();
+
+        let route = vec![(mix_peer, mix.mix_key()), (recipient_peer, recipient.mix_key())];
+        mix.send_mix(1, &route, b"mixed").unwrap();
+        let Some(Outbound::Mix { ours: Some((1, to)) }) = mix.in_flight.values().next() else {
+            panic!("packet should be sent to the first mix");
+        };
+        assert_eq!(*to, recipient_peer);
+
+        let hops = [Hop { address: mix_peer.to_bytes(), key: mix.mix_key(), delay: Duration::ZERO },
+            Hop { address: recipient_peer.to_bytes(), key: recipient.mix_key(), delay: Duration::ZERO }];
+        let packet = SphinxPacket::new(&hops, b"mixed").unwrap().to_bytes();
+        let cell = Cell { circuit_id: 0, body: Some(cell::Body::Mix(packet)) }.encode_to_vec();
+
+        // The mix holds it for the recipient, and won't take the same packet twice
+        assert!(mix.handle_cell(PeerId::random(), &cell).error.is_empty());
+        assert_eq!(mix.held.len(), 1);
+        assert_eq!(mix.held[0].next_hop, recipient_peer);
+        assert!(mix.handle_cell(PeerId::random(), &cell).error.contains("Replayed"));
+
+        let forwarded = Cell { circuit_id: 0, body: Some(cell::Body::Mix(mix.held[0].packet.clone())) };
+        assert!(recipient.handle_cell(mix_peer, &forwarded.encode_to_vec()).error.is_empty());
+        assert!(matches!(
+            recipient.pending_events.pop_front(),
+            Some(CircuitEvent::Received { data }) if data == b"mixed"
+        ));
+    }
+
+    #[test]
+    fn test_forwarding_requires_mixing() {
+        let mut node = relay();
+        let hops = [Hop { address: vec![], key: node.mix_key(), delay: Duration::ZERO },
+            Hop { address: PeerId::random().to_bytes(), key: [9; 32], delay: Duration::ZERO }];
+        let packet = SphinxPacket::new(&hops, b"m").unwrap().to_bytes();
+        let cell = Cell { circuit_id: 0, body: Some(cell::Body::Mix(packet)) }.encode_to_vec();
+        assert_eq!(node.handle_cell(PeerId::random(), &cell).error, "Not mixing");
+        assert!(node.held.is_empty());
+    }
+
+    fn mix_cell(hops: &[Hop]) -> Vec<u8> {
+        let packet = SphinxPacket::new(hops, b"m").unwrap().to_bytes();
+        Cell { circuit_id: 0, body: Some(cell::Body::Mix(packet)) }.encode_to_vec()
+    }
+
+    #[test]
+    fn test_mix_key_rotation() {
+        let mut mix = relay();
+        mix.enable_mix();
+        let hop = |key| [Hop { address: vec![], key, delay: Duration::ZERO },
+            Hop { address: PeerId::random().to_bytes(), key: [9; 32], delay: Duration::ZERO }];
+
+        let old_key = mix.mix_key();
+        let before = mix_cell(&hop(old_key));
+        assert!(mix.handle_cell(PeerId::random(), &before).error.is_empty());
+        mix.rotate_mix_key();
+        assert_ne!(mix.mix_key(), old_key);
+        assert!(matches!(mix.pending_events.pop_front(), Some(CircuitEvent::MixKeyRotated)));
+
+        // Packets built to the previous key still get through, once; the new key's cache starts empty
+        assert!(mix.handle_cell(PeerId::random(), &mix_cell(&hop(old_key))).error.is_empty());
+        assert!(mix.handle_cell(PeerId::random(), &before).error.contains("Replayed"));
+        assert!(mix.mix_key.replays.is_empty());
+        assert!(mix.handle_cell(PeerId::random(), &mix_cell(&hop(mix.mix_key()))).error.is_empty());
+
+        // Two rotations on, the old key and its replay tags are gone
+        mix.rotate_mix_key();
+        assert!(mix.handle_cell(PeerId::random(), &mix_cell(&hop(old_key))).error.contains("refused"));
+    }
+
+    #[test]
+    fn test_mix_queue_bounded() {
+        let mut mix = relay();
+        mix.enable_mix();
+        let hops = [Hop { address: vec![], key: mix.mix_key(), delay: MAX_DELAY },
+            Hop { address: PeerId::random().to_bytes(), key: [9; 32], delay: Duration::ZERO }];
+        for _ in 0..MAX_HELD {
+            assert!(mix.handle_cell(PeerId::random(), &mix_cell(&hops)).error.is_empty());
+        }
+        assert_eq!(mix.handle_cell(PeerId::random(), &mix_cell(&hops)).error, "Mix queue full");
+        assert_eq!(mix.held.len(), MAX_HELD);
     }
 }
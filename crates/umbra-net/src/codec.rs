@@ -0,0 +1,161 @@
+// Length-prefixed byte codec for request_response protocols
+// Frames are a u32 big-endian length followed by the payload (already protobuf-encoded), padded
+// into an umbra_wire Frame so the length only ever shows one of a few bucket sizes
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response;
+use libp2p::StreamProtocol;
+use std::io;
+use umbra_wire::framing::{Frame, MAX_FRAME_PAYLOAD};
+
+/// Upper bound on a single padded frame: the largest bucket, so any payload that pads at
+/// all fits (a hybrid handshake message is ~7 KB)
+pub const DEFAULT_MAX_FRAME_SIZE: usize = MAX_FRAME_PAYLOAD + 4;
+
+/// Raw `Vec<u8>` requests and responses, each sent as one length-prefixed frame
+#[derive(Debug, Clone)]
+pub struct LengthPrefixedCodec {
+    max_frame_size: usize,
+}
+
+impl LengthPrefixedCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    async fn read_frame<T>(&self, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut len_bytes = [0u8; 4];
+        io.read_exact(&mut len_bytes).await?;
+        let len = u32::from_be_bytes(len_bytes) as usize;
+
+        if len > self.max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Frame too large: {} > {}", len, self.max_frame_size),
+            ));
+        }
+
+        let mut buf = vec![0u8; len];
+        io.read_exact(&mut buf).await?;
+        Frame::unpad(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write_frame<T>(&self, io: &mut T, data: &[u8]) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let frame = Frame::pad(data.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if frame.len() > self.max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Frame too large: {} > {}", frame.len(), self.max_frame_size),
+            ));
+        }
+
+        io.write_all(&(frame.len() as u32).to_be_bytes()).await?;
+        io.write_all(&frame).await?;
+        io.close().await
+    }
+}
+
+impl Default for LengthPrefixedCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+#[async_trait]
+impl request_response::Codec for LengthPrefixedCodec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read_frame(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read_frame(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, req: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write_frame(io, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, res: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write_frame(io, &res).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use request_response::Codec;
+
+    const PROTOCOL: StreamProtocol = StreamProtocol::new("/umbra/test/1");
+
+    #[tokio::test]
+    async fn test_frame_roundtrip() {
+        let mut codec = LengthPrefixedCodec::default();
+        let mut buf = Cursor::new(Vec::new());
+        codec.write_request(&PROTOCOL, &mut buf, b"hello".to_vec()).await.unwrap();
+
+        let mut reader = Cursor::new(buf.into_inner());
+        let req = codec.read_request(&PROTOCOL, &mut reader).await.unwrap();
+        assert_eq!(req, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_frames_padded_to_buckets() {
+        let mut codec = LengthPrefixedCodec::default();
+        for len in [1, 600, 7000] {
+            let mut buf = Cursor::new(Vec::new());
+            codec.write_response(&PROTOCOL, &mut buf, vec![1u8; len]).await.unwrap();
+            let wire = buf.into_inner();
+            assert!(umbra_wire::framing::FRAME_BUCKETS.contains(&(wire.len() - 4)));
+
+            let mut reader = Cursor::new(wire);
+            assert_eq!(codec.read_response(&PROTOCOL, &mut reader).await.unwrap().len(), len);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payload_past_32k_bucket() {
+        // Pads into the 128 KiB bucket, which the default limit has to allow
+        let mut codec = LengthPrefixedCodec::default();
+        let mut buf = Cursor::new(Vec::new());
+        codec.write_request(&PROTOCOL, &mut buf, vec![7u8; 40 * 1024]).await.unwrap();
+
+        let mut reader = Cursor::new(buf.into_inner());
+        assert_eq!(codec.read_request(&PROTOCOL, &mut reader).await.unwrap(), vec![7u8; 40 * 1024]);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_rejected() {
+        let mut codec = LengthPrefixedCodec::new(8);
+
+        let mut buf = Cursor::new(Vec::new());
+        assert!(codec.write_request(&PROTOCOL, &mut buf, vec![0u8; 9]).await.is_err());
+
+        // A peer claiming a huge length is refused before we allocate
+        let mut reader = Cursor::new(u32::MAX.to_be_bytes().to_vec());
+        assert!(codec.read_response(&PROTOCOL, &mut reader).await.is_err());
+    }
+}
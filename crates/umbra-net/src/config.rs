@@ -0,0 +1,405 @@
+// Node configuration: what a P2PNode listens on, who it bootstraps from, how gossipsub and
+// connections are tuned, which optional behaviours run and where its identity comes from.
+// Built in code from `NodeConfig::default()` with the `with_*` methods, or loaded from TOML:
+//
+//     listen = ["/ip4/0.0.0.0/udp/4001/quic-v1", "/ip6/::/udp/4001/quic-v1"]
+//     bootstrap = ["/ip4/203.0.113.7/udp/4001/quic-v1/p2p/12D3KooW..."]
+//     tcp = false
+//     idle_timeout_secs = 60
+//     ping_interval_secs = 15
+//     key_file = "/var/lib/umbra/node_keys.bin"   # passphrase from UMBRA_PASSPHRASE
+//
+//     [gossipsub]
+//     heartbeat_ms = 1000
+//     mesh_n = 6
+//
+//     [behaviours]
+//     file = false
+//     relay = true
+//
+// Anything left out keeps its default.
+
+use crate::error::{NetError, Result};
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Where a node's libp2p keypair (and so its PeerId) and its signing identity come from
+#[derive(Clone, Default)]
+pub enum IdentitySource {
+    /// Fresh keys every start
+    #[default]
+    Ephemeral,
+    /// A keypair the caller already holds (the signing identity is still fresh)
+    Keypair(Box<libp2p::identity::Keypair>),
+    /// Both keys from an encrypted key file, created on first start.
+    /// Without a passphrase here, it is read from `UMBRA_PASSPHRASE`.
+    KeyFile {
+        path: PathBuf,
+        passphrase: Option<Zeroizing<String>>,
+    },
+}
+
+impl std::fmt::Debug for IdentitySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentitySource::Ephemeral => write!(f, "Ephemeral"),
+            IdentitySource::Keypair(keypair) => write!(f, "Keypair({})", keypair.public().to_peer_id()),
+            IdentitySource::KeyFile { path, .. } => write!(f, "KeyFile({})", path.display()),
+        }
+    }
+}
+
+/// Gossipsub mesh tuning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipsubSettings {
+    pub heartbeat_interval: Duration,
+    /// Peers we aim to keep in each topic's mesh
+    pub mesh_n: usize,
+    /// Below this, grafts more peers at the next heartbeat
+    pub mesh_n_low: usize,
+    /// Above this, prunes peers at the next heartbeat
+    pub mesh_n_high: usize,
+    /// Heartbeats a message stays in the cache, to answer gossip about it
+    pub history_length: usize,
+}
+
+impl Default for GossipsubSettings {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(1),
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            history_length: 5,
+        }
+    }
+}
+
+/// Optional behaviours; messaging (DHT, gossipsub, handshakes, direct messages, circuits)
+/// always runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Behaviours {
+    pub ping: bool,
+    pub identify: bool,
+    /// Depositing to and fetching from mailboxes (serving one is `enable_mailbox`)
+    pub mailbox: bool,
+    pub file: bool,
+    /// Relay onion circuits for others, announced in the DHT
+    pub relay: bool,
+    /// Mix Sphinx packets for others (relays as well)
+    pub mix: bool,
+}
+
+impl Default for Behaviours {
+    fn default() -> Self {
+        Self {
+            ping: true,
+            identify: true,
+            mailbox: true,
+            file: true,
+            relay: false,
+            mix: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// QUIC (`/udp/../quic-v1`) addresses, and TCP ones when `tcp` is on; IPv4 or IPv6
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Peers dialed at start, each address ending in `/p2p/<peer id>`
+    pub bootstrap_peers: Vec<Multiaddr>,
+    /// Also run TCP (with Noise and Yamux) next to QUIC
+    pub tcp: bool,
+    pub gossipsub: GossipsubSettings,
+    /// How long a connection with no streams open is kept
+    pub idle_connection_timeout: Duration,
+    pub ping_interval: Duration,
+    pub behaviours: Behaviours,
+    pub identity: IdentitySource,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: vec![quic_addr("0.0.0.0", 0)],
+            bootstrap_peers: Vec::new(),
+            tcp: false,
+            gossipsub: GossipsubSettings::default(),
+            idle_connection_timeout: Duration::from_secs(60),
+            ping_interval: Duration::from_secs(15),
+            behaviours: Behaviours::default(),
+            identity: IdentitySource::default(),
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Listen on QUIC over IPv4 on `port` (0 for any), instead of the addresses set so far
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.listen_addrs = vec![quic_addr("0.0.0.0", port)];
+        self
+    }
+
+    /// Listen on `addr` as well
+    pub fn with_listen_addr(mut self, addr: Multiaddr) -> Self {
+        self.listen_addrs.push(addr);
+        self
+    }
+
+    pub fn with_bootstrap_peer(mut self, addr: Multiaddr) -> Self {
+        self.bootstrap_peers.push(addr);
+        self
+    }
+
+    pub fn with_tcp(mut self, enabled: bool) -> Self {
+        self.tcp = enabled;
+        self
+    }
+
+    pub fn with_gossipsub(mut self, gossipsub: GossipsubSettings) -> Self {
+        self.gossipsub = gossipsub;
+        self
+    }
+
+    pub fn with_idle_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_connection_timeout = timeout;
+        self
+    }
+
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    pub fn with_behaviours(mut self, behaviours: Behaviours) -> Self {
+        self.behaviours = behaviours;
+        self
+    }
+
+    pub fn with_identity(mut self, identity: IdentitySource) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Keep the node's keys in `path`, encrypted under `passphrase`
+    pub fn with_key_file(self, path: impl Into<PathBuf>, passphrase: &str) -> Self {
+        self.with_identity(IdentitySource::KeyFile {
+            path: path.into(),
+            passphrase: Some(Zeroizing::new(passphrase.to_string())),
+        })
+    }
+
+    /// Read a TOML config file
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse a TOML config; unknown keys are an error, so typos don't pass silently
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| NetError::Config(e.to_string()))?;
+        let mut config = NodeConfig::default();
+
+        if let Some(listen) = file.listen {
+            config.listen_addrs = listen.iter().map(|addr| parse_addr(addr)).collect::<Result<_>>()?;
+        }
+        if let Some(bootstrap) = file.bootstrap {
+            config.bootstrap_peers = bootstrap.iter().map(|addr| parse_addr(addr)).collect::<Result<_>>()?;
+        }
+        config.tcp = file.tcp.unwrap_or(config.tcp);
+        if let Some(secs) = file.idle_timeout_secs {
+            config.idle_connection_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.ping_interval_secs {
+            config.ping_interval = Duration::from_secs(secs);
+        }
+        match (file.identity.as_deref(), file.key_file) {
+            (None | Some("ephemeral"), None) => {}
+            (None, Some(path)) => {
+                config.identity = IdentitySource::KeyFile { path, passphrase: None };
+            }
+            (Some("ephemeral"), Some(_)) => {
+                return Err(NetError::Config("identity = \"ephemeral\" conflicts with key_file".to_string()));
+            }
+            (Some(other), _) => return Err(NetError::Config(format!("Unknown identity source {:?}", other))),
+        }
+
+        let gossipsub = file.gossipsub.unwrap_or_default();
+        let settings = &mut config.gossipsub;
+        if let Some(ms) = gossipsub.heartbeat_ms {
+            settings.heartbeat_interval = Duration::from_millis(ms);
+        }
+        settings.mesh_n = gossipsub.mesh_n.unwrap_or(settings.mesh_n);
+        settings.mesh_n_low = gossipsub.mesh_n_low.unwrap_or(settings.mesh_n_low);
+        settings.mesh_n_high = gossipsub.mesh_n_high.unwrap_or(settings.mesh_n_high);
+        settings.history_length = gossipsub.history_length.unwrap_or(settings.history_length);
+
+        let behaviours = file.behaviours.unwrap_or_default();
+        let enabled = &mut config.behaviours;
+        enabled.ping = behaviours.ping.unwrap_or(enabled.ping);
+        enabled.identify = behaviours.identify.unwrap_or(enabled.identify);
+        enabled.mailbox = behaviours.mailbox.unwrap_or(enabled.mailbox);
+        enabled.file = behaviours.file.unwrap_or(enabled.file);
+        enabled.relay = behaviours.relay.unwrap_or(enabled.relay);
+        enabled.mix = behaviours.mix.unwrap_or(enabled.mix);
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the addresses fit the transports, and bootstrap peers name who they are
+    pub fn validate(&self) -> Result<()> {
+        if self.listen_addrs.is_empty() {
+            return Err(NetError::Config("Nothing to listen on".to_string()));
+        }
+        for addr in &self.listen_addrs {
+            let quic = addr.iter().any(|protocol| matches!(protocol, Protocol::QuicV1));
+            let tcp = addr.iter().any(|protocol| matches!(protocol, Protocol::Tcp(_)));
+            let supported = quic || (tcp && self.tcp);
+            if !supported {
+                return Err(NetError::Config(format!("No transport for listen address {}", addr)));
+            }
+        }
+        for addr in &self.bootstrap_peers {
+            if !addr.iter().any(|protocol| matches!(protocol, Protocol::P2p(_))) {
+                return Err(NetError::Config(format!("Bootstrap address {} has no /p2p/ peer id", addr)));
+            }
+        }
+        let gossipsub = &self.gossipsub;
+        if !(gossipsub.mesh_n_low <= gossipsub.mesh_n && gossipsub.mesh_n <= gossipsub.mesh_n_high) {
+            return Err(NetError::Config("Gossipsub needs mesh_n_low <= mesh_n <= mesh_n_high".to_string()));
+        }
+        for (name, duration) in [
+            ("Gossipsub heartbeat", gossipsub.heartbeat_interval),
+            ("Ping interval", self.ping_interval),
+            ("Idle connection timeout", self.idle_connection_timeout),
+        ] {
+            if duration.is_zero() {
+                return Err(NetError::Config(format!("{} must be non-zero", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn quic_addr(ip: &str, port: u16) -> Multiaddr {
+    format!("/ip4/{}/udp/{}/quic-v1", ip, port).parse().expect("valid multiaddr")
+}
+
+fn parse_addr(addr: &str) -> Result<Multiaddr> {
+    addr.parse().map_err(|e| NetError::Config(format!("Invalid address {:?}: {}", addr, e)))
+}
+
+/// The TOML layout; every key optional
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    listen: Option<Vec<String>>,
+    bootstrap: Option<Vec<String>>,
+    tcp: Option<bool>,
+    idle_timeout_secs: Option<u64>,
+    ping_interval_secs: Option<u64>,
+    identity: Option<String>,
+    key_file: Option<PathBuf>,
+    gossipsub: Option<GossipsubFile>,
+    behaviours: Option<BehavioursFile>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct GossipsubFile {
+    heartbeat_ms: Option<u64>,
+    mesh_n: Option<usize>,
+    mesh_n_low: Option<usize>,
+    mesh_n_high: Option<usize>,
+    history_length: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BehavioursFile {
+    ping: Option<bool>,
+    identify: Option<bool>,
+    mailbox: Option<bool>,
+    file: Option<bool>,
+    relay: Option<bool>,
+    mix: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_match_previous_node() {
+        let config = NodeConfig::default();
+        assert_eq!(config.listen_addrs, vec!["/ip4/0.0.0.0/udp/0/quic-v1".parse::<Multiaddr>().unwrap()]);
+        assert_eq!(config.gossipsub.heartbeat_interval, Duration::from_secs(1));
+        assert_eq!(config.idle_connection_timeout, Duration::from_secs(60));
+        assert!(config.validate().is_ok());
+        assert_eq!(NodeConfig::from_toml("").unwrap().behaviours, Behaviours::default());
+    }
+
+    #[test]
+    fn test_toml_config() {
+        let peer = libp2p::PeerId::random();
+        let config = NodeConfig::from_toml(&format!(
+            r#"
+            listen = ["/ip4/0.0.0.0/udp/4001/quic-v1", "/ip6/::/udp/4001/quic-v1", "/ip6/::/tcp/4001"]
+            bootstrap = ["/ip4/203.0.113.7/udp/4001/quic-v1/p2p/{}"]
+            tcp = true
+            idle_timeout_secs = 120
+            key_file = "/var/lib/umbra/node_keys.bin"
+
+            [gossipsub]
+            heartbeat_ms = 700
+            mesh_n = 8
+
+            [behaviours]
+            file = false
+            relay = true
+            "#,
+            peer
+        ))
+        .unwrap();
+
+        assert_eq!(config.listen_addrs.len(), 3);
+        assert_eq!(config.listen_addrs[1].to_string(), "/ip6/::/udp/4001/quic-v1");
+        assert_eq!(config.bootstrap_peers.len(), 1);
+        assert!(config.tcp);
+        assert_eq!(config.idle_connection_timeout, Duration::from_secs(120));
+        assert_eq!(config.gossipsub.heartbeat_interval, Duration::from_millis(700));
+        assert_eq!((config.gossipsub.mesh_n, config.gossipsub.mesh_n_low), (8, 5));
+        assert!(!config.behaviours.file && config.behaviours.relay && config.behaviours.mailbox);
+        assert!(matches!(
+            config.identity,
+            IdentitySource::KeyFile { ref path, passphrase: None } if path == Path::new("/var/lib/umbra/node_keys.bin")
+        ));
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        assert!(NodeConfig::from_toml("listen = [\"not an address\"]").is_err());
+        assert!(NodeConfig::from_toml("listn = []").is_err());
+        assert!(NodeConfig::from_toml("listen = []").is_err());
+        // TCP addresses need TCP on
+        assert!(NodeConfig::from_toml("listen = [\"/ip4/0.0.0.0/tcp/4001\"]").is_err());
+        assert!(NodeConfig::from_toml("bootstrap = [\"/ip4/203.0.113.7/udp/4001/quic-v1\"]").is_err());
+        assert!(NodeConfig::from_toml("[gossipsub]\nmesh_n = 20").is_err());
+        assert!(NodeConfig::from_toml("identity = \"somewhere\"").is_err());
+        assert!(NodeConfig::from_toml("identity = \"ephemeral\"\nkey_file = \"keys.bin\"").is_err());
+    }
+
+    #[test]
+    fn test_zero_durations_rejected() {
+        assert!(NodeConfig::from_toml("[gossipsub]\nheartbeat_ms = 0").is_err());
+        assert!(NodeConfig::from_toml("ping_interval_secs = 0").is_err());
+        assert!(NodeConfig::from_toml("idle_timeout_secs = 0").is_err());
+        assert!(NodeConfig::default().with_ping_interval(Duration::ZERO).validate().is_err());
+        assert!(NodeConfig::default().validate().is_ok());
+    }
+}
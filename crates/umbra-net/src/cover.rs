@@ -1,14 +1,186 @@
-/// Cover traffic daemon (stub for W3-W6)
-pub struct CoverTraffic {}
+// Cover traffic daemon
+// Dummy messages on the same paths as real ones: group messages on the topics we've joined and
+// direct messages to peers we share a session with. They are encrypted and signed like any chat
+// message, with the cover flag inside the ciphertext, so only the receiver can tell them apart;
+// it drops them once decrypted.
+
+use libp2p::PeerId;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+/// Longest dummy chat content, in characters
+const MAX_DUMMY_CONTENT: usize = 256;
+
+/// When cover messages go out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoverSchedule {
+    /// Exponentially distributed gaps with this mean (a Poisson process)
+    Poisson { mean: Duration },
+    /// One message every `interval`
+    Constant { interval: Duration },
+}
+
+impl CoverSchedule {
+    /// Time until the next cover message
+    pub fn next_delay(&self) -> Duration {
+        match *self {
+            CoverSchedule::Poisson { mean } => {
+                let uniform: f64 = rand::random();
+                mean.mul_f64(-(1.0 - uniform).ln())
+            }
+            CoverSchedule::Constant { interval } => interval,
+        }
+    }
+}
+
+/// Cover traffic settings
+#[derive(Clone, Debug)]
+pub struct CoverConfig {
+    pub schedule: CoverSchedule,
+    /// Send cover messages to the groups we've joined
+    pub topics: bool,
+    /// Send cover messages to connected peers we share a session with
+    pub direct: bool,
+}
+
+impl Default for CoverConfig {
+    fn default() -> Self {
+        Self {
+            schedule: CoverSchedule::Poisson { mean: Duration::from_secs(5) },
+            topics: true,
+            direct: true,
+        }
+    }
+}
+
+/// Where a cover message goes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CoverTarget {
+    Topic(String),
+    Peer(PeerId),
+}
+
+/// Real and cover message counters, to measure what cover traffic costs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    pub real_sent: u64,
+    pub real_sent_bytes: u64,
+    pub cover_sent: u64,
+    pub cover_sent_bytes: u64,
+    pub cover_received: u64,
+    pub cover_received_bytes: u64,
+}
+
+impl TrafficStats {
+    /// Share of the bytes we sent that were cover traffic (0.0 when nothing was sent)
+    pub fn overhead(&self) -> f64 {
+        let total = self.real_sent_bytes + self.cover_sent_bytes;
+        if total == 0 {
+            0.0
+        } else {
+            self.cover_sent_bytes as f64 / total as f64
+        }
+    }
+}
+
+/// Cover traffic daemon: decides when the next dummy goes out and where
+pub struct CoverTraffic {
+    config: CoverConfig,
+    /// Deadline of the next cover message, from the first `tick` on
+    next: Option<Pin<Box<Sleep>>>,
+}
 
 impl Default for CoverTraffic {
     fn default() -> Self {
-        Self::new()
+        Self::new(CoverConfig::default())
     }
 }
 
 impl CoverTraffic {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(config: CoverConfig) -> Self {
+        Self { config, next: None }
+    }
+
+    pub fn config(&self) -> &CoverConfig {
+        &self.config
+    }
+
+    /// Wait until the next cover message is due (cancel-safe: the deadline survives a drop)
+    pub async fn tick(&mut self) {
+        let schedule = self.config.schedule;
+        let next = self.next.get_or_insert_with(|| Box::pin(tokio::time::sleep(schedule.next_delay())));
+        next.as_mut().await;
+        next.as_mut().reset(Instant::now() + schedule.next_delay());
+    }
+
+    /// Pick a destination for the next cover message among the enabled kinds
+    pub fn pick_target(&self, topics: &[String], peers: &[PeerId]) -> Option<CoverTarget> {
+        let topics = if self.config.topics { topics } else { &[] };
+        let peers = if self.config.direct { peers } else { &[] };
+        let choice = rand::thread_rng().gen_range(0..(topics.len() + peers.len()).max(1));
+        match topics.get(choice) {
+            Some(topic) => Some(CoverTarget::Topic(topic.clone())),
+            None => peers.get(choice - topics.len()).map(|peer| CoverTarget::Peer(*peer)),
+        }
+    }
+}
+
+/// Random chat content of random length, so dummies vary in size like real messages
+pub(crate) fn dummy_content() -> String {
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 ";
+    let mut rng = rand::thread_rng();
+    let len = rng.gen_range(1..=MAX_DUMMY_CONTENT);
+    (0..len).map(|_| *CHARS.choose(&mut rng).unwrap() as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_schedule() {
+        let schedule = CoverSchedule::Constant { interval: Duration::from_millis(250) };
+        assert!((0..10).all(|_| schedule.next_delay() == Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_poisson_schedule_mean() {
+        let mean = Duration::from_millis(100);
+        let schedule = CoverSchedule::Poisson { mean };
+        let samples = 20_000;
+        let total: Duration = (0..samples).map(|_| schedule.next_delay()).sum();
+        let average = total / samples;
+        assert!(average > mean * 9 / 10 && average < mean * 11 / 10, "average gap {:?}", average);
+    }
+
+    #[test]
+    fn test_pick_target_respects_config() {
+        let topics = vec!["room".to_string()];
+        let peers = vec![PeerId::random()];
+
+        let topics_only = CoverTraffic::new(CoverConfig { direct: false, ..Default::default() });
+        let direct_only = CoverTraffic::new(CoverConfig { topics: false, ..Default::default() });
+        for _ in 0..20 {
+            assert_eq!(topics_only.pick_target(&topics, &peers), Some(CoverTarget::Topic("room".to_string())));
+            assert_eq!(direct_only.pick_target(&topics, &peers), Some(CoverTarget::Peer(peers[0])));
+        }
+        assert_eq!(CoverTraffic::default().pick_target(&[], &[]), None);
+    }
+
+    #[test]
+    fn test_overhead() {
+        assert_eq!(TrafficStats::default().overhead(), 0.0);
+        let stats = TrafficStats { real_sent_bytes: 300, cover_sent_bytes: 100, ..Default::default() };
+        assert_eq!(stats.overhead(), 0.25);
+    }
+
+    #[test]
+    fn test_dummy_content_varies() {
+        let lengths: std::collections::HashSet<usize> = (0..50).map(|_| dummy_content().len()).collect();
+        assert!(lengths.len() > 1);
+        assert!(lengths.iter().all(|len| (1..=MAX_DUMMY_CONTENT).contains(len)));
     }
 }
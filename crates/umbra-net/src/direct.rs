@@ -0,0 +1,439 @@
+// Direct 1:1 messaging over /umbra/dm/1
+// Each DirectMessage is a request; the addressed peer answers with a DirectAck for the same id.
+// Acks are each held back a random delay by a DelayedAck, so they don't time the arrival.
+
+use crate::codec::LengthPrefixedCodec;
+use crate::requests::{RequestEvent, RequestProtocol, Requests};
+use crate::timing::DelayedAck;
+use libp2p::request_response::{self, OutboundFailure, OutboundRequestId, ProtocolSupport, ResponseChannel};
+use libp2p::{PeerId, StreamProtocol};
+use prost::Message;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::time::Duration;
+use tokio::time::Sleep;
+use tracing::{debug, warn};
+use umbra_wire::message::{DirectAck, DirectMessage, PrekeyMessage};
+
+/// Stream protocol for direct messages
+pub const DM_PROTOCOL: StreamProtocol = StreamProtocol::new("/umbra/dm/1");
+
+/// How long to wait for an ack before retrying
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attempts per message before reporting it undelivered
+const MAX_SEND_ATTEMPTS: u32 = 3;
+
+/// Recently received message ids remembered for dedup (retries after a lost ack)
+const SEEN_CAPACITY: usize = 1024;
+
+/// Default hold-back for acks (well under the sender's request timeout)
+pub const DEFAULT_ACK_DELAY_MS: u64 = 100;
+
+/// Outcome of a `send_direct`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Peer acknowledged the message
+    Delivered,
+    /// Gave up after retries
+    Failed(String),
+}
+
+/// Events emitted by the direct messaging protocol
+#[derive(Debug)]
+pub enum DirectEvent {
+    /// A direct message arrived (encoded `EncryptedMessage`)
+    Received {
+        peer_id: PeerId,
+        message_id: u64,
+        data: Vec<u8>,
+    },
+    /// First messages of a session set up from our prekey bundle
+    PrekeyReceived {
+        peer_id: PeerId,
+        message_id: u64,
+        message: PrekeyMessage,
+    },
+    /// Delivery result for a message we sent
+    Ack {
+        peer_id: PeerId,
+        message_id: u64,
+        status: DeliveryStatus,
+    },
+}
+
+/// A sent message that hasn't been acked yet
+struct InFlight {
+    message_id: u64,
+    data: Vec<u8>,
+    attempts: u32,
+}
+
+/// Point-to-point delivery of encrypted messages with per-message acks
+pub struct DirectBehaviour {
+    requests: Requests,
+    in_flight: HashMap<OutboundRequestId, InFlight>,
+    seen: HashSet<(PeerId, u64)>,
+    seen_order: VecDeque<(PeerId, u64)>,
+    pending_events: VecDeque<DirectEvent>,
+    /// Schedules held-back acks (none: ack straight away)
+    acks: Option<DelayedAck>,
+    /// Held-back acks by the key they're scheduled under
+    ack_channels: HashMap<Vec<u8>, (ResponseChannel<Vec<u8>>, DirectAck)>,
+    next_ack_key: u64,
+    /// Next batch flush, while acks are held back
+    ack_flush: Option<Pin<Box<Sleep>>>,
+}
+
+impl DirectBehaviour {
+    pub fn new() -> Self {
+        Self {
+            requests: request_response::Behaviour::with_codec(
+                LengthPrefixedCodec::default(),
+                [(DM_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            in_flight: HashMap::new(),
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            pending_events: VecDeque::new(),
+            acks: Some(DelayedAck::new(DEFAULT_ACK_DELAY_MS)),
+            ack_channels: HashMap::new(),
+            next_ack_key: 0,
+            ack_flush: None,
+        }
+    }
+
+    /// Hold each ack back a random delay of up to `delay_ms`, or ack right away with `None`
+    pub fn set_ack_delay(&mut self, delay_ms: Option<u64>) {
+        if let Some(mut acks) = self.acks.take() {
+            for key in acks.take_all() {
+                self.send_ack(&key);
+            }
+        }
+        self.acks = delay_ms.map(DelayedAck::new);
+        self.ack_flush = None;
+    }
+
+    /// Send an encoded `EncryptedMessage` to `peer_id`; returns the id its ack will carry
+    pub fn send(&mut self, peer_id: PeerId, encrypted: Vec<u8>) -> u64 {
+        let message_id = rand::random();
+        self.send_as(peer_id, message_id, encrypted);
+        message_id
+    }
+
+    /// `send` under an id picked ahead of time
+    pub fn send_as(&mut self, peer_id: PeerId, message_id: u64, encrypted: Vec<u8>) {
+        let data = DirectMessage { message_id, encrypted, prekey: None }.encode_to_vec();
+        self.send_request(peer_id, message_id, data, 1);
+    }
+
+    /// Send the first messages of a prekey session; acked like any other message
+    pub fn send_prekey(&mut self, peer_id: PeerId, message: PrekeyMessage) -> u64 {
+        let message_id = rand::random();
+        let data = DirectMessage { message_id, encrypted: Vec::new(), prekey: Some(message) }.encode_to_vec();
+        self.send_request(peer_id, message_id, data, 1);
+        message_id
+    }
+
+    fn send_request(&mut self, peer_id: PeerId, message_id: u64, data: Vec<u8>, attempts: u32) {
+        debug!("Sending direct message {} to {} (attempt {})", message_id, peer_id, attempts);
+        let request_id = self.requests.send_request(&peer_id, data.clone());
+        self.in_flight.insert(request_id, InFlight { message_id, data, attempts });
+    }
+
+    /// Decode an inbound message and build its ack (duplicates are acked but not re-delivered)
+    fn handle_request(&mut self, peer_id: PeerId, request: &[u8]) -> Result<DirectAck, String> {
+        let msg = DirectMessage::decode(request)
+            .map_err(|e| format!("Failed to decode direct message: {}", e))?;
+
+        let key = (peer_id, msg.message_id);
+        if self.seen.insert(key) {
+            self.seen_order.push_back(key);
+            if self.seen_order.len() > SEEN_CAPACITY {
+                if let Some(oldest) = self.seen_order.pop_front() {
+                    self.seen.remove(&oldest);
+                }
+            }
+
+            self.pending_events.push_back(match msg.prekey {
+                Some(message) => DirectEvent::PrekeyReceived {
+                    peer_id,
+                    message_id: msg.message_id,
+                    message,
+                },
+                None => DirectEvent::Received {
+                    peer_id,
+                    message_id: msg.message_id,
+                    data: msg.encrypted,
+                },
+            });
+        } else {
+            debug!("Duplicate direct message {} from {}", msg.message_id, peer_id);
+        }
+
+        Ok(DirectAck { message_id: msg.message_id })
+    }
+
+    fn on_request_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    match self.handle_request(peer, &request) {
+                        Ok(ack) => self.schedule_ack(channel, ack),
+                        // No ack: the sender's retries run out and it reports the failure
+                        Err(e) => warn!("Direct message from {} rejected: {}", peer, e),
+                    }
+                }
+                request_response::Message::Response { request_id, response } => {
+                    self.on_response(peer, request_id, &response);
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                self.on_send_failure(peer, request_id, error);
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Inbound direct stream from {} failed: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    /// Ack now, or hold it for the next batch when acks are delayed
+    fn schedule_ack(&mut self, channel: ResponseChannel<Vec<u8>>, ack: DirectAck) {
+        let Some(acks) = self.acks.as_mut() else {
+            let _ = self.requests.send_response(channel, ack.encode_to_vec());
+            return;
+        };
+
+        let key = self.next_ack_key.to_be_bytes().to_vec();
+        self.next_ack_key += 1;
+        acks.schedule_ack(key.clone());
+        self.ack_channels.insert(key, (channel, ack));
+        // Wake for whichever ack is due first
+        if let Some(next) = acks.next_due() {
+            match self.ack_flush.as_mut() {
+                Some(flush) if flush.deadline() <= next => {}
+                Some(flush) => flush.as_mut().reset(next),
+                None => self.ack_flush = Some(Box::pin(tokio::time::sleep_until(next))),
+            }
+        }
+    }
+
+    fn send_ack(&mut self, key: &[u8]) {
+        if let Some((channel, ack)) = self.ack_channels.remove(key) {
+            // The sender may have hung up meanwhile; it retries and we ack the duplicate
+            let _ = self.requests.send_response(channel, ack.encode_to_vec());
+        }
+    }
+
+    /// Send every ack whose delay is up once the batch timer fires; re-arm while some are left
+    fn poll_ack_flush(&mut self, cx: &mut std::task::Context) {
+        let Some(flush) = self.ack_flush.as_mut() else {
+            return;
+        };
+        if flush.as_mut().poll(cx).is_pending() {
+            return;
+        }
+        let Some(acks) = self.acks.as_mut() else {
+            self.ack_flush = None;
+            return;
+        };
+
+        let ready = acks.get_ready_acks();
+        match acks.next_due() {
+            Some(next) => {
+                flush.as_mut().reset(next);
+                // Register the waker for the new deadline
+                let _ = flush.as_mut().poll(cx);
+            }
+            None => self.ack_flush = None,
+        }
+        if !ready.is_empty() {
+            debug!("Sending {} delayed ack(s)", ready.len());
+        }
+        for key in ready {
+            self.send_ack(&key);
+        }
+    }
+
+    fn on_response(&mut self, peer_id: PeerId, request_id: OutboundRequestId, response: &[u8]) {
+        let Some(in_flight) = self.in_flight.remove(&request_id) else {
+            return;
+        };
+
+        let status = match DirectAck::decode(response) {
+            Ok(ack) if ack.message_id == in_flight.message_id => DeliveryStatus::Delivered,
+            Ok(ack) => DeliveryStatus::Failed(format!("Ack for wrong message id {}", ack.message_id)),
+            Err(e) => DeliveryStatus::Failed(format!("Invalid ack: {}", e)),
+        };
+
+        self.pending_events.push_back(DirectEvent::Ack {
+            peer_id,
+            message_id: in_flight.message_id,
+            status,
+        });
+    }
+
+    /// Retry a failed send, or report it undelivered once attempts run out
+    fn on_send_failure(&mut self, peer_id: PeerId, request_id: OutboundRequestId, error: OutboundFailure) {
+        let Some(in_flight) = self.in_flight.remove(&request_id) else {
+            return;
+        };
+
+        let retryable = !matches!(error, OutboundFailure::UnsupportedProtocols);
+        if retryable && in_flight.attempts < MAX_SEND_ATTEMPTS {
+            debug!("Direct message {} to {} failed ({}), retrying", in_flight.message_id, peer_id, error);
+            self.send_request(peer_id, in_flight.message_id, in_flight.data, in_flight.attempts + 1);
+            return;
+        }
+
+        self.pending_events.push_back(DirectEvent::Ack {
+            peer_id,
+            message_id: in_flight.message_id,
+            status: DeliveryStatus::Failed(error.to_string()),
+        });
+    }
+}
+
+impl Default for DirectBehaviour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Runs on request_response over /umbra/dm/1
+impl RequestProtocol for DirectBehaviour {
+    type Event = DirectEvent;
+
+    fn requests(&mut self) -> &mut Requests {
+        &mut self.requests
+    }
+
+    fn on_event(&mut self, event: RequestEvent) {
+        self.on_request_event(event);
+    }
+
+    fn pop_event(&mut self) -> Option<DirectEvent> {
+        self.pending_events.pop_front()
+    }
+
+    fn poll_tasks(&mut self, cx: &mut Context) {
+        self.poll_ack_flush(cx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(message_id: u64, encrypted: &[u8]) -> Vec<u8> {
+        DirectMessage { message_id, encrypted: encrypted.to_vec(), prekey: None }.encode_to_vec()
+    }
+
+    #[test]
+    fn test_request_acked_and_delivered() {
+        let mut direct = DirectBehaviour::new();
+        let peer = PeerId::random();
+
+        let ack = direct.handle_request(peer, &request(7, b"ciphertext")).unwrap();
+        assert_eq!(ack.message_id, 7);
+
+        match direct.pending_events.pop_front() {
+            Some(DirectEvent::Received { peer_id, message_id, data }) => {
+                assert_eq!(peer_id, peer);
+                assert_eq!(message_id, 7);
+                assert_eq!(data, b"ciphertext");
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_prekey_message_delivered() {
+        let mut direct = DirectBehaviour::new();
+        let prekey = PrekeyMessage { init: None, messages: vec![b"first".to_vec()] };
+        let data = DirectMessage { message_id: 3, encrypted: Vec::new(), prekey: Some(prekey.clone()) }.encode_to_vec();
+
+        direct.handle_request(PeerId::random(), &data).unwrap();
+        match direct.pending_events.pop_front() {
+            Some(DirectEvent::PrekeyReceived { message_id, message, .. }) => {
+                assert_eq!(message_id, 3);
+                assert_eq!(message, prekey);
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_acked_not_redelivered() {
+        let mut direct = DirectBehaviour::new();
+        let peer = PeerId::random();
+
+        direct.handle_request(peer, &request(1, b"a")).unwrap();
+        let ack = direct.handle_request(peer, &request(1, b"a")).unwrap();
+        assert_eq!(ack.message_id, 1);
+        assert_eq!(direct.pending_events.len(), 1);
+
+        // Same id from a different peer is a different message
+        direct.handle_request(PeerId::random(), &request(1, b"a")).unwrap();
+        assert_eq!(direct.pending_events.len(), 2);
+    }
+
+    #[test]
+    fn test_seen_set_bounded() {
+        let mut direct = DirectBehaviour::new();
+        let peer = PeerId::random();
+
+        for id in 0..(SEEN_CAPACITY as u64 + 10) {
+            direct.handle_request(peer, &request(id, b"x")).unwrap();
+        }
+        assert_eq!(direct.seen.len(), SEEN_CAPACITY);
+        assert_eq!(direct.seen_order.len(), SEEN_CAPACITY);
+    }
+
+    #[test]
+    fn test_garbage_request_rejected() {
+        let mut direct = DirectBehaviour::new();
+        assert!(direct.handle_request(PeerId::random(), &[0xFF, 0xFF, 0xFF]).is_err());
+        assert!(direct.pending_events.is_empty());
+    }
+
+    #[test]
+    fn test_send_retried_then_failed() {
+        let mut direct = DirectBehaviour::new();
+        let peer = PeerId::random();
+        let message_id = direct.send(peer, b"ciphertext".to_vec());
+
+        for attempt in 1..=MAX_SEND_ATTEMPTS {
+            let request_id = *direct.in_flight.keys().next().unwrap();
+            assert_eq!(direct.in_flight[&request_id].attempts, attempt);
+            direct.on_send_failure(peer, request_id, OutboundFailure::ConnectionClosed);
+        }
+
+        assert!(direct.in_flight.is_empty());
+        match direct.pending_events.pop_front() {
+            Some(DirectEvent::Ack { message_id: id, status: DeliveryStatus::Failed(_), .. }) => {
+                assert_eq!(id, message_id);
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ack_must_match_message_id() {
+        let mut direct = DirectBehaviour::new();
+        let peer = PeerId::random();
+        let message_id = direct.send(peer, b"ciphertext".to_vec());
+        let request_id = *direct.in_flight.keys().next().unwrap();
+
+        let wrong = DirectAck { message_id: message_id.wrapping_add(1) }.encode_to_vec();
+        direct.on_response(peer, request_id, &wrong);
+        assert!(matches!(
+            direct.pending_events.pop_front(),
+            Some(DirectEvent::Ack { status: DeliveryStatus::Failed(_), .. })
+        ));
+    }
+}
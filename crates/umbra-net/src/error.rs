@@ -26,6 +26,39 @@ pub enum NetError {
     #[error("Protocol error: {0}")]
     Protocol(String),
     
+    #[error("Missing signature from peer {0}")]
+    MissingSignature(String),
+    
+    #[error("Malformed signature: {0}")]
+    MalformedSignature(String),
+    
+    #[error("No verification key registered for peer {0}")]
+    UnknownPeerKey(String),
+    
+    #[error("Signature verification failed: {0}")]
+    SignatureInvalid(String),
+    
+    #[error("Post-quantum signature verification failed: {0}")]
+    PqSignatureInvalid(String),
+    
+    #[error("Not a member of group {0}")]
+    NotInGroup(String),
+    
+    #[error("Already in a group on {0}; leave it first")]
+    InGroup(String),
+    
+    #[error("MLS error: {0}")]
+    Mls(#[from] umbra_mls::MlsError),
+    
+    #[error("Invalid config: {0}")]
+    Config(String),
+    
+    #[error("{0} is disabled in this node's config")]
+    Disabled(&'static str),
+    
+    #[error("Cover traffic (nothing to deliver)")]
+    CoverTraffic,
+    
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -0,0 +1,1564 @@
+// File transfer over /umbra/file/1
+// The sender offers a signed manifest; once the receiver accepts, it pulls the file chunk by
+// chunk with a few requests in flight. Each chunk is encrypted under a key derived from the
+// session between the two peers and checked against the manifest's Merkle root before it is
+// written to disk, so neither side ever holds more than a few chunks in memory.
+//
+// Downloads swarm: besides the sender, every peer we can reach is asked which chunks it has
+// (a bitfield), and chunks are pulled from all of them at once, rarest first. Whoever holds a
+// verified chunk passes it on with the proof it came with, so a peer feeding us bad data is
+// caught on its first chunk and dropped, costing us nothing but that chunk.
+//
+// A folder is offered as one signed manifest listing its directories and files. Once accepted,
+// each file is downloaded like any other, under its own file id, into a fresh directory; the
+// folder completes when the last of them does, and fails as a whole if any of them fails.
+
+use crate::codec::LengthPrefixedCodec;
+use crate::requests::{RequestEvent, RequestProtocol, Requests};
+use futures::stream::{FuturesUnordered, StreamExt};
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel};
+use libp2p::{PeerId, StreamProtocol};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Sleep;
+use tracing::{debug, warn};
+use umbra_crypto::identity::HybridVerifyingKey;
+use umbra_crypto::merkle::{self, MerkleTree};
+use umbra_crypto::StreamChunks;
+use umbra_wire::file::{
+    decode_bitfield, encode_bitfield, file_request, ChunkRequest, FileManifest, FileRequest,
+    FileResponse, FileStatus, FolderManifest, HaveRequest, MAX_CHUNK_SIZE,
+};
+use zeroize::Zeroizing;
+
+/// Stream protocol for file offers, bitfields and chunk requests
+pub const FILE_PROTOCOL: StreamProtocol = StreamProtocol::new("/umbra/file/1");
+
+/// Plaintext bytes per chunk: the most a manifest may ask for, so a sealed chunk and its proof
+/// fit the 128 KiB frame bucket
+pub const CHUNK_SIZE: u32 = MAX_CHUNK_SIZE;
+
+const MAX_FRAME_SIZE: usize = 128 * 1024;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Chunk requests each source has in flight
+const WINDOW: usize = 4;
+
+/// Failed requests before a source is dropped (a bad chunk drops it at once)
+const MAX_SOURCE_FAILURES: u32 = 3;
+
+/// How long a source with nothing we need waits before being asked what it has now
+const BITFIELD_REFRESH: Duration = Duration::from_secs(2);
+
+/// A transfer's id: the manifest's random file id
+pub type FileId = [u8; 32];
+
+/// Events emitted by the file transfer protocol
+#[derive(Debug)]
+pub enum FileEvent {
+    /// A peer offers us a file; the manifest is consistent and signed by `verify_key`
+    Offered {
+        peer: PeerId,
+        manifest: Box<FileManifest>,
+        verify_key: Box<HybridVerifyingKey>,
+    },
+    /// A peer offers us a folder; the manifest is safe to extract and signed by `verify_key`
+    FolderOffered {
+        peer: PeerId,
+        manifest: Box<FolderManifest>,
+        verify_key: Box<HybridVerifyingKey>,
+    },
+    /// Progress, completion or failure of one of our transfers
+    Update(FileUpdate),
+}
+
+/// What a node learns about its file transfers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileUpdate {
+    /// `peer` offers us a file; take it with `accept_file`
+    Offered { peer: PeerId, file_id: FileId, name: String, size: u64 },
+    /// `peer` offers us a folder of `files` files, `size` bytes in all; take it with
+    /// `accept_folder`. Its progress and outcome come as those of a file with id `folder_id`.
+    FolderOffered { peer: PeerId, folder_id: FileId, name: String, files: usize, size: u64 },
+    /// `done` of `total` chunks are verified and on disk
+    Progress { file_id: FileId, done: u64, total: u64 },
+    /// The whole file is at `path` and matches its manifest
+    Completed { file_id: FileId, path: PathBuf },
+    Failed { file_id: FileId, error: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RequestKind {
+    Offer(FileId),
+    Have(FileId),
+    Chunk(FileId, u64),
+}
+
+/// A file we serve chunks of
+struct Shared {
+    path: PathBuf,
+    manifest: FileManifest,
+    tree: MerkleTree,
+    /// Peers it was offered to, or `None` for a finished download, which anyone who knows
+    /// the file id (and so got the manifest) may fetch
+    peers: Option<HashSet<PeerId>>,
+}
+
+impl Shared {
+    fn serves(&self, peer: &PeerId) -> bool {
+        self.peers.as_ref().map_or(true, |peers| peers.contains(peer))
+    }
+}
+
+/// A chunk we have, and what it takes to pass it on
+struct Verified {
+    leaf: [u8; 32],
+    proof: Vec<[u8; 32]>,
+}
+
+/// A peer we pull chunks from
+#[derive(Default)]
+struct Source {
+    /// Which chunks it has, once it has told us
+    have: Option<Vec<bool>>,
+    in_flight: usize,
+    failures: u32,
+    /// Waiting for its bitfield
+    asking: bool,
+    /// Had nothing we need last time we looked
+    stale: bool,
+}
+
+/// A file we're pulling from whoever has it
+struct Download {
+    manifest: FileManifest,
+    root: [u8; 32],
+    path: PathBuf,
+    file: File,
+    chunks: Vec<Option<Verified>>,
+    done: u64,
+    sources: HashMap<PeerId, Source>,
+    /// Chunks requested, and from whom
+    in_flight: HashMap<u64, PeerId>,
+    paused: bool,
+    /// Permissions to give the file once it's complete
+    mode: Option<u32>,
+}
+
+impl Download {
+    /// The missing chunk `peer` has that the fewest sources have
+    fn rarest_for(&self, peer: &PeerId) -> Option<u64> {
+        let have = self.sources.get(peer)?.have.as_ref()?;
+        (0..self.chunks.len())
+            .filter(|&i| have[i] && self.chunks[i].is_none() && !self.in_flight.contains_key(&(i as u64)))
+            .min_by_key(|&i| (self.availability(i), i))
+            .map(|i| i as u64)
+    }
+
+    fn availability(&self, index: usize) -> usize {
+        self.sources.values()
+            .filter(|source| source.have.as_ref().is_some_and(|have| have[index]))
+            .count()
+    }
+}
+
+/// A folder we offered or are pulling; its files travel as transfers of their own
+struct Folder {
+    files: Vec<FileId>,
+    /// Set while we're pulling it
+    download: Option<FolderDownload>,
+}
+
+struct FolderDownload {
+    root: PathBuf,
+    /// Files not complete yet
+    remaining: usize,
+    /// Chunks done of each file
+    progress: HashMap<FileId, u64>,
+    total: u64,
+    /// Applied once every file is in, so a read-only directory can still be filled
+    dir_modes: Vec<(PathBuf, u32)>,
+}
+
+/// A chunk request we've checked, to be read off disk and sealed away from the event loop
+struct ChunkRead {
+    path: PathBuf,
+    offset: u64,
+    len: usize,
+    index: u64,
+    leaf: [u8; 32],
+    proof: Vec<[u8; 32]>,
+    cipher: StreamChunks,
+}
+
+impl ChunkRead {
+    /// Blocking: reads the file
+    fn run(self) -> FileResponse {
+        let chunk = match read_chunk(&self.path, self.offset, self.len) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Can't read chunk {} of {}: {}", self.index, self.path.display(), e);
+                return FileResponse::with_status(FileStatus::FileUnavailable);
+            }
+        };
+        // Never seal different data under a chunk's nonce: the file must still match the manifest
+        if merkle::leaf_hash(&chunk) != self.leaf {
+            warn!("{} changed since it was offered", self.path.display());
+            return FileResponse::with_status(FileStatus::FileUnavailable);
+        }
+        let Ok(ciphertext) = self.cipher.seal(self.index, &chunk) else {
+            return FileResponse::with_status(FileStatus::FileUnavailable);
+        };
+
+        FileResponse {
+            index: self.index,
+            ciphertext,
+            proof: self.proof.iter().map(|hash| hash.to_vec()).collect(),
+            ..FileResponse::with_status(FileStatus::FileOk)
+        }
+    }
+}
+
+/// How to answer a request: right away, or once a chunk has been read
+enum Reply {
+    Now(FileResponse),
+    Chunk(ChunkRead),
+}
+
+type Serving = Pin<Box<dyn Future<Output = (ResponseChannel<Vec<u8>>, FileResponse)> + Send>>;
+
+/// Why a chunk from a source was no good
+enum ChunkError {
+    /// The source couldn't serve it
+    Unavailable(String),
+    /// The source sent something that doesn't match the manifest
+    Invalid(String),
+}
+
+/// Sender, seeder and receiver side of the file transfer protocol
+pub struct FileBehaviour {
+    requests: Requests,
+    /// Transfer keys: the session key shared with each peer
+    sessions: HashMap<PeerId, [u8; 32]>,
+    shared: HashMap<FileId, Shared>,
+    /// Offers made to us, not accepted yet
+    offers: HashMap<FileId, (PeerId, FileManifest)>,
+    downloads: HashMap<FileId, Download>,
+    /// Folder offers made to us, not accepted yet
+    folder_offers: HashMap<FileId, (PeerId, FolderManifest)>,
+    folders: HashMap<FileId, Folder>,
+    /// The folder each file of a folder belongs to
+    folder_of: HashMap<FileId, FileId>,
+    pending_events: VecDeque<FileEvent>,
+    in_flight: HashMap<OutboundRequestId, (PeerId, RequestKind)>,
+    /// Chunks being read on the blocking pool, with the channel each answer goes back on
+    serving: FuturesUnordered<Serving>,
+    /// Fires when stale sources are due to be asked for their bitfield again
+    refresh: Option<Pin<Box<Sleep>>>,
+}
+
+impl FileBehaviour {
+    pub fn new() -> Self {
+        Self {
+            requests: request_response::Behaviour::with_codec(
+                LengthPrefixedCodec::new(MAX_FRAME_SIZE),
+                [(FILE_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            sessions: HashMap::new(),
+            shared: HashMap::new(),
+            offers: HashMap::new(),
+            downloads: HashMap::new(),
+            folder_offers: HashMap::new(),
+            folders: HashMap::new(),
+            folder_of: HashMap::new(),
+            pending_events: VecDeque::new(),
+            in_flight: HashMap::new(),
+            serving: FuturesUnordered::new(),
+            refresh: None,
+        }
+    }
+
+    /// Session key shared with `peer`; chunk keys are derived from it
+    pub fn set_session_key(&mut self, peer: PeerId, session_key: [u8; 32]) {
+        self.sessions.insert(peer, session_key);
+    }
+
+    /// Offer `path` to `peer` under `manifest` (whose root is `tree`'s)
+    pub fn offer(&mut self, peer: PeerId, path: PathBuf, manifest: FileManifest, tree: MerkleTree) -> crate::error::Result<FileId> {
+        let file_id = manifest.file_id().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        let request = FileRequest {
+            request: Some(file_request::Request::Offer(manifest.clone())),
+        };
+        let shared = self.shared
+            .entry(file_id)
+            .or_insert_with(|| Shared { path, manifest, tree, peers: Some(HashSet::new()) });
+        if let Some(peers) = shared.peers.as_mut() {
+            peers.insert(peer);
+        }
+        self.send_request(peer, RequestKind::Offer(file_id), request);
+        Ok(file_id)
+    }
+
+    /// Start pulling an offered file into `path`, from the peer that offered it to begin with
+    pub fn accept(&mut self, file_id: FileId, path: PathBuf) -> crate::error::Result<()> {
+        let (peer, _) = self.offers.get(&file_id)
+            .ok_or_else(|| crate::error::NetError::InvalidMessage("No such file offer".to_string()))?;
+        if !self.sessions.contains_key(peer) {
+            return Err(crate::error::NetError::Crypto(format!("No session with {}", peer)));
+        }
+        let (peer, manifest) = self.offers.remove(&file_id).expect("checked above");
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(&path)?;
+        self.start_download(peer, manifest, path, file, None)
+    }
+
+    /// Offer the folder `manifest` describes to `peer`; `files` holds each of its file
+    /// entries' path on disk and Merkle tree, in manifest order
+    pub fn offer_folder(
+        &mut self,
+        peer: PeerId,
+        manifest: FolderManifest,
+        files: Vec<(PathBuf, MerkleTree)>,
+    ) -> crate::error::Result<FileId> {
+        let folder_id = manifest.folder_id().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        if manifest.files().count() != files.len() {
+            return Err(crate::error::NetError::InvalidMessage("Files don't match the folder manifest".to_string()));
+        }
+        let request = FileRequest {
+            request: Some(file_request::Request::Folder(manifest.clone())),
+        };
+        // The manifest travels in a single frame
+        if request.encode_to_vec().len() > MAX_FRAME_SIZE - 4 {
+            return Err(crate::error::NetError::InvalidMessage("Folder manifest too large".to_string()));
+        }
+
+        let mut file_ids = Vec::new();
+        for (entry, (path, tree)) in manifest.files().zip(files) {
+            let file_manifest = manifest.file_manifest(entry)
+                .map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+            let file_id = file_manifest.file_id().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+            let shared = self.shared
+                .entry(file_id)
+                .or_insert_with(|| Shared { path, manifest: file_manifest, tree, peers: Some(HashSet::new()) });
+            if let Some(peers) = shared.peers.as_mut() {
+                peers.insert(peer);
+            }
+            file_ids.push(file_id);
+        }
+        self.folders.entry(folder_id).or_insert(Folder { files: file_ids, download: None });
+        self.send_request(peer, RequestKind::Offer(folder_id), request);
+        Ok(folder_id)
+    }
+
+    /// Start pulling an offered folder into a new directory named after it under `dir`;
+    /// returns the directory. Refuses to touch anything already there.
+    pub fn accept_folder(&mut self, folder_id: FileId, dir: &Path) -> crate::error::Result<PathBuf> {
+        let (peer, manifest) = self.folder_offers.get(&folder_id)
+            .ok_or_else(|| crate::error::NetError::InvalidMessage("No such folder offer".to_string()))?;
+        if !self.sessions.contains_key(peer) {
+            return Err(crate::error::NetError::Crypto(format!("No session with {}", peer)));
+        }
+
+        let root = dir.join(&manifest.name);
+        fs::create_dir(&root)?;
+        let files = match create_folder(&root, manifest) {
+            Ok(files) => files,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&root);
+                return Err(e);
+            }
+        };
+        let (peer, manifest) = self.folder_offers.remove(&folder_id).expect("checked above");
+
+        let dir_modes = manifest.entries.iter()
+            .filter(|entry| entry.directory)
+            .filter_map(|entry| Some((entry.path_under(&root).ok()?, entry.mode)))
+            .collect();
+        let total = files.iter().map(|(file_manifest, ..)| file_manifest.chunk_count).sum();
+        let file_ids: Vec<FileId> = files.iter()
+            .map(|(file_manifest, ..)| file_manifest.file_id().expect("checked with the folder"))
+            .collect();
+        for file_id in &file_ids {
+            self.folder_of.insert(*file_id, folder_id);
+        }
+        self.folders.insert(folder_id, Folder {
+            files: file_ids.clone(),
+            download: Some(FolderDownload {
+                root: root.clone(),
+                remaining: file_ids.len(),
+                progress: HashMap::new(),
+                total,
+                dir_modes,
+            }),
+        });
+
+        if file_ids.is_empty() {
+            self.finish_folder(folder_id);
+        }
+        for (file_manifest, path, file, mode) in files {
+            if let Err(e) = self.start_download(peer, file_manifest, path, file, Some(mode)) {
+                // Undo the whole accept: nothing of it stays tracked or on disk, and the offer stands
+                self.cancel(folder_id);
+                let _ = fs::remove_dir_all(&root);
+                self.folder_offers.insert(folder_id, (peer, manifest));
+                return Err(e);
+            }
+        }
+        Ok(root)
+    }
+
+    /// Pull `manifest`'s file into `file`, from `peer` to begin with
+    fn start_download(
+        &mut self,
+        peer: PeerId,
+        manifest: FileManifest,
+        path: PathBuf,
+        file: File,
+        mode: Option<u32>,
+    ) -> crate::error::Result<()> {
+        let file_id = manifest.file_id().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        let root = manifest.root().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        file.set_len(manifest.size)?;
+        let chunk_count = manifest.chunk_count as usize;
+        // Whoever offers a file has all of it
+        let sender = Source {
+            have: Some(vec![true; chunk_count]),
+            ..Default::default()
+        };
+        self.downloads.insert(file_id, Download {
+            manifest,
+            root,
+            path,
+            file,
+            chunks: (0..chunk_count).map(|_| None).collect(),
+            done: 0,
+            sources: HashMap::from([(peer, sender)]),
+            in_flight: HashMap::new(),
+            paused: false,
+            mode,
+        });
+        self.pump(file_id);
+        Ok(())
+    }
+
+    /// Also pull `file_id` from `peer`, if it turns out to have any of it
+    pub fn add_source(&mut self, file_id: FileId, peer: PeerId) {
+        if !self.sessions.contains_key(&peer) {
+            return;
+        }
+        let Some(download) = self.downloads.get_mut(&file_id) else {
+            return;
+        };
+        if download.sources.contains_key(&peer) {
+            return;
+        }
+        download.sources.insert(peer, Source::default());
+        self.ask_bitfield(file_id, peer);
+    }
+
+    /// A peer we can now reach: ask it what it has of every download
+    pub fn add_peer(&mut self, peer: PeerId) {
+        let file_ids: Vec<FileId> = self.downloads.keys().copied().collect();
+        for file_id in file_ids {
+            self.add_source(file_id, peer);
+        }
+    }
+
+    /// Stop asking for chunks of a file or folder (those in flight still land); false if
+    /// there's no such download
+    pub fn pause(&mut self, file_id: FileId) -> bool {
+        let mut found = false;
+        for file_id in self.members(file_id) {
+            if let Some(download) = self.downloads.get_mut(&file_id) {
+                download.paused = true;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Carry on with the chunks still missing
+    pub fn resume(&mut self, file_id: FileId) -> bool {
+        let mut found = false;
+        for file_id in self.members(file_id) {
+            if let Some(download) = self.downloads.get_mut(&file_id) {
+                download.paused = false;
+                found = true;
+                self.pump(file_id);
+            }
+        }
+        found
+    }
+
+    /// Drop a file or folder transfer, whichever side we're on
+    pub fn cancel(&mut self, file_id: FileId) {
+        self.folder_offers.remove(&file_id);
+        if let Some(folder) = self.folders.remove(&file_id) {
+            for file_id in folder.files {
+                self.folder_of.remove(&file_id);
+                self.cancel(file_id);
+            }
+        }
+        self.shared.remove(&file_id);
+        self.offers.remove(&file_id);
+        self.downloads.remove(&file_id);
+    }
+
+    /// The files a transfer id stands for: a folder's, or the file itself
+    fn members(&self, file_id: FileId) -> Vec<FileId> {
+        match self.folders.get(&file_id) {
+            Some(folder) => folder.files.clone(),
+            None => vec![file_id],
+        }
+    }
+
+    fn send_request(&mut self, peer: PeerId, kind: RequestKind, request: FileRequest) {
+        let request_id = self.requests.send_request(&peer, request.encode_to_vec());
+        self.in_flight.insert(request_id, (peer, kind));
+    }
+
+    fn ask_bitfield(&mut self, file_id: FileId, peer: PeerId) {
+        let Some(source) = self.downloads.get_mut(&file_id).and_then(|download| download.sources.get_mut(&peer)) else {
+            return;
+        };
+        source.asking = true;
+        source.stale = false;
+        let request = FileRequest {
+            request: Some(file_request::Request::Have(HaveRequest { file_id: file_id.to_vec() })),
+        };
+        self.send_request(peer, RequestKind::Have(file_id), request);
+    }
+
+    /// Fill every source's window with the rarest chunks it can give us
+    fn pump(&mut self, file_id: FileId) {
+        let Some(download) = self.downloads.get_mut(&file_id) else {
+            return;
+        };
+        if download.sources.is_empty() {
+            self.fail(file_id, "No sources left".to_string());
+            return;
+        }
+        if download.paused {
+            return;
+        }
+
+        let mut requests = Vec::new();
+        let peers: Vec<PeerId> = download.sources.keys().copied().collect();
+        for peer in peers {
+            while download.sources[&peer].in_flight < WINDOW {
+                let Some(index) = download.rarest_for(&peer) else {
+                    let source = download.sources.get_mut(&peer).expect("listed above");
+                    source.stale = source.have.is_some() && source.in_flight == 0;
+                    break;
+                };
+                let source = download.sources.get_mut(&peer).expect("listed above");
+                source.in_flight += 1;
+                source.stale = false;
+                download.in_flight.insert(index, peer);
+                requests.push((peer, index));
+            }
+        }
+        for (peer, index) in requests {
+            let request = FileRequest {
+                request: Some(file_request::Request::Chunk(ChunkRequest { file_id: file_id.to_vec(), index })),
+            };
+            self.send_request(peer, RequestKind::Chunk(file_id, index), request);
+        }
+    }
+
+    /// Answer an offer, a bitfield or a chunk request from `peer`
+    fn handle_request(&mut self, peer: PeerId, request: &[u8]) -> Reply {
+        let Ok(FileRequest { request: Some(request) }) = FileRequest::decode_from_bytes(request) else {
+            return Reply::Now(FileResponse::with_status(FileStatus::FileInvalid));
+        };
+
+        let response = match request {
+            file_request::Request::Offer(manifest) => {
+                let (Ok(verify_key), Ok(file_id)) = (manifest.verify(), manifest.file_id()) else {
+                    return Reply::Now(FileResponse::with_status(FileStatus::FileInvalid));
+                };
+                if !self.offers.contains_key(&file_id) && !self.downloads.contains_key(&file_id) {
+                    self.offers.insert(file_id, (peer, manifest.clone()));
+                    self.pending_events.push_back(FileEvent::Offered {
+                        peer,
+                        manifest: Box::new(manifest),
+                        verify_key: Box::new(verify_key),
+                    });
+                }
+                FileResponse::with_status(FileStatus::FileOk)
+            }
+            file_request::Request::Folder(manifest) => {
+                let (Ok(verify_key), Ok(folder_id)) = (manifest.verify(), manifest.folder_id()) else {
+                    return Reply::Now(FileResponse::with_status(FileStatus::FileInvalid));
+                };
+                if !self.folder_offers.contains_key(&folder_id) && !self.folders.contains_key(&folder_id) {
+                    self.folder_offers.insert(folder_id, (peer, manifest.clone()));
+                    self.pending_events.push_back(FileEvent::FolderOffered {
+                        peer,
+                        manifest: Box::new(manifest),
+                        verify_key: Box::new(verify_key),
+                    });
+                }
+                FileResponse::with_status(FileStatus::FileOk)
+            }
+            file_request::Request::Have(request) => self.bitfield(peer, &request.file_id),
+            file_request::Request::Chunk(request) => return self.serve_chunk(peer, &request),
+        };
+        Reply::Now(response)
+    }
+
+    fn bitfield(&self, peer: PeerId, file_id: &[u8]) -> FileResponse {
+        let Ok(file_id) = FileId::try_from(file_id) else {
+            return FileResponse::with_status(FileStatus::FileInvalid);
+        };
+        let have = if let Some(shared) = self.shared.get(&file_id).filter(|shared| shared.serves(&peer)) {
+            vec![true; shared.manifest.chunk_count as usize]
+        } else if let Some(download) = self.downloads.get(&file_id) {
+            download.chunks.iter().map(Option::is_some).collect()
+        } else {
+            return FileResponse::with_status(FileStatus::FileUnknown);
+        };
+        if !self.sessions.contains_key(&peer) {
+            return FileResponse::with_status(FileStatus::FileNoSession);
+        }
+        FileResponse {
+            bitfield: encode_bitfield(&have),
+            ..FileResponse::with_status(FileStatus::FileOk)
+        }
+    }
+
+    /// Check a chunk request; the chunk itself is read by the `ChunkRead` this returns
+    fn serve_chunk(&self, peer: PeerId, request: &ChunkRequest) -> Reply {
+        let Ok(file_id) = FileId::try_from(request.file_id.as_slice()) else {
+            return Reply::Now(FileResponse::with_status(FileStatus::FileInvalid));
+        };
+        let index = request.index;
+        // The whole file if we have it, else whatever we've downloaded so far
+        let found = if let Some(shared) = self.shared.get(&file_id).filter(|shared| shared.serves(&peer)) {
+            let Some((leaf, proof)) = shared.tree.leaf(index as usize).zip(shared.tree.proof(index as usize)) else {
+                return Reply::Now(FileResponse::with_status(FileStatus::FileInvalid));
+            };
+            (&shared.path, &shared.manifest, leaf, proof)
+        } else if let Some(download) = self.downloads.get(&file_id) {
+            match download.chunks.get(index as usize) {
+                Some(Some(verified)) => (&download.path, &download.manifest, verified.leaf, verified.proof.clone()),
+                _ => return Reply::Now(FileResponse::with_status(FileStatus::FileUnavailable)),
+            }
+        } else {
+            return Reply::Now(FileResponse::with_status(FileStatus::FileUnknown));
+        };
+        let (path, manifest, leaf, proof) = found;
+        let Some(session_key) = self.sessions.get(&peer) else {
+            return Reply::Now(FileResponse::with_status(FileStatus::FileNoSession));
+        };
+        let Ok(cipher) = chunk_cipher(session_key, &file_id, manifest) else {
+            return Reply::Now(FileResponse::with_status(FileStatus::FileUnavailable));
+        };
+
+        Reply::Chunk(ChunkRead {
+            path: path.clone(),
+            offset: index * u64::from(manifest.chunk_size),
+            len: manifest.chunk_len(index),
+            index,
+            leaf,
+            proof,
+            cipher,
+        })
+    }
+
+    fn on_have_response(&mut self, peer: PeerId, file_id: FileId, response: &[u8]) {
+        let Some(download) = self.downloads.get_mut(&file_id) else {
+            return;
+        };
+        let chunk_count = download.manifest.chunk_count;
+        let Some(source) = download.sources.get_mut(&peer) else {
+            return;
+        };
+        source.asking = false;
+
+        let have = FileResponse::decode_from_bytes(response)
+            .ok()
+            .filter(|response| response.status == FileStatus::FileOk as i32)
+            .and_then(|response| decode_bitfield(&response.bitfield, chunk_count).ok());
+        match have {
+            Some(have) => {
+                source.have = Some(have);
+                self.pump(file_id);
+            }
+            None => {
+                debug!("{} has none of the file to give", peer);
+                self.drop_source(file_id, peer);
+            }
+        }
+    }
+
+    fn on_chunk_response(&mut self, peer: PeerId, file_id: FileId, index: u64, response: &[u8]) {
+        let Some(download) = self.downloads.get_mut(&file_id) else {
+            return;
+        };
+        // A source we've since dropped may still answer; its chunks went to someone else
+        if download.in_flight.get(&index) != Some(&peer) {
+            return;
+        }
+        download.in_flight.remove(&index);
+        if let Some(source) = download.sources.get_mut(&peer) {
+            source.in_flight -= 1;
+        }
+
+        let result = match self.sessions.get(&peer) {
+            Some(session_key) => open_chunk(session_key, download, index, response),
+            None => Err(ChunkError::Unavailable(format!("No session with {}", peer))),
+        };
+        let (chunk, verified) = match result {
+            Ok(opened) => opened,
+            Err(ChunkError::Unavailable(error)) => {
+                self.source_failed(file_id, peer, error);
+                self.pump(file_id);
+                return;
+            }
+            Err(ChunkError::Invalid(error)) => {
+                warn!("Dropping {} as a source: chunk {}: {}", peer, index, error);
+                self.drop_source(file_id, peer);
+                return;
+            }
+        };
+
+        let written = download.file.seek(SeekFrom::Start(index * u64::from(download.manifest.chunk_size)))
+            .and_then(|_| download.file.write_all(&chunk));
+        if let Err(e) = written {
+            let error = format!("Writing {}: {}", download.path.display(), e);
+            self.fail(file_id, error);
+            return;
+        }
+
+        if download.chunks[index as usize].replace(verified).is_none() {
+            download.done += 1;
+        }
+        let (done, total) = (download.done, download.manifest.chunk_count);
+        self.update(FileUpdate::Progress { file_id, done, total });
+        if done == total {
+            self.finish(file_id);
+        } else {
+            self.pump(file_id);
+        }
+    }
+
+    /// Count a failed request against `peer`, dropping it once it has failed too often
+    fn source_failed(&mut self, file_id: FileId, peer: PeerId, error: String) {
+        let Some(source) = self.downloads.get_mut(&file_id).and_then(|download| download.sources.get_mut(&peer)) else {
+            return;
+        };
+        source.failures += 1;
+        debug!("Request to {} failed ({}/{}): {}", peer, source.failures, MAX_SOURCE_FAILURES, error);
+        if source.failures >= MAX_SOURCE_FAILURES {
+            self.drop_source(file_id, peer);
+        }
+    }
+
+    /// Stop using `peer` for a download; its chunks in flight go back to the others
+    fn drop_source(&mut self, file_id: FileId, peer: PeerId) {
+        let Some(download) = self.downloads.get_mut(&file_id) else {
+            return;
+        };
+        download.sources.remove(&peer);
+        download.in_flight.retain(|_, source| *source != peer);
+        self.pump(file_id);
+    }
+
+    fn fail(&mut self, file_id: FileId, error: String) {
+        self.downloads.remove(&file_id);
+        self.update(FileUpdate::Failed { file_id, error });
+    }
+
+    /// Report on a transfer; a folder's files are reported as the folder
+    fn update(&mut self, update: FileUpdate) {
+        let file_id = match &update {
+            FileUpdate::Progress { file_id, .. } | FileUpdate::Completed { file_id, .. } | FileUpdate::Failed { file_id, .. } => *file_id,
+            FileUpdate::Offered { .. } | FileUpdate::FolderOffered { .. } => {
+                self.pending_events.push_back(FileEvent::Update(update));
+                return;
+            }
+        };
+        let Some(folder_id) = self.folder_of.get(&file_id).copied() else {
+            self.pending_events.push_back(FileEvent::Update(update));
+            return;
+        };
+        let Some(folder) = self.folders.get_mut(&folder_id).and_then(|folder| folder.download.as_mut()) else {
+            return;
+        };
+
+        match update {
+            FileUpdate::Progress { done, .. } => {
+                folder.progress.insert(file_id, done);
+                let (done, total) = (folder.progress.values().sum(), folder.total);
+                self.pending_events.push_back(FileEvent::Update(FileUpdate::Progress { file_id: folder_id, done, total }));
+            }
+            FileUpdate::Completed { .. } => {
+                folder.remaining -= 1;
+                if folder.remaining == 0 {
+                    self.finish_folder(folder_id);
+                }
+            }
+            FileUpdate::Failed { error, .. } => {
+                self.cancel(folder_id);
+                self.pending_events.push_back(FileEvent::Update(FileUpdate::Failed { file_id: folder_id, error }));
+            }
+            FileUpdate::Offered { .. } | FileUpdate::FolderOffered { .. } => unreachable!("handled above"),
+        }
+    }
+
+    /// Every file of a folder is in: set its directories' modes, deepest first
+    fn finish_folder(&mut self, folder_id: FileId) {
+        let Some(folder) = self.folders.get_mut(&folder_id).and_then(|folder| folder.download.take()) else {
+            return;
+        };
+        let mut dir_modes = folder.dir_modes;
+        dir_modes.sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+        for (path, mode) in dir_modes {
+            if let Err(e) = set_mode(&path, mode) {
+                warn!("Can't set the mode of {}: {}", path.display(), e);
+            }
+        }
+        let update = FileUpdate::Completed { file_id: folder_id, path: folder.root };
+        self.pending_events.push_back(FileEvent::Update(update));
+    }
+
+    /// Every chunk is in: keep serving the file to others, now that we have all of it
+    fn finish(&mut self, file_id: FileId) {
+        let Some(download) = self.downloads.remove(&file_id) else {
+            return;
+        };
+        if let Err(e) = download.file.sync_all() {
+            self.update(FileUpdate::Failed { file_id, error: e.to_string() });
+            return;
+        }
+        if let Some(mode) = download.mode {
+            if let Err(e) = set_mode(&download.path, mode) {
+                warn!("Can't set the mode of {}: {}", download.path.display(), e);
+            }
+        }
+
+        let leaves = download.chunks.iter().map(|chunk| chunk.as_ref().expect("all chunks in").leaf).collect();
+        self.shared.insert(file_id, Shared {
+            path: download.path.clone(),
+            manifest: download.manifest,
+            tree: MerkleTree::from_leaves(leaves),
+            peers: None,
+        });
+        self.update(FileUpdate::Completed { file_id, path: download.path });
+    }
+
+    fn on_offer_response(&mut self, file_id: FileId, response: &[u8]) {
+        let status = FileResponse::decode_from_bytes(response)
+            .map_err(|e| format!("Invalid response: {}", e))
+            .and_then(|response| FileStatus::try_from(response.status).map_err(|_| format!("Unknown status {}", response.status)));
+        match status {
+            Ok(FileStatus::FileOk) => debug!("File offer delivered"),
+            Ok(status) => self.fail_offer(file_id, status.as_str_name().to_string()),
+            Err(error) => self.fail_offer(file_id, error),
+        }
+    }
+
+    fn fail_offer(&mut self, file_id: FileId, error: String) {
+        self.update(FileUpdate::Failed { file_id, error });
+    }
+
+    /// Ask stale sources for their bitfield again once the refresh timer fires
+    fn poll_refresh(&mut self, cx: &mut std::task::Context) {
+        let stale = self.downloads.values()
+            .any(|download| !download.paused && download.sources.values().any(|source| source.stale));
+        if !stale {
+            self.refresh = None;
+            return;
+        }
+        let timer = self.refresh.get_or_insert_with(|| Box::pin(tokio::time::sleep(BITFIELD_REFRESH)));
+        if timer.as_mut().poll(cx).is_pending() {
+            return;
+        }
+        self.refresh = None;
+
+        let due: Vec<(FileId, PeerId)> = self.downloads.iter()
+            .filter(|(_, download)| !download.paused)
+            .flat_map(|(file_id, download)| {
+                download.sources.iter().filter(|(_, source)| source.stale).map(|(peer, _)| (*file_id, *peer))
+            })
+            .collect();
+        for (file_id, peer) in due {
+            self.ask_bitfield(file_id, peer);
+        }
+    }
+
+    fn on_request_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => match self.handle_request(peer, &request) {
+                    Reply::Now(response) => {
+                        let _ = self.requests.send_response(channel, response.encode_to_vec());
+                    }
+                    Reply::Chunk(read) => {
+                        let task = tokio::task::spawn_blocking(move || read.run());
+                        self.serving.push(Box::pin(async move {
+                            let response = task.await
+                                .unwrap_or_else(|_| FileResponse::with_status(FileStatus::FileUnavailable));
+                            (channel, response)
+                        }));
+                    }
+                },
+                request_response::Message::Response { request_id, response } => {
+                    match self.in_flight.remove(&request_id) {
+                        Some((_, RequestKind::Offer(file_id))) => self.on_offer_response(file_id, &response),
+                        Some((peer, RequestKind::Have(file_id))) => self.on_have_response(peer, file_id, &response),
+                        Some((peer, RequestKind::Chunk(file_id, index))) => {
+                            self.on_chunk_response(peer, file_id, index, &response);
+                        }
+                        None => {}
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure { request_id, error, .. } => {
+                match self.in_flight.remove(&request_id) {
+                    Some((_, RequestKind::Offer(file_id))) => self.fail_offer(file_id, error.to_string()),
+                    Some((peer, RequestKind::Have(file_id))) => {
+                        let source = self.downloads.get_mut(&file_id).and_then(|download| download.sources.get_mut(&peer));
+                        if let Some(source) = source {
+                            source.asking = false;
+                            source.stale = true;
+                        }
+                        self.source_failed(file_id, peer, error.to_string());
+                    }
+                    Some((peer, RequestKind::Chunk(file_id, index))) => {
+                        let Some(download) = self.downloads.get_mut(&file_id) else {
+                            return;
+                        };
+                        if download.in_flight.get(&index) == Some(&peer) {
+                            download.in_flight.remove(&index);
+                            if let Some(source) = download.sources.get_mut(&peer) {
+                                source.in_flight -= 1;
+                            }
+                            self.source_failed(file_id, peer, error.to_string());
+                            self.pump(file_id);
+                        }
+                    }
+                    None => {}
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Inbound file stream from {} failed: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+}
+
+impl Default for FileBehaviour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Size of the file at `path` and the Merkle tree over its chunks, read one chunk at a time
+pub fn hash_file(path: &Path, chunk_size: u32) -> io::Result<(u64, MerkleTree)> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; chunk_size as usize];
+    let mut leaves = Vec::new();
+    let mut size = 0u64;
+    loop {
+        let len = read_full(&mut file, &mut buf)?;
+        if len > 0 || leaves.is_empty() {
+            leaves.push(merkle::leaf_hash(&buf[..len]));
+        }
+        size += len as u64;
+        if len < buf.len() {
+            break;
+        }
+    }
+    Ok((size, MerkleTree::from_leaves(leaves)))
+}
+
+/// Directories and files under `root`, sorted, as '/'-separated paths relative to it, with
+/// their modes. Symlinks are left out: a folder offer never reaches outside its root.
+pub fn walk_folder(root: &Path) -> io::Result<Vec<(String, PathBuf, bool, u32)>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![(String::new(), root.to_path_buf())];
+    while let Some((prefix, dir)) = pending.pop() {
+        let mut children: Vec<fs::DirEntry> = fs::read_dir(&dir)?.collect::<io::Result<_>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let name = child.file_name().into_string()
+                .map_err(|name| io::Error::new(io::ErrorKind::InvalidData, format!("Not a UTF-8 name: {:?}", name)))?;
+            let relative = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+            let metadata = fs::symlink_metadata(child.path())?;
+            if metadata.is_dir() {
+                entries.push((relative.clone(), child.path(), true, mode_of(&metadata)));
+                pending.push((relative, child.path()));
+            } else if metadata.is_file() {
+                entries.push((relative, child.path(), false, mode_of(&metadata)));
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/// Lay out `manifest`'s directories under the fresh directory `root` and create its files,
+/// empty, ready to be downloaded into
+fn create_folder(root: &Path, manifest: &FolderManifest) -> crate::error::Result<Vec<(FileManifest, PathBuf, File, u32)>> {
+    let invalid = |e: umbra_wire::WireError| crate::error::NetError::InvalidMessage(e.to_string());
+    let canonical_root = root.canonicalize()?;
+    let mut files = Vec::new();
+    for entry in &manifest.entries {
+        let path = entry.path_under(root).map_err(invalid)?;
+        let dir = if entry.directory { path.as_path() } else { path.parent().unwrap_or(root) };
+        fs::create_dir_all(dir)?;
+        // Nothing on the way may lead out of the root
+        if !dir.canonicalize()?.starts_with(&canonical_root) {
+            return Err(crate::error::NetError::InvalidMessage(format!("{} is outside the folder", entry.path)));
+        }
+        if !entry.directory {
+            let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+            files.push((manifest.file_manifest(entry).map_err(invalid)?, path, file, entry.mode));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+/// Give `path` permission bits `mode`; nothing beyond rwx for owner, group and others
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+/// Fill `buf` as far as the file goes
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+fn read_chunk(path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut chunk = vec![0u8; len];
+    file.read_exact(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Chunks of a file travel as a STREAM keyed by the session, with the file id as its salt
+fn chunk_cipher(session_key: &[u8; 32], file_id: &FileId, manifest: &FileManifest) -> umbra_crypto::Result<StreamChunks> {
+    StreamChunks::new(session_key, file_id, manifest.chunk_size as usize, manifest.chunk_count)
+}
+
+/// Decrypt a chunk response and check it against the manifest's root
+fn open_chunk(
+    session_key: &[u8; 32],
+    download: &Download,
+    index: u64,
+    response: &[u8],
+) -> Result<(Zeroizing<Vec<u8>>, Verified), ChunkError> {
+    let response = FileResponse::decode_from_bytes(response)
+        .map_err(|e| ChunkError::Invalid(format!("Invalid response: {}", e)))?;
+    match FileStatus::try_from(response.status) {
+        Ok(FileStatus::FileOk) => {}
+        Ok(status) => return Err(ChunkError::Unavailable(status.as_str_name().to_string())),
+        Err(_) => return Err(ChunkError::Invalid(format!("Unknown status {}", response.status))),
+    }
+    if response.index != index {
+        return Err(ChunkError::Invalid(format!("Got chunk {} instead", response.index)));
+    }
+
+    let file_id = download.manifest.file_id().map_err(|e| ChunkError::Invalid(e.to_string()))?;
+    let chunk = chunk_cipher(session_key, &file_id, &download.manifest)
+        .and_then(|cipher| cipher.open(index, &response.ciphertext))
+        .map_err(|e| ChunkError::Invalid(e.to_string()))?;
+    let proof = response.proof.iter()
+        .map(|hash| <[u8; 32]>::try_from(hash.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ChunkError::Invalid("Malformed proof".to_string()))?;
+    let leaf = merkle::leaf_hash(&chunk);
+    let valid = chunk.len() == download.manifest.chunk_len(index)
+        && merkle::verify_proof(&download.root, index as usize, download.chunks.len(), &leaf, &proof);
+    if !valid {
+        return Err(ChunkError::Invalid("Chunk doesn't match the manifest".to_string()));
+    }
+    Ok((chunk, Verified { leaf, proof }))
+}
+
+// Runs on request_response over /umbra/file/1
+impl RequestProtocol for FileBehaviour {
+    type Event = FileEvent;
+
+    fn requests(&mut self) -> &mut Requests {
+        &mut self.requests
+    }
+
+    fn on_event(&mut self, event: RequestEvent) {
+        self.on_request_event(event);
+    }
+
+    fn pop_event(&mut self) -> Option<FileEvent> {
+        self.pending_events.pop_front()
+    }
+
+    fn poll_tasks(&mut self, cx: &mut Context) {
+        self.poll_refresh(cx);
+        while let Poll::Ready(Some((channel, response))) = self.serving.poll_next_unpin(cx) {
+            let _ = self.requests.send_response(channel, response.encode_to_vec());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use umbra_crypto::IdentityKey;
+
+    const SESSION_KEY: [u8; 32] = [5u8; 32];
+
+    impl FileBehaviour {
+        /// `handle_request`, reading any chunk right here
+        fn answer(&mut self, peer: PeerId, request: &[u8]) -> FileResponse {
+            match self.handle_request(peer, request) {
+                Reply::Now(response) => response,
+                Reply::Chunk(read) => read.run(),
+            }
+        }
+    }
+
+    /// Node 0 offering a file to every other node; all pairs share a session
+    struct Swarm {
+        nodes: Vec<FileBehaviour>,
+        ids: Vec<PeerId>,
+        file_id: FileId,
+        dir: tempfile::TempDir,
+        data: Vec<u8>,
+    }
+
+    fn swarm(len: usize, chunk_size: u32, nodes: usize) -> Swarm {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.bin");
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+
+        let ids: Vec<PeerId> = (0..nodes).map(|_| PeerId::random()).collect();
+        let mut behaviours: Vec<FileBehaviour> = (0..nodes).map(|_| FileBehaviour::new()).collect();
+        for (node, behaviour) in behaviours.iter_mut().enumerate() {
+            for (peer, id) in ids.iter().enumerate() {
+                if peer != node {
+                    behaviour.set_session_key(*id, SESSION_KEY);
+                }
+            }
+        }
+
+        let (size, tree) = hash_file(&source, chunk_size).unwrap();
+        let manifest = FileManifest::new(&IdentityKey::generate().unwrap(), "source.bin", size, chunk_size, tree.root()).unwrap();
+        let offer = FileRequest { request: Some(file_request::Request::Offer(manifest.clone())) }.encode_to_vec();
+        let mut file_id = [0u8; 32];
+        for node in 1..nodes {
+            file_id = behaviours[0].offer(ids[node], source.clone(), manifest.clone(), tree.clone()).unwrap();
+            let response = behaviours[node].answer(ids[0], &offer);
+            assert_eq!(response.status, FileStatus::FileOk as i32);
+            assert!(matches!(behaviours[node].pending_events.pop_front(), Some(FileEvent::Offered { .. })));
+        }
+        Swarm { nodes: behaviours, ids, file_id, dir, data }
+    }
+
+    impl Swarm {
+        fn dest(&self, node: usize) -> PathBuf {
+            self.dir.path().join(format!("received-{}.bin", node))
+        }
+
+        fn accept(&mut self, node: usize) {
+            let dest = self.dest(node);
+            self.nodes[node].accept(self.file_id, dest).unwrap();
+        }
+
+        fn download(&self, node: usize) -> &Download {
+            &self.nodes[node].downloads[&self.file_id]
+        }
+
+        /// Answer what `node` has asked for (as if over the wire), download by download:
+        /// bitfields first, then the chunks in `order`, tampering with those from `bad`
+        fn exchange(&mut self, node: usize, order: impl Fn(&mut Vec<(u64, PeerId)>), bad: Option<PeerId>) {
+            let mut file_ids: Vec<FileId> = self.nodes[node].downloads.keys().copied().collect();
+            file_ids.sort();
+            for file_id in file_ids {
+                self.exchange_file(node, file_id, &order, bad);
+            }
+        }
+
+        fn exchange_file(&mut self, node: usize, file_id: FileId, order: &impl Fn(&mut Vec<(u64, PeerId)>), bad: Option<PeerId>) {
+            let Some(download) = self.nodes[node].downloads.get(&file_id) else {
+                return;
+            };
+            let asking: Vec<PeerId> = download.sources.iter().filter(|(_, s)| s.asking).map(|(p, _)| *p).collect();
+            let mut chunks: Vec<(u64, PeerId)> = download.in_flight.iter().map(|(i, p)| (*i, *p)).collect();
+            chunks.sort();
+            order(&mut chunks);
+
+            for peer in asking {
+                let server = self.ids.iter().position(|id| *id == peer).unwrap();
+                let response = self.nodes[server].answer(self.ids[node], &have_request(file_id));
+                self.nodes[node].on_have_response(peer, file_id, &response.encode_to_vec());
+            }
+            for (index, peer) in chunks {
+                let server = self.ids.iter().position(|id| *id == peer).unwrap();
+                let mut response = self.nodes[server].answer(self.ids[node], &chunk_request(file_id, index));
+                if Some(peer) == bad {
+                    response.ciphertext[0] ^= 1;
+                }
+                self.nodes[node].on_chunk_response(peer, file_id, index, &response.encode_to_vec());
+            }
+        }
+
+        /// Exchange until `node` is done downloading; returns where the transfer went, if it
+        /// got it all
+        fn run(&mut self, node: usize) -> Option<PathBuf> {
+            for _ in 0..100 {
+                if self.nodes[node].downloads.is_empty() {
+                    break;
+                }
+                self.exchange(node, |_| {}, None);
+            }
+            let file_id = self.file_id;
+            self.nodes[node].pending_events.drain(..).find_map(|event| match event {
+                FileEvent::Update(FileUpdate::Completed { file_id: id, path }) if id == file_id => Some(path),
+                _ => None,
+            })
+        }
+    }
+
+    /// Node 0 offering node 1 a folder of a few files, an empty one and an empty directory
+    fn folder_swarm(name: &str) -> Swarm {
+        let mut s = swarm(3000, 1024, 2);
+        s.file_id = offer_folder(&mut s, name);
+        s
+    }
+
+    fn offer_folder(s: &mut Swarm, name: &str) -> FileId {
+        let source = s.dir.path().join("sources").join(name);
+        fs::create_dir_all(source.join("sub/deeper")).unwrap();
+        fs::create_dir_all(source.join("nothing")).unwrap();
+        fs::write(source.join("a.txt"), &s.data).unwrap();
+        fs::write(source.join("sub/b.bin"), vec![7u8; 5000]).unwrap();
+        fs::write(source.join("sub/deeper/empty.bin"), b"").unwrap();
+        set_mode(&source.join("sub/b.bin"), 0o600).unwrap();
+
+        let mut entries = Vec::new();
+        let mut files = Vec::new();
+        for (relative, full, directory, mode) in walk_folder(&source).unwrap() {
+            if directory {
+                entries.push(umbra_wire::file::FolderEntry::directory(&relative, mode));
+            } else {
+                let (size, tree) = hash_file(&full, 1024).unwrap();
+                entries.push(umbra_wire::file::FolderEntry::file(&relative, size, mode, tree.root()));
+                files.push((full, tree));
+            }
+        }
+        let manifest = FolderManifest::new(&IdentityKey::generate().unwrap(), name, 1024, entries).unwrap();
+        let folder_id = s.nodes[0].offer_folder(s.ids[1], manifest.clone(), files).unwrap();
+        let offer = FileRequest { request: Some(file_request::Request::Folder(manifest)) }.encode_to_vec();
+        let response = s.nodes[1].answer(s.ids[0], &offer);
+        assert_eq!(response.status, FileStatus::FileOk as i32);
+        assert!(matches!(s.nodes[1].pending_events.pop_front(), Some(FileEvent::FolderOffered { .. })));
+        folder_id
+    }
+
+    fn chunk_request(file_id: FileId, index: u64) -> Vec<u8> {
+        FileRequest {
+            request: Some(file_request::Request::Chunk(ChunkRequest { file_id: file_id.to_vec(), index })),
+        }
+        .encode_to_vec()
+    }
+
+    fn have_request(file_id: FileId) -> Vec<u8> {
+        FileRequest {
+            request: Some(file_request::Request::Have(HaveRequest { file_id: file_id.to_vec() })),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn test_transfer_out_of_order() {
+        let mut s = swarm(10_000, 1024, 2);
+        s.accept(1);
+        assert_eq!(s.download(1).in_flight.len(), WINDOW);
+
+        while s.nodes[1].downloads.contains_key(&s.file_id) {
+            s.exchange(1, |chunks| chunks.reverse(), None);
+        }
+        assert_eq!(s.run(1), Some(s.dest(1)));
+        assert_eq!(std::fs::read(s.dest(1)).unwrap(), s.data);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut s = swarm(10_000, 1024, 2);
+        s.accept(1);
+        assert!(s.nodes[1].pause(s.file_id));
+
+        // Paused: chunks in flight land, nothing new is asked for
+        s.exchange(1, |_| {}, None);
+        assert_eq!(s.download(1).done, WINDOW as u64);
+        assert!(s.download(1).in_flight.is_empty());
+
+        assert!(s.nodes[1].resume(s.file_id));
+        assert!(!s.download(1).in_flight.is_empty());
+        assert_eq!(s.run(1), Some(s.dest(1)));
+        assert_eq!(std::fs::read(s.dest(1)).unwrap(), s.data);
+    }
+
+    #[test]
+    fn test_rarest_first() {
+        let mut s = swarm(10_000, 1024, 3);
+        s.accept(1);
+        s.exchange(1, |_| {}, None);
+        s.exchange(1, |_| {}, None);
+        assert_eq!(s.download(1).done, 8);
+
+        // Node 2 gets 0..4 from the sender and learns node 1 has 0..8
+        s.accept(2);
+        s.nodes[2].pause(s.file_id);
+        s.nodes[2].add_source(s.file_id, s.ids[1]);
+        s.exchange(2, |_| {}, None);
+        s.nodes[2].resume(s.file_id);
+
+        // Only the sender has 8 and 9, so it's asked for those before anything else
+        let download = s.download(2);
+        assert_eq!(download.in_flight.len(), 6);
+        assert_eq!(download.in_flight[&8], s.ids[0]);
+        assert_eq!(download.in_flight[&9], s.ids[0]);
+    }
+
+    #[test]
+    fn test_chunks_passed_on_by_other_receivers() {
+        let mut s = swarm(10_000, 1024, 3);
+        s.accept(1);
+        s.exchange(1, |_| {}, None);
+
+        // The sender goes away: node 2 gets what node 1 has so far, proofs and all
+        s.accept(2);
+        s.nodes[2].add_source(s.file_id, s.ids[1]);
+        s.nodes[2].drop_source(s.file_id, s.ids[0]);
+        s.exchange(2, |_| {}, None);
+        s.exchange(2, |_| {}, None);
+        assert_eq!(s.download(2).done, 4);
+        assert!(s.download(2).sources[&s.ids[1]].stale);
+
+        // Node 1 finishes and keeps serving; asked again, it has the rest for node 2
+        assert_eq!(s.run(1), Some(s.dest(1)));
+        s.nodes[2].ask_bitfield(s.file_id, s.ids[1]);
+        assert_eq!(s.run(2), Some(s.dest(2)));
+        assert_eq!(std::fs::read(s.dest(2)).unwrap(), s.data);
+    }
+
+    #[test]
+    fn test_bad_source_dropped() {
+        let mut s = swarm(10_000, 1024, 3);
+        s.accept(1);
+        assert_eq!(s.run(1), Some(s.dest(1)));
+
+        s.accept(2);
+        s.nodes[2].add_source(s.file_id, s.ids[1]);
+        s.exchange(2, |_| {}, None);
+        assert!(s.download(2).in_flight.values().any(|peer| *peer == s.ids[1]));
+
+        // Node 1 turns bad: dropped on its first chunk, the sender fetches the rest
+        let bad = s.ids[1];
+        s.exchange(2, |_| {}, Some(bad));
+        assert!(!s.download(2).sources.contains_key(&bad));
+        assert!(s.download(2).in_flight.values().all(|peer| *peer == s.ids[0]));
+        assert_eq!(s.run(2), Some(s.dest(2)));
+        assert_eq!(std::fs::read(s.dest(2)).unwrap(), s.data);
+    }
+
+    #[test]
+    fn test_fails_without_sources() {
+        let mut s = swarm(3000, 1024, 2);
+        s.accept(1);
+        s.nodes[1].drop_source(s.file_id, s.ids[0]);
+        assert!(!s.nodes[1].downloads.contains_key(&s.file_id));
+        assert!(matches!(
+            s.nodes[1].pending_events.pop_front(),
+            Some(FileEvent::Update(FileUpdate::Failed { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_only_offered_peers_served() {
+        let mut s = swarm(3000, 1024, 2);
+        let stranger = PeerId::random();
+        s.nodes[0].set_session_key(stranger, SESSION_KEY);
+        let response = s.nodes[0].answer(stranger, &chunk_request(s.file_id, 0));
+        assert_eq!(response.status, FileStatus::FileUnknown as i32);
+        let response = s.nodes[0].answer(stranger, &have_request(s.file_id));
+        assert_eq!(response.status, FileStatus::FileUnknown as i32);
+    }
+
+    #[test]
+    fn test_changed_file_not_served() {
+        let mut s = swarm(3000, 1024, 2);
+        std::fs::write(s.dir.path().join("source.bin"), vec![0u8; 3000]).unwrap();
+        let response = s.nodes[0].answer(s.ids[1], &chunk_request(s.file_id, 1));
+        assert_eq!(response.status, FileStatus::FileUnavailable as i32);
+    }
+
+    #[test]
+    fn test_folder_transfer() {
+        let mut s = folder_swarm("project");
+        let into = s.dir.path().join("into");
+        fs::create_dir(&into).unwrap();
+        let root = s.nodes[1].accept_folder(s.file_id, &into).unwrap();
+        assert_eq!(root, into.join("project"));
+        assert_eq!(s.nodes[1].downloads.len(), 3);
+
+        assert_eq!(s.run(1), Some(root.clone()));
+        let source = s.dir.path().join("sources/project");
+        for file in ["a.txt", "sub/b.bin", "sub/deeper/empty.bin"] {
+            assert_eq!(fs::read(root.join(file)).unwrap(), fs::read(source.join(file)).unwrap(), "{}", file);
+        }
+        assert!(root.join("nothing").is_dir());
+        #[cfg(unix)]
+        assert_eq!(mode_of(&fs::metadata(root.join("sub/b.bin")).unwrap()), 0o600);
+    }
+
+    #[test]
+    fn test_folder_progress_reported_as_folder() {
+        let mut s = folder_swarm("project");
+        let into = s.dir.path().join("into");
+        fs::create_dir(&into).unwrap();
+        s.nodes[1].accept_folder(s.file_id, &into).unwrap();
+        s.exchange(1, |_| {}, None);
+
+        let folder_id = s.file_id;
+        let total = s.nodes[1].folders[&folder_id].download.as_ref().unwrap().total;
+        assert_eq!(total, 3 + 5 + 1);
+        let progress: Vec<u64> = s.nodes[1].pending_events.iter()
+            .map(|event| match event {
+                FileEvent::Update(FileUpdate::Progress { file_id, done, total: 9 }) if *file_id == folder_id => *done,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_concurrent_folders() {
+        let mut s = folder_swarm("first");
+        let first = s.file_id;
+        let second = offer_folder(&mut s, "second");
+        let into = s.dir.path().join("into");
+        fs::create_dir(&into).unwrap();
+        s.nodes[1].accept_folder(first, &into).unwrap();
+        s.nodes[1].accept_folder(second, &into).unwrap();
+
+        for _ in 0..100 {
+            s.exchange(1, |_| {}, None);
+        }
+        let completed: HashSet<FileId> = s.nodes[1].pending_events.drain(..)
+            .filter_map(|event| match event {
+                FileEvent::Update(FileUpdate::Completed { file_id, .. }) => Some(file_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(completed, HashSet::from([first, second]));
+        assert_eq!(fs::read(into.join("second/sub/b.bin")).unwrap(), vec![7u8; 5000]);
+    }
+
+    #[test]
+    fn test_folder_not_extracted_over_existing() {
+        let mut s = folder_swarm("project");
+        let into = s.dir.path().join("into");
+        fs::create_dir_all(into.join("project")).unwrap();
+        fs::write(into.join("project/a.txt"), b"mine").unwrap();
+        assert!(s.nodes[1].accept_folder(s.file_id, &into).is_err());
+        assert_eq!(fs::read(into.join("project/a.txt")).unwrap(), b"mine");
+
+        // The offer stands, for somewhere else
+        let elsewhere = s.dir.path().join("elsewhere");
+        fs::create_dir(&elsewhere).unwrap();
+        s.nodes[1].accept_folder(s.file_id, &elsewhere).unwrap();
+        assert_eq!(s.run(1), Some(elsewhere.join("project")));
+    }
+
+    #[test]
+    fn test_failed_accept_rolled_back() {
+        let mut s = folder_swarm("project");
+        let folder_id = s.file_id;
+        // The folder's second file can't be started once the first one is
+        let entry = s.nodes[1].folder_offers[&folder_id].1.entries.iter()
+            .rposition(|entry| !entry.directory)
+            .unwrap();
+        let root = std::mem::take(&mut s.nodes[1].folder_offers.get_mut(&folder_id).unwrap().1.entries[entry].root);
+
+        assert!(s.nodes[1].accept_folder(folder_id, s.dir.path()).is_err());
+        assert!(s.nodes[1].downloads.is_empty());
+        assert!(s.nodes[1].folders.is_empty());
+        assert!(s.nodes[1].folder_of.is_empty());
+        assert!(!s.dir.path().join("project").exists());
+
+        // The offer is still there to accept
+        s.nodes[1].folder_offers.get_mut(&folder_id).unwrap().1.entries[entry].root = root;
+        s.nodes[1].accept_folder(folder_id, s.dir.path()).unwrap();
+        assert_eq!(s.run(1), Some(s.dir.path().join("project")));
+    }
+
+    #[test]
+    fn test_failed_file_fails_folder() {
+        let mut s = folder_swarm("project");
+        let into = s.dir.path().join("into");
+        fs::create_dir(&into).unwrap();
+        s.nodes[1].accept_folder(s.file_id, &into).unwrap();
+        let file_id = *s.nodes[1].downloads.keys().next().unwrap();
+        s.nodes[1].drop_source(file_id, s.ids[0]);
+
+        assert!(s.nodes[1].downloads.is_empty());
+        assert!(!s.nodes[1].folders.contains_key(&s.file_id));
+        assert!(matches!(
+            s.nodes[1].pending_events.pop_back(),
+            Some(FileEvent::Update(FileUpdate::Failed { file_id, .. })) if file_id == s.file_id
+        ));
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let mut s = swarm(0, 1024, 2);
+        s.accept(1);
+        assert_eq!(s.run(1), Some(s.dest(1)));
+        assert!(std::fs::read(s.dest(1)).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,647 @@
+// MLS groups behind topic chat, and the control messages that change them
+// Invites and join requests go point-to-point; a GroupChange (an MLS commit plus a summary,
+// signed by the committer) is published on the group's topic, and sent straight to a member it
+// adds so they get the Welcome. The wire types carry MLS objects as opaque bytes: everything
+// that builds, signs or applies them lives here.
+
+use crate::error::{NetError, Result};
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use umbra_crypto::{HybridVerifyingKey, IdentityKey};
+use umbra_mls::codec::{Decode, Encode};
+use umbra_mls::roles::GroupRoles;
+use umbra_mls::{
+    CipherSuite, CommitOutput, Credential, Group, KeyPackage, KeyPackageBundle, MlsError, MlsMessage, ProcessedMessage,
+    Proposal, SignatureKeyPair,
+};
+use umbra_wire::group::{
+    group_change, group_control, AddMember, GroupChange, GroupControl, GroupInvite, GroupMetadata, JoinRequest,
+    RemoveMember, Role, SetRole,
+};
+
+const INVITE_LABEL: &str = "GroupInvite";
+const JOIN_REQUEST_LABEL: &str = "JoinRequest";
+const CHANGE_LABEL: &str = "GroupChange";
+
+/// What a node learns about the groups it's in (or invited to)
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupUpdate {
+    /// `inviter` asked us into the group on `topic`; `accept_group_invite` answers
+    Invited { topic: String, inviter: PeerId, metadata: GroupMetadata },
+    /// Our join request was granted and the Welcome processed
+    Joined { topic: String },
+    /// A commit by `by` took effect; `change` is what the commit itself does
+    Changed { topic: String, by: PeerId, change: group_change::Change },
+    /// A commit removed us
+    Removed { topic: String },
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn encode<M: prost::Message>(message: &M) -> Vec<u8> {
+    message.encode_to_vec()
+}
+
+fn invalid(reason: &str) -> NetError {
+    NetError::InvalidMessage(reason.to_string())
+}
+
+fn role_to_wire(role: umbra_mls::Role) -> Role {
+    match role {
+        umbra_mls::Role::Member => Role::Member,
+        umbra_mls::Role::Admin => Role::Admin,
+    }
+}
+
+fn role_from_wire(role: Role) -> umbra_mls::Role {
+    match role {
+        Role::Member => umbra_mls::Role::Member,
+        Role::Admin => umbra_mls::Role::Admin,
+    }
+}
+
+pub fn metadata_to_wire(metadata: &umbra_mls::GroupMetadata) -> GroupMetadata {
+    GroupMetadata {
+        name: metadata.name.clone(),
+        topic: metadata.topic.clone(),
+    }
+}
+
+fn metadata_from_wire(metadata: &GroupMetadata) -> umbra_mls::GroupMetadata {
+    umbra_mls::GroupMetadata {
+        name: metadata.name.clone(),
+        topic: metadata.topic.clone(),
+    }
+}
+
+/// Signed invitation to `group`; only admins may invite
+pub fn invite(group: &Group) -> Result<GroupInvite> {
+    if !group.is_admin(group.own_leaf_index()) {
+        return Err(MlsError::NotAdmin.into());
+    }
+    let mut invite = GroupInvite {
+        group_id: group.id().0,
+        metadata: Some(metadata_to_wire(&group.metadata())),
+        cipher_suite: group.cipher_suite().id() as u32,
+        inviter_key: group.signature_key().to_vec(),
+        timestamp: now(),
+        signature: Vec::new(),
+    };
+    invite.signature = group.sign_with_label(INVITE_LABEL, &encode(&invite))?;
+    Ok(invite)
+}
+
+/// Check the inviter's signature (whether they are an admin is up to the group); returns the
+/// ciphersuite the joiner's KeyPackage must use
+pub fn verify_invite(invite: &GroupInvite) -> Result<CipherSuite> {
+    let id = u16::try_from(invite.cipher_suite).map_err(|_| invalid("Unknown ciphersuite"))?;
+    let suite = CipherSuite::from_id(id)?;
+    let unsigned = GroupInvite { signature: Vec::new(), ..invite.clone() };
+    suite.verify_with_label(&invite.inviter_key, INVITE_LABEL, &encode(&unsigned), &invite.signature)?;
+    Ok(suite)
+}
+
+/// Ask to be added to `group_id` with the bundle's KeyPackage
+pub fn join_request(group_id: &[u8], bundle: &KeyPackageBundle) -> Result<JoinRequest> {
+    let mut request = JoinRequest {
+        group_id: group_id.to_vec(),
+        key_package: bundle.key_package.to_bytes(),
+        timestamp: now(),
+        signature: Vec::new(),
+    };
+    request.signature = bundle.sign_with_label(JOIN_REQUEST_LABEL, &encode(&request))?;
+    Ok(request)
+}
+
+/// The verified KeyPackage to add
+pub fn verify_join_request(request: &JoinRequest) -> Result<KeyPackage> {
+    let key_package = KeyPackage::from_bytes(&request.key_package)?;
+    let suite = key_package.cipher_suite;
+    key_package.verify(suite)?;
+
+    let unsigned = JoinRequest { signature: Vec::new(), ..request.clone() };
+    suite.verify_with_label(
+        &key_package.leaf_node.signature_key,
+        JOIN_REQUEST_LABEL,
+        &encode(&unsigned),
+        &request.signature,
+    )?;
+    Ok(key_package)
+}
+
+/// Add the member behind a verified join request
+pub fn add_member(group: &mut Group, key_package: KeyPackage) -> Result<GroupChange> {
+    let change = group_change::Change::Add(AddMember {
+        identity: key_package.leaf_node.credential.identity.clone(),
+    });
+    let output = group.add_member(key_package)?;
+    signed(group, change, output)
+}
+
+pub fn remove_member(group: &mut Group, leaf_index: u32) -> Result<GroupChange> {
+    let output = group.remove_member(leaf_index)?;
+    signed(group, group_change::Change::Remove(RemoveMember { leaf_index }), output)
+}
+
+/// Promote (`Role::Admin`) or demote (`Role::Member`) a member
+pub fn set_role(group: &mut Group, leaf_index: u32, role: Role) -> Result<GroupChange> {
+    if group.role(leaf_index) == Some(role_from_wire(role)) {
+        return Err(MlsError::InvalidProposal("Member already has that role".to_string()).into());
+    }
+    let output = group.set_role(leaf_index, role_from_wire(role))?;
+    let change = group_change::Change::SetRole(SetRole { leaf_index, role: role as i32 });
+    signed(group, change, output)
+}
+
+pub fn set_metadata(group: &mut Group, metadata: GroupMetadata) -> Result<GroupChange> {
+    if metadata_to_wire(&group.metadata()) == metadata {
+        return Err(MlsError::InvalidProposal("Metadata unchanged".to_string()).into());
+    }
+    let output = group.set_metadata(metadata_from_wire(&metadata))?;
+    signed(group, group_change::Change::Metadata(metadata), output)
+}
+
+fn signed(group: &Group, change: group_change::Change, output: CommitOutput) -> Result<GroupChange> {
+    let MlsMessage::Public(commit) = &output.commit else {
+        return Err(invalid("Commit must be a PublicMessage"));
+    };
+    let mut message = GroupChange {
+        group_id: group.id().0,
+        epoch: commit.content.epoch,
+        sender: commit.content.sender,
+        change: Some(change),
+        commit: output.commit.to_bytes(),
+        welcome: output.welcome.map(|w| w.to_bytes()).unwrap_or_default(),
+        signature: Vec::new(),
+    };
+    message.signature = group.sign_with_label(CHANGE_LABEL, &encode(&message))?;
+    Ok(message)
+}
+
+/// Verify the committer's signature against our view of the group and that the summary is
+/// what the commit actually does, then apply the commit
+pub fn apply_change(group: &mut Group, change: &GroupChange) -> Result<ProcessedMessage> {
+    if change.group_id != group.id().0 {
+        return Err(MlsError::WrongGroup.into());
+    }
+    let commit = MlsMessage::from_bytes(&change.commit)?;
+    match &commit {
+        MlsMessage::Public(public) if public.content.sender == change.sender && public.content.epoch == change.epoch => {}
+        _ => return Err(invalid("Commit doesn't match the change")),
+    }
+
+    let sender = group
+        .members()
+        .into_iter()
+        .find(|m| m.leaf_index == change.sender)
+        .ok_or(MlsError::MemberNotFound)?;
+    let unsigned = GroupChange { signature: Vec::new(), ..change.clone() };
+    group
+        .cipher_suite()
+        .verify_with_label(&sender.signature_key, CHANGE_LABEL, &encode(&unsigned), &change.signature)?;
+
+    // The committer signs the summary, but only the commit is enforced: they must agree
+    let summary = summarize(group, &group.commit_proposals(&commit)?)?;
+    if change.change.as_ref() != Some(&summary) {
+        return Err(invalid("Change summary doesn't match the commit"));
+    }
+
+    Ok(group.process_message(&commit)?)
+}
+
+/// Welcome for the member this change added, if any
+pub fn welcome(change: &GroupChange) -> Result<Option<MlsMessage>> {
+    if change.welcome.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(MlsMessage::from_bytes(&change.welcome)?))
+}
+
+/// What a commit's proposals do to `group`, in GroupChange terms (one change per commit)
+fn summarize(group: &Group, proposals: &[Proposal]) -> Result<group_change::Change> {
+    let [proposal] = proposals else {
+        return Err(invalid("A change commits exactly one proposal"));
+    };
+    match proposal {
+        Proposal::Add(key_package) => Ok(group_change::Change::Add(AddMember {
+            identity: key_package.leaf_node.credential.identity.clone(),
+        })),
+        Proposal::Remove(leaf_index) => Ok(group_change::Change::Remove(RemoveMember { leaf_index: *leaf_index })),
+        Proposal::GroupContextExtensions(extensions) => {
+            let metadata = umbra_mls::GroupMetadata::from_extensions(extensions)?;
+            let roles = GroupRoles::from_extensions(extensions)?;
+            let changed: Vec<_> = group
+                .members()
+                .into_iter()
+                .filter(|m| roles.role(&m.signature_key) != m.role)
+                .collect();
+            match (metadata != group.metadata(), changed.as_slice()) {
+                (true, []) => Ok(group_change::Change::Metadata(metadata_to_wire(&metadata))),
+                (false, [member]) => Ok(group_change::Change::SetRole(SetRole {
+                    leaf_index: member.leaf_index,
+                    role: role_to_wire(roles.role(&member.signature_key)) as i32,
+                })),
+                _ => Err(invalid("Extensions must change the metadata or one role")),
+            }
+        }
+        Proposal::Update(_) => Err(invalid("Updates aren't group changes")),
+    }
+}
+
+/// Peer behind a member's credential (node groups use the PeerId as the identity)
+fn member_peer(identity: &[u8]) -> Option<PeerId> {
+    PeerId::from_bytes(identity).ok()
+}
+
+fn topic_of(group_id: &[u8]) -> Result<String> {
+    String::from_utf8(group_id.to_vec()).map_err(|_| invalid("Group id isn't a topic"))
+}
+
+/// A node's MLS groups, one per topic (the group id is the topic), and the invites in flight
+pub struct Groups {
+    local_peer_id: PeerId,
+    signer: SignatureKeyPair,
+    groups: HashMap<String, Group>,
+    /// Invites we haven't answered yet, by topic
+    invites: HashMap<String, (PeerId, GroupInvite)>,
+    /// Our KeyPackage for each group we asked to join, until its Welcome arrives
+    joining: HashMap<String, KeyPackageBundle>,
+    /// Peers we invited and haven't added yet
+    invited: HashSet<(String, PeerId)>,
+}
+
+impl Groups {
+    /// Groups signed with the node's hybrid identity, so members are bound to the keys peers
+    /// learn in the handshake
+    pub fn new(local_peer_id: PeerId, identity: IdentityKey) -> Self {
+        Self {
+            local_peer_id,
+            signer: SignatureKeyPair::from_identity(identity),
+            groups: HashMap::new(),
+            invites: HashMap::new(),
+            joining: HashMap::new(),
+            invited: HashSet::new(),
+        }
+    }
+
+    fn credential(&self) -> Credential {
+        Credential::basic(self.local_peer_id.to_bytes())
+    }
+
+    /// Start the group for `topic` with us as its only member and admin (no-op if we're in it)
+    pub fn create(&mut self, topic: &str) -> Result<()> {
+        if !self.groups.contains_key(topic) {
+            let group = Group::create_with_id(topic.as_bytes().to_vec(), self.credential(), self.signer.clone())?;
+            self.groups.insert(topic.to_string(), group);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, topic: &str) -> Option<&Group> {
+        self.groups.get(topic)
+    }
+
+    pub fn get_mut(&mut self, topic: &str) -> Result<&mut Group> {
+        self.groups.get_mut(topic).ok_or_else(|| NetError::NotInGroup(topic.to_string()))
+    }
+
+    /// Forget our group on `topic`; an invite to it is kept, so it can still be accepted
+    pub fn leave(&mut self, topic: &str) -> bool {
+        self.joining.remove(topic);
+        self.groups.remove(topic).is_some()
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.groups.keys().map(String::as_str)
+    }
+
+    /// Other members of the group on `topic`
+    pub fn members(&self, topic: &str) -> Vec<PeerId> {
+        self.groups.get(topic).map_or_else(Vec::new, |group| {
+            group
+                .members()
+                .iter()
+                .filter_map(|m| member_peer(&m.identity))
+                .filter(|peer| *peer != self.local_peer_id)
+                .collect()
+        })
+    }
+
+    /// Leaf index of `peer` in the group on `topic`
+    pub fn leaf_of(&self, topic: &str, peer: &PeerId) -> Result<u32> {
+        self.groups
+            .get(topic)
+            .ok_or_else(|| NetError::NotInGroup(topic.to_string()))?
+            .member_index(&peer.to_bytes())
+            .ok_or_else(|| MlsError::MemberNotFound.into())
+    }
+
+    /// Seal `plaintext` as an MLS application message for the group on `topic`
+    pub fn encrypt(&mut self, topic: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.get_mut(topic)?.encrypt_application_message(plaintext)?.to_bytes())
+    }
+
+    /// Open an application message for one of our groups: its topic, the member who sent it
+    /// (from their leaf credential) and the plaintext. Commits only come in through `on_change`.
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<(String, PeerId, Vec<u8>)> {
+        let message = MlsMessage::from_bytes(data)?;
+        let topic = match &message {
+            MlsMessage::Private(private) if private.is_application() => topic_of(&private.group_id)?,
+            _ => return Err(invalid("Not an MLS application message")),
+        };
+        let group = self.get_mut(&topic)?;
+        let ProcessedMessage::Application { sender, data } = group.process_message(&message)? else {
+            return Err(invalid("Not an MLS application message"));
+        };
+        let peer = group
+            .members()
+            .into_iter()
+            .find(|m| m.leaf_index == sender)
+            .and_then(|m| member_peer(&m.identity))
+            .ok_or(MlsError::MemberNotFound)?;
+        Ok((topic, peer, data))
+    }
+
+    /// Invite `peer` (admins only); returns the control message to send them
+    pub fn invite(&mut self, topic: &str, peer: PeerId) -> Result<GroupControl> {
+        let invite = invite(self.get_mut(topic)?)?;
+        self.invited.insert((topic.to_string(), peer));
+        Ok(GroupControl { message: Some(group_control::Message::Invite(invite)) })
+    }
+
+    /// Keep an invite from `inviter` until it's accepted; returns its topic
+    pub fn on_invite(&mut self, inviter: PeerId, invite: GroupInvite) -> Result<String> {
+        if verify_invite(&invite)? != self.signer.cipher_suite() {
+            return Err(invalid("Group uses a ciphersuite we don't sign with"));
+        }
+        let topic = topic_of(&invite.group_id)?;
+        self.invites.insert(topic.clone(), (inviter, invite));
+        Ok(topic)
+    }
+
+    /// Answer the invite for `topic`: returns the inviter and the join request to send them.
+    /// Fails while we're still in a group on that topic (even one of our own with nobody
+    /// else in it); leave it first.
+    pub fn accept_invite(&mut self, topic: &str) -> Result<(PeerId, GroupControl)> {
+        if self.groups.contains_key(topic) {
+            return Err(NetError::InGroup(topic.to_string()));
+        }
+        let (inviter, invite) = self.invites.remove(topic).ok_or_else(|| NetError::NotInGroup(topic.to_string()))?;
+        let bundle = KeyPackageBundle::new(self.credential(), self.signer.clone())?;
+        let request = join_request(&invite.group_id, &bundle)?;
+
+        self.joining.insert(topic.to_string(), bundle);
+        Ok((inviter, GroupControl { message: Some(group_control::Message::JoinRequest(request)) }))
+    }
+
+    /// Add `peer` if we invited them and the request carries their own KeyPackage, signed with
+    /// the identity key (`peer_key`) they proved in the handshake; returns the topic and the
+    /// change to publish (and send to them, for the Welcome)
+    pub fn on_join_request(
+        &mut self,
+        peer: PeerId,
+        peer_key: &HybridVerifyingKey,
+        request: JoinRequest,
+    ) -> Result<(String, GroupChange)> {
+        let topic = topic_of(&request.group_id)?;
+        if !self.invited.contains(&(topic.clone(), peer)) {
+            return Err(invalid("Join request without an invite"));
+        }
+        let key_package = verify_join_request(&request)?;
+        let mut signature_key = peer_key.classical().to_bytes().to_vec();
+        signature_key.extend_from_slice(peer_key.pq().unwrap_or_default());
+        if key_package.leaf_node.credential.identity != peer.to_bytes()
+            || key_package.leaf_node.signature_key != signature_key
+        {
+            return Err(invalid("KeyPackage belongs to another peer"));
+        }
+
+        let change = add_member(self.get_mut(&topic)?, key_package)?;
+        self.invited.remove(&(topic.clone(), peer));
+        Ok((topic, change))
+    }
+
+    /// Apply a change to our group, or join from its Welcome if it adds us. `None` for a change
+    /// that's already in (the same commit can arrive directly and on the topic).
+    pub fn on_change(&mut self, change: &GroupChange) -> Result<Option<GroupUpdate>> {
+        let topic = topic_of(&change.group_id)?;
+
+        if let Some(group) = self.groups.get_mut(&topic) {
+            if change.epoch < group.epoch() {
+                return Ok(None);
+            }
+            let by = group
+                .members()
+                .into_iter()
+                .find(|m| m.leaf_index == change.sender)
+                .and_then(|m| member_peer(&m.identity))
+                .ok_or(MlsError::MemberNotFound)?;
+            return match apply_change(group, change)? {
+                ProcessedMessage::Removed { .. } => {
+                    self.groups.remove(&topic);
+                    Ok(Some(GroupUpdate::Removed { topic }))
+                }
+                _ => {
+                    let change = change.change.clone().ok_or_else(|| invalid("Change without a summary"))?;
+                    Ok(Some(GroupUpdate::Changed { topic, by, change }))
+                }
+            };
+        }
+
+        // Not in it yet: this may be the Welcome we're waiting for
+        let Some(MlsMessage::Welcome(welcome)) = welcome(change)? else {
+            return Err(NetError::NotInGroup(topic));
+        };
+        let ours = self.joining.get(&topic).map(|bundle| bundle.key_package.reference());
+        if !ours.is_some_and(|reference| welcome.secrets.iter().any(|s| s.new_member == reference)) {
+            return Err(NetError::NotInGroup(topic));
+        }
+        let bundle = self.joining.remove(&topic).ok_or_else(|| NetError::NotInGroup(topic.clone()))?;
+        let group = Group::join(&welcome, bundle)?;
+        self.groups.insert(topic.clone(), group);
+        Ok(Some(GroupUpdate::Joined { topic }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: CipherSuite = CipherSuite::Curve25519ChaCha20;
+
+    fn bundle(name: &str) -> KeyPackageBundle {
+        KeyPackageBundle::new(Credential::basic(name.as_bytes()), SUITE.generate_signature_key_pair().unwrap()).unwrap()
+    }
+
+    fn create(name: &str) -> Group {
+        Group::create(Credential::basic(name.as_bytes()), SUITE.generate_signature_key_pair().unwrap()).unwrap()
+    }
+
+    fn roundtrip(message: group_control::Message) -> group_control::Message {
+        let bytes = GroupControl { message: Some(message) }.encode_to_vec();
+        GroupControl::decode_from_bytes(&bytes).unwrap().message.unwrap()
+    }
+
+    /// Alice's group with Bob added
+    fn pair() -> (Group, Group) {
+        let mut alice = create("alice");
+        let bob_bundle = bundle("bob");
+        let change = add_member(&mut alice, bob_bundle.key_package.clone()).unwrap();
+        let Some(MlsMessage::Welcome(welcome)) = welcome(&change).unwrap() else { panic!("no welcome") };
+        let bob = Group::join(&welcome, bob_bundle).unwrap();
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_invite_join_and_add() {
+        let mut alice = create("alice");
+        let mut sent = invite(&alice).unwrap();
+        let group_control::Message::Invite(received) = roundtrip(group_control::Message::Invite(sent.clone())) else {
+            panic!("expected invite")
+        };
+        assert_eq!(verify_invite(&received).unwrap(), SUITE);
+
+        sent.timestamp += 1;
+        assert!(verify_invite(&sent).is_err());
+
+        let bob = bundle("bob");
+        let request = join_request(&received.group_id, &bob).unwrap();
+        let key_package = verify_join_request(&request).unwrap();
+
+        let change = add_member(&mut alice, key_package).unwrap();
+        let Some(MlsMessage::Welcome(welcome)) = welcome(&change).unwrap() else { panic!("no welcome") };
+        let bob = Group::join(&welcome, bob).unwrap();
+        assert_eq!(bob.epoch(), alice.epoch());
+    }
+
+    #[test]
+    fn test_forged_join_request_rejected() {
+        let bob = bundle("bob");
+        let mut request = join_request(b"group", &bob).unwrap();
+        request.group_id = b"other group".to_vec();
+        assert!(verify_join_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_changes_applied_by_members() {
+        let (mut alice, mut bob) = pair();
+
+        let metadata = GroupMetadata {
+            name: "umbra".to_string(),
+            topic: "wire formats".to_string(),
+        };
+        let change = set_metadata(&mut alice, metadata.clone()).unwrap();
+        let group_control::Message::Change(change) = roundtrip(group_control::Message::Change(change)) else {
+            panic!("expected change")
+        };
+        apply_change(&mut bob, &change).unwrap();
+        assert_eq!(metadata_to_wire(&bob.metadata()), metadata);
+
+        // A tampered summary breaks the committer's signature
+        let mut change = set_role(&mut alice, 1, Role::Admin).unwrap();
+        change.change = Some(group_change::Change::SetRole(SetRole { leaf_index: 0, role: Role::Member as i32 }));
+        assert!(apply_change(&mut bob, &change).is_err());
+    }
+
+    #[test]
+    fn test_summary_must_match_commit() {
+        let (mut alice, mut bob) = pair();
+
+        // Signed by a real admin, but the commit promotes Bob while the summary says rename
+        let output = alice.set_role(1, umbra_mls::Role::Admin).unwrap();
+        let summary = group_change::Change::Metadata(GroupMetadata { name: "harmless".to_string(), topic: String::new() });
+        let change = signed(&alice, summary, output).unwrap();
+        assert!(matches!(apply_change(&mut bob, &change), Err(NetError::InvalidMessage(_))));
+        assert!(!bob.is_admin(1));
+    }
+
+    #[test]
+    fn test_members_cannot_invite_or_change() {
+        let (_alice, mut bob) = pair();
+        assert!(invite(&bob).is_err());
+        assert!(remove_member(&mut bob, 0).is_err());
+        assert!(set_role(&mut bob, 1, Role::Admin).is_err());
+    }
+
+    #[test]
+    fn test_node_groups_invite_and_join() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let bob_identity = IdentityKey::generate().unwrap();
+        let bob_key = bob_identity.hybrid_verifying_key();
+        let mut alice = Groups::new(alice_peer, IdentityKey::generate().unwrap());
+        let mut bob = Groups::new(bob_peer, bob_identity);
+        alice.create("room").unwrap();
+        bob.create("room").unwrap();
+
+        let Some(group_control::Message::Invite(invite)) = alice.invite("room", bob_peer).unwrap().message else {
+            panic!("expected invite")
+        };
+        assert_eq!(bob.on_invite(alice_peer, invite).unwrap(), "room");
+
+        // Bob's own group on the topic isn't silently dropped: he has to leave it first
+        assert!(matches!(bob.accept_invite("room"), Err(NetError::InGroup(_))));
+        assert!(bob.get("room").is_some());
+        assert!(bob.leave("room"));
+        let (inviter, request) = bob.accept_invite("room").unwrap();
+        assert_eq!(inviter, alice_peer);
+        let Some(group_control::Message::JoinRequest(request)) = request.message else { panic!("expected request") };
+
+        // Only the peer we invited may use the invite, with the key they shook hands with
+        assert!(alice.on_join_request(PeerId::random(), &bob_key, request.clone()).is_err());
+        let other_key = IdentityKey::generate().unwrap().hybrid_verifying_key();
+        assert!(alice.on_join_request(bob_peer, &other_key, request.clone()).is_err());
+        let (topic, change) = alice.on_join_request(bob_peer, &bob_key, request).unwrap();
+        assert_eq!(topic, "room");
+
+        assert_eq!(bob.on_change(&change).unwrap(), Some(GroupUpdate::Joined { topic: "room".to_string() }));
+        assert_eq!(bob.on_change(&change).unwrap(), None);
+        assert_eq!(alice.members("room"), vec![bob_peer]);
+        assert_eq!(bob.members("room"), vec![alice_peer]);
+        assert_eq!(bob.get("room").unwrap().epoch(), alice.get("room").unwrap().epoch());
+
+        // Chat under the group's keys, attributed to the sender's leaf
+        let data = bob.encrypt("room", b"hi alice").unwrap();
+        assert_eq!(alice.decrypt(&data).unwrap(), ("room".to_string(), bob_peer, b"hi alice".to_vec()));
+        assert!(alice.decrypt(&data).is_err());
+    }
+
+    #[test]
+    fn test_removed_member_cannot_read() {
+        let peers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        let identities: Vec<IdentityKey> = (0..3).map(|_| IdentityKey::generate().unwrap()).collect();
+        let mut groups: Vec<Groups> = peers.iter().zip(&identities).map(|(peer, id)| Groups::new(*peer, id.clone())).collect();
+        groups[0].create("room").unwrap();
+
+        // Alice adds Bob then Carol; every member applies each change
+        for joiner in 1..3 {
+            let Some(group_control::Message::Invite(invite)) = groups[0].invite("room", peers[joiner]).unwrap().message else {
+                panic!("expected invite")
+            };
+            groups[joiner].on_invite(peers[0], invite).unwrap();
+            let (_, request) = groups[joiner].accept_invite("room").unwrap();
+            let Some(group_control::Message::JoinRequest(request)) = request.message else { panic!("expected request") };
+            let key = identities[joiner].hybrid_verifying_key();
+            let (_, change) = groups[0].on_join_request(peers[joiner], &key, request).unwrap();
+            for member in groups.iter_mut().take(joiner + 1).skip(1) {
+                member.on_change(&change).unwrap();
+            }
+        }
+
+        let data = groups[1].encrypt("room", b"all three").unwrap();
+        assert_eq!(groups[2].decrypt(&data).unwrap().2, b"all three");
+
+        // Carol is removed: she learns it, and can't read what follows
+        let leaf = groups[0].leaf_of("room", &peers[2]).unwrap();
+        let change = remove_member(groups[0].get_mut("room").unwrap(), leaf).unwrap();
+        groups[1].on_change(&change).unwrap();
+        assert_eq!(groups[2].on_change(&change).unwrap(), Some(GroupUpdate::Removed { topic: "room".to_string() }));
+
+        let data = groups[0].encrypt("room", b"just us").unwrap();
+        assert_eq!(groups[1].decrypt(&data).unwrap().2, b"just us");
+        assert!(groups[2].decrypt(&data).is_err());
+    }
+}
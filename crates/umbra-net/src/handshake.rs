@@ -1,37 +1,71 @@
 // Simplified handshake behaviour - no more 6 HashMaps!
 // Just 2 fields: identity + sessions (state machine)
+// Messages travel point-to-point over /umbra/handshake/1 (request_response, empty ack responses)
 
-use libp2p::swarm::{ConnectionHandler, NetworkBehaviour};
-use libp2p::PeerId;
+use crate::codec::LengthPrefixedCodec;
+use crate::requests::{RequestEvent, RequestProtocol, Requests};
+use libp2p::request_response::{self, OutboundFailure, OutboundRequestId, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
 use std::collections::{HashMap, VecDeque};
+use std::task::Context;
+use std::time::{Duration, Instant};
 use umbra_crypto::handshake::Handshake;
-use umbra_crypto::identity::IdentityKey;
+use umbra_crypto::identity::{HybridVerifyingKey, IdentityKey, PqPolicy};
+use umbra_crypto::key_schedule::SessionKeys;
 use umbra_wire::handshake::{
+    HandshakeFinish as WireHandshakeFinish,
     HandshakeInit as WireHandshakeInit,
     HandshakeResp as WireHandshakeResp,
     HandshakeMessage, handshake_message,
 };
 use umbra_crypto::handshake::{
+    HandshakeFinish as CryptoHandshakeFinish,
     HandshakeInit as CryptoHandshakeInit,
     HandshakeResp as CryptoHandshakeResp,
 };
-use ed25519_dalek::VerifyingKey;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// Stream protocol for handshake messages
+pub const HANDSHAKE_PROTOCOL: StreamProtocol = StreamProtocol::new("/umbra/handshake/1");
+
+/// How long a handshake may stay unfinished before it's dropped
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Per-message delivery timeout on the handshake protocol
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attempts per handshake message before the handshake fails
+const MAX_SEND_ATTEMPTS: u32 = 3;
+
+/// How often stale handshakes are swept
+const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Session state for each peer - SIMPLE STATE MACHINE
 enum SessionState {
     /// Handshake initiated, waiting for response
     /// Stores the Handshake instance to preserve KEM keys for completion
     Pending {
-        handshake: Handshake,
-        #[allow(dead_code)]
+        handshake: Box<Handshake>,
         init: CryptoHandshakeInit,
+        started: Instant,
+    },
+    /// Responder sent its resp, waiting for the initiator's HandshakeFinish
+    AwaitingFinish {
+        keys: Box<SessionKeys>,
+        verify_key: Box<HybridVerifyingKey>,
+        started: Instant,
+    },
+    /// Initiator derived the keys, waiting for its HandshakeFinish to be acked
+    SendingFinish {
+        keys: Box<SessionKeys>,
+        verify_key: Box<HybridVerifyingKey>,
+        started: Instant,
     },
     /// Handshake complete, session established
     Established {
         session_key: [u8; 32],
         #[allow(dead_code)]
-        verify_key: VerifyingKey,
+        verify_key: HybridVerifyingKey,
     },
 }
 
@@ -42,7 +76,7 @@ pub enum HandshakeEvent {
     Completed {
         peer_id: PeerId,
         session_key: [u8; 32],
-        verify_key: VerifyingKey,
+        verify_key: Box<HybridVerifyingKey>,
     },
     /// Handshake failed
     Failed {
@@ -51,15 +85,19 @@ pub enum HandshakeEvent {
     },
 }
 
-/// Messages to send via gossipsub
+/// Messages queued for the handshake protocol
 #[derive(Debug)]
 pub enum HandshakeOutbound {
     SendInit { peer_id: PeerId, data: Vec<u8> },
     SendResp { peer_id: PeerId, data: Vec<u8> },
+    SendFinish { peer_id: PeerId, data: Vec<u8> },
 }
 
 /// Handshake protocol behaviour - SIMPLIFIED TO 4 FIELDS
 pub struct HandshakeBehaviour {
+    /// Our PeerId, which our messages name as their sender
+    local_peer_id: PeerId,
+
     /// Our identity key for authentication (hybrid Ed25519 + Dilithium3)
     identity: IdentityKey,
     
@@ -71,18 +109,70 @@ pub struct HandshakeBehaviour {
     
     /// Messages to send (temporary until we move to channels)
     pending_outbound: VecDeque<HandshakeOutbound>,
+
+    /// Whether classical-only peers may complete a handshake
+    pq_policy: PqPolicy,
+
+    /// Whether our inits ask for (and we send) a HandshakeFinish
+    key_confirmation: bool,
+
+    /// Max age of a Pending/AwaitingFinish session
+    timeout: Duration,
+
+    /// Wakes poll() to sweep stale sessions (created on first poll, needs a runtime)
+    timeout_check: Option<tokio::time::Interval>,
+
+    /// Point-to-point transport for handshake messages
+    requests: Requests,
+
+    /// Messages awaiting an ack, kept for retries
+    in_flight: HashMap<OutboundRequestId, InFlight>,
+}
+
+/// A sent handshake message that hasn't been acked yet
+struct InFlight {
+    data: Vec<u8>,
+    attempts: u32,
+    /// Whether this is our HandshakeFinish, whose ack completes the handshake
+    finish: bool,
 }
 
 impl HandshakeBehaviour {
-    pub fn new(identity: IdentityKey) -> Self {
+    pub fn new(local_peer_id: PeerId, identity: IdentityKey) -> Self {
         Self {
+            local_peer_id,
             identity,
             sessions: HashMap::new(),
             pending_events: VecDeque::new(),
             pending_outbound: VecDeque::new(),
+            pq_policy: PqPolicy::default(),
+            key_confirmation: true,
+            timeout: HANDSHAKE_TIMEOUT,
+            timeout_check: None,
+            requests: request_response::Behaviour::with_codec(
+                LengthPrefixedCodec::default(),
+                [(HANDSHAKE_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            in_flight: HashMap::new(),
         }
     }
 
+    /// Reject (or accept) peers that don't present a Dilithium3 key
+    pub fn set_pq_policy(&mut self, policy: PqPolicy) {
+        self.pq_policy = policy;
+    }
+
+    /// Send a HandshakeFinish after our inits (on by default)
+    pub fn set_key_confirmation(&mut self, enabled: bool) {
+        self.key_confirmation = enabled;
+    }
+
+    /// How long to wait for a resp/finish before emitting `Failed`
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
     /// Get session key for a peer (if handshake completed)
     pub fn get_session_key(&self, peer_id: &PeerId) -> Option<&[u8; 32]> {
         match self.sessions.get(peer_id) {
@@ -102,9 +192,11 @@ impl HandshakeBehaviour {
         
         // Create handshake init message
         let hs = Handshake::new(self.identity.clone())
-            .map_err(|e| format!("Failed to create handshake: {:?}", e))?;
+            .map_err(|e| format!("Failed to create handshake: {:?}", e))?
+            .with_pq_policy(self.pq_policy)
+            .with_key_confirmation(self.key_confirmation);
         
-        let crypto_init = hs.initiate(peer_id)
+        let crypto_init = hs.initiate(self.local_peer_id)
             .map_err(|e| format!("Failed to initiate handshake: {:?}", e))?;
         
         // Convert to wire format and queue for sending
@@ -115,8 +207,9 @@ impl HandshakeBehaviour {
         
         // FIX: Store BOTH handshake instance AND init to preserve KEM keys
         self.sessions.insert(peer_id, SessionState::Pending {
-            handshake: hs,
+            handshake: Box::new(hs),
             init: crypto_init,
+            started: Instant::now(),
         });
         
         self.pending_outbound.push_back(HandshakeOutbound::SendInit {
@@ -134,6 +227,16 @@ impl HandshakeBehaviour {
         
         match msg.message {
             Some(handshake_message::Message::Init(init)) => {
+                // Crossed inits: the higher PeerId keeps its own handshake, the lower one
+                // drops its own and answers (handle_init replaces the Pending session)
+                let ours_in_progress = matches!(
+                    self.sessions.get(&peer_id),
+                    Some(SessionState::Pending { .. } | SessionState::SendingFinish { .. })
+                );
+                if ours_in_progress && self.local_peer_id.to_bytes() > peer_id.to_bytes() {
+                    debug!("Ignoring init from {} that crossed ours", peer_id);
+                    return Ok(());
+                }
                 let resp_data = self.handle_init(peer_id, &init)?;
                 self.pending_outbound.push_back(HandshakeOutbound::SendResp {
                     peer_id,
@@ -143,6 +246,9 @@ impl HandshakeBehaviour {
             Some(handshake_message::Message::Resp(resp)) => {
                 self.handle_resp(peer_id, &resp)?;
             }
+            Some(handshake_message::Message::Finish(finish)) => {
+                self.handle_finish(peer_id, &finish)?;
+            }
             None => {
                 return Err("Empty handshake message".to_string());
             }
@@ -152,40 +258,133 @@ impl HandshakeBehaviour {
     }
 
     /// Get next outbound message to send
-    pub fn poll_outbound(&mut self) -> Option<HandshakeOutbound> {
+    fn poll_outbound(&mut self) -> Option<HandshakeOutbound> {
         self.pending_outbound.pop_front()
     }
 
+    /// Hand queued messages to the request_response behaviour
+    fn flush_outbound(&mut self) {
+        while let Some(outbound) = self.poll_outbound() {
+            let finish = matches!(outbound, HandshakeOutbound::SendFinish { .. });
+            let (HandshakeOutbound::SendInit { peer_id, data }
+            | HandshakeOutbound::SendResp { peer_id, data }
+            | HandshakeOutbound::SendFinish { peer_id, data }) = outbound;
+            self.send(peer_id, InFlight { data, attempts: 1, finish });
+        }
+    }
+
+    fn send(&mut self, peer_id: PeerId, in_flight: InFlight) {
+        debug!("Sending handshake message to {} ({} bytes, attempt {})", peer_id, in_flight.data.len(), in_flight.attempts);
+        let request_id = self.requests.send_request(&peer_id, in_flight.data.clone());
+        self.in_flight.insert(request_id, in_flight);
+    }
+
+    fn on_request_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    if let Err(e) = self.handle_message(peer, &request) {
+                        warn!("Handshake message from {} rejected: {}", peer, e);
+                    }
+                    // Ack receipt either way; the peer's retries are for delivery, not validity
+                    let _ = self.requests.send_response(channel, Vec::new());
+                }
+                request_response::Message::Response { request_id, .. } => {
+                    if self.in_flight.remove(&request_id).is_some_and(|sent| sent.finish) {
+                        self.on_finish_delivered(peer);
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                self.on_send_failure(peer, request_id, error);
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Inbound handshake stream from {} failed: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    /// Retry a failed send, or give up on the handshake once attempts run out
+    fn on_send_failure(&mut self, peer_id: PeerId, request_id: OutboundRequestId, error: OutboundFailure) {
+        let Some(in_flight) = self.in_flight.remove(&request_id) else {
+            return;
+        };
+
+        let retryable = !matches!(error, OutboundFailure::UnsupportedProtocols);
+        if retryable && in_flight.attempts < MAX_SEND_ATTEMPTS {
+            debug!("Handshake send to {} failed ({}), retrying", peer_id, error);
+            self.send(peer_id, InFlight { attempts: in_flight.attempts + 1, ..in_flight });
+            return;
+        }
+
+        // A session that already emitted `Completed` never fails afterwards
+        if matches!(self.sessions.get(&peer_id), Some(SessionState::Established { .. })) {
+            warn!("Handshake message to {} undelivered after completion: {}", peer_id, error);
+            return;
+        }
+
+        // Includes an undelivered finish: the responder will never confirm, so drop our side too
+        self.sessions.remove(&peer_id);
+        self.pending_events.push_back(HandshakeEvent::Failed {
+            peer_id,
+            error: format!("Failed to deliver handshake message: {}", error),
+        });
+    }
+
+    /// Drop unfinished handshakes older than the timeout, emitting `Failed` for each
+    fn expire_stale(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let stale: Vec<PeerId> = self.sessions.iter()
+            .filter_map(|(peer_id, state)| match state {
+                SessionState::Pending { started, .. }
+                | SessionState::AwaitingFinish { started, .. }
+                | SessionState::SendingFinish { started, .. }
+                    if now.saturating_duration_since(*started) >= timeout => Some(*peer_id),
+                _ => None,
+            })
+            .collect();
+
+        for peer_id in stale {
+            self.sessions.remove(&peer_id);
+            warn!("Handshake with {} timed out", peer_id);
+            self.pending_events.push_back(HandshakeEvent::Failed {
+                peer_id,
+                error: "Handshake timed out".to_string(),
+            });
+        }
+    }
+
     fn handle_init(&mut self, peer_id: PeerId, init: &WireHandshakeInit) -> Result<Vec<u8>, String> {
         // Convert from wire format
         let crypto_init = CryptoHandshakeInit::try_from(init)
             .map_err(|e| format!("Invalid init message: {}", e))?;
 
-        // Extract peer's verify key
-        let peer_key = ed25519_dalek::VerifyingKey::from_bytes(&crypto_init.verify_key)
+        // Extract peer's Ed25519 + Dilithium3 verify keys
+        let peer_key = HybridVerifyingKey::from_bytes(&crypto_init.verify_key, &crypto_init.pq_verify_key)
             .map_err(|e| format!("Invalid verify key: {}", e))?;
 
         // Create handshake and respond
         let hs = Handshake::new(self.identity.clone())
-            .map_err(|e| format!("Failed to create handshake: {:?}", e))?;
+            .map_err(|e| format!("Failed to create handshake: {:?}", e))?
+            .with_pq_policy(self.pq_policy);
 
-        let (crypto_resp, session_key) = hs.respond(peer_id, &crypto_init, &peer_key)
+        // The init must name the peer this stream comes from
+        let (crypto_resp, keys) = hs.respond(self.local_peer_id, peer_id, &crypto_init, &peer_key)
             .map_err(|e| format!("Failed to respond to handshake: {:?}", e))?;
 
-        // Store session as established
-        self.sessions.insert(peer_id, SessionState::Established {
-            session_key,
-            verify_key: peer_key,
-        });
-        
-        info!("✅ Handshake completed with {} (responder)", peer_id);
-
-        // Emit completion event
-        self.pending_events.push_back(HandshakeEvent::Completed {
-            peer_id,
-            session_key,
-            verify_key: peer_key,
-        });
+        if crypto_init.key_confirmation {
+            // Don't trust the key until the initiator proves it derived it too
+            debug!("Awaiting key confirmation from {}", peer_id);
+            self.sessions.insert(peer_id, SessionState::AwaitingFinish {
+                keys: Box::new(keys),
+                verify_key: Box::new(peer_key),
+                started: Instant::now(),
+            });
+        } else {
+            self.establish(peer_id, keys.session_key(), peer_key);
+            info!("✅ Handshake completed with {} (responder)", peer_id);
+        }
 
         // Convert to wire format and return
         let wire_resp = WireHandshakeResp::from(&crypto_resp);
@@ -201,85 +400,132 @@ impl HandshakeBehaviour {
         let crypto_resp = CryptoHandshakeResp::try_from(resp)
             .map_err(|e| format!("Invalid resp message: {}", e))?;
 
-        // Extract peer's verify key
-        let peer_key = ed25519_dalek::VerifyingKey::from_bytes(&crypto_resp.verify_key)
+        // Extract peer's Ed25519 + Dilithium3 verify keys
+        let peer_key = HybridVerifyingKey::from_bytes(&crypto_resp.verify_key, &crypto_resp.pq_verify_key)
             .map_err(|e| format!("Invalid verify key: {}", e))?;
 
         // FIX: Retrieve the stored handshake instance to complete with same KEM keys
-        let handshake = match self.sessions.remove(&peer_id) {
-            Some(SessionState::Pending { handshake, .. }) => handshake,
-            _ => {
+        let (handshake, init) = match self.sessions.remove(&peer_id) {
+            Some(SessionState::Pending { handshake, init, .. }) => (handshake, init),
+            Some(other) => {
+                self.sessions.insert(peer_id, other);
+                return Err(format!("No pending handshake found for peer {}", peer_id));
+            }
+            None => {
                 return Err(format!("No pending handshake found for peer {}", peer_id));
             }
         };
 
-        let session_key = handshake.complete(&crypto_resp, &peer_key)
+        let keys = handshake.complete(peer_id, &init, &crypto_resp, &peer_key)
             .map_err(|e| format!("Failed to complete handshake: {:?}", e))?;
 
-        // Update to established state
+        if init.key_confirmation {
+            let wire_finish = WireHandshakeFinish::from(&CryptoHandshakeFinish::new(&keys));
+            let msg = HandshakeMessage {
+                message: Some(handshake_message::Message::Finish(wire_finish)),
+            };
+            self.pending_outbound.push_back(HandshakeOutbound::SendFinish {
+                peer_id,
+                data: msg.encode_to_vec(),
+            });
+            // Not complete until the responder has the finish; it drops the session without one
+            self.sessions.insert(peer_id, SessionState::SendingFinish {
+                keys: Box::new(keys),
+                verify_key: Box::new(peer_key),
+                started: Instant::now(),
+            });
+            return Ok(());
+        }
+
+        self.establish(peer_id, keys.session_key(), peer_key);
+        info!("✅ Handshake completed with {} (initiator)", peer_id);
+
+        Ok(())
+    }
+
+    /// The responder acked our finish: the handshake is complete on both sides
+    fn on_finish_delivered(&mut self, peer_id: PeerId) {
+        match self.sessions.remove(&peer_id) {
+            Some(SessionState::SendingFinish { keys, verify_key, .. }) => {
+                self.establish(peer_id, keys.session_key(), *verify_key);
+                info!("✅ Handshake completed with {} (initiator, finish delivered)", peer_id);
+            }
+            Some(other) => {
+                self.sessions.insert(peer_id, other);
+            }
+            None => {}
+        }
+    }
+
+    fn handle_finish(&mut self, peer_id: PeerId, finish: &WireHandshakeFinish) -> Result<(), String> {
+        let crypto_finish = CryptoHandshakeFinish::try_from(finish)
+            .map_err(|e| format!("Invalid finish message: {}", e))?;
+
+        let (keys, verify_key) = match self.sessions.remove(&peer_id) {
+            Some(SessionState::AwaitingFinish { keys, verify_key, .. }) => (keys, verify_key),
+            Some(other) => {
+                self.sessions.insert(peer_id, other);
+                return Err(format!("Unexpected finish from peer {}", peer_id));
+            }
+            None => {
+                return Err(format!("Unexpected finish from peer {}", peer_id));
+            }
+        };
+
+        if let Err(e) = crypto_finish.verify(&keys) {
+            // Session already removed; the initiator has to start over
+            let error = format!("Key confirmation failed: {:?}", e);
+            self.pending_events.push_back(HandshakeEvent::Failed {
+                peer_id,
+                error: error.clone(),
+            });
+            return Err(error);
+        }
+
+        self.establish(peer_id, keys.session_key(), *verify_key);
+        info!("✅ Handshake completed with {} (responder, key confirmed)", peer_id);
+
+        Ok(())
+    }
+
+    /// Mark the session established and emit `Completed`
+    fn establish(&mut self, peer_id: PeerId, session_key: [u8; 32], verify_key: HybridVerifyingKey) {
         self.sessions.insert(peer_id, SessionState::Established {
             session_key,
-            verify_key: peer_key,
+            verify_key: verify_key.clone(),
         });
-        
-        info!("✅ Handshake completed with {} (initiator)", peer_id);
 
-        // Emit completion event
         self.pending_events.push_back(HandshakeEvent::Completed {
             peer_id,
             session_key,
-            verify_key: peer_key,
+            verify_key: Box::new(verify_key),
         });
-
-        Ok(())
     }
 }
 
-// NetworkBehaviour implementation (dummy - we use gossipsub for transport)
-impl NetworkBehaviour for HandshakeBehaviour {
-    type ConnectionHandler = libp2p::swarm::dummy::ConnectionHandler;
-    type ToSwarm = HandshakeEvent;
-
-    fn handle_established_inbound_connection(
-        &mut self,
-        _connection_id: libp2p::swarm::ConnectionId,
-        _peer: PeerId,
-        _local_addr: &libp2p::Multiaddr,
-        _remote_addr: &libp2p::Multiaddr,
-    ) -> Result<Self::ConnectionHandler, libp2p::swarm::ConnectionDenied> {
-        Ok(libp2p::swarm::dummy::ConnectionHandler)
-    }
-
-    fn handle_established_outbound_connection(
-        &mut self,
-        _connection_id: libp2p::swarm::ConnectionId,
-        _peer: PeerId,
-        _addr: &libp2p::Multiaddr,
-        _role_override: libp2p::core::Endpoint,
-    ) -> Result<Self::ConnectionHandler, libp2p::swarm::ConnectionDenied> {
-        Ok(libp2p::swarm::dummy::ConnectionHandler)
-    }
-
-    fn on_swarm_event(&mut self, _event: libp2p::swarm::FromSwarm) {}
-
-    fn on_connection_handler_event(
-        &mut self,
-        _peer_id: PeerId,
-        _connection_id: libp2p::swarm::ConnectionId,
-        _event: <Self::ConnectionHandler as ConnectionHandler>::ToBehaviour,
-    ) {
-    }
-
-    fn poll(
-        &mut self,
-        _cx: &mut std::task::Context,
-    ) -> std::task::Poll<libp2p::swarm::ToSwarm<Self::ToSwarm, <Self::ConnectionHandler as ConnectionHandler>::FromBehaviour>> {
-        // Return pending events
-        if let Some(event) = self.pending_events.pop_front() {
-            return std::task::Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(event));
-        }
-        
-        std::task::Poll::Pending
+// Runs on request_response over /umbra/handshake/1
+impl RequestProtocol for HandshakeBehaviour {
+    type Event = HandshakeEvent;
+
+    fn requests(&mut self) -> &mut Requests {
+        &mut self.requests
+    }
+
+    fn on_event(&mut self, event: RequestEvent) {
+        self.on_request_event(event);
+    }
+
+    fn pop_event(&mut self) -> Option<HandshakeEvent> {
+        self.pending_events.pop_front()
+    }
+
+    fn poll_tasks(&mut self, cx: &mut Context) {
+        // Sweep stale handshakes; the interval re-registers the waker
+        let timeout_check = self.timeout_check
+            .get_or_insert_with(|| tokio::time::interval(TIMEOUT_CHECK_INTERVAL));
+        while timeout_check.poll_tick(cx).is_ready() {}
+        self.expire_stale(Instant::now());
+        self.flush_outbound();
     }
 }
 
@@ -294,7 +540,7 @@ mod tests {
     #[test]
     fn test_handshake_behaviour_creation() {
         let identity = gen_identity();
-        let behaviour = HandshakeBehaviour::new(identity);
+        let behaviour = HandshakeBehaviour::new(PeerId::random(), identity);
         
         assert_eq!(behaviour.sessions.len(), 0);
     }
@@ -302,7 +548,7 @@ mod tests {
     #[test]
     fn test_session_state_simple() {
         let identity = gen_identity();
-        let mut behaviour = HandshakeBehaviour::new(identity);
+        let mut behaviour = HandshakeBehaviour::new(PeerId::random(), identity);
         
         let peer_id = PeerId::random();
         
@@ -316,4 +562,262 @@ mod tests {
             Some(SessionState::Pending { .. })
         ));
     }
+
+    #[test]
+    fn test_completed_event_carries_hybrid_key() {
+        let alice_id = gen_identity();
+        let bob_id = gen_identity();
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, alice_id.clone());
+        let mut bob = HandshakeBehaviour::new(bob_peer, bob_id.clone());
+        alice.set_pq_policy(PqPolicy::RequirePq);
+        bob.set_pq_policy(PqPolicy::RequirePq);
+
+        let finish = exchange(&mut alice, &mut bob, alice_peer, bob_peer).expect("Expected finish");
+        bob.handle_message(alice_peer, &finish).unwrap();
+        alice.on_finish_delivered(bob_peer);
+
+        match alice.pending_events.pop_front() {
+            Some(HandshakeEvent::Completed { verify_key, .. }) => {
+                assert_eq!(*verify_key, bob_id.hybrid_verifying_key());
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        match bob.pending_events.pop_front() {
+            Some(HandshakeEvent::Completed { verify_key, .. }) => {
+                assert_eq!(*verify_key, alice_id.hybrid_verifying_key());
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    /// Run init -> resp (-> finish) between two behaviours, returning the finish if one was sent
+    fn exchange(alice: &mut HandshakeBehaviour, bob: &mut HandshakeBehaviour, alice_peer: PeerId, bob_peer: PeerId) -> Option<Vec<u8>> {
+        alice.initiate_handshake(bob_peer).unwrap();
+        let Some(HandshakeOutbound::SendInit { data, .. }) = alice.poll_outbound() else {
+            panic!("Expected init");
+        };
+        bob.handle_message(alice_peer, &data).unwrap();
+        let Some(HandshakeOutbound::SendResp { data, .. }) = bob.poll_outbound() else {
+            panic!("Expected resp");
+        };
+        alice.handle_message(bob_peer, &data).unwrap();
+        match alice.poll_outbound() {
+            Some(HandshakeOutbound::SendFinish { data, .. }) => Some(data),
+            None => None,
+            other => panic!("Unexpected outbound: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_crossed_inits() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+        let (mut low, low_peer, mut high, high_peer) = if alice_peer.to_bytes() < bob_peer.to_bytes() {
+            (alice, alice_peer, bob, bob_peer)
+        } else {
+            (bob, bob_peer, alice, alice_peer)
+        };
+
+        // Both start before either init is delivered
+        low.initiate_handshake(high_peer).unwrap();
+        high.initiate_handshake(low_peer).unwrap();
+        let Some(HandshakeOutbound::SendInit { data: low_init, .. }) = low.poll_outbound() else {
+            panic!("Expected init");
+        };
+        let Some(HandshakeOutbound::SendInit { data: high_init, .. }) = high.poll_outbound() else {
+            panic!("Expected init");
+        };
+
+        // The higher PeerId ignores the crossed init; the lower one answers
+        high.handle_message(low_peer, &low_init).unwrap();
+        assert!(high.poll_outbound().is_none());
+        low.handle_message(high_peer, &high_init).unwrap();
+        let Some(HandshakeOutbound::SendResp { data: resp, .. }) = low.poll_outbound() else {
+            panic!("Expected resp");
+        };
+
+        // One handshake, with the higher PeerId as initiator
+        high.handle_message(low_peer, &resp).unwrap();
+        let Some(HandshakeOutbound::SendFinish { data: finish, .. }) = high.poll_outbound() else {
+            panic!("Expected finish");
+        };
+        // A retried copy of the lower side's init doesn't disturb it either
+        high.handle_message(low_peer, &low_init).unwrap();
+        assert!(high.poll_outbound().is_none());
+
+        low.handle_message(high_peer, &finish).unwrap();
+        high.on_finish_delivered(low_peer);
+        assert!(matches!(low.pending_events.pop_front(), Some(HandshakeEvent::Completed { .. })));
+        assert!(matches!(high.pending_events.pop_front(), Some(HandshakeEvent::Completed { .. })));
+        assert!(low.get_session_key(&high_peer).is_some());
+        assert_eq!(low.get_session_key(&high_peer), high.get_session_key(&low_peer));
+    }
+
+    #[test]
+    fn test_responder_waits_for_finish() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let mut bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+
+        let finish = exchange(&mut alice, &mut bob, alice_peer, bob_peer).expect("Expected finish");
+
+        // Neither side is done until the finish arrives
+        assert!(alice.pending_events.is_empty());
+        assert!(alice.get_session_key(&bob_peer).is_none());
+        assert!(bob.pending_events.is_empty());
+        assert!(bob.get_session_key(&alice_peer).is_none());
+
+        bob.handle_message(alice_peer, &finish).unwrap();
+        assert!(matches!(bob.pending_events.pop_front(), Some(HandshakeEvent::Completed { .. })));
+        alice.on_finish_delivered(bob_peer);
+        assert!(matches!(alice.pending_events.pop_front(), Some(HandshakeEvent::Completed { .. })));
+        assert_eq!(bob.get_session_key(&alice_peer), alice.get_session_key(&bob_peer));
+    }
+
+    #[test]
+    fn test_undelivered_finish_fails_without_completing() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let mut bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+
+        alice.initiate_handshake(bob_peer).unwrap();
+        let Some(HandshakeOutbound::SendInit { data, .. }) = alice.poll_outbound() else {
+            panic!("Expected init");
+        };
+        bob.handle_message(alice_peer, &data).unwrap();
+        let Some(HandshakeOutbound::SendResp { data, .. }) = bob.poll_outbound() else {
+            panic!("Expected resp");
+        };
+        alice.handle_message(bob_peer, &data).unwrap();
+        alice.flush_outbound();
+
+        for _ in 1..=MAX_SEND_ATTEMPTS {
+            let request_id = *alice.in_flight.keys().next().unwrap();
+            assert!(alice.in_flight[&request_id].finish);
+            alice.on_send_failure(bob_peer, request_id, OutboundFailure::Timeout);
+        }
+
+        assert!(matches!(alice.pending_events.pop_front(), Some(HandshakeEvent::Failed { .. })));
+        assert!(alice.pending_events.is_empty());
+        assert!(alice.get_session_key(&bob_peer).is_none());
+    }
+
+    #[test]
+    fn test_bad_finish_fails_session() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let mut bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+
+        exchange(&mut alice, &mut bob, alice_peer, bob_peer).expect("Expected finish");
+
+        let forged = HandshakeMessage {
+            message: Some(handshake_message::Message::Finish(WireHandshakeFinish {
+                confirm_mac: vec![0u8; 32],
+            })),
+        };
+        assert!(bob.handle_message(alice_peer, &forged.encode_to_vec()).is_err());
+        assert!(matches!(bob.pending_events.pop_front(), Some(HandshakeEvent::Failed { .. })));
+        assert!(bob.get_session_key(&alice_peer).is_none());
+        assert!(bob.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_without_key_confirmation() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let mut bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+        alice.set_key_confirmation(false);
+
+        // Two-message flow: no finish, both sides complete immediately
+        assert!(exchange(&mut alice, &mut bob, alice_peer, bob_peer).is_none());
+        assert!(matches!(alice.pending_events.pop_front(), Some(HandshakeEvent::Completed { .. })));
+        assert!(matches!(bob.pending_events.pop_front(), Some(HandshakeEvent::Completed { .. })));
+    }
+
+    #[test]
+    fn test_stale_sessions_time_out() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let mut bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+        alice.set_timeout(Duration::from_secs(5));
+        bob.set_timeout(Duration::from_secs(5));
+
+        // Bob is left in AwaitingFinish; alice has a second, unanswered Pending
+        exchange(&mut alice, &mut bob, alice_peer, bob_peer);
+        alice.on_finish_delivered(bob_peer);
+        let carol_peer = PeerId::random();
+        alice.initiate_handshake(carol_peer).unwrap();
+        alice.pending_events.clear();
+
+        alice.expire_stale(Instant::now());
+        bob.expire_stale(Instant::now());
+        assert!(alice.pending_events.is_empty());
+        assert!(bob.pending_events.is_empty());
+
+        let later = Instant::now() + Duration::from_secs(6);
+        alice.expire_stale(later);
+        bob.expire_stale(later);
+
+        match alice.pending_events.pop_front() {
+            Some(HandshakeEvent::Failed { peer_id, .. }) => assert_eq!(peer_id, carol_peer),
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        assert!(alice.pending_events.is_empty());
+        // Established sessions never expire
+        assert!(alice.get_session_key(&bob_peer).is_some());
+
+        match bob.pending_events.pop_front() {
+            Some(HandshakeEvent::Failed { peer_id, .. }) => assert_eq!(peer_id, alice_peer),
+            other => panic!("Unexpected event: {:?}", other),
+        }
+        assert!(bob.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_send_retried_then_failed() {
+        let mut alice = HandshakeBehaviour::new(PeerId::random(), gen_identity());
+        let bob_peer = PeerId::random();
+
+        alice.initiate_handshake(bob_peer).unwrap();
+        alice.flush_outbound();
+
+        for attempt in 1..=MAX_SEND_ATTEMPTS {
+            assert_eq!(alice.in_flight.len(), 1);
+            let request_id = *alice.in_flight.keys().next().unwrap();
+            assert_eq!(alice.in_flight[&request_id].attempts, attempt);
+            assert!(alice.pending_events.is_empty());
+            alice.on_send_failure(bob_peer, request_id, OutboundFailure::Timeout);
+        }
+
+        assert!(alice.in_flight.is_empty());
+        assert!(matches!(alice.pending_events.pop_front(), Some(HandshakeEvent::Failed { .. })));
+        assert!(alice.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_relayed_init_rejected() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let eve_peer = PeerId::random();
+        let mut alice = HandshakeBehaviour::new(alice_peer, gen_identity());
+        let mut bob = HandshakeBehaviour::new(bob_peer, gen_identity());
+
+        // Alice meant her init for Eve, who replays it to Bob as her own
+        alice.initiate_handshake(eve_peer).unwrap();
+        let Some(HandshakeOutbound::SendInit { data, .. }) = alice.poll_outbound() else {
+            panic!("Expected init");
+        };
+        assert!(bob.handle_message(eve_peer, &data).is_err());
+        assert!(bob.poll_outbound().is_none());
+        assert!(bob.sessions.is_empty());
+    }
 }
@@ -0,0 +1,305 @@
+// Long-term node keys at rest
+// The libp2p keypair (our PeerId), the hybrid identity that the handshake and messages sign
+// with, the onion key circuits are built to, and our prekey secrets live in one file, sealed
+// under a key stretched from a passphrase with Argon2id, so a restarted node is the same peer
+// to everyone it talked to before and can still open sessions started from its old bundle
+
+use crate::config::IdentitySource;
+use crate::error::{NetError, Result};
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use umbra_crypto::prekey::PrekeyStore;
+use umbra_crypto::{Envelope, HybridKem, IdentityKey};
+use zeroize::Zeroizing;
+
+/// Environment variable the passphrase is read from when the config doesn't carry one
+pub const PASSPHRASE_ENV: &str = "UMBRA_PASSPHRASE";
+
+/// Key file name inside a data dir
+pub const KEY_FILE: &str = "node_keys.bin";
+
+const MAGIC: &[u8; 8] = b"UMBRAKEY";
+const VERSION: u8 = 2;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN;
+
+/// Everything that makes a node recognizable: its PeerId, its signing identity, its onion key
+/// and the prekeys behind its published bundle
+pub struct NodeKeys {
+    pub keypair: Keypair,
+    pub identity: IdentityKey,
+    pub onion_key: HybridKem,
+    pub prekeys: PrekeyStore,
+}
+
+impl NodeKeys {
+    pub fn generate() -> Result<Self> {
+        Self::with_keypair(Keypair::generate_ed25519())
+    }
+
+    fn with_keypair(keypair: Keypair) -> Result<Self> {
+        let crypto = |what: &str, e: umbra_crypto::CryptoError| NetError::Crypto(format!("{}: {}", what, e));
+        let identity = IdentityKey::generate().map_err(|e| crypto("Identity key", e))?;
+        Ok(Self {
+            keypair,
+            onion_key: HybridKem::generate().map_err(|e| crypto("Onion key", e))?,
+            prekeys: PrekeyStore::new(identity.clone()).map_err(|e| crypto("Prekey init", e))?,
+            identity,
+        })
+    }
+
+    /// The keys a node configured with `source` runs with, and the file to save prekey changes
+    /// to when they come from one
+    pub fn from_source(source: &IdentitySource) -> Result<(Self, Option<KeyFile>)> {
+        match source {
+            IdentitySource::Ephemeral => Ok((Self::generate()?, None)),
+            IdentitySource::Keypair(keypair) => Ok((Self::with_keypair((**keypair).clone())?, None)),
+            IdentitySource::KeyFile { path, passphrase } => {
+                let passphrase = match passphrase {
+                    Some(passphrase) => passphrase.clone(),
+                    None => Zeroizing::new(std::env::var(PASSPHRASE_ENV).map_err(|_| {
+                        NetError::Config(format!("Key file {} needs a passphrase ({})", path.display(), PASSPHRASE_ENV))
+                    })?),
+                };
+                let (file, keys) = KeyFile::open(path, &passphrase)?;
+                Ok((keys, Some(file)))
+            }
+        }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    /// Load the keys at `path`, or create and save new ones if there is no file yet.
+    /// An existing file that won't open is an error, never overwritten.
+    pub fn load_or_create(path: &Path, passphrase: &str) -> Result<Self> {
+        KeyFile::open(path, passphrase).map(|(_, keys)| keys)
+    }
+
+    /// Decrypt a key file written by `save`
+    pub fn load(path: &Path, passphrase: &str) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let salt = read_header(path, &data)?;
+        let plaintext = envelope(passphrase, salt)?
+            .decrypt(&data[HEADER_LEN..])
+            .map_err(|_| NetError::Crypto("Wrong passphrase or corrupt key file".to_string()))?;
+        Self::decode(&plaintext)
+    }
+
+    /// Encrypt under `passphrase` and write (owner-only, write-then-rename)
+    pub fn save(&self, path: &Path, passphrase: &str) -> Result<()> {
+        KeyFile::create(path, passphrase, self)?.save_prekeys(&self.prekeys)
+    }
+
+    /// The sections that never change: u32 length || libp2p keypair (protobuf), then the
+    /// identity key and the onion key (X25519 secret || Kyber768 secret), each length-prefixed
+    fn encode_fixed(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let keypair = Zeroizing::new(
+            self.keypair
+                .to_protobuf_encoding()
+                .map_err(|e| NetError::Crypto(format!("Keypair encode: {}", e)))?,
+        );
+        let mut onion_key = Zeroizing::new(self.onion_key.classical_secret_bytes().to_vec());
+        onion_key.extend_from_slice(self.onion_key.pq_secret_key());
+
+        let mut bytes = Zeroizing::new(Vec::new());
+        for section in [&keypair[..], &self.identity.to_bytes()[..], &onion_key[..]] {
+            push_section(&mut bytes, section);
+        }
+        Ok(bytes)
+    }
+
+    /// The fixed sections, then the prekey store
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let corrupt = || NetError::Crypto("Corrupt key file".to_string());
+        let mut rest = bytes;
+        let mut sections = [&[][..]; 4];
+        for section in &mut sections {
+            *section = take_section(&mut rest).ok_or_else(corrupt)?;
+        }
+        let [keypair, identity, onion_key, prekeys] = sections;
+        if !rest.is_empty() || onion_key.len() < HybridKem::CLASSICAL_KEY_LEN {
+            return Err(corrupt());
+        }
+
+        let identity = IdentityKey::from_bytes(identity).map_err(|_| corrupt())?;
+        let (classical, pq) = onion_key.split_at(HybridKem::CLASSICAL_KEY_LEN);
+        let classical = Zeroizing::new(<[u8; 32]>::try_from(classical).map_err(|_| corrupt())?);
+        Ok(Self {
+            keypair: Keypair::from_protobuf_encoding(keypair).map_err(|_| corrupt())?,
+            onion_key: HybridKem::from_secret_bytes(*classical, pq).map_err(|_| corrupt())?,
+            prekeys: PrekeyStore::from_bytes(identity.clone(), prekeys).map_err(|_| corrupt())?,
+            identity,
+        })
+    }
+}
+
+/// An open key file. Keeps the stretched key and the sections that never change, so the file
+/// can be rewritten whenever the prekeys change without running Argon2id again.
+pub struct KeyFile {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    envelope: Envelope,
+    fixed: Zeroizing<Vec<u8>>,
+}
+
+impl KeyFile {
+    /// Open the key file at `path`, or create it with fresh keys if there is none yet
+    pub fn open(path: &Path, passphrase: &str) -> Result<(Self, NodeKeys)> {
+        if !path.exists() {
+            let keys = NodeKeys::generate()?;
+            let file = Self::create(path, passphrase, &keys)?;
+            file.save_prekeys(&keys.prekeys)?;
+            return Ok((file, keys));
+        }
+        let data = std::fs::read(path)?;
+        let salt: [u8; SALT_LEN] = read_header(path, &data)?.try_into().expect("salt length");
+        let envelope = envelope(passphrase, &salt)?;
+        let plaintext = envelope
+            .decrypt(&data[HEADER_LEN..])
+            .map_err(|_| NetError::Crypto("Wrong passphrase or corrupt key file".to_string()))?;
+        let keys = NodeKeys::decode(&plaintext)?;
+        let fixed = keys.encode_fixed()?;
+        Ok((Self { path: path.to_path_buf(), salt, envelope, fixed }, keys))
+    }
+
+    /// A new file for `keys` under a fresh salt; nothing is written until `save_prekeys`
+    fn create(path: &Path, passphrase: &str, keys: &NodeKeys) -> Result<Self> {
+        if passphrase.is_empty() {
+            return Err(NetError::Config("Key file passphrase is empty".to_string()));
+        }
+        let salt: [u8; SALT_LEN] = rand::random();
+        Ok(Self {
+            path: path.to_path_buf(),
+            envelope: envelope(passphrase, &salt)?,
+            salt,
+            fixed: keys.encode_fixed()?,
+        })
+    }
+
+    /// Rewrite the file with the current `prekeys` (owner-only, write-then-rename)
+    pub fn save_prekeys(&self, prekeys: &PrekeyStore) -> Result<()> {
+        let mut plaintext = Zeroizing::new(self.fixed.to_vec());
+        push_section(&mut plaintext, &prekeys.to_bytes());
+        let sealed = self
+            .envelope
+            .encrypt(&plaintext)
+            .map_err(|e| NetError::Crypto(format!("Key file encrypt: {}", e)))?;
+
+        let mut data = Vec::with_capacity(HEADER_LEN + sealed.len());
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&sealed);
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&tmp)?.write_all(&data)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Check the magic and version; returns the salt
+fn read_header<'a>(path: &Path, data: &'a [u8]) -> Result<&'a [u8]> {
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+        return Err(NetError::Crypto(format!("{} is not a key file", path.display())));
+    }
+    if data[MAGIC.len()] != VERSION {
+        return Err(NetError::Crypto(format!("Unsupported key file version {}", data[MAGIC.len()])));
+    }
+    Ok(&data[MAGIC.len() + 1..HEADER_LEN])
+}
+
+fn push_section(bytes: &mut Vec<u8>, section: &[u8]) {
+    bytes.extend_from_slice(&(section.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(section);
+}
+
+fn take_section<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let section = bytes.get(4..4 + len)?;
+    *bytes = &bytes[4 + len..];
+    Some(section)
+}
+
+/// Argon2id(passphrase, salt) as the file key
+fn envelope(passphrase: &str, salt: &[u8]) -> Result<Envelope> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| NetError::Crypto(format!("Passphrase KDF: {}", e)))?;
+    Envelope::new(key.as_ref()).map_err(|e| NetError::Crypto(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_survive_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(KEY_FILE);
+
+        let created = NodeKeys::load_or_create(&path, "correct horse").unwrap();
+        let loaded = NodeKeys::load_or_create(&path, "correct horse").unwrap();
+        assert_eq!(loaded.peer_id(), created.peer_id());
+        assert_eq!(loaded.identity.hybrid_verifying_key(), created.identity.hybrid_verifying_key());
+        assert_eq!(loaded.onion_key.classical_public_key(), created.onion_key.classical_public_key());
+        assert_eq!(loaded.onion_key.pq_public_key().unwrap(), created.onion_key.pq_public_key().unwrap());
+        assert_eq!(loaded.prekeys.bundle().unwrap().one_time_prekeys, created.prekeys.bundle().unwrap().one_time_prekeys);
+
+        // Nothing secret is stored in the clear
+        let data = std::fs::read(&path).unwrap();
+        let seed = created.identity.to_bytes();
+        assert!(!data.windows(32).any(|window| window == &seed[..32]));
+    }
+
+    #[test]
+    fn test_prekey_changes_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(KEY_FILE);
+        let (file, mut keys) = KeyFile::open(&path, "correct horse").unwrap();
+
+        keys.prekeys.rotate_signed_prekey().unwrap();
+        file.save_prekeys(&keys.prekeys).unwrap();
+        let loaded = NodeKeys::load(&path, "correct horse").unwrap();
+        assert_eq!(loaded.prekeys.bundle().unwrap().signed_prekey_id, keys.prekeys.bundle().unwrap().signed_prekey_id);
+        assert_eq!(loaded.peer_id(), keys.peer_id());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_fails_and_keeps_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(KEY_FILE);
+        let created = NodeKeys::load_or_create(&path, "correct horse").unwrap();
+
+        assert!(NodeKeys::load_or_create(&path, "battery staple").is_err());
+        assert_eq!(NodeKeys::load(&path, "correct horse").unwrap().peer_id(), created.peer_id());
+    }
+
+    #[test]
+    fn test_rejects_tampered_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(KEY_FILE);
+        NodeKeys::load_or_create(&path, "correct horse").unwrap();
+
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        assert!(NodeKeys::load(&path, "correct horse").is_err());
+
+        std::fs::write(&path, b"not a key file").unwrap();
+        assert!(NodeKeys::load(&path, "correct horse").is_err());
+    }
+}
@@ -1,17 +1,37 @@
 pub mod error;
 pub mod transport;
 pub mod circuit;
+pub mod config;
+pub mod codec;
 pub mod cover;
+pub mod direct;
+pub mod file;
+pub mod group;
 pub mod handshake;
+pub mod keystore;
+pub mod mailbox;
+pub mod mailbox_store;
 pub mod message;
+pub mod prekeys;
+pub mod relays;
+pub mod requests;
 
 pub use error::{NetError, Result};
 pub use transport::P2PNode;
-pub use message::MessageExchange;
+pub use config::{Behaviours, GossipsubSettings, IdentitySource, NodeConfig};
+pub use cover::{CoverConfig, CoverSchedule, TrafficStats};
+pub use direct::DeliveryStatus;
+pub use file::{FileId, FileUpdate};
+pub use group::GroupUpdate;
+pub use keystore::NodeKeys;
+pub use mailbox::MailboxUpdate;
+pub use mailbox_store::MailboxConfig;
+pub use message::{DecryptedMessage, MessageExchange, VerificationMode, VerificationStatus};
 
 pub mod prelude {
     pub use crate::error::{NetError, Result};
     pub use crate::transport::P2PNode;
+    pub use crate::config::NodeConfig;
 }
 pub mod timing;
 pub use timing::{TimingJitter, DelayedAck};
@@ -0,0 +1,294 @@
+// Store-and-forward mailbox over /umbra/mailbox/1
+// A node with a MailboxStore serves deposits and fetches for peers that are offline;
+// every node can deposit into, and fetch its own messages from, someone else's mailbox
+
+use crate::codec::LengthPrefixedCodec;
+use crate::requests::{RequestEvent, RequestProtocol, Requests};
+use crate::mailbox_store::MailboxStore;
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+use umbra_wire::mailbox::{
+    mailbox_request, Deposit, Fetch, MailboxRequest, MailboxResponse, MailboxStatus, StoredMessage,
+};
+
+/// Stream protocol for mailbox requests
+pub const MAILBOX_PROTOCOL: StreamProtocol = StreamProtocol::new("/umbra/mailbox/1");
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fetch responses carry a batch of up to `max_fetch_bytes` (192 KiB by default), which pads
+/// past the 128 KiB bucket into the 512 KiB one: the largest frame there is, so no batch needs more
+const MAX_FRAME_SIZE: usize = umbra_wire::framing::MAX_FRAME_PAYLOAD + 4;
+
+/// Events emitted by the mailbox protocol (client side)
+#[derive(Debug)]
+pub enum MailboxEvent {
+    /// `mailbox` stored our message under `id`
+    Deposited { mailbox: PeerId, id: u64 },
+    /// A batch of our messages (oldest first) and how many are still waiting
+    Fetched {
+        mailbox: PeerId,
+        messages: Vec<StoredMessage>,
+        remaining: u32,
+    },
+    /// A deposit or fetch was refused or never answered
+    Failed { mailbox: PeerId, error: String },
+}
+
+/// What a node learns about its mailbox traffic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailboxUpdate {
+    Deposited { mailbox: PeerId, id: u64 },
+    /// Mailbox emptied; `delivered` messages went to the direct message receiver
+    Drained { mailbox: PeerId, delivered: usize },
+    Failed { mailbox: PeerId, error: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RequestKind {
+    Deposit,
+    Fetch,
+}
+
+/// Client and (with a store) server side of the mailbox protocol
+pub struct MailboxBehaviour {
+    local_peer_id: PeerId,
+    requests: Requests,
+    store: Option<MailboxStore>,
+    pending_events: VecDeque<MailboxEvent>,
+    in_flight: HashMap<OutboundRequestId, (PeerId, RequestKind)>,
+}
+
+impl MailboxBehaviour {
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Self {
+            local_peer_id,
+            requests: request_response::Behaviour::with_codec(
+                LengthPrefixedCodec::new(MAX_FRAME_SIZE),
+                [(MAILBOX_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            store: None,
+            pending_events: VecDeque::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Start holding messages for other peers
+    pub fn serve(&mut self, store: MailboxStore) {
+        self.store = Some(store);
+    }
+
+    pub fn store(&self) -> Option<&MailboxStore> {
+        self.store.as_ref()
+    }
+
+    /// Leave an encoded `EncryptedMessage` for the owner of `tag` at `mailbox`
+    pub fn deposit(&mut self, mailbox: PeerId, tag: [u8; 32], message: Vec<u8>) {
+        let request = MailboxRequest {
+            request: Some(mailbox_request::Request::Deposit(Deposit { tag: tag.to_vec(), message })),
+        };
+        self.send_request(mailbox, RequestKind::Deposit, request);
+    }
+
+    /// Ask `mailbox` for our messages (and delete the ones `fetch` acks)
+    pub fn fetch(&mut self, mailbox: PeerId, fetch: Fetch) {
+        let request = MailboxRequest {
+            request: Some(mailbox_request::Request::Fetch(fetch)),
+        };
+        self.send_request(mailbox, RequestKind::Fetch, request);
+    }
+
+    fn send_request(&mut self, mailbox: PeerId, kind: RequestKind, request: MailboxRequest) {
+        let request_id = self.requests.send_request(&mailbox, request.encode_to_vec());
+        self.in_flight.insert(request_id, (mailbox, kind));
+    }
+
+    /// Serve one request from `peer` against our store
+    fn handle_request(&mut self, peer: PeerId, request: &[u8], now: u64) -> MailboxResponse {
+        let Some(store) = self.store.as_mut() else {
+            return MailboxResponse::with_status(MailboxStatus::MailboxUnavailable);
+        };
+        let Ok(MailboxRequest { request: Some(request) }) = MailboxRequest::decode_from_bytes(request) else {
+            return MailboxResponse::with_status(MailboxStatus::MailboxInvalid);
+        };
+
+        match request {
+            mailbox_request::Request::Deposit(deposit) => {
+                let Ok(tag) = <[u8; 32]>::try_from(deposit.tag.as_slice()) else {
+                    return MailboxResponse::with_status(MailboxStatus::MailboxInvalid);
+                };
+                match store.deposit(peer, tag, deposit.message, now) {
+                    Ok(id) => MailboxResponse { id, ..MailboxResponse::with_status(MailboxStatus::MailboxOk) },
+                    Err(status) => MailboxResponse::with_status(status),
+                }
+            }
+            mailbox_request::Request::Fetch(fetch) => {
+                let (Ok(tag), Some(auth)) = (fetch.verify(&self.local_peer_id), fetch.auth.as_ref()) else {
+                    return MailboxResponse::with_status(MailboxStatus::MailboxUnauthorized);
+                };
+                if !store.check_fresh(auth.timestamp, &auth.nonce, now) {
+                    return MailboxResponse::with_status(MailboxStatus::MailboxUnauthorized);
+                }
+                let batch = store.fetch(&tag, &fetch.ack, now);
+                MailboxResponse {
+                    messages: batch.messages,
+                    remaining: batch.remaining as u32,
+                    ..MailboxResponse::with_status(MailboxStatus::MailboxOk)
+                }
+            }
+        }
+    }
+
+    fn on_response(&mut self, mailbox: PeerId, kind: RequestKind, response: &[u8]) {
+        let event = match MailboxResponse::decode_from_bytes(response) {
+            Ok(response) => match (MailboxStatus::try_from(response.status), kind) {
+                (Ok(MailboxStatus::MailboxOk), RequestKind::Deposit) => MailboxEvent::Deposited { mailbox, id: response.id },
+                (Ok(MailboxStatus::MailboxOk), RequestKind::Fetch) => MailboxEvent::Fetched {
+                    mailbox,
+                    messages: response.messages,
+                    remaining: response.remaining,
+                },
+                (Ok(status), _) => MailboxEvent::Failed { mailbox, error: status.as_str_name().to_string() },
+                (Err(_), _) => MailboxEvent::Failed { mailbox, error: format!("Unknown status {}", response.status) },
+            },
+            Err(e) => MailboxEvent::Failed { mailbox, error: format!("Invalid response: {}", e) },
+        };
+        self.pending_events.push_back(event);
+    }
+
+    fn on_request_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    debug!("Mailbox request from {}", peer);
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                    let response = self.handle_request(peer, &request, now);
+                    let _ = self.requests.send_response(channel, response.encode_to_vec());
+                }
+                request_response::Message::Response { request_id, response } => {
+                    if let Some((mailbox, kind)) = self.in_flight.remove(&request_id) {
+                        self.on_response(mailbox, kind, &response);
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure { request_id, error, .. } => {
+                if let Some((mailbox, _)) = self.in_flight.remove(&request_id) {
+                    self.pending_events.push_back(MailboxEvent::Failed { mailbox, error: error.to_string() });
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Inbound mailbox stream from {} failed: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+}
+
+// Runs on request_response over /umbra/mailbox/1
+impl RequestProtocol for MailboxBehaviour {
+    type Event = MailboxEvent;
+
+    fn requests(&mut self) -> &mut Requests {
+        &mut self.requests
+    }
+
+    fn on_event(&mut self, event: RequestEvent) {
+        self.on_request_event(event);
+    }
+
+    fn pop_event(&mut self) -> Option<MailboxEvent> {
+        self.pending_events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailbox_store::MailboxConfig;
+    use umbra_crypto::IdentityKey;
+    use umbra_wire::mailbox::recipient_tag;
+
+    const NOW: u64 = 1_000_000;
+
+    fn request(request: mailbox_request::Request) -> Vec<u8> {
+        MailboxRequest { request: Some(request) }.encode_to_vec()
+    }
+
+    fn serving(local_peer_id: PeerId) -> MailboxBehaviour {
+        let mut mailbox = MailboxBehaviour::new(local_peer_id);
+        mailbox.serve(MailboxStore::open(MailboxConfig::default(), NOW).unwrap());
+        mailbox
+    }
+
+    fn status(response: &MailboxResponse) -> MailboxStatus {
+        MailboxStatus::try_from(response.status).unwrap()
+    }
+
+    #[test]
+    fn test_deposit_then_authenticated_fetch() {
+        let local_peer_id = PeerId::random();
+        let mut mailbox = serving(local_peer_id);
+        let bob = IdentityKey::generate().unwrap();
+        let tag = recipient_tag(&bob.hybrid_verifying_key());
+        // Fetch timestamps come from the real clock
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let deposit = Deposit { tag: tag.to_vec(), message: b"sealed".to_vec() };
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Deposit(deposit)), now);
+        assert_eq!(status(&response), MailboxStatus::MailboxOk);
+
+        let fetch = Fetch::new(&bob, &local_peer_id, Vec::new()).unwrap();
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Fetch(fetch.clone())), now);
+        assert_eq!(status(&response), MailboxStatus::MailboxOk);
+        assert_eq!(response.messages.len(), 1);
+        assert_eq!(response.messages[0].message, b"sealed");
+
+        // The same fetch can't be replayed
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Fetch(fetch)), now);
+        assert_eq!(status(&response), MailboxStatus::MailboxUnauthorized);
+
+        // Someone else's fetch sees only their own (empty) mailbox
+        let eve = Fetch::new(&IdentityKey::generate().unwrap(), &local_peer_id, Vec::new()).unwrap();
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Fetch(eve)), now);
+        assert!(response.messages.is_empty());
+        assert_eq!(mailbox.store().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fetch_for_other_mailbox_rejected() {
+        let mut mailbox = serving(PeerId::random());
+        let bob = IdentityKey::generate().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let deposit = Deposit { tag: recipient_tag(&bob.hybrid_verifying_key()).to_vec(), message: b"sealed".to_vec() };
+        mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Deposit(deposit)), now);
+
+        // Signed for a different mailbox, e.g. captured there and replayed here
+        let fetch = Fetch::new(&bob, &PeerId::random(), Vec::new()).unwrap();
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Fetch(fetch)), now);
+        assert_eq!(status(&response), MailboxStatus::MailboxUnauthorized);
+        assert_eq!(mailbox.store().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_not_serving() {
+        let mut mailbox = MailboxBehaviour::new(PeerId::random());
+        let deposit = Deposit { tag: vec![0; 32], message: b"sealed".to_vec() };
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Deposit(deposit)), NOW);
+        assert_eq!(status(&response), MailboxStatus::MailboxUnavailable);
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let mut mailbox = serving(PeerId::random());
+        assert_eq!(status(&mailbox.handle_request(PeerId::random(), &[0xFF, 0xFF], NOW)), MailboxStatus::MailboxInvalid);
+
+        let deposit = Deposit { tag: vec![0; 5], message: b"sealed".to_vec() };
+        let response = mailbox.handle_request(PeerId::random(), &request(mailbox_request::Request::Deposit(deposit)), NOW);
+        assert_eq!(status(&response), MailboxStatus::MailboxInvalid);
+    }
+}
@@ -0,0 +1,644 @@
+// Mailbox storage for store-and-forward delivery
+// Sealed messages queue per recipient tag under quotas and expire after a TTL. Every change is
+// appended to a log on disk by a writer thread, so a restart loses nothing and the event loop
+// never waits on the disk; the log is compacted on open and once it outgrows the live messages.
+
+use crate::error::{NetError, Result};
+use libp2p::PeerId;
+use prost::Message;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::warn;
+use umbra_wire::mailbox::{mailbox_record, MailboxEntry, MailboxRecord, MailboxRemoved, MailboxStatus, StoredMessage};
+
+/// How far a fetch timestamp may be from our clock
+pub const FRESHNESS_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Window over which `max_deposits_per_sender` is counted
+pub const DEPOSIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Longest fetch nonce remembered (clients send 16 bytes)
+const MAX_NONCE_LEN: usize = 32;
+
+/// Logs smaller than this are never compacted while running
+const COMPACT_MIN_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct MailboxConfig {
+    /// Log file; `None` keeps everything in memory
+    pub path: Option<PathBuf>,
+    /// Largest sealed message accepted
+    pub max_message_size: usize,
+    pub max_messages_per_tag: usize,
+    pub max_bytes_per_tag: usize,
+    /// Across all recipients
+    pub max_total_bytes: usize,
+    /// Deposits one peer may make per `DEPOSIT_WINDOW`, whatever the tag
+    pub max_deposits_per_sender: u32,
+    /// Messages older than this are dropped unread
+    pub ttl: Duration,
+    /// Upper bound on the messages returned by one fetch (by size)
+    pub max_fetch_bytes: usize,
+    /// Fetch nonces remembered within `FRESHNESS_WINDOW`; once full, fetches are refused
+    /// until some expire rather than forgetting nonces that could then be replayed
+    pub max_seen_nonces: usize,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_message_size: 32 * 1024,
+            max_messages_per_tag: 256,
+            max_bytes_per_tag: 2 * 1024 * 1024,
+            max_total_bytes: 256 * 1024 * 1024,
+            max_deposits_per_sender: 60,
+            ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            max_fetch_bytes: 192 * 1024,
+            max_seen_nonces: 100_000,
+        }
+    }
+}
+
+/// Outcome of a fetch: a batch (oldest first) and how many are still waiting
+#[derive(Debug, Default)]
+pub struct FetchBatch {
+    pub messages: Vec<StoredMessage>,
+    pub remaining: usize,
+}
+
+pub struct MailboxStore {
+    config: MailboxConfig,
+    mailboxes: HashMap<[u8; 32], VecDeque<StoredMessage>>,
+    total_bytes: usize,
+    next_id: u64,
+    /// Fetch nonces seen within the freshness window, with the time they stop mattering
+    seen_nonces: HashMap<Vec<u8>, u64>,
+    /// Deposits per sender in the current window: (window start, count)
+    senders: HashMap<PeerId, (u64, u32)>,
+    log: Option<LogWriter>,
+    /// Bytes in the log file, live or not
+    log_bytes: usize,
+}
+
+impl MailboxStore {
+    /// Open the store, replaying the log at `config.path` if there is one. What was stored is
+    /// held to the current quotas and TTL again, as if it were being deposited now.
+    pub fn open(config: MailboxConfig, now: u64) -> Result<Self> {
+        let mut store = Self {
+            config,
+            mailboxes: HashMap::new(),
+            total_bytes: 0,
+            next_id: 1,
+            seen_nonces: HashMap::new(),
+            senders: HashMap::new(),
+            log: None,
+            log_bytes: 0,
+        };
+
+        let Some(path) = store.config.path.clone() else {
+            return Ok(store);
+        };
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let (stored, next_id) = replay(&path, &bytes)?;
+
+        store.next_id = next_id;
+        let mut dropped = 0;
+        for (tag, message) in stored.into_values() {
+            let expired = message.deposited_at.saturating_add(store.config.ttl.as_secs()) <= now;
+            if expired || store.admit(&tag, &message.message).is_err() {
+                dropped += 1;
+                continue;
+            }
+            store.total_bytes += message.message.len();
+            store.mailboxes.entry(tag).or_default().push_back(message);
+        }
+        if dropped > 0 {
+            warn!("Dropped {} stored mailbox message(s) that are expired or over quota", dropped);
+        }
+
+        store.log = Some(LogWriter::spawn(path));
+        store.compact();
+        Ok(store)
+    }
+
+    pub fn config(&self) -> &MailboxConfig {
+        &self.config
+    }
+
+    /// Messages waiting across all recipients
+    pub fn len(&self) -> usize {
+        self.mailboxes.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mailboxes.is_empty()
+    }
+
+    /// Queue `message` from `sender` for `tag`; returns its id
+    pub fn deposit(
+        &mut self,
+        sender: PeerId,
+        tag: [u8; 32],
+        message: Vec<u8>,
+        now: u64,
+    ) -> std::result::Result<u64, MailboxStatus> {
+        let window = DEPOSIT_WINDOW.as_secs();
+        self.senders.retain(|_, (start, _)| start.saturating_add(window) > now);
+        let (_, count) = self.senders.entry(sender).or_insert((now, 0));
+        if *count >= self.config.max_deposits_per_sender {
+            return Err(MailboxStatus::MailboxRateLimited);
+        }
+        *count += 1;
+
+        let removed = self.expire(now);
+        self.log_removed(removed);
+        self.admit(&tag, &message)?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.total_bytes += message.len();
+        let message = StoredMessage { id, message, deposited_at: now };
+        self.append(mailbox_record::Record::Deposited(MailboxEntry {
+            tag: tag.to_vec(),
+            message: Some(message.clone()),
+        }));
+        self.mailboxes.entry(tag).or_default().push_back(message);
+        Ok(id)
+    }
+
+    /// Accept a fetch's timestamp and nonce at most once
+    pub fn check_fresh(&mut self, timestamp: u64, nonce: &[u8], now: u64) -> bool {
+        let window = FRESHNESS_WINDOW.as_secs();
+        self.seen_nonces.retain(|_, until| *until > now);
+
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN || timestamp.abs_diff(now) > window {
+            return false;
+        }
+        if self.seen_nonces.contains_key(nonce) || self.seen_nonces.len() >= self.config.max_seen_nonces {
+            return false;
+        }
+        self.seen_nonces.insert(nonce.to_vec(), timestamp.max(now) + window);
+        true
+    }
+
+    /// Delete `ack`ed messages, then return the next batch for `tag`
+    pub fn fetch(&mut self, tag: &[u8; 32], ack: &[u64], now: u64) -> FetchBatch {
+        let mut removed = self.expire(now);
+        let Some(queue) = self.mailboxes.get_mut(tag) else {
+            self.log_removed(removed);
+            return FetchBatch::default();
+        };
+
+        if !ack.is_empty() {
+            let mut freed = 0;
+            queue.retain(|m| {
+                let acked = ack.contains(&m.id);
+                if acked {
+                    freed += m.message.len();
+                    removed.push(m.id);
+                }
+                !acked
+            });
+            self.total_bytes -= freed;
+        }
+
+        let mut batch = FetchBatch::default();
+        let mut size = 0;
+        for message in queue.iter() {
+            // Always hand out at least one, whatever the batch limit
+            if !batch.messages.is_empty() && size + message.message.len() > self.config.max_fetch_bytes {
+                break;
+            }
+            size += message.message.len();
+            batch.messages.push(message.clone());
+        }
+        batch.remaining = queue.len() - batch.messages.len();
+
+        if queue.is_empty() {
+            self.mailboxes.remove(tag);
+        }
+        self.log_removed(removed);
+        batch
+    }
+
+    /// Whether `message` fits under the size limit and `tag`'s and the node's quotas
+    fn admit(&self, tag: &[u8; 32], message: &[u8]) -> std::result::Result<(), MailboxStatus> {
+        if message.is_empty() {
+            return Err(MailboxStatus::MailboxInvalid);
+        }
+        if message.len() > self.config.max_message_size {
+            return Err(MailboxStatus::MailboxTooLarge);
+        }
+        let queue = self.mailboxes.get(tag);
+        let queued = queue.map_or(0, VecDeque::len);
+        let queued_bytes: usize = queue.map_or(0, |q| q.iter().map(|m| m.message.len()).sum());
+        if queued >= self.config.max_messages_per_tag
+            || queued_bytes + message.len() > self.config.max_bytes_per_tag
+            || self.total_bytes + message.len() > self.config.max_total_bytes
+        {
+            return Err(MailboxStatus::MailboxQuotaExceeded);
+        }
+        Ok(())
+    }
+
+    /// Drop expired messages; returns the ids removed
+    fn expire(&mut self, now: u64) -> Vec<u64> {
+        let ttl = self.config.ttl.as_secs();
+        let mut removed = Vec::new();
+        for queue in self.mailboxes.values_mut() {
+            while queue.front().is_some_and(|m| m.deposited_at.saturating_add(ttl) <= now) {
+                if let Some(message) = queue.pop_front() {
+                    self.total_bytes -= message.message.len();
+                    removed.push(message.id);
+                }
+            }
+        }
+        self.mailboxes.retain(|_, queue| !queue.is_empty());
+        removed
+    }
+
+    fn log_removed(&mut self, ids: Vec<u64>) {
+        if !ids.is_empty() {
+            self.append(mailbox_record::Record::Removed(MailboxRemoved { ids }));
+        }
+    }
+
+    fn append(&mut self, record: mailbox_record::Record) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        let mut bytes = Vec::new();
+        push_record(&mut bytes, record);
+        self.log_bytes += bytes.len();
+        log.send(LogWrite::Append(bytes));
+
+        // Acks and expiry leave dead records behind: rewrite once they dominate the file
+        if self.log_bytes > COMPACT_MIN_BYTES && self.log_bytes > 2 * self.total_bytes {
+            self.compact();
+        }
+    }
+
+    /// Replace the log with just the live messages
+    fn compact(&mut self) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        let mut bytes = Vec::new();
+        push_record(&mut bytes, mailbox_record::Record::NextId(self.next_id));
+        let mut entries: Vec<(&[u8; 32], &StoredMessage)> = self
+            .mailboxes
+            .iter()
+            .flat_map(|(tag, queue)| queue.iter().map(move |message| (tag, message)))
+            .collect();
+        entries.sort_by_key(|(_, message)| message.id);
+        for (tag, message) in entries {
+            push_record(
+                &mut bytes,
+                mailbox_record::Record::Deposited(MailboxEntry {
+                    tag: tag.to_vec(),
+                    message: Some(message.clone()),
+                }),
+            );
+        }
+        self.log_bytes = bytes.len();
+        log.send(LogWrite::Rewrite(bytes));
+    }
+}
+
+/// Live messages by id, so oldest first
+type Replayed = BTreeMap<u64, ([u8; 32], StoredMessage)>;
+
+/// Messages still live in a log and the next free id
+fn replay(path: &Path, mut bytes: &[u8]) -> Result<(Replayed, u64)> {
+    let mut stored = BTreeMap::new();
+    let mut next_id = 1;
+    while !bytes.is_empty() {
+        let len = bytes.get(..4).map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize);
+        let Some((record, rest)) = len.and_then(|len| bytes[4..].get(..len).map(|record| (record, &bytes[4 + len..])))
+        else {
+            // A write cut short by a crash; compaction drops it
+            warn!("Ignoring truncated record at the end of {}", path.display());
+            break;
+        };
+        bytes = rest;
+
+        let record = MailboxRecord::decode(record)
+            .map_err(|e| NetError::InvalidMessage(format!("Corrupt mailbox log: {}", e)))?;
+        match record.record {
+            Some(mailbox_record::Record::Deposited(MailboxEntry { tag, message: Some(message) })) => {
+                let Ok(tag) = <[u8; 32]>::try_from(tag.as_slice()) else {
+                    continue;
+                };
+                next_id = next_id.max(message.id + 1);
+                stored.insert(message.id, (tag, message));
+            }
+            Some(mailbox_record::Record::Removed(removed)) => {
+                for id in removed.ids {
+                    stored.remove(&id);
+                }
+            }
+            Some(mailbox_record::Record::NextId(id)) => next_id = next_id.max(id),
+            _ => {}
+        }
+    }
+    Ok((stored, next_id))
+}
+
+fn push_record(bytes: &mut Vec<u8>, record: mailbox_record::Record) {
+    let record = MailboxRecord { record: Some(record) }.encode_to_vec();
+    bytes.extend_from_slice(&(record.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&record);
+}
+
+enum LogWrite {
+    Append(Vec<u8>),
+    /// The whole log, replacing what's on disk
+    Rewrite(Vec<u8>),
+}
+
+/// Thread that owns the log file; dropping it waits for queued writes to land
+struct LogWriter {
+    tx: Option<mpsc::Sender<LogWrite>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LogWriter {
+    fn spawn(path: PathBuf) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("mailbox-log".into())
+            .spawn(move || write_log(&path, rx))
+            .map_err(|e| warn!("Failed to start the mailbox log writer: {}", e))
+            .ok();
+        Self { tx: Some(tx), thread }
+    }
+
+    fn send(&self, write: LogWrite) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(write);
+        }
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_log(path: &Path, rx: mpsc::Receiver<LogWrite>) {
+    let mut file = None;
+    while let Ok(first) = rx.recv() {
+        // Everything queued meanwhile goes out in one write and one sync
+        let batch: Vec<LogWrite> = std::iter::once(first).chain(rx.try_iter()).collect();
+        if let Err(e) = write_batch(path, &mut file, batch) {
+            warn!("Failed to save mailbox to {}: {}", path.display(), e);
+            file = None;
+        }
+    }
+}
+
+fn write_batch(path: &Path, file: &mut Option<File>, batch: Vec<LogWrite>) -> std::io::Result<()> {
+    // A rewrite already holds everything queued before it
+    let start = batch.iter().rposition(|write| matches!(write, LogWrite::Rewrite(_)));
+    let mut appended = Vec::new();
+    for write in batch.into_iter().skip(start.unwrap_or(0)) {
+        match write {
+            LogWrite::Rewrite(bytes) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                let tmp = path.with_extension("tmp");
+                std::fs::write(&tmp, bytes)?;
+                std::fs::rename(&tmp, path)?;
+                *file = None;
+            }
+            LogWrite::Append(bytes) => appended.extend_from_slice(&bytes),
+        }
+    }
+    if appended.is_empty() {
+        return Ok(());
+    }
+
+    if file.is_none() {
+        *file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+    }
+    if let Some(file) = file {
+        file.write_all(&appended)?;
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG: [u8; 32] = [7u8; 32];
+
+    fn store(config: MailboxConfig) -> MailboxStore {
+        MailboxStore::open(config, 0).unwrap()
+    }
+
+    fn deposit(mailbox: &mut MailboxStore, tag: [u8; 32], message: Vec<u8>, now: u64) -> std::result::Result<u64, MailboxStatus> {
+        mailbox.deposit(PeerId::random(), tag, message, now)
+    }
+
+    #[test]
+    fn test_deposit_fetch_ack() {
+        let mut mailbox = store(MailboxConfig::default());
+        let a = deposit(&mut mailbox, TAG, b"first".to_vec(), 100).unwrap();
+        let b = deposit(&mut mailbox, TAG, b"second".to_vec(), 101).unwrap();
+
+        let batch = mailbox.fetch(&TAG, &[], 102);
+        let ids: Vec<u64> = batch.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![a, b]);
+        assert!(mailbox.fetch(&[8u8; 32], &[], 102).messages.is_empty());
+
+        // Nothing is deleted until it's acked
+        assert_eq!(mailbox.fetch(&TAG, &[a], 103).messages.len(), 1);
+        assert!(mailbox.fetch(&TAG, &[b], 103).messages.is_empty());
+        assert!(mailbox.is_empty());
+    }
+
+    #[test]
+    fn test_quotas() {
+        let config = MailboxConfig {
+            max_message_size: 8,
+            max_messages_per_tag: 2,
+            max_bytes_per_tag: 12,
+            max_total_bytes: 20,
+            ..Default::default()
+        };
+        let mut mailbox = store(config);
+
+        assert_eq!(deposit(&mut mailbox, TAG, vec![0; 9], 0), Err(MailboxStatus::MailboxTooLarge));
+        deposit(&mut mailbox, TAG, vec![0; 8], 0).unwrap();
+        assert_eq!(deposit(&mut mailbox, TAG, vec![0; 5], 0), Err(MailboxStatus::MailboxQuotaExceeded));
+        deposit(&mut mailbox, TAG, vec![0; 4], 0).unwrap();
+        assert_eq!(deposit(&mut mailbox, TAG, vec![0; 1], 0), Err(MailboxStatus::MailboxQuotaExceeded));
+
+        deposit(&mut mailbox, [1u8; 32], vec![0; 8], 0).unwrap();
+        assert_eq!(deposit(&mut mailbox, [2u8; 32], vec![0; 1], 0), Err(MailboxStatus::MailboxQuotaExceeded));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let config = MailboxConfig {
+            ttl: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut mailbox = store(config);
+        deposit(&mut mailbox, TAG, b"old".to_vec(), 0).unwrap();
+        deposit(&mut mailbox, TAG, b"new".to_vec(), 30).unwrap();
+
+        let batch = mailbox.fetch(&TAG, &[], 60);
+        assert_eq!(batch.messages.len(), 1);
+        assert_eq!(batch.messages[0].message, b"new");
+    }
+
+    #[test]
+    fn test_fetch_batches_by_size() {
+        let config = MailboxConfig {
+            max_fetch_bytes: 10,
+            ..Default::default()
+        };
+        let mut mailbox = store(config);
+        for _ in 0..3 {
+            deposit(&mut mailbox, TAG, vec![0; 6], 0).unwrap();
+        }
+
+        let batch = mailbox.fetch(&TAG, &[], 1);
+        assert_eq!(batch.messages.len(), 1);
+        assert_eq!(batch.remaining, 2);
+    }
+
+    #[test]
+    fn test_replayed_nonce_rejected() {
+        let mut mailbox = store(MailboxConfig::default());
+        let now = 1_000_000;
+        assert!(mailbox.check_fresh(now, b"nonce", now));
+        assert!(!mailbox.check_fresh(now, b"nonce", now + 1));
+        assert!(!mailbox.check_fresh(now - FRESHNESS_WINDOW.as_secs() - 1, b"other", now));
+        assert!(mailbox.check_fresh(now + 10, b"other", now));
+        assert!(!mailbox.check_fresh(now, &[7; MAX_NONCE_LEN + 1], now));
+    }
+
+    #[test]
+    fn test_seen_nonces_capped() {
+        let config = MailboxConfig {
+            max_seen_nonces: 2,
+            ..Default::default()
+        };
+        let mut mailbox = store(config);
+        let now = 1_000_000;
+        assert!(mailbox.check_fresh(now, b"a", now));
+        assert!(mailbox.check_fresh(now, b"b", now));
+        assert!(!mailbox.check_fresh(now, b"c", now));
+
+        // Room again once the remembered ones expire
+        let later = now + FRESHNESS_WINDOW.as_secs() + 1;
+        assert!(mailbox.check_fresh(later, b"c", later));
+    }
+
+    #[test]
+    fn test_persisted_across_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = MailboxConfig {
+            path: Some(dir.path().join("mailbox.bin")),
+            ..Default::default()
+        };
+
+        let mut mailbox = store(config.clone());
+        let a = deposit(&mut mailbox, TAG, b"kept".to_vec(), 0).unwrap();
+        let b = deposit(&mut mailbox, TAG, b"acked".to_vec(), 0).unwrap();
+        mailbox.fetch(&TAG, &[b], 1);
+        drop(mailbox);
+
+        let mut mailbox = store(config);
+        let batch = mailbox.fetch(&TAG, &[], 2);
+        assert_eq!(batch.messages.len(), 1);
+        assert_eq!(batch.messages[0].id, a);
+        assert!(deposit(&mut mailbox, TAG, b"next".to_vec(), 3).unwrap() > b);
+    }
+
+    #[test]
+    fn test_reload_applies_quotas_and_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = MailboxConfig {
+            path: Some(dir.path().join("mailbox.bin")),
+            ttl: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        let mut mailbox = store(config.clone());
+        deposit(&mut mailbox, TAG, b"old".to_vec(), 0).unwrap();
+        let kept = deposit(&mut mailbox, TAG, b"kept".to_vec(), 30).unwrap();
+        deposit(&mut mailbox, TAG, b"over".to_vec(), 31).unwrap();
+        deposit(&mut mailbox, [1u8; 32], vec![0; 100], 31).unwrap();
+        drop(mailbox);
+
+        // Tighter limits since the restart: one message per tag, nothing over 50 bytes
+        let config = MailboxConfig {
+            max_messages_per_tag: 1,
+            max_message_size: 50,
+            ..config
+        };
+        let mut mailbox = MailboxStore::open(config.clone(), 60).unwrap();
+        assert_eq!(mailbox.len(), 1);
+        assert_eq!(mailbox.fetch(&TAG, &[], 60).messages[0].id, kept);
+        drop(mailbox);
+
+        // And the compacted log keeps only what survived
+        assert_eq!(MailboxStore::open(config, 60).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_truncated_log_tail_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mailbox.bin");
+        let config = MailboxConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let mut mailbox = store(config.clone());
+        deposit(&mut mailbox, TAG, b"whole".to_vec(), 0).unwrap();
+        drop(mailbox);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 40, 1, 2]).unwrap();
+        drop(file);
+
+        assert_eq!(store(config).fetch(&TAG, &[], 1).messages[0].message, b"whole");
+    }
+
+    #[test]
+    fn test_deposits_rate_limited_per_sender() {
+        let config = MailboxConfig {
+            max_deposits_per_sender: 2,
+            ..Default::default()
+        };
+        let mut mailbox = store(config);
+        let sender = PeerId::random();
+
+        mailbox.deposit(sender, TAG, b"one".to_vec(), 0).unwrap();
+        mailbox.deposit(sender, [1u8; 32], b"two".to_vec(), 1).unwrap();
+        assert_eq!(mailbox.deposit(sender, TAG, b"three".to_vec(), 2), Err(MailboxStatus::MailboxRateLimited));
+        // Other senders aren't held back, and the window passes
+        mailbox.deposit(PeerId::random(), TAG, b"other".to_vec(), 2).unwrap();
+        mailbox.deposit(sender, TAG, b"later".to_vec(), DEPOSIT_WINDOW.as_secs()).unwrap();
+    }
+}
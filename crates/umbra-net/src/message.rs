@@ -4,32 +4,87 @@
 use crate::error::{NetError, Result};
 use libp2p::PeerId;
 use prost::Message;
-use tracing::debug;
+use tracing::{debug, warn};
 use umbra_crypto::session::SessionManager;
-use umbra_crypto::aead::Envelope;
-use umbra_wire::message::{ChatMessage, EncryptedMessage};
-use ed25519_dalek;
+use umbra_crypto::error::CryptoError;
+use umbra_crypto::identity::{HybridSignature, IdentityKey};
+use umbra_crypto::ratchet::{RatchetHeader, RatchetMessage};
+use umbra_wire::message::{ChatMessage, EncryptedMessage, GroupMessage};
 use umbra_identity::{Identity, Prover, verify_identity_proof};
 
+/// How `decrypt_message` treats messages whose sender can't be verified
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerificationMode {
+    /// Reject unless signed with the peer's registered key
+    #[default]
+    Strict,
+    /// Deliver anyway, flagged as `VerificationStatus::Unverified`
+    Lenient,
+}
+
+/// Outcome of checking the sender's signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationStatus {
+    /// Ed25519 and Dilithium3 signatures both verified
+    Verified,
+    /// Ed25519 verified, peer has no Dilithium3 key
+    ClassicalOnly,
+    /// Sender not verified (only returned in lenient mode)
+    Unverified,
+}
+
+impl VerificationStatus {
+    pub fn is_verified(&self) -> bool {
+        !matches!(self, VerificationStatus::Unverified)
+    }
+}
+
+/// A decrypted chat message and what we know about its sender
+#[derive(Debug, Clone)]
+pub struct DecryptedMessage {
+    pub username: String,
+    pub content: String,
+    /// ZK identity, if the sender attached a valid proof
+    pub identity: Option<[u8; 32]>,
+    pub verification: VerificationStatus,
+    /// Encoded `GroupControl` for the node to handle (control message, no chat content)
+    pub group_control: Option<Vec<u8>>,
+}
+
 /// Manages message encryption/decryption for all peers
 pub struct MessageExchange {
     session_mgr: SessionManager,
     local_peer_id: PeerId,
     identity: Option<Identity>,
     prover: Option<Prover>,
+    verification_mode: VerificationMode,
 }
 
 impl MessageExchange {
     pub fn new(local_peer_id: PeerId) -> Result<Self> {
-        let session_mgr = SessionManager::new(local_peer_id)
-            .map_err(|e| NetError::Crypto(format!("SessionManager init: {}", e)))?;
-        
-        Ok(Self { 
-            session_mgr, 
+        let identity = IdentityKey::generate()
+            .map_err(|e| NetError::Crypto(format!("Identity key: {}", e)))?;
+        Ok(Self::with_identity(local_peer_id, identity))
+    }
+
+    /// Sign and decrypt with a long-term identity (e.g. one reloaded from the data dir)
+    pub fn with_identity(local_peer_id: PeerId, identity: IdentityKey) -> Self {
+        Self {
+            session_mgr: SessionManager::with_identity(local_peer_id, identity),
             local_peer_id,
             identity: None,
             prover: None,
-        })
+            verification_mode: VerificationMode::default(),
+        }
+    }
+
+    pub fn verification_mode(&self) -> VerificationMode {
+        self.verification_mode
+    }
+
+    /// Choose between rejecting and flagging unverified senders
+    pub fn set_verification_mode(&mut self, mode: VerificationMode) {
+        self.verification_mode = mode;
     }
 
     /// Set identity and prover for ZK proofs
@@ -54,8 +109,12 @@ impl MessageExchange {
         username: &str,
         content: &str,
     ) -> Result<Vec<u8>> {
-        // Create plaintext message
-        let chat_msg = ChatMessage {
+        let chat_msg = self.chat_message(username, content)?;
+        self.encrypt_chat(peer, &chat_msg)
+    }
+
+    fn chat_message(&self, username: &str, content: &str) -> Result<ChatMessage> {
+        Ok(ChatMessage {
             username: username.to_string(),
             content: content.to_string(),
             timestamp: std::time::SystemTime::now()
@@ -65,8 +124,27 @@ impl MessageExchange {
             identity_id: self.identity.as_ref()
                 .map(|id| id.id.to_vec())
                 .unwrap_or_default(),
-        };
+            cover: false,
+            group_control: Vec::new(),
+        })
+    }
+
+    /// Cover message for a peer: encrypted and signed like any chat message
+    pub fn encrypt_cover(&mut self, peer: PeerId) -> Result<Vec<u8>> {
+        let mut chat_msg = self.chat_message("", &crate::cover::dummy_content())?;
+        chat_msg.cover = true;
+        self.encrypt_chat(peer, &chat_msg)
+    }
+
+    /// Cover message for a group, as the plaintext of an MLS application message
+    pub fn group_cover(&self) -> Result<Vec<u8>> {
+        let mut chat_msg = self.chat_message("", &crate::cover::dummy_content())?;
+        chat_msg.cover = true;
+        Ok(self.group_chat(chat_msg))
+    }
 
+    /// Sign and ratchet-encrypt a ChatMessage for one peer
+    fn encrypt_chat(&mut self, peer: PeerId, chat_msg: &ChatMessage) -> Result<Vec<u8>> {
         // Serialize to protobuf
         let plaintext = chat_msg.encode_to_vec();
 
@@ -74,149 +152,227 @@ impl MessageExchange {
         let hybrid_sig = self.session_mgr.sign(&plaintext)
             .map_err(|e| NetError::Crypto(format!("Sign: {}", e)))?;
 
-        // Get or create session and copy key
-        let session_key = {
-            let session = self.session_mgr.get_session(peer)
-                .map_err(|e| NetError::Crypto(format!("Get session: {}", e)))?;
-            *session.key()
-        };
-
-        // Encrypt with session key
-        let envelope = Envelope::new(&session_key)
-            .map_err(|e| NetError::Crypto(format!("Envelope init: {}", e)))?;
-        
-        let encrypted_data = envelope.encrypt(&plaintext)
+        // Get or create session and encrypt with the next ratchet message key
+        let ratchet_msg = self.session_mgr.get_session(peer)
+            .map_err(|e| NetError::Crypto(format!("Get session: {}", e)))?
+            .encrypt(&plaintext)
             .map_err(|e| NetError::Crypto(format!("Encrypt: {}", e)))?;
 
-        // Split into nonce || ciphertext
-        let (nonce, ciphertext) = encrypted_data.split_at(12);
+        let ratchet_header = ratchet_msg.header.to_bytes()
+            .map_err(|e| NetError::Crypto(format!("Ratchet header: {}", e)))?;
 
-        // Generate ZK proof if identity is set
-        let (identity_id, identity_proof) = if let (Some(identity), Some(prover)) = 
-            (&self.identity, &self.prover) {
-            match identity.generate_proof(prover) {
-                Ok(proof) => (identity.id.to_vec(), proof),
-                Err(e) => {
-                    debug!("⚠️  Failed to generate proof: {}", e);
-                    (vec![], vec![])
-                }
-            }
-        } else {
-            (vec![], vec![])
-        };
+        let (identity_id, identity_proof) = self.identity_proof();
 
         // Create encrypted message with hybrid signature
         let enc_msg = EncryptedMessage {
             sender: self.local_peer_id.to_bytes(),
-            nonce: nonce.to_vec(),
-            ciphertext: ciphertext.to_vec(),
+            nonce: vec![],
+            ciphertext: ratchet_msg.ciphertext,
             timestamp: chat_msg.timestamp,
             signature: hybrid_sig.classical,
             identity_id,
             identity_proof,
             pq_signature: hybrid_sig.pq.unwrap_or_default(),
+            ratchet_header,
         };
 
-        // Increment message counter
-        self.session_mgr.get_session(peer)
-            .map_err(|e| NetError::Crypto(format!("Get session: {}", e)))?
-            .increment();
-
         // Serialize to wire format
         Ok(enc_msg.encode_to_vec())
     }
 
+    /// Generate ZK proof if identity is set
+    fn identity_proof(&self) -> (Vec<u8>, Vec<u8>) {
+        if let (Some(identity), Some(prover)) = (&self.identity, &self.prover) {
+            match identity.generate_proof(prover) {
+                Ok(proof) => (identity.id.to_vec(), proof),
+                Err(e) => {
+                    debug!("⚠️  Failed to generate proof: {}", e);
+                    (vec![], vec![])
+                }
+            }
+        } else {
+            (vec![], vec![])
+        }
+    }
+
     /// Decrypt a chat message from a peer
     pub fn decrypt_message(
         &mut self,
         peer: PeerId,
         data: &[u8],
-    ) -> Result<(String, String, Option<[u8; 32]>)> {
+    ) -> Result<DecryptedMessage> {
         // Deserialize encrypted message
-        let enc_msg = EncryptedMessage::decode(data)
+        let mut enc_msg = EncryptedMessage::decode(data)
             .map_err(|e| NetError::Protocol(format!("Decode EncryptedMessage: {}", e)))?;
 
-        // Get session for peer
-        let session = self.session_mgr.get_session(peer)
-            .map_err(|e| NetError::Crypto(format!("Get session: {}", e)))?;
-
-        // Reconstruct encrypted data (nonce || ciphertext)
-        let mut encrypted_data = Vec::with_capacity(enc_msg.nonce.len() + enc_msg.ciphertext.len());
-        encrypted_data.extend_from_slice(&enc_msg.nonce);
-        encrypted_data.extend_from_slice(&enc_msg.ciphertext);
+        let header = RatchetHeader::from_bytes(&enc_msg.ratchet_header)
+            .map_err(|e| NetError::Protocol(format!("Decode ratchet header: {}", e)))?;
+        let ratchet_msg = RatchetMessage {
+            header,
+            ciphertext: std::mem::take(&mut enc_msg.ciphertext),
+        };
 
-        // Decrypt
-        let envelope = Envelope::new(session.key())
-            .map_err(|e| NetError::Crypto(format!("Envelope init: {}", e)))?;
-        
-        let plaintext = envelope.decrypt(&encrypted_data)
+        // Decrypt with the peer's ratchet, held back until the sender checks out
+        let opened = self.session_mgr.get_session(peer)
+            .map_err(|e| NetError::Crypto(format!("Get session: {}", e)))?
+            .open(&ratchet_msg)
             .map_err(|e| NetError::Crypto(format!("Decrypt: {}", e)))?;
 
-        // Verify signature if present AND peer key is registered
-        if !enc_msg.signature.is_empty() {
-            // Parse Ed25519 signature
-            if enc_msg.signature.len() != 64 {
-                debug!("⚠️  Invalid signature length, skipping verification");
-            } else {
-                let mut sig_bytes = [0u8; 64];
-                sig_bytes.copy_from_slice(&enc_msg.signature);
-                let signature = ed25519_dalek::Signature::from_bytes(&sig_bytes);
-                
-                // Try to verify - if peer key not registered or verification fails, just log warning
-                if self.session_mgr.get_peer_key(&peer).is_some() {
-                    match self.session_mgr.verify(&peer, &plaintext, &signature) {
-                        Ok(_) => {
-                            debug!("✅ Ed25519 signature verified for peer {}", peer);
-                        }
-                        Err(e) => {
-                            debug!("⚠️  Signature verification failed (mock keys): {}", e);
-                            debug!("   This is expected until proper key exchange is implemented (v0.6.0)");
-                        }
-                    }
-                } else {
-                    debug!("⚠️  Peer key not registered, skipping signature verification for {}", peer);
-                }
-            }
-            
-            // TODO: Verify Dilithium3 signature if pq_signature is present
-            if !enc_msg.pq_signature.is_empty() {
-                debug!("ℹ️  Dilithium3 signature present but verification not yet implemented");
-            }
-        }
+        // Verify sender signature (strict mode rejects, lenient mode flags)
+        let verification = self.check_sender(peer, &enc_msg.signature, &enc_msg.pq_signature, &opened.plaintext)?;
+
+        let plaintext = self.session_mgr.get_session(peer)
+            .map_err(|e| NetError::Crypto(format!("Get session: {}", e)))?
+            .commit(opened);
 
         // Verify ZK identity proof if present
-        let mut verified_identity: Option<[u8; 32]> = None;
-        if !enc_msg.identity_id.is_empty() && !enc_msg.identity_proof.is_empty() {
-            if enc_msg.identity_id.len() == 32 {
-                let mut id = [0u8; 32];
-                id.copy_from_slice(&enc_msg.identity_id);
-                
-                if let Some(prover) = &self.prover {
-                    match verify_identity_proof(prover, &enc_msg.identity_proof, &id) {
-                        Ok(true) => {
-                            debug!("✅ Identity proof verified for {}", hex::encode(&id[..8]));
-                            verified_identity = Some(id);
-                        }
-                        Ok(false) => {
-                            debug!("❌ Identity proof verification failed");
-                        }
-                        Err(e) => {
-                            debug!("⚠️  Identity proof error: {}", e);
-                        }
-                    }
-                } else {
-                    debug!("⚠️  No prover available to verify identity proof");
-                }
-            }
-        }
+        let verified_identity = self.verify_identity(&enc_msg.identity_id, &enc_msg.identity_proof);
 
         // Deserialize chat message
         let chat_msg = ChatMessage::decode(&plaintext[..])
             .map_err(|e| NetError::Protocol(format!("Decode ChatMessage: {}", e)))?;
 
+        if chat_msg.cover {
+            return Err(NetError::CoverTraffic);
+        }
+
         debug!("Decrypted and verified message from {}: {}", chat_msg.username, chat_msg.content);
 
-        Ok((chat_msg.username, chat_msg.content, verified_identity))
+        Ok(DecryptedMessage {
+            username: chat_msg.username,
+            content: chat_msg.content,
+            identity: verified_identity,
+            verification,
+            group_control: (!chat_msg.group_control.is_empty()).then_some(chat_msg.group_control),
+        })
+    }
+
+    /// Strict mode rejects unverified senders, lenient mode flags them
+    fn check_sender(
+        &self,
+        peer: PeerId,
+        signature: &[u8],
+        pq_signature: &[u8],
+        signed: &[u8],
+    ) -> Result<VerificationStatus> {
+        match self.verify_sender(peer, signature, pq_signature, signed) {
+            Ok(status) => Ok(status),
+            Err(e) if self.verification_mode == VerificationMode::Lenient => {
+                warn!("⚠️  Unverified message from {}: {}", peer, e);
+                Ok(VerificationStatus::Unverified)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check a ZK identity proof, if one is attached
+    fn verify_identity(&self, identity_id: &[u8], identity_proof: &[u8]) -> Option<[u8; 32]> {
+        if identity_proof.is_empty() || identity_id.len() != 32 {
+            return None;
+        }
+
+        let mut id = [0u8; 32];
+        id.copy_from_slice(identity_id);
+        
+        let Some(prover) = &self.prover else {
+            debug!("⚠️  No prover available to verify identity proof");
+            return None;
+        };
+
+        match verify_identity_proof(prover, identity_proof, &id) {
+            Ok(true) => {
+                debug!("✅ Identity proof verified for {}", hex::encode(&id[..8]));
+                Some(id)
+            }
+            Ok(false) => {
+                debug!("❌ Identity proof verification failed");
+                None
+            }
+            Err(e) => {
+                debug!("⚠️  Identity proof error: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Check the hybrid signature over `signed` against the peer's registered key
+    fn verify_sender(
+        &self,
+        peer: PeerId,
+        signature: &[u8],
+        pq_signature: &[u8],
+        signed: &[u8],
+    ) -> Result<VerificationStatus> {
+        if signature.is_empty() {
+            return Err(NetError::MissingSignature(peer.to_string()));
+        }
+        if signature.len() != 64 {
+            return Err(NetError::MalformedSignature(format!(
+                "expected 64-byte Ed25519 signature, got {} bytes",
+                signature.len()
+            )));
+        }
+
+        let peer_key = self.session_mgr.get_peer_hybrid_key(&peer)
+            .ok_or_else(|| NetError::UnknownPeerKey(peer.to_string()))?;
+
+        // Ed25519 and (if the peer has one) Dilithium3 must both pass
+        let signature = HybridSignature {
+            classical: signature.to_vec(),
+            pq: (!pq_signature.is_empty()).then(|| pq_signature.to_vec()),
+        };
+        self.session_mgr.verify_hybrid(&peer, signed, &signature)
+            .map_err(|e| match e {
+                CryptoError::PostQuantum(msg) => NetError::PqSignatureInvalid(msg),
+                other => NetError::SignatureInvalid(other.to_string()),
+            })?;
+
+        debug!("✅ Hybrid signature verified for peer {}", peer);
+
+        if peer_key.is_hybrid() {
+            Ok(VerificationStatus::Verified)
+        } else {
+            Ok(VerificationStatus::ClassicalOnly)
+        }
+    }
+
+    /// An encoded `GroupControl` (invite, join request, Welcome), encrypted and signed for `peer`
+    /// over the pairwise session
+    pub fn group_control_message(&mut self, peer: PeerId, control: Vec<u8>) -> Result<Vec<u8>> {
+        let mut chat_msg = self.chat_message("", "")?;
+        chat_msg.group_control = control;
+        self.encrypt_chat(peer, &chat_msg)
+    }
+
+    /// A chat message for a group, as the plaintext of an MLS application message
+    pub fn encrypt_group_message(&self, username: &str, content: &str) -> Result<Vec<u8>> {
+        Ok(self.group_chat(self.chat_message(username, content)?))
+    }
+
+    fn group_chat(&self, chat_msg: ChatMessage) -> Vec<u8> {
+        let (_, identity_proof) = self.identity_proof();
+        GroupMessage { chat: Some(chat_msg), identity_proof }.encode_to_vec()
+    }
+
+    /// Read the plaintext of a group's MLS application message. MLS has already checked the
+    /// sender's signature, with the identity key they joined under (the one from their
+    /// handshake), so there's nothing left to verify but the ZK identity.
+    pub fn decrypt_group_message(&self, plaintext: &[u8]) -> Result<DecryptedMessage> {
+        let group_msg = GroupMessage::decode(plaintext)
+            .map_err(|e| NetError::Protocol(format!("Decode GroupMessage: {}", e)))?;
+        let chat_msg = group_msg.chat
+            .ok_or_else(|| NetError::Protocol("GroupMessage without a chat message".to_string()))?;
+        if chat_msg.cover {
+            return Err(NetError::CoverTraffic);
+        }
+
+        Ok(DecryptedMessage {
+            identity: self.verify_identity(&chat_msg.identity_id, &group_msg.identity_proof),
+            username: chat_msg.username,
+            content: chat_msg.content,
+            verification: VerificationStatus::Verified,
+            group_control: None,
+        })
     }
 
     /// Clean up expired sessions
@@ -236,28 +392,30 @@ mod tests {
 
     #[test]
     fn test_message_roundtrip() {
-        let shared_peer_id = PeerId::random(); // Use same PeerID for both to derive same symmetric key
-        let mut alice = MessageExchange::new(shared_peer_id).unwrap();
-        let mut bob = MessageExchange::new(shared_peer_id).unwrap();
-        
-        let peer_id = PeerId::random();
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = MessageExchange::new(alice_peer).unwrap();
+        let mut bob = MessageExchange::new(bob_peer).unwrap();
+        alice.session_manager_mut().set_session_key(bob_peer, [7u8; 32]).unwrap();
+        bob.session_manager_mut().set_session_key(alice_peer, [7u8; 32]).unwrap();
 
         // Register keys for signature verification
-        let alice_pubkey = *alice.session_manager().public_key();
-        bob.session_manager_mut().register_peer(peer_id, alice_pubkey);
+        let alice_pubkey = alice.session_manager().hybrid_public_key();
+        bob.session_manager_mut().register_peer_hybrid(alice_peer, alice_pubkey);
 
         // Alice encrypts
         let encrypted = alice.encrypt_message(
-            peer_id,
+            bob_peer,
             "alice",
             "hello bob!",
         ).unwrap();
 
-        // Bob decrypts (same local_peer_id means same derived symmetric key)
-        let (username, content, _identity) = bob.decrypt_message(peer_id, &encrypted).unwrap();
+        // Bob decrypts (both ratchets are seeded from the same session key)
+        let msg = bob.decrypt_message(alice_peer, &encrypted).unwrap();
         
-        assert_eq!(username, "alice");
-        assert_eq!(content, "hello bob!");
+        assert_eq!(msg.username, "alice");
+        assert_eq!(msg.content, "hello bob!");
+        assert_eq!(msg.verification, VerificationStatus::Verified);
     }
 
     #[test]
@@ -267,6 +425,8 @@ mod tests {
         
         let alice_peer = PeerId::random();
         let eve_peer = PeerId::random();
+        alice.session_manager_mut().set_session_key(alice_peer, [1u8; 32]).unwrap();
+        eve.session_manager_mut().set_session_key(eve_peer, [2u8; 32]).unwrap();
 
         // Alice encrypts for alice_peer
         let encrypted = alice.encrypt_message(
@@ -286,6 +446,7 @@ mod tests {
     fn test_session_increment() {
         let mut exchange = MessageExchange::new(PeerId::random()).unwrap();
         let peer = PeerId::random();
+        exchange.session_manager_mut().set_session_key(peer, [3u8; 32]).unwrap();
 
         // Send 3 messages
         for _ in 0..3 {
@@ -296,4 +457,188 @@ mod tests {
         let session = exchange.session_mgr.get_session(peer).unwrap();
         assert_eq!(session.msg_count(), 3);
     }
+
+    #[test]
+    fn test_no_session_fails() {
+        let mut alice = MessageExchange::new(PeerId::random()).unwrap();
+        let mut bob = MessageExchange::new(PeerId::random()).unwrap();
+        let peer = PeerId::random();
+
+        // No handshake or prekey session: nothing to encrypt or decrypt with
+        assert!(alice.encrypt_message(peer, "alice", "hi").is_err());
+
+        bob.session_manager_mut().set_session_key(peer, [4u8; 32]).unwrap();
+        let encrypted = bob.encrypt_message(peer, "bob", "hi").unwrap();
+        assert!(alice.decrypt_message(peer, &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_each_message_uses_fresh_key() {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = MessageExchange::new(alice_peer).unwrap();
+        let mut bob = MessageExchange::new(bob_peer).unwrap();
+        alice.session_manager_mut().set_session_key(bob_peer, [11u8; 32]).unwrap();
+        bob.session_manager_mut().set_session_key(alice_peer, [11u8; 32]).unwrap();
+        register_both(&mut alice, alice_peer, &mut bob, bob_peer);
+
+        let first = alice.encrypt_message(bob_peer, "alice", "one").unwrap();
+        let second = alice.encrypt_message(bob_peer, "alice", "two").unwrap();
+
+        // Delivered out of order, both still decrypt
+        assert_eq!(bob.decrypt_message(alice_peer, &second).unwrap().content, "two");
+        assert_eq!(bob.decrypt_message(alice_peer, &first).unwrap().content, "one");
+
+        let reply = bob.encrypt_message(alice_peer, "bob", "three").unwrap();
+        assert_eq!(alice.decrypt_message(bob_peer, &reply).unwrap().content, "three");
+    }
+
+    fn register_both(alice: &mut MessageExchange, alice_peer: PeerId, bob: &mut MessageExchange, bob_peer: PeerId) {
+        let alice_key = alice.session_manager().hybrid_public_key();
+        let bob_key = bob.session_manager().hybrid_public_key();
+        alice.session_manager_mut().register_peer_hybrid(bob_peer, bob_key);
+        bob.session_manager_mut().register_peer_hybrid(alice_peer, alice_key);
+    }
+
+    fn pair() -> (MessageExchange, PeerId, MessageExchange, PeerId) {
+        let alice_peer = PeerId::random();
+        let bob_peer = PeerId::random();
+        let mut alice = MessageExchange::new(alice_peer).unwrap();
+        let mut bob = MessageExchange::new(bob_peer).unwrap();
+        alice.session_manager_mut().set_session_key(bob_peer, [5u8; 32]).unwrap();
+        bob.session_manager_mut().set_session_key(alice_peer, [5u8; 32]).unwrap();
+        (alice, alice_peer, bob, bob_peer)
+    }
+
+    /// Re-encode a message with its signature fields rewritten
+    fn resign(data: &[u8], edit: impl FnOnce(&mut EncryptedMessage)) -> Vec<u8> {
+        let mut msg = EncryptedMessage::decode(data).unwrap();
+        edit(&mut msg);
+        msg.encode_to_vec()
+    }
+
+    #[test]
+    fn test_strict_is_default() {
+        let (alice, _, _, _) = pair();
+        assert_eq!(alice.verification_mode(), VerificationMode::Strict);
+    }
+
+    #[test]
+    fn test_strict_rejects_unknown_peer_key() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "hi").unwrap();
+
+        let result = bob.decrypt_message(alice_peer, &encrypted);
+        assert!(matches!(result, Err(NetError::UnknownPeerKey(_))));
+    }
+
+    #[test]
+    fn test_lenient_flags_unknown_peer_key() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        bob.set_verification_mode(VerificationMode::Lenient);
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "hi").unwrap();
+
+        let msg = bob.decrypt_message(alice_peer, &encrypted).unwrap();
+        assert_eq!(msg.content, "hi");
+        assert_eq!(msg.verification, VerificationStatus::Unverified);
+        assert!(!msg.verification.is_verified());
+    }
+
+    #[test]
+    fn test_strict_rejects_missing_signature() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        register_both(&mut alice, alice_peer, &mut bob, bob_peer);
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "hi").unwrap();
+        let stripped = resign(&encrypted, |m| {
+            m.signature.clear();
+            m.pq_signature.clear();
+        });
+
+        let result = bob.decrypt_message(alice_peer, &stripped);
+        assert!(matches!(result, Err(NetError::MissingSignature(_))));
+    }
+
+    #[test]
+    fn test_strict_rejects_malformed_signature() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        register_both(&mut alice, alice_peer, &mut bob, bob_peer);
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "hi").unwrap();
+        let truncated = resign(&encrypted, |m| m.signature.truncate(32));
+
+        let result = bob.decrypt_message(alice_peer, &truncated);
+        assert!(matches!(result, Err(NetError::MalformedSignature(_))));
+    }
+
+    #[test]
+    fn test_strict_rejects_bad_signatures() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        register_both(&mut alice, alice_peer, &mut bob, bob_peer);
+
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "one").unwrap();
+        let bad_classical = resign(&encrypted, |m| m.signature[0] ^= 0xFF);
+        let result = bob.decrypt_message(alice_peer, &bad_classical);
+        assert!(matches!(result, Err(NetError::SignatureInvalid(_))));
+
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "two").unwrap();
+        let bad_pq = resign(&encrypted, |m| m.pq_signature[0] ^= 0xFF);
+        let result = bob.decrypt_message(alice_peer, &bad_pq);
+        assert!(matches!(result, Err(NetError::PqSignatureInvalid(_))));
+    }
+
+    #[test]
+    fn test_rejected_message_leaves_ratchet_usable() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        register_both(&mut alice, alice_peer, &mut bob, bob_peer);
+
+        // A forged signature on an authentic ciphertext must not consume its message key
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "hi").unwrap();
+        let forged = resign(&encrypted, |m| m.signature[0] ^= 0xFF);
+        assert!(matches!(bob.decrypt_message(alice_peer, &forged), Err(NetError::SignatureInvalid(_))));
+
+        let msg = bob.decrypt_message(alice_peer, &encrypted).unwrap();
+        assert_eq!(msg.content, "hi");
+        let next = alice.encrypt_message(bob_peer, "alice", "again").unwrap();
+        assert_eq!(bob.decrypt_message(alice_peer, &next).unwrap().content, "again");
+    }
+
+    #[test]
+    fn test_classical_only_peer_status() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        let alice_key = *alice.session_manager().public_key();
+        bob.session_manager_mut().register_peer(alice_peer, alice_key);
+
+        let encrypted = alice.encrypt_message(bob_peer, "alice", "hi").unwrap();
+        let msg = bob.decrypt_message(alice_peer, &encrypted).unwrap();
+        assert_eq!(msg.verification, VerificationStatus::ClassicalOnly);
+        assert!(msg.verification.is_verified());
+    }
+
+    #[test]
+    fn test_group_message_plaintext() {
+        let (alice, _, bob, _) = pair();
+        let data = alice.encrypt_group_message("alice", "hi all").unwrap();
+        let msg = bob.decrypt_group_message(&data).unwrap();
+        assert_eq!((msg.username.as_str(), msg.content.as_str()), ("alice", "hi all"));
+        assert_eq!(msg.identity, None);
+    }
+
+    #[test]
+    fn test_cover_dropped_after_decryption() {
+        let (mut alice, alice_peer, mut bob, bob_peer) = pair();
+        register_both(&mut alice, alice_peer, &mut bob, bob_peer);
+
+        let cover = alice.encrypt_cover(bob_peer).unwrap();
+        assert!(matches!(bob.decrypt_message(alice_peer, &cover), Err(NetError::CoverTraffic)));
+
+        // The ratchet moved on with it, so real messages still get through
+        let real = alice.encrypt_message(bob_peer, "alice", "real").unwrap();
+        assert_eq!(bob.decrypt_message(alice_peer, &real).unwrap().content, "real");
+    }
+
+    #[test]
+    fn test_group_cover_dropped_after_decryption() {
+        let (alice, _, bob, _) = pair();
+        let cover = alice.group_cover().unwrap();
+        assert!(matches!(bob.decrypt_group_message(&cover), Err(NetError::CoverTraffic)));
+    }
 }
@@ -0,0 +1,166 @@
+// Prekey bundles in the Kademlia DHT
+// Records live under /umbra/prekeys/<peer id> and carry a signature by the libp2p key that
+// peer id is derived from, so nobody can publish a bundle in someone else's name; prekey
+// messages left at a mailbox are signed the same way
+
+use crate::error::{NetError, Result};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::{kad, PeerId};
+use prost::Message;
+use umbra_crypto::identity::HybridVerifyingKey;
+use umbra_crypto::prekey::PrekeyBundle;
+use umbra_wire::handshake::{PrekeyBundle as WirePrekeyBundle, PrekeyRecord};
+use umbra_wire::message::{mailbox_message, MailboxMessage, PrekeyMessage};
+
+const RECORD_PREFIX: &[u8] = b"/umbra/prekeys/";
+
+/// DHT key of `peer`'s prekey record
+pub fn record_key(peer: &PeerId) -> kad::RecordKey {
+    let mut key = RECORD_PREFIX.to_vec();
+    key.extend_from_slice(&peer.to_bytes());
+    kad::RecordKey::new(&key)
+}
+
+/// Record value for our bundle, signed with our libp2p key
+pub fn encode_record(keypair: &Keypair, bundle: &PrekeyBundle) -> Result<Vec<u8>> {
+    let bundle = WirePrekeyBundle::from(bundle).encode_to_vec();
+    let signature = keypair
+        .sign(&bundle)
+        .map_err(|e| NetError::Crypto(format!("Failed to sign prekey record: {}", e)))?;
+
+    Ok(PrekeyRecord {
+        bundle,
+        public_key: keypair.public().encode_protobuf(),
+        signature,
+    }
+    .encode_to_vec())
+}
+
+/// Check that a record value was published by `peer` and return its verified bundle and identity
+pub fn decode_record(peer: &PeerId, value: &[u8]) -> Result<(PrekeyBundle, HybridVerifyingKey)> {
+    let record = PrekeyRecord::decode(value)
+        .map_err(|e| NetError::InvalidMessage(format!("Failed to decode prekey record: {}", e)))?;
+
+    let public_key = PublicKey::try_decode_protobuf(&record.public_key)
+        .map_err(|e| NetError::MalformedSignature(format!("Invalid prekey record key: {}", e)))?;
+    if PeerId::from_public_key(&public_key) != *peer {
+        return Err(NetError::SignatureInvalid(format!("Prekey record not published by {}", peer)));
+    }
+    if !public_key.verify(&record.bundle, &record.signature) {
+        return Err(NetError::SignatureInvalid(format!("Prekey record from {}", peer)));
+    }
+
+    let bundle = WirePrekeyBundle::decode(record.bundle.as_slice())
+        .map_err(|e| NetError::InvalidMessage(format!("Failed to decode prekey bundle: {}", e)))?;
+    let bundle = PrekeyBundle::try_from(&bundle).map_err(|e| NetError::InvalidMessage(e.to_string()))?;
+    let verify_key = bundle
+        .verify()
+        .map_err(|e| NetError::SignatureInvalid(format!("Prekey bundle from {}: {}", peer, e)))?;
+
+    Ok((bundle, verify_key))
+}
+
+/// Mailbox envelope for a prekey message, signed with our libp2p key: the recipient reads it
+/// from a mailbox rather than over a connection that already authenticates us
+pub fn mailbox_envelope(keypair: &Keypair, message: &PrekeyMessage) -> Result<MailboxMessage> {
+    let prekey = message.encode_to_vec();
+    let sender_signature = keypair
+        .sign(&prekey)
+        .map_err(|e| NetError::Crypto(format!("Failed to sign prekey message: {}", e)))?;
+
+    Ok(MailboxMessage {
+        message: Some(mailbox_message::Message::Prekey(prekey)),
+        sender_key: keypair.public().encode_protobuf(),
+        sender_signature,
+    })
+}
+
+/// Check a prekey message's mailbox envelope and return the peer that signed it, with the message
+pub fn open_mailbox_envelope(envelope: &MailboxMessage) -> Result<(PeerId, PrekeyMessage)> {
+    let Some(mailbox_message::Message::Prekey(prekey)) = &envelope.message else {
+        return Err(NetError::InvalidMessage("Not a prekey message".to_string()));
+    };
+    let public_key = PublicKey::try_decode_protobuf(&envelope.sender_key)
+        .map_err(|e| NetError::MalformedSignature(format!("Invalid prekey sender key: {}", e)))?;
+    if !public_key.verify(prekey, &envelope.sender_signature) {
+        return Err(NetError::SignatureInvalid("Prekey message sender".to_string()));
+    }
+
+    let message = PrekeyMessage::decode(prekey.as_slice())
+        .map_err(|e| NetError::InvalidMessage(format!("Failed to decode prekey message: {}", e)))?;
+    Ok((PeerId::from_public_key(&public_key), message))
+}
+
+/// Messages to a peer whose session comes from its prekey bundle
+pub(crate) enum PendingSession {
+    /// Looking up the bundle; plaintexts (username, content) wait for the session, then go
+    /// to `mailbox` if one was given and the peer isn't connected
+    Lookup {
+        query: kad::QueryId,
+        queued: Vec<(String, String)>,
+        mailbox: Option<PeerId>,
+    },
+    /// Session seeded; waiting for the peer to connect
+    Ready(PrekeyMessage),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use umbra_crypto::{IdentityKey, PrekeyStore};
+
+    fn bundle() -> PrekeyBundle {
+        PrekeyStore::new(IdentityKey::generate().unwrap()).unwrap().bundle().unwrap()
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = PeerId::from(keypair.public());
+        let bundle = bundle();
+
+        let value = encode_record(&keypair, &bundle).unwrap();
+        let (decoded, _) = decode_record(&peer, &value).unwrap();
+        assert_eq!(decoded, bundle);
+    }
+
+    #[test]
+    fn test_record_bound_to_peer() {
+        let keypair = Keypair::generate_ed25519();
+        let value = encode_record(&keypair, &bundle()).unwrap();
+
+        // A valid record republished under another peer's key
+        assert!(matches!(decode_record(&PeerId::random(), &value), Err(NetError::SignatureInvalid(_))));
+
+        // A bundle swapped into someone else's record
+        let mut record = PrekeyRecord::decode(value.as_slice()).unwrap();
+        record.bundle = WirePrekeyBundle::from(&bundle()).encode_to_vec();
+        let peer = PeerId::from(keypair.public());
+        assert!(decode_record(&peer, &record.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_mailbox_envelope_bound_to_sender() {
+        let keypair = Keypair::generate_ed25519();
+        let message = PrekeyMessage { init: None, messages: vec![b"first".to_vec()] };
+
+        let envelope = mailbox_envelope(&keypair, &message).unwrap();
+        let (sender, opened) = open_mailbox_envelope(&envelope).unwrap();
+        assert_eq!(sender, PeerId::from(keypair.public()));
+        assert_eq!(opened, message);
+
+        // Someone else's key on the same signature, or another message under it
+        let mut rekeyed = envelope.clone();
+        rekeyed.sender_key = Keypair::generate_ed25519().public().encode_protobuf();
+        assert!(matches!(open_mailbox_envelope(&rekeyed), Err(NetError::SignatureInvalid(_))));
+        let mut swapped = envelope.clone();
+        let other = PrekeyMessage { init: None, messages: vec![b"forged".to_vec()] };
+        swapped.message = Some(mailbox_message::Message::Prekey(other.encode_to_vec()));
+        assert!(matches!(open_mailbox_envelope(&swapped), Err(NetError::SignatureInvalid(_))));
+
+        // Unsigned
+        let mut unsigned = envelope;
+        unsigned.sender_key.clear();
+        assert!(open_mailbox_envelope(&unsigned).is_err());
+    }
+}
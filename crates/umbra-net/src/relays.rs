@@ -0,0 +1,124 @@
+// Relay descriptors in the Kademlia DHT
+// A node's onion key lives under /umbra/relays/<peer id>, signed by the libp2p key that peer id
+// comes from. Nodes that relay for others also announce themselves as providers of /umbra/relays;
+// the descriptor says whether they mix Sphinx packets as well. Sphinx packets are built to the
+// descriptor's mix key rather than the onion key; it rotates, so descriptors go stale.
+
+use crate::error::{NetError, Result};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::{kad, PeerId};
+use prost::Message;
+use std::time::{SystemTime, UNIX_EPOCH};
+use umbra_crypto::onion::OnionPublicKey;
+use umbra_wire::circuit::{RelayDescriptor, RelayRecord};
+
+const RECORD_PREFIX: &[u8] = b"/umbra/relays/";
+
+/// What a relay descriptor tells us about its publisher
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Descriptor {
+    pub onion_key: OnionPublicKey,
+    /// X25519 key Sphinx packets to this peer are built to, for the current epoch
+    pub mix_key: [u8; 32],
+    /// Forwards Sphinx mix packets
+    pub mix: bool,
+}
+
+/// Provider key relays announce themselves under
+pub fn provider_key() -> kad::RecordKey {
+    kad::RecordKey::new(b"/umbra/relays")
+}
+
+/// DHT key of `peer`'s descriptor
+pub fn record_key(peer: &PeerId) -> kad::RecordKey {
+    let mut key = RECORD_PREFIX.to_vec();
+    key.extend_from_slice(&peer.to_bytes());
+    kad::RecordKey::new(&key)
+}
+
+/// Record value for our descriptor, signed with our libp2p key
+pub fn encode_record(keypair: &Keypair, descriptor: &Descriptor) -> Result<Vec<u8>> {
+    let descriptor = RelayDescriptor {
+        onion_key: descriptor.onion_key.classical.to_vec(),
+        pq_onion_key: descriptor.onion_key.pq.clone(),
+        published_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        mix: descriptor.mix,
+        mix_key: descriptor.mix_key.to_vec(),
+    }
+    .encode_to_vec();
+    let signature = keypair
+        .sign(&descriptor)
+        .map_err(|e| NetError::Crypto(format!("Failed to sign relay descriptor: {}", e)))?;
+
+    Ok(RelayRecord {
+        descriptor,
+        public_key: keypair.public().encode_protobuf(),
+        signature,
+    }
+    .encode_to_vec())
+}
+
+/// Check that a record value was published by `peer` and return its descriptor
+pub fn decode_record(peer: &PeerId, value: &[u8]) -> Result<Descriptor> {
+    let record = RelayRecord::decode(value)
+        .map_err(|e| NetError::InvalidMessage(format!("Failed to decode relay record: {}", e)))?;
+
+    let public_key = PublicKey::try_decode_protobuf(&record.public_key)
+        .map_err(|e| NetError::MalformedSignature(format!("Invalid relay record key: {}", e)))?;
+    if PeerId::from_public_key(&public_key) != *peer {
+        return Err(NetError::SignatureInvalid(format!("Relay record not published by {}", peer)));
+    }
+    if !public_key.verify(&record.descriptor, &record.signature) {
+        return Err(NetError::SignatureInvalid(format!("Relay record from {}", peer)));
+    }
+
+    let descriptor = RelayDescriptor::decode(record.descriptor.as_slice())
+        .map_err(|e| NetError::InvalidMessage(format!("Failed to decode relay descriptor: {}", e)))?;
+    let classical = descriptor.onion_key.as_slice().try_into()
+        .map_err(|_| NetError::InvalidMessage("Invalid onion key length".to_string()))?;
+    let mix_key = descriptor.mix_key.as_slice().try_into()
+        .map_err(|_| NetError::InvalidMessage("Invalid mix key length".to_string()))?;
+
+    Ok(Descriptor {
+        onion_key: OnionPublicKey { classical, pq: descriptor.pq_onion_key },
+        mix_key,
+        mix: descriptor.mix,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use umbra_crypto::HybridKem;
+
+    fn descriptor(mix: bool) -> Descriptor {
+        Descriptor { onion_key: OnionPublicKey::of(&HybridKem::generate().unwrap()).unwrap(), mix_key: rand::random(), mix }
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = PeerId::from(keypair.public());
+        for mix in [false, true] {
+            let descriptor = descriptor(mix);
+            let value = encode_record(&keypair, &descriptor).unwrap();
+            assert_eq!(decode_record(&peer, &value).unwrap(), descriptor);
+        }
+    }
+
+    #[test]
+    fn test_record_bound_to_peer() {
+        let keypair = Keypair::generate_ed25519();
+        let value = encode_record(&keypair, &descriptor(false)).unwrap();
+
+        // A valid record republished under another peer's key
+        assert!(matches!(decode_record(&PeerId::random(), &value), Err(NetError::SignatureInvalid(_))));
+
+        // Someone else's onion key swapped into the record
+        let mut record = RelayRecord::decode(value.as_slice()).unwrap();
+        let other = encode_record(&Keypair::generate_ed25519(), &descriptor(false)).unwrap();
+        record.descriptor = RelayRecord::decode(other.as_slice()).unwrap().descriptor;
+        let peer = PeerId::from(keypair.public());
+        assert!(matches!(decode_record(&peer, &record.encode_to_vec()), Err(NetError::SignatureInvalid(_))));
+    }
+}
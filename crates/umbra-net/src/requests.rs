@@ -0,0 +1,139 @@
+// Shared NetworkBehaviour plumbing for the request_response protocols
+// Each protocol keeps its own state and reacts to request events; RequestBehaviour hands the
+// connection lifecycle to its request_response::Behaviour and drives its poll loop, so none of
+// them carries its own delegation.
+
+use crate::codec::LengthPrefixedCodec;
+use libp2p::core::Endpoint;
+use libp2p::request_response;
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::ops::{Deref, DerefMut};
+use std::task::{Context, Poll};
+
+/// The request_response behaviour every protocol runs on
+pub type Requests = request_response::Behaviour<LengthPrefixedCodec>;
+
+/// What a protocol's `Requests` hands back
+pub type RequestEvent = request_response::Event<Vec<u8>, Vec<u8>>;
+
+/// A protocol layered on `Requests`
+pub trait RequestProtocol: Send + 'static {
+    /// Event handed up to the swarm
+    type Event: Send + 'static;
+
+    fn requests(&mut self) -> &mut Requests;
+
+    /// Handle an event from `Requests`
+    fn on_event(&mut self, event: RequestEvent);
+
+    /// Next event to hand up, if any
+    fn pop_event(&mut self) -> Option<Self::Event>;
+
+    /// Drive timers and queues; runs before every pass of the poll loop
+    fn poll_tasks(&mut self, _cx: &mut Context) {}
+
+    /// See a swarm event before `Requests` does
+    fn on_swarm_event(&mut self, _event: &FromSwarm) {}
+}
+
+/// A protocol wired into the swarm; derefs to the protocol itself
+pub struct RequestBehaviour<P>(P);
+
+impl<P> RequestBehaviour<P> {
+    pub fn new(protocol: P) -> Self {
+        Self(protocol)
+    }
+}
+
+impl<P> Deref for RequestBehaviour<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.0
+    }
+}
+
+impl<P> DerefMut for RequestBehaviour<P> {
+    fn deref_mut(&mut self) -> &mut P {
+        &mut self.0
+    }
+}
+
+impl<P: RequestProtocol> NetworkBehaviour for RequestBehaviour<P> {
+    type ConnectionHandler = <Requests as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = P::Event;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.0.requests().handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.0.requests().handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.0.requests().handle_pending_outbound_connection(connection_id, maybe_peer, addresses, effective_role)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.0.requests().handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.0.on_swarm_event(&event);
+        self.0.requests().on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.0.requests().on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        loop {
+            self.0.poll_tasks(cx);
+            if let Some(event) = self.0.pop_event() {
+                return Poll::Ready(ToSwarm::GenerateEvent(event));
+            }
+
+            match self.0.requests().poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => self.0.on_event(event),
+                Poll::Ready(other) => {
+                    return Poll::Ready(other.map_out(|_| unreachable!("request events are handled above")));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
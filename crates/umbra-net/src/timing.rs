@@ -3,6 +3,7 @@ use tokio::time::{sleep, Instant};
 use rand::Rng;
 
 /// Timing jitter for message sends
+#[derive(Clone, Debug)]
 pub struct TimingJitter {
     min_delay: Duration,
     max_delay: Duration,
@@ -37,7 +38,8 @@ impl TimingJitter {
     }
 }
 
-/// Delayed ACK scheduler
+/// Delayed ACK scheduler: each ack waits a random delay in `0..=max_delay`
+#[derive(Debug)]
 pub struct DelayedAck {
     max_delay: Duration,
     pending: Vec<(Instant, Vec<u8>)>,
@@ -52,7 +54,8 @@ impl DelayedAck {
     }
     
     pub fn schedule_ack(&mut self, message_id: Vec<u8>) {
-        let send_time = Instant::now() + self.max_delay;
+        let delay_ms = rand::thread_rng().gen_range(0..=self.max_delay.as_millis()) as u64;
+        let send_time = Instant::now() + Duration::from_millis(delay_ms);
         self.pending.push((send_time, message_id));
     }
     
@@ -71,6 +74,20 @@ impl DelayedAck {
     pub fn pending_count(&self) -> usize {
         self.pending.len()
     }
+
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// When the earliest scheduled ack is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.iter().map(|(time, _)| *time).min()
+    }
+
+    /// Drop every scheduled ack and return them, ready or not
+    pub fn take_all(&mut self) -> Vec<Vec<u8>> {
+        self.pending.drain(..).map(|(_, id)| id).collect()
+    }
 }
 
 #[cfg(test)]
@@ -95,11 +112,29 @@ mod tests {
         
         ack.schedule_ack(vec![1, 2, 3]);
         assert_eq!(ack.pending_count(), 1);
+        assert!(ack.next_due().unwrap() <= Instant::now() + Duration::from_millis(10));
         
+        // Nothing is handed out before it's due
+        ack.pending[0].0 = Instant::now() + Duration::from_secs(1);
         let ready = ack.get_ready_acks();
         assert_eq!(ready.len(), 0);
     }
     
+    #[test]
+    fn test_delayed_ack_delays_are_random() {
+        let mut ack = DelayedAck::new(1000);
+        let start = Instant::now();
+
+        for id in 0..32u8 {
+            ack.schedule_ack(vec![id]);
+        }
+
+        let due: Vec<Instant> = ack.pending.iter().map(|(time, _)| *time).collect();
+        assert!(due.iter().all(|time| *time <= start + Duration::from_millis(1000) + Duration::from_millis(50)));
+        assert!(due.iter().any(|time| *time != due[0]), "every ack got the same delay");
+        assert_eq!(ack.next_due(), due.iter().min().copied());
+    }
+
     #[tokio::test]
     async fn test_delayed_ack_timeout() {
         let mut ack = DelayedAck::new(50);
@@ -111,4 +146,15 @@ mod tests {
         let ready = ack.get_ready_acks();
         assert_eq!(ready.len(), 1);
     }
+
+    #[test]
+    fn test_delayed_ack_take_all() {
+        let mut ack = DelayedAck::new(1000);
+
+        ack.schedule_ack(vec![1]);
+        ack.schedule_ack(vec![2]);
+
+        assert_eq!(ack.take_all(), vec![vec![1], vec![2]]);
+        assert_eq!(ack.pending_count(), 0);
+    }
 }
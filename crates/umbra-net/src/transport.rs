@@ -1,3 +1,4 @@
+use libp2p::swarm::behaviour::toggle::Toggle;
 use libp2p::swarm::NetworkBehaviour;
 use libp2p::{
     gossipsub, identify, kad, ping,
@@ -5,20 +6,41 @@ use libp2p::{
     Multiaddr, PeerId, Swarm,
 };
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
-use std::time::Duration;
+use tokio::time::Instant;
 use tracing::{debug, info, warn};
+use crate::circuit::{CircuitBehaviour, CircuitEvent, CIRCUIT_HOPS, ENTRY_GUARDS, MIX_HOPS, MIX_KEY_LIFETIME};
+use crate::config::NodeConfig;
+use crate::keystore::{KeyFile, NodeKeys};
+use crate::cover::{CoverConfig, CoverTarget, CoverTraffic, TrafficStats};
+use crate::direct::{DeliveryStatus, DirectBehaviour, DirectEvent};
+use crate::file::{FileBehaviour, FileEvent, FileId, FileUpdate, CHUNK_SIZE};
+use crate::group::{GroupUpdate, Groups};
 use crate::handshake::{HandshakeBehaviour, HandshakeEvent};
+use crate::mailbox::{MailboxBehaviour, MailboxEvent, MailboxUpdate};
+use crate::mailbox_store::{MailboxConfig, MailboxStore};
+use crate::prekeys::PendingSession;
+use crate::requests::RequestBehaviour;
+use crate::timing::TimingJitter;
+use umbra_crypto::onion::OnionPublicKey;
+use umbra_crypto::prekey::{PrekeyInit, PrekeyStore, DEFAULT_ONE_TIME_PREKEYS};
+use umbra_wire::framing::{Frame, MAX_FRAME_PAYLOAD};
+use umbra_wire::message::PrekeyMessage;
 
-/// Combined network behaviour for UMBRA P2P
+/// Combined network behaviour for UMBRA P2P (the toggled ones per `NodeConfig::behaviours`)
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "UmbraEvent")]
 pub struct UmbraBehaviour {
-    ping: ping::Behaviour,
-    identify: identify::Behaviour,
+    ping: Toggle<ping::Behaviour>,
+    identify: Toggle<identify::Behaviour>,
     kad: kad::Behaviour<kad::store::MemoryStore>,
     gossipsub: gossipsub::Behaviour,
-    handshake: HandshakeBehaviour,
+    handshake: RequestBehaviour<HandshakeBehaviour>,
+    direct: RequestBehaviour<DirectBehaviour>,
+    mailbox: Toggle<RequestBehaviour<MailboxBehaviour>>,
+    file: Toggle<RequestBehaviour<FileBehaviour>>,
+    circuit: RequestBehaviour<CircuitBehaviour>,
 }
 
 #[derive(Debug)]
@@ -28,6 +50,10 @@ pub enum UmbraEvent {
     Kad(kad::Event),
     Gossipsub(gossipsub::Event),
     Handshake(HandshakeEvent),
+    Direct(DirectEvent),
+    Mailbox(MailboxEvent),
+    File(FileEvent),
+    Circuit(CircuitEvent),
 }
 
 impl From<ping::Event> for UmbraEvent {
@@ -60,6 +86,30 @@ impl From<HandshakeEvent> for UmbraEvent {
     }
 }
 
+impl From<DirectEvent> for UmbraEvent {
+    fn from(event: DirectEvent) -> Self {
+        UmbraEvent::Direct(event)
+    }
+}
+
+impl From<MailboxEvent> for UmbraEvent {
+    fn from(event: MailboxEvent) -> Self {
+        UmbraEvent::Mailbox(event)
+    }
+}
+
+impl From<FileEvent> for UmbraEvent {
+    fn from(event: FileEvent) -> Self {
+        UmbraEvent::File(event)
+    }
+}
+
+impl From<CircuitEvent> for UmbraEvent {
+    fn from(event: CircuitEvent) -> Self {
+        UmbraEvent::Circuit(event)
+    }
+}
+
 pub struct P2PNode {
     swarm: Swarm<UmbraBehaviour>,
     local_peer_id: PeerId,
@@ -67,23 +117,110 @@ pub struct P2PNode {
     message_tx: tokio::sync::mpsc::UnboundedSender<(PeerId, Vec<u8>)>,
     connection_rx: Option<tokio::sync::mpsc::UnboundedReceiver<PeerId>>,
     connection_tx: tokio::sync::mpsc::UnboundedSender<PeerId>,
+    direct_rx: Option<tokio::sync::mpsc::UnboundedReceiver<(PeerId, crate::message::DecryptedMessage)>>,
+    direct_tx: tokio::sync::mpsc::UnboundedSender<(PeerId, crate::message::DecryptedMessage)>,
+    delivery_rx: Option<tokio::sync::mpsc::UnboundedReceiver<(PeerId, u64, DeliveryStatus)>>,
+    delivery_tx: tokio::sync::mpsc::UnboundedSender<(PeerId, u64, DeliveryStatus)>,
+    mailbox_rx: Option<tokio::sync::mpsc::UnboundedReceiver<MailboxUpdate>>,
+    mailbox_tx: tokio::sync::mpsc::UnboundedSender<MailboxUpdate>,
+    /// Messages delivered so far by each mailbox we're draining
+    mailbox_drains: HashMap<PeerId, usize>,
+    file_rx: Option<tokio::sync::mpsc::UnboundedReceiver<FileUpdate>>,
+    file_tx: tokio::sync::mpsc::UnboundedSender<FileUpdate>,
+    group_rx: Option<tokio::sync::mpsc::UnboundedReceiver<GroupUpdate>>,
+    group_tx: tokio::sync::mpsc::UnboundedSender<GroupUpdate>,
+    /// MLS groups behind our topics, and the invites in flight
+    groups: Groups,
+    /// Direct message ids of group control messages (acks aren't forwarded to the app)
+    control_sends: HashSet<u64>,
     message_exchange: crate::message::MessageExchange,
+    /// Signs our prekey records (the key our PeerId comes from)
+    local_key: libp2p::identity::Keypair,
+    prekeys: PrekeyStore,
+    /// Where prekey changes are saved, when the keys came from a key file
+    key_file: Option<KeyFile>,
+    /// Sessions being set up from a peer's prekey bundle
+    prekey_sessions: HashMap<PeerId, PendingSession>,
+    /// Prekey messages awaiting their ack
+    prekey_sends: HashMap<u64, (PeerId, PrekeyMessage)>,
+    /// Peers whose session came from a prekey exchange on the current connection; a handshake
+    /// finishing alongside it must not re-seed the ratchet under the other side's feet
+    prekey_peers: HashSet<PeerId>,
+    /// Route `send_direct` over an onion circuit instead of straight to the peer
+    circuit_routing: bool,
+    /// Onion keys from relay descriptors we've looked up
+    onion_keys: HashMap<PeerId, OnionPublicKey>,
+    /// Mix keys from the same descriptors and when we fetched them; they rotate, so old ones are
+    /// looked up again
+    mix_keys: HashMap<PeerId, ([u8; 32], Instant)>,
+    /// Whether we published our descriptor, so a mix key rotation republishes it
+    descriptor_published: bool,
+    /// Peers announcing themselves as relays
+    relays: HashSet<PeerId>,
+    /// Relays every circuit enters through, kept while they keep relaying, so a hostile relay
+    /// only sees us as its direct client if it was picked as a guard
+    entry_guards: Vec<PeerId>,
+    /// Descriptor lookups in flight
+    onion_lookups: HashMap<kad::QueryId, PeerId>,
+    relay_search: Option<kad::QueryId>,
+    /// Whether we've searched for relays since the last circuit was built
+    relays_searched: bool,
+    /// The circuit direct messages go through, and whether it's built yet
+    dm_circuit: Option<(u64, bool)>,
+    /// Direct messages waiting for the circuit or the recipient's onion key: (message id, peer, encrypted)
+    circuit_queue: Vec<(u64, PeerId, Vec<u8>)>,
+    /// Messages handed to the circuit, awaiting the exit's result: message id -> (peer, circuit)
+    circuit_sends: HashMap<u64, (PeerId, u64)>,
+    /// Send `send_direct` messages through mixes as Sphinx packets (takes precedence over circuits)
+    mix_routing: bool,
+    /// Relays whose descriptor says they mix
+    mixes: HashSet<PeerId>,
+    /// Direct messages waiting for mixes or the recipient's onion key: (message id, peer, encrypted)
+    mix_queue: Vec<(u64, PeerId, Vec<u8>)>,
+    cover: Option<CoverTraffic>,
+    /// Direct message ids of cover messages (their delivery results aren't forwarded to the app)
+    cover_sends: HashSet<u64>,
+    traffic: TrafficStats,
+    /// Random hold-back for publishes, direct messages and mailbox requests, so they don't
+    /// go out the instant they're made
+    jitter: Option<TimingJitter>,
+    /// Sends waiting out their jitter
+    send_queue: Vec<(Instant, Outgoing)>,
+}
+
+/// A send held back by the timing jitter
+enum Outgoing {
+    Publish { topic: String, data: Vec<u8> },
+    Direct { peer: PeerId, message_id: u64, data: Vec<u8> },
+    Deposit { mailbox: PeerId, tag: [u8; 32], message: Vec<u8> },
+    Fetch { mailbox: PeerId, fetch: umbra_wire::mailbox::Fetch },
 }
 
 impl P2PNode {
     pub async fn new() -> crate::error::Result<Self> {
-        Self::new_with_port(0).await
+        Self::from_config(NodeConfig::default()).await
     }
 
     pub async fn new_with_port(port: u16) -> crate::error::Result<Self> {
-        let local_key = libp2p::identity::Keypair::generate_ed25519();
+        Self::from_config(NodeConfig::default().with_port(port)).await
+    }
+
+    pub async fn from_config(config: NodeConfig) -> crate::error::Result<Self> {
+        config.validate()?;
+        let (NodeKeys { keypair: local_key, identity, onion_key, prekeys }, key_file) = NodeKeys::from_source(&config.identity)?;
         let local_peer_id = PeerId::from(local_key.public());
         
         info!("Local peer id: {}", local_peer_id);
         
-        // Configure gossipsub with message deduplication
+        // Configure gossipsub with message deduplication; payloads are padded frames,
+        // so leave room for the largest bucket plus gossipsub's own envelope
         let gossipsub_config = gossipsub::ConfigBuilder::default()
-            .heartbeat_interval(Duration::from_secs(1))
+            .heartbeat_interval(config.gossipsub.heartbeat_interval)
+            .mesh_n(config.gossipsub.mesh_n)
+            .mesh_n_low(config.gossipsub.mesh_n_low)
+            .mesh_n_high(config.gossipsub.mesh_n_high)
+            .history_length(config.gossipsub.history_length)
+            .max_transmit_size(MAX_FRAME_PAYLOAD + 4 + 4 * 1024)
             .validation_mode(gossipsub::ValidationMode::Strict)
             .message_id_fn(|message| {
                 let mut hasher = DefaultHasher::new();
@@ -99,60 +236,151 @@ impl P2PNode {
         )
         .map_err(|e| crate::error::NetError::Transport(format!("Gossipsub init: {}", e)))?;
         
+        // One identity for messages, the handshake and prekeys
+        let message_exchange = crate::message::MessageExchange::with_identity(local_peer_id, identity);
+
+        let enabled = &config.behaviours;
         let behaviour = UmbraBehaviour {
-            ping: ping::Behaviour::new(ping::Config::new()),
-            identify: identify::Behaviour::new(identify::Config::new(
-                "/umbra/0.1.0".to_string(),
-                local_key.public(),
-            )),
+            ping: enabled.ping
+                .then(|| ping::Behaviour::new(ping::Config::new().with_interval(config.ping_interval)))
+                .into(),
+            identify: enabled.identify
+                .then(|| identify::Behaviour::new(identify::Config::new(
+                    "/umbra/0.1.0".to_string(),
+                    local_key.public(),
+                )))
+                .into(),
             kad: kad::Behaviour::new(
                 local_peer_id,
                 kad::store::MemoryStore::new(local_peer_id),
             ),
             gossipsub,
-            handshake: {
-                // Generate identity key for handshake (hybrid Ed25519 + Dilithium3)
-                let identity = umbra_crypto::identity::IdentityKey::generate()
-                    .map_err(|e| crate::error::NetError::Crypto(format!("Identity generation failed: {}", e)))?;
-                HandshakeBehaviour::new(identity)
-            },
+            // Handshake signs with the same hybrid identity as messages,
+            // so the keys peers learn in the handshake verify our messages
+            handshake: RequestBehaviour::new(HandshakeBehaviour::new(
+                local_peer_id,
+                message_exchange.session_manager().identity().clone(),
+            )),
+            direct: RequestBehaviour::new(DirectBehaviour::new()),
+            mailbox: enabled.mailbox.then(|| RequestBehaviour::new(MailboxBehaviour::new(local_peer_id))).into(),
+            file: enabled.file.then(|| RequestBehaviour::new(FileBehaviour::new())).into(),
+            circuit: RequestBehaviour::new(CircuitBehaviour::with_onion_key(onion_key)?),
+        };
+        
+        // Create swarm with QUIC transport, and TCP alongside if configured (libp2p 0.53 API)
+        let idle_timeout = config.idle_connection_timeout;
+        let builder = libp2p::SwarmBuilder::with_existing_identity(local_key.clone()).with_tokio();
+        let mut swarm = if config.tcp {
+            builder
+                .with_tcp(libp2p::tcp::Config::default(), libp2p::noise::Config::new, libp2p::yamux::Config::default)
+                .map_err(|e| crate::error::NetError::Transport(format!("TCP transport: {:?}", e)))?
+                .with_quic()
+                .with_behaviour(|_| behaviour)
+                .map_err(|e| crate::error::NetError::Transport(format!("Swarm build failed: {:?}", e)))?
+                .with_swarm_config(|c| c.with_idle_connection_timeout(idle_timeout))
+                .build()
+        } else {
+            builder
+                .with_quic()
+                .with_behaviour(|_| behaviour)
+                .map_err(|e| crate::error::NetError::Transport(format!("Swarm build failed: {:?}", e)))?
+                .with_swarm_config(|c| c.with_idle_connection_timeout(idle_timeout))
+                .build()
         };
         
-        // Create swarm with QUIC transport (libp2p 0.53 API)
-        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(local_key)
-            .with_tokio()
-            .with_quic()
-            .with_behaviour(|_| behaviour)
-            .map_err(|e| crate::error::NetError::Transport(format!("Swarm build failed: {:?}", e)))?
-            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-            .build();
+        // Every node stores DHT records (prekey bundles) for others
+        swarm.behaviour_mut().kad.set_mode(Some(kad::Mode::Server));
         
-        // Listen on QUIC with specified port
-        let listen_addr = format!("/ip4/0.0.0.0/udp/{}/quic-v1", port);
-        swarm.listen_on(listen_addr.parse()
-            .map_err(|e| crate::error::NetError::Transport(format!("Invalid listen address: {:?}", e)))?)
-            .map_err(|e| crate::error::NetError::Transport(format!("Listen failed: {:?}", e)))?;
+        for addr in &config.listen_addrs {
+            swarm.listen_on(addr.clone())
+                .map_err(|e| crate::error::NetError::Transport(format!("Listen on {} failed: {:?}", addr, e)))?;
+        }
         
         let (message_tx, message_rx) = tokio::sync::mpsc::unbounded_channel();
         let (connection_tx, connection_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (direct_tx, direct_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (delivery_tx, delivery_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (mailbox_tx, mailbox_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (file_tx, file_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (group_tx, group_rx) = tokio::sync::mpsc::unbounded_channel();
+        let groups = Groups::new(local_peer_id, message_exchange.session_manager().identity().clone());
         
-        let message_exchange = crate::message::MessageExchange::new(local_peer_id)
-            .map_err(|e| crate::error::NetError::Transport(format!("MessageExchange init: {}", e)))?;
-        
-        Ok(Self {
+        let mut node = Self {
             swarm,
             local_peer_id,
             message_rx: Some(message_rx),
             message_tx,
             connection_rx: Some(connection_rx),
             connection_tx,
+            direct_rx: Some(direct_rx),
+            direct_tx,
+            delivery_rx: Some(delivery_rx),
+            delivery_tx,
+            mailbox_rx: Some(mailbox_rx),
+            mailbox_tx,
+            mailbox_drains: HashMap::new(),
+            file_rx: Some(file_rx),
+            file_tx,
+            group_rx: Some(group_rx),
+            group_tx,
+            groups,
+            control_sends: HashSet::new(),
             message_exchange,
-        })
+            local_key,
+            prekeys,
+            key_file,
+            prekey_sessions: HashMap::new(),
+            prekey_sends: HashMap::new(),
+            prekey_peers: HashSet::new(),
+            circuit_routing: false,
+            onion_keys: HashMap::new(),
+            mix_keys: HashMap::new(),
+            descriptor_published: false,
+            relays: HashSet::new(),
+            entry_guards: Vec::new(),
+            onion_lookups: HashMap::new(),
+            relay_search: None,
+            relays_searched: false,
+            dm_circuit: None,
+            circuit_queue: Vec::new(),
+            circuit_sends: HashMap::new(),
+            mix_routing: false,
+            mixes: HashSet::new(),
+            mix_queue: Vec::new(),
+            cover: None,
+            cover_sends: HashSet::new(),
+            traffic: TrafficStats::default(),
+            jitter: Some(TimingJitter::default_jitter()),
+            send_queue: Vec::new(),
+        };
+        
+        if !config.bootstrap_peers.is_empty() {
+            for addr in config.bootstrap_peers {
+                node.dial(addr)?;
+            }
+            node.bootstrap()?;
+        }
+        if config.behaviours.mix {
+            node.enable_mix()?;
+        } else if config.behaviours.relay {
+            node.enable_relay()?;
+        }
+        Ok(node)
     }
     
     pub fn local_peer_id(&self) -> &PeerId {
         &self.local_peer_id
     }
+
+    /// Public half of the identity our handshakes and messages are signed with
+    pub fn identity_key(&self) -> umbra_crypto::identity::HybridVerifyingKey {
+        self.message_exchange.session_manager().hybrid_public_key()
+    }
+    
+    /// The onion key circuits and Sphinx packets are built to
+    pub fn onion_key(&self) -> &OnionPublicKey {
+        self.swarm.behaviour().circuit.onion_key()
+    }
     
     pub fn listening_addresses(&self) -> Vec<Multiaddr> {
         self.swarm.listeners().cloned().collect()
@@ -186,14 +414,185 @@ impl P2PNode {
         Ok(())
     }
     
-    /// Publish message to gossipsub topic
+    /// Publish message to gossipsub topic, once the send jitter (if any) has passed
     pub fn publish(&mut self, topic: &str, data: Vec<u8>) -> crate::error::Result<()> {
+        // Padded into a fixed-size frame, so the length only shows its bucket
+        let data = Frame::pad(data)
+            .map_err(|e| crate::error::NetError::InvalidMessage(format!("Cannot frame message: {}", e)))?;
+        if self.jitter.is_none() {
+            return self.publish_now(topic, data);
+        }
+        // Fail up front like gossipsub would, rather than silently after the jitter
+        if self.topic_peers(topic).is_empty() {
+            return Err(crate::error::NetError::Transport("Publish failed: InsufficientPeers".to_string()));
+        }
+        self.send_jittered(Outgoing::Publish { topic: topic.to_string(), data });
+        Ok(())
+    }
+
+    fn publish_now(&mut self, topic: &str, data: Vec<u8>) -> crate::error::Result<()> {
         let topic = gossipsub::IdentTopic::new(topic);
         self.swarm.behaviour_mut().gossipsub.publish(topic, data)
             .map_err(|e| crate::error::NetError::Transport(format!("Publish failed: {}", e)))?;
         Ok(())
     }
 
+    /// Subscribe to `topic` and start its MLS group, with us as the only member and admin until
+    /// we invite peers (leave it before accepting an invite to someone else's)
+    pub fn join_group(&mut self, topic: &str) -> crate::error::Result<()> {
+        self.groups.create(topic)?;
+        self.subscribe(topic)
+    }
+    
+    /// Unsubscribe and forget the group (its admins still have to remove us to rekey)
+    pub fn leave_group(&mut self, topic: &str) -> crate::error::Result<()> {
+        let ident = gossipsub::IdentTopic::new(topic);
+        self.swarm.behaviour_mut().gossipsub.unsubscribe(&ident)
+            .map_err(|e| crate::error::NetError::Transport(format!("Unsubscribe failed: {}", e)))?;
+        self.groups.leave(topic);
+        Ok(())
+    }
+    
+    /// Encrypt once under the MLS group's current epoch and publish; every member can read it
+    pub fn send_group_message(&mut self, topic: &str, username: &str, content: &str) -> crate::error::Result<()> {
+        let plaintext = self.message_exchange.encrypt_group_message(username, content)?;
+        let data = self.groups.encrypt(topic, &plaintext)?;
+        self.traffic.real_sent += 1;
+        self.traffic.real_sent_bytes += data.len() as u64;
+        self.publish(topic, data)
+    }
+    
+    /// Decrypt a message received on a group topic (`NetError::CoverTraffic` for cover
+    /// messages, which have nothing to show)
+    pub fn decrypt_group_message(&mut self, data: &[u8]) -> crate::error::Result<crate::message::DecryptedMessage> {
+        let result = self.groups.decrypt(data)
+            .and_then(|(_, _, plaintext)| self.message_exchange.decrypt_group_message(&plaintext));
+        self.count_cover(result, data.len())
+    }
+    
+    /// Peers gossipsub knows to be subscribed to `topic`
+    fn topic_peers(&self, topic: &str) -> Vec<PeerId> {
+        self.swarm.behaviour().gossipsub.all_peers()
+            .filter(|(_, topics)| topics.iter().any(|t| t.as_str() == topic))
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
+    /// Our MLS group on `topic`, if we're in one
+    pub fn group(&self, topic: &str) -> Option<&umbra_mls::Group> {
+        self.groups.get(topic)
+    }
+
+    /// Invite `peer` into our group on `topic` (admins only); if they accept, their join
+    /// request adds them
+    pub fn invite_to_group(&mut self, topic: &str, peer: PeerId) -> crate::error::Result<()> {
+        let control = self.groups.invite(topic, peer)?;
+        self.send_group_control(peer, control)
+    }
+
+    /// Answer the invite we got for `topic`; `GroupUpdate::Joined` follows once the inviter
+    /// adds us
+    pub fn accept_group_invite(&mut self, topic: &str) -> crate::error::Result<()> {
+        let (inviter, control) = self.groups.accept_invite(topic)?;
+        self.send_group_control(inviter, control)
+    }
+
+    /// Remove `peer` from our group on `topic` (admins only)
+    pub fn remove_from_group(&mut self, topic: &str, peer: PeerId) -> crate::error::Result<()> {
+        let leaf_index = self.groups.leaf_of(topic, &peer)?;
+        let change = crate::group::remove_member(self.groups.get_mut(topic)?, leaf_index)?;
+        self.commit_group_change(topic, change);
+        Ok(())
+    }
+
+    /// Promote or demote `peer` in our group on `topic` (admins only)
+    pub fn set_group_role(&mut self, topic: &str, peer: PeerId, role: umbra_wire::group::Role) -> crate::error::Result<()> {
+        let leaf_index = self.groups.leaf_of(topic, &peer)?;
+        let change = crate::group::set_role(self.groups.get_mut(topic)?, leaf_index, role)?;
+        self.commit_group_change(topic, change);
+        Ok(())
+    }
+
+    /// Rename our group on `topic` or change its description (admins only)
+    pub fn set_group_metadata(&mut self, topic: &str, metadata: umbra_wire::group::GroupMetadata) -> crate::error::Result<()> {
+        let change = crate::group::set_metadata(self.groups.get_mut(topic)?, metadata)?;
+        self.commit_group_change(topic, change);
+        Ok(())
+    }
+
+    /// DM a group control message to `peer` over our handshake session
+    fn send_group_control(&mut self, peer: PeerId, control: umbra_wire::group::GroupControl) -> crate::error::Result<()> {
+        if self.get_session_key(&peer).is_none() {
+            return Err(crate::error::NetError::PeerNotFound(format!("No session with {}", peer)));
+        }
+        let data = self.message_exchange.group_control_message(peer, control.encode_to_vec())?;
+        let message_id = self.swarm.behaviour_mut().direct.send(peer, data);
+        self.control_sends.insert(message_id);
+        Ok(())
+    }
+
+    /// Tell the rest of the group on `topic` about a commit we made (already applied on our side)
+    fn commit_group_change(&mut self, topic: &str, change: umbra_wire::group::GroupChange) {
+        if let Some(summary) = change.change.clone() {
+            let by = self.local_peer_id;
+            let _ = self.group_tx.send(GroupUpdate::Changed { topic: topic.to_string(), by, change: summary });
+        }
+        // Alone in the group (or nobody online), there's no one to tell
+        if self.topic_peers(topic).is_empty() {
+            return;
+        }
+        let control = umbra_wire::group::GroupControl {
+            message: Some(umbra_wire::group::group_control::Message::Change(change)),
+        };
+        if let Err(e) = self.publish(topic, control.encode_to_vec()) {
+            warn!("Failed to publish group change for {}: {}", topic, e);
+        }
+    }
+
+    /// Handle a control message DMed to us by `peer`
+    fn on_group_control(&mut self, peer: PeerId, data: &[u8]) -> crate::error::Result<()> {
+        use umbra_wire::group::{group_control, GroupControl};
+        let control = GroupControl::decode_from_bytes(data)
+            .map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        match control.message {
+            Some(group_control::Message::Invite(invite)) => {
+                let metadata = invite.metadata.clone().unwrap_or_default();
+                let topic = self.groups.on_invite(peer, invite)?;
+                info!("✉️ {} invited us to {}", peer, topic);
+                let _ = self.group_tx.send(GroupUpdate::Invited { topic, inviter: peer, metadata });
+            }
+            Some(group_control::Message::JoinRequest(request)) => {
+                let peer_key = self.message_exchange.session_manager().get_peer_hybrid_key(&peer).cloned()
+                    .ok_or_else(|| crate::error::NetError::UnknownPeerKey(peer.to_string()))?;
+                let (topic, change) = self.groups.on_join_request(peer, &peer_key, request)?;
+                // The new member isn't on the topic yet: their Welcome goes to them directly
+                self.send_group_control(peer, GroupControl {
+                    message: Some(group_control::Message::Change(change.clone())),
+                })?;
+                self.commit_group_change(&topic, change);
+            }
+            Some(group_control::Message::Change(change)) => self.on_group_change(&change)?,
+            None => return Err(crate::error::NetError::InvalidMessage("Empty group control".to_string())),
+        }
+        Ok(())
+    }
+
+    /// Apply a commit to one of our groups (or join through its Welcome)
+    fn on_group_change(&mut self, change: &umbra_wire::group::GroupChange) -> crate::error::Result<()> {
+        let Some(update) = self.groups.on_change(change)? else {
+            return Ok(());
+        };
+        match &update {
+            GroupUpdate::Joined { topic } => self.subscribe(topic)?,
+            GroupUpdate::Removed { topic } => {
+                let _ = self.swarm.behaviour_mut().gossipsub.unsubscribe(&gossipsub::IdentTopic::new(topic));
+            }
+            _ => {}
+        }
+        let _ = self.group_tx.send(update);
+        Ok(())
+    }
+
     /// Send encrypted message to a topic
     pub fn send_encrypted_message(
         &mut self,
@@ -211,9 +610,1019 @@ impl P2PNode {
         Ok(())
     }
 
-    /// Decrypt received message
-    pub fn decrypt_message(&mut self, peer: PeerId, data: &[u8]) -> crate::error::Result<(String, String, Option<[u8; 32]>)> {
-        self.message_exchange.decrypt_message(peer, data)
+    /// Send an encrypted message straight to `peer` over /umbra/dm/1 (no topic involved),
+    /// or through a circuit or mixes if circuit or mix routing is on
+    ///
+    /// Returns the message id; its `DeliveryStatus` arrives on the delivery receiver. Mixed
+    /// messages only get one if they fail before leaving the first mix.
+    pub fn send_direct(
+        &mut self,
+        peer: PeerId,
+        username: &str,
+        content: &str,
+    ) -> crate::error::Result<u64> {
+        if !self.has_session(&peer) {
+            return Err(crate::error::NetError::PeerNotFound(format!("No session with {}", peer)));
+        }
+        let encrypted_data = self.message_exchange.encrypt_message(peer, username, content)?;
+        self.traffic.real_sent += 1;
+        self.traffic.real_sent_bytes += encrypted_data.len() as u64;
+        Ok(self.dispatch_direct(peer, encrypted_data))
+    }
+
+    /// Send an encrypted direct message, once its jitter has passed, the way the routing
+    /// settings say
+    fn dispatch_direct(&mut self, peer: PeerId, encrypted_data: Vec<u8>) -> u64 {
+        let message_id = rand::random();
+        self.send_jittered(Outgoing::Direct { peer, message_id, data: encrypted_data });
+        message_id
+    }
+
+    fn route_direct(&mut self, peer: PeerId, message_id: u64, encrypted_data: Vec<u8>) {
+        if self.mix_routing {
+            self.mix_queue.push((message_id, peer, encrypted_data));
+            self.pump_mixes();
+        } else if self.circuit_routing {
+            self.circuit_queue.push((message_id, peer, encrypted_data));
+            self.pump_circuits();
+        } else {
+            self.swarm.behaviour_mut().direct.send_as(peer, message_id, encrypted_data);
+        }
+    }
+
+    /// Pass a delivery result on to the app, unless it's for a cover message
+    fn report_delivery(&mut self, peer: PeerId, message_id: u64, status: DeliveryStatus) {
+        if !self.cover_sends.remove(&message_id) {
+            let _ = self.delivery_tx.send((peer, message_id, status));
+        }
+    }
+
+    /// Send dummy messages on `config`'s schedule, indistinguishable on the wire from real ones
+    pub fn enable_cover_traffic(&mut self, config: CoverConfig) {
+        self.cover = Some(CoverTraffic::new(config));
+    }
+
+    pub fn disable_cover_traffic(&mut self) {
+        self.cover = None;
+    }
+
+    /// Delay each publish, direct message and mailbox request by a random amount from
+    /// `jitter` (on by default), or send straight away with `None`. Key distribution
+    /// (group control and prekey messages) and handshakes aren't held back.
+    pub fn set_timing_jitter(&mut self, jitter: Option<TimingJitter>) {
+        self.jitter = jitter;
+    }
+
+    /// Hold each delivery ack back a random delay of up to `delay_ms` (on by default), or
+    /// ack each direct message as it arrives with `None`
+    pub fn set_ack_delay(&mut self, delay_ms: Option<u64>) {
+        self.swarm.behaviour_mut().direct.set_ack_delay(delay_ms);
+    }
+
+    /// Queue `outgoing` behind a random jitter, or send it now when jitter is off.
+    /// Never due before what's already queued, so sends keep the order they were made in.
+    fn send_jittered(&mut self, outgoing: Outgoing) {
+        match &self.jitter {
+            Some(jitter) => {
+                let due = Instant::now() + jitter.jitter_duration();
+                let due = self.send_queue.last().map_or(due, |(last, _)| due.max(*last));
+                self.send_queue.push((due, outgoing));
+            }
+            None => self.send_outgoing(outgoing),
+        }
+    }
+
+    /// Send whatever has waited out its jitter
+    fn flush_send_queue(&mut self) {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.send_queue)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        self.send_queue = waiting;
+        for (_, outgoing) in due {
+            self.send_outgoing(outgoing);
+        }
+    }
+
+    fn send_outgoing(&mut self, outgoing: Outgoing) {
+        match outgoing {
+            Outgoing::Publish { topic, data } => {
+                if let Err(e) = self.publish_now(&topic, data) {
+                    warn!("Jittered publish to {} dropped: {}", topic, e);
+                }
+            }
+            Outgoing::Direct { peer, message_id, data } => self.route_direct(peer, message_id, data),
+            Outgoing::Deposit { mailbox, tag, message } => {
+                if let Ok(behaviour) = self.mailbox() {
+                    behaviour.deposit(mailbox, tag, message);
+                }
+            }
+            Outgoing::Fetch { mailbox, fetch } => {
+                if let Ok(behaviour) = self.mailbox() {
+                    behaviour.fetch(mailbox, fetch);
+                }
+            }
+        }
+    }
+
+    /// Counters of real and cover messages, to measure the cover traffic overhead
+    pub fn traffic_stats(&self) -> TrafficStats {
+        self.traffic
+    }
+
+    /// Send one cover message to a joined group or a peer we share a session with
+    fn send_cover(&mut self) {
+        let Some(cover) = &self.cover else {
+            return;
+        };
+        let topics: Vec<String> = self.groups.topics().map(String::from).collect();
+        let peers: Vec<PeerId> = self.swarm.connected_peers()
+            .filter(|peer| self.has_session(peer))
+            .copied()
+            .collect();
+
+        let sent = match cover.pick_target(&topics, &peers) {
+            Some(CoverTarget::Topic(topic)) => self.message_exchange.group_cover()
+                .and_then(|plaintext| self.groups.encrypt(&topic, &plaintext))
+                .and_then(|data| {
+                    let len = data.len();
+                    self.publish(&topic, data).map(|()| len)
+                }),
+            Some(CoverTarget::Peer(peer)) => self.message_exchange.encrypt_cover(peer).map(|data| {
+                let len = data.len();
+                let message_id = self.dispatch_direct(peer, data);
+                self.cover_sends.insert(message_id);
+                len
+            }),
+            None => return,
+        };
+        match sent {
+            Ok(len) => {
+                self.traffic.cover_sent += 1;
+                self.traffic.cover_sent_bytes += len as u64;
+            }
+            Err(e) => debug!("Cover message not sent: {}", e),
+        }
+    }
+
+    /// Count a message that decrypted to cover traffic
+    fn count_cover<T>(&mut self, result: crate::error::Result<T>, len: usize) -> crate::error::Result<T> {
+        if matches!(result, Err(crate::error::NetError::CoverTraffic)) {
+            self.traffic.cover_received += 1;
+            self.traffic.cover_received_bytes += len as u64;
+        }
+        result
+    }
+
+    /// Route direct messages over a 3-hop onion circuit, so no relay sees both ends.
+    /// Recipients must have published their onion key (`publish_onion_key`).
+    pub fn set_circuit_routing(&mut self, enabled: bool) {
+        self.circuit_routing = enabled;
+        if !enabled {
+            if let Some((circuit, _)) = self.dm_circuit.take() {
+                self.swarm.behaviour_mut().circuit.destroy(circuit);
+            }
+        }
+    }
+
+    /// Send direct messages as Sphinx packets through 3 mixes, each holding them for a random
+    /// delay, so timing doesn't link sender and recipient either. Recipients must have
+    /// published their onion key (`publish_onion_key`).
+    pub fn set_mix_routing(&mut self, enabled: bool) {
+        self.mix_routing = enabled;
+    }
+
+    /// Relays our circuits enter through
+    pub fn entry_guards(&self) -> &[PeerId] {
+        &self.entry_guards
+    }
+
+    /// Relays the direct message circuit goes through, once built
+    pub fn circuit_path(&self) -> Option<Vec<PeerId>> {
+        let (circuit, true) = self.dm_circuit? else {
+            return None;
+        };
+        self.swarm.behaviour().circuit.circuit(circuit).map(|c| c.path())
+    }
+
+    /// Publish our onion key so others can build circuits through us and seal messages to us
+    pub fn publish_onion_key(&mut self) -> crate::error::Result<()> {
+        let circuit = &self.swarm.behaviour().circuit;
+        let descriptor = crate::relays::Descriptor {
+            onion_key: circuit.onion_key().clone(),
+            mix_key: circuit.mix_key(),
+            mix: circuit.is_mixing(),
+        };
+        let value = crate::relays::encode_record(&self.local_key, &descriptor)?;
+        let record = kad::Record::new(crate::relays::record_key(&self.local_peer_id), value);
+        self.swarm.behaviour_mut().kad.put_record(record, kad::Quorum::One)
+            .map_err(|e| crate::error::NetError::Discovery(format!("Publishing onion key failed: {:?}", e)))?;
+        self.descriptor_published = true;
+        Ok(())
+    }
+
+    /// Relay circuits for other peers, and announce it in the DHT
+    pub fn enable_relay(&mut self) -> crate::error::Result<()> {
+        self.swarm.behaviour_mut().circuit.enable_relay();
+        self.publish_onion_key()?;
+        self.swarm.behaviour_mut().kad.start_providing(crate::relays::provider_key())
+            .map_err(|e| crate::error::NetError::Discovery(format!("Announcing relay failed: {:?}", e)))?;
+        info!("🧅 Relaying circuits");
+        Ok(())
+    }
+
+    /// Relay circuits and forward Sphinx mix packets for other peers, and announce both
+    pub fn enable_mix(&mut self) -> crate::error::Result<()> {
+        self.swarm.behaviour_mut().circuit.enable_mix();
+        self.enable_relay()?;
+        info!("🔀 Mixing packets");
+        Ok(())
+    }
+
+    /// Move queued circuit messages along: look up missing onion keys, build the circuit,
+    /// and hand over whatever is ready
+    fn pump_circuits(&mut self) {
+        if self.circuit_queue.is_empty() {
+            return;
+        }
+
+        let recipients: HashSet<PeerId> = self.circuit_queue.iter().map(|(_, peer, _)| *peer).collect();
+        for peer in recipients {
+            self.lookup_onion_key(peer);
+        }
+
+        match self.dm_circuit {
+            Some((circuit, true)) => {
+                let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.circuit_queue)
+                    .into_iter()
+                    .partition(|(_, peer, _)| self.onion_keys.contains_key(peer));
+                self.circuit_queue = waiting;
+                for (message_id, peer, data) in ready {
+                    let recipient_key = self.onion_keys[&peer].clone();
+                    match self.swarm.behaviour_mut().circuit.send(circuit, message_id, peer, &recipient_key, &data) {
+                        Ok(()) => {
+                            self.circuit_sends.insert(message_id, (peer, circuit));
+                        }
+                        Err(e) => {
+                            self.report_delivery(peer, message_id, DeliveryStatus::Failed(e.to_string()));
+                        }
+                    }
+                }
+            }
+            Some((_, false)) => {}
+            None => self.build_dm_circuit(),
+        }
+    }
+
+    fn build_dm_circuit(&mut self) {
+        let recipients: HashSet<PeerId> = self.circuit_queue.iter().map(|(_, peer, _)| *peer).collect();
+        let mut candidates: Vec<(PeerId, OnionPublicKey)> = self.relays.iter()
+            .filter(|peer| **peer != self.local_peer_id && !recipients.contains(peer))
+            .filter_map(|peer| self.onion_keys.get(peer).map(|key| (*peer, key.clone())))
+            .collect();
+
+        if candidates.len() >= CIRCUIT_HOPS {
+            use rand::seq::SliceRandom;
+            let mut rng = rand::thread_rng();
+
+            // Guards that stopped relaying are replaced; the others stay for every circuit
+            self.entry_guards.retain(|guard| self.relays.contains(guard));
+            let new_guards: Vec<PeerId> = candidates.iter()
+                .map(|(peer, _)| *peer)
+                .filter(|peer| !self.entry_guards.contains(peer))
+                .collect();
+            let missing = ENTRY_GUARDS.saturating_sub(self.entry_guards.len());
+            self.entry_guards.extend(new_guards.choose_multiple(&mut rng, missing));
+
+            let guards: Vec<usize> = (0..candidates.len())
+                .filter(|i| self.entry_guards.contains(&candidates[*i].0))
+                .collect();
+            let Some(&guard) = guards.choose(&mut rng) else {
+                return self.fail_circuit_queue("No entry guard available");
+            };
+            let guard = candidates.swap_remove(guard);
+            candidates.shuffle(&mut rng);
+            candidates.truncate(CIRCUIT_HOPS - 1);
+            candidates.insert(0, guard);
+            match self.swarm.behaviour_mut().circuit.build(candidates) {
+                Ok(circuit) => {
+                    debug!("Building circuit {}", circuit);
+                    self.dm_circuit = Some((circuit, false));
+                }
+                Err(e) => self.fail_circuit_queue(&e.to_string()),
+            }
+        } else if !self.search_relays() {
+            self.fail_circuit_queue(&format!("Found {} of {} relays for a circuit", candidates.len(), CIRCUIT_HOPS));
+        }
+    }
+
+    /// Look for relays after finding too few; false once a fresh search turned up no more
+    fn search_relays(&mut self) -> bool {
+        if self.relay_search.is_some() || self.onion_lookups.values().any(|peer| self.relays.contains(peer)) {
+            // Still finding relays
+            true
+        } else if !self.relays_searched {
+            self.relays_searched = true;
+            self.relay_search = Some(self.swarm.behaviour_mut().kad.get_providers(crate::relays::provider_key()));
+            true
+        } else {
+            self.relays_searched = false;
+            false
+        }
+    }
+
+    /// Send queued mix messages whose recipient's onion key we have, each through its own
+    /// random choice of mixes
+    fn pump_mixes(&mut self) {
+        if self.mix_queue.is_empty() {
+            return;
+        }
+
+        let recipients: HashSet<PeerId> = self.mix_queue.iter().map(|(_, peer, _)| *peer).collect();
+        for peer in &recipients {
+            self.lookup_onion_key(*peer);
+        }
+
+        let mixes: Vec<(PeerId, [u8; 32])> = self.mixes.iter()
+            .filter(|peer| **peer != self.local_peer_id && !recipients.contains(peer))
+            .filter_map(|peer| self.fresh_mix_key(peer).map(|key| (*peer, key)))
+            .collect();
+        if mixes.len() < MIX_HOPS {
+            if !self.search_relays() {
+                self.fail_mix_queue(&format!("Found {} of {} mixes", mixes.len(), MIX_HOPS));
+            }
+            return;
+        }
+
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.mix_queue)
+            .into_iter()
+            .partition(|(_, peer, _)| self.fresh_mix_key(peer).is_some());
+        self.mix_queue = waiting;
+        for (message_id, peer, data) in ready {
+            use rand::seq::SliceRandom;
+            let mut route: Vec<_> = mixes.choose_multiple(&mut rand::thread_rng(), MIX_HOPS).cloned().collect();
+            route.push((peer, self.mix_keys[&peer].0));
+            if let Err(e) = self.swarm.behaviour_mut().circuit.send_mix(message_id, &route, &data) {
+                self.report_delivery(peer, message_id, DeliveryStatus::Failed(e.to_string()));
+            }
+        }
+    }
+
+    fn fail_mix_queue(&mut self, reason: &str) {
+        warn!("Dropping {} mix message(s): {}", self.mix_queue.len(), reason);
+        for (message_id, peer, _) in std::mem::take(&mut self.mix_queue) {
+            self.report_delivery(peer, message_id, DeliveryStatus::Failed(reason.to_string()));
+        }
+    }
+
+    fn fail_circuit_queue(&mut self, reason: &str) {
+        warn!("Dropping {} circuit message(s): {}", self.circuit_queue.len(), reason);
+        for (message_id, peer, _) in std::mem::take(&mut self.circuit_queue) {
+            self.report_delivery(peer, message_id, DeliveryStatus::Failed(reason.to_string()));
+        }
+    }
+
+    /// `peer`'s mix key, unless it may have rotated out since we looked it up
+    fn fresh_mix_key(&self, peer: &PeerId) -> Option<[u8; 32]> {
+        self.mix_keys.get(peer).filter(|(_, fetched)| fetched.elapsed() < MIX_KEY_LIFETIME).map(|(key, _)| *key)
+    }
+
+    fn lookup_onion_key(&mut self, peer: PeerId) {
+        if self.fresh_mix_key(&peer).is_some() || self.onion_lookups.values().any(|p| *p == peer) {
+            return;
+        }
+        let query = self.swarm.behaviour_mut().kad.get_record(crate::relays::record_key(&peer));
+        self.onion_lookups.insert(query, peer);
+    }
+
+    fn on_onion_lookup(&mut self, query: kad::QueryId, result: Result<kad::GetRecordOk, kad::GetRecordError>) {
+        let Some(peer) = self.onion_lookups.remove(&query) else {
+            return;
+        };
+
+        let outcome = match result {
+            Ok(kad::GetRecordOk::FoundRecord(found)) => crate::relays::decode_record(&peer, &found.record.value),
+            Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. }) => {
+                Err(crate::error::NetError::PeerNotFound(format!("No onion key for {}", peer)))
+            }
+            Err(e) => Err(crate::error::NetError::Discovery(format!("Onion key lookup failed: {}", e))),
+        };
+
+        match outcome {
+            Ok(descriptor) => {
+                if descriptor.mix {
+                    self.mixes.insert(peer);
+                } else {
+                    self.mixes.remove(&peer);
+                }
+                self.onion_keys.insert(peer, descriptor.onion_key);
+                self.mix_keys.insert(peer, (descriptor.mix_key, Instant::now()));
+            }
+            Err(e) => {
+                debug!("{}", e);
+                self.relays.remove(&peer);
+                self.mixes.remove(&peer);
+                let mut failed = Vec::new();
+                for queue in [&mut self.circuit_queue, &mut self.mix_queue] {
+                    let (lost, waiting): (Vec<_>, Vec<_>) = std::mem::take(queue)
+                        .into_iter()
+                        .partition(|(_, p, _)| *p == peer);
+                    *queue = waiting;
+                    failed.extend(lost);
+                }
+                for (message_id, peer, _) in failed {
+                    self.report_delivery(peer, message_id, DeliveryStatus::Failed(e.to_string()));
+                }
+            }
+        }
+        self.pump_circuits();
+        self.pump_mixes();
+    }
+
+    fn on_relay_providers(
+        &mut self,
+        query: kad::QueryId,
+        result: Result<kad::GetProvidersOk, kad::GetProvidersError>,
+        last: bool,
+    ) {
+        if self.relay_search != Some(query) {
+            return;
+        }
+        if let Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) = result {
+            for relay in providers {
+                if relay != self.local_peer_id && self.relays.insert(relay) {
+                    self.lookup_onion_key(relay);
+                }
+            }
+        }
+        if last {
+            self.relay_search = None;
+            self.pump_circuits();
+            self.pump_mixes();
+        }
+    }
+
+    fn on_circuit_event(&mut self, event: CircuitEvent) {
+        match event {
+            CircuitEvent::Built { circuit } => {
+                if let Some((id, built)) = self.dm_circuit.as_mut() {
+                    if *id == circuit {
+                        *built = true;
+                        self.relays_searched = false;
+                        info!("🧅 Circuit {} built", circuit);
+                        self.pump_circuits();
+                    }
+                }
+            }
+            CircuitEvent::Closed { circuit, reason } => {
+                let Some((id, built)) = self.dm_circuit else {
+                    return;
+                };
+                if id != circuit {
+                    return;
+                }
+                warn!("Circuit {} closed: {}", circuit, reason);
+                self.dm_circuit = None;
+                let lost: Vec<u64> = self.circuit_sends.iter()
+                    .filter(|(_, (_, c))| *c == circuit)
+                    .map(|(message_id, _)| *message_id)
+                    .collect();
+                for message_id in lost {
+                    if let Some((peer, _)) = self.circuit_sends.remove(&message_id) {
+                        self.report_delivery(peer, message_id, DeliveryStatus::Failed(reason.clone()));
+                    }
+                }
+                if built {
+                    // Try a fresh circuit for whatever is still queued
+                    self.pump_circuits();
+                } else {
+                    self.fail_circuit_queue(&format!("Circuit build failed: {}", reason));
+                }
+            }
+            CircuitEvent::Delivered { message_id, status, .. } => {
+                if let Some((peer, _)) = self.circuit_sends.remove(&message_id) {
+                    self.report_delivery(peer, message_id, status);
+                }
+            }
+            CircuitEvent::Received { data } => match self.decrypt_forwarded(&data) {
+                Ok((sender, msg)) => self.on_direct_message(sender, msg),
+                Err(crate::error::NetError::CoverTraffic) => {}
+                Err(e) => warn!("Dropped message from circuit: {}", e),
+            },
+            CircuitEvent::MixFailed { message_id, recipient, reason } => {
+                self.report_delivery(recipient, message_id, DeliveryStatus::Failed(reason));
+            }
+            CircuitEvent::MixKeyRotated => {
+                if self.descriptor_published {
+                    if let Err(e) = self.publish_onion_key() {
+                        warn!("Failed to republish our descriptor: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decrypt a message that reached us through someone else (mailbox, circuit exit):
+    /// the sender comes from the message itself
+    fn decrypt_forwarded(&mut self, data: &[u8]) -> crate::error::Result<(PeerId, crate::message::DecryptedMessage)> {
+        use prost::Message;
+
+        let sender = umbra_wire::message::EncryptedMessage::decode(data)
+            .ok()
+            .and_then(|m| PeerId::from_bytes(&m.sender).ok())
+            .ok_or_else(|| crate::error::NetError::InvalidMessage("Malformed forwarded message".to_string()))?;
+        let result = self.message_exchange.decrypt_message(sender, data);
+        let msg = self.count_cover(result, data.len())?;
+        Ok((sender, msg))
+    }
+
+    /// Publish our prekey bundle to the DHT so peers can start sessions while we're offline
+    pub fn publish_prekeys(&mut self) -> crate::error::Result<()> {
+        self.prekeys.replenish(DEFAULT_ONE_TIME_PREKEYS);
+        // Saved before the bundle goes out, so a restart never brings back a spent prekey
+        if let Some(key_file) = &self.key_file {
+            key_file.save_prekeys(&self.prekeys)?;
+        }
+        let bundle = self.prekeys.bundle()
+            .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+        let value = crate::prekeys::encode_record(&self.local_key, &bundle)?;
+        let record = kad::Record::new(crate::prekeys::record_key(&self.local_peer_id), value);
+        self.swarm.behaviour_mut().kad.put_record(record, kad::Quorum::One)
+            .map_err(|e| crate::error::NetError::Discovery(format!("Publishing prekeys failed: {:?}", e)))?;
+        Ok(())
+    }
+
+    /// Send to a peer that may be offline: the session is set up from its published prekey
+    /// bundle, and the messages go out together with the session setup once it is connected
+    pub fn send_offline(&mut self, peer: PeerId, username: &str, content: &str) -> crate::error::Result<()> {
+        self.queue_prekey_message(peer, None, username, content)
+    }
+
+    /// Add a message to the prekey session with `peer`, starting one if needed; with a
+    /// `mailbox`, the session setup is left there instead of waiting for the peer to connect
+    fn queue_prekey_message(
+        &mut self,
+        peer: PeerId,
+        mailbox: Option<PeerId>,
+        username: &str,
+        content: &str,
+    ) -> crate::error::Result<()> {
+        match self.prekey_sessions.get_mut(&peer) {
+            Some(PendingSession::Lookup { queued, mailbox: via, .. }) => {
+                queued.push((username.to_string(), content.to_string()));
+                *via = mailbox.or(*via);
+            }
+            Some(PendingSession::Ready(message)) => {
+                message.messages.push(self.message_exchange.encrypt_message(peer, username, content)?);
+                if let Some(mailbox) = mailbox {
+                    self.deposit_prekey_message(mailbox, peer)?;
+                }
+            }
+            None => {
+                let query = self.swarm.behaviour_mut().kad.get_record(crate::prekeys::record_key(&peer));
+                let queued = vec![(username.to_string(), content.to_string())];
+                self.prekey_sessions.insert(peer, PendingSession::Lookup { query, queued, mailbox });
+            }
+        }
+        Ok(())
+    }
+
+    fn on_prekey_lookup(&mut self, query: kad::QueryId, result: Result<kad::GetRecordOk, kad::GetRecordError>) {
+        let Some(peer) = self.prekey_sessions.iter().find_map(|(peer, session)| {
+            matches!(session, PendingSession::Lookup { query: q, .. } if *q == query).then_some(*peer)
+        }) else {
+            return;
+        };
+
+        let mailbox = match self.prekey_sessions.get(&peer) {
+            Some(PendingSession::Lookup { mailbox, .. }) => *mailbox,
+            _ => None,
+        };
+        let outcome = match result {
+            Ok(kad::GetRecordOk::FoundRecord(found)) => self.start_prekey_session(peer, &found.record.value),
+            Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. }) => {
+                Err(crate::error::NetError::PeerNotFound(format!("No prekey bundle for {}", peer)))
+            }
+            Err(e) => Err(crate::error::NetError::Discovery(format!("Prekey lookup failed: {}", e))),
+        };
+
+        let outcome = outcome.and_then(|()| match mailbox {
+            Some(mailbox) if !self.swarm.is_connected(&peer) => self.deposit_prekey_message(mailbox, peer),
+            _ => Ok(()),
+        });
+        match outcome {
+            Ok(()) if self.swarm.is_connected(&peer) => self.flush_prekey_messages(peer),
+            Ok(()) if self.prekey_sessions.contains_key(&peer) => {
+                debug!("Prekey session with {} ready, waiting for it to connect", peer)
+            }
+            Ok(()) => {}
+            Err(e) => {
+                warn!("Dropping messages for {}: {}", peer, e);
+                self.prekey_sessions.remove(&peer);
+            }
+        }
+    }
+
+    /// Seed the ratchet from `peer`'s bundle and encrypt what was queued for it
+    fn start_prekey_session(&mut self, peer: PeerId, record: &[u8]) -> crate::error::Result<()> {
+        let (bundle, verify_key) = crate::prekeys::decode_record(&peer, record)?;
+        let identity = self.message_exchange.session_manager().identity();
+        let (init, keys) = umbra_crypto::prekey::initiate(identity, &bundle)
+            .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+
+        let session_manager = self.message_exchange.session_manager_mut();
+        session_manager.register_peer_hybrid(peer, verify_key);
+        session_manager.set_session_key(peer, keys.session_key())
+            .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+
+        let queued = match self.prekey_sessions.remove(&peer) {
+            Some(PendingSession::Lookup { queued, .. }) => queued,
+            _ => Vec::new(),
+        };
+        let mut messages = Vec::with_capacity(queued.len());
+        for (username, content) in queued {
+            messages.push(self.message_exchange.encrypt_message(peer, &username, &content)?);
+        }
+
+        let message = PrekeyMessage { init: Some((&init).into()), messages };
+        self.prekey_sessions.insert(peer, PendingSession::Ready(message));
+        Ok(())
+    }
+
+    fn flush_prekey_messages(&mut self, peer: PeerId) {
+        if !matches!(self.prekey_sessions.get(&peer), Some(PendingSession::Ready(_))) {
+            return;
+        }
+        let Some(PendingSession::Ready(message)) = self.prekey_sessions.remove(&peer) else {
+            return;
+        };
+
+        info!("Delivering {} prekey message(s) to {}", message.messages.len(), peer);
+        let message_id = self.swarm.behaviour_mut().direct.send_prekey(peer, message.clone());
+        self.prekey_sends.insert(message_id, (peer, message));
+        self.prekey_peers.insert(peer);
+    }
+
+    /// Responder side: set up the session from our prekeys and decrypt the messages that came with it
+    fn accept_prekey_message(
+        &mut self,
+        peer: PeerId,
+        message: PrekeyMessage,
+    ) -> crate::error::Result<Vec<crate::message::DecryptedMessage>> {
+        let init = message.init.as_ref()
+            .ok_or_else(|| crate::error::NetError::InvalidMessage("Prekey message without init".to_string()))?;
+        let init = PrekeyInit::try_from(init)
+            .map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        let (verify_key, keys) = self.prekeys.accept(&init)
+            .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+
+        let session_manager = self.message_exchange.session_manager_mut();
+        session_manager.register_peer_hybrid(peer, verify_key);
+        session_manager.set_session_key(peer, keys.session_key())
+            .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+
+        // The one-time prekey is spent: publish a topped-up bundle
+        if let Err(e) = self.publish_prekeys() {
+            warn!("Failed to republish prekeys: {}", e);
+        }
+
+        let mut decrypted = Vec::with_capacity(message.messages.len());
+        for data in &message.messages {
+            match self.message_exchange.decrypt_message(peer, data) {
+                Ok(msg) => decrypted.push(msg),
+                Err(e) => warn!("Dropped prekey message from {}: {}", peer, e),
+            }
+        }
+        Ok(decrypted)
+    }
+
+    fn on_direct_message(&mut self, peer_id: PeerId, msg: crate::message::DecryptedMessage) {
+        if let Some(control) = &msg.group_control {
+            if let Err(e) = self.on_group_control(peer_id, control) {
+                warn!("Dropped group control message from {}: {}", peer_id, e);
+            }
+        } else {
+            let _ = self.direct_tx.send((peer_id, msg));
+        }
+    }
+
+    /// Hold sealed messages for offline peers (store-and-forward), persisted per `config`
+    pub fn enable_mailbox(&mut self, config: MailboxConfig) -> crate::error::Result<()> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let store = MailboxStore::open(config, now)?;
+        info!("📬 Mailbox serving {} stored message(s)", store.len());
+        self.mailbox()?.serve(store);
+        Ok(())
+    }
+
+    /// Our mailbox address: senders deposit under it, only we can fetch from it
+    pub fn mailbox_tag(&self) -> [u8; 32] {
+        umbra_wire::mailbox::recipient_tag(&self.message_exchange.session_manager().hybrid_public_key())
+    }
+
+    /// Leave an encrypted message for `recipient` at `mailbox`, for it to fetch later.
+    /// Without a session, one is set up from the recipient's prekey bundle and left there
+    /// along with the message, so neither side has to be online when the other is.
+    pub fn deposit_to_mailbox(
+        &mut self,
+        mailbox: PeerId,
+        recipient: PeerId,
+        username: &str,
+        content: &str,
+    ) -> crate::error::Result<()> {
+        use umbra_wire::message::{mailbox_message, MailboxMessage};
+
+        self.mailbox()?;
+        if self.prekey_sessions.contains_key(&recipient)
+            || !self.message_exchange.session_manager().has_session(&recipient)
+        {
+            return self.queue_prekey_message(recipient, Some(mailbox), username, content);
+        }
+        let encrypted = self.message_exchange.encrypt_message(recipient, username, content)?;
+        let message = MailboxMessage { message: Some(mailbox_message::Message::Encrypted(encrypted)), ..Default::default() };
+        let deposit = self.mailbox_deposit(mailbox, recipient, message)?;
+        self.send_jittered(deposit);
+        Ok(())
+    }
+
+    /// Leave the prekey session setup for `peer`, and what was queued with it, at `mailbox`
+    fn deposit_prekey_message(&mut self, mailbox: PeerId, peer: PeerId) -> crate::error::Result<()> {
+        let Some(PendingSession::Ready(message)) = self.prekey_sessions.remove(&peer) else {
+            return Ok(());
+        };
+        info!("📬 Leaving {} prekey message(s) for {} at {}", message.messages.len(), peer, mailbox);
+        let envelope = crate::prekeys::mailbox_envelope(&self.local_key, &message)?;
+        let deposit = self.mailbox_deposit(mailbox, peer, envelope)?;
+        self.send_outgoing(deposit);
+        Ok(())
+    }
+
+    fn mailbox_deposit(
+        &self,
+        mailbox: PeerId,
+        recipient: PeerId,
+        message: umbra_wire::message::MailboxMessage,
+    ) -> crate::error::Result<Outgoing> {
+        use prost::Message;
+
+        let verify_key = self.message_exchange.session_manager().get_peer_hybrid_key(&recipient)
+            .ok_or_else(|| crate::error::NetError::UnknownPeerKey(recipient.to_string()))?;
+        let tag = umbra_wire::mailbox::recipient_tag(verify_key);
+        Ok(Outgoing::Deposit { mailbox, tag, message: message.encode_to_vec() })
+    }
+
+    /// Collect our messages from `mailbox`; they arrive on the direct message receiver and
+    /// are deleted from the mailbox once received
+    pub fn fetch_mailbox(&mut self, mailbox: PeerId) -> crate::error::Result<()> {
+        self.mailbox_drains.entry(mailbox).or_insert(0);
+        self.send_fetch(mailbox, Vec::new())
+    }
+
+    fn mailbox(&mut self) -> crate::error::Result<&mut MailboxBehaviour> {
+        self.swarm.behaviour_mut().mailbox.as_mut()
+            .map(|mailbox| &mut **mailbox)
+            .ok_or(crate::error::NetError::Disabled("Mailbox"))
+    }
+
+    fn send_fetch(&mut self, mailbox: PeerId, ack: Vec<u64>) -> crate::error::Result<()> {
+        let fetch = umbra_wire::mailbox::Fetch::new(self.message_exchange.session_manager().identity(), &mailbox, ack)
+            .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+        self.mailbox()?;
+        self.send_jittered(Outgoing::Fetch { mailbox, fetch });
+        Ok(())
+    }
+
+    /// Deliver a fetched batch, then ack it (which also asks for the next one)
+    fn on_mailbox_batch(&mut self, mailbox: PeerId, messages: Vec<umbra_wire::mailbox::StoredMessage>) {
+        use prost::Message;
+        use umbra_wire::message::{mailbox_message, MailboxMessage};
+
+        if messages.is_empty() {
+            let delivered = self.mailbox_drains.remove(&mailbox).unwrap_or_default();
+            let _ = self.mailbox_tx.send(MailboxUpdate::Drained { mailbox, delivered });
+            return;
+        }
+
+        // Undecryptable messages are acked too, so they don't clog the mailbox
+        let ack: Vec<u64> = messages.iter().map(|stored| stored.id).collect();
+        let mut setups = Vec::new();
+        let mut encrypted = Vec::new();
+        for stored in messages {
+            match MailboxMessage::decode(stored.message.as_slice()) {
+                Ok(envelope) => match envelope.message {
+                    Some(mailbox_message::Message::Prekey(_)) => setups.push((stored.id, envelope)),
+                    Some(mailbox_message::Message::Encrypted(data)) => encrypted.push((stored.id, data)),
+                    None => warn!("Dropped empty mailbox message {} from {}", stored.id, mailbox),
+                },
+                Err(e) => warn!("Dropped malformed mailbox message {} from {}: {}", stored.id, mailbox, e),
+            }
+        }
+
+        // Session setups first, so the messages sent after one decrypt whatever order they were stored in
+        let mut delivered = Vec::new();
+        for (id, envelope) in setups {
+            let accepted = crate::prekeys::open_mailbox_envelope(&envelope)
+                .and_then(|(sender, message)| Ok((sender, self.accept_prekey_message(sender, message)?)));
+            match accepted {
+                Ok((sender, messages)) => delivered.extend(messages.into_iter().map(|msg| (sender, msg))),
+                Err(e) => warn!("Rejected mailbox prekey message {} from {}: {}", id, mailbox, e),
+            }
+        }
+        for (id, data) in encrypted {
+            match self.decrypt_forwarded(&data) {
+                Ok(message) => delivered.push(message),
+                Err(crate::error::NetError::CoverTraffic) => {}
+                Err(e) => warn!("Dropped mailbox message {} from {}: {}", id, mailbox, e),
+            }
+        }
+        for (sender, msg) in delivered {
+            *self.mailbox_drains.entry(mailbox).or_insert(0) += 1;
+            self.on_direct_message(sender, msg);
+        }
+
+        if let Err(e) = self.send_fetch(mailbox, ack) {
+            warn!("Failed to ack mailbox messages at {}: {}", mailbox, e);
+        }
+    }
+
+    /// Offer the file at `path` to `peer`, which must have completed a handshake with us.
+    /// Progress and the outcome arrive on the file receiver.
+    pub fn offer_file(&mut self, peer: PeerId, path: &std::path::Path) -> crate::error::Result<FileId> {
+        if self.get_session_key(&peer).is_none() {
+            return Err(crate::error::NetError::Crypto(format!("No session with {}", peer)));
+        }
+        self.offer_file_to(vec![peer], path)
+    }
+
+    /// Offer the file at `path` to every member of our group on `topic` we share a session with;
+    /// they fetch it from each other as well as from us
+    pub fn offer_file_to_group(&mut self, topic: &str, path: &std::path::Path) -> crate::error::Result<FileId> {
+        let members: Vec<PeerId> = self.groups.members(topic).into_iter()
+            .filter(|peer| self.get_session_key(peer).is_some())
+            .collect();
+        if members.is_empty() {
+            return Err(crate::error::NetError::Transport(format!("No members of {} to offer to", topic)));
+        }
+        self.offer_file_to(members, path)
+    }
+
+    fn offer_file_to(&mut self, peers: Vec<PeerId>, path: &std::path::Path) -> crate::error::Result<FileId> {
+        let name = path.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| crate::error::NetError::InvalidMessage(format!("Not a file: {}", path.display())))?;
+        let (size, tree) = crate::file::hash_file(path, CHUNK_SIZE)?;
+        let manifest = umbra_wire::file::FileManifest::new(
+            self.message_exchange.session_manager().identity(),
+            name,
+            size,
+            CHUNK_SIZE,
+            tree.root(),
+        )
+        .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+        let file_id = manifest.file_id().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        for peer in peers {
+            self.file()?.offer(peer, path.to_path_buf(), manifest.clone(), tree.clone())?;
+        }
+        Ok(file_id)
+    }
+
+    /// Offer the folder at `path`, with everything under it, to `peer`, which must have
+    /// completed a handshake with us. It's tracked like a file, under the id returned.
+    pub fn offer_folder(&mut self, peer: PeerId, path: &std::path::Path) -> crate::error::Result<FileId> {
+        if self.get_session_key(&peer).is_none() {
+            return Err(crate::error::NetError::Crypto(format!("No session with {}", peer)));
+        }
+        self.offer_folder_to(vec![peer], path)
+    }
+
+    /// Offer the folder at `path` to every member of our group on `topic` we share a session with
+    pub fn offer_folder_to_group(&mut self, topic: &str, path: &std::path::Path) -> crate::error::Result<FileId> {
+        let members: Vec<PeerId> = self.groups.members(topic).into_iter()
+            .filter(|peer| self.get_session_key(peer).is_some())
+            .collect();
+        if members.is_empty() {
+            return Err(crate::error::NetError::Transport(format!("No members of {} to offer to", topic)));
+        }
+        self.offer_folder_to(members, path)
+    }
+
+    fn offer_folder_to(&mut self, peers: Vec<PeerId>, path: &std::path::Path) -> crate::error::Result<FileId> {
+        let name = path.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| crate::error::NetError::InvalidMessage(format!("Not a folder: {}", path.display())))?;
+        let mut entries = Vec::new();
+        let mut files = Vec::new();
+        for (relative, full, directory, mode) in crate::file::walk_folder(path)? {
+            if directory {
+                entries.push(umbra_wire::file::FolderEntry::directory(&relative, mode));
+            } else {
+                let (size, tree) = crate::file::hash_file(&full, CHUNK_SIZE)?;
+                entries.push(umbra_wire::file::FolderEntry::file(&relative, size, mode, tree.root()));
+                files.push((full, tree));
+            }
+        }
+        let manifest = umbra_wire::file::FolderManifest::new(
+            self.message_exchange.session_manager().identity(),
+            name,
+            CHUNK_SIZE,
+            entries,
+        )
+        .map_err(|e| crate::error::NetError::Crypto(e.to_string()))?;
+        let folder_id = manifest.folder_id().map_err(|e| crate::error::NetError::InvalidMessage(e.to_string()))?;
+        for peer in peers {
+            self.file()?.offer_folder(peer, manifest.clone(), files.clone())?;
+        }
+        Ok(folder_id)
+    }
+
+    /// Download an offered file to `path`, from every peer we can reach that has some of it
+    pub fn accept_file(&mut self, file_id: FileId, path: &std::path::Path) -> crate::error::Result<()> {
+        self.file()?.accept(file_id, path.to_path_buf())?;
+        for peer in self.connected_peers() {
+            self.file()?.add_source(file_id, peer);
+        }
+        Ok(())
+    }
+
+    /// Download an offered folder into a new directory under `dir`; returns that directory
+    pub fn accept_folder(&mut self, folder_id: FileId, dir: &std::path::Path) -> crate::error::Result<std::path::PathBuf> {
+        let root = self.file()?.accept_folder(folder_id, dir)?;
+        for peer in self.connected_peers() {
+            self.file()?.add_peer(peer);
+        }
+        Ok(root)
+    }
+
+    /// Stop requesting chunks of a download (file or folder) until `resume_file`
+    pub fn pause_file(&mut self, file_id: FileId) -> bool {
+        self.file().is_ok_and(|file| file.pause(file_id))
+    }
+
+    pub fn resume_file(&mut self, file_id: FileId) -> bool {
+        self.file().is_ok_and(|file| file.resume(file_id))
+    }
+
+    /// Decline an offer, abandon a download or stop serving a file
+    pub fn cancel_file(&mut self, file_id: FileId) {
+        if let Ok(file) = self.file() {
+            file.cancel(file_id);
+        }
+    }
+
+    fn file(&mut self) -> crate::error::Result<&mut FileBehaviour> {
+        self.swarm.behaviour_mut().file.as_mut()
+            .map(|file| &mut **file)
+            .ok_or(crate::error::NetError::Disabled("File transfer"))
+    }
+
+    fn on_file_event(&mut self, event: FileEvent) {
+        match event {
+            FileEvent::Offered { peer, manifest, verify_key } => {
+                // Only take files signed by the identity the peer proved in its handshake
+                let known = self.message_exchange.session_manager().get_peer_hybrid_key(&peer);
+                let Ok(file_id) = manifest.file_id() else {
+                    return;
+                };
+                if known != Some(&*verify_key) {
+                    warn!("Ignoring file offer from {}: not signed by its identity", peer);
+                    self.cancel_file(file_id);
+                    return;
+                }
+                info!("📁 {} offers {} ({} bytes)", peer, manifest.name, manifest.size);
+                let _ = self.file_tx.send(FileUpdate::Offered { peer, file_id, name: manifest.name, size: manifest.size });
+            }
+            FileEvent::FolderOffered { peer, manifest, verify_key } => {
+                let known = self.message_exchange.session_manager().get_peer_hybrid_key(&peer);
+                let Ok(folder_id) = manifest.folder_id() else {
+                    return;
+                };
+                if known != Some(&*verify_key) {
+                    warn!("Ignoring folder offer from {}: not signed by its identity", peer);
+                    self.cancel_file(folder_id);
+                    return;
+                }
+                let (files, size) = (manifest.files().count(), manifest.size());
+                info!("📁 {} offers folder {} ({} files, {} bytes)", peer, manifest.name, files, size);
+                let _ = self.file_tx.send(FileUpdate::FolderOffered { peer, folder_id, name: manifest.name, files, size });
+            }
+            FileEvent::Update(update) => {
+                if let FileUpdate::Failed { ref error, .. } = update {
+                    warn!("File transfer failed: {}", error);
+                }
+                let _ = self.file_tx.send(update);
+            }
+        }
+    }
+
+    /// Decrypt received message (`NetError::CoverTraffic` for cover messages, which have nothing to show)
+    pub fn decrypt_message(&mut self, peer: PeerId, data: &[u8]) -> crate::error::Result<crate::message::DecryptedMessage> {
+        let result = self.message_exchange.decrypt_message(peer, data);
+        self.count_cover(result, data.len())
+    }
+
+    /// Strict (default) rejects unverified senders, lenient flags them
+    pub fn set_verification_mode(&mut self, mode: crate::message::VerificationMode) {
+        self.message_exchange.set_verification_mode(mode);
     }
     
     /// Set identity for ZK proofs
@@ -238,6 +1647,31 @@ impl P2PNode {
         self.message_rx.take()
     }
     
+    /// Take direct message receiver (already decrypted and verified)
+    pub fn take_direct_message_receiver(&mut self) -> Option<tokio::sync::mpsc::UnboundedReceiver<(PeerId, crate::message::DecryptedMessage)>> {
+        self.direct_rx.take()
+    }
+    
+    /// Take receiver for `send_direct` delivery results
+    pub fn take_delivery_receiver(&mut self) -> Option<tokio::sync::mpsc::UnboundedReceiver<(PeerId, u64, DeliveryStatus)>> {
+        self.delivery_rx.take()
+    }
+    
+    /// Take the receiver for group invites and membership changes
+    pub fn take_group_receiver(&mut self) -> Option<tokio::sync::mpsc::UnboundedReceiver<GroupUpdate>> {
+        self.group_rx.take()
+    }
+    
+    /// Take receiver for mailbox deposit and fetch results
+    pub fn take_mailbox_receiver(&mut self) -> Option<tokio::sync::mpsc::UnboundedReceiver<MailboxUpdate>> {
+        self.mailbox_rx.take()
+    }
+    
+    /// Take receiver for file offers, transfer progress and results
+    pub fn take_file_receiver(&mut self) -> Option<tokio::sync::mpsc::UnboundedReceiver<FileUpdate>> {
+        self.file_rx.take()
+    }
+    
     /// Take connection receiver for application use
     pub fn take_connection_receiver(&mut self) -> Option<tokio::sync::mpsc::UnboundedReceiver<PeerId>> {
         self.connection_rx.take()
@@ -252,23 +1686,20 @@ impl P2PNode {
     pub async fn poll_once(&mut self) -> crate::error::Result<()> {
         use futures::StreamExt;
         
-        // Check for outbound handshake messages
-        while let Some(outbound) = self.swarm.behaviour_mut().handshake.poll_outbound() {
-            use crate::handshake::HandshakeOutbound;
-            match outbound {
-                HandshakeOutbound::SendInit { peer_id, data } | 
-                HandshakeOutbound::SendResp { peer_id, data } => {
-                    debug!("Sending handshake message to {} ({} bytes)", peer_id, data.len());
-                    // Get first topic (if any)
-                    let topic_opt = self.swarm.behaviour().gossipsub.topics().next().cloned();
-                    if let Some(topic) = topic_opt {
-                        let _ = self.swarm.behaviour_mut().gossipsub.publish(topic, data);
-                    }
-                }
+        let send_due = self.send_queue.iter().map(|(due, _)| *due).min();
+        let event = tokio::select! {
+            event = self.swarm.select_next_some() => event,
+            _ = cover_tick(self.cover.as_mut()) => {
+                self.send_cover();
+                return Ok(());
             }
-        }
+            _ = sleep_until(send_due) => {
+                self.flush_send_queue();
+                return Ok(());
+            }
+        };
         
-        match self.swarm.select_next_some().await {
+        match event {
             SwarmEvent::NewListenAddr { address, .. } => {
                 debug!("Listening on {:?}", address);
             }
@@ -289,20 +1720,53 @@ impl P2PNode {
                     UmbraEvent::Kad(kad::Event::RoutingUpdated { peer, .. }) => {
                         debug!("Routing table updated: {}", peer);
                     }
+                    UmbraEvent::Kad(kad::Event::OutboundQueryProgressed {
+                        id,
+                        result: kad::QueryResult::GetRecord(result),
+                        ..
+                    }) => {
+                        if self.onion_lookups.contains_key(&id) {
+                            self.on_onion_lookup(id, result);
+                        } else {
+                            self.on_prekey_lookup(id, result);
+                        }
+                    }
+                    UmbraEvent::Kad(kad::Event::OutboundQueryProgressed {
+                        id,
+                        result: kad::QueryResult::GetProviders(result),
+                        step,
+                        ..
+                    }) => {
+                        self.on_relay_providers(id, result, step.last);
+                    }
+                    UmbraEvent::Kad(kad::Event::OutboundQueryProgressed {
+                        result: kad::QueryResult::PutRecord(Err(e)),
+                        ..
+                    }) => {
+                        debug!("DHT record not replicated: {}", e);
+                    }
                     UmbraEvent::Gossipsub(gossipsub::Event::Message {
                         propagation_source,
                         message,
                         ..
                     }) => {
-                        // Try to parse as handshake message first
-                        if let Ok(_hs_msg) = umbra_wire::handshake::HandshakeMessage::decode_from_bytes(&message.data) {
-                            debug!("Received handshake message from {}", propagation_source);
-                            if let Err(e) = self.swarm.behaviour_mut().handshake.handle_message(propagation_source, &message.data) {
-                                warn!("Handshake message processing failed: {}", e);
-                            }
-                        } else {
-                            // Regular chat message (silent logging)
-                            let _ = self.message_tx.send((propagation_source, message.data));
+                        // Chat or control message (handshakes use their own protocol). Group
+                        // changes are ours to apply; MLS chat can't decode as one (it starts with a
+                        // zero byte, which is no protobuf tag)
+                        match Frame::unpad(&message.data) {
+                            Ok(data) => match umbra_wire::group::GroupControl::decode_from_bytes(&data) {
+                                Ok(umbra_wire::group::GroupControl {
+                                    message: Some(umbra_wire::group::group_control::Message::Change(change)),
+                                }) => {
+                                    if let Err(e) = self.on_group_change(&change) {
+                                        debug!("Dropped group change from {}: {}", propagation_source, e);
+                                    }
+                                }
+                                _ => {
+                                    let _ = self.message_tx.send((propagation_source, data));
+                                }
+                            },
+                            Err(e) => debug!("Dropped unframed message from {}: {}", propagation_source, e),
                         }
                     }
                     UmbraEvent::Handshake(event) => {
@@ -311,35 +1775,101 @@ impl P2PNode {
                             HandshakeEvent::Completed { peer_id, session_key, verify_key } => {
                                 info!("✅ Quantum-safe handshake completed with {}", peer_id);
                                 
-                                // Register peer's verify key for message signature verification
-                                self.message_exchange.session_manager_mut().register_peer(peer_id, verify_key);
-                                
-                                // Set the session key from handshake (replaces symmetric derivation)
-                                self.message_exchange.session_manager_mut().set_session_key(peer_id, session_key);
+                                // Register peer's Ed25519 + Dilithium3 keys for message signature verification
+                                self.message_exchange.session_manager_mut().register_peer_hybrid(peer_id, *verify_key);
+                                // File chunks are keyed from the handshake itself; the peer may have
+                                // chunks of what we're downloading
+                                if let Ok(file) = self.file() {
+                                    file.set_session_key(peer_id, session_key);
+                                    file.add_peer(peer_id);
+                                }
                                 
-                                info!("🔑 Registered quantum-resistant session key for {}", peer_id);
+                                // Seed the Double Ratchet from the handshake key,
+                                // unless a prekey exchange already set up this connection's session
+                                if self.prekey_peers.contains(&peer_id) {
+                                    debug!("Keeping prekey session with {}", peer_id);
+                                } else {
+                                    match self.message_exchange.session_manager_mut().set_session_key(peer_id, session_key) {
+                                        Ok(()) => info!("🔑 Registered quantum-resistant session key for {}", peer_id),
+                                        Err(e) => warn!("Failed to seed ratchet for {}: {}", peer_id, e),
+                                    }
+                                }
                             }
                             HandshakeEvent::Failed { peer_id, error } => {
                                 warn!("❌ Handshake with {} failed: {}", peer_id, error);
                             }
                         }
                     }
+                    UmbraEvent::Direct(DirectEvent::Received { peer_id, data, .. }) => {
+                        match self.decrypt_message(peer_id, &data) {
+                            Ok(msg) => self.on_direct_message(peer_id, msg),
+                            Err(crate::error::NetError::CoverTraffic) => {}
+                            Err(e) => warn!("Dropped direct message from {}: {}", peer_id, e),
+                        }
+                    }
+                    UmbraEvent::Direct(DirectEvent::PrekeyReceived { peer_id, message, .. }) => {
+                        match self.accept_prekey_message(peer_id, message) {
+                            Ok(messages) => {
+                                self.prekey_peers.insert(peer_id);
+                                for msg in messages {
+                                    self.on_direct_message(peer_id, msg);
+                                }
+                            }
+                            Err(e) => warn!("Rejected prekey message from {}: {}", peer_id, e),
+                        }
+                    }
+                    UmbraEvent::Direct(DirectEvent::Ack { peer_id, message_id, status }) => {
+                        if let DeliveryStatus::Failed(ref e) = status {
+                            warn!("Direct message {} to {} not delivered: {}", message_id, peer_id, e);
+                        }
+                        if let Some((peer, message)) = self.prekey_sends.remove(&message_id) {
+                            // Undelivered: retry on the next connection unless a newer session replaced it
+                            if status != DeliveryStatus::Delivered && !self.prekey_sessions.contains_key(&peer) {
+                                self.prekey_sessions.insert(peer, PendingSession::Ready(message));
+                                self.prekey_peers.remove(&peer);
+                            }
+                        } else if !self.control_sends.remove(&message_id) {
+                            self.report_delivery(peer_id, message_id, status);
+                        }
+                    }
+                    UmbraEvent::Mailbox(MailboxEvent::Deposited { mailbox, id }) => {
+                        let _ = self.mailbox_tx.send(MailboxUpdate::Deposited { mailbox, id });
+                    }
+                    UmbraEvent::Mailbox(MailboxEvent::Fetched { mailbox, messages, .. }) => {
+                        self.on_mailbox_batch(mailbox, messages);
+                    }
+                    UmbraEvent::Mailbox(MailboxEvent::Failed { mailbox, error }) => {
+                        warn!("Mailbox request to {} failed: {}", mailbox, error);
+                        self.mailbox_drains.remove(&mailbox);
+                        let _ = self.mailbox_tx.send(MailboxUpdate::Failed { mailbox, error });
+                    }
+                    UmbraEvent::File(event) => self.on_file_event(event),
+                    UmbraEvent::Circuit(event) => self.on_circuit_event(event),
                     _ => {}
                 }
             }
-            SwarmEvent::ConnectionEstablished { peer_id,  .. } => {
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
                 // Connected (silent)
                 
                 // Notify application
                 let _ = self.connection_tx.send(peer_id);
                 
-                // Initiate quantum-safe handshake
-                if let Err(e) = self.swarm.behaviour_mut().handshake.initiate_handshake(peer_id) {
-                    warn!("Failed to initiate handshake with {}: {}", peer_id, e);
+                // Peer is back: deliver what we queued for it through its prekey bundle
+                self.flush_prekey_messages(peer_id);
+                
+                // Initiate quantum-safe handshake (dialer only, so both sides don't initiate at once)
+                if endpoint.is_dialer() && num_established.get() == 1 {
+                    if let Err(e) = self.swarm.behaviour_mut().handshake.initiate_handshake(peer_id) {
+                        warn!("Failed to initiate handshake with {}: {}", peer_id, e);
+                    }
                 }
             }
-            SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
                 debug!("Connection to {} closed: {:?}", peer_id, cause);
+                
+                if num_established == 0 {
+                    self.prekey_peers.remove(&peer_id);
+                }
             }
             SwarmEvent::IncomingConnection { .. } => {
                 debug!("Incoming connection");
@@ -376,21 +1906,50 @@ impl P2PNode {
     pub fn get_session_key(&self, peer_id: &PeerId) -> Option<&[u8; 32]> {
         self.swarm.behaviour().handshake.get_session_key(peer_id)
     }
+
+    /// Whether `peer` can read what we send it: a finished handshake, or a prekey session
+    /// whose setup it has been sent
+    pub fn has_session(&self, peer: &PeerId) -> bool {
+        let ready = self.get_session_key(peer).is_some() || self.prekey_peers.contains(peer);
+        ready && self.message_exchange.session_manager().has_session(peer)
+    }
     
     /// Initiate handshake with a peer (for manual testing)
     pub fn initiate_handshake(&mut self, peer_id: PeerId) -> Result<(), String> {
         self.swarm.behaviour_mut().handshake.initiate_handshake(peer_id)
     }
+
+    /// Reject classical-only peers in handshakes and message verification
+    pub fn set_pq_policy(&mut self, policy: umbra_crypto::identity::PqPolicy) {
+        self.swarm.behaviour_mut().handshake.set_pq_policy(policy);
+        self.message_exchange.session_manager_mut().set_pq_policy(policy);
+    }
+}
+
+/// Next cover traffic tick, or never when cover traffic is off
+async fn cover_tick(cover: Option<&mut CoverTraffic>) {
+    match cover {
+        Some(cover) => cover.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
     
     #[tokio::test]
     async fn test_node_creation() {
         let node = P2PNode::new().await.unwrap();
-        assert!(node.local_peer_id().to_base58().len() > 0);
+        assert!(!node.local_peer_id().to_base58().is_empty());
     }
     
     #[tokio::test]
@@ -406,6 +1965,17 @@ mod tests {
         let result = node.subscribe("test-topic");
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_send_direct_without_session_fails() {
+        let mut node = P2PNode::new().await.unwrap();
+        let peer = PeerId::random();
+
+        assert!(!node.has_session(&peer));
+        let result = node.send_direct(peer, "alice", "hi");
+        assert!(matches!(result, Err(crate::error::NetError::PeerNotFound(_))));
+        assert_eq!(node.traffic_stats().real_sent, 0);
+    }
 }
 
 
@@ -0,0 +1,93 @@
+// Onion routing: Alice's direct messages to Bob go over a 3-hop circuit through relays
+// she finds in the DHT, and reach Bob sealed to his onion key
+
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{DeliveryStatus, P2PNode};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_direct_messages_over_circuit() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let mut nodes = Vec::new();
+    for port in 19051..=19053 {
+        nodes.push(P2PNode::new_with_port(port).await.unwrap());
+    }
+    let mut alice = P2PNode::new_with_port(19054).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19055).await.unwrap();
+    let mut alice_delivery = alice.take_delivery_receiver().unwrap();
+    let mut bob_rx = bob.take_direct_message_receiver().unwrap();
+    nodes.push(alice);
+    nodes.push(bob);
+    const ALICE: usize = 3;
+    const BOB: usize = 4;
+
+    let mut nodes = run_for(nodes, Duration::from_millis(500)).await;
+    let addrs: Vec<_> = nodes.iter().map(|n| n.listening_addresses()[0].clone()).collect();
+    let relays: HashSet<_> = nodes[..3].iter().map(|n| *n.local_peer_id()).collect();
+    let alice_peer_id = *nodes[ALICE].local_peer_id();
+    let bob_peer_id = *nodes[BOB].local_peer_id();
+
+    // Relays know each other; Alice and Bob know the relays and have met (handshake)
+    nodes[0].dial(addrs[1].clone()).unwrap();
+    nodes[0].dial(addrs[2].clone()).unwrap();
+    nodes[1].dial(addrs[2].clone()).unwrap();
+    for relay_addr in &addrs[..3] {
+        nodes[ALICE].dial(relay_addr.clone()).unwrap();
+        nodes[BOB].dial(relay_addr.clone()).unwrap();
+    }
+    nodes[ALICE].dial(addrs[BOB].clone()).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    for relay in &mut nodes[..3] {
+        relay.enable_relay().unwrap();
+    }
+    nodes[BOB].publish_onion_key().unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    nodes[ALICE].set_circuit_routing(true);
+    let first = nodes[ALICE].send_direct(bob_peer_id, "alice", "through the onion").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(8)).await;
+
+    let (from, msg) = bob_rx.try_recv().expect("Bob should receive the message");
+    assert_eq!(from, alice_peer_id);
+    assert_eq!(msg.content, "through the onion");
+    assert_eq!(alice_delivery.try_recv().unwrap(), (bob_peer_id, first, DeliveryStatus::Delivered));
+
+    // Three distinct relays, none of them Bob
+    let path = nodes[ALICE].circuit_path().expect("circuit should be built");
+    assert_eq!(path.len(), 3);
+    assert_eq!(path.iter().copied().collect::<HashSet<_>>(), relays);
+    assert!(nodes[ALICE].entry_guards().contains(&path[0]));
+
+    // Later messages reuse the circuit
+    let second = nodes[ALICE].send_direct(bob_peer_id, "alice", "again").unwrap();
+    let nodes = run_for(nodes, Duration::from_secs(3)).await;
+    assert_eq!(bob_rx.try_recv().unwrap().1.content, "again");
+    assert_eq!(alice_delivery.try_recv().unwrap(), (bob_peer_id, second, DeliveryStatus::Delivered));
+    assert_eq!(nodes[ALICE].circuit_path(), Some(path));
+    assert_eq!(nodes[ALICE].entry_guards().len(), 3);
+}
@@ -0,0 +1,104 @@
+// Nodes built from a NodeConfig: bootstrap peers, IPv6 and TCP listeners, disabled behaviours,
+// keys kept in an encrypted key file
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{Behaviours, IdentitySource, NetError, NodeConfig, P2PNode};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+/// Alice listening on `listen`, Bob bootstrapping from her there; both connect on their own
+async fn bootstrap_pair(listen: &str, tcp: bool) -> Vec<P2PNode> {
+    let alice_key = libp2p::identity::Keypair::generate_ed25519();
+    let alice_id = alice_key.public().to_peer_id();
+
+    let alice = P2PNode::from_config(
+        NodeConfig {
+            listen_addrs: vec![listen.parse().unwrap()],
+            ..NodeConfig::default()
+        }
+        .with_tcp(tcp)
+        .with_identity(IdentitySource::Keypair(Box::new(alice_key))),
+    )
+    .await
+    .unwrap();
+    assert_eq!(*alice.local_peer_id(), alice_id);
+
+    let bootstrap = format!("{}/p2p/{}", listen, alice_id).parse().unwrap();
+    let bob = P2PNode::from_config(
+        NodeConfig::default()
+            .with_tcp(tcp)
+            .with_bootstrap_peer(bootstrap),
+    )
+    .await
+    .unwrap();
+
+    run_for(vec![alice, bob], Duration::from_secs(3)).await
+}
+
+#[tokio::test]
+async fn test_bootstrap_over_ipv6() {
+    let nodes = bootstrap_pair("/ip6/::1/udp/19101/quic-v1", false).await;
+    assert!(nodes[1].connected_peers().contains(nodes[0].local_peer_id()));
+}
+
+#[tokio::test]
+async fn test_tcp_transport() {
+    let nodes = bootstrap_pair("/ip4/127.0.0.1/tcp/19102", true).await;
+    assert!(nodes[1].connected_peers().contains(nodes[0].local_peer_id()));
+}
+
+#[tokio::test]
+async fn test_disabled_file_transfer() {
+    let config = NodeConfig::default().with_behaviours(Behaviours {
+        file: false,
+        ..Behaviours::default()
+    });
+    let mut node = P2PNode::from_config(config).await.unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let result = node.accept_file([0u8; 32], &dir.path().join("file.bin"));
+    assert!(matches!(result, Err(NetError::Disabled(_))));
+    assert!(!node.pause_file([0u8; 32]));
+}
+
+#[tokio::test]
+async fn test_tcp_listener_needs_tcp() {
+    let config = NodeConfig::default().with_listen_addr("/ip4/127.0.0.1/tcp/0".parse().unwrap());
+    assert!(matches!(P2PNode::from_config(config).await, Err(NetError::Config(_))));
+}
+
+#[tokio::test]
+async fn test_key_file_keeps_identity() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("node_keys.bin");
+
+    let first = P2PNode::from_config(NodeConfig::default().with_key_file(&path, "hunter2")).await.unwrap();
+    let (peer_id, identity, onion_key) = (*first.local_peer_id(), first.identity_key(), first.onion_key().clone());
+    drop(first);
+
+    let second = P2PNode::from_config(NodeConfig::default().with_key_file(&path, "hunter2")).await.unwrap();
+    assert_eq!(*second.local_peer_id(), peer_id);
+    assert_eq!(second.identity_key(), identity);
+    assert_eq!(*second.onion_key(), onion_key);
+
+    let wrong = P2PNode::from_config(NodeConfig::default().with_key_file(&path, "hunter3")).await;
+    assert!(matches!(wrong, Err(NetError::Crypto(_))));
+}
@@ -0,0 +1,92 @@
+// Cover traffic: Alice sends dummy group and direct messages on a schedule; Bob decrypts and
+// drops them without the app seeing anything, and both sides count them
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{CoverConfig, CoverSchedule, NetError, P2PNode};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_cover_traffic_dropped_by_receiver() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let mut alice = P2PNode::new_with_port(19071).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19072).await.unwrap();
+    let mut alice_delivery = alice.take_delivery_receiver().unwrap();
+    let mut bob_group_rx = bob.take_message_receiver().unwrap();
+    let mut bob_direct_rx = bob.take_direct_message_receiver().unwrap();
+    alice.join_group("room").unwrap();
+
+    let nodes = run_for(vec![alice, bob], Duration::from_millis(500)).await;
+    let bob_addr = nodes[1].listening_addresses()[0].clone();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    let mut nodes = nodes;
+    nodes[0].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(4)).await;
+
+    // Bob joins Alice's group by invite
+    nodes[0].invite_to_group("room", bob_peer_id).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+    nodes[1].accept_group_invite("room").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+
+    nodes[0].enable_cover_traffic(CoverConfig {
+        schedule: CoverSchedule::Constant { interval: Duration::from_millis(200) },
+        ..Default::default()
+    });
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    // Let the last ones land
+    nodes[0].disable_cover_traffic();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+
+    let sent = nodes[0].traffic_stats();
+    assert!(sent.cover_sent >= 5, "only {} cover messages sent", sent.cover_sent);
+    assert_eq!(sent.real_sent, 0);
+    assert_eq!(sent.overhead(), 1.0);
+
+    // Direct cover never reaches the app, nor do its delivery results
+    assert!(bob_direct_rx.try_recv().is_err());
+    assert!(alice_delivery.try_recv().is_err());
+
+    // Group cover only turns out to be cover once decrypted
+    let mut group_cover = 0;
+    while let Ok((_, data)) = bob_group_rx.try_recv() {
+        assert!(matches!(nodes[1].decrypt_group_message(&data), Err(NetError::CoverTraffic)));
+        group_cover += 1;
+    }
+    let received = nodes[1].traffic_stats();
+    assert!(received.cover_received > group_cover, "direct cover should arrive too");
+    assert_eq!(received.cover_received, sent.cover_sent);
+
+    // Real traffic still gets through
+    let message_id = nodes[0].send_direct(bob_peer_id, "alice", "real").unwrap();
+    let nodes = run_for(nodes, Duration::from_secs(1)).await;
+    assert_eq!(bob_direct_rx.try_recv().unwrap().1.content, "real");
+    assert_eq!(alice_delivery.try_recv().unwrap().1, message_id);
+
+    let stats = nodes[0].traffic_stats();
+    assert_eq!(stats.real_sent, 1);
+    assert!(stats.overhead() > 0.5 && stats.overhead() < 1.0);
+}
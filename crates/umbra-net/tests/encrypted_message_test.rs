@@ -1,34 +1,38 @@
 // End-to-end test for encrypted message exchange
 
-use umbra_net::MessageExchange;
+use umbra_net::{MessageExchange, NetError};
+use umbra_wire::message::EncryptedMessage;
 use libp2p::PeerId;
+use prost::Message;
 
 #[test]
 fn test_message_exchange_roundtrip() {
-    let shared_peer_id = PeerId::random(); // Both use same PeerID for symmetric key derivation
-    let mut alice = MessageExchange::new(shared_peer_id).unwrap();
-    let mut bob = MessageExchange::new(shared_peer_id).unwrap();
-    
-    let peer_id = PeerId::random();
+    // Both sides seeded from the same session key, as after a handshake
+    let alice_peer = PeerId::random();
+    let bob_peer = PeerId::random();
+    let mut alice = MessageExchange::new(alice_peer).unwrap();
+    let mut bob = MessageExchange::new(bob_peer).unwrap();
+    alice.session_manager_mut().set_session_key(bob_peer, [1u8; 32]).unwrap();
+    bob.session_manager_mut().set_session_key(alice_peer, [1u8; 32]).unwrap();
     
     // Register each other's public keys for signature verification
     let alice_pubkey = *alice.session_manager().public_key();
     let bob_pubkey = *bob.session_manager().public_key();
-    alice.session_manager_mut().register_peer(peer_id, bob_pubkey);
-    bob.session_manager_mut().register_peer(peer_id, alice_pubkey);
+    alice.session_manager_mut().register_peer(bob_peer, bob_pubkey);
+    bob.session_manager_mut().register_peer(alice_peer, alice_pubkey);
     
     // Alice encrypts a message
     let encrypted = alice.encrypt_message(
-        peer_id,
+        bob_peer,
         "alice",
         "Hello Bob!",
     ).unwrap();
     
     // Bob decrypts and verifies signature
-    let (username, content, _identity) = bob.decrypt_message(peer_id, &encrypted).unwrap();
+    let msg = bob.decrypt_message(alice_peer, &encrypted).unwrap();
     
-    assert_eq!(username, "alice");
-    assert_eq!(content, "Hello Bob!");
+    assert_eq!(msg.username, "alice");
+    assert_eq!(msg.content, "Hello Bob!");
 }
 
 #[test]
@@ -38,6 +42,8 @@ fn test_message_exchange_wrong_peer() {
     
     let alice_peer = PeerId::random();
     let eve_peer = PeerId::random();
+    alice.session_manager_mut().set_session_key(alice_peer, [2u8; 32]).unwrap();
+    eve.session_manager_mut().set_session_key(eve_peer, [3u8; 32]).unwrap();
     
     // Register keys
     let alice_pubkey = alice.session_manager().public_key();
@@ -59,6 +65,7 @@ fn test_message_exchange_wrong_peer() {
 fn test_message_exchange_multiple_messages() {
     let mut alice = MessageExchange::new(PeerId::random()).unwrap();
     let peer = PeerId::random();
+    alice.session_manager_mut().set_session_key(peer, [4u8; 32]).unwrap();
     
     // Send 5 messages
     for i in 0..5 {
@@ -75,18 +82,19 @@ fn test_message_exchange_multiple_messages() {
 
 #[test]
 fn test_signature_verification_success() {
-    let shared_peer_id = PeerId::random();
-    let mut alice = MessageExchange::new(shared_peer_id).unwrap();
-    let mut bob = MessageExchange::new(shared_peer_id).unwrap();
-    
     let alice_peer = PeerId::random();
+    let bob_peer = PeerId::random();
+    let mut alice = MessageExchange::new(alice_peer).unwrap();
+    let mut bob = MessageExchange::new(bob_peer).unwrap();
+    alice.session_manager_mut().set_session_key(bob_peer, [5u8; 32]).unwrap();
+    bob.session_manager_mut().set_session_key(alice_peer, [5u8; 32]).unwrap();
     
     // Bob registers Alice's public key
     let alice_pubkey = alice.session_manager().public_key();
     bob.session_manager_mut().register_peer(alice_peer, *alice_pubkey);
     
     // Alice sends message
-    let encrypted = alice.encrypt_message(alice_peer, "alice", "signed message").unwrap();
+    let encrypted = alice.encrypt_message(bob_peer, "alice", "signed message").unwrap();
     
     // Bob decrypts and verifies (should succeed)
     let result = bob.decrypt_message(alice_peer, &encrypted);
@@ -94,53 +102,60 @@ fn test_signature_verification_success() {
 }
 
 #[test]
-#[ignore = "Signature enforcement not yet implemented - dev mode uses mock keys"]
 fn test_signature_verification_fails_wrong_key() {
-    let shared_peer_id = PeerId::random();
-    let mut alice = MessageExchange::new(shared_peer_id).unwrap();
-    let mut bob = MessageExchange::new(shared_peer_id).unwrap();
-    let eve = MessageExchange::new(PeerId::random()).unwrap();
-    
     let alice_peer = PeerId::random();
+    let bob_peer = PeerId::random();
+    let mut alice = MessageExchange::new(alice_peer).unwrap();
+    let mut bob = MessageExchange::new(bob_peer).unwrap();
+    let eve = MessageExchange::new(PeerId::random()).unwrap();
+    alice.session_manager_mut().set_session_key(bob_peer, [6u8; 32]).unwrap();
+    bob.session_manager_mut().set_session_key(alice_peer, [6u8; 32]).unwrap();
     
     // Bob registers EVE's public key instead of Alice's (wrong key!)
     let eve_pubkey = eve.session_manager().public_key();
     bob.session_manager_mut().register_peer(alice_peer, *eve_pubkey);
     
     // Alice sends message
-    let encrypted = alice.encrypt_message(alice_peer, "alice", "signed message").unwrap();
+    let encrypted = alice.encrypt_message(bob_peer, "alice", "signed message").unwrap();
     
     // Bob tries to decrypt but signature verification should fail
     let result = bob.decrypt_message(alice_peer, &encrypted);
     assert!(result.is_err(), "Wrong public key should fail verification");
     
     // Check it's a signature error
-    let err_msg = result.unwrap_err().to_string();
+    let err = result.unwrap_err();
+    assert!(matches!(err, NetError::SignatureInvalid(_)), "Unexpected error: {}", err);
+    let err_msg = err.to_string();
     assert!(err_msg.contains("Signature") || err_msg.contains("signature"), 
             "Error should mention signature: {}", err_msg);
 }
 
 #[test]
 fn test_message_tampering_detected() {
-    let mut alice = MessageExchange::new(PeerId::random()).unwrap();
-    let mut bob = MessageExchange::new(PeerId::random()).unwrap();
-    
-    let peer_id = PeerId::random();
+    let alice_peer = PeerId::random();
+    let bob_peer = PeerId::random();
+    let mut alice = MessageExchange::new(alice_peer).unwrap();
+    let mut bob = MessageExchange::new(bob_peer).unwrap();
+    alice.session_manager_mut().set_session_key(bob_peer, [7u8; 32]).unwrap();
+    bob.session_manager_mut().set_session_key(alice_peer, [7u8; 32]).unwrap();
     
     // Register Alice's key with Bob
     let alice_pubkey = alice.session_manager().public_key();
-    bob.session_manager_mut().register_peer(peer_id, *alice_pubkey);
+    bob.session_manager_mut().register_peer(alice_peer, *alice_pubkey);
     
     // Alice sends message
-    let mut encrypted = alice.encrypt_message(peer_id, "alice", "original message").unwrap();
+    let encrypted = alice.encrypt_message(bob_peer, "alice", "original message").unwrap();
     
     // Eve tampers with the ciphertext (flip some bits in the middle)
-    let len = encrypted.len();
-    if len > 40 {
-        encrypted[len / 2] ^= 0xFF; // Flip bits in middle of message
-    }
+    let mut message = EncryptedMessage::decode(encrypted.as_slice()).unwrap();
+    let len = message.ciphertext.len();
+    message.ciphertext[len / 2] ^= 0xFF;
+    let tampered = message.encode_to_vec();
     
     // Bob tries to decrypt - should fail (either decryption or signature verification)
-    let result = bob.decrypt_message(peer_id, &encrypted);
+    let result = bob.decrypt_message(alice_peer, &tampered);
     assert!(result.is_err(), "Tampered message should fail");
+
+    // The untouched message still goes through
+    assert_eq!(bob.decrypt_message(alice_peer, &encrypted).unwrap().content, "original message");
 }
@@ -0,0 +1,187 @@
+// File transfer: an offered file or folder is pulled chunk by chunk, verified and written to disk
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{FileUpdate, P2PNode};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_offer_and_download_file() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("notes.bin");
+    // A few chunks, the last one partial
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+    std::fs::write(&source, &data).unwrap();
+
+    let mut alice = P2PNode::new_with_port(19091).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19092).await.unwrap();
+    let mut alice_files = alice.take_file_receiver().unwrap();
+    let mut bob_files = bob.take_file_receiver().unwrap();
+
+    let nodes = run_for(vec![alice, bob], Duration::from_millis(500)).await;
+    let bob_addr = nodes[1].listening_addresses()[0].clone();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    let alice_peer_id = *nodes[0].local_peer_id();
+    let mut nodes = nodes;
+    nodes[0].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(4)).await;
+
+    let file_id = nodes[0].offer_file(bob_peer_id, &source).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+    assert_eq!(
+        bob_files.try_recv().unwrap(),
+        FileUpdate::Offered { peer: alice_peer_id, file_id, name: "notes.bin".to_string(), size: data.len() as u64 }
+    );
+
+    let dest = dir.path().join("received.bin");
+    nodes[1].accept_file(file_id, &dest).unwrap();
+    let _nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    let mut completed = None;
+    while let Ok(update) = bob_files.try_recv() {
+        match update {
+            FileUpdate::Progress { total, .. } => assert_eq!(total, 3),
+            FileUpdate::Completed { file_id: id, path } => completed = Some((id, path)),
+            other => panic!("unexpected update {:?}", other),
+        }
+    }
+    assert_eq!(completed, Some((file_id, dest.clone())));
+    assert_eq!(std::fs::read(&dest).unwrap(), data);
+    assert!(alice_files.try_recv().is_err(), "the sender saw no failure");
+}
+
+#[tokio::test]
+async fn test_offer_needs_session() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("notes.bin");
+    std::fs::write(&source, b"hello").unwrap();
+
+    let mut alice = P2PNode::new_with_port(0).await.unwrap();
+    let stranger = libp2p::PeerId::random();
+    assert!(alice.offer_file(stranger, &source).is_err());
+}
+
+#[tokio::test]
+async fn test_group_members_swarm_file() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("video.bin");
+    let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 241) as u8).collect();
+    std::fs::write(&source, &data).unwrap();
+
+    let mut nodes = Vec::new();
+    let mut receivers = Vec::new();
+    for port in [19093, 19094, 19095] {
+        let mut node = P2PNode::new_with_port(port).await.unwrap();
+        receivers.push(node.take_file_receiver().unwrap());
+        nodes.push(node);
+    }
+    nodes[0].join_group("room").unwrap();
+
+    let mut nodes = run_for(nodes, Duration::from_millis(500)).await;
+    let alice_addr = nodes[0].listening_addresses()[0].clone();
+    let bob_addr = nodes[1].listening_addresses()[0].clone();
+    nodes[1].dial(alice_addr.clone()).unwrap();
+    nodes[2].dial(alice_addr).unwrap();
+    nodes[2].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(4)).await;
+
+    // Alice's group: the others join by invite, one at a time
+    for i in 1..3 {
+        let peer = *nodes[i].local_peer_id();
+        nodes[0].invite_to_group("room", peer).unwrap();
+        nodes = run_for(nodes, Duration::from_secs(1)).await;
+        nodes[i].accept_group_invite("room").unwrap();
+        nodes = run_for(nodes, Duration::from_secs(2)).await;
+    }
+
+    let file_id = nodes[0].offer_file_to_group("room", &source).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+    for node in [1, 2] {
+        assert!(matches!(receivers[node].try_recv().unwrap(), FileUpdate::Offered { file_id: id, .. } if id == file_id));
+        nodes[node].accept_file(file_id, &dir.path().join(format!("received-{}.bin", node))).unwrap();
+    }
+    let _nodes = run_for(nodes, Duration::from_secs(4)).await;
+
+    for node in [1, 2] {
+        let dest = dir.path().join(format!("received-{}.bin", node));
+        let completed = std::iter::from_fn(|| receivers[node].try_recv().ok())
+            .any(|update| update == FileUpdate::Completed { file_id, path: dest.clone() });
+        assert!(completed, "node {} didn't finish", node);
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+    }
+}
+
+#[tokio::test]
+async fn test_offer_and_download_folder() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("project");
+    std::fs::create_dir_all(source.join("src/empty")).unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 239) as u8).collect();
+    std::fs::write(source.join("README"), b"read me").unwrap();
+    std::fs::write(source.join("src/data.bin"), &data).unwrap();
+
+    let mut alice = P2PNode::new_with_port(19096).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19097).await.unwrap();
+    let _alice_files = alice.take_file_receiver().unwrap();
+    let mut bob_files = bob.take_file_receiver().unwrap();
+
+    let mut nodes = run_for(vec![alice, bob], Duration::from_millis(500)).await;
+    let bob_addr = nodes[1].listening_addresses()[0].clone();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    nodes[0].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(4)).await;
+
+    let folder_id = nodes[0].offer_folder(bob_peer_id, &source).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+    match bob_files.try_recv().unwrap() {
+        FileUpdate::FolderOffered { folder_id: id, name, files, size, .. } => {
+            assert_eq!((id, name.as_str(), files, size), (folder_id, "project", 2, data.len() as u64 + 7));
+        }
+        other => panic!("unexpected update {:?}", other),
+    }
+
+    let into = dir.path().join("downloads");
+    std::fs::create_dir(&into).unwrap();
+    let root = nodes[1].accept_folder(folder_id, &into).unwrap();
+    let _nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    let completed = std::iter::from_fn(|| bob_files.try_recv().ok())
+        .any(|update| update == FileUpdate::Completed { file_id: folder_id, path: root.clone() });
+    assert!(completed, "folder didn't arrive");
+    assert_eq!(std::fs::read(root.join("README")).unwrap(), b"read me");
+    assert_eq!(std::fs::read(root.join("src/data.bin")).unwrap(), data);
+    assert!(root.join("src/empty").is_dir());
+}
@@ -0,0 +1,80 @@
+// MLS group membership between two nodes: Alice invites Bob, Bob accepts and joins through
+// the Welcome, then a change Alice publishes on the topic reaches Bob's copy of the group
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{GroupUpdate, P2PNode};
+use umbra_wire::group::{group_change, GroupMetadata};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_invite_join_and_change() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let mut alice = P2PNode::new_with_port(19111).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19112).await.unwrap();
+    let mut alice_updates = alice.take_group_receiver().unwrap();
+    let mut bob_updates = bob.take_group_receiver().unwrap();
+
+    let mut nodes = run_for(vec![alice, bob], Duration::from_millis(500)).await;
+    let bob_addr = nodes[1].listening_addresses()[0].clone();
+    let alice_peer_id = *nodes[0].local_peer_id();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    nodes[0].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+
+    nodes[0].join_group("room").unwrap();
+    nodes[0].invite_to_group("room", bob_peer_id).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+
+    assert_eq!(
+        bob_updates.try_recv().unwrap(),
+        GroupUpdate::Invited { topic: "room".to_string(), inviter: alice_peer_id, metadata: GroupMetadata::default() }
+    );
+    nodes[1].accept_group_invite("room").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    assert_eq!(bob_updates.try_recv().unwrap(), GroupUpdate::Joined { topic: "room".to_string() });
+    assert!(matches!(
+        alice_updates.try_recv().unwrap(),
+        GroupUpdate::Changed { by, change: group_change::Change::Add(_), .. } if by == alice_peer_id
+    ));
+    assert_eq!(nodes[1].group("room").unwrap().member_count(), 2);
+
+    // Published on the topic, applied by Bob
+    let metadata = GroupMetadata { name: "umbra".to_string(), topic: "wire formats".to_string() };
+    nodes[0].set_group_metadata("room", metadata.clone()).unwrap();
+    let nodes = run_for(nodes, Duration::from_secs(2)).await;
+
+    assert_eq!(
+        bob_updates.try_recv().unwrap(),
+        GroupUpdate::Changed {
+            topic: "room".to_string(),
+            by: alice_peer_id,
+            change: group_change::Change::Metadata(metadata.clone()),
+        }
+    );
+    assert_eq!(nodes[1].group("room").unwrap().metadata().name, "umbra");
+    assert_eq!(nodes[1].group("room").unwrap().epoch(), nodes[0].group("room").unwrap().epoch());
+}
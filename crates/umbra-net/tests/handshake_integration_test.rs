@@ -1,7 +1,7 @@
 // Integration test: Verify Alice→Bob handshake and message encryption/decryption
 // This ensures the handshake produces matching keys and messages can be exchanged
 
-use umbra_net::MessageExchange;
+use umbra_net::{MessageExchange, VerificationStatus};
 use libp2p::PeerId;
 use umbra_crypto::identity::IdentityKey;
 use umbra_crypto::handshake::Handshake;
@@ -12,15 +12,19 @@ fn gen_identity() -> IdentityKey {
 
 #[test]
 fn test_complete_handshake_flow() {
-    // Setup: Create identities for Alice and Bob
-    let alice_id = gen_identity();
-    let alice_pk = *alice_id.verifying_key();
-    let bob_id = gen_identity();
-    let bob_pk = *bob_id.verifying_key();
-    
     let alice_peer = PeerId::random();
     let bob_peer = PeerId::random();
     
+    // Create message exchanges for both peers
+    let mut alice_exchange = MessageExchange::new(alice_peer).unwrap();
+    let mut bob_exchange = MessageExchange::new(bob_peer).unwrap();
+    
+    // Setup: the handshake signs with the same identities as messages
+    let alice_id = alice_exchange.session_manager().identity().clone();
+    let alice_pk = alice_id.hybrid_verifying_key();
+    let bob_id = bob_exchange.session_manager().identity().clone();
+    let bob_pk = bob_id.hybrid_verifying_key();
+    
     // Phase 1: Handshake
     println!("Phase 1: Performing handshake...");
     
@@ -31,11 +35,12 @@ fn test_complete_handshake_flow() {
     
     // Bob responds
     let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-    let (resp, bob_key) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+    let (resp, bob_keys) = bob_hs.respond(bob_peer, alice_peer, &init, &alice_pk).unwrap();
+    let bob_key = bob_keys.session_key();
     println!("  ✓ Bob responded and derived key: {:?}", &bob_key[..8]);
     
     // Alice completes
-    let alice_key = alice_hs.complete(&resp, &bob_pk).unwrap();
+    let alice_key = alice_hs.complete(bob_peer, &init, &resp, &bob_pk).unwrap().session_key();
     println!("  ✓ Alice completed and derived key: {:?}", &alice_key[..8]);
     
     // CRITICAL: Verify both derived the same session key
@@ -45,17 +50,13 @@ fn test_complete_handshake_flow() {
     // Phase 2: Message Exchange
     println!("\nPhase 2: Testing message encryption/decryption...");
     
-    // Create message exchanges for both peers
-    let mut alice_exchange = MessageExchange::new(alice_peer).unwrap();
-    let mut bob_exchange = MessageExchange::new(bob_peer).unwrap();
-    
-    // Register peer keys for signature verification
-    alice_exchange.session_manager_mut().register_peer(bob_peer, bob_pk);
-    bob_exchange.session_manager_mut().register_peer(alice_peer, alice_pk);
+    // Register peer keys learned in the handshake for signature verification
+    alice_exchange.session_manager_mut().register_peer_hybrid(bob_peer, bob_pk);
+    bob_exchange.session_manager_mut().register_peer_hybrid(alice_peer, alice_pk);
     
     // Set the handshake-derived session keys
-    alice_exchange.session_manager_mut().set_session_key(bob_peer, alice_key);
-    bob_exchange.session_manager_mut().set_session_key(alice_peer, bob_key);
+    alice_exchange.session_manager_mut().set_session_key(bob_peer, alice_key).unwrap();
+    bob_exchange.session_manager_mut().set_session_key(alice_peer, bob_key).unwrap();
     println!("  ✓ Session keys installed");
     
     // Alice sends a message to Bob
@@ -68,10 +69,11 @@ fn test_complete_handshake_flow() {
     println!("  ✓ Alice encrypted message ({} bytes)", encrypted.len());
     
     // Bob decrypts Alice's message
-    let (username, decrypted_content, _identity) = bob_exchange.decrypt_message(
+    let msg = bob_exchange.decrypt_message(
         alice_peer,
         &encrypted,
     ).unwrap();
+    let (username, decrypted_content) = (msg.username, msg.content);
     println!("  ✓ Bob decrypted message from {}: '{}'", username, decrypted_content);
     
     // Verify message integrity
@@ -92,10 +94,12 @@ fn test_complete_handshake_flow() {
     println!("  ✓ Bob encrypted reply");
     
     // Alice decrypts Bob's reply
-    let (username2, decrypted_reply, _) = alice_exchange.decrypt_message(
+    let reply_msg = alice_exchange.decrypt_message(
         bob_peer,
         &encrypted_reply,
     ).unwrap();
+    assert_eq!(reply_msg.verification, VerificationStatus::Verified);
+    let (username2, decrypted_reply) = (reply_msg.username, reply_msg.content);
     println!("  ✓ Alice decrypted reply from {}: '{}'", username2, decrypted_reply);
     
     assert_eq!(username2, "bob");
@@ -113,9 +117,9 @@ fn test_complete_handshake_flow() {
 fn test_handshake_wrong_keys_fail() {
     // This test ensures that using different handshake instances fails
     let alice_id = gen_identity();
-    let alice_pk = *alice_id.verifying_key();
+    let alice_pk = alice_id.hybrid_verifying_key();
     let bob_id = gen_identity();
-    let bob_pk = *bob_id.verifying_key();
+    let bob_pk = bob_id.hybrid_verifying_key();
     
     let alice_peer = PeerId::random();
     let bob_peer = PeerId::random();
@@ -126,15 +130,15 @@ fn test_handshake_wrong_keys_fail() {
     
     // Bob responds
     let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-    let (resp, bob_key) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+    let (resp, _bob_keys) = bob_hs.respond(bob_peer, alice_peer, &init, &alice_pk).unwrap();
     
     // Alice tries to complete with DIFFERENT handshake instance (WRONG!)
     let alice_hs2 = Handshake::new(gen_identity()).unwrap();
-    let alice_key2 = alice_hs2.complete(&resp, &bob_pk).unwrap();
+    let result = alice_hs2.complete(bob_peer, &init, &resp, &bob_pk);
     
-    // Keys should NOT match (this is the bug we're fixing)
-    assert_ne!(alice_key2, bob_key, 
-        "Using different handshake instances MUST result in different keys!");
+    // Must fail: the other instance never owned init's ephemeral keys
+    assert!(result.is_err(), 
+        "Using different handshake instances MUST NOT produce a session!");
     
     println!("✅ Test confirmed: Different handshake instances = different keys");
 }
@@ -146,6 +150,8 @@ fn test_message_fails_with_wrong_key() {
     
     let mut alice_exchange = MessageExchange::new(alice_peer).unwrap();
     let mut eve_exchange = MessageExchange::new(PeerId::random()).unwrap();
+    alice_exchange.session_manager_mut().set_session_key(bob_peer, [1u8; 32]).unwrap();
+    eve_exchange.session_manager_mut().set_session_key(alice_peer, [2u8; 32]).unwrap();
     
     // Alice encrypts with one session key
     let encrypted = alice_exchange.encrypt_message(
@@ -87,7 +87,7 @@ async fn test_node_creation_and_listening() {
     let node = node_task.await.unwrap();
     
     // Verify peer ID is valid
-    assert!(node.local_peer_id().to_base58().len() > 0);
+    assert!(!node.local_peer_id().to_base58().is_empty());
     
     // Verify listening addresses exist
     let addrs = node.listening_addresses();
@@ -97,3 +97,169 @@ async fn test_node_creation_and_listening() {
 }
 
 
+
+#[tokio::test]
+async fn test_handshake_without_topic() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+    
+    // Neither node subscribes to anything: handshakes must not depend on gossipsub
+    let mut node1 = P2PNode::new_with_port(19011).await.unwrap();
+    let mut node2 = P2PNode::new_with_port(19012).await.unwrap();
+    
+    let node1_task = tokio::spawn(async move {
+        timeout(Duration::from_millis(500), node1.run()).await.ok();
+        node1
+    });
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let mut node1 = node1_task.await.unwrap();
+    
+    let node1_peer_id = *node1.local_peer_id();
+    let node2_peer_id = *node2.local_peer_id();
+    node2.dial(node1.listening_addresses()[0].clone()).unwrap();
+    
+    let node1_task = tokio::spawn(async move {
+        timeout(Duration::from_secs(5), node1.run()).await.ok();
+        node1
+    });
+    let node2_task = tokio::spawn(async move {
+        timeout(Duration::from_secs(5), node2.run()).await.ok();
+        node2
+    });
+    let (node1, node2) = tokio::join!(node1_task, node2_task);
+    let (node1, node2) = (node1.unwrap(), node2.unwrap());
+    
+    let key1 = node1.get_session_key(&node2_peer_id).expect("node1 should have a session");
+    let key2 = node2.get_session_key(&node1_peer_id).expect("node2 should have a session");
+    assert_eq!(key1, key2);
+}
+
+#[tokio::test]
+async fn test_direct_message_delivered_and_acked() {
+    use umbra_net::DeliveryStatus;
+    
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+    
+    let mut node1 = P2PNode::new_with_port(19021).await.unwrap();
+    let mut node2 = P2PNode::new_with_port(19022).await.unwrap();
+    let mut dm_rx = node1.take_direct_message_receiver().unwrap();
+    let mut delivery_rx = node2.take_delivery_receiver().unwrap();
+    
+    let node1_task = tokio::spawn(async move {
+        timeout(Duration::from_millis(500), node1.run()).await.ok();
+        node1
+    });
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let mut node1 = node1_task.await.unwrap();
+    
+    let node1_peer_id = *node1.local_peer_id();
+    let node2_peer_id = *node2.local_peer_id();
+    node2.dial(node1.listening_addresses()[0].clone()).unwrap();
+    
+    // Let the handshake finish
+    let node1_task = tokio::spawn(async move {
+        timeout(Duration::from_secs(5), node1.run()).await.ok();
+        node1
+    });
+    let node2_task = tokio::spawn(async move {
+        timeout(Duration::from_secs(5), node2.run()).await.ok();
+        node2
+    });
+    let (node1, node2) = tokio::join!(node1_task, node2_task);
+    let (mut node1, mut node2) = (node1.unwrap(), node2.unwrap());
+    
+    let message_id = node2.send_direct(node1_peer_id, "bob", "just for you").unwrap();
+    
+    let node1_task = tokio::spawn(async move {
+        timeout(Duration::from_secs(3), node1.run()).await.ok();
+        node1
+    });
+    let node2_task = tokio::spawn(async move {
+        timeout(Duration::from_secs(3), node2.run()).await.ok();
+        node2
+    });
+    let _ = tokio::join!(node1_task, node2_task);
+    
+    let (from, msg) = dm_rx.try_recv().expect("node1 should receive the DM");
+    assert_eq!(from, node2_peer_id);
+    assert_eq!(msg.content, "just for you");
+    assert!(msg.verification.is_verified());
+    
+    let (to, acked_id, status) = delivery_rx.try_recv().expect("node2 should get an ack");
+    assert_eq!(to, node1_peer_id);
+    assert_eq!(acked_id, message_id);
+    assert_eq!(status, DeliveryStatus::Delivered);
+}
+
+/// Run every node concurrently for `secs`, then hand them back
+async fn run_all(nodes: Vec<P2PNode>, secs: u64) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes.into_iter().map(|mut node| {
+        tokio::spawn(async move {
+            timeout(Duration::from_secs(secs), node.run()).await.ok();
+            node
+        })
+    }).collect();
+    
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_group_message_reaches_every_member() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+    
+    let mut nodes = Vec::new();
+    let mut receivers = Vec::new();
+    for port in [19031, 19032, 19033] {
+        let mut node = P2PNode::new_with_port(port).await.unwrap();
+        receivers.push(node.take_message_receiver().unwrap());
+        nodes.push(node);
+    }
+    nodes[0].join_group("room").unwrap();
+    let mut nodes = run_all(nodes, 1).await;
+    
+    // Full mesh: invites and join requests travel over pairwise sessions
+    let addrs: Vec<_> = nodes.iter().map(|n| n.listening_addresses()[0].clone()).collect();
+    nodes[1].dial(addrs[0].clone()).unwrap();
+    nodes[2].dial(addrs[0].clone()).unwrap();
+    nodes[2].dial(addrs[1].clone()).unwrap();
+    let mut nodes = run_all(nodes, 6).await;
+    
+    // node0's group: the others join by invite, one at a time
+    for i in 1..3 {
+        let peer = *nodes[i].local_peer_id();
+        nodes[0].invite_to_group("room", peer).unwrap();
+        nodes = run_all(nodes, 1).await;
+        nodes[i].accept_group_invite("room").unwrap();
+        nodes = run_all(nodes, 2).await;
+    }
+    
+    for (i, node) in nodes.iter_mut().enumerate() {
+        node.send_group_message("room", &format!("user{}", i), &format!("hello from {}", i)).unwrap();
+    }
+    let mut nodes = run_all(nodes, 3).await;
+    
+    for (i, rx) in receivers.iter_mut().enumerate() {
+        let mut received = Vec::new();
+        while let Ok((_, data)) = rx.try_recv() {
+            if let Ok(msg) = nodes[i].decrypt_group_message(&data) {
+                received.push(msg.content);
+            }
+        }
+        received.sort();
+        
+        let expected: Vec<String> = (0..3).filter(|j| *j != i).map(|j| format!("hello from {}", j)).collect();
+        assert_eq!(received, expected, "node{} should read every other member", i);
+    }
+}
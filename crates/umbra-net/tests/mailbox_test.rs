@@ -0,0 +1,144 @@
+// Store-and-forward: Alice leaves messages at a mailbox node while Bob is offline,
+// and Bob collects (and clears) them when he's back
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{MailboxConfig, MailboxUpdate, P2PNode};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_mailbox_deposit_and_fetch() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut mailbox = P2PNode::new_with_port(19041).await.unwrap();
+    mailbox
+        .enable_mailbox(MailboxConfig {
+            path: Some(dir.path().join("mailbox.bin")),
+            ..Default::default()
+        })
+        .unwrap();
+    let mut alice = P2PNode::new_with_port(19042).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19043).await.unwrap();
+    let mut alice_updates = alice.take_mailbox_receiver().unwrap();
+    let mut bob_updates = bob.take_mailbox_receiver().unwrap();
+    let mut bob_rx = bob.take_direct_message_receiver().unwrap();
+
+    let mut nodes = run_for(vec![mailbox, alice, bob], Duration::from_millis(500)).await;
+    let mailbox_addr = nodes[0].listening_addresses()[0].clone();
+    let bob_addr = nodes[2].listening_addresses()[0].clone();
+    let mailbox_peer_id = *nodes[0].local_peer_id();
+    let bob_peer_id = *nodes[2].local_peer_id();
+
+    // Alice and Bob have met before (handshake), and both know the mailbox
+    nodes[1].dial(bob_addr).unwrap();
+    nodes[1].dial(mailbox_addr.clone()).unwrap();
+    nodes[2].dial(mailbox_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    // Bob goes offline; Alice leaves two messages
+    let bob = nodes.remove(2);
+    nodes[1].deposit_to_mailbox(mailbox_peer_id, bob_peer_id, "alice", "are you there?").unwrap();
+    nodes[1].deposit_to_mailbox(mailbox_peer_id, bob_peer_id, "alice", "call me back").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    for _ in 0..2 {
+        assert!(matches!(alice_updates.try_recv(), Ok(MailboxUpdate::Deposited { .. })));
+    }
+    assert!(bob_rx.try_recv().is_err());
+
+    // Bob is back and drains his mailbox
+    nodes.push(bob);
+    nodes[2].fetch_mailbox(mailbox_peer_id).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    assert_eq!(bob_rx.try_recv().unwrap().1.content, "are you there?");
+    assert_eq!(bob_rx.try_recv().unwrap().1.content, "call me back");
+    assert_eq!(
+        bob_updates.try_recv().unwrap(),
+        MailboxUpdate::Drained { mailbox: mailbox_peer_id, delivered: 2 }
+    );
+
+    // Fetched messages were deleted
+    nodes[2].fetch_mailbox(mailbox_peer_id).unwrap();
+    let _ = run_for(nodes, Duration::from_secs(2)).await;
+    assert_eq!(
+        bob_updates.try_recv().unwrap(),
+        MailboxUpdate::Drained { mailbox: mailbox_peer_id, delivered: 0 }
+    );
+    assert!(bob_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_mailbox_prekey_session_with_both_offline() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let mut mailbox = P2PNode::new_with_port(19044).await.unwrap();
+    mailbox.enable_mailbox(MailboxConfig::default()).unwrap();
+    let mut bob = P2PNode::new_with_port(19045).await.unwrap();
+    let mut alice = P2PNode::new_with_port(19046).await.unwrap();
+    let mut alice_updates = alice.take_mailbox_receiver().unwrap();
+    let mut bob_updates = bob.take_mailbox_receiver().unwrap();
+    let mut bob_rx = bob.take_direct_message_receiver().unwrap();
+
+    let mut nodes = run_for(vec![mailbox, bob, alice], Duration::from_millis(500)).await;
+    let mailbox_addr = nodes[0].listening_addresses()[0].clone();
+    let mailbox_peer_id = *nodes[0].local_peer_id();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    let alice_peer_id = *nodes[2].local_peer_id();
+
+    // Bob publishes his prekey bundle through the mailbox node, then goes offline
+    nodes[1].dial(mailbox_addr.clone()).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    nodes[1].publish_prekeys().unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    let bob = nodes.remove(1);
+
+    // Alice has never met Bob: the session setup goes into the mailbox with her messages
+    nodes[1].dial(mailbox_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    nodes[1].deposit_to_mailbox(mailbox_peer_id, bob_peer_id, "alice", "hello from the past").unwrap();
+    nodes[1].deposit_to_mailbox(mailbox_peer_id, bob_peer_id, "alice", "still here?").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+    assert!(matches!(alice_updates.try_recv(), Ok(MailboxUpdate::Deposited { .. })));
+
+    // Alice goes offline for good before Bob comes back
+    drop(nodes.remove(1));
+    nodes.insert(1, bob);
+    nodes[1].fetch_mailbox(mailbox_peer_id).unwrap();
+    let _ = run_for(nodes, Duration::from_secs(3)).await;
+
+    let (from, first) = bob_rx.try_recv().expect("Bob should receive the first message");
+    assert_eq!(from, alice_peer_id);
+    assert_eq!(first.content, "hello from the past");
+    let (from, second) = bob_rx.try_recv().unwrap();
+    assert_eq!((from, second.content.as_str()), (alice_peer_id, "still here?"));
+    assert!(second.verification.is_verified());
+    assert_eq!(
+        bob_updates.try_recv().unwrap(),
+        MailboxUpdate::Drained { mailbox: mailbox_peer_id, delivered: 2 }
+    );
+}
@@ -0,0 +1,83 @@
+// Mix routing: Alice's direct messages to Bob go as Sphinx packets through three mixes she
+// finds in the DHT, each holding them for a random delay
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::P2PNode;
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_direct_messages_through_mixes() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let mut nodes = Vec::new();
+    for port in 19061..=19063 {
+        nodes.push(P2PNode::new_with_port(port).await.unwrap());
+    }
+    let mut alice = P2PNode::new_with_port(19064).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19065).await.unwrap();
+    let mut alice_delivery = alice.take_delivery_receiver().unwrap();
+    let mut bob_rx = bob.take_direct_message_receiver().unwrap();
+    nodes.push(alice);
+    nodes.push(bob);
+    const ALICE: usize = 3;
+    const BOB: usize = 4;
+
+    let mut nodes = run_for(nodes, Duration::from_millis(500)).await;
+    let addrs: Vec<_> = nodes.iter().map(|n| n.listening_addresses()[0].clone()).collect();
+    let alice_peer_id = *nodes[ALICE].local_peer_id();
+    let bob_peer_id = *nodes[BOB].local_peer_id();
+
+    // Mixes know each other; Alice and Bob know the mixes and have met (handshake)
+    nodes[0].dial(addrs[1].clone()).unwrap();
+    nodes[0].dial(addrs[2].clone()).unwrap();
+    nodes[1].dial(addrs[2].clone()).unwrap();
+    for mix_addr in &addrs[..3] {
+        nodes[ALICE].dial(mix_addr.clone()).unwrap();
+        nodes[BOB].dial(mix_addr.clone()).unwrap();
+    }
+    nodes[ALICE].dial(addrs[BOB].clone()).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    for mix in &mut nodes[..3] {
+        mix.enable_mix().unwrap();
+    }
+    nodes[BOB].publish_onion_key().unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+
+    nodes[ALICE].set_mix_routing(true);
+    nodes[ALICE].send_direct(bob_peer_id, "alice", "through the mixes").unwrap();
+    nodes[ALICE].send_direct(bob_peer_id, "alice", "and again").unwrap();
+    let _nodes = run_for(nodes, Duration::from_secs(10)).await;
+
+    let mut received = Vec::new();
+    while let Ok((from, msg)) = bob_rx.try_recv() {
+        assert_eq!(from, alice_peer_id);
+        received.push(msg.content);
+    }
+    // The mixes' random delays may reorder them
+    received.sort();
+    assert_eq!(received, ["and again", "through the mixes"]);
+    assert!(alice_delivery.try_recv().is_err(), "no mix refused a packet");
+}
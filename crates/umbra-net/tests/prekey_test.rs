@@ -0,0 +1,81 @@
+// Asynchronous session setup: Alice messages Bob from his DHT prekey bundle before they
+// have ever been connected, and Bob picks the session up when they meet
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{DeliveryStatus, P2PNode};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_first_message_from_prekey_bundle() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let relay = P2PNode::new_with_port(19031).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19032).await.unwrap();
+    let mut alice = P2PNode::new_with_port(19033).await.unwrap();
+    let mut bob_rx = bob.take_direct_message_receiver().unwrap();
+    let mut alice_rx = alice.take_direct_message_receiver().unwrap();
+    let mut bob_delivery = bob.take_delivery_receiver().unwrap();
+
+    let mut nodes = run_for(vec![relay, bob, alice], Duration::from_millis(500)).await;
+    let relay_addr = nodes[0].listening_addresses()[0].clone();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    let alice_peer_id = *nodes[2].local_peer_id();
+
+    // Bob publishes his bundle through the relay
+    nodes[1].dial(relay_addr.clone()).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    nodes[1].publish_prekeys().unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+
+    // Bob goes offline (his event loop stops); Alice only knows the relay
+    let bob = nodes.remove(1);
+    let bob_addr = bob.listening_addresses()[0].clone();
+    nodes[1].dial(relay_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    nodes[1].send_offline(bob_peer_id, "alice", "hello while you were away").unwrap();
+    nodes[1].send_offline(bob_peer_id, "alice", "second message").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(3)).await;
+    assert!(bob_rx.try_recv().is_err());
+
+    // Bob is back: the queued messages set up his side of the session
+    nodes.insert(1, bob);
+    nodes[2].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(5)).await;
+
+    let (from, first) = bob_rx.try_recv().expect("Bob should receive the first message");
+    assert_eq!(from, alice_peer_id);
+    assert_eq!(first.username, "alice");
+    assert_eq!(first.content, "hello while you were away");
+    assert_eq!(bob_rx.try_recv().unwrap().1.content, "second message");
+
+    // The live handshake didn't pull the session out from under them: Bob can reply
+    let reply_id = nodes[1].send_direct(alice_peer_id, "bob", "got it").unwrap();
+    let _ = run_for(nodes, Duration::from_secs(3)).await;
+
+    let (from, reply) = alice_rx.try_recv().expect("Alice should receive the reply");
+    assert_eq!(from, bob_peer_id);
+    assert_eq!(reply.content, "got it");
+    assert_eq!(bob_delivery.try_recv().unwrap(), (alice_peer_id, reply_id, DeliveryStatus::Delivered));
+}
@@ -0,0 +1,72 @@
+// Send jitter and delayed acks: publishes and direct messages wait out their jitter before
+// going on the wire, and direct message acks are held back but still arrive
+
+use std::time::Duration;
+use tokio::time::timeout;
+use umbra_net::{DeliveryStatus, P2PNode, TimingJitter};
+
+/// Drive every node's event loop for `duration`
+async fn run_for(nodes: Vec<P2PNode>, duration: Duration) -> Vec<P2PNode> {
+    let tasks: Vec<_> = nodes
+        .into_iter()
+        .map(|mut node| {
+            tokio::spawn(async move {
+                timeout(duration, node.run()).await.ok();
+                node
+            })
+        })
+        .collect();
+
+    let mut nodes = Vec::new();
+    for task in tasks {
+        nodes.push(task.await.unwrap());
+    }
+    nodes
+}
+
+#[tokio::test]
+async fn test_jittered_publish_and_delayed_ack() {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init()
+        .ok();
+
+    let mut alice = P2PNode::new_with_port(19081).await.unwrap();
+    let mut bob = P2PNode::new_with_port(19082).await.unwrap();
+    let mut alice_delivery = alice.take_delivery_receiver().unwrap();
+    let mut bob_group_rx = bob.take_message_receiver().unwrap();
+    let mut bob_direct_rx = bob.take_direct_message_receiver().unwrap();
+    alice.join_group("room").unwrap();
+
+    let nodes = run_for(vec![alice, bob], Duration::from_millis(500)).await;
+    let bob_addr = nodes[1].listening_addresses()[0].clone();
+    let bob_peer_id = *nodes[1].local_peer_id();
+    let mut nodes = nodes;
+    nodes[0].dial(bob_addr).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(4)).await;
+
+    // Bob joins Alice's group by invite
+    nodes[0].invite_to_group("room", bob_peer_id).unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(1)).await;
+    nodes[1].accept_group_invite("room").unwrap();
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+
+    // Held back for the jitter, then published
+    nodes[0].set_timing_jitter(Some(TimingJitter::new(800, 1000)));
+    nodes[0].send_group_message("room", "alice", "jittered").unwrap();
+    let nodes = run_for(nodes, Duration::from_millis(300)).await;
+    assert!(bob_group_rx.try_recv().is_err(), "published before the jitter was up");
+    let mut nodes = run_for(nodes, Duration::from_secs(2)).await;
+    let (_, data) = bob_group_rx.try_recv().unwrap();
+    assert_eq!(nodes[1].decrypt_group_message(&data).unwrap().content, "jittered");
+
+    // Direct messages wait out the same jitter; Bob holds his ack back, but it still comes
+    // in well before the sender gives up
+    nodes[1].set_ack_delay(Some(500));
+    let message_id = nodes[0].send_direct(bob_peer_id, "alice", "hello").unwrap();
+    let nodes = run_for(nodes, Duration::from_millis(300)).await;
+    assert!(bob_direct_rx.try_recv().is_err(), "sent before the jitter was up");
+    let _nodes = run_for(nodes, Duration::from_secs(2)).await;
+    assert_eq!(bob_direct_rx.try_recv().unwrap().1.content, "hello");
+    assert_eq!(alice_delivery.try_recv().unwrap(), (bob_peer_id, message_id, DeliveryStatus::Delivered));
+}
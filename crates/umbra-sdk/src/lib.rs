@@ -1,5 +1,12 @@
-use umbra_net::P2PNode;
+use umbra_net::{CoverConfig, DecryptedMessage, DeliveryStatus, FileId, FileUpdate, GroupUpdate, MailboxConfig, MailboxUpdate, P2PNode, TimingJitter, TrafficStats};
 use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+pub use umbra_mls as mls;
+pub use umbra_net::{IdentitySource, NodeConfig};
+pub use umbra_net::keystore::{KEY_FILE, PASSPHRASE_ENV};
+pub use umbra_wire::group::{GroupMetadata, Role};
 
 pub struct Node {
     p2p: P2PNode,
@@ -7,7 +14,12 @@ pub struct Node {
 
 impl Node {
     pub async fn spawn() -> Result<Self> {
-        let p2p = P2PNode::new().await?;
+        Self::spawn_with_config(NodeConfig::default()).await
+    }
+    
+    /// Start a node with the listen addresses, bootstrap peers, tuning and behaviours in `config`
+    pub async fn spawn_with_config(config: NodeConfig) -> Result<Self> {
+        let p2p = P2PNode::from_config(config).await?;
         Ok(Self { p2p })
     }
     
@@ -38,6 +50,65 @@ impl Node {
         Ok(())
     }
     
+    /// Subscribe to `topic` and start its MLS group, with us as its admin until we accept an
+    /// invite to someone else's
+    pub fn join_group(&mut self, topic: &str) -> Result<()> {
+        self.p2p.join_group(topic)?;
+        Ok(())
+    }
+    
+    /// Encrypt a chat message under the MLS group's current epoch and publish it on `topic`
+    pub fn send_group_message(&mut self, topic: &str, username: &str, content: &str) -> Result<()> {
+        self.p2p.send_group_message(topic, username, content)?;
+        Ok(())
+    }
+    
+    /// Decrypt a message received on a group topic
+    pub fn decrypt_group_message(&mut self, data: &[u8]) -> Result<DecryptedMessage> {
+        Ok(self.p2p.decrypt_group_message(data)?)
+    }
+    
+    /// Our MLS group on `topic`, if we're in one
+    pub fn group(&self, topic: &str) -> Option<&mls::Group> {
+        self.p2p.group(topic)
+    }
+    
+    /// Invite a peer we've completed a handshake with into our group on `topic`
+    pub fn invite(&mut self, topic: &str, peer: &str) -> Result<()> {
+        let peer: libp2p::PeerId = peer.parse()?;
+        self.p2p.invite_to_group(topic, peer)?;
+        Ok(())
+    }
+    
+    /// Accept an invite from the group updates
+    pub fn accept_invite(&mut self, topic: &str) -> Result<()> {
+        self.p2p.accept_group_invite(topic)?;
+        Ok(())
+    }
+    
+    pub fn remove_member(&mut self, topic: &str, peer: &str) -> Result<()> {
+        let peer: libp2p::PeerId = peer.parse()?;
+        self.p2p.remove_from_group(topic, peer)?;
+        Ok(())
+    }
+    
+    /// Promote (`Role::Admin`) or demote (`Role::Member`) a member
+    pub fn set_role(&mut self, topic: &str, peer: &str, role: Role) -> Result<()> {
+        let peer: libp2p::PeerId = peer.parse()?;
+        self.p2p.set_group_role(topic, peer, role)?;
+        Ok(())
+    }
+    
+    pub fn set_group_metadata(&mut self, topic: &str, metadata: GroupMetadata) -> Result<()> {
+        self.p2p.set_group_metadata(topic, metadata)?;
+        Ok(())
+    }
+    
+    /// Group invites, joins and membership changes
+    pub fn take_group_updates(&mut self) -> Option<UnboundedReceiver<GroupUpdate>> {
+        self.p2p.take_group_receiver()
+    }
+    
     pub fn add_peer(&mut self, peer_id: &str, addr: &str) -> Result<()> {
         let peer_id: libp2p::PeerId = peer_id.parse()?;
         let multiaddr: libp2p::Multiaddr = addr.parse()?;
@@ -45,6 +116,144 @@ impl Node {
         Ok(())
     }
     
+    /// Send an encrypted direct message; returns its id for the delivery receiver
+    pub fn send_direct(&mut self, peer: &str, username: &str, content: &str) -> Result<u64> {
+        let peer: libp2p::PeerId = peer.parse()?;
+        Ok(self.p2p.send_direct(peer, username, content)?)
+    }
+    
+    /// Send direct messages over 3-hop onion circuits instead of straight to the peer
+    pub fn route_via_circuits(&mut self, enabled: bool) {
+        self.p2p.set_circuit_routing(enabled);
+    }
+    
+    /// Send direct messages as Sphinx packets through 3 mixes, with random delays at each
+    pub fn route_via_mixes(&mut self, enabled: bool) {
+        self.p2p.set_mix_routing(enabled);
+    }
+    
+    /// Send dummy messages to our groups and peers on `config`'s schedule
+    pub fn enable_cover_traffic(&mut self, config: CoverConfig) {
+        self.p2p.enable_cover_traffic(config);
+    }
+    
+    /// Hold publishes, direct messages and mailbox requests back by a random `min_ms..=max_ms`
+    /// (10-100 ms by default); 0..=0 turns it off
+    pub fn set_send_jitter(&mut self, min_ms: u64, max_ms: u64) {
+        let jitter = (max_ms > 0).then(|| TimingJitter::new(min_ms, max_ms));
+        self.p2p.set_timing_jitter(jitter);
+    }
+    
+    /// Real and cover message counters, for the cover traffic overhead
+    pub fn traffic_stats(&self) -> TrafficStats {
+        self.p2p.traffic_stats()
+    }
+    
+    /// Publish our onion key so peers can reach us over circuits
+    pub fn publish_onion_key(&mut self) -> Result<()> {
+        self.p2p.publish_onion_key()?;
+        Ok(())
+    }
+    
+    /// Relay circuits for other peers
+    pub fn enable_relay(&mut self) -> Result<()> {
+        self.p2p.enable_relay()?;
+        Ok(())
+    }
+    
+    /// Relay circuits and mix Sphinx packets for other peers
+    pub fn enable_mix(&mut self) -> Result<()> {
+        self.p2p.enable_mix()?;
+        Ok(())
+    }
+    
+    /// Serve a store-and-forward mailbox for offline peers, persisted to `path` if given
+    pub fn enable_mailbox(&mut self, path: Option<PathBuf>) -> Result<()> {
+        self.p2p.enable_mailbox(MailboxConfig { path, ..Default::default() })?;
+        Ok(())
+    }
+    
+    /// Leave an encrypted message for `recipient` at the `mailbox` node
+    pub fn deposit(&mut self, mailbox: &str, recipient: &str, username: &str, content: &str) -> Result<()> {
+        let mailbox: libp2p::PeerId = mailbox.parse()?;
+        let recipient: libp2p::PeerId = recipient.parse()?;
+        self.p2p.deposit_to_mailbox(mailbox, recipient, username, content)?;
+        Ok(())
+    }
+    
+    /// Collect (and clear) our messages held at the `mailbox` node
+    pub fn fetch_mailbox(&mut self, mailbox: &str) -> Result<()> {
+        let mailbox: libp2p::PeerId = mailbox.parse()?;
+        self.p2p.fetch_mailbox(mailbox)?;
+        Ok(())
+    }
+    
+    /// Offer a file to a peer we've completed a handshake with
+    pub fn send_file(&mut self, peer: &str, path: &Path) -> Result<FileId> {
+        let peer: libp2p::PeerId = peer.parse()?;
+        Ok(self.p2p.offer_file(peer, path)?)
+    }
+    
+    /// Offer a file to every member of a group; they also fetch it from each other
+    pub fn send_file_to_group(&mut self, topic: &str, path: &Path) -> Result<FileId> {
+        Ok(self.p2p.offer_file_to_group(topic, path)?)
+    }
+    
+    /// Offer a folder and everything under it to a peer we've completed a handshake with
+    pub fn send_folder(&mut self, peer: &str, path: &Path) -> Result<FileId> {
+        let peer: libp2p::PeerId = peer.parse()?;
+        Ok(self.p2p.offer_folder(peer, path)?)
+    }
+    
+    /// Download an offered folder into a new directory under `dir`; returns that directory
+    pub fn accept_folder(&mut self, folder_id: FileId, dir: &Path) -> Result<PathBuf> {
+        Ok(self.p2p.accept_folder(folder_id, dir)?)
+    }
+    
+    /// Download an offered file to `path`
+    pub fn accept_file(&mut self, file_id: FileId, path: &Path) -> Result<()> {
+        self.p2p.accept_file(file_id, path)?;
+        Ok(())
+    }
+    
+    pub fn pause_file(&mut self, file_id: FileId) -> bool {
+        self.p2p.pause_file(file_id)
+    }
+    
+    pub fn resume_file(&mut self, file_id: FileId) -> bool {
+        self.p2p.resume_file(file_id)
+    }
+    
+    pub fn cancel_file(&mut self, file_id: FileId) {
+        self.p2p.cancel_file(file_id);
+    }
+    
+    /// File offers, transfer progress and results
+    pub fn take_file_updates(&mut self) -> Option<UnboundedReceiver<FileUpdate>> {
+        self.p2p.take_file_receiver()
+    }
+    
+    /// Data published on our topics, with the peer that relayed it; group chat opens with
+    /// `decrypt_group_message`
+    pub fn take_messages(&mut self) -> Option<UnboundedReceiver<(libp2p::PeerId, Vec<u8>)>> {
+        self.p2p.take_message_receiver()
+    }
+    
+    /// Direct messages, including those fetched from a mailbox
+    pub fn take_direct_messages(&mut self) -> Option<UnboundedReceiver<(libp2p::PeerId, DecryptedMessage)>> {
+        self.p2p.take_direct_message_receiver()
+    }
+    
+    /// Delivery results for `send_direct`
+    pub fn take_delivery_updates(&mut self) -> Option<UnboundedReceiver<(libp2p::PeerId, u64, DeliveryStatus)>> {
+        self.p2p.take_delivery_receiver()
+    }
+    
+    /// Deposit confirmations and fetch progress
+    pub fn take_mailbox_updates(&mut self) -> Option<UnboundedReceiver<MailboxUpdate>> {
+        self.p2p.take_mailbox_receiver()
+    }
+    
     pub fn bootstrap(&mut self) -> Result<()> {
         self.p2p.bootstrap()?;
         Ok(())
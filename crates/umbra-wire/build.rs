@@ -1,5 +1,5 @@
 fn main() {
-    let proto_files = &["proto/handshake.proto", "proto/message.proto"];
+    let proto_files = &["proto/handshake.proto", "proto/message.proto", "proto/group.proto", "proto/mailbox.proto", "proto/circuit.proto", "proto/file.proto"];
     let proto_include = &["proto"];
     
     prost_build::compile_protos(proto_files, proto_include)
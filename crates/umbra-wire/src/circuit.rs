@@ -0,0 +1,34 @@
+// Onion circuit wire messages (protobuf generated)
+
+use crate::error::{Result, WireError};
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/umbra.circuit.rs"));
+}
+
+pub use proto::{
+    cell, relay_command, Cell, CellAck, Create, Created, Deliver, Delivered, Destroy, Extend, Extended,
+    RelayCommand, RelayDescriptor, RelayRecord, Sealed,
+};
+
+impl Cell {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
+
+impl RelayCommand {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
@@ -1,10 +1,18 @@
 // Conversions between umbra-crypto handshake types and wire protobuf types
 
 use umbra_crypto::handshake::{
+    HandshakeFinish as CryptoHandshakeFinish,
     HandshakeInit as CryptoHandshakeInit,
     HandshakeResp as CryptoHandshakeResp,
 };
-use crate::handshake::{HandshakeInit, HandshakeResp};
+use umbra_crypto::prekey::{
+    OneTimePrekey as CryptoOneTimePrekey,
+    PrekeyBundle as CryptoPrekeyBundle,
+    PrekeyInit as CryptoPrekeyInit,
+};
+use crate::handshake::{HandshakeFinish, HandshakeInit, HandshakeResp, OneTimePrekey, PrekeyBundle, PrekeyInit};
+use crate::circuit::Create;
+use umbra_crypto::onion::OnionCreate;
 
 impl From<&CryptoHandshakeInit> for HandshakeInit {
     fn from(init: &CryptoHandshakeInit) -> Self {
@@ -15,6 +23,8 @@ impl From<&CryptoHandshakeInit> for HandshakeInit {
             signature: init.signature.to_vec(),
             verify_key: init.verify_key.to_vec(),
             pq_signature: init.pq_signature.clone(),
+            pq_verify_key: init.pq_verify_key.clone(),
+            key_confirmation: init.key_confirmation,
         }
     }
 }
@@ -52,6 +62,8 @@ impl TryFrom<&HandshakeInit> for CryptoHandshakeInit {
             signature,
             pq_signature: proto.pq_signature.clone(),
             verify_key,
+            pq_verify_key: proto.pq_verify_key.clone(),
+            key_confirmation: proto.key_confirmation,
         })
     }
 }
@@ -65,6 +77,8 @@ impl From<&CryptoHandshakeResp> for HandshakeResp {
             signature: resp.signature.to_vec(),
             verify_key: resp.verify_key.to_vec(),
             pq_signature: resp.pq_signature.clone(),
+            pq_verify_key: resp.pq_verify_key.clone(),
+            confirm_mac: resp.confirm_mac.to_vec(),
         }
     }
 }
@@ -82,6 +96,9 @@ impl TryFrom<&HandshakeResp> for CryptoHandshakeResp {
         if proto.verify_key.len() != 32 {
             return Err("Invalid verify_key length");
         }
+        if proto.confirm_mac.len() != 32 {
+            return Err("Invalid confirm_mac length");
+        }
         
         let x25519_pk: [u8; 32] = proto.x25519_pk.as_slice()
             .try_into()
@@ -95,6 +112,10 @@ impl TryFrom<&HandshakeResp> for CryptoHandshakeResp {
             .try_into()
             .map_err(|_| "verify_key conversion failed")?;
         
+        let confirm_mac: [u8; 32] = proto.confirm_mac.as_slice()
+            .try_into()
+            .map_err(|_| "confirm_mac conversion failed")?;
+        
         Ok(CryptoHandshakeResp {
             peer_id: proto.peer_id.clone(),
             x25519_pk,
@@ -102,6 +123,147 @@ impl TryFrom<&HandshakeResp> for CryptoHandshakeResp {
             signature,
             pq_signature: proto.pq_signature.clone(),
             verify_key,
+            pq_verify_key: proto.pq_verify_key.clone(),
+            confirm_mac,
+        })
+    }
+}
+
+impl From<&CryptoHandshakeFinish> for HandshakeFinish {
+    fn from(finish: &CryptoHandshakeFinish) -> Self {
+        HandshakeFinish {
+            confirm_mac: finish.confirm_mac.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<&HandshakeFinish> for CryptoHandshakeFinish {
+    type Error = &'static str;
+    
+    fn try_from(proto: &HandshakeFinish) -> Result<Self, Self::Error> {
+        let confirm_mac: [u8; 32] = proto.confirm_mac.as_slice()
+            .try_into()
+            .map_err(|_| "Invalid confirm_mac length")?;
+        
+        Ok(CryptoHandshakeFinish { confirm_mac })
+    }
+}
+
+impl From<&CryptoPrekeyBundle> for PrekeyBundle {
+    fn from(bundle: &CryptoPrekeyBundle) -> Self {
+        PrekeyBundle {
+            verify_key: bundle.verify_key.to_vec(),
+            pq_verify_key: bundle.pq_verify_key.clone(),
+            signed_prekey_id: bundle.signed_prekey_id,
+            signed_prekey: bundle.signed_prekey.to_vec(),
+            pq_prekey: bundle.pq_prekey.clone(),
+            one_time_prekeys: bundle
+                .one_time_prekeys
+                .iter()
+                .map(|p| OneTimePrekey { id: p.id, public_key: p.public_key.to_vec() })
+                .collect(),
+            signature: bundle.signature.clone(),
+            pq_signature: bundle.pq_signature.clone(),
+        }
+    }
+}
+
+impl TryFrom<&PrekeyBundle> for CryptoPrekeyBundle {
+    type Error = &'static str;
+
+    fn try_from(proto: &PrekeyBundle) -> Result<Self, Self::Error> {
+        let verify_key: [u8; 32] = proto.verify_key.as_slice()
+            .try_into()
+            .map_err(|_| "Invalid verify_key length")?;
+
+        let signed_prekey: [u8; 32] = proto.signed_prekey.as_slice()
+            .try_into()
+            .map_err(|_| "Invalid signed_prekey length")?;
+
+        let one_time_prekeys = proto
+            .one_time_prekeys
+            .iter()
+            .map(|p| {
+                let public_key: [u8; 32] = p.public_key.as_slice()
+                    .try_into()
+                    .map_err(|_| "Invalid one-time prekey length")?;
+                Ok(CryptoOneTimePrekey { id: p.id, public_key })
+            })
+            .collect::<Result<Vec<_>, Self::Error>>()?;
+
+        Ok(CryptoPrekeyBundle {
+            verify_key,
+            pq_verify_key: proto.pq_verify_key.clone(),
+            signed_prekey_id: proto.signed_prekey_id,
+            signed_prekey,
+            pq_prekey: proto.pq_prekey.clone(),
+            one_time_prekeys,
+            signature: proto.signature.clone(),
+            pq_signature: proto.pq_signature.clone(),
+        })
+    }
+}
+
+impl From<&CryptoPrekeyInit> for PrekeyInit {
+    fn from(init: &CryptoPrekeyInit) -> Self {
+        PrekeyInit {
+            verify_key: init.verify_key.to_vec(),
+            pq_verify_key: init.pq_verify_key.clone(),
+            signed_prekey_id: init.signed_prekey_id,
+            one_time_prekey_id: init.one_time_prekey_id,
+            ephemeral_key: init.ephemeral_key.to_vec(),
+            pq_ct: init.pq_ct.clone(),
+            signature: init.signature.clone(),
+            pq_signature: init.pq_signature.clone(),
+        }
+    }
+}
+
+impl TryFrom<&PrekeyInit> for CryptoPrekeyInit {
+    type Error = &'static str;
+
+    fn try_from(proto: &PrekeyInit) -> Result<Self, Self::Error> {
+        let verify_key: [u8; 32] = proto.verify_key.as_slice()
+            .try_into()
+            .map_err(|_| "Invalid verify_key length")?;
+
+        let ephemeral_key: [u8; 32] = proto.ephemeral_key.as_slice()
+            .try_into()
+            .map_err(|_| "Invalid ephemeral_key length")?;
+
+        Ok(CryptoPrekeyInit {
+            verify_key,
+            pq_verify_key: proto.pq_verify_key.clone(),
+            signed_prekey_id: proto.signed_prekey_id,
+            one_time_prekey_id: proto.one_time_prekey_id,
+            ephemeral_key,
+            pq_ct: proto.pq_ct.clone(),
+            signature: proto.signature.clone(),
+            pq_signature: proto.pq_signature.clone(),
+        })
+    }
+}
+
+impl From<&OnionCreate> for Create {
+    fn from(create: &OnionCreate) -> Self {
+        Create {
+            ephemeral_key: create.ephemeral_key.to_vec(),
+            pq_ciphertext: create.pq_ciphertext.clone(),
+        }
+    }
+}
+
+impl TryFrom<&Create> for OnionCreate {
+    type Error = &'static str;
+
+    fn try_from(proto: &Create) -> Result<Self, Self::Error> {
+        let ephemeral_key: [u8; 32] = proto.ephemeral_key.as_slice()
+            .try_into()
+            .map_err(|_| "Invalid ephemeral_key length")?;
+
+        Ok(OnionCreate {
+            ephemeral_key,
+            pq_ciphertext: proto.pq_ciphertext.clone(),
         })
     }
 }
@@ -134,12 +296,13 @@ mod tests {
         assert_eq!(recovered.peer_id, crypto_init.peer_id);
         assert_eq!(recovered.x25519_pk, crypto_init.x25519_pk);
         assert_eq!(recovered.signature, crypto_init.signature);
+        assert_eq!(recovered.pq_verify_key, crypto_init.pq_verify_key);
     }
 
     #[test]
     fn test_handshake_resp_conversion() {
         let alice_id = gen_identity();
-        let alice_pk = *alice_id.verifying_key();
+        let alice_pk = alice_id.hybrid_verifying_key();
         let bob_id = gen_identity();
         
         let alice_peer = PeerId::random();
@@ -149,7 +312,7 @@ mod tests {
         let init = alice_hs.initiate(alice_peer).unwrap();
         
         let bob_hs = Handshake::new(bob_id.clone()).unwrap();
-        let (crypto_resp, _) = bob_hs.respond(bob_peer, &init, &alice_pk).unwrap();
+        let (crypto_resp, _) = bob_hs.respond(bob_peer, alice_peer, &init, &alice_pk).unwrap();
         
         // Convert to proto
         let proto_resp = HandshakeResp::from(&crypto_resp);
@@ -160,6 +323,7 @@ mod tests {
         assert_eq!(recovered.peer_id, crypto_resp.peer_id);
         assert_eq!(recovered.x25519_pk, crypto_resp.x25519_pk);
         assert_eq!(recovered.signature, crypto_resp.signature);
+        assert_eq!(recovered.confirm_mac, crypto_resp.confirm_mac);
     }
 
     #[test]
@@ -171,6 +335,8 @@ mod tests {
             signature: vec![0u8; 64],
             verify_key: vec![0u8; 32],
             pq_signature: vec![],
+            pq_verify_key: vec![],
+            key_confirmation: false,
         };
         
         let result = CryptoHandshakeInit::try_from(&proto_init);
@@ -186,6 +352,8 @@ mod tests {
             signature: vec![0u8; 32], // Wrong length!
             verify_key: vec![0u8; 32],
             pq_signature: vec![],
+            pq_verify_key: vec![],
+            key_confirmation: false,
         };
         
         let result = CryptoHandshakeInit::try_from(&proto_init);
@@ -201,10 +369,57 @@ mod tests {
             signature: vec![0u8; 64],
             verify_key: vec![0u8; 32],
             pq_signature: vec![],
+            pq_verify_key: vec![],
+            key_confirmation: false,
         };
         
         // Should succeed (peer_id can be empty Vec, though invalid)
         let result = CryptoHandshakeInit::try_from(&proto_init);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_handshake_finish_conversion() {
+        let finish = CryptoHandshakeFinish { confirm_mac: [9u8; 32] };
+        
+        let proto_finish = HandshakeFinish::from(&finish);
+        let recovered = CryptoHandshakeFinish::try_from(&proto_finish).unwrap();
+        assert_eq!(recovered.confirm_mac, finish.confirm_mac);
+        
+        let short = HandshakeFinish { confirm_mac: vec![0u8; 16] };
+        assert!(CryptoHandshakeFinish::try_from(&short).is_err());
+    }
+
+    #[test]
+    fn test_prekey_conversion() {
+        let mut store = umbra_crypto::PrekeyStore::new(gen_identity()).unwrap();
+        let bundle = store.bundle().unwrap();
+
+        let recovered = CryptoPrekeyBundle::try_from(&PrekeyBundle::from(&bundle)).unwrap();
+        assert_eq!(recovered, bundle);
+        recovered.verify().unwrap();
+
+        let (init, _) = umbra_crypto::prekey::initiate(&gen_identity(), &recovered).unwrap();
+        let recovered = CryptoPrekeyInit::try_from(&PrekeyInit::from(&init)).unwrap();
+        assert_eq!(recovered, init);
+        store.accept(&recovered).unwrap();
+
+        let mut short = PrekeyInit::from(&init);
+        short.ephemeral_key.truncate(16);
+        assert!(CryptoPrekeyInit::try_from(&short).is_err());
+    }
+
+    #[test]
+    fn test_onion_create_conversion() {
+        let onion_key = umbra_crypto::HybridKem::generate().unwrap();
+        let onion_key = umbra_crypto::OnionPublicKey::of(&onion_key).unwrap();
+        let (create, _) = umbra_crypto::HopKeys::create(&onion_key).unwrap();
+
+        let recovered = OnionCreate::try_from(&Create::from(&create)).unwrap();
+        assert_eq!(recovered, create);
+
+        let mut short = Create::from(&create);
+        short.ephemeral_key.truncate(16);
+        assert!(OnionCreate::try_from(&short).is_err());
+    }
 }
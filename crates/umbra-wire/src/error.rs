@@ -11,6 +11,9 @@ pub enum WireError {
     #[error("Invalid payload length")]
     InvalidLength,
     
+    #[error("Invalid fragment: {0}")]
+    InvalidFragment(String),
+    
     #[error("Serialization error: {0}")]
     Serialization(String),
     
@@ -19,6 +22,18 @@ pub enum WireError {
     
     #[error("Protobuf decode error: {0}")]
     Decode(#[from] prost::DecodeError),
+
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] umbra_crypto::CryptoError),
+
+    #[error("Invalid route: {0}")]
+    InvalidRoute(String),
+
+    #[error("Packet authentication failed")]
+    PacketMac,
+
+    #[error("Replayed packet")]
+    Replay,
 }
 
 pub type Result<T> = std::result::Result<T, WireError>;
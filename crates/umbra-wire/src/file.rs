@@ -0,0 +1,496 @@
+// File transfer wire messages (protobuf generated) and manifest signing
+// A manifest fixes a file's size, chunking and Merkle root under the sender's hybrid identity,
+// so every chunk can be checked on its own, whoever it comes from. A folder manifest does the
+// same for a tree of files, each fetched as a file of its own.
+
+use crate::error::{Result, WireError};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use umbra_crypto::identity::{HybridSignature, HybridVerifyingKey, IdentityKey, PqPolicy};
+use umbra_crypto::Transcript;
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/umbra.file.rs"));
+}
+
+pub use proto::{
+    file_request, ChunkRequest, FileManifest, FileRequest, FileResponse, FileStatus, FolderEntry, FolderManifest,
+    HaveRequest,
+};
+
+/// Most chunks a manifest may have, so its bitfield fits in one frame
+pub const MAX_CHUNK_COUNT: u64 = 1 << 19;
+
+/// Largest chunk a manifest may ask for, so a sealed chunk and its proof fit one 128 KiB frame
+pub const MAX_CHUNK_SIZE: u32 = 120 * 1024;
+
+/// Largest file a manifest may describe (or folder manifest, all its files together); a
+/// receiver sizes the files on disk up front
+pub const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+/// Most entries a folder manifest may list
+pub const MAX_FOLDER_ENTRIES: usize = 4096;
+
+/// Longest entry path in a folder manifest, in bytes
+pub const MAX_PATH_LEN: usize = 1024;
+
+/// Chunks a file of `size` bytes splits into (an empty file is one empty chunk)
+pub fn chunk_count(size: u64, chunk_size: u32) -> u64 {
+    size.div_ceil(u64::from(chunk_size)).max(1)
+}
+
+/// Pack one bit per chunk, first chunk in the high bit of the first byte
+pub fn encode_bitfield(have: &[bool]) -> Vec<u8> {
+    let mut bitfield = vec![0u8; have.len().div_ceil(8)];
+    for (i, _) in have.iter().enumerate().filter(|(_, have)| **have) {
+        bitfield[i / 8] |= 0x80 >> (i % 8);
+    }
+    bitfield
+}
+
+/// Unpack the bitfield of a file with `chunk_count` chunks; spare bits must be clear
+pub fn decode_bitfield(bitfield: &[u8], chunk_count: u64) -> Result<Vec<bool>> {
+    let chunk_count = chunk_count as usize;
+    if bitfield.len() != chunk_count.div_ceil(8) {
+        return Err(WireError::InvalidMessage);
+    }
+    let have: Vec<bool> = (0..bitfield.len() * 8)
+        .map(|i| bitfield[i / 8] & (0x80 >> (i % 8)) != 0)
+        .collect();
+    if have[chunk_count..].iter().any(|bit| *bit) {
+        return Err(WireError::InvalidMessage);
+    }
+    Ok(have[..chunk_count].to_vec())
+}
+
+impl FileManifest {
+    /// Signed manifest for a file of `size` bytes whose chunks hash to `root`
+    pub fn new(identity: &IdentityKey, name: &str, size: u64, chunk_size: u32, root: [u8; 32]) -> Result<Self> {
+        let mut manifest = FileManifest {
+            file_id: rand::random::<[u8; 32]>().to_vec(),
+            name: name.to_string(),
+            size,
+            chunk_size,
+            chunk_count: chunk_count(size, chunk_size),
+            root: root.to_vec(),
+            verify_key: identity.verifying_key().to_bytes().to_vec(),
+            pq_verify_key: identity.pq_verifying_key(),
+            signature: Vec::new(),
+            pq_signature: Vec::new(),
+        };
+        manifest.check()?;
+        let signature = identity.sign(&manifest.signed_hash())?;
+        manifest.signature = signature.classical;
+        manifest.pq_signature = signature.pq.unwrap_or_default();
+        Ok(manifest)
+    }
+
+    /// Check the manifest is consistent and signed; returns the signer
+    pub fn verify(&self) -> Result<HybridVerifyingKey> {
+        self.check()?;
+        let verify_key: [u8; 32] = self.verify_key.as_slice().try_into().map_err(|_| WireError::InvalidMessage)?;
+        let verify_key = HybridVerifyingKey::from_bytes(&verify_key, &self.pq_verify_key)?;
+
+        let signature = HybridSignature {
+            classical: self.signature.clone(),
+            pq: Some(self.pq_signature.clone()),
+        };
+        verify_key.verify(&self.signed_hash(), &signature, PqPolicy::RequirePq)?;
+        Ok(verify_key)
+    }
+
+    pub fn file_id(&self) -> Result<[u8; 32]> {
+        self.file_id.as_slice().try_into().map_err(|_| WireError::InvalidMessage)
+    }
+
+    pub fn root(&self) -> Result<[u8; 32]> {
+        self.root.as_slice().try_into().map_err(|_| WireError::InvalidMessage)
+    }
+
+    /// Bytes in chunk `index`
+    pub fn chunk_len(&self, index: u64) -> usize {
+        let start = index * u64::from(self.chunk_size);
+        self.size.saturating_sub(start).min(u64::from(self.chunk_size)) as usize
+    }
+
+    fn check(&self) -> Result<()> {
+        self.file_id()?;
+        self.root()?;
+        if self.chunk_size == 0
+            || self.chunk_size > MAX_CHUNK_SIZE
+            || self.size > MAX_FILE_SIZE
+            || self.chunk_count != chunk_count(self.size, self.chunk_size)
+            || self.chunk_count > MAX_CHUNK_COUNT
+        {
+            return Err(WireError::InvalidMessage);
+        }
+        // A bare file name: whoever accepts picks the directory
+        if !is_bare_name(&self.name) {
+            return Err(WireError::InvalidMessage);
+        }
+        Ok(())
+    }
+
+    fn signed_hash(&self) -> [u8; 32] {
+        use prost::Message;
+
+        let mut unsigned = self.clone();
+        unsigned.signature.clear();
+        unsigned.pq_signature.clear();
+        let mut transcript = Transcript::new();
+        transcript.append(b"file manifest", &unsigned.encode_to_vec());
+        transcript.hash()
+    }
+}
+
+/// A single path component that can't point anywhere else
+fn is_bare_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+/// Check a folder entry's path and turn it into a relative path: '/'-separated plain names only
+/// (no drive letters either), so joined onto the directory a folder is extracted into, it
+/// can't end up outside it
+pub fn relative_path(path: &str) -> Result<PathBuf> {
+    if path.is_empty() || path.len() > MAX_PATH_LEN {
+        return Err(WireError::InvalidMessage);
+    }
+    let relative: PathBuf = path.split('/').collect();
+    let plain = path.split('/').all(|name| is_bare_name(name) && !name.contains(':'))
+        && relative.components().all(|component| matches!(component, Component::Normal(_)));
+    if !plain {
+        return Err(WireError::InvalidMessage);
+    }
+    Ok(relative)
+}
+
+impl FolderEntry {
+    /// A file of `size` bytes whose chunks hash to `root`, with a fresh file id
+    pub fn file(path: &str, size: u64, mode: u32, root: [u8; 32]) -> Self {
+        FolderEntry {
+            path: path.to_string(),
+            directory: false,
+            size,
+            mode,
+            root: root.to_vec(),
+            file_id: rand::random::<[u8; 32]>().to_vec(),
+        }
+    }
+
+    pub fn directory(path: &str, mode: u32) -> Self {
+        FolderEntry {
+            path: path.to_string(),
+            directory: true,
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn file_id(&self) -> Result<[u8; 32]> {
+        self.file_id.as_slice().try_into().map_err(|_| WireError::InvalidMessage)
+    }
+
+    /// Where the entry goes in the folder extracted at `root`
+    pub fn path_under(&self, root: &Path) -> Result<PathBuf> {
+        Ok(root.join(relative_path(&self.path)?))
+    }
+}
+
+impl FolderManifest {
+    /// Signed manifest for a folder of `entries`, all chunked by `chunk_size`
+    pub fn new(identity: &IdentityKey, name: &str, chunk_size: u32, entries: Vec<FolderEntry>) -> Result<Self> {
+        let mut manifest = FolderManifest {
+            folder_id: rand::random::<[u8; 32]>().to_vec(),
+            name: name.to_string(),
+            chunk_size,
+            entries,
+            verify_key: identity.verifying_key().to_bytes().to_vec(),
+            pq_verify_key: identity.pq_verifying_key(),
+            signature: Vec::new(),
+            pq_signature: Vec::new(),
+        };
+        manifest.check()?;
+        let signature = identity.sign(&manifest.signed_hash())?;
+        manifest.signature = signature.classical;
+        manifest.pq_signature = signature.pq.unwrap_or_default();
+        Ok(manifest)
+    }
+
+    /// Check the manifest is consistent, safe to extract and signed; returns the signer
+    pub fn verify(&self) -> Result<HybridVerifyingKey> {
+        self.check()?;
+        let verify_key: [u8; 32] = self.verify_key.as_slice().try_into().map_err(|_| WireError::InvalidMessage)?;
+        let verify_key = HybridVerifyingKey::from_bytes(&verify_key, &self.pq_verify_key)?;
+
+        let signature = HybridSignature {
+            classical: self.signature.clone(),
+            pq: Some(self.pq_signature.clone()),
+        };
+        verify_key.verify(&self.signed_hash(), &signature, PqPolicy::RequirePq)?;
+        Ok(verify_key)
+    }
+
+    pub fn folder_id(&self) -> Result<[u8; 32]> {
+        self.folder_id.as_slice().try_into().map_err(|_| WireError::InvalidMessage)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &FolderEntry> {
+        self.entries.iter().filter(|entry| !entry.directory)
+    }
+
+    /// Bytes in all the files
+    pub fn size(&self) -> u64 {
+        self.files().map(|entry| entry.size).sum()
+    }
+
+    /// A file entry as a single-file manifest to fetch it with. It carries no signature of its
+    /// own: the folder's covers it.
+    pub fn file_manifest(&self, entry: &FolderEntry) -> Result<FileManifest> {
+        if entry.directory {
+            return Err(WireError::InvalidMessage);
+        }
+        let name = entry.path.rsplit('/').next().unwrap_or_default();
+        Ok(FileManifest {
+            file_id: entry.file_id.clone(),
+            name: name.to_string(),
+            size: entry.size,
+            chunk_size: self.chunk_size,
+            chunk_count: chunk_count(entry.size, self.chunk_size),
+            root: entry.root.clone(),
+            verify_key: self.verify_key.clone(),
+            pq_verify_key: self.pq_verify_key.clone(),
+            signature: Vec::new(),
+            pq_signature: Vec::new(),
+        })
+    }
+
+    fn check(&self) -> Result<()> {
+        let folder_id = self.folder_id()?;
+        if !is_bare_name(&self.name)
+            || self.chunk_size == 0
+            || self.chunk_size > MAX_CHUNK_SIZE
+            || self.entries.len() > MAX_FOLDER_ENTRIES
+        {
+            return Err(WireError::InvalidMessage);
+        }
+        let total = self.files().try_fold(0u64, |total, entry| total.checked_add(entry.size));
+        if total.map_or(true, |total| total > MAX_FILE_SIZE) {
+            return Err(WireError::InvalidMessage);
+        }
+
+        let mut paths = HashSet::new();
+        let mut file_ids = HashSet::from([folder_id]);
+        for entry in &self.entries {
+            relative_path(&entry.path)?;
+            if !paths.insert(entry.path.as_str()) {
+                return Err(WireError::InvalidMessage);
+            }
+            if entry.directory {
+                if entry.size != 0 || !entry.root.is_empty() || !entry.file_id.is_empty() {
+                    return Err(WireError::InvalidMessage);
+                }
+            } else {
+                let root: Result<[u8; 32]> = entry.root.as_slice().try_into().map_err(|_| WireError::InvalidMessage);
+                root?;
+                if !file_ids.insert(entry.file_id()?) || chunk_count(entry.size, self.chunk_size) > MAX_CHUNK_COUNT {
+                    return Err(WireError::InvalidMessage);
+                }
+            }
+        }
+        // A file can't also be a directory other entries live in
+        for entry in &self.entries {
+            let mut parent = entry.path.as_str();
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                if self.files().any(|file| file.path == dir) {
+                    return Err(WireError::InvalidMessage);
+                }
+                parent = dir;
+            }
+        }
+        Ok(())
+    }
+
+    fn signed_hash(&self) -> [u8; 32] {
+        use prost::Message;
+
+        let mut unsigned = self.clone();
+        unsigned.signature.clear();
+        unsigned.pq_signature.clear();
+        let mut transcript = Transcript::new();
+        transcript.append(b"folder manifest", &unsigned.encode_to_vec());
+        transcript.hash()
+    }
+}
+
+impl FileRequest {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
+
+impl FileResponse {
+    /// Response carrying only a status
+    pub fn with_status(status: FileStatus) -> Self {
+        FileResponse {
+            status: status as i32,
+            ..Default::default()
+        }
+    }
+
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_signed_and_verified() {
+        let identity = IdentityKey::generate().unwrap();
+        let manifest = FileManifest::new(&identity, "photo.jpg", 1000, 256, [7u8; 32]).unwrap();
+
+        assert_eq!(manifest.chunk_count, 4);
+        assert_eq!(manifest.chunk_len(3), 232);
+        assert_eq!(manifest.verify().unwrap(), identity.hybrid_verifying_key());
+    }
+
+    #[test]
+    fn test_tampered_manifest_rejected() {
+        let identity = IdentityKey::generate().unwrap();
+        let manifest = FileManifest::new(&identity, "photo.jpg", 1000, 256, [7u8; 32]).unwrap();
+
+        let mut swapped_root = manifest.clone();
+        swapped_root.root = vec![8u8; 32];
+        assert!(swapped_root.verify().is_err());
+
+        // Inconsistent chunking is refused before the signature is even checked
+        let mut wrong_count = manifest.clone();
+        wrong_count.chunk_count = 5;
+        assert!(wrong_count.verify().is_err());
+    }
+
+    #[test]
+    fn test_manifest_names_must_be_bare() {
+        let identity = IdentityKey::generate().unwrap();
+        for name in ["", "..", "../etc/passwd", "dir/file", "C:\\file"] {
+            assert!(FileManifest::new(&identity, name, 10, 256, [0u8; 32]).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_bitfield_roundtrip() {
+        let have: Vec<bool> = (0..11).map(|i| i % 3 == 0).collect();
+        let bitfield = encode_bitfield(&have);
+        assert_eq!(bitfield, vec![0b1001_0010, 0b0100_0000]);
+        assert_eq!(decode_bitfield(&bitfield, 11).unwrap(), have);
+
+        // Wrong length, or bits past the last chunk
+        assert!(decode_bitfield(&bitfield, 17).is_err());
+        assert!(decode_bitfield(&[0b1001_0010, 0b0100_0001], 11).is_err());
+    }
+
+    #[test]
+    fn test_huge_manifest_rejected() {
+        let identity = IdentityKey::generate().unwrap();
+        assert!(FileManifest::new(&identity, "huge.bin", (MAX_CHUNK_COUNT + 1) * 1024, 1024, [0u8; 32]).is_err());
+        assert!(FileManifest::new(&identity, "huge.bin", MAX_FILE_SIZE + 1, MAX_CHUNK_SIZE, [0u8; 32]).is_err());
+        // Chunks have to fit a frame, whatever the chunk count
+        assert!(FileManifest::new(&identity, "big.bin", 1000, MAX_CHUNK_SIZE + 1, [0u8; 32]).is_err());
+        assert!(FileManifest::new(&identity, "big.bin", 1000, MAX_CHUNK_SIZE, [0u8; 32]).is_ok());
+    }
+
+    fn folder_entries() -> Vec<FolderEntry> {
+        vec![
+            FolderEntry::directory("docs", 0o755),
+            FolderEntry::file("docs/readme.txt", 1000, 0o644, [1u8; 32]),
+            FolderEntry::file("docs/img/logo.png", 0, 0o600, [2u8; 32]),
+            FolderEntry::directory("empty", 0o700),
+        ]
+    }
+
+    #[test]
+    fn test_folder_manifest_signed_and_verified() {
+        let identity = IdentityKey::generate().unwrap();
+        let manifest = FolderManifest::new(&identity, "project", 256, folder_entries()).unwrap();
+
+        assert_eq!(manifest.verify().unwrap(), identity.hybrid_verifying_key());
+        assert_eq!(manifest.files().count(), 2);
+        assert_eq!(manifest.size(), 1000);
+
+        let readme = manifest.file_manifest(&manifest.entries[1]).unwrap();
+        assert_eq!(readme.name, "readme.txt");
+        assert_eq!(readme.chunk_count, 4);
+        assert!(manifest.file_manifest(&manifest.entries[0]).is_err());
+
+        let mut renamed = manifest.clone();
+        renamed.entries[1].path = "docs/other.txt".to_string();
+        assert!(renamed.verify().is_err());
+    }
+
+    #[test]
+    fn test_unsafe_paths_rejected() {
+        for path in ["", "/etc/passwd", "../up", "a/../../up", "a//b", "a/./b", "a/", "C:/windows", "C:evil", "a\\b", "nul\0"] {
+            assert!(relative_path(path).is_err(), "{:?}", path);
+        }
+        assert_eq!(relative_path("a/b/c.txt").unwrap(), Path::new("a").join("b").join("c.txt"));
+
+        let identity = IdentityKey::generate().unwrap();
+        let mut entries = folder_entries();
+        entries.push(FolderEntry::file("../outside", 10, 0o644, [3u8; 32]));
+        assert!(FolderManifest::new(&identity, "project", 256, entries).is_err());
+        assert!(FolderManifest::new(&identity, "..", 256, folder_entries()).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_entries_rejected() {
+        let identity = IdentityKey::generate().unwrap();
+
+        let mut duplicate = folder_entries();
+        duplicate.push(FolderEntry::file("docs/readme.txt", 5, 0o644, [3u8; 32]));
+        assert!(FolderManifest::new(&identity, "project", 256, duplicate).is_err());
+
+        // docs/readme.txt as a file and as a directory
+        let mut nested = folder_entries();
+        nested.push(FolderEntry::file("docs/readme.txt/inner", 5, 0o644, [3u8; 32]));
+        assert!(FolderManifest::new(&identity, "project", 256, nested).is_err());
+
+        let mut shared_id = folder_entries();
+        shared_id[2].file_id = shared_id[1].file_id.clone();
+        assert!(FolderManifest::new(&identity, "project", 256, shared_id).is_err());
+    }
+
+    #[test]
+    fn test_huge_folder_rejected() {
+        let identity = IdentityKey::generate().unwrap();
+        assert!(FolderManifest::new(&identity, "project", MAX_CHUNK_SIZE + 1, folder_entries()).is_err());
+
+        let mut huge = folder_entries();
+        huge[1].size = MAX_FILE_SIZE + 1;
+        assert!(FolderManifest::new(&identity, "project", MAX_CHUNK_SIZE, huge.clone()).is_err());
+        // Each file fits, but not all of them together (nor does an overflowing sum)
+        huge[1].size = MAX_FILE_SIZE / 2 + 1;
+        huge[2].size = MAX_FILE_SIZE / 2 + 1;
+        assert!(FolderManifest::new(&identity, "project", MAX_CHUNK_SIZE, huge.clone()).is_err());
+        huge[2].size = u64::MAX;
+        assert!(FolderManifest::new(&identity, "project", MAX_CHUNK_SIZE, huge).is_err());
+    }
+
+    #[test]
+    fn test_empty_file_is_one_chunk() {
+        assert_eq!(chunk_count(0, 1024), 1);
+        assert_eq!(chunk_count(1024, 1024), 1);
+        assert_eq!(chunk_count(1025, 1024), 2);
+    }
+}
@@ -1,9 +1,23 @@
-use crate::error::Result;
+use crate::error::{Result, WireError};
 use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 
-/// Fixed frame size for metadata protection
+/// Fixed frame size for metadata protection (the smallest bucket)
 pub const FRAME_SIZE: usize = 512;
 
+/// Sizes a frame can have on the wire: payloads are padded up to the smallest one that fits
+pub const FRAME_BUCKETS: [usize; 6] = [FRAME_SIZE, 2048, 8192, 32 * 1024, 128 * 1024, 512 * 1024];
+
+/// Largest payload a frame can carry
+pub const MAX_FRAME_PAYLOAD: usize = FRAME_BUCKETS[FRAME_BUCKETS.len() - 1] - 4;
+
+/// Frame size for a payload of `len` bytes, if any bucket holds it
+pub fn bucket_for(len: usize) -> Option<usize> {
+    FRAME_BUCKETS.iter().copied().find(|bucket| len + 4 <= *bucket)
+}
+
 /// Message frame with padding
 #[derive(Clone, Debug)]
 pub struct Frame {
@@ -13,19 +27,32 @@ pub struct Frame {
 
 impl Frame {
     pub fn new(payload: Vec<u8>) -> Result<Self> {
-        if payload.len() > FRAME_SIZE - 4 {
-            return Err(crate::error::WireError::PayloadTooLarge);
-        }
+        let size = bucket_for(payload.len()).ok_or(WireError::PayloadTooLarge)?;
         
-        let padding_len = FRAME_SIZE - payload.len() - 4;
+        let padding_len = size - payload.len() - 4;
         let mut padding = vec![0u8; padding_len];
         rand::thread_rng().fill(&mut padding[..]);
         
         Ok(Self { payload, padding })
     }
     
+    /// Pad `payload` into a frame and serialize it
+    pub fn pad(payload: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(Self::new(payload)?.serialize())
+    }
+    
+    /// Payload of a serialized frame
+    pub fn unpad(data: &[u8]) -> Result<Vec<u8>> {
+        Ok(Self::deserialize(data)?.payload)
+    }
+    
+    /// Size of the serialized frame
+    pub fn size(&self) -> usize {
+        4 + self.payload.len() + self.padding.len()
+    }
+    
     pub fn serialize(&self) -> Vec<u8> {
-        let mut frame = Vec::with_capacity(FRAME_SIZE);
+        let mut frame = Vec::with_capacity(self.size());
         
         // Length prefix (4 bytes)
         let len = self.payload.len() as u32;
@@ -41,14 +68,14 @@ impl Frame {
     }
     
     pub fn deserialize(data: &[u8]) -> Result<Self> {
-        if data.len() != FRAME_SIZE {
-            return Err(crate::error::WireError::InvalidFrameSize);
+        if !FRAME_BUCKETS.contains(&data.len()) {
+            return Err(WireError::InvalidFrameSize);
         }
         
         let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
         
-        if len > FRAME_SIZE - 4 {
-            return Err(crate::error::WireError::InvalidLength);
+        if len > data.len() - 4 {
+            return Err(WireError::InvalidLength);
         }
         
         let payload = data[4..4 + len].to_vec();
@@ -58,26 +85,136 @@ impl Frame {
     }
 }
 
+/// Bytes in front of every fragment: message id, index, total, checksum
+pub const FRAGMENT_HEADER_SIZE: usize = 8 + 2 + 2 + 4;
+
+/// How long a partly received message waits for its missing fragments
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bytes of partly received messages held before the oldest are dropped
+pub const DEFAULT_REASSEMBLY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Partly received messages held before the oldest are dropped
+pub const DEFAULT_REASSEMBLY_MESSAGES: usize = 256;
+
+/// Completed message ids remembered, so late duplicates don't start them over
+const COMPLETED_CAPACITY: usize = 1024;
+
+/// Which message a fragment belongs to and where it goes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FragmentHeader {
+    pub message_id: u64,
+    pub index: u16,
+    pub total: u16,
+    /// Over the rest of the header and the fragment's data
+    pub checksum: u32,
+}
+
+/// One piece of a fragmented message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fragment {
+    pub header: FragmentHeader,
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    pub fn new(message_id: u64, index: u16, total: u16, data: Vec<u8>) -> Self {
+        let checksum = fragment_checksum(message_id, index, total, &data);
+        Self { header: FragmentHeader { message_id, index, total, checksum }, data }
+    }
+    
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FRAGMENT_HEADER_SIZE + self.data.len());
+        out.extend_from_slice(&self.header.message_id.to_be_bytes());
+        out.extend_from_slice(&self.header.index.to_be_bytes());
+        out.extend_from_slice(&self.header.total.to_be_bytes());
+        out.extend_from_slice(&self.header.checksum.to_be_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
+    
+    /// Parse a fragment, checking its header is consistent and its checksum matches
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < FRAGMENT_HEADER_SIZE {
+            return Err(WireError::InvalidFragment("Shorter than its header".to_string()));
+        }
+        let message_id = u64::from_be_bytes(bytes[0..8].try_into().expect("8 bytes"));
+        let index = u16::from_be_bytes([bytes[8], bytes[9]]);
+        let total = u16::from_be_bytes([bytes[10], bytes[11]]);
+        let checksum = u32::from_be_bytes(bytes[12..16].try_into().expect("4 bytes"));
+        let data = bytes[FRAGMENT_HEADER_SIZE..].to_vec();
+        
+        if total == 0 || index >= total {
+            return Err(WireError::InvalidFragment(format!("Fragment {} of {}", index, total)));
+        }
+        if checksum != fragment_checksum(message_id, index, total, &data) {
+            return Err(WireError::InvalidFragment("Checksum mismatch".to_string()));
+        }
+        Ok(Self { header: FragmentHeader { message_id, index, total, checksum }, data })
+    }
+}
+
+fn fragment_checksum(message_id: u64, index: u16, total: u16, data: &[u8]) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(message_id.to_be_bytes());
+    hasher.update(index.to_be_bytes());
+    hasher.update(total.to_be_bytes());
+    hasher.update(data);
+    let digest = hasher.finalize();
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
 /// Message fragmenter for large payloads
 pub struct Fragmenter {
     max_payload_size: usize,
 }
 
 impl Fragmenter {
+    /// Fragments that fit the smallest frame, header included
     pub fn new() -> Self {
         Self {
-            max_payload_size: FRAME_SIZE - 4,
+            max_payload_size: FRAME_SIZE - 4 - FRAGMENT_HEADER_SIZE,
         }
     }
     
-    pub fn fragment(&self, data: &[u8]) -> Vec<Vec<u8>> {
-        data.chunks(self.max_payload_size)
-            .map(|chunk| chunk.to_vec())
-            .collect()
+    /// Fragments of at most `fragment_size` bytes, header included
+    pub fn with_fragment_size(fragment_size: usize) -> Result<Self> {
+        if fragment_size <= FRAGMENT_HEADER_SIZE {
+            return Err(WireError::InvalidFragment(format!(
+                "fragment size {} leaves no room for data",
+                fragment_size
+            )));
+        }
+        Ok(Self {
+            max_payload_size: fragment_size - FRAGMENT_HEADER_SIZE,
+        })
+    }
+    
+    /// Split `data` into encoded fragments under a fresh message id
+    pub fn fragment(&self, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(self.max_payload_size).collect()
+        };
+        let total = u16::try_from(chunks.len()).map_err(|_| WireError::PayloadTooLarge)?;
+        let message_id = rand::random();
+        
+        Ok(chunks.into_iter()
+            .enumerate()
+            .map(|(index, chunk)| Fragment::new(message_id, index as u16, total, chunk.to_vec()).encode())
+            .collect())
     }
     
-    pub fn reassemble(&self, fragments: Vec<Vec<u8>>) -> Vec<u8> {
-        fragments.into_iter().flatten().collect()
+    /// Put a complete set of one message's fragments back together, in whatever order
+    pub fn reassemble(&self, fragments: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut reassembler = Reassembler::new();
+        for fragment in fragments {
+            if let Some(message) = reassembler.push(&fragment)? {
+                return Ok(message);
+            }
+        }
+        Err(WireError::InvalidFragment("Fragments missing".to_string()))
     }
 }
 
@@ -87,6 +224,172 @@ impl Default for Fragmenter {
     }
 }
 
+/// A message with some of its fragments in
+struct Partial {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Reassembly buffer: collects fragments of many messages as they arrive (in any order,
+/// duplicated or not), hands out each message once complete, and drops messages whose
+/// fragments stop coming or that would push it past its memory caps
+pub struct Reassembler {
+    timeout: Duration,
+    max_bytes: usize,
+    max_messages: usize,
+    partial: HashMap<u64, Partial>,
+    bytes: usize,
+    completed: HashSet<u64>,
+    completed_order: VecDeque<u64>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_REASSEMBLY_TIMEOUT, DEFAULT_REASSEMBLY_BYTES, DEFAULT_REASSEMBLY_MESSAGES)
+    }
+    
+    pub fn with_limits(timeout: Duration, max_bytes: usize, max_messages: usize) -> Self {
+        Self {
+            timeout,
+            max_bytes,
+            max_messages,
+            partial: HashMap::new(),
+            bytes: 0,
+            completed: HashSet::new(),
+            completed_order: VecDeque::new(),
+        }
+    }
+    
+    /// Add an encoded fragment; returns the message it completes, if any
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.push_at(fragment, Instant::now())
+    }
+    
+    fn push_at(&mut self, fragment: &[u8], now: Instant) -> Result<Option<Vec<u8>>> {
+        let Fragment { header, data } = Fragment::decode(fragment)?;
+        self.expire_at(now);
+        
+        if header.total == 1 {
+            return Ok(Some(data));
+        }
+        if self.completed.contains(&header.message_id) {
+            // Duplicate of a message already handed out
+            return Ok(None);
+        }
+        if data.len() > self.max_bytes {
+            return Err(WireError::InvalidFragment("Fragment larger than the reassembly buffer".to_string()));
+        }
+        
+        if let Some(partial) = self.partial.get(&header.message_id) {
+            if partial.chunks.len() != header.total as usize {
+                return Err(WireError::InvalidFragment(format!(
+                    "Message {} claims {} fragments, earlier ones said {}",
+                    header.message_id, header.total, partial.chunks.len()
+                )));
+            }
+            if partial.chunks[header.index as usize].is_some() {
+                // Duplicate
+                return Ok(None);
+            }
+        } else {
+            while self.partial.len() >= self.max_messages {
+                self.evict_oldest(header.message_id);
+            }
+        }
+        while self.bytes + data.len() > self.max_bytes {
+            if !self.evict_oldest(header.message_id) {
+                // Nothing else left to drop: this message alone is over the cap, and always will be
+                self.drop_partial(header.message_id);
+                return Err(WireError::InvalidFragment("Message larger than the reassembly buffer".to_string()));
+            }
+        }
+        
+        let partial = self.partial.entry(header.message_id).or_insert_with(|| Partial {
+            chunks: vec![None; header.total as usize],
+            received: 0,
+            bytes: 0,
+            started: now,
+        });
+        partial.received += 1;
+        partial.bytes += data.len();
+        self.bytes += data.len();
+        partial.chunks[header.index as usize] = Some(data);
+        
+        if partial.received < partial.chunks.len() {
+            return Ok(None);
+        }
+        let partial = self.partial.remove(&header.message_id).expect("just updated");
+        self.bytes -= partial.bytes;
+        self.completed.insert(header.message_id);
+        self.completed_order.push_back(header.message_id);
+        if self.completed_order.len() > COMPLETED_CAPACITY {
+            if let Some(oldest) = self.completed_order.pop_front() {
+                self.completed.remove(&oldest);
+            }
+        }
+        Ok(Some(partial.chunks.into_iter().flatten().flatten().collect()))
+    }
+    
+    /// Drop messages that have waited longer than the timeout; returns how many
+    pub fn expire(&mut self) -> usize {
+        self.expire_at(Instant::now())
+    }
+    
+    fn expire_at(&mut self, now: Instant) -> usize {
+        let timeout = self.timeout;
+        let before = self.partial.len();
+        let mut freed = 0;
+        self.partial.retain(|_, partial| {
+            let keep = now.saturating_duration_since(partial.started) < timeout;
+            if !keep {
+                freed += partial.bytes;
+            }
+            keep
+        });
+        self.bytes -= freed;
+        before - self.partial.len()
+    }
+    
+    /// Drop the longest-waiting message other than `keep`; false if there is none
+    fn evict_oldest(&mut self, keep: u64) -> bool {
+        let oldest = self.partial.iter()
+            .filter(|(id, _)| **id != keep)
+            .min_by_key(|(_, partial)| partial.started)
+            .map(|(id, _)| *id);
+        match oldest {
+            Some(id) => {
+                self.drop_partial(id);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    fn drop_partial(&mut self, message_id: u64) {
+        if let Some(partial) = self.partial.remove(&message_id) {
+            self.bytes -= partial.bytes;
+        }
+    }
+    
+    /// Messages still waiting for fragments
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+    
+    /// Bytes held for incomplete messages
+    pub fn pending_bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,15 +415,156 @@ mod tests {
         assert_eq!(deserialized.payload, payload);
     }
     
+    #[test]
+    fn test_frame_buckets() {
+        for (len, size) in [(0, 512), (508, 512), (509, 2048), (5000, 8192), (MAX_FRAME_PAYLOAD, 512 * 1024)] {
+            let padded = Frame::pad(vec![7u8; len]).unwrap();
+            assert_eq!(padded.len(), size, "payload of {} bytes", len);
+            assert_eq!(Frame::unpad(&padded).unwrap(), vec![7u8; len]);
+        }
+        assert!(Frame::new(vec![0u8; MAX_FRAME_PAYLOAD + 1]).is_err());
+    }
+    
+    #[test]
+    fn test_deserialize_rejects_bad_frames() {
+        // Not a bucket size
+        assert!(Frame::deserialize(&[0u8; 600]).is_err());
+        
+        // Length prefix past the end of the frame
+        let mut frame = Frame::pad(b"short".to_vec()).unwrap();
+        frame[..4].copy_from_slice(&(FRAME_SIZE as u32).to_be_bytes());
+        assert!(Frame::unpad(&frame).is_err());
+    }
+    
     #[test]
     fn test_fragmenter() {
         let fragmenter = Fragmenter::new();
-        let large_data = vec![0u8; 1024];
+        let large_data: Vec<u8> = (0..1024u32).map(|i| i as u8).collect();
         
-        let fragments = fragmenter.fragment(&large_data);
+        let fragments = fragmenter.fragment(&large_data).unwrap();
         assert!(fragments.len() >= 3);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= FRAME_SIZE - 4));
         
-        let reassembled = fragmenter.reassemble(fragments);
+        let reassembled = fragmenter.reassemble(fragments).unwrap();
         assert_eq!(reassembled, large_data);
+
+        // No room for data is an error, not a panic
+        assert!(Fragmenter::with_fragment_size(FRAGMENT_HEADER_SIZE).is_err());
+    }
+    
+    #[test]
+    fn test_fragment_header_roundtrip() {
+        let fragment = Fragment::new(42, 1, 3, b"middle".to_vec());
+        let decoded = Fragment::decode(&fragment.encode()).unwrap();
+        assert_eq!(decoded, fragment);
+        assert_eq!(decoded.header.message_id, 42);
+        assert_eq!((decoded.header.index, decoded.header.total), (1, 3));
+    }
+    
+    #[test]
+    fn test_corrupt_fragment_rejected() {
+        let mut encoded = Fragment::new(1, 0, 2, b"data".to_vec()).encode();
+        *encoded.last_mut().unwrap() ^= 1;
+        assert!(Fragment::decode(&encoded).is_err());
+        
+        // Index past the total
+        assert!(Fragment::decode(&Fragment::new(1, 2, 2, Vec::new()).encode()).is_err());
+        assert!(Fragment::decode(&[0u8; 3]).is_err());
+    }
+    
+    #[test]
+    fn test_empty_and_single_fragment_messages() {
+        let fragmenter = Fragmenter::new();
+        for data in [Vec::new(), b"small".to_vec()] {
+            let fragments = fragmenter.fragment(&data).unwrap();
+            assert_eq!(fragments.len(), 1);
+            assert_eq!(fragmenter.reassemble(fragments).unwrap(), data);
+        }
+    }
+    
+    #[test]
+    fn test_reassemble_shuffled_with_duplicates() {
+        use rand::seq::SliceRandom;
+        
+        let fragmenter = Fragmenter::with_fragment_size(64).unwrap();
+        let first: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let second: Vec<u8> = (0..700u32).map(|i| (i * 3) as u8).collect();
+        
+        // Two messages interleaved, shuffled, with some fragments sent twice
+        let mut fragments = fragmenter.fragment(&first).unwrap();
+        fragments.extend(fragmenter.fragment(&second).unwrap());
+        let duplicates: Vec<Vec<u8>> = fragments.iter().step_by(5).cloned().collect();
+        fragments.extend(duplicates);
+        fragments.shuffle(&mut rand::thread_rng());
+        
+        let mut reassembler = Reassembler::new();
+        let mut messages = Vec::new();
+        for fragment in &fragments {
+            if let Some(message) = reassembler.push(fragment).unwrap() {
+                messages.push(message);
+            }
+        }
+        
+        messages.sort_by_key(|m| m.len());
+        assert_eq!(messages, vec![second, first]);
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+    }
+    
+    #[test]
+    fn test_dropped_fragment_times_out() {
+        let fragmenter = Fragmenter::with_fragment_size(64).unwrap();
+        let mut fragments = fragmenter.fragment(&[9u8; 500]).unwrap();
+        fragments.remove(3);
+        
+        let mut reassembler = Reassembler::with_limits(Duration::from_secs(5), 1024 * 1024, 16);
+        let start = Instant::now();
+        for fragment in &fragments {
+            assert_eq!(reassembler.push_at(fragment, start).unwrap(), None);
+        }
+        assert_eq!(reassembler.pending(), 1);
+        assert!(reassembler.pending_bytes() > 0);
+        
+        assert_eq!(reassembler.expire_at(start + Duration::from_secs(4)), 0);
+        assert_eq!(reassembler.expire_at(start + Duration::from_secs(6)), 1);
+        assert_eq!(reassembler.pending_bytes(), 0);
+        
+        // Missing fragments make a complete set impossible
+        assert!(fragmenter.reassemble(fragments).is_err());
+    }
+    
+    #[test]
+    fn test_memory_caps_evict_oldest() {
+        let fragmenter = Fragmenter::with_fragment_size(116).unwrap();
+        let mut reassembler = Reassembler::with_limits(DEFAULT_REASSEMBLY_TIMEOUT, 250, 2);
+        let start = Instant::now();
+        
+        // First fragment of three 2-fragment messages: only two may wait at once
+        let messages: Vec<Vec<Vec<u8>>> = (0..3).map(|_| fragmenter.fragment(&[1u8; 200]).unwrap()).collect();
+        for (i, fragments) in messages.iter().enumerate() {
+            reassembler.push_at(&fragments[0], start + Duration::from_secs(i as u64)).unwrap();
+        }
+        assert_eq!(reassembler.pending(), 2);
+        
+        // 100-byte fragments: message 2's second one pushes message 1 out to stay under 250 bytes
+        let done = reassembler.push_at(&messages[2][1], start + Duration::from_secs(3)).unwrap();
+        assert_eq!(done, Some(vec![1u8; 200]));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+        
+        // A message that can never fit is refused outright
+        let big = fragmenter.fragment(&[2u8; 300]).unwrap();
+        reassembler.push(&big[0]).unwrap();
+        reassembler.push(&big[1]).unwrap();
+        assert!(reassembler.push(&big[2]).is_err());
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+    }
+    
+    #[test]
+    fn test_conflicting_total_rejected() {
+        let mut reassembler = Reassembler::new();
+        reassembler.push(&Fragment::new(5, 0, 3, b"a".to_vec()).encode()).unwrap();
+        assert!(reassembler.push(&Fragment::new(5, 1, 4, b"b".to_vec()).encode()).is_err());
     }
 }
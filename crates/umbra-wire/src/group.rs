@@ -0,0 +1,46 @@
+// Group membership wire messages (protobuf generated)
+// MLS objects (KeyPackages, commits, Welcomes) travel as opaque bytes; building, signing and
+// applying them is up to the node that holds the MLS group state
+
+use crate::error::{Result, WireError};
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/umbra.group.rs"));
+}
+
+pub use proto::{
+    group_change, group_control, AddMember, GroupChange, GroupControl, GroupInvite, GroupMetadata, JoinRequest,
+    RemoveMember, Role, SetRole,
+};
+
+impl GroupControl {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_roundtrip() {
+        let change = GroupChange {
+            group_id: b"room".to_vec(),
+            epoch: 3,
+            sender: 1,
+            change: Some(group_change::Change::SetRole(SetRole { leaf_index: 2, role: Role::Admin as i32 })),
+            commit: vec![1, 2, 3],
+            welcome: Vec::new(),
+            signature: vec![4; 64],
+        };
+        let control = GroupControl { message: Some(group_control::Message::Change(change)) };
+        assert_eq!(GroupControl::decode_from_bytes(&control.encode_to_vec()).unwrap(), control);
+        assert!(GroupControl::decode_from_bytes(&[0xFF, 0xFF]).is_err());
+    }
+}
@@ -6,7 +6,10 @@ pub mod proto {
 use crate::error::{WireError, Result};
 
 // Re-export for convenience
-pub use proto::{HandshakeInit, HandshakeResp, HandshakeMessage, handshake_message};
+pub use proto::{
+    HandshakeInit, HandshakeResp, HandshakeFinish, HandshakeMessage, handshake_message, OneTimePrekey, PrekeyBundle,
+    PrekeyInit, PrekeyRecord,
+};
 
 impl HandshakeMessage {
     pub fn encode_to_vec(&self) -> Vec<u8> {
@@ -37,6 +40,8 @@ mod tests {
             signature: vec![9u8; 64],
             verify_key: vec![7u8; 32],
             pq_signature: vec![],
+            pq_verify_key: vec![3u8; 16],
+            key_confirmation: true,
         };
         
         let msg = HandshakeMessage {
@@ -51,6 +56,8 @@ mod tests {
             assert_eq!(decoded_init.x25519_pk, init.x25519_pk);
             assert_eq!(decoded_init.signature, init.signature);
             assert_eq!(decoded_init.verify_key, init.verify_key);
+            assert_eq!(decoded_init.pq_verify_key, init.pq_verify_key);
+            assert!(decoded_init.key_confirmation);
         } else {
             panic!("Expected Init message");
         }
@@ -65,6 +72,8 @@ mod tests {
             signature: vec![9u8; 64],
             verify_key: vec![7u8; 32],
             pq_signature: vec![],
+            pq_verify_key: vec![3u8; 16],
+            confirm_mac: vec![4u8; 32],
         };
         
         let msg = HandshakeMessage {
@@ -79,8 +88,29 @@ mod tests {
             assert_eq!(decoded_resp.x25519_pk, resp.x25519_pk);
             assert_eq!(decoded_resp.signature, resp.signature);
             assert_eq!(decoded_resp.verify_key, resp.verify_key);
+            assert_eq!(decoded_resp.pq_verify_key, resp.pq_verify_key);
+            assert_eq!(decoded_resp.confirm_mac, resp.confirm_mac);
         } else {
             panic!("Expected Resp message");
         }
     }
+
+    #[test]
+    fn test_handshake_finish_roundtrip() {
+        let finish = HandshakeFinish {
+            confirm_mac: vec![4u8; 32],
+        };
+        
+        let msg = HandshakeMessage {
+            message: Some(handshake_message::Message::Finish(finish.clone())),
+        };
+        
+        let decoded = HandshakeMessage::decode_from_bytes(&msg.encode_to_vec()).unwrap();
+        
+        if let Some(handshake_message::Message::Finish(decoded_finish)) = decoded.message {
+            assert_eq!(decoded_finish.confirm_mac, finish.confirm_mac);
+        } else {
+            panic!("Expected Finish message");
+        }
+    }
 }
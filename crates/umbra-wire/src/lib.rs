@@ -5,6 +5,11 @@ pub mod handshake;
 pub mod message;
 pub mod framing;
 pub mod convert;
+pub mod group;
+pub mod mailbox;
+pub mod circuit;
+pub mod sphinx;
+pub mod file;
 
 pub use error::{WireError, Result};
 
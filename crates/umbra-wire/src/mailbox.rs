@@ -0,0 +1,172 @@
+// Store-and-forward mailbox wire messages (protobuf generated) and fetch authentication
+// Messages are deposited under a recipient tag derived from the recipient's hybrid identity;
+// only a fetch signed by that identity can read or delete them
+
+use crate::error::{Result, WireError};
+use libp2p::PeerId;
+use std::time::{SystemTime, UNIX_EPOCH};
+use umbra_crypto::identity::{HybridSignature, HybridVerifyingKey, IdentityKey, PqPolicy};
+use umbra_crypto::Transcript;
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/umbra.mailbox.rs"));
+}
+
+pub use proto::{
+    mailbox_record, mailbox_request, Deposit, Fetch, MailboxAuth, MailboxEntry, MailboxRecord, MailboxRemoved,
+    MailboxRequest, MailboxResponse, MailboxStatus, StoredMessage,
+};
+
+/// Mailbox address of an identity (SHA-256 over both verifying keys)
+pub fn recipient_tag(verify_key: &HybridVerifyingKey) -> [u8; 32] {
+    let mut transcript = Transcript::new();
+    transcript.append(b"mailbox tag verify_key", verify_key.classical().as_bytes());
+    transcript.append(b"mailbox tag pq_verify_key", verify_key.pq().unwrap_or_default());
+    transcript.hash()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl Fetch {
+    /// Signed fetch for `identity`'s messages held by `mailbox`, deleting the `ack`ed ones first
+    pub fn new(identity: &IdentityKey, mailbox: &PeerId, ack: Vec<u64>) -> Result<Self> {
+        let mut fetch = Fetch {
+            auth: Some(MailboxAuth {
+                verify_key: identity.verifying_key().to_bytes().to_vec(),
+                pq_verify_key: identity.pq_verifying_key(),
+                timestamp: now(),
+                nonce: rand::random::<[u8; 16]>().to_vec(),
+                signature: Vec::new(),
+                pq_signature: Vec::new(),
+            }),
+            ack,
+        };
+        let signature = identity.sign(&fetch.signed_hash(mailbox))?;
+        let auth = fetch.auth.as_mut().ok_or(WireError::InvalidMessage)?;
+        auth.signature = signature.classical;
+        auth.pq_signature = signature.pq.unwrap_or_default();
+        Ok(fetch)
+    }
+
+    /// Check the signature as `mailbox`; returns the tag of the messages being fetched.
+    /// Freshness and nonce reuse are up to the mailbox, which remembers what it has seen.
+    pub fn verify(&self, mailbox: &PeerId) -> Result<[u8; 32]> {
+        let auth = self.auth.as_ref().ok_or(WireError::InvalidMessage)?;
+        let verify_key: [u8; 32] = auth.verify_key.as_slice().try_into().map_err(|_| WireError::InvalidMessage)?;
+        let verify_key = HybridVerifyingKey::from_bytes(&verify_key, &auth.pq_verify_key)?;
+
+        let signature = HybridSignature {
+            classical: auth.signature.clone(),
+            pq: Some(auth.pq_signature.clone()),
+        };
+        verify_key.verify(&self.signed_hash(mailbox), &signature, PqPolicy::RequirePq)?;
+        Ok(recipient_tag(&verify_key))
+    }
+
+    /// Covers the mailbox too, so a fetch can't be replayed at another mailbox holding the same tag
+    fn signed_hash(&self, mailbox: &PeerId) -> [u8; 32] {
+        use prost::Message;
+
+        let mut unsigned = self.clone();
+        if let Some(auth) = unsigned.auth.as_mut() {
+            auth.signature.clear();
+            auth.pq_signature.clear();
+        }
+        let mut transcript = Transcript::new();
+        transcript.append(b"mailbox fetch", &unsigned.encode_to_vec());
+        transcript.append(b"mailbox fetch mailbox", &mailbox.to_bytes());
+        transcript.hash()
+    }
+}
+
+impl MailboxRequest {
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
+
+impl MailboxResponse {
+    /// Response carrying only a status
+    pub fn with_status(status: MailboxStatus) -> Self {
+        MailboxResponse {
+            status: status as i32,
+            ..Default::default()
+        }
+    }
+
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn decode_from_bytes(bytes: &[u8]) -> Result<Self> {
+        use prost::Message;
+        Self::decode(bytes).map_err(WireError::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fetch_signed_for_own_tag() {
+        let identity = IdentityKey::generate().unwrap();
+        let mailbox = PeerId::random();
+        let fetch = Fetch::new(&identity, &mailbox, vec![1, 2]).unwrap();
+
+        let request = MailboxRequest {
+            request: Some(mailbox_request::Request::Fetch(fetch)),
+        };
+        let Some(mailbox_request::Request::Fetch(fetch)) =
+            MailboxRequest::decode_from_bytes(&request.encode_to_vec()).unwrap().request
+        else {
+            panic!("expected fetch")
+        };
+        assert_eq!(fetch.verify(&mailbox).unwrap(), recipient_tag(&identity.hybrid_verifying_key()));
+    }
+
+    #[test]
+    fn test_tampered_fetch_rejected() {
+        let identity = IdentityKey::generate().unwrap();
+        let mailbox = PeerId::random();
+
+        // Acks are covered, so nobody can replay a fetch to delete other messages
+        let mut fetch = Fetch::new(&identity, &mailbox, vec![1]).unwrap();
+        fetch.ack.push(2);
+        assert!(fetch.verify(&mailbox).is_err());
+
+        // Swapping in another identity's keys changes the tag and breaks the signature
+        let other = IdentityKey::generate().unwrap();
+        let mut fetch = Fetch::new(&identity, &mailbox, Vec::new()).unwrap();
+        let auth = fetch.auth.as_mut().unwrap();
+        auth.verify_key = other.verifying_key().to_bytes().to_vec();
+        auth.pq_verify_key = other.pq_verifying_key();
+        assert!(fetch.verify(&mailbox).is_err());
+    }
+
+    #[test]
+    fn test_fetch_bound_to_mailbox() {
+        let identity = IdentityKey::generate().unwrap();
+        let mailbox = PeerId::random();
+        let fetch = Fetch::new(&identity, &mailbox, Vec::new()).unwrap();
+
+        // A fetch captured at one mailbox can't be replayed at another
+        assert!(fetch.verify(&mailbox).is_ok());
+        assert!(fetch.verify(&PeerId::random()).is_err());
+    }
+
+    #[test]
+    fn test_tags_differ_per_identity() {
+        let a = IdentityKey::generate().unwrap().hybrid_verifying_key();
+        let b = IdentityKey::generate().unwrap().hybrid_verifying_key();
+        assert_ne!(recipient_tag(&a), recipient_tag(&b));
+        assert_eq!(recipient_tag(&a), recipient_tag(&a.clone()));
+    }
+}
@@ -1,7 +1,14 @@
 // Message wire format (protobuf generated)
 
+// Generated code refers to imported handshake types as `super::handshake`
+use crate::handshake::proto as handshake;
+
+#[allow(clippy::module_inception)]
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/umbra.message.rs"));
 }
 
-pub use message::{ChatMessage, EncryptedMessage, IdentityAnnouncement, Message};
+pub use message::{
+    mailbox_message, ChatMessage, DirectAck, DirectMessage, EncryptedMessage, GroupMessage,
+    IdentityAnnouncement, MailboxMessage, Message, PrekeyMessage,
+};
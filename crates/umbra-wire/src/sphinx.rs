@@ -0,0 +1,561 @@
+// Sphinx mix packets
+// A packet is PACKET_SIZE bytes at every hop: a blinded X25519 group element, the layered routing
+// information with its MAC, and a wide-block encrypted payload. Each mix strips one layer,
+// re-blinds the group element and pads the routing information back to full length, so the packet
+// leaving a mix shares no bits with the one that went in and gives away neither the route length
+// nor the mix's position on it.
+
+use crate::error::{Result, WireError};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::time::Duration;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Longest route a packet can carry
+pub const MAX_HOPS: usize = 5;
+/// Room for a hop address in the routing information (a length byte, then up to 63 bytes)
+pub const ADDRESS_SIZE: usize = 64;
+/// Size of every packet on the wire
+pub const PACKET_SIZE: usize = 8192;
+/// Size of the header: group element, routing information and MAC
+pub const HEADER_SIZE: usize = 32 + BETA_SIZE + MAC_SIZE;
+/// Size of the encrypted payload
+pub const PAYLOAD_SIZE: usize = PACKET_SIZE - HEADER_SIZE;
+/// Largest message a packet can carry
+pub const MAX_MESSAGE_SIZE: usize = PAYLOAD_SIZE - TAG_SIZE - 4;
+/// Longest a hop can be asked to hold a packet; anything longer is refused
+pub const MAX_DELAY: Duration = Duration::from_secs(5);
+
+const MAC_SIZE: usize = 16;
+// Per-hop routing block: command, delay in ms, next hop address, next hop's MAC
+const ROUTING_SIZE: usize = 1 + 4 + ADDRESS_SIZE + MAC_SIZE;
+const BETA_SIZE: usize = MAX_HOPS * ROUTING_SIZE;
+// Zero bytes at the front of the payload; the last hop checks them to detect tampering
+const TAG_SIZE: usize = 16;
+
+const FORWARD: u8 = 1;
+const DELIVER: u8 = 2;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// One mix on a packet's route
+#[derive(Clone, Debug)]
+pub struct Hop {
+    /// Address the previous hop forwards the packet to (PeerId bytes)
+    pub address: Vec<u8>,
+    /// The hop's X25519 mix key
+    pub key: [u8; 32],
+    /// How long the hop holds the packet before forwarding it (ignored for the last hop)
+    pub delay: Duration,
+}
+
+/// What a mix does with a packet it processed
+#[derive(Debug)]
+pub enum Processed {
+    /// Hold the packet for `delay`, then pass it to `next_hop`
+    Forward { next_hop: Vec<u8>, delay: Duration, packet: SphinxPacket },
+    /// The packet was for us
+    Deliver { message: Vec<u8> },
+}
+
+/// Fixed-size mix packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SphinxPacket {
+    alpha: [u8; 32],
+    beta: Vec<u8>,
+    gamma: [u8; MAC_SIZE],
+    payload: Vec<u8>,
+}
+
+impl SphinxPacket {
+    /// Wrap `message` for `route`; the last hop is the recipient
+    pub fn new(route: &[Hop], message: &[u8]) -> Result<Self> {
+        if route.iter().any(|hop| hop.delay > MAX_DELAY) {
+            return Err(WireError::InvalidRoute(format!("Hop delay over {:?}", MAX_DELAY)));
+        }
+        Self::build(route, message)
+    }
+
+    /// `new` without the delay limit, which only the mixes enforce
+    fn build(route: &[Hop], message: &[u8]) -> Result<Self> {
+        if route.is_empty() || route.len() > MAX_HOPS {
+            return Err(WireError::InvalidRoute(format!("{} hops (1 to {} allowed)", route.len(), MAX_HOPS)));
+        }
+        if route.iter().any(|hop| hop.address.len() >= ADDRESS_SIZE) {
+            return Err(WireError::InvalidRoute("Hop address too long".to_string()));
+        }
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(WireError::PayloadTooLarge);
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut wide = Zeroizing::new([0u8; 64]);
+        rng.fill_bytes(wide.as_mut());
+        let mut secret = Scalar::from_bytes_mod_order_wide(&wide);
+        let alpha = MontgomeryPoint::mul_base(&secret).to_bytes();
+
+        // Shared secret with each hop, blinding the group element as it will be along the way
+        let mut keys = Vec::with_capacity(route.len());
+        let mut hop_alpha = MontgomeryPoint(alpha);
+        for hop in route {
+            let shared = Zeroizing::new((MontgomeryPoint(hop.key) * secret).to_bytes());
+            if *shared == [0u8; 32] {
+                return Err(WireError::InvalidRoute("Low-order mix key".to_string()));
+            }
+            let blind = blinding_factor(&hop_alpha.to_bytes(), &shared);
+            keys.push(HopKeys::derive(&shared));
+            hop_alpha *= blind;
+            secret *= blind;
+        }
+        secret.zeroize();
+
+        // Bytes the earlier hops' padding turns into by the time the packet reaches the last hop
+        let n = route.len();
+        let mut filler = Vec::with_capacity((n - 1) * ROUTING_SIZE);
+        for hop_keys in &keys[..n - 1] {
+            filler.extend_from_slice(&[0u8; ROUTING_SIZE]);
+            let stream = keystream(&hop_keys.rho, BETA_SIZE + ROUTING_SIZE);
+            let offset = stream.len() - filler.len();
+            xor(&mut filler, &stream[offset..]);
+        }
+
+        let mut beta = vec![0u8; BETA_SIZE - filler.len()];
+        beta[..ROUTING_SIZE].copy_from_slice(&routing_block(DELIVER, Duration::ZERO, &[], &[0u8; MAC_SIZE]));
+        rng.fill_bytes(&mut beta[ROUTING_SIZE..]);
+        let stream = keystream(&keys[n - 1].rho, beta.len());
+        xor(&mut beta, &stream);
+        beta.extend_from_slice(&filler);
+        let mut gamma = mac(&keys[n - 1].mu, &beta);
+
+        for i in (0..n - 1).rev() {
+            let mut layer = routing_block(FORWARD, route[i].delay, &route[i + 1].address, &gamma);
+            layer.extend_from_slice(&beta[..BETA_SIZE - ROUTING_SIZE]);
+            xor(&mut layer, &keystream(&keys[i].rho, BETA_SIZE));
+            beta = layer;
+            gamma = mac(&keys[i].mu, &beta);
+        }
+
+        let mut payload = vec![0u8; PAYLOAD_SIZE];
+        payload[TAG_SIZE..TAG_SIZE + 4].copy_from_slice(&(message.len() as u32).to_be_bytes());
+        payload[TAG_SIZE + 4..TAG_SIZE + 4 + message.len()].copy_from_slice(message);
+        rng.fill_bytes(&mut payload[TAG_SIZE + 4 + message.len()..]);
+        for hop_keys in keys.iter().rev() {
+            lioness_encrypt(&hop_keys.lioness, &mut payload);
+        }
+
+        Ok(Self { alpha, beta, gamma, payload })
+    }
+
+    /// Strip our layer with our mix key, rejecting packets we have processed before
+    pub fn process(&self, mix_secret: &[u8; 32], replays: &mut ReplayCache) -> Result<Processed> {
+        let shared = Zeroizing::new(x25519_dalek::x25519(*mix_secret, self.alpha));
+        if *shared == [0u8; 32] {
+            return Err(WireError::PacketMac);
+        }
+        let keys = HopKeys::derive(&shared);
+
+        let mut verifier = HmacSha256::new_from_slice(&keys.mu).expect("HMAC accepts any key length");
+        verifier.update(&self.beta);
+        if verifier.verify_truncated_left(&self.gamma).is_err() {
+            return Err(WireError::PacketMac);
+        }
+        // Only tag packets that authenticated, so forgeries cannot fill the cache
+        if !replays.insert(keys.tag) {
+            return Err(WireError::Replay);
+        }
+
+        let mut routing = self.beta.clone();
+        routing.extend_from_slice(&[0u8; ROUTING_SIZE]);
+        xor(&mut routing, &keystream(&keys.rho, BETA_SIZE + ROUTING_SIZE));
+
+        let mut payload = self.payload.clone();
+        lioness_decrypt(&keys.lioness, &mut payload);
+
+        match routing[0] {
+            FORWARD => {
+                let delay = Duration::from_millis(u32::from_be_bytes(routing[1..5].try_into().unwrap()) as u64);
+                if delay > MAX_DELAY {
+                    return Err(WireError::InvalidRoute(format!("Hop delay over {:?}", MAX_DELAY)));
+                }
+                let address_len = routing[5] as usize;
+                if address_len >= ADDRESS_SIZE {
+                    return Err(WireError::InvalidRoute("Hop address too long".to_string()));
+                }
+                let next_hop = routing[6..6 + address_len].to_vec();
+                let gamma = routing[5 + ADDRESS_SIZE..ROUTING_SIZE].try_into().unwrap();
+                let blind = blinding_factor(&self.alpha, &shared);
+                let alpha = (MontgomeryPoint(self.alpha) * blind).to_bytes();
+
+                Ok(Processed::Forward {
+                    next_hop,
+                    delay,
+                    packet: Self { alpha, beta: routing[ROUTING_SIZE..].to_vec(), gamma, payload },
+                })
+            }
+            DELIVER => {
+                if payload[..TAG_SIZE] != [0u8; TAG_SIZE] {
+                    return Err(WireError::PacketMac);
+                }
+                let len = u32::from_be_bytes(payload[TAG_SIZE..TAG_SIZE + 4].try_into().unwrap()) as usize;
+                if len > MAX_MESSAGE_SIZE {
+                    return Err(WireError::InvalidLength);
+                }
+                Ok(Processed::Deliver { message: payload[TAG_SIZE + 4..TAG_SIZE + 4 + len].to_vec() })
+            }
+            command => Err(WireError::InvalidRoute(format!("Unknown routing command {}", command))),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_SIZE);
+        bytes.extend_from_slice(&self.alpha);
+        bytes.extend_from_slice(&self.beta);
+        bytes.extend_from_slice(&self.gamma);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != PACKET_SIZE {
+            return Err(WireError::InvalidFrameSize);
+        }
+        let (alpha, rest) = bytes.split_at(32);
+        let (beta, rest) = rest.split_at(BETA_SIZE);
+        let (gamma, payload) = rest.split_at(MAC_SIZE);
+        Ok(Self {
+            alpha: alpha.try_into().unwrap(),
+            beta: beta.to_vec(),
+            gamma: gamma.try_into().unwrap(),
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// A fresh mix key: the secret `SphinxPacket::process` takes, and the public key for `Hop::key`
+pub fn mix_keypair() -> (Zeroizing<[u8; 32]>, [u8; 32]) {
+    let mut secret = Zeroizing::new([0u8; 32]);
+    rand::thread_rng().fill_bytes(secret.as_mut());
+    let public = MontgomeryPoint::mul_base_clamped(*secret).to_bytes();
+    (secret, public)
+}
+
+/// Tags of packets a mix has already processed under one mix key. It only needs to live as long
+/// as that key, so the mix bounds it by rotating the key.
+#[derive(Debug, Default)]
+pub struct ReplayCache {
+    seen: HashSet<[u8; 32]>,
+}
+
+impl ReplayCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a tag; false if it was already there
+    fn insert(&mut self, tag: [u8; 32]) -> bool {
+        self.seen.insert(tag)
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Forget every tag (only safe once the mix key has changed)
+    pub fn clear(&mut self) {
+        self.seen.clear();
+    }
+}
+
+/// Keys a hop derives from its shared secret
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct HopKeys {
+    rho: [u8; 32],
+    mu: [u8; 32],
+    lioness: [[u8; 32]; 4],
+    tag: [u8; 32],
+}
+
+impl HopKeys {
+    fn derive(shared: &[u8; 32]) -> Self {
+        Self {
+            rho: prf(shared, &[b"sphinx rho"]),
+            mu: prf(shared, &[b"sphinx mu"]),
+            lioness: [
+                prf(shared, &[b"sphinx lioness 1"]),
+                prf(shared, &[b"sphinx lioness 2"]),
+                prf(shared, &[b"sphinx lioness 3"]),
+                prf(shared, &[b"sphinx lioness 4"]),
+            ],
+            tag: prf(shared, &[b"sphinx replay tag"]),
+        }
+    }
+}
+
+fn prf(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in data {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn mac(key: &[u8; 32], data: &[u8]) -> [u8; MAC_SIZE] {
+    prf(key, &[data])[..MAC_SIZE].try_into().unwrap()
+}
+
+fn blinding_factor(alpha: &[u8; 32], shared: &[u8; 32]) -> Scalar {
+    Scalar::from_bytes_mod_order(prf(shared, &[b"sphinx blind", alpha]))
+}
+
+fn keystream(key: &[u8; 32], len: usize) -> Vec<u8> {
+    let mut stream = vec![0u8; len];
+    ChaCha20::new(key.into(), &[0u8; 12].into()).apply_keystream(&mut stream);
+    stream
+}
+
+fn xor(data: &mut [u8], stream: &[u8]) {
+    for (byte, key) in data.iter_mut().zip(stream) {
+        *byte ^= key;
+    }
+}
+
+fn routing_block(command: u8, delay: Duration, address: &[u8], gamma: &[u8; MAC_SIZE]) -> Vec<u8> {
+    let mut block = vec![0u8; ROUTING_SIZE];
+    block[0] = command;
+    block[1..5].copy_from_slice(&(delay.as_millis().min(u32::MAX as u128) as u32).to_be_bytes());
+    block[5] = address.len() as u8;
+    block[6..6 + address.len()].copy_from_slice(address);
+    block[5 + ADDRESS_SIZE..].copy_from_slice(gamma);
+    block
+}
+
+// LIONESS wide-block cipher over the payload: changing any bit scrambles all of it
+fn lioness_round_stream(key: &[u8; 32], left: &[u8], right: &mut [u8]) {
+    let mut round_key = Zeroizing::new(*key);
+    xor(round_key.as_mut(), left);
+    xor(right, &keystream(&round_key, right.len()));
+}
+
+fn lioness_round_mac(key: &[u8; 32], left: &mut [u8], right: &[u8]) {
+    xor(left, &prf(key, &[right]));
+}
+
+fn lioness_encrypt(keys: &[[u8; 32]; 4], payload: &mut [u8]) {
+    let (left, right) = payload.split_at_mut(32);
+    lioness_round_stream(&keys[0], left, right);
+    lioness_round_mac(&keys[1], left, right);
+    lioness_round_stream(&keys[2], left, right);
+    lioness_round_mac(&keys[3], left, right);
+}
+
+fn lioness_decrypt(keys: &[[u8; 32]; 4], payload: &mut [u8]) {
+    let (left, right) = payload.split_at_mut(32);
+    lioness_round_mac(&keys[3], left, right);
+    lioness_round_stream(&keys[2], left, right);
+    lioness_round_mac(&keys[1], left, right);
+    lioness_round_stream(&keys[0], left, right);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mix {
+        secret: [u8; 32],
+        replays: ReplayCache,
+    }
+
+    impl Mix {
+        fn process(&mut self, packet: &SphinxPacket) -> Result<Processed> {
+            packet.process(&self.secret, &mut self.replays)
+        }
+    }
+
+    fn route(len: usize) -> (Vec<Hop>, Vec<Mix>) {
+        let mut hops = Vec::new();
+        let mut mixes = Vec::new();
+        for i in 0..len {
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            hops.push(Hop {
+                address: vec![i as u8; 38],
+                key: MontgomeryPoint::mul_base_clamped(secret).to_bytes(),
+                delay: Duration::from_millis(100 * i as u64),
+            });
+            mixes.push(Mix { secret, replays: ReplayCache::new() });
+        }
+        (hops, mixes)
+    }
+
+    /// Run a packet through every mix, returning the packet each one sent on and the message
+    fn run(packet: SphinxPacket, hops: &[Hop], mixes: &mut [Mix]) -> (Vec<SphinxPacket>, Vec<u8>) {
+        let mut packets = vec![packet];
+        for (i, mix) in mixes.iter_mut().enumerate() {
+            let bytes = packets.last().unwrap().to_bytes();
+            assert_eq!(bytes.len(), PACKET_SIZE);
+            let packet = SphinxPacket::from_bytes(&bytes).unwrap();
+            match mix.process(&packet).unwrap() {
+                Processed::Forward { next_hop, delay, packet } => {
+                    assert_eq!(next_hop, hops[i + 1].address);
+                    assert_eq!(delay, hops[i].delay);
+                    packets.push(packet);
+                }
+                Processed::Deliver { message } => {
+                    assert_eq!(i, mixes.len() - 1, "only the last hop delivers");
+                    return (packets, message);
+                }
+            }
+        }
+        panic!("packet was never delivered");
+    }
+
+    fn differing_bits(a: &[u8], b: &[u8]) -> u32 {
+        a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for len in 1..=MAX_HOPS {
+            let (hops, mut mixes) = route(len);
+            let packet = SphinxPacket::new(&hops, b"through the mixes").unwrap();
+            let (packets, message) = run(packet, &hops, &mut mixes);
+            assert_eq!(packets.len(), len);
+            assert_eq!(message, b"through the mixes");
+        }
+    }
+
+    #[test]
+    fn test_largest_message() {
+        let (hops, mut mixes) = route(3);
+        let message = vec![0x5a; MAX_MESSAGE_SIZE];
+        let packet = SphinxPacket::new(&hops, &message).unwrap();
+        assert_eq!(run(packet, &hops, &mut mixes).1, message);
+
+        let too_long = vec![0; MAX_MESSAGE_SIZE + 1];
+        assert!(matches!(SphinxPacket::new(&hops, &too_long), Err(WireError::PayloadTooLarge)));
+    }
+
+    #[test]
+    fn test_invalid_routes() {
+        assert!(matches!(SphinxPacket::new(&[], b"m"), Err(WireError::InvalidRoute(_))));
+        let (hops, _) = route(MAX_HOPS + 1);
+        assert!(matches!(SphinxPacket::new(&hops, b"m"), Err(WireError::InvalidRoute(_))));
+
+        let (mut hops, _) = route(2);
+        hops[1].address = vec![0; ADDRESS_SIZE];
+        assert!(matches!(SphinxPacket::new(&hops, b"m"), Err(WireError::InvalidRoute(_))));
+
+        let (mut hops, _) = route(2);
+        hops[0].delay = MAX_DELAY + Duration::from_millis(1);
+        assert!(matches!(SphinxPacket::new(&hops, b"m"), Err(WireError::InvalidRoute(_))));
+    }
+
+    #[test]
+    fn test_long_delay_refused_by_mix() {
+        let (mut hops, mut mixes) = route(2);
+        hops[0].delay = MAX_DELAY;
+        assert!(mixes[0].process(&SphinxPacket::new(&hops, b"m").unwrap()).is_ok());
+
+        // A sender that skips the check still can't park a packet at the mix
+        hops[0].delay = Duration::from_secs(3600);
+        let packet = SphinxPacket::build(&hops, b"m").unwrap();
+        assert!(matches!(mixes[0].process(&packet), Err(WireError::InvalidRoute(_))));
+    }
+
+    #[test]
+    fn test_input_and_output_unlinkable() {
+        let (hops, mut mixes) = route(MAX_HOPS);
+        let packet = SphinxPacket::new(&hops, b"same message").unwrap();
+        let (packets, _) = run(packet, &hops, &mut mixes);
+
+        for pair in packets.windows(2) {
+            let (input, output) = (pair[0].to_bytes(), pair[1].to_bytes());
+
+            // No field survives a hop unchanged, not even in part
+            assert_ne!(pair[0].alpha, pair[1].alpha);
+            assert_ne!(pair[0].gamma, pair[1].gamma);
+            assert!(pair[0].beta.windows(16).all(|w| !pair[1].beta.windows(16).any(|v| v == w)));
+            assert!(pair[0].payload.chunks(16).zip(pair[1].payload.chunks(16)).all(|(a, b)| a != b));
+
+            // About half the bits flip, as between two unrelated random strings
+            let bits = (PACKET_SIZE * 8) as u32;
+            let flipped = differing_bits(&input, &output);
+            assert!(flipped > bits * 45 / 100 && flipped < bits * 55 / 100, "{} of {} bits differ", flipped, bits);
+
+            // Bytes in the same position agree about as often as chance (1 in 256)
+            let same = input.iter().zip(&output).filter(|(a, b)| a == b).count();
+            assert!(same < PACKET_SIZE / 64, "{} bytes unchanged", same);
+        }
+    }
+
+    #[test]
+    fn test_same_message_same_route_unlinkable() {
+        let (hops, _) = route(3);
+        let a = SphinxPacket::new(&hops, b"same message").unwrap().to_bytes();
+        let b = SphinxPacket::new(&hops, b"same message").unwrap().to_bytes();
+
+        let bits = (PACKET_SIZE * 8) as u32;
+        let flipped = differing_bits(&a, &b);
+        assert!(flipped > bits * 45 / 100 && flipped < bits * 55 / 100);
+    }
+
+    #[test]
+    fn test_replay_rejected() {
+        let (hops, mut mixes) = route(3);
+        let packet = SphinxPacket::new(&hops, b"once").unwrap();
+
+        assert!(mixes[0].process(&packet).is_ok());
+        assert_eq!(mixes[0].replays.len(), 1);
+        assert!(matches!(mixes[0].process(&packet), Err(WireError::Replay)));
+    }
+
+    #[test]
+    fn test_tampered_header_rejected() {
+        let (hops, mut mixes) = route(3);
+        let packet = SphinxPacket::new(&hops, b"m").unwrap();
+
+        for position in [0, 32, HEADER_SIZE - 1] {
+            let mut bytes = packet.to_bytes();
+            bytes[position] ^= 1;
+            let tampered = SphinxPacket::from_bytes(&bytes).unwrap();
+            assert!(matches!(mixes[0].process(&tampered), Err(WireError::PacketMac)));
+        }
+        // Failed packets leave no replay tag behind
+        assert!(mixes[0].replays.is_empty());
+
+        // The wrong mix key fails the same way
+        assert!(matches!(mixes[1].process(&packet), Err(WireError::PacketMac)));
+    }
+
+    #[test]
+    fn test_tampered_payload_detected_at_last_hop() {
+        let (hops, mut mixes) = route(3);
+        let mut bytes = SphinxPacket::new(&hops, b"m").unwrap().to_bytes();
+        bytes[PACKET_SIZE - 1] ^= 1;
+        let mut packet = SphinxPacket::from_bytes(&bytes).unwrap();
+
+        for mix in &mut mixes[..2] {
+            match mix.process(&packet).unwrap() {
+                Processed::Forward { packet: next, .. } => packet = next,
+                Processed::Deliver { .. } => panic!("delivered early"),
+            }
+        }
+        assert!(matches!(mixes[2].process(&packet), Err(WireError::PacketMac)));
+    }
+
+    #[test]
+    fn test_from_bytes_requires_exact_size() {
+        assert!(matches!(SphinxPacket::from_bytes(&[0; PACKET_SIZE - 1]), Err(WireError::InvalidFrameSize)));
+        assert!(matches!(SphinxPacket::from_bytes(&[0; PACKET_SIZE + 1]), Err(WireError::InvalidFrameSize)));
+    }
+}